@@ -1,13 +1,57 @@
+use crate::rabbitmq::QueueOptions;
 use aws_config::{Region, SdkConfig};
 use serde::Deserialize;
+use shared::tracer::LogFormat;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::sync::OnceLock;
+use std::time::Duration;
 use tokio::sync::OnceCell;
 use url::Url;
+use validator::{Validate, ValidationError};
+
+/// Timestamp of a tracker location
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LocationTimestamp {
+    /// the time reported by the tracker, or the time it was received if implausible
+    #[default]
+    Device,
+    /// the time the location was received by the decoder
+    Received,
+}
+
+/// What to do when a user signing in already has the maximum amount of sessions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionLimitPolicy {
+    /// delete the oldest sessions of the user to make room for the new one
+    #[default]
+    EvictOldest,
+    /// refuse to create the new session until the user signs out of another one
+    RejectNew,
+}
+
+/// What to do with a location of a tracker whose IMEI is not registered
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownImeiPolicy {
+    /// discard the location
+    #[default]
+    Drop,
+    /// hold the location, so it can be backfilled once a tracker with the IMEI is registered
+    Hold,
+    /// register a tracker with the IMEI on `UNKNOWN_IMEI_AUTO_REGISTER_ORG_ID` and store the location on it
+    AutoRegister,
+}
 
 fn def_http_port() -> u16 {
     3000
 }
 
+fn def_http_host() -> IpAddr {
+    IpAddr::V4(Ipv4Addr::LOCALHOST)
+}
+
 fn def_is_development() -> bool {
     false
 }
@@ -40,7 +84,165 @@ fn def_aws_uploads_bucket_name() -> String {
     String::from("rastercar-uploads")
 }
 
-#[derive(Deserialize, Debug)]
+fn def_tracker_id_cache_capacity() -> usize {
+    100_000
+}
+
+fn def_bcrypt_cost() -> u32 {
+    bcrypt::DEFAULT_COST
+}
+
+fn def_auth_rate_limit() -> u32 {
+    20
+}
+
+fn def_auth_rate_limit_window_seconds() -> u64 {
+    60
+}
+
+fn def_api_rate_limit() -> u32 {
+    600
+}
+
+fn def_api_rate_limit_window_seconds() -> u64 {
+    60
+}
+
+fn def_login_history_retention_days() -> u64 {
+    90
+}
+
+fn def_outbox_retention_days() -> u64 {
+    7
+}
+
+fn def_outbox_relay_interval_ms() -> u64 {
+    1000
+}
+
+fn def_clear_sessions_enabled() -> bool {
+    true
+}
+
+fn def_clear_sessions_interval_seconds() -> u64 {
+    5 * 60
+}
+
+fn def_odometer_interval_seconds() -> u64 {
+    5 * 60
+}
+
+fn def_phone_number_default_region() -> phonenumber::country::Id {
+    phonenumber::country::Id::BR
+}
+
+fn def_location_reject_null_island() -> bool {
+    true
+}
+
+fn def_location_downsample_resolution_seconds() -> u64 {
+    60
+}
+
+fn def_location_downsample_min_heading_change_degrees() -> f64 {
+    30.0
+}
+
+fn def_tracker_reporting_interval_seconds() -> i32 {
+    60
+}
+
+fn def_tracker_offline_after_missed_reports() -> i32 {
+    5
+}
+
+fn def_device_time_max_ahead_seconds() -> u64 {
+    300
+}
+
+fn def_device_time_max_behind_days() -> u64 {
+    30
+}
+
+fn def_unknown_imei_location_retention_hours() -> u64 {
+    72
+}
+
+fn def_unknown_imei_location_max_per_imei() -> u64 {
+    500
+}
+
+fn def_unknown_imei_location_max_rows() -> u64 {
+    100_000
+}
+
+fn def_socketio_allow_jwt_auth() -> bool {
+    true
+}
+
+fn def_tracker_events_queue_auto_delete() -> bool {
+    true
+}
+
+fn def_tracker_events_prefetch() -> u16 {
+    100
+}
+
+fn def_rmq_publish_channels() -> usize {
+    4
+}
+
+fn def_tracer_enabled() -> bool {
+    true
+}
+
+fn def_tracer_sample_ratio() -> f64 {
+    1.0
+}
+
+fn def_tracer_parent_based() -> bool {
+    true
+}
+
+fn has_url_scheme(url: &str, schemes: &[&str]) -> Result<(), ValidationError> {
+    match Url::parse(url) {
+        Ok(url) if schemes.contains(&url.scheme()) => Ok(()),
+        Ok(_) => {
+            let mut err = ValidationError::new("url_scheme");
+            err.message = Some(format!("url scheme must be one of: {}", schemes.join(", ")).into());
+            Err(err)
+        }
+        Err(e) => {
+            let mut err = ValidationError::new("url");
+            err.message = Some(format!("invalid url: {}", e).into());
+            Err(err)
+        }
+    }
+}
+
+fn is_postgres_url(url: &str) -> Result<(), ValidationError> {
+    has_url_scheme(url, &["postgres", "postgresql"])
+}
+
+fn is_amqp_uri(url: &str) -> Result<(), ValidationError> {
+    has_url_scheme(url, &["amqp", "amqps"])
+}
+
+fn is_http_url(url: &Url) -> Result<(), ValidationError> {
+    has_url_scheme(url.as_str(), &["http", "https"])
+}
+
+fn is_s3_key_prefix(prefix: &str) -> Result<(), ValidationError> {
+    if prefix.is_empty() || prefix.starts_with('/') || prefix.ends_with('/') {
+        let mut err = ValidationError::new("s3_key_prefix");
+        err.message = Some("must not be empty nor start or end with a slash".into());
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct AppConfig {
     /// if the application is running in `development` mode
     #[serde(default = "def_is_development")]
@@ -48,35 +250,300 @@ pub struct AppConfig {
 
     /// the application tenant
     #[serde(default = "def_tenant_slug")]
+    #[validate(length(min = 1, message = "must not be empty"))]
     pub tenant_slug: String,
 
     /// http port the api will listen for requests on
     #[serde(default = "def_http_port")]
+    #[validate(range(min = 1, message = "must be a port between 1 and 65535"))]
     pub http_port: u16,
 
+    /// ip address the api will listen for requests on, either IPv4 or IPv6, use
+    /// `0.0.0.0` (or `::`) to listen on every interface, eg: inside a container
+    #[serde(default = "def_http_host")]
+    pub http_host: IpAddr,
+
     /// postgres URL
     #[serde(default = "def_db_url")]
+    #[validate(custom = "is_postgres_url")]
     pub db_url: String,
 
     /// rabbitmq uri
     #[serde(default = "def_rmq_uri")]
+    #[validate(custom = "is_amqp_uri")]
     pub rmq_uri: String,
 
     /// rastercar frontend url, eg: https://rastercar.homolog.com for homolog environments etc
     #[serde(default = "def_frontend_url")]
+    #[validate(custom = "is_http_url")]
     pub frontend_url: Url,
 
     /// 256 bit secret used to generate Json Web Tokens
     #[serde(default = "def_jwt_secret")]
+    #[validate(length(min = 32, message = "must have at least 32 characters"))]
     pub jwt_secret: String,
 
     /// AWS region
     #[serde(default = "def_aws_region")]
+    #[validate(length(min = 1, message = "must not be empty"))]
     pub aws_region: String,
 
     /// AWS S3 bucket used for all uploads by the API
     #[serde(default = "def_aws_uploads_bucket_name")]
+    #[validate(length(min = 3, max = 63, message = "must have between 3 and 63 characters"))]
     pub aws_uploads_bucket_name: String,
+
+    /// prefix of the key of every object uploaded by the API (eg: `staging/rastercar`), so
+    /// multiple environments can share a bucket, defaults to the tenant slug
+    #[validate(custom = "is_s3_key_prefix")]
+    pub aws_uploads_key_prefix: Option<String>,
+
+    /// maximum amount of tracker IMEIs kept on the IMEI -> ID cache
+    #[serde(default = "def_tracker_id_cache_capacity")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub tracker_id_cache_capacity: usize,
+
+    /// seconds a tracker IMEI is kept on the IMEI -> ID cache, if not set
+    /// IMEIs only leave the cache when evicted or the tracker is changed
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub tracker_id_cache_ttl_seconds: Option<u64>,
+
+    /// decimal places tracker coordinates are rounded to before being stored, not set to
+    /// store them as received. Trackers report more precision than GPS accuracy provides,
+    /// rounding trades some accuracy for smaller and more compressible locations, as a
+    /// rule of thumb 4 decimal places are ~11m, 5 are ~1.1m and 6 are ~11cm at the equator
+    #[validate(range(max = 15, message = "must be between 0 and 15"))]
+    pub location_coordinate_decimals: Option<u32>,
+
+    /// a location at the same (rounded) coordinates of the tracker last location is not
+    /// stored if received within this many seconds of it, not set to store every location.
+    /// Reduces the locations of parked vehicles, at the cost of their last location time
+    /// being up to this many seconds old
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub location_dedupe_seconds: Option<u64>,
+
+    /// if locations at exactly (0, 0) are dropped, as trackers without a GPS fix often report
+    /// them. Enabled by default, disable it if trackers are used near the gulf of guinea
+    #[serde(default = "def_location_reject_null_island")]
+    pub location_reject_null_island: bool,
+
+    /// seconds the time reported by a tracker can be ahead of the time its location was
+    /// received before it is considered implausible, implausible device times are not
+    /// trusted and the location is stored at the time it was received instead
+    #[serde(default = "def_device_time_max_ahead_seconds")]
+    pub device_time_max_ahead_seconds: u64,
+
+    /// days the time reported by a tracker can be behind the time its location was received
+    /// before it is considered implausible, trackers buffer locations while offline so this
+    /// should be longer than they are expected to be offline, but it still catches trackers
+    /// whose clocks were reset (eg: to 2000-01-01 or by a GPS week rollover)
+    #[serde(default = "def_device_time_max_behind_days")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub device_time_max_behind_days: u64,
+
+    /// what happens to locations of trackers whose IMEI is not registered, `drop` (default),
+    /// `hold` or `auto_register`
+    #[serde(default)]
+    pub unknown_imei_policy: UnknownImeiPolicy,
+
+    /// organization trackers are registered on with `UNKNOWN_IMEI_POLICY=auto_register`,
+    /// required by said policy, the organization plan tracker limit is respected
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub unknown_imei_auto_register_org_id: Option<i32>,
+
+    /// hours locations of trackers whose IMEI is not registered are held for, so installers
+    /// can check a device is reporting and its locations can be backfilled once registered
+    #[serde(default = "def_unknown_imei_location_retention_hours")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub unknown_imei_location_retention_hours: u64,
+
+    /// maximum amount of held locations of a single unregistered IMEI, the oldest are deleted first
+    #[serde(default = "def_unknown_imei_location_max_per_imei")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub unknown_imei_location_max_per_imei: u64,
+
+    /// maximum amount of held locations of unregistered IMEIs, the oldest are deleted first
+    #[serde(default = "def_unknown_imei_location_max_rows")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub unknown_imei_location_max_rows: u64,
+
+    /// days after which tracker locations are downsampled to `LOCATION_DOWNSAMPLE_RESOLUTION_SECONDS`,
+    /// deleting the others for good, not set (the default) to keep every location forever
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub location_downsample_after_days: Option<u64>,
+
+    /// seconds between the locations kept when downsampling, locations where the vehicle
+    /// stops, starts moving or changes direction are kept regardless
+    #[serde(default = "def_location_downsample_resolution_seconds")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub location_downsample_resolution_seconds: u64,
+
+    /// degrees the direction of a moving vehicle must change at a location for it
+    /// to be kept when downsampling, `180` to only keep stops and the resolution
+    #[serde(default = "def_location_downsample_min_heading_change_degrees")]
+    #[validate(range(min = 0.0, max = 180.0, message = "must be between 0 and 180"))]
+    pub location_downsample_min_heading_change_degrees: f64,
+
+    /// seconds between the positions of trackers without a configured reporting interval
+    #[serde(default = "def_tracker_reporting_interval_seconds")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub tracker_reporting_interval_seconds: i32,
+
+    /// amount of reporting intervals a tracker can go without sending a position
+    /// before it is considered offline (and listed as stale)
+    #[serde(default = "def_tracker_offline_after_missed_reports")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub tracker_offline_after_missed_reports: i32,
+
+    /// timestamp the distance traveled by vehicles is bucketed into days by, `device` or `received`
+    #[serde(default)]
+    pub daily_distance_timestamp: LocationTimestamp,
+
+    /// bcrypt cost to hash passwords with, passwords hashed with a lower
+    /// cost are re-hashed with this cost when their users sign in
+    #[serde(default = "def_bcrypt_cost")]
+    #[validate(range(min = 4, max = 31, message = "must be between 4 and 31"))]
+    pub bcrypt_cost: u32,
+
+    /// maximum requests a client ip can make to the unauthenticated auth
+    /// routes (eg: sign in) per rate limit window
+    #[serde(default = "def_auth_rate_limit")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub auth_rate_limit: u32,
+
+    /// duration in seconds of the auth rate limit window
+    #[serde(default = "def_auth_rate_limit_window_seconds")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub auth_rate_limit_window_seconds: u64,
+
+    /// maximum requests a client ip can make to the API routes per rate limit window,
+    /// including the auth routes, which are also limited by `auth_rate_limit`
+    #[serde(default = "def_api_rate_limit")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub api_rate_limit: u32,
+
+    /// duration in seconds of the API rate limit window
+    #[serde(default = "def_api_rate_limit_window_seconds")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub api_rate_limit_window_seconds: u64,
+
+    /// days logins are kept on the users login history
+    #[serde(default = "def_login_history_retention_days")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub login_history_retention_days: u64,
+
+    /// days published outbox messages are kept, to investigate duplicated or missing events
+    #[serde(default = "def_outbox_retention_days")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub outbox_retention_days: u64,
+
+    /// milliseconds between each publishing of the pending outbox messages, a lower
+    /// interval delivers events sooner at the cost of more database queries
+    #[serde(default = "def_outbox_relay_interval_ms")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub outbox_relay_interval_ms: u64,
+
+    /// if expired sessions are periodically deleted, if disabled they are kept until
+    /// deleted on demand by a superuser (see `POST /auth/clear-expired-sessions`)
+    #[serde(default = "def_clear_sessions_enabled")]
+    pub clear_sessions_enabled: bool,
+
+    /// seconds between each deletion of the expired sessions
+    #[serde(default = "def_clear_sessions_interval_seconds")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub clear_sessions_interval_seconds: u64,
+
+    /// seconds between each update of the vehicle odometers from the tracker locations
+    #[serde(default = "def_odometer_interval_seconds")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub odometer_interval_seconds: u64,
+
+    /// maximum active sessions a user can have at once, not counting impersonation
+    /// sessions, if not set users can have any amount of sessions
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub max_sessions_per_user: Option<u64>,
+
+    /// what happens when a user with `MAX_SESSIONS_PER_USER` sessions signs in,
+    /// `evict_oldest` (default) or `reject_new`
+    #[serde(default)]
+    pub session_limit_policy: SessionLimitPolicy,
+
+    /// region of phone numbers sent without a country code (eg: `BR`), as ISO 3166-1 alpha-2
+    #[serde(default = "def_phone_number_default_region")]
+    pub phone_number_default_region: phonenumber::country::Id,
+
+    /// email address users of blocked organizations are told to contact to settle their billing
+    #[validate(email(message = "must be a valid email"))]
+    pub billing_contact_email: Option<String>,
+
+    /// if SocketIO connections can still be authenticated with a short lived JWT on the
+    /// handshake payload instead of a single use connection ticket, meant to be disabled
+    /// once every client authenticates with tickets
+    #[serde(default = "def_socketio_allow_jwt_auth")]
+    pub socketio_allow_jwt_auth: bool,
+
+    /// if the tracker events queue survives RabbitMQ restarts, only useful together with
+    /// `TRACKER_EVENTS_QUEUE_AUTO_DELETE=false`, as a auto deleted queue is gone as soon as
+    /// the API consumer disconnects
+    #[serde(default)]
+    pub tracker_events_queue_durable: bool,
+
+    /// if the tracker events queue is deleted when the API consumer disconnects, the default.
+    ///
+    /// this keeps RabbitMQ from piling up positions while the API is down, at the cost of
+    /// dropping every position sent while the API restarts, disable it to keep them instead
+    #[serde(default = "def_tracker_events_queue_auto_delete")]
+    pub tracker_events_queue_auto_delete: bool,
+
+    /// milliseconds a tracker event can wait on the queue before being discarded (or dead
+    /// lettered), recommended for non auto deleted queues so a long API downtime does not
+    /// fill RabbitMQ with stale positions, if not set events never expire
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub tracker_events_queue_ttl_ms: Option<u32>,
+
+    /// exchange that expired tracker events are sent to, if not set they are discarded.
+    ///
+    /// the exchange (and a queue bound to it) must already exist for the events to be kept
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub tracker_events_queue_dead_letter_exchange: Option<String>,
+
+    /// maximum unacknowledged tracker events delivered to the API consumer at once, `0`
+    /// means unlimited, higher values improve throughput at the cost of memory
+    #[serde(default = "def_tracker_events_prefetch")]
+    pub tracker_events_prefetch: u16,
+
+    /// amount of RabbitMQ channels messages are published on, publishes are distributed
+    /// between them in a round robin so concurrent publishes do not contend on one channel
+    #[serde(default = "def_rmq_publish_channels")]
+    #[validate(range(min = 1, max = 64, message = "must be between 1 and 64"))]
+    pub rmq_publish_channels: usize,
+
+    /// if tracing spans should be exported to jaeger
+    #[serde(default = "def_tracer_enabled")]
+    pub tracer_enabled: bool,
+
+    /// ratio of traces to sample, from 0 to 1
+    #[serde(default = "def_tracer_sample_ratio")]
+    #[validate(range(min = 0.0, max = 1.0, message = "must be between 0 and 1"))]
+    pub tracer_sample_ratio: f64,
+
+    /// if the sampling decision of parent spans (eg: from the decoder service) should be respected
+    #[serde(default = "def_tracer_parent_based")]
+    pub tracer_parent_based: bool,
+
+    /// seconds between each export of the service metrics to stdout, if not set metrics are not exported
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub metrics_export_interval_seconds: Option<u64>,
+
+    /// format of the logs written to stdout, `pretty` or `json`, ignored
+    /// on development where logs are always pretty printed
+    #[serde(default)]
+    pub log_format: LogFormat,
+
+    /// if the JSON bodies of requests and responses should be logged at debug level, with
+    /// sensitive fields redacted, ignored outside of development
+    #[serde(default)]
+    pub log_payloads: bool,
 }
 
 impl AppConfig {
@@ -87,12 +554,94 @@ impl AppConfig {
     /// cannot be parsed to the desired data type, eg:
     ///
     /// ENV_VAR_THAT_SHOULD_BE_BOOL=not_a_bool
+    ///
+    /// or if any loaded value is invalid, listing every invalid env var, eg:
+    ///
+    /// DB_URL=mysql://localhost
     pub fn from_env() -> AppConfig {
-        match envy::from_env::<AppConfig>() {
+        let config = match envy::from_env::<AppConfig>() {
             Ok(config) => config,
             Err(error) => {
                 panic!("[CFG] failed to load application config, {:#?}", error)
             }
+        };
+
+        if let Err(errors) = config.validate() {
+            panic!(
+                "[CFG] invalid application config\n{}",
+                shared::config::describe_validation_errors(&errors)
+            )
+        }
+
+        if config.unknown_imei_policy == UnknownImeiPolicy::AutoRegister
+            && config.unknown_imei_auto_register_org_id.is_none()
+        {
+            panic!(
+                "[CFG] invalid application config\n  UNKNOWN_IMEI_AUTO_REGISTER_ORG_ID: required when UNKNOWN_IMEI_POLICY is auto_register"
+            )
+        }
+
+        config
+    }
+}
+
+impl AppConfig {
+    /// seconds without positions after which a tracker with the given
+    /// reporting interval (`None` for the default one) is offline
+    pub fn tracker_offline_after_seconds(&self, reporting_interval_seconds: Option<i32>) -> i64 {
+        let interval =
+            reporting_interval_seconds.unwrap_or(self.tracker_reporting_interval_seconds);
+
+        i64::from(interval) * i64::from(self.tracker_offline_after_missed_reports)
+    }
+
+    /// the format of the logs written to stdout
+    pub fn log_format(&self) -> LogFormat {
+        if self.is_development {
+            LogFormat::Pretty
+        } else {
+            self.log_format
+        }
+    }
+
+    /// if request and response bodies should be logged, only ever on development
+    pub fn log_payloads(&self) -> bool {
+        self.is_development && self.log_payloads
+    }
+
+    /// the declaration options of the tracker events queue
+    ///
+    /// changing any of them requires deleting the existing queue, as RabbitMQ refuses
+    /// to redeclare a queue with different options
+    pub fn tracker_events_queue_options(&self) -> QueueOptions {
+        QueueOptions {
+            durable: self.tracker_events_queue_durable,
+            auto_delete: self.tracker_events_queue_auto_delete,
+            message_ttl_ms: self.tracker_events_queue_ttl_ms,
+            dead_letter_exchange: self.tracker_events_queue_dead_letter_exchange.clone(),
+        }
+    }
+
+    /// the address the api listens for requests on
+    pub fn http_addr(&self) -> SocketAddr {
+        SocketAddr::new(self.http_host, self.http_port)
+    }
+
+    /// the prefix of the key of every object uploaded by the API
+    pub fn uploads_key_prefix(&self) -> &str {
+        self.aws_uploads_key_prefix
+            .as_deref()
+            .unwrap_or(&self.tenant_slug)
+    }
+
+    pub fn tracing_opts(&self) -> shared::tracer::TracingOpts {
+        shared::tracer::TracingOpts {
+            enabled: self.tracer_enabled,
+            sample_ratio: self.tracer_sample_ratio,
+            parent_based: self.tracer_parent_based,
+            metrics_export_interval: self
+                .metrics_export_interval_seconds
+                .map(Duration::from_secs),
         }
     }
 }
@@ -115,3 +664,18 @@ pub async fn aws_config() -> &'static SdkConfig {
     static INSTANCE: OnceCell<SdkConfig> = OnceCell::const_new();
     INSTANCE.get_or_init(get_aws_config).await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn refuses_s3_key_prefixes_that_would_make_empty_key_segments() {
+        assert!(is_s3_key_prefix("rastercar").is_ok());
+        assert!(is_s3_key_prefix("staging/rastercar").is_ok());
+
+        assert!(is_s3_key_prefix("").is_err());
+        assert!(is_s3_key_prefix("/rastercar").is_err());
+        assert!(is_s3_key_prefix("rastercar/").is_err());
+    }
+}
@@ -1,11 +1,22 @@
+use crate::{
+    config::app_config,
+    modules::{
+        tracking::{compaction, utils::prune_unknown_imei_locations},
+        vehicle::{odometer, photo_upload},
+    },
+    rabbitmq::Rmq,
+    server::rate_limit::RateLimiters,
+    services::{outbox, s3::S3},
+};
 use chrono::Utc;
 use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
-use shared::entity::session;
-use std::time::Duration;
+use shared::entity::{login_history, session};
+use std::{sync::Arc, time::Duration};
+use tracing::{error, info};
 
 /// starts a tokio task that deletes all the expired user sessions every inteval
 pub fn start_clear_sessions_cronjob(db: DatabaseConnection, interval: Duration) {
-    println!("[CRON] clearing expired sessions every 5 minutes");
+    println!("[CRON] clearing expired sessions every {:?}", interval);
 
     tokio::spawn(async move {
         let mut interval = tokio::time::interval(interval);
@@ -13,10 +24,170 @@ pub fn start_clear_sessions_cronjob(db: DatabaseConnection, interval: Duration)
         loop {
             interval.tick().await;
 
-            let _ = session::Entity::delete_many()
-                .filter(session::Column::ExpiresAt.lt(Utc::now()))
+            match session::Entity::delete_expired(&db).await {
+                Ok(deleted) => info!("[CRON] deleted {} expired sessions", deleted),
+                Err(e) => error!("[CRON] failed to clear expired sessions: {}", e),
+            }
+        }
+    });
+}
+
+/// starts a tokio task that deletes the logins older than `LOGIN_HISTORY_RETENTION_DAYS` every interval
+pub fn start_clear_login_history_cronjob(db: DatabaseConnection, interval: Duration) {
+    println!("[CRON] clearing old login history every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let retention_days = app_config().login_history_retention_days as i64;
+
+            let result = login_history::Entity::delete_many()
+                .filter(
+                    login_history::Column::CreatedAt
+                        .lt(Utc::now() - chrono::Duration::days(retention_days)),
+                )
                 .exec(&db)
                 .await;
+
+            if let Err(e) = result {
+                error!("[CRON] failed to clear old login history: {}", e);
+            }
+        }
+    });
+}
+
+/// starts a tokio task that publishes the pending outbox messages every interval
+pub fn start_outbox_relay_cronjob(db: DatabaseConnection, rmq: Arc<Rmq>, interval: Duration) {
+    println!("[CRON] relaying outbox messages every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = outbox::relay_pending_messages(&db, &rmq).await {
+                error!("[CRON] failed to relay outbox messages: {}", e);
+            }
+        }
+    });
+}
+
+/// starts a tokio task that deletes the outbox messages published more than
+/// `OUTBOX_RETENTION_DAYS` ago every interval
+pub fn start_prune_outbox_cronjob(db: DatabaseConnection, interval: Duration) {
+    println!(
+        "[CRON] pruning published outbox messages every {:?}",
+        interval
+    );
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            let retention_days = app_config().outbox_retention_days as i64;
+            let published_before = Utc::now() - chrono::Duration::days(retention_days);
+
+            match outbox::prune_published_messages(&db, published_before).await {
+                Ok(deleted) => info!("[CRON] deleted {} published outbox messages", deleted),
+                Err(e) => error!("[CRON] failed to prune published outbox messages: {}", e),
+            }
+        }
+    });
+}
+
+/// starts a tokio task that accumulates the distance traveled by the vehicles every interval
+pub fn start_odometer_cronjob(db: DatabaseConnection, interval: Duration) {
+    println!("[CRON] updating vehicle odometers every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = odometer::update_odometers(&db).await {
+                error!("[CRON] failed to update vehicle odometers: {}", e);
+            }
+        }
+    });
+}
+
+/// starts a tokio task that deletes the held locations of unregistered IMEIs
+/// that are older than the retention or over the limits every interval
+pub fn start_prune_unknown_imei_locations_cronjob(db: DatabaseConnection, interval: Duration) {
+    println!(
+        "[CRON] pruning locations of unknown IMEIs every {:?}",
+        interval
+    );
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = prune_unknown_imei_locations(&db).await {
+                error!("[CRON] failed to prune locations of unknown IMEIs: {}", e);
+            }
+        }
+    });
+}
+
+/// starts a tokio task that downsamples the tracker locations older than
+/// `LOCATION_DOWNSAMPLE_AFTER_DAYS` every interval
+pub fn start_compact_locations_cronjob(db: DatabaseConnection, interval: Duration) {
+    println!("[CRON] downsampling old locations every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            match compaction::compact_old_locations(&db).await {
+                Ok(deleted) => info!("[CRON] downsampling deleted {} old locations", deleted),
+                Err(e) => error!("[CRON] failed to downsample old locations: {}", e),
+            }
+        }
+    });
+}
+
+/// starts a tokio task that aborts the stale multipart uploads of vehicle photos every interval
+pub fn start_abort_stale_uploads_cronjob(db: DatabaseConnection, s3: S3, interval: Duration) {
+    println!(
+        "[CRON] aborting stale multipart uploads every {:?}",
+        interval
+    );
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+
+            if let Err(e) = photo_upload::abort_stale_uploads(&db, &s3).await {
+                error!("[CRON] failed to abort stale multipart uploads: {}", e);
+            }
+        }
+    });
+}
+
+/// starts a tokio task that removes the ended windows of the rate limiters every interval
+pub fn start_prune_rate_limiters_cronjob(rate_limiters: RateLimiters, interval: Duration) {
+    println!("[CRON] pruning rate limiters every {:?}", interval);
+
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+
+        loop {
+            interval.tick().await;
+            rate_limiters.prune();
         }
     });
 }
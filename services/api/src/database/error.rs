@@ -1,5 +1,10 @@
-use crate::modules::common::responses::{internal_error_res, SimpleError};
-use convert_case::{Case, Casing};
+use crate::modules::common::{
+    error_codes::{
+        EMAIL_IN_USE, IMEI_IN_USE, PHONE_NUMBER_IN_USE, PLATE_IN_USE, SSN_IN_USE, TRACKER_IN_USE,
+        USERNAME_IN_USE, VEHICLE_IN_USE,
+    },
+    responses::{internal_error_res, SimpleError},
+};
 use http::StatusCode;
 use sea_orm::{DbErr, RuntimeErr, SqlxError};
 
@@ -31,37 +36,266 @@ impl From<DbError> for (StatusCode, SimpleError) {
     }
 }
 
+/// Error codes of every unique constraint of the database, constraints without a error code
+/// are not violated by user input (eg: tokens) and are handled as internal errors.
+///
+/// codes are not derived from the constraint names so only registered error codes are sent
+static UNIQUE_CONSTRAINT_ERROR_CODES: [(&str, Option<&str>); 15] = [
+    ("organization_billing_email_unique", Some(EMAIL_IN_USE)),
+    ("organization_owner_id_unique", None),
+    ("user_email_unique", Some(EMAIL_IN_USE)),
+    ("user_username_unique", Some(USERNAME_IN_USE)),
+    ("user_reset_password_token_unique", None),
+    ("user_confirm_email_token_unique", None),
+    ("user_magic_link_token_unique", None),
+    ("vehicle_plate_unique", Some(PLATE_IN_USE)),
+    ("vehicle_tracker_imei_unique", Some(IMEI_IN_USE)),
+    ("vehicle_tracker_vehicle_id_unique", Some(VEHICLE_IN_USE)),
+    ("vehicle_tracker_id_organization_id_unique", None),
+    (
+        "vehicle_tracker_assignment_open_tracker_unique",
+        Some(TRACKER_IN_USE),
+    ),
+    (
+        "vehicle_tracker_last_location_vehicle_tracker_id_unique",
+        None,
+    ),
+    ("sim_card_ssn_unique", Some(SSN_IN_USE)),
+    ("sim_card_phone_number_unique", Some(PHONE_NUMBER_IN_USE)),
+];
+
 fn handle_sqlx_error(sqlx_error: SqlxError) -> (StatusCode, SimpleError) {
     match sqlx_error {
-        SqlxError::Database(e) => {
-            if !e.is_unique_violation() {
-                return internal_error_res();
-            }
+        SqlxError::Database(e) if e.is_unique_violation() => e
+            .constraint()
+            .and_then(unique_constraint_error_code)
+            .map(|code| (StatusCode::BAD_REQUEST, SimpleError::from(code)))
+            .unwrap_or_else(internal_error_res),
+        _ => internal_error_res(),
+    }
+}
+
+/// the error code of a violated unique constraint, if it is violated by user input
+fn unique_constraint_error_code(constraint: &str) -> Option<&'static str> {
+    UNIQUE_CONSTRAINT_ERROR_CODES
+        .iter()
+        .find(|(name, _)| *name == constraint)
+        .and_then(|(_, code)| *code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+    use crate::modules::common::error_codes::ERROR_CODES;
+    use migration::seeder;
+    use regex::Regex;
+    use sea_orm::{ActiveModelTrait, EntityTrait, Set, TransactionTrait};
+    use shared::entity::{sim_card, vehicle_tracker};
+    use sqlx::error::{DatabaseError, ErrorKind};
+    use std::{borrow::Cow, error::Error, fmt, fs};
+
+    /// a unique violation of `constraint` as reported by the database driver
+    #[derive(Debug)]
+    struct UniqueViolation(&'static str);
+
+    impl fmt::Display for UniqueViolation {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(
+                f,
+                "duplicate key value violates unique constraint {}",
+                self.0
+            )
+        }
+    }
+
+    impl Error for UniqueViolation {}
+
+    impl DatabaseError for UniqueViolation {
+        fn message(&self) -> &str {
+            "duplicate key value violates unique constraint"
+        }
+
+        fn code(&self) -> Option<Cow<'_, str>> {
+            Some(Cow::Borrowed("23505"))
+        }
+
+        fn as_error(&self) -> &(dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn as_error_mut(&mut self) -> &mut (dyn Error + Send + Sync + 'static) {
+            self
+        }
+
+        fn into_error(self: Box<Self>) -> Box<dyn Error + Send + Sync + 'static> {
+            self
+        }
+
+        fn constraint(&self) -> Option<&str> {
+            Some(self.0)
+        }
+
+        fn kind(&self) -> ErrorKind {
+            ErrorKind::UniqueViolation
+        }
+    }
+
+    /// the response of a failed query, with the error message of its body
+    fn error_response(err: DbErr) -> (StatusCode, String) {
+        let (status, body) = <(StatusCode, SimpleError)>::from(DbError::from(err));
 
-            if let Some(constraint) = e.constraint() {
-                if let Some(column_name) = get_column_name_from_unique_constraint_name(constraint) {
-                    let snake_cased_col_name = column_name.to_case(Case::ScreamingSnake);
+        let body = serde_json::to_value(body).unwrap();
 
-                    let error_msg = format!("{}_IN_USE", snake_cased_col_name);
+        (status, body["error"].as_str().unwrap().to_owned())
+    }
+
+    /// the response of a query failing with a unique violation of `constraint`
+    fn unique_violation_response(constraint: &'static str) -> (StatusCode, String) {
+        let err = SqlxError::Database(Box::new(UniqueViolation(constraint)));
+
+        error_response(DbErr::Exec(RuntimeErr::SqlxError(err)))
+    }
 
-                    return (StatusCode::BAD_REQUEST, SimpleError::from(error_msg));
-                }
+    #[test]
+    fn unique_constraint_error_codes_are_registered() {
+        for (constraint, code) in UNIQUE_CONSTRAINT_ERROR_CODES.iter() {
+            if let Some(code) = code {
+                assert!(
+                    ERROR_CODES
+                        .iter()
+                        .any(|error_code| error_code.code == *code),
+                    "{constraint} has the unregistered error code {code}"
+                );
             }
+        }
+    }
+
+    #[test]
+    fn every_unique_constraint_of_the_migrations_is_mapped() {
+        let unique_constraint = Regex::new(r#""(\w+_unique)""#).unwrap();
+        let migrations_dir = concat!(env!("CARGO_MANIFEST_DIR"), "/../migration/src");
+
+        for entry in fs::read_dir(migrations_dir).unwrap() {
+            let migration = fs::read_to_string(entry.unwrap().path()).unwrap();
 
-            internal_error_res()
+            for captures in unique_constraint.captures_iter(&migration) {
+                let constraint = &captures[1];
+
+                assert!(
+                    UNIQUE_CONSTRAINT_ERROR_CODES
+                        .iter()
+                        .any(|(name, _)| *name == constraint),
+                    "unique constraint {constraint} is not in UNIQUE_CONSTRAINT_ERROR_CODES"
+                );
+            }
         }
-        _ => internal_error_res(),
     }
-}
 
-/// Extracts the column name from the name of a database unique constraint.
-/// assuming the naming pattern: `<table_name>_<column>_unique`.
-///
-/// returns `Some(<column>)` if the pattern is ok otherwise `None`.
-fn get_column_name_from_unique_constraint_name(unique_constraint_name: &str) -> Option<&str> {
-    if let Some(non_suffixed_constraint_name) = unique_constraint_name.strip_suffix("_unique") {
-        return non_suffixed_constraint_name.split('_').last();
+    #[test]
+    fn unknown_unique_constraints_are_internal_errors() {
+        assert_eq!(
+            unique_constraint_error_code("vehicle_plate_unique"),
+            Some(PLATE_IN_USE)
+        );
+        assert_eq!(
+            unique_constraint_error_code("user_magic_link_token_unique"),
+            None
+        );
+        assert_eq!(unique_constraint_error_code("vehicle_name_unique"), None);
+    }
+
+    #[test]
+    fn user_username_and_email_violations_get_the_in_use_codes() {
+        assert_eq!(
+            unique_violation_response("user_username_unique"),
+            (StatusCode::BAD_REQUEST, USERNAME_IN_USE.to_owned())
+        );
+        assert_eq!(
+            unique_violation_response("user_email_unique"),
+            (StatusCode::BAD_REQUEST, EMAIL_IN_USE.to_owned())
+        );
     }
 
-    None
+    #[test]
+    fn tracker_and_sim_card_violations_get_the_in_use_codes() {
+        let bad_request = |code: &str| (StatusCode::BAD_REQUEST, code.to_owned());
+
+        assert_eq!(
+            unique_violation_response("vehicle_tracker_imei_unique"),
+            bad_request(IMEI_IN_USE)
+        );
+        assert_eq!(
+            unique_violation_response("sim_card_ssn_unique"),
+            bad_request(SSN_IN_USE)
+        );
+        assert_eq!(
+            unique_violation_response("sim_card_phone_number_unique"),
+            bad_request(PHONE_NUMBER_IN_USE)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database with PostGIS and TimescaleDB, see database::test_db"]
+    async fn duplicate_tracker_and_sim_card_inserts_get_the_in_use_codes() {
+        let txn = test_db::begin().await;
+
+        let org_id = seeder::gen_organization(&txn).await.unwrap();
+        let tracker_id = seeder::gen_tracker(&txn, org_id, None).await.unwrap();
+        let sim_card_id = seeder::gen_sim_card(&txn, org_id, None).await.unwrap();
+
+        let tracker = vehicle_tracker::Entity::find_by_id(tracker_id)
+            .one(&txn)
+            .await
+            .unwrap()
+            .unwrap();
+
+        let sim_card = sim_card::Entity::find_by_id(sim_card_id)
+            .one(&txn)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // each insert runs on a savepoint, as a failed statement aborts the transaction
+        let duplicate_tracker = vehicle_tracker::ActiveModel {
+            model: Set(tracker.model),
+            imei: Set(tracker.imei),
+            organization_id: Set(org_id),
+            ..Default::default()
+        };
+
+        let savepoint = txn.begin().await.unwrap();
+        let err = duplicate_tracker.insert(&savepoint).await.unwrap_err();
+        savepoint.rollback().await.unwrap();
+
+        assert_eq!(
+            error_response(err),
+            (StatusCode::BAD_REQUEST, IMEI_IN_USE.to_owned())
+        );
+
+        let duplicate_sim_card = |ssn: String, phone_number: String| sim_card::ActiveModel {
+            ssn: Set(ssn),
+            phone_number: Set(phone_number),
+            apn_user: Set(sim_card.apn_user.clone()),
+            apn_address: Set(sim_card.apn_address.clone()),
+            apn_password: Set(sim_card.apn_password.clone()),
+            organization_id: Set(org_id),
+            ..Default::default()
+        };
+
+        let same_ssn = duplicate_sim_card(sim_card.ssn.clone(), String::from("+5511900000001"));
+        let same_phone =
+            duplicate_sim_card(String::from("0099999999"), sim_card.phone_number.clone());
+
+        for (duplicate, code) in [(same_ssn, SSN_IN_USE), (same_phone, PHONE_NUMBER_IN_USE)] {
+            let savepoint = txn.begin().await.unwrap();
+            let err = duplicate.insert(&savepoint).await.unwrap_err();
+            savepoint.rollback().await.unwrap();
+
+            assert_eq!(
+                error_response(err),
+                (StatusCode::BAD_REQUEST, code.to_owned())
+            );
+        }
+    }
 }
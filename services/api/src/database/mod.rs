@@ -1,3 +1,6 @@
 pub mod db;
 pub mod error;
 pub mod helpers;
+
+#[cfg(test)]
+pub mod test_db;
@@ -0,0 +1,33 @@
+//! Database used by the tests that need postgres, since the migrations need the
+//! PostGIS and TimescaleDB extensions those tests are ignored by default, to run them
+//! point `TEST_DATABASE_URL` to a disposable database and run `cargo test -- --ignored`
+//!
+//! tests should seed their data on a transaction from [`begin`] and never commit it,
+//! so the database is left as it was and the tests can run concurrently.
+
+use super::db;
+use sea_orm::{DatabaseConnection, DatabaseTransaction, TransactionTrait};
+use tokio::sync::OnceCell;
+
+/// connects to the test database, running the migrations on the first connection
+pub async fn connect() -> DatabaseConnection {
+    static MIGRATED: OnceCell<()> = OnceCell::const_new();
+
+    let db_url = std::env::var("TEST_DATABASE_URL")
+        .expect("TEST_DATABASE_URL must be set to run the database tests");
+
+    let db = db::connect(&db_url).await;
+
+    MIGRATED.get_or_init(|| db::run_migrations(&db)).await;
+
+    db
+}
+
+/// connects to the test database and begins a transaction to seed the test data on
+pub async fn begin() -> DatabaseTransaction {
+    connect()
+        .await
+        .begin()
+        .await
+        .expect("failed to begin test transaction")
+}
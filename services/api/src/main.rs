@@ -8,36 +8,87 @@ mod services;
 mod tracer;
 mod utils;
 
-use crate::{modules::tracking::cache::TrackerIdCache, services::s3::S3};
+use crate::{
+    modules::tracking::cache::TrackerIdCache,
+    server::rate_limit::RateLimiters,
+    services::{s3::S3, ses::Ses},
+};
 use config::app_config;
 use sea_orm::DatabaseConnection;
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
 };
-use std::sync::Arc;
-use std::{
-    net::{IpAddr, Ipv4Addr, SocketAddr},
-    time::Duration,
-};
+use std::{net::SocketAddr, time::Duration};
+use std::{num::NonZeroUsize, sync::Arc};
 use tokio::{sync::RwLock, task};
 
 #[tokio::main]
 pub async fn main() {
+    let args: Vec<String> = std::env::args().collect();
+
+    // `api openapi <path>` only exports the openapi docs, so it must
+    // run before loading the config or connecting to any service
+    if args.get(1).is_some_and(|cmd| cmd == "openapi") {
+        let path = args.get(2).map_or("openapi.json", String::as_str);
+
+        match server::open_api::write_openapi_doc(path) {
+            Ok(_) => println!("[APP] openapi docs written to {}", path),
+            Err(e) => {
+                eprintln!("[APP] {}", e);
+                std::process::exit(1)
+            }
+        }
+
+        return;
+    }
+
+    // loading the config validates it, so invalid env vars are
+    // reported before attempting to connect to any service
     let cfg = app_config();
 
-    tracer::init("rastercar_api", cfg.is_development).expect("failed to init tracer");
+    tracer::init("rastercar_api", cfg.log_format(), &cfg.tracing_opts())
+        .expect("failed to init tracer");
 
     let db = database::db::connect(&cfg.db_url).await;
 
-    modules::globals::TRACKER_ID_CACHE
-        .get_or_init(|| Arc::new(RwLock::new(TrackerIdCache::new(db.clone()))));
+    modules::globals::TRACKER_ID_CACHE.get_or_init(|| {
+        let capacity = NonZeroUsize::new(cfg.tracker_id_cache_capacity)
+            .expect("TRACKER_ID_CACHE_CAPACITY must be greater than 0");
 
-    database::db::run_migrations(&db).await;
+        let ttl = cfg.tracker_id_cache_ttl_seconds.map(Duration::from_secs);
+
+        Arc::new(RwLock::new(TrackerIdCache::new(db.clone(), capacity, ttl)))
+    });
 
-    cronjobs::start_clear_sessions_cronjob(db.clone(), Duration::from_secs(5 * 60));
+    database::db::run_migrations(&db).await;
 
-    let rmq = Arc::new(rabbitmq::Rmq::new(&cfg.rmq_uri).await);
+    if cfg.clear_sessions_enabled {
+        cronjobs::start_clear_sessions_cronjob(
+            db.clone(),
+            Duration::from_secs(cfg.clear_sessions_interval_seconds),
+        );
+    }
+
+    cronjobs::start_clear_login_history_cronjob(db.clone(), Duration::from_secs(60 * 60));
+    cronjobs::start_odometer_cronjob(
+        db.clone(),
+        Duration::from_secs(cfg.odometer_interval_seconds),
+    );
+    cronjobs::start_prune_unknown_imei_locations_cronjob(db.clone(), Duration::from_secs(60));
+
+    if cfg.location_downsample_after_days.is_some() {
+        cronjobs::start_compact_locations_cronjob(db.clone(), Duration::from_secs(10 * 60));
+    }
+
+    let rmq = Arc::new(
+        rabbitmq::Rmq::new(
+            &cfg.rmq_uri,
+            cfg.tracker_events_queue_options(),
+            cfg.rmq_publish_channels,
+        )
+        .await,
+    );
     let rmq_reconnect_ref = rmq.clone();
     let rmq_shutdown_ref = rmq.clone();
 
@@ -45,6 +96,13 @@ pub async fn main() {
         rmq_reconnect_ref.start_reconnection_task().await;
     });
 
+    cronjobs::start_outbox_relay_cronjob(
+        db.clone(),
+        rmq.clone(),
+        Duration::from_millis(cfg.outbox_relay_interval_ms),
+    );
+    cronjobs::start_prune_outbox_cronjob(db.clone(), Duration::from_secs(60 * 60));
+
     let db_conn_pool_shutdown_ref = db.clone();
 
     listen_to_shutdown_signals(
@@ -53,17 +111,28 @@ pub async fn main() {
         db_conn_pool_shutdown_ref,
     );
 
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), cfg.http_port);
+    let addr = cfg.http_addr();
     println!("[WEB] soon listening on {}", addr);
 
     let s3 = S3::new().await;
+    let ses = Ses::new().await;
+
+    cronjobs::start_abort_stale_uploads_cronjob(
+        db.clone(),
+        s3.clone(),
+        Duration::from_secs(60 * 60),
+    );
 
     let listener = tokio::net::TcpListener::bind(&addr)
         .await
         .unwrap_or_else(|_| panic!("[WEB] failed to get address {}", addr));
 
-    let server =
-        server::controller::new(db, s3, rmq).into_make_service_with_connect_info::<SocketAddr>();
+    let rate_limiters = RateLimiters::from_config();
+
+    cronjobs::start_prune_rate_limiters_cronjob(rate_limiters.clone(), Duration::from_secs(60));
+
+    let server = server::controller::new(db, s3, ses, rmq, rate_limiters)
+        .into_make_service_with_connect_info::<SocketAddr>();
 
     axum::serve(listener, server)
         .await
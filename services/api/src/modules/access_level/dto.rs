@@ -8,12 +8,18 @@ use validator::{Validate, ValidationError};
 fn is_known_permissions(permissions: &[String]) -> Result<(), ValidationError> {
     let allowed_permissions = Permission::to_string_vec();
 
-    let permissions_are_allowed = permissions
+    let unknown_permissions: Vec<&str> = permissions
         .iter()
-        .all(|permission| allowed_permissions.contains(permission));
+        .filter(|permission| !allowed_permissions.contains(permission))
+        .map(String::as_str)
+        .collect();
 
-    if !permissions_are_allowed {
-        return Err(ValidationError::new("permission not allowed"));
+    if !unknown_permissions.is_empty() {
+        let mut err = ValidationError::new("permission not allowed");
+        err.message =
+            Some(format!("unknown permissions: {}", unknown_permissions.join(", ")).into());
+
+        return Err(err);
     }
 
     Ok(())
@@ -45,6 +51,37 @@ pub struct UpdateAccessLevelDto {
     pub permissions: Option<Vec<String>>,
 }
 
+/// A portable access level, to replicate access levels across organizations
+#[derive(Serialize, Deserialize, Clone, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLevelTemplateDto {
+    #[validate(length(min = 1))]
+    pub name: String,
+    pub description: String,
+    #[validate(custom = "is_known_permissions")]
+    pub permissions: Vec<String>,
+}
+
+impl From<entity::access_level::Model> for AccessLevelTemplateDto {
+    fn from(m: entity::access_level::Model) -> Self {
+        Self {
+            name: m.name,
+            description: m.description,
+            permissions: m.permissions,
+        }
+    }
+}
+
+/// A access level with the amount of users assigned to it
+#[derive(Serialize, Clone, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AccessLevelUsageDto {
+    pub id: i32,
+    pub name: String,
+    pub is_fixed: bool,
+    pub user_count: i64,
+}
+
 #[derive(Serialize, Clone, ToSchema)]
 #[serde(rename_all = "camelCase")]
 #[schema(as = access_level::dto::AccessLevelDto)]
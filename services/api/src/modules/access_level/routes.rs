@@ -1,5 +1,6 @@
 use super::dto::{
-    self, AccessLevelDto, CreateAccessLevelDto, ListAccessLevelsDto, UpdateAccessLevelDto,
+    self, AccessLevelDto, AccessLevelTemplateDto, AccessLevelUsageDto, CreateAccessLevelDto,
+    ListAccessLevelsDto, UpdateAccessLevelDto,
 };
 use crate::database::error::DbError;
 use crate::database::helpers::set_if_some;
@@ -20,8 +21,8 @@ use axum::{
     Json, Router,
 };
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
-    QuerySelect, QueryTrait, Set,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DbErr, EntityTrait, FromQueryResult, JoinType,
+    PaginatorTrait, QueryFilter, QueryOrder, QuerySelect, QueryTrait, RelationTrait, Set,
 };
 use sea_query::extension::postgres::PgExpr;
 use sea_query::Expr;
@@ -31,12 +32,24 @@ use shared::entity::{access_level, user};
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/", get(list_access_level))
+        .route("/usage", get(list_access_level_usage))
         .route(
             "/",
             post(create_access_level)
                 .route_layer(AclLayer::single(Permission::ManageUserAccessLevels)),
         )
+        .route(
+            "/sync-fixed-permissions",
+            post(sync_fixed_access_level_permissions)
+                .route_layer(AclLayer::single(Permission::ManageUserAccessLevels)),
+        )
+        .route(
+            "/import",
+            post(import_access_level)
+                .route_layer(AclLayer::single(Permission::ManageUserAccessLevels)),
+        )
         .route("/:access_level_id", get(access_level_by_id))
+        .route("/:access_level_id/export", get(export_access_level))
         .route(
             "/:access_level_id",
             put(update_access_level)
@@ -114,6 +127,95 @@ pub async fn list_access_level(
     Ok(Json(result))
 }
 
+/// A access level row with the amount of users assigned to it
+#[derive(FromQueryResult)]
+struct AccessLevelUsageRow {
+    id: i32,
+    name: String,
+    is_fixed: bool,
+    user_count: i64,
+}
+
+/// List access levels usage
+///
+/// lists the access levels of the request user organization with the amount of users
+/// assigned to each of them, so its known beforehand if they can be deleted and how many
+/// users are affected by editing them
+#[utoipa::path(
+    get,
+    tag = "access-level",
+    path = "/access-level/usage",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListAccessLevelsDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of access levels and their user counts",
+            content_type = "application/json",
+            body = PaginatedAccessLevelUsage,
+        ),
+    ),
+)]
+pub async fn list_access_level_usage(
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListAccessLevelsDto>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<AccessLevelUsageDto>>, (StatusCode, SimpleError)> {
+    let paginator = access_level::Entity::find()
+        .select_only()
+        .column(access_level::Column::Id)
+        .column(access_level::Column::Name)
+        .column(access_level::Column::IsFixed)
+        .column_as(
+            Expr::col((user::Entity, user::Column::Id)).count(),
+            "user_count",
+        )
+        .join(JoinType::LeftJoin, access_level::Relation::User.def())
+        .filter(access_level::Column::OrganizationId.eq(org_id))
+        .apply_if(filter.name, |query, name| {
+            if !name.is_empty() {
+                let col = Expr::col((access_level::Entity, access_level::Column::Name));
+                query.filter(col.ilike(format!("%{}%", name)))
+            } else {
+                query
+            }
+        })
+        .group_by(access_level::Column::Id)
+        .order_by_asc(access_level::Column::Id)
+        .into_model::<AccessLevelUsageRow>()
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|row| AccessLevelUsageDto {
+            id: row.id,
+            name: row.name,
+            is_fixed: row.is_fixed,
+            user_count: row.user_count,
+        })
+        .collect();
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
 /// Get a access level by id
 #[utoipa::path(
     get,
@@ -177,6 +279,83 @@ pub async fn create_access_level(
     Ok(Json(created_access_level))
 }
 
+/// Export a access level as a template
+///
+/// returns a portable access level, without ids or timestamps, that can be imported
+/// on other organizations to replicate it (see `POST /access-level/import`)
+#[utoipa::path(
+    get,
+    tag = "access-level",
+    path = "/access-level/{access_level_id}/export",
+    security(("session_id" = [])),
+    params(
+        ("access_level_id" = u128, Path, description = "id of the access level to export"),
+    ),
+    responses(
+        (
+            status = OK,
+            content_type = "application/json",
+            body = AccessLevelTemplateDto,
+        ),
+    ),
+)]
+pub async fn export_access_level(
+    OrgBoundEntityFromPathId(v): OrgBoundEntityFromPathId<access_level::Entity>,
+) -> Result<Json<AccessLevelTemplateDto>, (StatusCode, SimpleError)> {
+    Ok(Json(AccessLevelTemplateDto::from(v)))
+}
+
+/// Import a access level template
+///
+/// Required permissions: MANAGE_USER_ACCESS_LEVELS
+///
+/// creates a access level on the request user organization from a template exported
+/// with `GET /access-level/{access_level_id}/export`, templates with unknown permissions
+/// are rejected so typos are not silently ignored
+#[utoipa::path(
+    post,
+    tag = "access-level",
+    path = "/access-level/import",
+    security(("session_id" = [])),
+    request_body(content = AccessLevelTemplateDto, content_type = "application/json"),
+    responses(
+        (
+            status = OK,
+            content_type = "application/json",
+            body = access_level::dto::AccessLevelDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid template, eg: with unknown permissions / VALIDATION",
+            body = crate::modules::common::responses::ValidationErrorResponse,
+        ),
+    ),
+)]
+pub async fn import_access_level(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<AccessLevelTemplateDto>,
+) -> Result<Json<AccessLevelDto>, (StatusCode, SimpleError)> {
+    let mut permissions = dto.permissions;
+    permissions.sort();
+    permissions.dedup();
+
+    let imported_access_level: AccessLevelDto = access_level::ActiveModel {
+        name: Set(dto.name),
+        description: Set(dto.description),
+        permissions: Set(permissions),
+        is_fixed: Set(false),
+        organization_id: Set(Some(org_id)),
+        ..Default::default()
+    }
+    .insert(&db)
+    .await
+    .map_err(DbError::from)?
+    .into();
+
+    Ok(Json(imported_access_level))
+}
+
 /// Update a access level
 ///
 /// Required permissions: MANAGE_USER_ACCESS_LEVELS
@@ -259,6 +438,11 @@ pub async fn update_access_level(
             content_type = "application/json",
             example = json!("Access level deleted successfully"),
         ),
+        (
+            status = NOT_FOUND,
+            description = "access level not found",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn delete_access_level(
@@ -274,10 +458,7 @@ pub async fn delete_access_level(
         ));
     }
 
-    let access_level_to_delete = access_level::Entity::find()
-        .filter(access_level::Column::OrganizationId.eq(org_id))
-        .filter(access_level::Column::Id.eq(access_level_id))
-        .one(&db)
+    let access_level_to_delete = find_org_access_level(&db, access_level_id, org_id)
         .await
         .map_err(DbError::from)?
         .ok_or((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))?;
@@ -314,9 +495,111 @@ pub async fn delete_access_level(
         .map_err(DbError::from)?;
 
     if delete_result.rows_affected < 1 {
-        let err_msg = "Access level not exist or does not belong to the request user organization";
-        Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)))
+        Err((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))
     } else {
         Ok(Json(String::from("access level deleted successfully")))
     }
 }
+
+/// finds the access level of the organization, access levels of other
+/// organizations and the ones without organization are not found
+async fn find_org_access_level<C: ConnectionTrait>(
+    db: &C,
+    access_level_id: i32,
+    org_id: i32,
+) -> Result<Option<access_level::Model>, DbErr> {
+    access_level::Entity::find()
+        .filter(access_level::Column::OrganizationId.eq(org_id))
+        .filter(access_level::Column::Id.eq(access_level_id))
+        .one(db)
+        .await
+}
+
+/// Syncs the permissions of fixed access levels
+///
+/// Sets the permissions of the fixed (root) access levels to every existing permission,
+/// so they are granted permissions created after they were. For organization users only the
+/// fixed access levels of their organization are synced, for superusers all of them are.
+///
+/// Non fixed access levels are never changed and running this multiple times has no effect
+#[utoipa::path(
+    post,
+    tag = "access-level",
+    path = "/access-level/sync-fixed-permissions",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "success message",
+            body = String,
+            content_type = "application/json",
+            example = json!("1 fixed access levels synced"),
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user lacks permissions",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn sync_fixed_access_level_permissions(
+    Extension(req_user): Extension<RequestUser>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<String>, (StatusCode, SimpleError)> {
+    let org_id = req_user.get_org_id();
+
+    let all_permissions = Permission::to_string_vec();
+
+    let update_result = access_level::Entity::update_many()
+        .col_expr(
+            access_level::Column::Permissions,
+            Expr::value(all_permissions.clone()),
+        )
+        .filter(access_level::Column::IsFixed.eq(true))
+        .filter(Expr::col(access_level::Column::Permissions).ne(all_permissions))
+        .apply_if(org_id, |query, id| {
+            query.filter(access_level::Column::OrganizationId.eq(id))
+        })
+        .exec(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(format!(
+        "{} fixed access levels synced",
+        update_result.rows_affected
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+    use migration::seeder;
+
+    #[tokio::test]
+    #[ignore = "needs a database with PostGIS and TimescaleDB, see database::test_db"]
+    async fn access_levels_of_other_organizations_are_not_found() {
+        let txn = test_db::begin().await;
+
+        let org_id = seeder::gen_organization(&txn).await.unwrap();
+        let other_org_id = seeder::gen_organization(&txn).await.unwrap();
+        let access_level_id = seeder::gen_access_level(&txn, false, Some(org_id), vec![])
+            .await
+            .unwrap();
+
+        let found = find_org_access_level(&txn, access_level_id, other_org_id)
+            .await
+            .unwrap();
+
+        assert_eq!(found, None);
+
+        let found = find_org_access_level(&txn, access_level_id, org_id)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            found.map(|access_level| access_level.id),
+            Some(access_level_id)
+        );
+    }
+}
@@ -5,11 +5,12 @@ use crate::modules::{
         REGEX_CONTAINS_SYMBOLIC_CHARACTER, REGEX_CONTAINS_UPPERCASE_CHARACTER,
         REGEX_IS_LOWERCASE_ALPHANUMERIC_WITH_UNDERSCORES,
     },
+    user::dto::SimpleUserDto,
 };
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use shared::entity;
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
 // --- INPUT
@@ -82,12 +83,55 @@ pub struct ResetPassword {
     pub password_reset_token: String,
 }
 
+/// The kind of a token sent by email to a user
+#[derive(Deserialize, Serialize, ToSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum UserTokenKind {
+    /// token of a recover password email
+    PasswordReset,
+    /// token of a email address confirmation email
+    EmailConfirmation,
+    /// token of a magic link sign in email
+    MagicLink,
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidateToken {
+    #[validate(length(min = 5))]
+    pub token: String,
+
+    pub kind: UserTokenKind,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct ListImpersonationLogDto {
+    /// filter records by the superuser that impersonated
+    pub impersonator_id: Option<i32>,
+
+    /// filter records by the impersonated user
+    pub impersonated_user_id: Option<i32>,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct ListOrgSessionsDto {
+    /// filter sessions by user
+    pub user_id: Option<i32>,
+}
+
 // --- OUTPUT
 
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct SignInResponse {
     pub user: UserDto,
+
+    /// sessions of the user deleted because it reached the maximum amount of sessions
+    pub evicted_sessions: Vec<SessionDto>,
 }
 
 #[derive(Serialize, ToSchema)]
@@ -101,6 +145,61 @@ pub struct SessionDto {
 
     /// if this session is the same that was used on the request that is returning this
     pub same_as_from_request: bool,
+
+    /// id of the superuser impersonating the session user, `null` for regular sessions
+    pub impersonator_id: Option<i32>,
+}
+
+/// A active session of a organization user
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrgSessionDto {
+    pub session: SessionDto,
+    pub user: SimpleUserDto,
+}
+
+/// Amount of expired sessions deleted
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ClearedSessionsDto {
+    pub deleted: u64,
+}
+
+/// Why a token is not valid
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum InvalidTokenReason {
+    /// the token is not a JWT signed by the API
+    Malformed,
+    /// the token expiration date has passed
+    Expired,
+    /// the token was already used or replaced by a newer one
+    NotFound,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TokenValidationDto {
+    pub valid: bool,
+
+    /// why the token is not valid, `null` for valid tokens
+    pub reason: Option<InvalidTokenReason>,
+}
+
+impl TokenValidationDto {
+    pub fn valid() -> Self {
+        Self {
+            valid: true,
+            reason: None,
+        }
+    }
+
+    pub fn invalid(reason: InvalidTokenReason) -> Self {
+        Self {
+            valid: false,
+            reason: Some(reason),
+        }
+    }
 }
 
 #[derive(Serialize, Clone, ToSchema)]
@@ -113,6 +212,15 @@ pub struct OrganizationDto {
     pub owner_id: Option<i32>,
     pub billing_email: String,
     pub billing_email_verified: bool,
+    pub email_sender: Option<String>,
+    /// maximum amount of vehicles the organization can have, `null` for no limit
+    pub max_vehicles: Option<i32>,
+    /// maximum amount of trackers the organization can have, `null` for no limit
+    pub max_trackers: Option<i32>,
+    /// networks the organization users can send requests from, `null` for no restriction
+    pub ip_allowlist: Option<Vec<String>>,
+    /// access level of users created without one, `null` if they must have one
+    pub default_access_level_id: Option<i32>,
 }
 
 /// A rastercar user with his organization and access level
@@ -140,6 +248,11 @@ impl From<entity::organization::Model> for OrganizationDto {
             owner_id: m.owner_id,
             blocked: m.blocked,
             billing_email_verified: m.billing_email_verified,
+            email_sender: m.email_sender,
+            max_vehicles: m.max_vehicles,
+            max_trackers: m.max_trackers,
+            ip_allowlist: m.ip_allowlist,
+            default_access_level_id: m.default_access_level_id,
         }
     }
 }
@@ -153,6 +266,7 @@ impl From<entity::session::Model> for SessionDto {
             created_at: m.created_at,
             expires_at: m.expires_at,
             same_as_from_request: false,
+            impersonator_id: m.impersonator_id,
         }
     }
 }
@@ -8,18 +8,21 @@ use crate::{
         auth::session::SessionId,
         common::{
             error_codes::{
-                INVALID_SESSION, MISSING_PERMISSIONS, NO_SID_COOKIE, ORGANIZATION_BLOCKED,
+                INVALID_SESSION, IP_NOT_ALLOWED, MISSING_PERMISSIONS, NO_SID_COOKIE,
+                ORGANIZATION_BLOCKED,
             },
             responses::{internal_error_msg, ApiError, SimpleError},
         },
+        organization::ip_allowlist,
     },
     server::controller::AppState,
 };
 use anyhow::Error;
 use axum::{
-    extract::State,
+    extract::{OriginalUri, State},
     response::{IntoResponse, Response},
 };
+use axum_client_ip::SecureClientIp;
 use convert_case::{Case, Casing};
 use futures_util::future::BoxFuture;
 use http::Request;
@@ -28,7 +31,9 @@ use shared::constants::Permission;
 use std::convert::Infallible;
 use std::task::Context;
 use std::task::Poll;
+use strum::IntoEnumIterator;
 use tower::{Layer, Service};
+use tracing::Span;
 
 /// Simple extractor for routes that are only allowed for regular users
 #[derive(Clone)]
@@ -41,6 +46,13 @@ impl RequestUser {
         self.0.organization.as_ref().map(|user| user.id)
     }
 
+    /// If the user is a superuser, superusers are the users not bound
+    /// to a organization (`user.organization_id` is `NULL`) and are
+    /// meant to manage the whole application, not a single organization
+    pub fn is_superuser(&self) -> bool {
+        self.0.organization.is_none()
+    }
+
     /// get the missing permissions the user does not have
     pub fn get_missing_permissions(&self, required_permissions: &[Permission]) -> Vec<String> {
         required_permissions
@@ -53,6 +65,19 @@ impl RequestUser {
             .filter(|item| !self.0.access_level.permissions.contains(item))
             .collect()
     }
+
+    /// get the permissions the user has, as checked by `AclLayer`
+    ///
+    /// permissions stored on the user access level that are no longer known are not included
+    pub fn get_effective_permissions(&self) -> Vec<String> {
+        Permission::iter()
+            .filter(|permission| {
+                self.get_missing_permissions(std::slice::from_ref(permission))
+                    .is_empty()
+            })
+            .map(|permission| permission.to_string().to_case(Case::ScreamingSnake))
+            .collect()
+    }
 }
 
 /// The logged in user password, this is exposed as a struct to be used
@@ -60,38 +85,43 @@ impl RequestUser {
 #[derive(Clone)]
 pub struct RequestUserPassword(pub String);
 
+/// Id of the superuser impersonating the request user, `None` if the
+/// request session is a regular session (see `AuthService::new_impersonation_session`)
+#[derive(Clone, Copy)]
+pub struct RequestImpersonator(pub Option<i32>);
+
 fn handle_fetch_user_result(
-    user_fetch_result: Result<Option<UserDtoEntities>, Error>,
-) -> Result<UserDtoEntities, (http::StatusCode, SimpleError)> {
+    user_fetch_result: Result<Option<(UserDtoEntities, Option<i32>)>, Error>,
+) -> Result<(UserDtoEntities, Option<i32>), (http::StatusCode, SimpleError)> {
     if let Ok(maybe_user) = user_fetch_result {
-        return match maybe_user {
-            Some(entities) => {
-                if let Some(org) = entities.2.clone() {
-                    if org.blocked {
-                        return Err((
-                            StatusCode::UNAUTHORIZED,
-                            SimpleError::from(ORGANIZATION_BLOCKED),
-                        ));
-                    }
-                }
-
-                Ok(entities)
-            }
-            None => Err((StatusCode::UNAUTHORIZED, SimpleError::from(INVALID_SESSION))),
-        };
+        return maybe_user.ok_or((StatusCode::UNAUTHORIZED, SimpleError::from(INVALID_SESSION)));
     }
 
     Err(internal_error_msg("failed to fetch user session"))
 }
 
+/// routes users of blocked organizations can still use, so they can sign
+/// out and find out why their organization is blocked and who to contact
+const BLOCKED_ORGANIZATION_ALLOWED_PATHS: [&str; 2] =
+    ["/auth/sign-out", "/organization/billing-status"];
+
 /// middleware for routes that require a normal user, this queries the DB to get the request user by his session ID cookie,
 /// so use it only within routes that need the user data, adds the following extensions:
 ///
 /// - `SessionId`
 /// - `RequestUser`
 /// - `RequestUserPassword`
+/// - `RequestImpersonator`
+///
+/// requests of users whose organization has a ip allowlist are refused
+/// unless the client ip is within one of the allowlisted networks
+///
+/// requests of users of blocked organizations are refused with `ORGANIZATION_BLOCKED`, but
+/// for the `BLOCKED_ORGANIZATION_ALLOWED_PATHS` and impersonation sessions, so superusers
+/// can still help them, the organization is read on every request so blocking it is immediate
 pub async fn require_user(
     State(state): State<AppState>,
+    SecureClientIp(client_ip): SecureClientIp,
     mut req: http::Request<axum::body::Body>,
     next: axum::middleware::Next,
 ) -> Result<Response, (StatusCode, SimpleError)> {
@@ -105,7 +135,42 @@ pub async fn require_user(
             .get_user_from_session_id(session_token)
             .await;
 
-        let user_access_level_and_org = handle_fetch_user_result(user_fetch_result)?;
+        let (user_access_level_and_org, impersonator_id) =
+            handle_fetch_user_result(user_fetch_result)?;
+
+        let org_blocked = user_access_level_and_org
+            .2
+            .as_ref()
+            .is_some_and(|org| org.blocked);
+
+        if org_blocked && impersonator_id.is_none() {
+            let path = req
+                .extensions()
+                .get::<OriginalUri>()
+                .map(|uri| uri.path())
+                .unwrap_or(req.uri().path());
+
+            if !BLOCKED_ORGANIZATION_ALLOWED_PATHS.contains(&path) {
+                return Err((
+                    StatusCode::FORBIDDEN,
+                    SimpleError::from(ORGANIZATION_BLOCKED),
+                ));
+            }
+        }
+
+        if let Some(allowlist) = user_access_level_and_org
+            .2
+            .as_ref()
+            .and_then(|org| org.ip_allowlist.as_ref())
+        {
+            if !ip_allowlist::is_allowed(allowlist, client_ip) {
+                return Err((StatusCode::FORBIDDEN, SimpleError::from(IP_NOT_ALLOWED)));
+            }
+        }
+
+        if let Some(impersonator_id) = impersonator_id {
+            Span::current().record("impersonator_id", impersonator_id);
+        }
 
         let user_password = user_access_level_and_org.0.password.clone();
 
@@ -115,6 +180,8 @@ pub async fn require_user(
         req.extensions_mut().insert(RequestUser(user));
         req.extensions_mut()
             .insert(RequestUserPassword(user_password));
+        req.extensions_mut()
+            .insert(RequestImpersonator(impersonator_id));
 
         return Ok(next.run(req).await);
     }
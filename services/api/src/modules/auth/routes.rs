@@ -1,47 +1,78 @@
-use super::dto::{self};
+use super::dto::{
+    self, ClearedSessionsDto, InvalidTokenReason, ListImpersonationLogDto, ListOrgSessionsDto,
+    OrgSessionDto, SessionDto, TokenValidationDto, UserTokenKind,
+};
 use super::jwt;
-use super::middleware::{AclLayer, RequestUser};
+use super::middleware::{AclLayer, RequestImpersonator, RequestUser};
+use super::service::{hash_password, NewSession, NewSessionError};
 use super::session::{OptionalSessionId, SessionId};
 use crate::database::error::DbError;
 use crate::modules::common;
+use crate::modules::common::dto::{Pagination, PaginationResult};
 use crate::modules::common::error_codes::EMAIL_ALREADY_VERIFIED;
-use crate::modules::common::extractors::{DbConnection, OrganizationId, ValidatedJson};
+use crate::modules::common::extractors::{
+    DbConnection, OrganizationId, SuperUser, ValidatedJson, ValidatedQuery,
+};
 use crate::modules::common::responses::{internal_error_msg, internal_error_res};
 use crate::modules::common::{error_codes, responses::SimpleError};
 use crate::server::controller::AppState;
+use crate::server::rate_limit::{self, RateLimiter};
 use anyhow::Result;
 use axum::extract::Path;
 use axum::{
     extract::State,
     http::StatusCode,
-    routing::{delete, post},
+    routing::{delete, get, post},
     Extension, Json, Router,
 };
 use axum_client_ip::SecureClientIp;
 use axum_extra::headers::UserAgent;
 use axum_extra::TypedHeader;
-use bcrypt::{hash, DEFAULT_COST};
+use chrono::Utc;
 use http::HeaderMap;
+use jsonwebtoken::errors::ErrorKind;
 use migration::Expr;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter};
+use sea_orm::{ColumnTrait, ConnectionTrait, DbErr, EntityTrait, PaginatorTrait, QueryFilter};
+use sea_orm::{QueryOrder, QueryTrait};
 use shared::constants::Permission;
-use shared::entity::{organization, session, user};
+use shared::entity::{access_level, impersonation_log, organization, session, user};
+use std::sync::Arc;
 
 pub fn create_router(state: AppState) -> Router<AppState> {
+    let public_router = create_public_router(state.rate_limiters.auth.clone());
+
     Router::new()
         .route(
             "/session/:public-session-id",
             delete(delete_session).route_layer(AclLayer::single(Permission::LogoffUser)),
         )
+        .route("/sessions/:public-session-id", get(get_session))
+        .route(
+            "/org-sessions",
+            get(list_org_sessions).route_layer(AclLayer::single(Permission::LogoffUser)),
+        )
+        .route("/me/permissions", get(get_request_user_permissions))
         .route("/sign-out", post(sign_out))
         .route(
             "/sign-out/:public-session-id",
             delete(sign_out_session_by_id),
         )
+        //
+        .route("/impersonate/:user_id", post(impersonate_user))
+        .route("/stop-impersonation", post(stop_impersonation))
+        .route("/impersonation-log", get(list_impersonation_log))
+        .route("/clear-expired-sessions", post(clear_expired_sessions))
         .layer(axum::middleware::from_fn_with_state(
             state,
             super::middleware::require_user,
         ))
+        .merge(public_router)
+}
+
+/// routes that do not require a session, rate limited by client ip
+/// since they are the target of credential stuffing and email spam
+fn create_public_router(limiter: Arc<RateLimiter>) -> Router<AppState> {
+    Router::new()
         .route("/sign-up", post(sign_up))
         .route("/sign-in", post(sign_in))
         .route(
@@ -56,17 +87,42 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             "/confirm-email-address-by-token",
             post(confirm_user_email_address_by_token),
         )
+        .route("/validate-token", post(validate_token))
+        .route("/request-magic-link", post(request_magic_link))
+        .route("/sign-in-by-magic-link", post(sign_in_by_magic_link))
+        .route_layer(axum::middleware::from_fn_with_state(
+            limiter,
+            rate_limit::limit_by_ip,
+        ))
+}
+
+/// maps a failure to create a session to its response
+fn new_session_error_res(error: NewSessionError) -> (StatusCode, SimpleError) {
+    match error {
+        NewSessionError::SessionLimitReached => (
+            StatusCode::FORBIDDEN,
+            SimpleError::from(error_codes::SESSION_LIMIT_REACHED),
+        ),
+        NewSessionError::InternalError => internal_error_msg("failed to create session"),
+    }
 }
 
 fn sign_in_or_up_response(
     user: dto::UserDto,
-    ses_token: SessionId,
+    session: NewSession,
 ) -> (HeaderMap, Json<dto::SignInResponse>) {
     let mut headers = HeaderMap::new();
 
-    headers.insert("Set-Cookie", ses_token.into_set_cookie_header());
+    headers.insert("Set-Cookie", session.session_id.into_set_cookie_header());
 
-    let res_body = dto::SignInResponse { user };
+    let res_body = dto::SignInResponse {
+        user,
+        evicted_sessions: session
+            .evicted_sessions
+            .into_iter()
+            .map(SessionDto::from)
+            .collect(),
+    };
 
     (headers, Json(res_body))
 }
@@ -89,6 +145,11 @@ fn sign_in_or_up_response(
             content_type = "application/json",
             example = json!("session deleted successfully"),
         ),
+        (
+            status = NOT_FOUND,
+            description = "session not found or belongs to a user of another organization",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn delete_session(
@@ -98,21 +159,15 @@ pub async fn delete_session(
     State(state): State<AppState>,
     DbConnection(db): DbConnection,
 ) -> Result<(HeaderMap, Json<String>), (StatusCode, SimpleError)> {
-    let (session_to_delete, session_to_delete_user) =
-        session::Entity::find_with_user_by_public_id(session_id as i32, &db)
-            .await
-            .map_err(DbError::from)?
-            .ok_or((
-                StatusCode::NOT_FOUND,
-                SimpleError::from("session not found"),
-            ))?;
-
-    if org_id != session_to_delete_user.organization_id.unwrap_or(0) {
-        return Err((
-            StatusCode::UNAUTHORIZED,
-            SimpleError::from("session does not belong to user org"),
-        ));
-    }
+    // sessions of users from other organizations are reported as not found
+    // so the response does not tell if the session exists
+    let session_to_delete = find_org_session(&db, session_id as i32, org_id)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            SimpleError::from("session not found"),
+        ))?;
 
     state
         .auth_service
@@ -134,10 +189,213 @@ pub async fn delete_session(
     Ok((headers, Json(String::from("session deleted successfully"))))
 }
 
+/// finds the session by its public id if it belongs to a user of the organization
+async fn find_org_session<C: ConnectionTrait>(
+    db: &C,
+    public_id: i32,
+    org_id: i32,
+) -> Result<Option<session::Model>, DbErr> {
+    let session = session::Entity::find_with_user_by_public_id(public_id, db)
+        .await?
+        .filter(|(_, user)| user.organization_id == Some(org_id))
+        .map(|(session, _)| session);
+
+    Ok(session)
+}
+
+/// Gets the permissions of the request user
+///
+/// the permissions the request user has through its access level, these are the same
+/// permissions checked when calling endpoints, so clients can use them to show or hide
+/// actions the user cannot perform.
+#[utoipa::path(
+    get,
+    tag = "auth",
+    path = "/auth/me/permissions",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "the permissions of the request user in screaming snake case",
+            content_type = "application/json",
+            body = Vec<String>,
+            example = json!(["CREATE_VEHICLE", "UPDATE_VEHICLE"]),
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_request_user_permissions(
+    Extension(req_user): Extension<RequestUser>,
+) -> Json<Vec<String>> {
+    Json(req_user.get_effective_permissions())
+}
+
+/// Gets a session by its public id
+///
+/// Required permissions: LOGOFF_USER, unless the session belongs to the request user
+///
+/// sessions of users of other organizations are not found.
+#[utoipa::path(
+    get,
+    tag = "auth",
+    path = "/auth/sessions/{public_session_id}",
+    security(("session_id" = [])),
+    params(
+        ("public_session_id" = i32, Path, description = "public id of the session"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the session with its user",
+            content_type = "application/json",
+            body = OrgSessionDto,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "the session belongs to another user and the user lacks permissions / MISSING_PERMISSIONS",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "session not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_session(
+    Path(public_session_id): Path<i32>,
+    OrganizationId(org_id): OrganizationId,
+    Extension(req_user): Extension<RequestUser>,
+    Extension(req_user_session): Extension<SessionId>,
+    State(state): State<AppState>,
+) -> Result<Json<OrgSessionDto>, (StatusCode, SimpleError)> {
+    let (ses, ses_user) = state
+        .auth_service
+        .get_session_by_public_id(public_session_id, Some(org_id))
+        .await
+        .map_err(|_| internal_error_res())?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            SimpleError::from("session not found"),
+        ))?;
+
+    let can_see_other_users_sessions = req_user
+        .get_missing_permissions(&[Permission::LogoffUser])
+        .is_empty();
+
+    if ses_user.id != req_user.0.id && !can_see_other_users_sessions {
+        return Err((
+            StatusCode::FORBIDDEN,
+            SimpleError::from(error_codes::MISSING_PERMISSIONS),
+        ));
+    }
+
+    let is_current_session = SessionId::from_database_value(ses.session_token.clone())
+        .map(|id| id.get_id() == req_user_session.get_id())
+        .unwrap_or(false);
+
+    let mut session_dto = SessionDto::from(ses);
+    session_dto.same_as_from_request = is_current_session;
+
+    Ok(Json(OrgSessionDto {
+        session: session_dto,
+        user: ses_user.into(),
+    }))
+}
+
+/// Lists the active sessions of the organization users
+///
+/// Required permissions: LOGOFF_USER
+///
+/// lists the non expired sessions of every user of the request user
+/// organization, most recent sessions first.
+#[utoipa::path(
+    get,
+    tag = "auth",
+    path = "/auth/org-sessions",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListOrgSessionsDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of sessions with their users",
+            content_type = "application/json",
+            body = PaginatedOrgSession,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user lacks permissions",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_org_sessions(
+    OrganizationId(org_id): OrganizationId,
+    Extension(req_user_session): Extension<SessionId>,
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListOrgSessionsDto>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<OrgSessionDto>>, (StatusCode, SimpleError)> {
+    let paginator = session::Entity::find()
+        .find_also_related(user::Entity)
+        // the session user must belong to the request org, this
+        // also excludes sessions of users without a organization
+        .filter(user::Column::OrganizationId.eq(org_id))
+        .filter(session::Column::ExpiresAt.gt(Utc::now()))
+        .apply_if(filter.user_id, |query, id| {
+            query.filter(session::Column::UserId.eq(id))
+        })
+        .order_by_desc(session::Column::CreatedAt)
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let current_session_id = req_user_session.get_id();
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .filter_map(|(ses, ses_user)| {
+            let is_current_session = SessionId::from_database_value(ses.session_token.clone())
+                .map(|id| id.get_id() == current_session_id)
+                .unwrap_or(false);
+
+            let mut session_dto = SessionDto::from(ses);
+            session_dto.same_as_from_request = is_current_session;
+
+            Some(OrgSessionDto {
+                session: session_dto,
+                user: ses_user?.into(),
+            })
+        })
+        .collect();
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
 /// Signs out of the current user session
 ///
 /// signs out by deleting the user session present in the sid (session id)
-/// request cookie
+/// request cookie, signing out of a impersonation session is recorded on
+/// the impersonation log.
 #[utoipa::path(
     post,
     tag = "auth",
@@ -157,14 +415,25 @@ pub async fn delete_session(
     ),
 )]
 pub async fn sign_out(
+    client_ip: SecureClientIp,
     Extension(session): Extension<SessionId>,
+    Extension(impersonator): Extension<RequestImpersonator>,
     State(state): State<AppState>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
 ) -> Result<(StatusCode, HeaderMap), (StatusCode, SimpleError)> {
-    state
-        .auth_service
-        .delete_session(&session)
-        .await
-        .or(Err(internal_error_msg("failed to delete session")))?;
+    if impersonator.0.is_some() {
+        state
+            .auth_service
+            .stop_impersonation_session(&session, client_ip.0, user_agent.to_string())
+            .await
+            .or(Err(internal_error_msg("failed to delete session")))?;
+    } else {
+        state
+            .auth_service
+            .delete_session(&session)
+            .await
+            .or(Err(internal_error_msg("failed to delete session")))?;
+    }
 
     let mut headers = HeaderMap::new();
     headers.insert("Set-Cookie", session.into_delete_cookie_header());
@@ -200,18 +469,18 @@ async fn sign_out_session_by_id(
     Extension(req_user): Extension<RequestUser>,
     Extension(req_user_session): Extension<SessionId>,
     Path(public_session_id): Path<i32>,
-    DbConnection(db): DbConnection,
     State(state): State<AppState>,
 ) -> Result<(StatusCode, HeaderMap), (StatusCode, SimpleError)> {
-    let maybe_session_to_delete = session::Entity::find()
-        .filter(session::Column::PublicId.eq(public_session_id))
-        .one(&db)
-        .await
-        .map_err(DbError::from)?;
+    let request_user = req_user.0;
+    let request_user_org_id = request_user.organization.as_ref().map(|org| org.id);
 
-    if let Some(session_to_delete) = maybe_session_to_delete {
-        let request_user = req_user.0;
+    let maybe_session_to_delete = state
+        .auth_service
+        .get_session_by_public_id(public_session_id, request_user_org_id)
+        .await
+        .map_err(|_| internal_error_res())?;
 
+    if let Some((session_to_delete, _)) = maybe_session_to_delete {
         if session_to_delete.user_id != request_user.id {
             return Err((
                 StatusCode::UNAUTHORIZED,
@@ -248,6 +517,288 @@ async fn sign_out_session_by_id(
     ))
 }
 
+/// Impersonates a user
+///
+/// Only accessible to superusers, creates a short lived session for the user, replacing
+/// the request session. The session is marked as impersonated by the request user and
+/// its creation is recorded on the impersonation log.
+///
+/// to stop impersonating just sign out of the impersonation session.
+#[utoipa::path(
+    post,
+    tag = "auth",
+    path = "/auth/impersonate/{user_id}",
+    security(("session_id" = [])),
+    params(
+        ("user_id" = i32, Path, description = "id of the user to impersonate"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "impersonation session created",
+            body = SignInResponse,
+            headers(("Set-Cookie" = String, description = "impersonation session id cookie"))
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "cannot impersonate the request user",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser, the request session is a impersonation session or the user to impersonate is a superuser",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "user not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[allow(clippy::too_many_arguments)]
+pub async fn impersonate_user(
+    _: SuperUser,
+    Path(user_id): Path<i32>,
+    client_ip: SecureClientIp,
+    Extension(req_user): Extension<RequestUser>,
+    Extension(req_user_session): Extension<SessionId>,
+    Extension(impersonator): Extension<RequestImpersonator>,
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<(HeaderMap, Json<dto::SignInResponse>), (StatusCode, SimpleError)> {
+    if impersonator.0.is_some() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            SimpleError::from("impersonation sessions cannot impersonate"),
+        ));
+    }
+
+    let impersonator_id = req_user.0.id;
+
+    if user_id == impersonator_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("cannot impersonate yourself"),
+        ));
+    }
+
+    let (user_to_impersonate, org) = user::Entity::find_by_id(user_id)
+        .find_also_related(organization::Entity)
+        .one(&db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((StatusCode::NOT_FOUND, SimpleError::from("user not found")))?;
+
+    if org.is_none() {
+        return Err((
+            StatusCode::FORBIDDEN,
+            SimpleError::from("cannot impersonate a superuser"),
+        ));
+    }
+
+    let access_level = access_level::Entity::find_by_id(user_to_impersonate.access_level_id)
+        .one(&db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or_else(internal_error_res)?;
+
+    let (session_token, impersonation_session) = state
+        .auth_service
+        .new_impersonation_session(
+            user_id,
+            impersonator_id,
+            client_ip.0,
+            user_agent.to_string(),
+        )
+        .await
+        .or(Err(internal_error_msg("failed to create session")))?;
+
+    // the impersonation session cookie replaces the superuser one
+    state
+        .auth_service
+        .delete_session(&req_user_session)
+        .await
+        .ok();
+
+    tracing::info!(
+        impersonator_id,
+        impersonated_user_id = user_id,
+        session_public_id = impersonation_session.public_id,
+        "impersonation started"
+    );
+
+    let user = dto::UserDto::from((user_to_impersonate, access_level, org));
+
+    // impersonation sessions do not count to the user sessions limit
+    let session = NewSession {
+        session_id: session_token,
+        evicted_sessions: Vec::new(),
+    };
+
+    Ok(sign_in_or_up_response(user, session))
+}
+
+/// Stops impersonating a user
+///
+/// signs out of the request session, that must be a impersonation session,
+/// recording it on the impersonation log.
+#[utoipa::path(
+    post,
+    tag = "auth",
+    path = "/auth/stop-impersonation",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "impersonation session signed out",
+            headers(("Set-Cookie" = String, description = "expired cookie sid, so the client browser deletes the cookie"))
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "request session is not a impersonation session",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn stop_impersonation(
+    client_ip: SecureClientIp,
+    Extension(session): Extension<SessionId>,
+    Extension(req_user): Extension<RequestUser>,
+    Extension(impersonator): Extension<RequestImpersonator>,
+    State(state): State<AppState>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+) -> Result<(StatusCode, HeaderMap), (StatusCode, SimpleError)> {
+    let not_impersonating_err = (
+        StatusCode::BAD_REQUEST,
+        SimpleError::from("request session is not a impersonation session"),
+    );
+
+    let Some(impersonator_id) = impersonator.0 else {
+        return Err(not_impersonating_err);
+    };
+
+    let stopped = state
+        .auth_service
+        .stop_impersonation_session(&session, client_ip.0, user_agent.to_string())
+        .await
+        .or(Err(internal_error_msg("failed to delete session")))?;
+
+    if !stopped {
+        return Err(not_impersonating_err);
+    }
+
+    tracing::info!(
+        impersonator_id,
+        impersonated_user_id = req_user.0.id,
+        "impersonation stopped"
+    );
+
+    let mut headers = HeaderMap::new();
+    headers.insert("Set-Cookie", session.into_delete_cookie_header());
+
+    Ok((StatusCode::OK, headers))
+}
+
+/// Lists the impersonation log
+///
+/// Only accessible to superusers, lists when superusers started and
+/// stopped impersonating users, most recent records first.
+#[utoipa::path(
+    get,
+    tag = "auth",
+    path = "/auth/impersonation-log",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListImpersonationLogDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of impersonation log records",
+            content_type = "application/json",
+            body = PaginatedImpersonationLog,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_impersonation_log(
+    _: SuperUser,
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListImpersonationLogDto>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<impersonation_log::Model>>, (StatusCode, SimpleError)> {
+    let paginator = impersonation_log::Entity::find()
+        .apply_if(filter.impersonator_id, |query, id| {
+            query.filter(impersonation_log::Column::ImpersonatorId.eq(id))
+        })
+        .apply_if(filter.impersonated_user_id, |query, id| {
+            query.filter(impersonation_log::Column::ImpersonatedUserId.eq(id))
+        })
+        .order_by_desc(impersonation_log::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
+/// Deletes the expired sessions
+///
+/// Only accessible to superusers, deletes every expired session at once instead
+/// of waiting for the clear sessions cronjob, eg: for cleaning up after incidents
+#[utoipa::path(
+    post,
+    tag = "auth",
+    path = "/auth/clear-expired-sessions",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "amount of deleted sessions",
+            content_type = "application/json",
+            body = ClearedSessionsDto,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn clear_expired_sessions(
+    _: SuperUser,
+    DbConnection(db): DbConnection,
+) -> Result<Json<ClearedSessionsDto>, (StatusCode, SimpleError)> {
+    let deleted = session::Entity::delete_expired(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    tracing::info!(deleted, "expired sessions cleared on demand");
+
+    Ok(Json(ClearedSessionsDto { deleted }))
+}
+
 /// Signs in
 ///
 /// Sign in by credentials (email, password)
@@ -278,6 +829,11 @@ async fn sign_out_session_by_id(
             description = "invalid password",
             body = SimpleError,
         ),
+        (
+            status = FORBIDDEN,
+            description = "SESSION_LIMIT_REACHED",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn sign_in(
@@ -302,20 +858,18 @@ pub async fn sign_in(
             ),
         })?;
 
-    let session_token = state
+    let session = state
         .auth_service
-        .new_session(user.id, client_ip.0, user_agent.to_string())
+        .new_session(
+            user.id,
+            client_ip.0,
+            user_agent.to_string(),
+            old_session_token.get_value(),
+        )
         .await
-        .or(Err((
-            StatusCode::INTERNAL_SERVER_ERROR,
-            SimpleError::from("failed to create session"),
-        )))?;
+        .map_err(new_session_error_res)?;
 
-    if let Some(old_ses_token) = old_session_token.get_value() {
-        state.auth_service.delete_session(&old_ses_token).await.ok();
-    }
-
-    Ok(sign_in_or_up_response(user, session_token))
+    Ok(sign_in_or_up_response(user, session))
 }
 
 /// Signs up a new user rastercar user
@@ -380,13 +934,13 @@ pub async fn sign_up(
         .await
         .or(Err(internal_error_res()))?;
 
-    let session_token = state
+    let session = state
         .auth_service
-        .new_session(created_user.id, client_ip.0, user_agent.to_string())
+        .new_session(created_user.id, client_ip.0, user_agent.to_string(), None)
         .await
-        .or(Err(internal_error_msg("failed to create session")))?;
+        .map_err(new_session_error_res)?;
 
-    Ok(sign_in_or_up_response(created_user, session_token))
+    Ok(sign_in_or_up_response(created_user, session))
 }
 
 /// Requests a password reset email
@@ -416,6 +970,16 @@ pub async fn sign_up(
             description = "invalid dto error message",
             body = SimpleError,
         ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "EMAIL_SERVICE_UNAVAILABLE, the message broker is unreachable",
+            body = SimpleError,
+        ),
+        (
+            status = BAD_GATEWAY,
+            description = "EMAIL_REJECTED, the message broker refused the email",
+            body = SimpleError,
+        ),
     ),
 )]
 #[tracing::instrument(skip_all)]
@@ -437,11 +1001,19 @@ pub async fn request_recover_password_email(
             .await
             .or(Err(internal_error_res()))?;
 
+        let sender = match usr.organization_id {
+            Some(org_id) => organization::Entity::find_by_id(org_id)
+                .one(&db)
+                .await
+                .map_err(DbError::from)?
+                .and_then(|org| org.email_sender),
+            None => None,
+        };
+
         state
             .mailer_service
-            .send_recover_password_email(payload.email, token, usr.username)
-            .await
-            .or(Err(internal_error_res()))?;
+            .send_recover_password_email(payload.email, token, usr.username, sender)
+            .await?;
 
         return Ok(Json("password recovery email queued successfully"));
     }
@@ -452,9 +1024,154 @@ pub async fn request_recover_password_email(
     ))
 }
 
+/// response of magic link requests, whether a account exists with the email or not
+const MAGIC_LINK_REQUESTED: &str =
+    "if a account exists with the email address a magic link was sent to it";
+
+/// Requests a magic link email
+///
+/// Sends a email with a link to sign in without a password to the
+/// provided email address if a active user account exists with it.
+///
+/// the same response is returned whether a account exists with the
+/// email address or not, so accounts cannot be enumerated.
+#[utoipa::path(
+    post,
+    tag = "auth",
+    path = "/auth/request-magic-link",
+    request_body = EmailAddress,
+    responses(
+        (
+            status = OK,
+            description = "success message",
+            body = String,
+            content_type = "application/json",
+            example = json!("if a account exists with the email address a magic link was sent to it"),
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message",
+            body = SimpleError,
+        ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "EMAIL_SERVICE_UNAVAILABLE, the message broker is unreachable",
+            body = SimpleError,
+        ),
+        (
+            status = BAD_GATEWAY,
+            description = "EMAIL_REJECTED, the message broker refused the email",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn request_magic_link(
+    DbConnection(db): DbConnection,
+    State(state): State<AppState>,
+    ValidatedJson(payload): ValidatedJson<common::dto::EmailAddress>,
+) -> Result<Json<&'static str>, (StatusCode, SimpleError)> {
+    let maybe_user = user::Entity::find()
+        .filter(user::Column::Email.eq(&payload.email))
+        .one(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    // the response must not reveal if a account exists with the email
+    let Some(usr) = maybe_user else {
+        return Ok(Json(MAGIC_LINK_REQUESTED));
+    };
+
+    let token = state
+        .auth_service
+        .gen_and_set_user_magic_link_token(usr.id)
+        .await
+        .or(Err(internal_error_res()))?;
+
+    let sender = match usr.organization_id {
+        Some(org_id) => organization::Entity::find_by_id(org_id)
+            .one(&db)
+            .await
+            .map_err(DbError::from)?
+            .and_then(|org| org.email_sender),
+        None => None,
+    };
+
+    state
+        .mailer_service
+        .send_magic_link_email(payload.email, token, usr.username, sender)
+        .await?;
+
+    Ok(Json(MAGIC_LINK_REQUESTED))
+}
+
+/// Signs in by magic link
+///
+/// Signs in with the token of a magic link email, the token
+/// is invalidated so the link can not be used again.
+#[utoipa::path(
+    post,
+    tag = "auth",
+    path = "/auth/sign-in-by-magic-link",
+    request_body = Token,
+    responses(
+        (
+            status = OK,
+            description = "sign in successful",
+            body = SignInResponse,
+            headers(("Set-Cookie" = String, description = "new session id cookie"))
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid token",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "the token was already used or replaced by a newer one",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "SESSION_LIMIT_REACHED",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn sign_in_by_magic_link(
+    client_ip: SecureClientIp,
+    old_session_token: OptionalSessionId,
+    State(state): State<AppState>,
+    TypedHeader(user_agent): TypedHeader<UserAgent>,
+    ValidatedJson(payload): ValidatedJson<common::dto::Token>,
+) -> Result<(HeaderMap, Json<dto::SignInResponse>), (StatusCode, SimpleError)> {
+    jwt::decode(&payload.token).or(Err((
+        StatusCode::UNAUTHORIZED,
+        SimpleError::from("invalid token"),
+    )))?;
+
+    let (user, session) = state
+        .auth_service
+        .sign_in_by_magic_link_token(
+            &payload.token,
+            client_ip.0,
+            user_agent.to_string(),
+            old_session_token.get_value(),
+        )
+        .await
+        .map_err(new_session_error_res)?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            SimpleError::from("user not found with this magic link token"),
+        ))?;
+
+    Ok(sign_in_or_up_response(user, session))
+}
+
 /// Recover password by token
 ///
-/// Sets a new password for the account in the recover password JWT.
+/// Sets a new password for the account in the recover password JWT,
+/// signing out every session of the account.
 #[utoipa::path(
     post,
     tag = "auth",
@@ -476,6 +1193,7 @@ pub async fn request_recover_password_email(
     ),
 )]
 pub async fn change_password_by_recovery_token(
+    State(state): State<AppState>,
     DbConnection(db): DbConnection,
     ValidatedJson(payload): ValidatedJson<dto::ResetPassword>,
 ) -> Result<Json<&'static str>, (StatusCode, SimpleError)> {
@@ -492,18 +1210,20 @@ pub async fn change_password_by_recovery_token(
 
     if let Some(usr) = maybe_user {
         let new_password_hash =
-            hash(&payload.new_password, DEFAULT_COST).or(Err(internal_error_res()))?;
+            hash_password(&payload.new_password).or(Err(internal_error_res()))?;
 
-        user::Entity::update_many()
-            .filter(organization::Column::Id.eq(usr.id))
-            .col_expr(user::Column::Password, Expr::value(new_password_hash))
-            .col_expr(
-                user::Column::ResetPasswordToken,
-                Expr::value::<Option<String>>(None),
-            )
-            .exec(&db)
+        // whoever requested the recovery has no session, so every session is signed out
+        let signed_out_sessions = state
+            .auth_service
+            .change_password(usr.id, new_password_hash, None)
             .await
-            .map_err(DbError::from)?;
+            .or(Err(internal_error_msg("failed to change password")))?;
+
+        tracing::info!(
+            user_id = usr.id,
+            signed_out_sessions,
+            "password changed by recovery token"
+        );
 
         return Ok(Json("password changed successfully"));
     }
@@ -579,3 +1299,108 @@ pub async fn confirm_user_email_address_by_token(
         SimpleError::from("user not found with this reset password token"),
     ))
 }
+
+/// Validate a token without consuming it
+///
+/// Checks if a token sent by email (eg: recover password) can still be used,
+/// allowing expired or already used links to be reported before the user
+/// fills any form. The user the token belongs to is not revealed.
+#[utoipa::path(
+    post,
+    tag = "auth",
+    path = "/auth/validate-token",
+    request_body = ValidateToken,
+    responses(
+        (
+            status = OK,
+            description = "token validation result",
+            body = TokenValidationDto,
+            content_type = "application/json",
+        ),
+    ),
+)]
+pub async fn validate_token(
+    DbConnection(db): DbConnection,
+    ValidatedJson(payload): ValidatedJson<dto::ValidateToken>,
+) -> Result<Json<TokenValidationDto>, (StatusCode, SimpleError)> {
+    if let Err(e) = jwt::decode(&payload.token) {
+        let reason = match e.kind() {
+            ErrorKind::ExpiredSignature => InvalidTokenReason::Expired,
+            _ => InvalidTokenReason::Malformed,
+        };
+
+        return Ok(Json(TokenValidationDto::invalid(reason)));
+    }
+
+    let token_column = match payload.kind {
+        UserTokenKind::PasswordReset => user::Column::ResetPasswordToken,
+        UserTokenKind::EmailConfirmation => user::Column::ConfirmEmailToken,
+        UserTokenKind::MagicLink => user::Column::MagicLinkToken,
+    };
+
+    let token_in_use = user::Entity::find()
+        .filter(token_column.eq(&payload.token))
+        .count(&db)
+        .await
+        .map_err(DbError::from)?
+        > 0;
+
+    if !token_in_use {
+        return Ok(Json(TokenValidationDto::invalid(
+            InvalidTokenReason::NotFound,
+        )));
+    }
+
+    Ok(Json(TokenValidationDto::valid()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+    use migration::seeder;
+    use rand_chacha::ChaCha8Rng;
+    use rand_core::{OsRng, RngCore, SeedableRng};
+    use sea_orm::{ActiveModelTrait, Set};
+
+    #[tokio::test]
+    #[ignore = "needs a database with PostGIS and TimescaleDB, see database::test_db"]
+    async fn sessions_of_users_of_other_organizations_are_not_found() {
+        let txn = test_db::begin().await;
+
+        let org_id = seeder::gen_organization(&txn).await.unwrap();
+        let other_org_id = seeder::gen_organization(&txn).await.unwrap();
+        let access_level_id = seeder::gen_access_level(&txn, false, Some(org_id), vec![])
+            .await
+            .unwrap();
+        let user_id = seeder::gen_user(&txn, org_id, access_level_id)
+            .await
+            .unwrap();
+
+        let mut rng = ChaCha8Rng::seed_from_u64(OsRng.next_u64());
+
+        let session = session::ActiveModel {
+            ip: Set(String::from("127.0.0.1")),
+            user_agent: Set(String::from("test")),
+            expires_at: Set(Utc::now() + chrono::Duration::days(1)),
+            user_id: Set(user_id),
+            session_token: Set(SessionId::generate_new(&mut rng).into_database_value()),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await
+        .unwrap();
+
+        let found = find_org_session(&txn, session.public_id, other_org_id)
+            .await
+            .unwrap();
+
+        assert_eq!(found, None);
+
+        let found = find_org_session(&txn, session.public_id, org_id)
+            .await
+            .unwrap();
+
+        assert_eq!(found.map(|found| found.public_id), Some(session.public_id));
+    }
+}
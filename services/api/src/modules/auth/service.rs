@@ -1,27 +1,194 @@
 use super::dto::{self, OrganizationDto, UserDto};
 use super::jwt::{self, Claims};
-use crate::modules::auth::session::{SessionId, SESSION_DAYS_DURATION};
+use crate::config::{app_config, SessionLimitPolicy};
+use crate::modules::auth::session::{
+    SessionId, IMPERSONATION_SESSION_HOURS, SESSION_DAYS_DURATION,
+};
+use crate::modules::organization::activity::{self, ActivityEvent, ImpersonationActivity};
 use anyhow::{Context, Result};
-use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::{Duration, Utc};
+use bcrypt::{hash, verify, BcryptResult, HashParts};
+use chrono::{DateTime, Duration, Utc};
 use ipnetwork::IpNetwork;
 use migration::Expr;
 use rand_chacha::ChaCha8Rng;
+use rand_core::RngCore;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set,
-    TransactionTrait, TryIntoModel,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set, TransactionError, TransactionTrait,
+    TryIntoModel,
 };
 use shared::constants::Permission;
-use shared::entity::{access_level, organization, session, user};
+use shared::entity::{
+    access_level, impersonation_log, login_history, organization, session,
+    socket_connection_ticket, user,
+};
+use std::fmt;
 use std::net::IpAddr;
 use std::sync::{Arc, Mutex};
 
+/// seconds a SocketIO connection ticket can be used for after being created
+pub const SOCKET_CONNECTION_TICKET_SECONDS: i64 = 30;
+
+/// `impersonation_log.action` of a superuser starting to impersonate a user
+pub const IMPERSONATION_STARTED: &str = "started";
+
+/// `impersonation_log.action` of a impersonation session being signed out
+pub const IMPERSONATION_STOPPED: &str = "stopped";
+
+/// adds a impersonation event to the activity feed of the impersonated user organization
+async fn record_impersonation_activity<C: ConnectionTrait>(
+    db: &C,
+    event: ActivityEvent,
+) -> Result<(), DbErr> {
+    let (ActivityEvent::ImpersonationStarted(data) | ActivityEvent::ImpersonationStopped(data)) =
+        &event
+    else {
+        return Ok(());
+    };
+
+    let org_id = user::Entity::find_by_id(data.impersonated_user_id)
+        .one(db)
+        .await?
+        .and_then(|u| u.organization_id);
+
+    match org_id {
+        Some(org_id) => activity::record_activity(db, org_id, event).await,
+        None => Ok(()),
+    }
+}
+
+/// hashes a password with the configured bcrypt cost
+pub fn hash_password<P: AsRef<[u8]>>(password: P) -> BcryptResult<String> {
+    hash(password, app_config().bcrypt_cost)
+}
+
 pub enum UserFromCredentialsError {
     NotFound,
     InternalError,
     InvalidPassword,
 }
 
+#[derive(Debug)]
+pub enum NewSessionError {
+    /// the user has `MAX_SESSIONS_PER_USER` sessions and the policy rejects new ones
+    SessionLimitReached,
+    InternalError,
+}
+
+impl fmt::Display for NewSessionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NewSessionError::SessionLimitReached => write!(f, "user sessions limit reached"),
+            NewSessionError::InternalError => write!(f, "failed to create session"),
+        }
+    }
+}
+
+impl std::error::Error for NewSessionError {}
+
+impl From<DbErr> for NewSessionError {
+    fn from(_: DbErr) -> Self {
+        NewSessionError::InternalError
+    }
+}
+
+/// A session created for a user
+pub struct NewSession {
+    pub session_id: SessionId,
+
+    /// sessions of the user deleted to stay within `MAX_SESSIONS_PER_USER`
+    pub evicted_sessions: Vec<session::Model>,
+}
+
+/// makes room for a new session of the user if the user has `MAX_SESSIONS_PER_USER`
+/// active sessions (impersonation ones are not counted), either deleting the oldest
+/// sessions, which are returned, or failing, according to the `SESSION_LIMIT_POLICY`
+async fn enforce_session_limit<C: ConnectionTrait>(
+    tx: &C,
+    user_id: i32,
+) -> Result<Vec<session::Model>, NewSessionError> {
+    let Some(max_sessions) = app_config().max_sessions_per_user else {
+        return Ok(Vec::new());
+    };
+
+    // locks the user so concurrent sign ins cannot go over the limit
+    user::Entity::find_by_id(user_id)
+        .lock_exclusive()
+        .one(tx)
+        .await?;
+
+    let active_sessions = session::Entity::find()
+        .filter(session::Column::UserId.eq(user_id))
+        .filter(session::Column::ImpersonatorId.is_null())
+        .filter(session::Column::ExpiresAt.gt(Utc::now()))
+        .order_by_asc(session::Column::CreatedAt)
+        .order_by_asc(session::Column::PublicId)
+        .all(tx)
+        .await?;
+
+    let over_limit = (active_sessions.len() as u64 + 1).saturating_sub(max_sessions) as usize;
+
+    if over_limit == 0 {
+        return Ok(Vec::new());
+    }
+
+    if app_config().session_limit_policy == SessionLimitPolicy::RejectNew {
+        return Err(NewSessionError::SessionLimitReached);
+    }
+
+    let evicted: Vec<session::Model> = active_sessions.into_iter().take(over_limit).collect();
+
+    session::Entity::delete_many()
+        .filter(session::Column::PublicId.is_in(evicted.iter().map(|s| s.public_id)))
+        .exec(tx)
+        .await?;
+
+    Ok(evicted)
+}
+
+/// creates a session for the user and records the login on the user login history, deleting
+/// `replaced_session` and making room for the session, see `AuthService::new_session`
+async fn insert_session<C: ConnectionTrait>(
+    tx: &C,
+    user_id: i32,
+    ses_token: SessionId,
+    ip: String,
+    user_agent: String,
+    replaced_session: Option<SessionId>,
+) -> Result<Vec<session::Model>, NewSessionError> {
+    if let Some(replaced) = replaced_session {
+        session::Entity::delete_many()
+            .filter(session::Column::SessionToken.eq(replaced.into_database_value()))
+            .exec(tx)
+            .await?;
+    }
+
+    let evicted_sessions = enforce_session_limit(tx, user_id).await?;
+
+    let created_session = session::ActiveModel {
+        ip: Set(ip.clone()),
+        user_agent: Set(user_agent.clone()),
+        expires_at: Set(Utc::now() + Duration::days(SESSION_DAYS_DURATION)),
+        user_id: Set(user_id),
+        session_token: Set(ses_token.into_database_value()),
+        ..Default::default()
+    }
+    .insert(tx)
+    .await?;
+
+    login_history::ActiveModel {
+        user_id: Set(user_id),
+        session_public_id: Set(created_session.public_id),
+        user_agent: Set(user_agent),
+        ip: Set(ip),
+        ..Default::default()
+    }
+    .insert(tx)
+    .await?;
+
+    Ok(evicted_sessions)
+}
+
 #[derive(Clone)]
 pub struct AuthService {
     rng: Arc<Mutex<ChaCha8Rng>>,
@@ -36,27 +203,177 @@ impl AuthService {
         }
     }
 
-    /// generates a new session token and creates a new session record on the DB for the user
+    /// generates a new session token and creates a new session record on the DB for the user,
+    /// recording the login on the user login history, `replaced_session` (eg: the session on
+    /// the sign in request cookie) is deleted and does not count to the user sessions limit.
+    ///
+    /// if the user has `MAX_SESSIONS_PER_USER` sessions, the oldest ones are deleted or the
+    /// session is not created, according to the `SESSION_LIMIT_POLICY`
     pub async fn new_session(
         &self,
         user_identifier: i32,
         client_ip: IpAddr,
         client_user_agent: String,
-    ) -> Result<SessionId> {
+        replaced_session: Option<SessionId>,
+    ) -> Result<NewSession, NewSessionError> {
         let ses_token = SessionId::generate_new(&mut self.rng.lock().unwrap());
+        let ip = IpNetwork::from(client_ip).to_string();
 
-        let new_session = session::ActiveModel {
-            ip: Set(IpNetwork::from(client_ip).to_string()),
-            user_agent: Set(client_user_agent),
-            expires_at: Set(Utc::now() + Duration::days(SESSION_DAYS_DURATION)),
-            user_id: Set(user_identifier),
-            session_token: Set(ses_token.into_database_value()),
-            ..Default::default()
-        };
+        let evicted_sessions = self
+            .db
+            .transaction::<_, Vec<session::Model>, NewSessionError>(|tx| {
+                Box::pin(async move {
+                    insert_session(
+                        tx,
+                        user_identifier,
+                        ses_token,
+                        ip,
+                        client_user_agent,
+                        replaced_session,
+                    )
+                    .await
+                })
+            })
+            .await
+            .map_err(|e| match e {
+                TransactionError::Connection(_) => NewSessionError::InternalError,
+                TransactionError::Transaction(e) => e,
+            })?;
+
+        if !evicted_sessions.is_empty() {
+            let evicted: Vec<i32> = evicted_sessions.iter().map(|s| s.public_id).collect();
+            tracing::info!(
+                user_id = user_identifier,
+                ?evicted,
+                "sessions limit reached, evicted the oldest sessions"
+            );
+        }
 
-        new_session.insert(&self.db).await?;
+        Ok(NewSession {
+            session_id: ses_token,
+            evicted_sessions,
+        })
+    }
+
+    /// creates a session for `user_id` on behalf of the superuser `impersonator_id`, the session
+    /// is marked as impersonated, lasts `IMPERSONATION_SESSION_HOURS` and its creation is recorded
+    /// on the impersonation log.
+    pub async fn new_impersonation_session(
+        &self,
+        user_id: i32,
+        impersonator_id: i32,
+        client_ip: IpAddr,
+        client_user_agent: String,
+    ) -> Result<(SessionId, session::Model)> {
+        let ses_token = SessionId::generate_new(&mut self.rng.lock().unwrap());
+        let ip = IpNetwork::from(client_ip).to_string();
+
+        let created_session = self
+            .db
+            .transaction::<_, session::Model, DbErr>(|tx| {
+                Box::pin(async move {
+                    let created_session = session::ActiveModel {
+                        ip: Set(ip.clone()),
+                        user_agent: Set(client_user_agent.clone()),
+                        expires_at: Set(Utc::now() + Duration::hours(IMPERSONATION_SESSION_HOURS)),
+                        user_id: Set(user_id),
+                        impersonator_id: Set(Some(impersonator_id)),
+                        session_token: Set(ses_token.into_database_value()),
+                        ..Default::default()
+                    }
+                    .insert(tx)
+                    .await?;
+
+                    impersonation_log::ActiveModel {
+                        action: Set(String::from(IMPERSONATION_STARTED)),
+                        impersonator_id: Set(impersonator_id),
+                        impersonated_user_id: Set(user_id),
+                        session_public_id: Set(created_session.public_id),
+                        user_agent: Set(client_user_agent),
+                        ip: Set(ip),
+                        ..Default::default()
+                    }
+                    .insert(tx)
+                    .await?;
+
+                    record_impersonation_activity(
+                        tx,
+                        ActivityEvent::ImpersonationStarted(ImpersonationActivity {
+                            impersonated_user_id: user_id,
+                            session_public_id: created_session.public_id,
+                        }),
+                    )
+                    .await?;
+
+                    Ok(created_session)
+                })
+            })
+            .await?;
+
+        Ok((ses_token, created_session))
+    }
+
+    /// deletes a impersonation session, recording it on the impersonation log
+    ///
+    /// returns `false` if the session does not exist or is not a impersonation session
+    pub async fn stop_impersonation_session(
+        &self,
+        session_id: &SessionId,
+        client_ip: IpAddr,
+        client_user_agent: String,
+    ) -> Result<bool> {
+        let session_token = session_id.into_database_value();
+        let ip = IpNetwork::from(client_ip).to_string();
+
+        let stopped = self
+            .db
+            .transaction::<_, bool, DbErr>(|tx| {
+                Box::pin(async move {
+                    let maybe_session = session::Entity::find()
+                        .filter(session::Column::SessionToken.eq(session_token.clone()))
+                        .one(tx)
+                        .await?;
+
+                    let Some(ses) = maybe_session else {
+                        return Ok(false);
+                    };
+
+                    let Some(impersonator_id) = ses.impersonator_id else {
+                        return Ok(false);
+                    };
+
+                    impersonation_log::ActiveModel {
+                        action: Set(String::from(IMPERSONATION_STOPPED)),
+                        impersonator_id: Set(impersonator_id),
+                        impersonated_user_id: Set(ses.user_id),
+                        session_public_id: Set(ses.public_id),
+                        user_agent: Set(client_user_agent),
+                        ip: Set(ip),
+                        ..Default::default()
+                    }
+                    .insert(tx)
+                    .await?;
+
+                    record_impersonation_activity(
+                        tx,
+                        ActivityEvent::ImpersonationStopped(ImpersonationActivity {
+                            impersonated_user_id: ses.user_id,
+                            session_public_id: ses.public_id,
+                        }),
+                    )
+                    .await?;
+
+                    session::Entity::delete_many()
+                        .filter(session::Column::SessionToken.eq(session_token))
+                        .exec(tx)
+                        .await?;
+
+                    Ok(true)
+                })
+            })
+            .await?;
 
-        Ok(ses_token)
+        Ok(stopped)
     }
 
     /// lists all sessions belonging to a user
@@ -70,6 +387,29 @@ impl AuthService {
         Ok(sessions)
     }
 
+    /// finds a session and its user by the session public ID, sessions of users
+    /// that do not belong to the organization are treated as not found, `None`
+    /// finds only sessions of users without a organization (eg: superusers)
+    pub async fn get_session_by_public_id(
+        &self,
+        public_id: i32,
+        org_id: Option<i32>,
+    ) -> Result<Option<(session::Model, user::Model)>> {
+        let org_filter = match org_id {
+            Some(org_id) => user::Column::OrganizationId.eq(org_id),
+            None => user::Column::OrganizationId.is_null(),
+        };
+
+        let found = session::Entity::find()
+            .find_also_related(user::Entity)
+            .filter(session::Column::PublicId.eq(public_id))
+            .filter(org_filter)
+            .one(&self.db)
+            .await?;
+
+        Ok(found.and_then(|(ses, ses_user)| Some((ses, ses_user?))))
+    }
+
     /// deletes a session by its token
     pub async fn delete_session(&self, session_id: &SessionId) -> Result<()> {
         session::Entity::delete_many()
@@ -90,26 +430,81 @@ impl AuthService {
         Ok(())
     }
 
-    /// gets the user from the session token if the session is not expired
+    /// sets a new password for the user, invalidating any pending password reset token and
+    /// deleting every session of the user but `session_to_keep` (if any), so sessions created
+    /// with the old password (possibly by someone who had access to it) are signed out.
+    ///
+    /// returns the amount of deleted sessions
+    pub async fn change_password(
+        &self,
+        user_id: i32,
+        new_password_hash: String,
+        session_to_keep: Option<&SessionId>,
+    ) -> Result<u64> {
+        let session_to_keep = session_to_keep.map(|ses| ses.into_database_value());
+
+        let deleted_sessions = self
+            .db
+            .transaction::<_, u64, DbErr>(|tx| {
+                Box::pin(async move {
+                    user::Entity::update_many()
+                        .col_expr(user::Column::Password, Expr::value(new_password_hash))
+                        .col_expr(
+                            user::Column::ResetPasswordToken,
+                            Expr::value::<Option<String>>(None),
+                        )
+                        .filter(user::Column::Id.eq(user_id))
+                        .exec(tx)
+                        .await?;
+
+                    let deleted = session::Entity::delete_many()
+                        .filter(session::Column::UserId.eq(user_id))
+                        .apply_if(session_to_keep, |query, token| {
+                            query.filter(session::Column::SessionToken.ne(token))
+                        })
+                        .exec(tx)
+                        .await?;
+
+                    Ok(deleted.rows_affected)
+                })
+            })
+            .await?;
+
+        Ok(deleted_sessions)
+    }
+
+    /// gets the user from the session token if the session is not expired, together
+    /// with the id of the impersonator if its a impersonation session
     pub async fn get_user_from_session_id(
         &self,
         session_id: SessionId,
-    ) -> Result<Option<UserDtoEntities>> {
-        let result = user::Entity::find()
-            .inner_join(session::Entity)
+    ) -> Result<Option<(UserDtoEntities, Option<i32>)>> {
+        let result = session::Entity::find()
             .filter(session::Column::ExpiresAt.gt(Utc::now()))
             .filter(session::Column::SessionToken.eq(session_id.into_database_value()))
-            .find_also_related(organization::Entity)
+            .find_also_related(user::Entity)
             .one(&self.db)
             .await?;
 
-        if let Some((user, organization)) = result {
+        if let Some((session, Some(user))) = result {
+            let organization = match user.organization_id {
+                Some(org_id) => organization::Entity::find_by_id(org_id)
+                    .one(&self.db)
+                    .await?
+                    .map(Some)
+                    .context("organization not found")?,
+                None => None,
+            };
+
             let access_level = access_level::Entity::find_by_id(user.access_level_id)
                 .one(&self.db)
                 .await?
                 .context("access level not found")?;
 
-            return Ok(Some((user, access_level, organization)));
+            return Ok(Some((
+                (user, access_level, organization),
+                session.impersonator_id,
+            )));
         }
 
         Ok(None)
@@ -136,19 +531,59 @@ impl AuthService {
                     .or(Err(UserFromCredentialsError::InternalError))?
                     .ok_or(UserFromCredentialsError::NotFound)?;
 
-                let pass_is_valid = verify(user_password, &user.password)
+                let pass_is_valid = verify(&user_password, &user.password)
                     .or(Err(UserFromCredentialsError::InternalError))?;
 
                 if !pass_is_valid {
                     return Err(UserFromCredentialsError::InvalidPassword);
                 }
 
+                self.upgrade_password_hash_cost(&user, &user_password).await;
+
                 Ok(UserDto::from((user, access_level, organization)))
             }
             None => Err(UserFromCredentialsError::NotFound),
         }
     }
 
+    /// re-hashes the password of the user if its hash has a lower cost than the configured
+    /// one, since the plain password is needed this should be called when the user signs in
+    ///
+    /// failing to upgrade is not a error for the sign in, as the current hash is still valid
+    async fn upgrade_password_hash_cost(&self, user: &user::Model, plain_password: &str) {
+        let configured_cost = app_config().bcrypt_cost;
+
+        let Ok(hash_parts) = user.password.parse::<HashParts>() else {
+            return;
+        };
+
+        if hash_parts.get_cost() >= configured_cost {
+            return;
+        }
+
+        let Ok(new_hash) = hash_password(plain_password) else {
+            return;
+        };
+
+        let result = user::Entity::update_many()
+            .col_expr(user::Column::Password, Expr::value(new_hash))
+            // if the password changed since it was read the new password must be kept
+            .filter(user::Column::Password.eq(&user.password))
+            .filter(user::Column::Id.eq(user.id))
+            .exec(&self.db)
+            .await;
+
+        match result {
+            Ok(_) => tracing::info!(
+                user_id = user.id,
+                from_cost = hash_parts.get_cost(),
+                to_cost = configured_cost,
+                "password hash cost upgraded"
+            ),
+            Err(err) => tracing::error!("failed to upgrade password hash cost: {:?}", err),
+        }
+    }
+
     /// checks if a email is in use by a organization or a user
     pub async fn check_email_in_use(&self, email: &str) -> Result<bool> {
         let org = organization::Entity::find()
@@ -190,6 +625,60 @@ impl AuthService {
         Ok(token)
     }
 
+    /// creates a single use ticket for the user to authenticate a SocketIO connection,
+    /// returning the ticket and when it expires
+    pub async fn gen_socket_connection_ticket(
+        &self,
+        user_id: i32,
+    ) -> Result<(String, DateTime<Utc>)> {
+        let mut bytes = [0u8; 32];
+        self.rng.lock().unwrap().fill_bytes(&mut bytes);
+
+        let ticket: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        let expires_at = Utc::now() + Duration::seconds(SOCKET_CONNECTION_TICKET_SECONDS);
+
+        // tickets are meant to be used right away, so expired ones are
+        // removed whenever a new one is created instead of on a schedule
+        socket_connection_ticket::Entity::delete_many()
+            .filter(socket_connection_ticket::Column::ExpiresAt.lt(Utc::now()))
+            .exec(&self.db)
+            .await?;
+
+        socket_connection_ticket::ActiveModel {
+            ticket: Set(ticket.clone()),
+            expires_at: Set(expires_at),
+            user_id: Set(user_id),
+            ..Default::default()
+        }
+        .insert(&self.db)
+        .await?;
+
+        Ok((ticket, expires_at))
+    }
+
+    /// consumes a SocketIO connection ticket, returning the id of its
+    /// user or `None` if the ticket does not exist, expired or was used
+    pub async fn consume_socket_connection_ticket(&self, ticket: &str) -> Result<Option<i32>> {
+        let Some(found) = socket_connection_ticket::Entity::find_by_id(ticket)
+            .one(&self.db)
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        // only one of concurrent attempts to use the same ticket deletes it
+        let deleted = socket_connection_ticket::Entity::delete_many()
+            .filter(socket_connection_ticket::Column::Ticket.eq(ticket))
+            .exec(&self.db)
+            .await?;
+
+        if deleted.rows_affected == 0 || found.expires_at < Utc::now() {
+            return Ok(None);
+        }
+
+        Ok(Some(found.user_id))
+    }
+
     pub fn get_user_id_from_token_aud(&self, aud: String) -> Result<i32> {
         let n = aud
             .strip_prefix("user:")
@@ -216,6 +705,128 @@ impl AuthService {
         Ok(token)
     }
 
+    /// generates a token for a invited user to set his password, as setting the password
+    /// is done by the password recovery flow the token is stored as the reset password token
+    pub async fn gen_and_set_user_invite_token(&self, user_id: i32) -> Result<String> {
+        let mut claims = Claims::default();
+
+        claims.set_expiration_in(Duration::days(7));
+        claims.aud = format!("user:{}", user_id);
+        claims.sub = String::from("user invite token");
+
+        let token = jwt::encode(&claims)?;
+
+        user::Entity::update_many()
+            .col_expr(user::Column::ResetPasswordToken, Expr::value(&token))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(token)
+    }
+
+    pub async fn gen_and_set_user_magic_link_token(&self, user_id: i32) -> Result<String> {
+        let mut claims = Claims::default();
+
+        claims.set_expiration_in(Duration::minutes(15));
+        claims.aud = format!("user:{}", user_id);
+        claims.sub = String::from("magic link token");
+
+        let token = jwt::encode(&claims)?;
+
+        user::Entity::update_many()
+            .col_expr(user::Column::MagicLinkToken, Expr::value(&token))
+            .filter(user::Column::Id.eq(user_id))
+            .exec(&self.db)
+            .await?;
+
+        Ok(token)
+    }
+
+    /// clears the magic link token, returning the user it belonged to or `None` if no user
+    /// has the token, the token is cleared and returned by a single query so concurrent
+    /// requests with the same token can not both sign in
+    /// signs in with a magic link token, creating a session for the user with the token, the
+    /// token is cleared in the same transaction the session is created, so the link can only
+    /// be used once but is not spent by a sign in that fails (eg: due to the sessions limit).
+    ///
+    /// returns `None` if no user has the token.
+    pub async fn sign_in_by_magic_link_token(
+        &self,
+        token: &str,
+        client_ip: IpAddr,
+        client_user_agent: String,
+        replaced_session: Option<SessionId>,
+    ) -> Result<Option<(UserDto, NewSession)>, NewSessionError> {
+        let ses_token = SessionId::generate_new(&mut self.rng.lock().unwrap());
+        let ip = IpNetwork::from(client_ip).to_string();
+        let token = token.to_owned();
+
+        let signed_in = self
+            .db
+            .transaction::<_, Option<(user::Model, Vec<session::Model>)>, NewSessionError>(|tx| {
+                Box::pin(async move {
+                    let Some(user) = user::Entity::update_many()
+                        .col_expr(
+                            user::Column::MagicLinkToken,
+                            Expr::value::<Option<String>>(None),
+                        )
+                        .filter(user::Column::MagicLinkToken.eq(token))
+                        .exec_with_returning(tx)
+                        .await?
+                        .pop()
+                    else {
+                        return Ok(None);
+                    };
+
+                    let evicted_sessions = insert_session(
+                        tx,
+                        user.id,
+                        ses_token,
+                        ip,
+                        client_user_agent,
+                        replaced_session,
+                    )
+                    .await?;
+
+                    Ok(Some((user, evicted_sessions)))
+                })
+            })
+            .await
+            .map_err(|e| match e {
+                TransactionError::Connection(_) => NewSessionError::InternalError,
+                TransactionError::Transaction(e) => e,
+            })?;
+
+        let Some((user, evicted_sessions)) = signed_in else {
+            return Ok(None);
+        };
+
+        let organization = match user.organization_id {
+            Some(org_id) => {
+                organization::Entity::find_by_id(org_id)
+                    .one(&self.db)
+                    .await?
+            }
+            None => None,
+        };
+
+        let access_level = access_level::Entity::find_by_id(user.access_level_id)
+            .one(&self.db)
+            .await?
+            .ok_or(NewSessionError::InternalError)?;
+
+        let session = NewSession {
+            session_id: ses_token,
+            evicted_sessions,
+        };
+
+        Ok(Some((
+            UserDto::from((user, access_level, organization)),
+            session,
+        )))
+    }
+
     pub async fn gen_and_set_user_confirm_email_token(&self, user_id: i32) -> Result<String> {
         let mut claims = Claims::default();
 
@@ -260,7 +871,7 @@ impl AuthService {
         &self,
         dto: dto::RegisterOrganization,
     ) -> Result<dto::UserDto> {
-        let password_hash = hash(dto.password, DEFAULT_COST)?;
+        let password_hash = hash_password(dto.password)?;
 
         let user_dto = self
             .db
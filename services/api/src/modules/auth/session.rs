@@ -12,6 +12,10 @@ use shared::entity::session;
 pub const SESSION_ID_COOKIE_NAME: &str = "sid";
 pub const SESSION_DAYS_DURATION: i64 = 5;
 
+/// duration of sessions created by superusers to impersonate other users, kept
+/// short since they are only meant for support
+pub const IMPERSONATION_SESSION_HOURS: i64 = 1;
+
 /// a u128 that identifies a user session stored on the `sessions` database table
 #[derive(Clone, Copy, Debug)]
 pub struct SessionId(u128);
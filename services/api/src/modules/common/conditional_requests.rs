@@ -0,0 +1,153 @@
+//! Support for HEAD and conditional requests on endpoints that serve S3 objects, such as photos,
+//! allowing clients to revalidate their cached copy of a object without downloading it again.
+
+use super::responses::{internal_error_res, SimpleError};
+use crate::services::s3::{ObjectMetadata, S3};
+use axum::{
+    async_trait,
+    extract::FromRequestParts,
+    http::{header, request::Parts, HeaderMap, HeaderValue, Method, StatusCode},
+    response::{IntoResponse, Response},
+};
+use axum_extra::headers::{
+    CacheControl, ContentLength, ETag, HeaderMapExt, IfModifiedSince, IfNoneMatch, LastModified,
+};
+use std::convert::Infallible;
+
+/// The `If-None-Match` and `If-Modified-Since` headers of a request
+///
+/// malformed headers are ignored, as if they were not sent
+pub struct Preconditions {
+    if_none_match: Option<IfNoneMatch>,
+    if_modified_since: Option<IfModifiedSince>,
+}
+
+#[async_trait]
+impl<S> FromRequestParts<S> for Preconditions
+where
+    S: Send + Sync,
+{
+    type Rejection = Infallible;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        Ok(Preconditions {
+            if_none_match: parts.headers.typed_get(),
+            if_modified_since: parts.headers.typed_get(),
+        })
+    }
+}
+
+impl Preconditions {
+    /// if the copy of the object the client has is up to date and a 304 can be sent instead of it
+    ///
+    /// as per RFC 9110, `If-Modified-Since` is only evaluated when `If-None-Match` is not sent
+    pub fn is_not_modified(&self, metadata: &ObjectMetadata) -> bool {
+        if let Some(if_none_match) = &self.if_none_match {
+            return parse_e_tag(metadata)
+                .is_some_and(|e_tag| !if_none_match.precondition_passes(&e_tag));
+        }
+
+        match (&self.if_modified_since, metadata.last_modified) {
+            (Some(if_modified_since), Some(last_modified)) => {
+                !if_modified_since.is_modified(last_modified)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_e_tag(metadata: &ObjectMetadata) -> Option<ETag> {
+    metadata
+        .e_tag
+        .as_deref()
+        .and_then(|e_tag| e_tag.parse().ok())
+}
+
+/// headers that allow the client to cache the object and revalidate it on every use
+fn validator_headers(metadata: &ObjectMetadata) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+
+    if let Some(e_tag) = parse_e_tag(metadata) {
+        headers.typed_insert(e_tag);
+    }
+
+    if let Some(last_modified) = metadata.last_modified {
+        headers.typed_insert(LastModified::from(last_modified));
+    }
+
+    // objects are only served to authenticated users, so shared caches must not store them
+    headers.typed_insert(CacheControl::new().with_private().with_no_cache());
+
+    headers
+}
+
+fn content_headers(metadata: &ObjectMetadata) -> HeaderMap {
+    let mut headers = validator_headers(metadata);
+
+    if let Some(content_type) = metadata
+        .content_type
+        .as_deref()
+        .and_then(|v| HeaderValue::from_str(v).ok())
+    {
+        headers.insert(header::CONTENT_TYPE, content_type);
+    }
+
+    headers
+}
+
+/// Responds with the S3 object of the given key, honoring conditional requests
+///
+/// - `304` without a body if the client copy of the object is up to date
+/// - `404` if the object does not exist
+/// - for HEAD requests only the object metadata is fetched, and no body is sent
+pub async fn s3_object_response(
+    s3: &S3,
+    key: String,
+    method: &Method,
+    preconditions: &Preconditions,
+) -> Result<Response, (StatusCode, SimpleError)> {
+    let not_found = || (StatusCode::NOT_FOUND, SimpleError::entity_not_found());
+
+    if method == Method::HEAD {
+        let metadata = s3
+            .head(key)
+            .await
+            .map_err(|_| internal_error_res())?
+            .ok_or_else(not_found)?;
+
+        if preconditions.is_not_modified(&metadata) {
+            return Ok((StatusCode::NOT_MODIFIED, validator_headers(&metadata)).into_response());
+        }
+
+        let mut headers = content_headers(&metadata);
+
+        if let Some(content_length) = metadata.content_length.and_then(|v| u64::try_from(v).ok()) {
+            headers.typed_insert(ContentLength(content_length));
+        }
+
+        return Ok(headers.into_response());
+    }
+
+    let object = s3
+        .get(key)
+        .await
+        .map_err(|_| internal_error_res())?
+        .ok_or_else(not_found)?;
+
+    if preconditions.is_not_modified(&object.metadata) {
+        return Ok((
+            StatusCode::NOT_MODIFIED,
+            validator_headers(&object.metadata),
+        )
+            .into_response());
+    }
+
+    let body = object
+        .body
+        .collect()
+        .await
+        .map_err(|_| internal_error_res())?
+        .into_bytes();
+
+    Ok((content_headers(&object.metadata), body).into_response())
+}
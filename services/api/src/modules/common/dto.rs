@@ -1,4 +1,4 @@
-use crate::modules::{access_level, user};
+use crate::modules::{access_level, auth, organization, tracker, tracking, user, vehicle};
 use axum::body::Bytes;
 use axum_typed_multipart::{FieldData, TryFromMultipart};
 use serde::{Deserialize, Deserializer, Serialize};
@@ -39,6 +39,12 @@ pub struct Pagination {
     pub page_size: u64,
 }
 
+/// Amount of records matching a filter
+#[derive(Serialize, ToSchema)]
+pub struct CountDto {
+    pub count: u64,
+}
+
 /// Pagination metadata of a executed query.
 ///
 /// this struct also requires `T` on the records field to implement
@@ -51,7 +57,16 @@ pub struct Pagination {
     PaginatedVehicle = PaginationResult<entity::vehicle::Model>,
     PaginatedSimCard = PaginationResult<entity::sim_card::Model>,
     PaginatedAccessLevel = PaginationResult<access_level::dto::AccessLevelDto>,
-    PaginatedVehicleTracker = PaginationResult<entity::vehicle_tracker::Model>
+    PaginatedAccessLevelUsage = PaginationResult<access_level::dto::AccessLevelUsageDto>,
+    PaginatedVehicleTracker = PaginationResult<entity::vehicle_tracker::Model>,
+    PaginatedOrganizationSummary = PaginationResult<organization::dto::OrganizationSummaryDto>,
+    PaginatedImpersonationLog = PaginationResult<entity::impersonation_log::Model>,
+    PaginatedLoginHistory = PaginationResult<entity::login_history::Model>,
+    PaginatedOrgSession = PaginationResult<auth::dto::OrgSessionDto>,
+    PaginatedOrganizationActivity = PaginationResult<organization::dto::ActivityDto>,
+    PaginatedStaleTracker = PaginationResult<tracker::dto::StaleTrackerDto>,
+    PaginatedVehicleWithPosition = PaginationResult<vehicle::dto::VehicleWithPositionDto>,
+    PaginatedUnknownImei = PaginationResult<tracking::dto::UnknownImeiDto>
 )]
 pub struct PaginationResult<T: for<'_s> ToSchema<'_s>> {
     /// 1 Indexed Page number
@@ -72,6 +87,47 @@ pub struct PaginationResult<T: for<'_s> ToSchema<'_s>> {
     pub records: Vec<T>,
 }
 
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkDeleteDto {
+    /// IDs of the entities to delete
+    #[validate(length(min = 1, max = 100))]
+    pub ids: Vec<i32>,
+}
+
+/// Result of a bulk delete, the requested IDs that were not deleted did
+/// not exist or do not belong to the organization of the request user
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkDeleteResultDto {
+    pub deleted: Vec<i32>,
+    pub not_found: Vec<i32>,
+}
+
+impl BulkDeleteResultDto {
+    pub fn new(requested: &[i32], deleted: Vec<i32>) -> Self {
+        let mut not_found: Vec<i32> = Vec::new();
+
+        for id in requested {
+            if !deleted.contains(id) && !not_found.contains(id) {
+                not_found.push(*id);
+            }
+        }
+
+        Self { deleted, not_found }
+    }
+}
+
+/// Query of destructive operations that can be previewed without being applied
+#[derive(Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct DryRun {
+    /// if the operation should be validated and return what would change, without applying it
+    #[serde(default, alias = "dry_run")]
+    pub dry_run: bool,
+}
+
 /// DTO to send a image, should be extracted from `multipart/form-data`
 /// requests containing a single field `image` field
 #[derive(TryFromMultipart, ToSchema)]
@@ -81,18 +137,13 @@ pub struct SingleImageDto {
 }
 
 /// Simple enum to order a query by ascending or descending order
-#[derive(Debug, ToSchema)]
+#[derive(Debug, Default, Clone, Copy, ToSchema)]
 pub enum AscOrDescOrder {
     Asc,
+    #[default]
     Desc,
 }
 
-impl Default for AscOrDescOrder {
-    fn default() -> Self {
-        Self::Desc
-    }
-}
-
 impl From<AscOrDescOrder> for sea_query::Order {
     fn from(value: AscOrDescOrder) -> Self {
         match value {
@@ -119,3 +170,17 @@ impl<'de> Deserialize<'de> for AscOrDescOrder {
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dry_run_accepts_its_former_snake_case_name() {
+        let parse = |json: &str| serde_json::from_str::<DryRun>(json).unwrap().dry_run;
+
+        assert!(parse(r#"{"dryRun":true}"#));
+        assert!(parse(r#"{"dry_run":true}"#));
+        assert!(!parse("{}"));
+    }
+}
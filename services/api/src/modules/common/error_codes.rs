@@ -1,28 +1,137 @@
 use core::str;
 
-/// a action could not be executed because
-pub static MISSING_PERMISSIONS: &str = "MISSING_PERMISSIONS";
+/// A error code of the API, sent on the error responses so clients can
+/// handle errors without relying on the error messages
+pub struct ErrorCode {
+    pub code: &'static str,
 
-/// a entity could not be created or updated with a given
-/// email because its already in use by another entity
-pub static EMAIL_IN_USE: &str = "EMAIL_IN_USE";
+    /// the doc comment of the error code
+    description: &'static str,
+}
 
-/// a user could not be created or updated with
-/// a given username because its already in use
-pub static USERNAME_IN_USE: &str = "USERNAME_IN_USE";
+impl ErrorCode {
+    /// the description of the error code as a single line
+    pub fn description(&self) -> String {
+        self.description
+            .lines()
+            .map(str::trim)
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
 
-/// a request to a endpoint was not authorized because it did
-/// not contain the session id cookie in the request headers
-pub static NO_SID_COOKIE: &str = "NO_SID_COOKIE";
+/// declares each error code as a static with its name as value, and the `ERROR_CODES`
+/// registry with all of them, error codes must be sent only through these statics so
+/// every code a client can get is in the registry
+macro_rules! error_codes {
+    ($($(#[doc = $doc:literal])+ $name:ident,)+) => {
+        $(
+            $(#[doc = $doc])+
+            pub static $name: &str = stringify!($name);
+        )+
 
-/// a request to a endpoint was not authorized because the
-/// session on the session id cookie is expired or does not exist
-pub static INVALID_SESSION: &str = "INVALID_SESSION";
+        /// every error code of the API
+        pub static ERROR_CODES: &[ErrorCode] = &[
+            $(ErrorCode { code: stringify!($name), description: concat!($($doc, "\n"),+) },)+
+        ];
+    };
+}
 
-/// a request to a endpoint was not authorized because
-/// the organization the user belongs to was blocked
-pub static ORGANIZATION_BLOCKED: &str = "ORGANIZATION_BLOCKED";
+error_codes! {
+    /// a action could not be executed because the access
+    /// level of the request user lacks the required permissions
+    MISSING_PERMISSIONS,
 
-/// cannot confirm or request a email to confirm a email
-/// address because it is already confirmed
-pub static EMAIL_ALREADY_VERIFIED: &str = "EMAIL_ALREADY_VERIFIED";
+    /// a entity could not be created or updated with a given
+    /// email because its already in use by another entity
+    EMAIL_IN_USE,
+
+    /// a user could not be created or updated with
+    /// a given username because its already in use
+    USERNAME_IN_USE,
+
+    /// a tracker could not be created or updated with a given IMEI
+    /// because its already in use by another tracker of the organization
+    IMEI_IN_USE,
+
+    /// a SIM card could not be created or updated with a given SSN
+    /// because its already in use by another SIM card of the organization
+    SSN_IN_USE,
+
+    /// a SIM card could not be created or updated with a given phone number
+    /// because its already in use by another SIM card of the organization
+    PHONE_NUMBER_IN_USE,
+
+    /// a vehicle could not be created or updated with a given plate
+    /// because its already in use by another vehicle of the organization
+    PLATE_IN_USE,
+
+    /// a tracker could not be installed on a vehicle because
+    /// the vehicle already has another tracker installed
+    VEHICLE_IN_USE,
+
+    /// a tracker could not be installed on a vehicle because
+    /// it is installed on another vehicle
+    TRACKER_IN_USE,
+
+    /// a phone number could not be parsed or is not a valid number for its region
+    INVALID_PHONE_NUMBER,
+
+    /// a request to a endpoint was not authorized because it did
+    /// not contain the session id cookie in the request headers
+    NO_SID_COOKIE,
+
+    /// a user could not sign in because it reached the maximum amount of
+    /// sessions and the API is configured to reject new ones
+    SESSION_LIMIT_REACHED,
+
+    /// a request to a endpoint was not authorized because the
+    /// session on the session id cookie is expired or does not exist
+    INVALID_SESSION,
+
+    /// a request to a endpoint was not authorized because
+    /// the organization the user belongs to was blocked
+    ORGANIZATION_BLOCKED,
+
+    /// cannot confirm or request a email to confirm a email
+    /// address because it is already confirmed
+    EMAIL_ALREADY_VERIFIED,
+
+    /// a email address cannot be used to send emails because
+    /// it is not a verified identity on the email provider
+    EMAIL_SENDER_NOT_VERIFIED,
+
+    /// the verification of a email address or domain as a email sender cannot be started
+    /// because it is a identity of another organization or was not added by a organization
+    EMAIL_SENDER_IN_USE,
+
+    /// a feature cannot be used because it is not
+    /// enabled for the organization of the request user
+    FEATURE_NOT_ENABLED,
+
+    /// a entity could not be created because the organization reached
+    /// the maximum amount of said entity its plan allows
+    LIMIT_REACHED,
+
+    /// a request was refused because its client ip is not within
+    /// the ip allowlist of the organization of the request user
+    IP_NOT_ALLOWED,
+
+    /// a user could not be created without a access level because
+    /// the organization does not have a default access level
+    NO_DEFAULT_ACCESS_LEVEL,
+
+    /// a email could not be queued because the message broker is unreachable, the request
+    /// can be retried later
+    EMAIL_SERVICE_UNAVAILABLE,
+
+    /// a email could not be queued because the message broker refused it
+    EMAIL_REJECTED,
+
+    /// a request body or query is invalid, see `ValidationErrorResponse` for the invalid fields
+    VALIDATION,
+
+    /// a request body was refused because its `Content-Type` is not a accepted one (eg: `text/plain`
+    /// for a JSON endpoint) or its charset is not `utf-8`
+    UNSUPPORTED_CONTENT_TYPE,
+}
@@ -1,23 +1,30 @@
 use crate::{
     database::error::DbError,
-    modules::{auth::middleware::RequestUser, common::responses::SimpleError},
+    modules::{
+        auth::middleware::RequestUser,
+        common::{
+            error_codes::UNSUPPORTED_CONTENT_TYPE,
+            responses::{SimpleError, ValidationErrorResponse},
+        },
+    },
     server::controller::AppState,
 };
 use axum::{
     async_trait,
     extract::{rejection::JsonRejection, FromRequest, FromRequestParts, Path, Query},
-    http::{request::Parts, Request, StatusCode},
+    http::{header::CONTENT_TYPE, request::Parts, HeaderMap, Request, StatusCode},
+    response::{IntoResponse, Response},
     Json,
 };
 use axum_typed_multipart::{BaseMultipart, TypedMultipartError};
 use sea_orm::DatabaseConnection;
 use serde::de::DeserializeOwned;
-use shared::entity::traits::QueryableByIdAndOrgId;
+use shared::entity::traits::{QueryableByIdAndOrgId, ReadableByIdAndOrgId};
 use validator::Validate;
 
 /// Wrapper struct that extracts from the request query exactly `axum::Query<T>`
 /// but also requires T to impl `Validate`, if validation fails a bad request code
-/// and simple error is returned
+/// and `ValidationErrorResponse` is returned
 #[derive(Clone, Copy)]
 pub struct ValidatedQuery<T>(pub T);
 
@@ -27,22 +34,55 @@ where
     S: Send + Sync,
     T: DeserializeOwned + Validate,
 {
-    type Rejection = (StatusCode, SimpleError);
+    type Rejection = Response;
 
     async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
         match Query::<T>::from_request_parts(parts, state).await {
             Ok(payload) => match payload.validate() {
                 Ok(_) => Ok(ValidatedQuery(payload.0)),
-                Err(e) => Err((StatusCode::BAD_REQUEST, SimpleError::from(e))),
+                Err(e) => Err(ValidationErrorResponse::from(e).into_response()),
             },
-            Err(rejection) => Err((rejection.status(), SimpleError::from(rejection.to_string()))),
+            Err(rejection) => {
+                Err((rejection.status(), SimpleError::from(rejection.to_string())).into_response())
+            }
         }
     }
 }
 
+/// Media types accepted by `ValidatedJson`, besides them any `application/*+json` type is accepted
+pub const JSON_CONTENT_TYPES: [&str; 1] = ["application/json"];
+
+/// if the request `Content-Type` is a JSON one (see `JSON_CONTENT_TYPES`) and its
+/// charset, if any, is `utf-8`, media types and charsets are case insensitive
+fn has_json_content_type(headers: &HeaderMap) -> bool {
+    let Some(content_type) = headers.get(CONTENT_TYPE).and_then(|v| v.to_str().ok()) else {
+        return false;
+    };
+
+    let mut parts = content_type.split(';');
+
+    let media_type = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+
+    let is_json = JSON_CONTENT_TYPES.contains(&media_type.as_str())
+        || (media_type.starts_with("application/") && media_type.ends_with("+json"));
+
+    let is_utf8 = parts.all(|param| match param.split_once('=') {
+        Some((name, value)) if name.trim().eq_ignore_ascii_case("charset") => {
+            let charset = value.trim().trim_matches('"');
+            charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("utf8")
+        }
+        _ => true,
+    });
+
+    is_json && is_utf8
+}
+
 /// Wrapper struct that extracts the request body as json exactly as `axum::Json<T>`
 /// but also requires T to impl `Validate`, if validation fails a bad request code
-/// and simple error is returned
+/// and `ValidationErrorResponse` is returned
+///
+/// requests without a JSON `Content-Type` (see `has_json_content_type`) are refused
+/// with `415 Unsupported Media Type` and `UNSUPPORTED_CONTENT_TYPE` before parsing the body
 #[derive(Clone, Copy)]
 pub struct ValidatedJson<T>(pub T);
 
@@ -53,25 +93,35 @@ where
     T: Validate,
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, SimpleError);
+    type Rejection = Response;
 
     async fn from_request(
         req: Request<axum::body::Body>,
         state: &S,
     ) -> Result<Self, Self::Rejection> {
+        if !has_json_content_type(req.headers()) {
+            return Err((
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                SimpleError::from(UNSUPPORTED_CONTENT_TYPE),
+            )
+                .into_response());
+        }
+
         match Json::<T>::from_request(req, state).await {
             Ok(payload) => match payload.validate() {
                 Ok(_) => Ok(ValidatedJson(payload.0)),
-                Err(e) => Err((StatusCode::BAD_REQUEST, SimpleError::from(e))),
+                Err(e) => Err(ValidationErrorResponse::from(e).into_response()),
             },
-            Err(rejection) => Err((rejection.status(), SimpleError::from(rejection.to_string()))),
+            Err(rejection) => {
+                Err((rejection.status(), SimpleError::from(rejection.to_string())).into_response())
+            }
         }
     }
 }
 
 /// Wrapper struct that extracts the request body from `axum_typed_multipart::TryFromMultipart`
-/// but also requires T to impl `Validate`, if validation fails a bad request code and simple
-/// error is returned
+/// but also requires T to impl `Validate`, if validation fails a bad request code and
+/// `ValidationErrorResponse` is returned
 #[derive(Clone, Copy)]
 pub struct ValidatedMultipart<T>(pub T);
 
@@ -82,7 +132,7 @@ where
     T: Validate,
     S: Send + Sync,
 {
-    type Rejection = (StatusCode, SimpleError);
+    type Rejection = Response;
 
     async fn from_request(
         req: Request<axum::body::Body>,
@@ -91,12 +141,13 @@ where
         match BaseMultipart::<T, TypedMultipartError>::from_request(req, state).await {
             Ok(payload) => match payload.data.validate() {
                 Ok(_) => Ok(ValidatedMultipart(payload.data)),
-                Err(e) => Err((StatusCode::BAD_REQUEST, SimpleError::from(e))),
+                Err(e) => Err(ValidationErrorResponse::from(e).into_response()),
             },
             Err(rejection) => Err((
                 StatusCode::BAD_REQUEST,
                 SimpleError::from(rejection.to_string()),
-            )),
+            )
+                .into_response()),
         }
     }
 }
@@ -132,6 +183,31 @@ where
     }
 }
 
+/// Requires the request user to be a superuser (see `RequestUser::is_superuser`), failing with
+/// `(StatusCode::FORBIDDEN, SimpleError::from("endpoint only for superusers"))` otherwise.
+///
+/// this requires the `RequestUser` extension to be available.
+#[derive(Clone, Copy)]
+pub struct SuperUser;
+
+#[async_trait]
+impl<S> FromRequestParts<S> for SuperUser
+where
+    S: Send + Sync,
+{
+    type Rejection = (StatusCode, SimpleError);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        match parts.extensions.get::<RequestUser>() {
+            Some(req_user) if req_user.is_superuser() => Ok(SuperUser),
+            _ => Err((
+                StatusCode::FORBIDDEN,
+                SimpleError::from("endpoint only for superusers"),
+            )),
+        }
+    }
+}
+
 /// Helper to get a DB connection from the state
 pub struct DbConnection(pub DatabaseConnection);
 
@@ -147,6 +223,12 @@ impl FromRequestParts<AppState> for DbConnection {
 /// Path extractor that fetches a entity by its ID on a endpoint path parameter
 /// and the OrganizationId of the request user, returns a 404 response if the
 /// entity is not found
+///
+/// Entities of other organizations are treated exactly as if they did not exist,
+/// so the response never tells the request user whether a ID is in use by another
+/// organization. Endpoints that find org bound entities manually must follow the
+/// same policy and reserve 403 for entities the request user can see but is not
+/// allowed to change (eg: fixed access levels)
 #[derive(Clone, Copy)]
 pub struct OrgBoundEntityFromPathId<T: QueryableByIdAndOrgId>(pub T::Model);
 
@@ -178,3 +260,98 @@ where
         Ok(OrgBoundEntityFromPathId(entity))
     }
 }
+
+/// Same as `OrgBoundEntityFromPathId` but also finds entities of other organizations the
+/// request user organization can read (see `ReadableByIdAndOrgId`), so it must only be
+/// used on read only endpoints
+#[derive(Clone, Copy)]
+pub struct OrgReadableEntityFromPathId<T: ReadableByIdAndOrgId>(pub T::Model);
+
+#[async_trait]
+impl<T> FromRequestParts<AppState> for OrgReadableEntityFromPathId<T>
+where
+    T: ReadableByIdAndOrgId,
+{
+    type Rejection = (http::StatusCode, SimpleError);
+
+    async fn from_request_parts(
+        parts: &mut Parts,
+        state: &AppState,
+    ) -> Result<Self, Self::Rejection> {
+        let org_id = OrganizationId::from_request_parts(parts, state).await?;
+
+        let id: Path<i32> = Path::from_request_parts(parts, state).await.map_err(|_| {
+            (
+                StatusCode::BAD_REQUEST,
+                SimpleError::from("failed to get entity id from path"),
+            )
+        })?;
+
+        let entity = T::find_readable_by_id_and_org_id(id.0, org_id.0, &state.db)
+            .await
+            .map_err(DbError::from)?
+            .ok_or((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))?;
+
+        Ok(OrgReadableEntityFromPathId(entity))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Validate)]
+    struct NameDto {
+        #[validate(length(min = 1))]
+        #[allow(dead_code)]
+        name: String,
+    }
+
+    async fn extract(content_type: Option<&str>, body: &'static str) -> Result<(), StatusCode> {
+        let mut req = Request::builder().method("POST").uri("/");
+
+        if let Some(content_type) = content_type {
+            req = req.header(CONTENT_TYPE, content_type);
+        }
+
+        let req = req.body(axum::body::Body::from(body)).unwrap();
+
+        ValidatedJson::<NameDto>::from_request(req, &())
+            .await
+            .map(|_| ())
+            .map_err(|res| res.status())
+    }
+
+    #[tokio::test]
+    async fn refuses_bodies_without_a_json_content_type() {
+        let body = r#"{ "name": "rastercar" }"#;
+        let unsupported = Err(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        assert_eq!(extract(None, body).await, unsupported);
+        assert_eq!(extract(Some("text/plain"), body).await, unsupported);
+        assert_eq!(
+            extract(Some("application/json; charset=latin1"), body).await,
+            unsupported
+        );
+    }
+
+    #[tokio::test]
+    async fn accepts_json_content_types() {
+        let body = r#"{ "name": "rastercar" }"#;
+
+        assert_eq!(extract(Some("application/json"), body).await, Ok(()));
+        assert_eq!(
+            extract(Some("Application/JSON; charset=\"UTF-8\""), body).await,
+            Ok(())
+        );
+        assert_eq!(
+            extract(Some("application/merge-patch+json"), body).await,
+            Ok(())
+        );
+        assert_eq!(
+            extract(Some("application/json"), r#"{ "name": "" }"#).await,
+            Err(StatusCode::BAD_REQUEST)
+        );
+    }
+}
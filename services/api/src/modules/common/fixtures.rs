@@ -0,0 +1,94 @@
+//! Models (and a unreachable database) shared by the unit tests, tests that need
+//! other values set them with the struct update syntax, eg:
+//!
+//! ```ignore
+//! sim_card::Model { vehicle_tracker_id: Some(10), ..fixtures::sim_card(1) }
+//! ```
+
+use crate::modules::{
+    access_level::dto::AccessLevelDto,
+    auth::{dto::UserDto, middleware::RequestUser},
+};
+use convert_case::{Case, Casing};
+use sea_orm::{DatabaseConnection, SqlxPostgresConnector};
+use shared::{
+    constants::{Permission, TrackerModel},
+    entity::{sim_card, vehicle_tracker},
+};
+use sqlx::postgres::PgPoolOptions;
+use std::time::Duration;
+
+/// a database connection to a port nothing listens on, queries on it fail with a
+/// connection error, for tests asserting the database is (or is not) queried
+pub fn unreachable_db() -> DatabaseConnection {
+    let pool = PgPoolOptions::new()
+        .acquire_timeout(Duration::from_secs(1))
+        .connect_lazy("postgres://rastercar@127.0.0.1:1/rastercar")
+        .unwrap();
+
+    SqlxPostgresConnector::from_sqlx_postgres_pool(pool)
+}
+
+/// a request user without a organization whose access level has the permissions
+pub fn user_with_permissions(permissions: &[Permission]) -> RequestUser {
+    RequestUser(UserDto {
+        id: 1,
+        created_at: Default::default(),
+        username: String::from("user"),
+        email: String::from("user@rastercar.com"),
+        email_verified: true,
+        profile_picture: None,
+        description: None,
+        organization: None,
+        access_level: AccessLevelDto {
+            id: 1,
+            created_at: Default::default(),
+            name: String::from("access level"),
+            description: String::from("access level"),
+            is_fixed: false,
+            permissions: permissions
+                .iter()
+                .map(|p| p.to_string().to_case(Case::ScreamingSnake))
+                .collect(),
+        },
+    })
+}
+
+/// a SIM card of the organization without PIN and PUK codes nor a tracker
+pub fn sim_card(organization_id: i32) -> sim_card::Model {
+    sim_card::Model {
+        id: 1,
+        created_at: Default::default(),
+        phone_number: String::from("+5511999999999"),
+        ssn: String::from("8955031234567890123"),
+        apn_address: String::from("apn.rastercar.com"),
+        apn_user: String::from("user"),
+        apn_password: String::from("password"),
+        pin: None,
+        pin2: None,
+        puk: None,
+        puk2: None,
+        organization_id,
+        vehicle_tracker_id: None,
+        data_quota_mb: None,
+    }
+}
+
+/// a tracker of the organization not installed on a vehicle
+pub fn tracker(organization_id: i32) -> vehicle_tracker::Model {
+    vehicle_tracker::Model {
+        id: 10,
+        created_at: Default::default(),
+        model: TrackerModel::H02,
+        imei: String::from("490154203237518"),
+        organization_id,
+        vehicle_id: None,
+        firmware_version: None,
+        notes: None,
+        installed_at: None,
+        reporting_interval_seconds: None,
+        loaned_to_org_id: None,
+        loan_expires_at: None,
+        loaned_at: None,
+    }
+}
@@ -0,0 +1,332 @@
+//! Parsing of geometries sent by clients as WKT or WKB, for interoperability with GIS tools.
+//!
+//! geometries are received on the standard GIS axis order (`x` = longitude, `y` = latitude)
+//! and converted to the internal one, where points are stored with the latitude as `x`.
+
+use geo_types::{Coord, Geometry, LineString, Polygon, Rect};
+use geozero::{
+    error::Result as GeozeroResult, geo_types::GeoWriter, wkb::Ewkb, wkt::WktStr, GeomProcessor,
+    GeozeroGeometry, ToWkt,
+};
+
+/// maximum amount of vertices of a polygon, as checking if a polygon is simple is quadratic
+pub const MAX_POLYGON_VERTICES: usize = 1000;
+
+/// Records the first and last coordinates of every polygon ring, as rings are closed when
+/// converted to `geo_types` and unclosed rings would otherwise be accepted silently
+#[derive(Default)]
+struct RingEnds {
+    rings: Vec<(Coord<f64>, Coord<f64>)>,
+    in_ring: bool,
+}
+
+impl GeomProcessor for RingEnds {
+    fn xy(&mut self, x: f64, y: f64, idx: usize) -> GeozeroResult<()> {
+        if let Some((first, last)) = self.rings.last_mut().filter(|_| self.in_ring) {
+            *last = Coord { x, y };
+
+            if idx == 0 {
+                *first = Coord { x, y };
+            }
+        }
+
+        Ok(())
+    }
+
+    fn linestring_begin(&mut self, tagged: bool, _size: usize, _idx: usize) -> GeozeroResult<()> {
+        // polygon rings are the only untagged linestrings
+        self.in_ring = !tagged;
+
+        if self.in_ring {
+            self.rings.push(Default::default());
+        }
+
+        Ok(())
+    }
+
+    fn linestring_end(&mut self, _tagged: bool, _idx: usize) -> GeozeroResult<()> {
+        self.in_ring = false;
+        Ok(())
+    }
+}
+
+/// decodes a hex string, `None` if it is not valid hex
+fn decode_hex(input: &str) -> Option<Vec<u8>> {
+    if !input.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..input.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(input.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// reads a geometry as hex encoded (E)WKB or as WKT
+fn read_geometry(input: &str, processor: &mut impl GeomProcessor) -> Result<(), String> {
+    match decode_hex(input) {
+        Some(wkb) => Ewkb(wkb)
+            .process_geom(processor)
+            .map_err(|e| format!("invalid WKB: {}", e)),
+        None => WktStr(input)
+            .process_geom(processor)
+            .map_err(|e| format!("invalid WKT: {}", e)),
+    }
+}
+
+/// orientation of the `c` point relative to the `a` -> `b` segment
+fn orientation(a: Coord<f64>, b: Coord<f64>, c: Coord<f64>) -> f64 {
+    (b.x - a.x) * (c.y - a.y) - (b.y - a.y) * (c.x - a.x)
+}
+
+/// if `c`, known to be collinear with the `a` -> `b` segment, is within it
+fn on_segment(a: Coord<f64>, b: Coord<f64>, c: Coord<f64>) -> bool {
+    c.x >= a.x.min(b.x) && c.x <= a.x.max(b.x) && c.y >= a.y.min(b.y) && c.y <= a.y.max(b.y)
+}
+
+fn segments_intersect(a: (Coord<f64>, Coord<f64>), b: (Coord<f64>, Coord<f64>)) -> bool {
+    let o1 = orientation(a.0, a.1, b.0);
+    let o2 = orientation(a.0, a.1, b.1);
+    let o3 = orientation(b.0, b.1, a.0);
+    let o4 = orientation(b.0, b.1, a.1);
+
+    if o1 * o2 < 0.0 && o3 * o4 < 0.0 {
+        return true;
+    }
+
+    (o1 == 0.0 && on_segment(a.0, a.1, b.0))
+        || (o2 == 0.0 && on_segment(a.0, a.1, b.1))
+        || (o3 == 0.0 && on_segment(b.0, b.1, a.0))
+        || (o4 == 0.0 && on_segment(b.0, b.1, a.1))
+}
+
+/// if a closed ring does not intersect itself, adjacent segments share a
+/// vertex so only segments that are not adjacent are checked
+fn is_simple_ring(ring: &LineString<f64>) -> bool {
+    let segments: Vec<_> = ring.lines().map(|line| (line.start, line.end)).collect();
+    let n = segments.len();
+
+    for i in 0..n {
+        for j in (i + 2)..n {
+            // the first and last segments share the closing vertex
+            if i == 0 && j == n - 1 {
+                continue;
+            }
+
+            if segments_intersect(segments[i], segments[j]) {
+                return false;
+            }
+        }
+    }
+
+    true
+}
+
+/// Parses a polygon sent as WKT or hex encoded (E)WKB, in the standard axis order
+/// (`x` = longitude), into a polygon in the internal axis order (`x` = latitude)
+///
+/// the polygon must be closed, simple (not intersect itself), without holes, with at
+/// most `MAX_POLYGON_VERTICES` vertices and within the valid longitude and latitude ranges
+pub fn parse_polygon(input: &str) -> Result<Polygon<f64>, String> {
+    let input = input.trim();
+
+    let mut writer = GeoWriter::new();
+    read_geometry(input, &mut writer)?;
+
+    let polygon = match writer.take_geometry() {
+        Some(Geometry::Polygon(polygon)) => polygon,
+        Some(_) => return Err(String::from("geometry must be a polygon")),
+        None => return Err(String::from("geometry is empty")),
+    };
+
+    let mut ring_ends = RingEnds::default();
+    read_geometry(input, &mut ring_ends)?;
+
+    if ring_ends.rings.iter().any(|(first, last)| first != last) {
+        return Err(String::from("polygon rings must be closed"));
+    }
+
+    if !polygon.interiors().is_empty() {
+        return Err(String::from("polygons with holes are not supported"));
+    }
+
+    let exterior = polygon.exterior();
+
+    if exterior.0.len() < 4 {
+        return Err(String::from(
+            "polygon must have at least 3 distinct vertices",
+        ));
+    }
+
+    if exterior.0.len() > MAX_POLYGON_VERTICES {
+        return Err(format!(
+            "polygon must have at most {} vertices",
+            MAX_POLYGON_VERTICES
+        ));
+    }
+
+    let out_of_range = exterior
+        .coords()
+        .any(|c| !(-180.0..=180.0).contains(&c.x) || !(-90.0..=90.0).contains(&c.y));
+
+    if out_of_range {
+        return Err(String::from(
+            "polygon coordinates must be longitude latitude pairs within their valid ranges",
+        ));
+    }
+
+    if !is_simple_ring(exterior) {
+        return Err(String::from("polygon must not intersect itself"));
+    }
+
+    let swapped: LineString<f64> = exterior
+        .coords()
+        .map(|c| Coord { x: c.y, y: c.x })
+        .collect();
+
+    Ok(Polygon::new(swapped, vec![]))
+}
+
+/// the polygon as WKT, to be sent to PostGIS with `ST_GeomFromText`
+pub fn polygon_to_wkt(polygon: &Polygon<f64>) -> Result<String, String> {
+    Geometry::Polygon(polygon.clone())
+        .to_wkt()
+        .map_err(|e| format!("failed to encode polygon: {}", e))
+}
+
+/// the bounding box of a polygon
+pub fn polygon_bounding_rect(polygon: &Polygon<f64>) -> Option<Rect<f64>> {
+    let mut coords = polygon.exterior().coords();
+    let first = *coords.next()?;
+
+    let (min, max) = coords.fold((first, first), |(min, max), c| {
+        (
+            Coord {
+                x: min.x.min(c.x),
+                y: min.y.min(c.y),
+            },
+            Coord {
+                x: max.x.max(c.x),
+                y: max.y.max(c.y),
+            },
+        )
+    });
+
+    Some(Rect::new(min, max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// WKT of a polygon with the vertices on a circle, closed by repeating the first
+    fn circle_wkt(distinct_vertices: usize) -> String {
+        let mut vertices: Vec<String> = (0..distinct_vertices)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * i as f64 / distinct_vertices as f64;
+                format!("{} {}", angle.cos(), angle.sin())
+            })
+            .collect();
+
+        vertices.push(vertices[0].clone());
+
+        format!("POLYGON(({}))", vertices.join(", "))
+    }
+
+    #[test]
+    fn swaps_the_axes_to_latitude_first() {
+        let polygon = parse_polygon("POLYGON((-46 -23, -45 -23, -45 -22, -46 -23))").unwrap();
+
+        let coords: Vec<(f64, f64)> = polygon.exterior().coords().map(|c| (c.x, c.y)).collect();
+
+        assert_eq!(
+            coords,
+            vec![
+                (-23.0, -46.0),
+                (-23.0, -45.0),
+                (-22.0, -45.0),
+                (-23.0, -46.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_hex_encoded_wkb() {
+        let wkt = parse_polygon("POLYGON((0 0, 1 0, 1 1, 0 0))").unwrap();
+
+        // the same polygon, in the standard axis order, as little endian WKB
+        let wkb = parse_polygon(concat!(
+            "01030000000100000004000000",
+            "00000000000000000000000000000000",
+            "000000000000F03F0000000000000000",
+            "000000000000F03F000000000000F03F",
+            "00000000000000000000000000000000",
+        ))
+        .unwrap();
+
+        assert_eq!(wkt, wkb);
+    }
+
+    #[test]
+    fn rejects_unclosed_rings() {
+        assert_eq!(
+            parse_polygon("POLYGON((0 0, 1 0, 1 1, 0 1))"),
+            Err(String::from("polygon rings must be closed"))
+        );
+    }
+
+    #[test]
+    fn rejects_self_intersecting_rings() {
+        assert_eq!(
+            parse_polygon("POLYGON((0 0, 1 1, 1 0, 0 1, 0 0))"),
+            Err(String::from("polygon must not intersect itself"))
+        );
+    }
+
+    #[test]
+    fn rejects_holes() {
+        assert_eq!(
+            parse_polygon("POLYGON((0 0, 10 0, 10 10, 0 10, 0 0), (2 2, 3 2, 3 3, 2 2))"),
+            Err(String::from("polygons with holes are not supported"))
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_coordinates() {
+        // valid if the axes were latitude first, as the internal order
+        let latitude_first = parse_polygon("POLYGON((10 100, 11 100, 11 101, 10 100))");
+        let longitude_out_of_range = parse_polygon("POLYGON((179 0, 181 0, 181 1, 179 0))");
+
+        for result in [latitude_first, longitude_out_of_range] {
+            assert_eq!(
+                result,
+                Err(String::from(
+                    "polygon coordinates must be longitude latitude pairs within their valid ranges"
+                ))
+            );
+        }
+    }
+
+    #[test]
+    fn limits_the_amount_of_vertices() {
+        // the closing vertex counts towards the limit
+        assert!(parse_polygon(&circle_wkt(MAX_POLYGON_VERTICES - 1)).is_ok());
+
+        assert_eq!(
+            parse_polygon(&circle_wkt(MAX_POLYGON_VERTICES)),
+            Err(format!(
+                "polygon must have at most {} vertices",
+                MAX_POLYGON_VERTICES
+            ))
+        );
+    }
+
+    #[test]
+    fn rejects_other_geometries() {
+        assert_eq!(
+            parse_polygon("POINT(0 0)"),
+            Err(String::from("geometry must be a polygon"))
+        );
+        assert!(parse_polygon("POLYGON((0 0, 1 0").is_err());
+    }
+}
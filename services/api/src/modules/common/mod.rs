@@ -1,6 +1,10 @@
+pub mod conditional_requests;
 pub mod dto;
 pub mod error_codes;
 pub mod extractors;
+#[cfg(test)]
+pub mod fixtures;
+pub mod geometry;
 pub mod multipart_form_data;
 pub mod responses;
 pub mod validators;
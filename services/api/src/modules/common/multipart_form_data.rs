@@ -14,6 +14,13 @@ pub fn get_image_extension_from_field_or_fail_request(
         .clone()
         .ok_or((StatusCode::BAD_REQUEST, SimpleError::from("empty filename")))?;
 
+    get_image_extension_or_fail_request(&file_name)
+}
+
+/// asserts a file name is of a image with a valid extension, returning the extension
+pub fn get_image_extension_or_fail_request(
+    file_name: &str,
+) -> Result<String, (StatusCode, SimpleError)> {
     let allowed_file_types = ["jpe", "jpg", "jpeg", "png", "webp"];
 
     let (_, file_extension) = file_name.rsplit_once('.').ok_or((
@@ -42,7 +49,22 @@ pub fn filename_from_img(
 ) -> Result<String, (StatusCode, SimpleError)> {
     let file_extension = get_image_extension_from_field_or_fail_request(img)?;
 
+    Ok(timestamped_filename(prefix, &file_extension))
+}
+
+/// same as `filename_from_img` but for a image file name, used when
+/// the image is not uploaded as multipart/form-data
+pub fn filename_from_img_name(
+    prefix: &str,
+    file_name: &str,
+) -> Result<String, (StatusCode, SimpleError)> {
+    let file_extension = get_image_extension_or_fail_request(file_name)?;
+
+    Ok(timestamped_filename(prefix, &file_extension))
+}
+
+fn timestamped_filename(prefix: &str, file_extension: &str) -> String {
     let timestamp = chrono::Utc::now().format("%d-%m-%Y_%H:%M:%S");
 
-    Ok(format!("{}_{}.{}", prefix, timestamp, file_extension))
+    format!("{}_{}.{}", prefix, timestamp, file_extension)
 }
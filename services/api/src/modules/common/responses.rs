@@ -1,11 +1,13 @@
+use super::error_codes::VALIDATION;
 use axum::{
     response::{IntoResponse, Response},
     Json,
 };
+use convert_case::{Case, Casing};
 use http::StatusCode;
 use serde::Serialize;
 use utoipa::ToSchema;
-use validator::ValidationErrors;
+use validator::{ValidationError, ValidationErrors, ValidationErrorsKind};
 
 /// A struct for simple API error responses, contains a timestamp from the moment
 /// of its creation and a error message
@@ -79,6 +81,125 @@ impl<T: Serialize> IntoResponse for ApiError<T> {
     }
 }
 
+/// A invalid field of a request body or query
+#[derive(Serialize, Clone, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct FieldError {
+    /// path of the field, with camel cased names and list indexes, eg: `to[1].email`,
+    /// `null` for errors of the whole request body or query
+    pub field: Option<String>,
+    /// human readable description of the error, never a error code
+    pub message: String,
+}
+
+/// Response of requests whose body or query is invalid, `error` is a human readable
+/// description of all the errors and `errors` has every invalid field
+#[derive(Serialize, Clone, ToSchema, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct ValidationErrorResponse {
+    pub error: String,
+    /// always `VALIDATION`
+    pub code: String,
+    pub errors: Vec<FieldError>,
+}
+
+/// the message of a validation error, validators without a message (eg: `#[validate(email)]`)
+/// are described from their code and params, as their codes are not API error codes
+fn field_error_message(error: &ValidationError) -> String {
+    if let Some(message) = &error.message {
+        return message.to_string();
+    }
+
+    let param = |name: &str| error.params.get(name).map(|value| value.to_string());
+
+    let bounds = || match (param("min"), param("max")) {
+        (Some(min), Some(max)) => format!("between {} and {}", min, max),
+        (Some(min), None) => format!("at least {}", min),
+        (None, Some(max)) => format!("at most {}", max),
+        (None, None) => String::from("valid"),
+    };
+
+    match error.code.as_ref() {
+        "email" => String::from("must be a valid email address"),
+        "url" => String::from("must be a valid URL"),
+        "required" => String::from("is required"),
+        "regex" => String::from("has a invalid format"),
+        "range" => format!("must be {}", bounds()),
+        "length" => match param("equal") {
+            Some(equal) => format!("length must be {}", equal),
+            None => format!("length must be {}", bounds()),
+        },
+        _ => String::from("is invalid"),
+    }
+}
+
+/// appends the errors of every field to `out`, recursing into nested structs and lists
+///
+/// validator keys the errors by the rust field names, they are camel cased as every DTO
+/// renames its fields to camel case, DTOs must keep doing so for the paths to be correct
+fn collect_field_errors(
+    errors: &ValidationErrors,
+    prefix: Option<&str>,
+    out: &mut Vec<FieldError>,
+) {
+    for (name, kind) in errors.errors() {
+        // struct level errors (eg: `#[validate(schema)]`) are keyed as `__all__`
+        let path = match (prefix, *name) {
+            (prefix, "__all__") => prefix.map(String::from),
+            (Some(prefix), name) => Some(format!("{}.{}", prefix, name.to_case(Case::Camel))),
+            (None, name) => Some(name.to_case(Case::Camel)),
+        };
+
+        match kind {
+            ValidationErrorsKind::Field(field_errors) => {
+                out.extend(field_errors.iter().map(|e| FieldError {
+                    field: path.clone(),
+                    message: field_error_message(e),
+                }));
+            }
+            ValidationErrorsKind::Struct(nested) => {
+                collect_field_errors(nested, path.as_deref(), out);
+            }
+            ValidationErrorsKind::List(items) => {
+                for (index, nested) in items {
+                    let item_path = format!("{}[{}]", path.as_deref().unwrap_or_default(), index);
+                    collect_field_errors(nested, Some(&item_path), out);
+                }
+            }
+        }
+    }
+}
+
+impl From<ValidationErrors> for ValidationErrorResponse {
+    fn from(v: ValidationErrors) -> Self {
+        let mut errors = Vec::new();
+        collect_field_errors(&v, None, &mut errors);
+
+        errors.sort_by(|a, b| a.field.cmp(&b.field));
+
+        let error = errors
+            .iter()
+            .map(|e| match &e.field {
+                Some(field) => format!("{}: {}", field, e.message),
+                None => e.message.clone(),
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        ValidationErrorResponse {
+            error,
+            code: String::from(VALIDATION),
+            errors,
+        }
+    }
+}
+
+impl IntoResponse for ValidationErrorResponse {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(self)).into_response()
+    }
+}
+
 pub fn internal_error_res() -> (StatusCode, SimpleError) {
     (StatusCode::INTERNAL_SERVER_ERROR, SimpleError::internal())
 }
@@ -86,3 +207,78 @@ pub fn internal_error_res() -> (StatusCode, SimpleError) {
 pub fn internal_error_msg(msg: &str) -> (StatusCode, SimpleError) {
     (StatusCode::INTERNAL_SERVER_ERROR, SimpleError::from(msg))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use validator::Validate;
+
+    #[derive(Validate)]
+    struct RecipientDto {
+        #[validate(email(message = "must be a valid email address"))]
+        email: String,
+    }
+
+    #[derive(Validate)]
+    struct SendEmailDto {
+        #[validate(length(min = 1, message = "must not be empty"))]
+        subject_line: String,
+
+        #[validate]
+        reply_to: RecipientDto,
+
+        #[validate]
+        to: Vec<RecipientDto>,
+    }
+
+    #[test]
+    fn lists_the_path_of_every_invalid_field() {
+        let recipient = |email: &str| RecipientDto {
+            email: String::from(email),
+        };
+
+        let dto = SendEmailDto {
+            subject_line: String::new(),
+            reply_to: recipient("reply"),
+            to: vec![recipient("a@rastercar.com"), recipient("b")],
+        };
+
+        let response = ValidationErrorResponse::from(dto.validate().unwrap_err());
+
+        let fields: Vec<(Option<&str>, &str)> = response
+            .errors
+            .iter()
+            .map(|e| (e.field.as_deref(), e.message.as_str()))
+            .collect();
+
+        assert_eq!(response.code, VALIDATION);
+        assert_eq!(
+            fields,
+            vec![
+                (Some("replyTo.email"), "must be a valid email address"),
+                (Some("subjectLine"), "must not be empty"),
+                (Some("to[1].email"), "must be a valid email address"),
+            ]
+        );
+    }
+
+    #[test]
+    fn validators_without_message_are_described_instead_of_sending_their_code() {
+        let mut length = ValidationError::new("length");
+        length.add_param("min".into(), &5);
+        length.add_param("max".into(), &32);
+
+        assert_eq!(
+            field_error_message(&length),
+            "length must be between 5 and 32"
+        );
+        assert_eq!(
+            field_error_message(&ValidationError::new("regex")),
+            "has a invalid format"
+        );
+        assert_eq!(
+            field_error_message(&ValidationError::new("some_custom_code")),
+            "is invalid"
+        );
+    }
+}
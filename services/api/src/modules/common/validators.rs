@@ -1,6 +1,10 @@
+use crate::config::app_config;
 use lazy_static::lazy_static;
+use phonenumber::{country, Mode};
 use regex::Regex;
 
+pub use shared::imei::is_valid_tracker_imei;
+
 lazy_static! {
     /// Matches:
     /// - mercosul vehicle plates (format: AAA-9A99)
@@ -18,4 +22,71 @@ lazy_static! {
     //
     pub static ref REGEX_IS_LOWERCASE_ALPHANUMERIC_WITH_UNDERSCORES: Regex =
         Regex::new(r"^[a-z0-9_]+$").unwrap();
+    //
+    /// Matches tracker IMEIs, not only 15 digit IMEIs since some tracker models identify
+    /// themselves by a numeric serial number. Only digits are allowed since the IMEI is a
+    /// segment of the tracker events routing keys, where `.`, `*` and `#` are special
+    pub static ref REGEX_IS_TRACKER_IMEI: Regex =
+        Regex::new(r"^[0-9]{1,20}$").unwrap();
+    //
+    /// Matches domain names with at least two labels (eg: `example.com`)
+    pub static ref REGEX_IS_DOMAIN: Regex =
+        Regex::new(r"^([a-zA-Z0-9]([a-zA-Z0-9-]{0,61}[a-zA-Z0-9])?\.)+[a-zA-Z]{2,63}$").unwrap();
+}
+
+/// Parses a phone number in international format or in the national format of the
+/// `PHONE_NUMBER_DEFAULT_REGION`, returning it in E.164 format (eg: `+5511987654321`)
+/// or `None` if it is not a valid phone number
+pub fn normalize_phone_number(phone: &str) -> Option<String> {
+    normalize_phone_number_of_region(phone, app_config().phone_number_default_region)
+}
+
+/// see `normalize_phone_number`, national numbers are parsed as numbers of `region`
+fn normalize_phone_number_of_region(phone: &str, region: country::Id) -> Option<String> {
+    phonenumber::parse(Some(region), phone)
+        .ok()
+        .filter(|number| number.is_valid())
+        .map(|number| number.format().mode(Mode::E164).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_national_and_international_phone_numbers_to_e164() {
+        let normalize = |phone| normalize_phone_number_of_region(phone, country::Id::BR);
+
+        assert_eq!(
+            normalize("(11) 98765-4321").as_deref(),
+            Some("+5511987654321")
+        );
+        assert_eq!(normalize("11987654321").as_deref(), Some("+5511987654321"));
+        assert_eq!(
+            normalize("+55 11 98765-4321").as_deref(),
+            Some("+5511987654321")
+        );
+        assert_eq!(
+            normalize("+1 650-253-0000").as_deref(),
+            Some("+16502530000")
+        );
+    }
+
+    #[test]
+    fn parses_national_phone_numbers_on_the_region() {
+        assert_eq!(
+            normalize_phone_number_of_region("(650) 253-0000", country::Id::US).as_deref(),
+            Some("+16502530000")
+        );
+    }
+
+    #[test]
+    fn refuses_invalid_phone_numbers() {
+        let normalize = |phone| normalize_phone_number_of_region(phone, country::Id::BR);
+
+        assert_eq!(normalize(""), None);
+        assert_eq!(normalize("123"), None);
+        assert_eq!(normalize("not a phone"), None);
+        assert_eq!(normalize("+55 11 1234"), None);
+    }
 }
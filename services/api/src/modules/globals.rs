@@ -1,6 +1,42 @@
 use tokio::sync::RwLock;
 
-use super::tracking::cache::TrackerIdCache;
+use super::tracking::cache::{self, TrackerIdCache, TrackerIdLookupError};
+use sea_orm::DatabaseConnection;
 use std::sync::{Arc, OnceLock};
 
+/// Initialized on startup by the API binary, code using it must not assume it is
+/// initialized (eg: on tests or other binaries) and fallback to the database instead
 pub static TRACKER_ID_CACHE: OnceLock<Arc<RwLock<TrackerIdCache>>> = OnceLock::new();
+
+/// gets a tracker ID by IMEI using the `TRACKER_ID_CACHE`, or directly
+/// from the database if the cache was not initialized
+pub async fn get_tracker_id(
+    imei: &str,
+    db: &DatabaseConnection,
+) -> Result<Option<i32>, TrackerIdLookupError> {
+    if let Some(tracker_id_cache) = TRACKER_ID_CACHE.get() {
+        return tracker_id_cache.write().await.get(imei).await;
+    }
+
+    cache::find_tracker_id(imei, db)
+        .await
+        .map_err(TrackerIdLookupError::Db)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::common::fixtures;
+
+    #[tokio::test]
+    async fn looks_up_the_database_when_the_cache_is_not_initialized() {
+        assert!(TRACKER_ID_CACHE.get().is_none());
+
+        // the lookup only fails if the database was queried
+        let db = fixtures::unreachable_db();
+
+        let result = get_tracker_id("490154203237518", &db).await;
+
+        assert!(matches!(result, Err(TrackerIdLookupError::Db(_))));
+    }
+}
@@ -0,0 +1,16 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use utoipa::ToSchema;
+use validator::Validate;
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct PreviewEmailTemplateDto {
+    /// name of the template to render, eg: `recover-password`
+    #[validate(length(min = 1))]
+    pub template: String,
+
+    /// replacements to render the template with
+    #[serde(default)]
+    pub context: HashMap<String, String>,
+}
@@ -0,0 +1,93 @@
+use super::dto::PreviewEmailTemplateDto;
+use crate::{
+    modules::{
+        auth,
+        common::{
+            extractors::{SuperUser, ValidatedJson},
+            responses::{internal_error_msg, SimpleError},
+        },
+    },
+    server::controller::AppState,
+    services::mailer::templates::EmailTemplate,
+};
+use axum::{response::Html, routing::post, Router};
+use handlebars::Handlebars;
+use http::StatusCode;
+
+pub fn create_router(state: AppState) -> Router<AppState> {
+    Router::new()
+        .route("/preview", post(preview_email_template))
+        .layer(axum::middleware::from_fn_with_state(
+            state,
+            auth::middleware::require_user,
+        ))
+}
+
+/// Renders a email template
+///
+/// Only accessible to superusers, renders the template with the given
+/// context returning its HTML, no email is sent.
+#[utoipa::path(
+    post,
+    tag = "mailer",
+    path = "/mailer/preview",
+    security(("session_id" = [])),
+    request_body = PreviewEmailTemplateDto,
+    responses(
+        (
+            status = OK,
+            description = "the rendered template",
+            body = String,
+            content_type = "text/html",
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "unknown template or missing context fields",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn preview_email_template(
+    _: SuperUser,
+    ValidatedJson(dto): ValidatedJson<PreviewEmailTemplateDto>,
+) -> Result<Html<String>, (StatusCode, SimpleError)> {
+    let template = EmailTemplate::from_name(&dto.template).ok_or((
+        StatusCode::BAD_REQUEST,
+        SimpleError::from(format!("unknown template: {}", dto.template)),
+    ))?;
+
+    let missing: Vec<&str> = template
+        .required_replacements()
+        .iter()
+        .copied()
+        .filter(|field| !dto.context.contains_key(*field))
+        .collect();
+
+    if !missing.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(format!("missing context fields: {}", missing.join(", "))),
+        ));
+    }
+
+    let html = template
+        .read()
+        .map_err(|_| internal_error_msg("failed to read template"))?;
+
+    let mut reg = Handlebars::new();
+    reg.set_strict_mode(true);
+
+    reg.register_template_string(template.name(), html)
+        .map_err(|_| internal_error_msg("failed to register template"))?;
+
+    let rendered = reg
+        .render(template.name(), &dto.context)
+        .map_err(|e| (StatusCode::BAD_REQUEST, SimpleError::from(e.to_string())))?;
+
+    Ok(Html(rendered))
+}
@@ -2,6 +2,7 @@ pub mod access_level;
 pub mod auth;
 pub mod common;
 pub mod globals;
+pub mod mailer;
 pub mod organization;
 pub mod sim_card;
 pub mod tracker;
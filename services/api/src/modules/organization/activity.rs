@@ -0,0 +1,93 @@
+//! Organization activity feed.
+//!
+//! events of different sources (eg: impersonations) are written to the `organization_activity`
+//! table by their producers when they happen, so the feed can be listed in chronological order
+//! without querying every event source.
+
+use sea_orm::{ActiveModelTrait, ConnectionTrait, DbErr, Set};
+use serde::{Deserialize, Serialize};
+use shared::entity::organization_activity;
+use strum::Display;
+use utoipa::ToSchema;
+
+/// Type of a organization activity event
+#[derive(Debug, Clone, Copy, Deserialize, ToSchema, Display)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum ActivityType {
+    ImpersonationStarted,
+    ImpersonationStopped,
+    OwnershipTransferred,
+}
+
+/// A superuser started or stopped impersonating a organization user
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImpersonationActivity {
+    pub impersonated_user_id: i32,
+    pub session_public_id: i32,
+}
+
+/// The organization ownership was transferred to another organization user
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OwnershipTransferActivity {
+    pub previous_owner_id: i32,
+    pub new_owner_id: i32,
+}
+
+/// A organization activity event, serialized as `{ "type": <ActivityType>, "data": <event data> }`
+#[derive(Serialize, Deserialize, ToSchema)]
+#[serde(tag = "type", content = "data", rename_all = "camelCase")]
+pub enum ActivityEvent {
+    ImpersonationStarted(ImpersonationActivity),
+    ImpersonationStopped(ImpersonationActivity),
+    OwnershipTransferred(OwnershipTransferActivity),
+}
+
+impl ActivityEvent {
+    pub fn activity_type(&self) -> ActivityType {
+        match self {
+            ActivityEvent::ImpersonationStarted(_) => ActivityType::ImpersonationStarted,
+            ActivityEvent::ImpersonationStopped(_) => ActivityType::ImpersonationStopped,
+            ActivityEvent::OwnershipTransferred(_) => ActivityType::OwnershipTransferred,
+        }
+    }
+
+    fn payload(&self) -> serde_json::Value {
+        match self {
+            ActivityEvent::ImpersonationStarted(data)
+            | ActivityEvent::ImpersonationStopped(data) => serde_json::json!(data),
+            ActivityEvent::OwnershipTransferred(data) => serde_json::json!(data),
+        }
+    }
+
+    /// parses a event from a `organization_activity` record
+    pub fn from_record(record: &organization_activity::Model) -> Result<Self, serde_json::Error> {
+        serde_json::from_value(serde_json::json!({
+            "type": record.r#type,
+            "data": record.payload,
+        }))
+    }
+}
+
+/// adds a event to the organization activity feed
+///
+/// should be called on the same transaction that persists the event source
+/// (if any) so the feed never contains events that did not happen
+pub async fn record_activity<C: ConnectionTrait>(
+    db: &C,
+    org_id: i32,
+    event: ActivityEvent,
+) -> Result<(), DbErr> {
+    organization_activity::ActiveModel {
+        organization_id: Set(org_id),
+        r#type: Set(event.activity_type().to_string()),
+        payload: Set(event.payload()),
+        ..Default::default()
+    }
+    .insert(db)
+    .await?;
+
+    Ok(())
+}
@@ -1,6 +1,13 @@
-use serde::Deserialize;
-use utoipa::ToSchema;
-use validator::Validate;
+use super::activity::{ActivityEvent, ActivityType};
+use super::ip_allowlist::is_valid_allowlist;
+use crate::modules::common::validators::REGEX_IS_DOMAIN;
+use crate::modules::user::dto::SimpleUserDto;
+use crate::services::ses::IdentityStatus;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared::constants::FeatureFlag;
+use utoipa::{IntoParams, ToSchema};
+use validator::{validate_email, Validate, ValidationError};
 
 #[derive(ToSchema, Validate, Deserialize)]
 #[serde(rename_all = "camelCase")]
@@ -10,4 +17,199 @@ pub struct UpdateOrganizationDto {
 
     #[validate(length(min = 5, max = 32))]
     pub name: Option<String>,
+
+    /// email address to send emails to the organization users, must be verified on SES
+    #[validate(email)]
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub email_sender: Option<Option<String>>,
+
+    /// if a confirmation email should be sent to the new billing email,
+    /// ignored if the billing email is not changed
+    #[serde(default)]
+    pub send_confirmation_email: bool,
+
+    /// access level of users created without one, must belong to the organization
+    #[validate(range(min = 1))]
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub default_access_level_id: Option<Option<i32>>,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct ListOrganizationsDto {
+    /// filter organizations by name (case insensitive)
+    pub name: Option<String>,
+
+    /// filter organizations by billing email (case insensitive)
+    pub billing_email: Option<String>,
+}
+
+/// A organization with its owner and the amount of entities it has
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OrganizationSummaryDto {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    pub name: String,
+    pub blocked: bool,
+    pub billing_email: String,
+    pub billing_email_verified: bool,
+    pub owner: Option<SimpleUserDto>,
+    pub user_count: i64,
+    pub vehicle_count: i64,
+    pub tracker_count: i64,
+    pub sim_card_count: i64,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SetFeatureFlagDto {
+    pub flag: FeatureFlag,
+    pub enabled: bool,
+}
+
+/// A feature flag of a organization
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FeatureFlagDto {
+    pub flag: FeatureFlag,
+    pub enabled: bool,
+}
+
+/// The plan limits of a organization, a `null` limit means unlimited
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SetOrganizationLimitsDto {
+    #[validate(range(min = 0))]
+    pub max_vehicles: Option<i32>,
+
+    #[validate(range(min = 0))]
+    pub max_trackers: Option<i32>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SetOrganizationBlockedDto {
+    /// blocked organizations users cannot use the API, but to sign out and get the billing status
+    pub blocked: bool,
+}
+
+/// If the organization is blocked and who to contact about it
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BillingStatusDto {
+    pub blocked: bool,
+
+    /// email address to contact to settle the organization billing, `null` if not configured
+    pub billing_contact_email: Option<String>,
+}
+
+fn is_valid_email_identity(identity: &str) -> Result<(), ValidationError> {
+    if validate_email(identity) || REGEX_IS_DOMAIN.is_match(identity) {
+        return Ok(());
+    }
+
+    let mut err = ValidationError::new("email_identity");
+    err.message = Some("must be a email address or a domain".into());
+
+    Err(err)
+}
+
+/// A email address or domain to verify as a email sender of the organization
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateEmailIdentityDto {
+    /// the email address or domain (eg: `example.com`), verifying a domain
+    /// allows any address of the domain to be used as the email sender
+    #[validate(length(max = 255), custom = "is_valid_email_identity")]
+    pub identity: String,
+}
+
+/// A email address or domain whose verification as a email sender was started
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailIdentityDto {
+    pub identity: String,
+
+    /// tokens of the DKIM CNAME records to create to verify a domain, the records are
+    /// `{token}._domainkey.{domain}` pointing to `{token}.dkim.amazonses.com`. Empty for
+    /// email addresses, which are verified by a link sent to them, or if the verification
+    /// was already started
+    pub dkim_tokens: Vec<String>,
+}
+
+/// The networks the organization users are allowed to send requests from
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SetIpAllowlistDto {
+    /// networks in CIDR notation (eg: `203.0.113.0/24`), `null` removes the restriction
+    #[validate(custom = "is_valid_allowlist", length(min = 1, max = 100))]
+    #[schema(example = json!(["203.0.113.0/24", "2001:db8::/32"]))]
+    pub networks: Option<Vec<String>>,
+}
+
+/// The organization user to transfer the organization ownership to
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct TransferOwnershipDto {
+    pub new_owner_id: i32,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct ListActivityDto {
+    /// filter events by type
+    #[serde(rename = "type")]
+    #[param(rename = "type")]
+    pub activity_type: Option<ActivityType>,
+
+    /// list events after a timestamp
+    pub after: Option<DateTime<Utc>>,
+
+    /// list events before a timestamp
+    pub before: Option<DateTime<Utc>>,
+}
+
+/// A event of the organization activity feed
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityDto {
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub event: ActivityEvent,
+}
+
+/// Verification status of a email address as a sender on SES
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum EmailSenderStatus {
+    /// emails can be sent from the address
+    Verified,
+    /// the verification of the address or its domain did not finish yet
+    Pending,
+    /// the verification of the address or its domain failed
+    Failed,
+    /// neither the address nor its domain were added to SES
+    NotFound,
+}
+
+impl From<IdentityStatus> for EmailSenderStatus {
+    fn from(status: IdentityStatus) -> Self {
+        match status {
+            IdentityStatus::Verified => Self::Verified,
+            IdentityStatus::Pending => Self::Pending,
+            IdentityStatus::Failed => Self::Failed,
+            IdentityStatus::NotFound => Self::NotFound,
+        }
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailSenderStatusDto {
+    pub email: String,
+    pub status: EmailSenderStatus,
 }
@@ -0,0 +1,135 @@
+use crate::modules::common::{
+    error_codes::FEATURE_NOT_ENABLED,
+    responses::{internal_error_res, SimpleError},
+};
+use chrono::Utc;
+use http::StatusCode;
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+};
+use shared::{constants::FeatureFlag, entity::org_feature_flag};
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use strum::IntoEnumIterator;
+
+/// How long the flags of a organization are cached, since the cache is per API
+/// instance this is the maximum time a flag change takes to reach every instance
+const FEATURE_FLAGS_TTL: Duration = Duration::from_secs(60);
+
+/// Flag -> enabled, containing every `FeatureFlag`
+pub type OrgFeatureFlags = HashMap<FeatureFlag, bool>;
+
+/// Cache of the feature flags of every organization, flags not set
+/// for a organization are resolved to `FeatureFlag::default_enabled`
+///
+/// cheap to clone and to read, so its meant to be used on the hot path
+#[derive(Clone)]
+pub struct FeatureFlagCache {
+    db: DatabaseConnection,
+
+    /// org id -> (flags, time the flags were loaded)
+    cache: Arc<RwLock<HashMap<i32, (OrgFeatureFlags, Instant)>>>,
+}
+
+impl FeatureFlagCache {
+    pub fn new(db: DatabaseConnection) -> Self {
+        Self {
+            db,
+            cache: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    /// gets all the flags of a organization
+    pub async fn get_all(&self, org_id: i32) -> Result<OrgFeatureFlags, DbErr> {
+        if let Some((flags, loaded_at)) = self.cache.read().unwrap().get(&org_id) {
+            if loaded_at.elapsed() < FEATURE_FLAGS_TTL {
+                return Ok(flags.clone());
+            }
+        }
+
+        let mut flags: OrgFeatureFlags = FeatureFlag::iter()
+            .map(|flag| (flag, flag.default_enabled()))
+            .collect();
+
+        let rows = org_feature_flag::Entity::find()
+            .filter(org_feature_flag::Column::OrganizationId.eq(org_id))
+            .all(&self.db)
+            .await?;
+
+        // rows of flags that no longer exist are ignored
+        for row in rows {
+            if let Some(flag) = FeatureFlag::from_db_value(&row.flag) {
+                flags.insert(flag, row.enabled);
+            }
+        }
+
+        self.cache
+            .write()
+            .unwrap()
+            .insert(org_id, (flags.clone(), Instant::now()));
+
+        Ok(flags)
+    }
+
+    /// checks if a feature is enabled for a organization
+    pub async fn is_enabled(&self, org_id: i32, flag: FeatureFlag) -> Result<bool, DbErr> {
+        let flags = self.get_all(org_id).await?;
+
+        Ok(flags.get(&flag).copied().unwrap_or(flag.default_enabled()))
+    }
+
+    /// helper for handlers of gated features, fails with
+    /// `(StatusCode::FORBIDDEN, SimpleError::from(FEATURE_NOT_ENABLED))`
+    /// if the feature is not enabled for the organization
+    pub async fn require(
+        &self,
+        org_id: i32,
+        flag: FeatureFlag,
+    ) -> Result<(), (StatusCode, SimpleError)> {
+        let enabled = self
+            .is_enabled(org_id, flag)
+            .await
+            .map_err(|_| internal_error_res())?;
+
+        if !enabled {
+            return Err((
+                StatusCode::FORBIDDEN,
+                SimpleError::from(FEATURE_NOT_ENABLED),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// enables or disables a feature for a organization
+    pub async fn set(&self, org_id: i32, flag: FeatureFlag, enabled: bool) -> Result<(), DbErr> {
+        let row = org_feature_flag::ActiveModel::from(org_feature_flag::Model {
+            organization_id: org_id,
+            flag: flag.to_db_value(),
+            enabled,
+            updated_at: Utc::now(),
+        });
+
+        org_feature_flag::Entity::insert(row)
+            .on_conflict(
+                OnConflict::columns([
+                    org_feature_flag::Column::OrganizationId,
+                    org_feature_flag::Column::Flag,
+                ])
+                .update_columns([
+                    org_feature_flag::Column::Enabled,
+                    org_feature_flag::Column::UpdatedAt,
+                ])
+                .to_owned(),
+            )
+            .exec(&self.db)
+            .await?;
+
+        self.cache.write().unwrap().remove(&org_id);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,38 @@
+//! Organization IP allowlisting.
+//!
+//! organizations can restrict the networks their users send requests from, requests of users
+//! of a organization with a allowlist are refused by `require_user` unless the client ip is in
+//! one of the allowlisted networks, organizations without a allowlist are unrestricted.
+
+use ipnetwork::IpNetwork;
+use std::{net::IpAddr, str::FromStr};
+use validator::ValidationError;
+
+/// validates every network of a allowlist is in CIDR notation (eg: `10.0.0.0/8`),
+/// networks with bits set to the right of the mask (eg: `10.0.0.1/8`) are refused
+/// just like the postgres `cidr` type does
+pub fn is_valid_allowlist(networks: &[String]) -> Result<(), ValidationError> {
+    let is_valid = |network: &String| {
+        IpNetwork::from_str(network).is_ok_and(|network| network.network() == network.ip())
+    };
+
+    if !networks.iter().all(is_valid) {
+        let mut err = ValidationError::new("invalid CIDR network");
+        err.message = Some("networks must be in CIDR notation, eg: 10.0.0.0/8".into());
+
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+/// if the ip is within any network of the allowlist
+pub fn is_allowed(allowlist: &[String], ip: IpAddr) -> bool {
+    // clients connecting to a dual stack socket over IPv4 have IPv4 mapped IPv6 addresses
+    let ip = ip.to_canonical();
+
+    allowlist
+        .iter()
+        .filter_map(|network| IpNetwork::from_str(network).ok())
+        .any(|network| network.contains(ip))
+}
@@ -0,0 +1,88 @@
+use crate::{
+    database::error::DbError,
+    modules::common::{error_codes::LIMIT_REACHED, responses::SimpleError},
+};
+use http::StatusCode;
+use sea_orm::{
+    ColumnTrait, DatabaseTransaction, EntityTrait, PaginatorTrait, QueryFilter, QuerySelect,
+};
+use shared::entity::{organization, vehicle, vehicle_tracker};
+
+/// A entity whose amount per organization is limited by the organization plan
+#[derive(Debug, Clone, Copy)]
+pub enum PlanLimit {
+    Vehicles,
+    Trackers,
+}
+
+impl PlanLimit {
+    fn max(&self, org: &organization::Model) -> Option<i32> {
+        match self {
+            PlanLimit::Vehicles => org.max_vehicles,
+            PlanLimit::Trackers => org.max_trackers,
+        }
+    }
+
+    async fn count(&self, txn: &DatabaseTransaction, org_id: i32) -> Result<u64, DbError> {
+        let count = match self {
+            PlanLimit::Vehicles => {
+                vehicle::Entity::find()
+                    .filter(vehicle::Column::OrganizationId.eq(org_id))
+                    .count(txn)
+                    .await?
+            }
+            PlanLimit::Trackers => {
+                vehicle_tracker::Entity::find()
+                    .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+                    .count(txn)
+                    .await?
+            }
+        };
+
+        Ok(count)
+    }
+}
+
+/// Amount of entities of the limit the organization plan still allows it to create,
+/// `None` if the plan does not limit them.
+///
+/// The organization row is locked until the transaction ends, so concurrent creations
+/// for the same organization are serialized and cannot go over the limit, therefore the
+/// entities should be inserted on the same transaction after calling this.
+pub async fn remaining(
+    txn: &DatabaseTransaction,
+    org_id: i32,
+    limit: PlanLimit,
+) -> Result<Option<u64>, (StatusCode, SimpleError)> {
+    let org = organization::Entity::find_by_id(org_id)
+        .lock_exclusive()
+        .one(txn)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))?;
+
+    let Some(max) = limit.max(&org) else {
+        return Ok(None);
+    };
+
+    let count = limit.count(txn, org_id).await?;
+
+    Ok(Some((max.max(0) as u64).saturating_sub(count)))
+}
+
+/// Errors if creating one more entity of the limit would exceed the organization plan,
+/// locking the organization row like `remaining`
+pub async fn ensure_below_limit(
+    txn: &DatabaseTransaction,
+    org_id: i32,
+    limit: PlanLimit,
+) -> Result<(), (StatusCode, SimpleError)> {
+    if remaining(txn, org_id, limit).await? == Some(0) {
+        return Err((
+            StatusCode::PAYMENT_REQUIRED,
+            SimpleError::from(LIMIT_REACHED),
+        ));
+    }
+
+    Ok(())
+}
@@ -1,2 +1,6 @@
+pub mod activity;
 pub mod dto;
+pub mod feature_flags;
+pub mod ip_allowlist;
+pub mod limits;
 pub mod routes;
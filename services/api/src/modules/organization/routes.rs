@@ -1,5 +1,14 @@
-use super::dto::UpdateOrganizationDto;
+use super::activity::{self, ActivityEvent, OwnershipTransferActivity};
+use super::dto::{
+    ActivityDto, BillingStatusDto, CreateEmailIdentityDto, EmailIdentityDto, EmailSenderStatusDto,
+    FeatureFlagDto, ListActivityDto, ListOrganizationsDto, OrganizationSummaryDto,
+    SetFeatureFlagDto, SetIpAllowlistDto, SetOrganizationBlockedDto, SetOrganizationLimitsDto,
+    TransferOwnershipDto, UpdateOrganizationDto,
+};
+use super::feature_flags::OrgFeatureFlags;
+use super::ip_allowlist;
 use crate::{
+    config::app_config,
     database::error::DbError,
     modules::{
         auth::{
@@ -8,26 +17,43 @@ use crate::{
         },
         common::{
             self,
-            error_codes::EMAIL_ALREADY_VERIFIED,
-            extractors::{DbConnection, ValidatedJson},
+            dto::{EmailAddress, Pagination, PaginationResult},
+            error_codes::{
+                EMAIL_ALREADY_VERIFIED, EMAIL_IN_USE, EMAIL_SENDER_IN_USE,
+                EMAIL_SENDER_NOT_VERIFIED, IP_NOT_ALLOWED,
+            },
+            extractors::{DbConnection, OrganizationId, SuperUser, ValidatedJson, ValidatedQuery},
             responses::{internal_error_res, SimpleError},
         },
     },
     server::controller::AppState,
-    services::mailer::service::ConfirmEmailRecipientType,
+    services::{mailer::service::ConfirmEmailRecipientType, ses::sender_identities},
 };
 use axum::{
-    extract::State,
-    routing::{patch, post},
+    extract::{Path, State},
+    routing::{get, patch, post, put},
     Extension, Json, Router,
 };
+use axum_client_ip::SecureClientIp;
 use http::StatusCode;
 use migration::Expr;
-use sea_orm::{ColumnTrait, EntityTrait, QueryFilter, QueryTrait};
-use shared::{constants::Permission, entity::organization};
+use sea_orm::{
+    sea_query::{extension::postgres::PgExpr, OnConflict},
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, PaginatorTrait,
+    QueryFilter, QueryOrder, QuerySelect, QueryTrait, Set, TransactionTrait,
+};
+use shared::{
+    constants::Permission,
+    entity::{
+        access_level, organization, organization_activity, organization_email_identity, sim_card,
+        traits::QueryableByIdAndOrgId, user, vehicle, vehicle_tracker,
+    },
+};
+use std::collections::HashMap;
 
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
+        .route("/", get(list_organizations))
         .route(
             "/",
             patch(update_org).route_layer(AclLayer::single(Permission::UpdateOrganization)),
@@ -42,15 +68,360 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             post(confirm_email_address_by_token)
                 .route_layer(AclLayer::single(Permission::UpdateOrganization)),
         )
+        .route(
+            "/email-sender/identity",
+            post(create_email_identity)
+                .route_layer(AclLayer::single(Permission::UpdateOrganization)),
+        )
+        .route(
+            "/email-sender/status",
+            get(get_email_sender_status)
+                .route_layer(AclLayer::single(Permission::UpdateOrganization)),
+        )
+        .route(
+            "/ip-allowlist",
+            put(set_ip_allowlist).route_layer(AclLayer::single(Permission::UpdateOrganization)),
+        )
+        .route("/transfer-ownership", post(transfer_ownership))
+        //
+        .route("/activity", get(list_activity))
+        //
+        .route("/feature-flags", get(get_org_feature_flags))
+        .route(
+            "/:org_id/feature-flags",
+            get(get_org_feature_flags_by_org_id),
+        )
+        .route("/:org_id/feature-flags", put(set_org_feature_flag))
+        .route("/:org_id/limits", put(set_org_limits))
+        .route("/:org_id/blocked", put(set_org_blocked))
+        .route("/billing-status", get(get_billing_status))
         .layer(axum::middleware::from_fn_with_state(
             state,
             auth::middleware::require_user,
         ))
 }
 
+/// Lists all organizations
+///
+/// Only accessible to superusers, returns the organizations
+/// with their owners and the amount of entities they have
+#[utoipa::path(
+    get,
+    tag = "organization",
+    path = "/organization",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListOrganizationsDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of organizations",
+            content_type = "application/json",
+            body = PaginatedOrganizationSummary,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_organizations(
+    _: SuperUser,
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListOrganizationsDto>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<OrganizationSummaryDto>>, (StatusCode, SimpleError)> {
+    let paginator = organization::Entity::find()
+        .apply_if(filter.name, |query, name| {
+            let col = Expr::col((organization::Entity, organization::Column::Name));
+            query.filter(col.ilike(format!("%{}%", name)))
+        })
+        .apply_if(filter.billing_email, |query, email| {
+            let col = Expr::col((organization::Entity, organization::Column::BillingEmail));
+            query.filter(col.ilike(format!("%{}%", email)))
+        })
+        .order_by_asc(organization::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let orgs = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?;
+
+    let org_ids: Vec<i32> = orgs.iter().map(|o| o.id).collect();
+    let owner_ids: Vec<i32> = orgs.iter().filter_map(|o| o.owner_id).collect();
+
+    let mut owners: HashMap<i32, user::Model> = user::Entity::find()
+        .filter(user::Column::Id.is_in(owner_ids))
+        .all(&db)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|u| (u.id, u))
+        .collect();
+
+    let user_cnt = count_by_org::<user::Entity>(&db, user::Column::OrganizationId, &org_ids)
+        .await
+        .map_err(DbError::from)?;
+
+    let vehicle_cnt =
+        count_by_org::<vehicle::Entity>(&db, vehicle::Column::OrganizationId, &org_ids)
+            .await
+            .map_err(DbError::from)?;
+
+    let tracker_cnt = count_by_org::<vehicle_tracker::Entity>(
+        &db,
+        vehicle_tracker::Column::OrganizationId,
+        &org_ids,
+    )
+    .await
+    .map_err(DbError::from)?;
+
+    let sim_card_cnt =
+        count_by_org::<sim_card::Entity>(&db, sim_card::Column::OrganizationId, &org_ids)
+            .await
+            .map_err(DbError::from)?;
+
+    let records = orgs
+        .into_iter()
+        .map(|org| OrganizationSummaryDto {
+            owner: org
+                .owner_id
+                .and_then(|id| owners.remove(&id))
+                .map(Into::into),
+            user_count: user_cnt.get(&org.id).copied().unwrap_or(0),
+            vehicle_count: vehicle_cnt.get(&org.id).copied().unwrap_or(0),
+            tracker_count: tracker_cnt.get(&org.id).copied().unwrap_or(0),
+            sim_card_count: sim_card_cnt.get(&org.id).copied().unwrap_or(0),
+            id: org.id,
+            created_at: org.created_at,
+            name: org.name,
+            blocked: org.blocked,
+            billing_email: org.billing_email,
+            billing_email_verified: org.billing_email_verified,
+        })
+        .collect();
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
+/// counts the rows of a entity for each organization, by its organization id column
+async fn count_by_org<E: EntityTrait>(
+    db: &DatabaseConnection,
+    org_id_col: E::Column,
+    org_ids: &[i32],
+) -> Result<HashMap<i32, i64>, DbErr> {
+    let rows: Vec<(Option<i32>, i64)> = E::find()
+        .select_only()
+        .column(org_id_col)
+        .column_as(org_id_col.count(), "count")
+        .filter(org_id_col.is_in(org_ids.to_vec()))
+        .group_by(org_id_col)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .filter_map(|(org_id, cnt)| org_id.map(|id| (id, cnt)))
+        .collect())
+}
+
+/// the SES identities of a email sender owned by the organization, see `sender_identities`
+async fn find_owned_sender_identities(
+    db: &DatabaseConnection,
+    org_id: i32,
+    email: &str,
+) -> Result<Vec<String>, DbErr> {
+    organization_email_identity::Entity::find()
+        .select_only()
+        .column(organization_email_identity::Column::Identity)
+        .filter(organization_email_identity::Column::OrganizationId.eq(org_id))
+        .filter(organization_email_identity::Column::Identity.is_in(sender_identities(email)))
+        .into_tuple()
+        .all(db)
+        .await
+}
+
+/// Starts the verification of a email sender
+///
+/// Required permissions: UPDATE_ORGANIZATION
+///
+/// adds a email address or domain as a SES identity owned by the organization, only
+/// addresses of identities owned by the organization can be set as its email sender.
+/// email addresses are verified by a link sent to them and domains by DNS records.
+///
+/// identities owned by other organizations or added to SES by other means (such as
+/// the domain of the platform) are refused with `EMAIL_SENDER_IN_USE`.
+#[utoipa::path(
+    post,
+    tag = "organization",
+    path = "/organization/email-sender/identity",
+    security(("session_id" = [])),
+    request_body = CreateEmailIdentityDto,
+    responses(
+        (
+            status = OK,
+            description = "the identity whose verification was started",
+            body = EmailIdentityDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / EMAIL_SENDER_IN_USE",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user lacks permissions",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn create_email_identity(
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+    ValidatedJson(dto): ValidatedJson<CreateEmailIdentityDto>,
+) -> Result<Json<EmailIdentityDto>, (StatusCode, SimpleError)> {
+    let identity = dto.identity.to_lowercase();
+
+    let in_use_err = || {
+        (
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(EMAIL_SENDER_IN_USE),
+        )
+    };
+
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    // the identity is recorded before creating it on SES, so concurrent
+    // requests of different organizations cannot both own it
+    let inserted =
+        organization_email_identity::Entity::insert(organization_email_identity::ActiveModel {
+            identity: Set(identity.clone()),
+            organization_id: Set(org_id),
+            ..Default::default()
+        })
+        .on_conflict(
+            OnConflict::column(organization_email_identity::Column::Identity)
+                .do_nothing()
+                .to_owned(),
+        )
+        .exec_without_returning(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    if inserted == 0 {
+        let owner_id: Option<i32> = organization_email_identity::Entity::find_by_id(&identity)
+            .select_only()
+            .column(organization_email_identity::Column::OrganizationId)
+            .into_tuple()
+            .one(&txn)
+            .await
+            .map_err(DbError::from)?;
+
+        if owner_id != Some(org_id) {
+            return Err(in_use_err());
+        }
+
+        return Ok(Json(EmailIdentityDto {
+            identity,
+            dkim_tokens: vec![],
+        }));
+    }
+
+    let dkim_tokens = state
+        .ses
+        .create_identity(&identity)
+        .await
+        .or(Err(internal_error_res()))?
+        .ok_or_else(in_use_err)?;
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    Ok(Json(EmailIdentityDto {
+        identity,
+        dkim_tokens,
+    }))
+}
+
+/// Gets the verification status of a email sender
+///
+/// Required permissions: UPDATE_ORGANIZATION
+///
+/// checks if a email address can be set as the organization email sender, that is if the
+/// address or its domain are verified on SES. statuses are cached for a minute, so a
+/// identity verified right before the check might still be listed as pending.
+///
+/// identities not owned by the organization are listed as not found, even if they exist on SES.
+#[utoipa::path(
+    get,
+    tag = "organization",
+    path = "/organization/email-sender/status",
+    security(("session_id" = [])),
+    params(
+        ("email" = String, Query, description = "email address to check"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the verification status of the sender",
+            body = EmailSenderStatusDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid email",
+            body = ValidationErrorResponse,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user lacks permissions",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_email_sender_status(
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+    ValidatedQuery(query): ValidatedQuery<EmailAddress>,
+) -> Result<Json<EmailSenderStatusDto>, (StatusCode, SimpleError)> {
+    let owned_identities = find_owned_sender_identities(&db, org_id, &query.email)
+        .await
+        .map_err(DbError::from)?;
+
+    let status = state
+        .ses
+        .sender_status(&query.email, &owned_identities)
+        .await
+        .or(Err(internal_error_res()))?;
+
+    Ok(Json(EmailSenderStatusDto {
+        email: query.email,
+        status: status.into(),
+    }))
+}
+
 /// Updates the user organization
 ///
 /// Required permissions: UPDATE_ORGANIZATION
+///
+/// Changing the billing email sets it as not verified, optionally sending
+/// a confirmation email to the new address.
 #[utoipa::path(
     patch,
     tag = "organization",
@@ -63,6 +434,16 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             description = "the updated organization",
             body = OrganizationDto,
         ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / EMAIL_SENDER_NOT_VERIFIED / EMAIL_IN_USE",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "default access level not found",
+            body = SimpleError,
+        ),
         (
             status = UNAUTHORIZED,
             description = "invalid session",
@@ -76,24 +457,114 @@ pub fn create_router(state: AppState) -> Router<AppState> {
     ),
 )]
 pub async fn update_org(
+    State(state): State<AppState>,
     DbConnection(db): DbConnection,
     Extension(req_user): Extension<RequestUser>,
     ValidatedJson(payload): ValidatedJson<UpdateOrganizationDto>,
 ) -> Result<Json<auth::dto::OrganizationDto>, (StatusCode, SimpleError)> {
     if let Some(org) = req_user.0.organization {
+        if let Some(Some(sender)) = &payload.email_sender {
+            let owned_identities = find_owned_sender_identities(&db, org.id, sender)
+                .await
+                .map_err(DbError::from)?;
+
+            let is_verified = state
+                .ses
+                .is_verified_sender(sender, &owned_identities)
+                .await
+                .or(Err(internal_error_res()))?;
+
+            if !is_verified {
+                return Err((
+                    StatusCode::BAD_REQUEST,
+                    SimpleError::from(EMAIL_SENDER_NOT_VERIFIED),
+                ));
+            }
+        }
+
+        if let Some(Some(access_level_id)) = payload.default_access_level_id {
+            access_level::Entity::find_by_id_and_org_id(access_level_id, org.id, &db)
+                .await
+                .map_err(DbError::from)?
+                .ok_or((
+                    StatusCode::NOT_FOUND,
+                    SimpleError::from("access level not found"),
+                ))?;
+        }
+
+        let new_billing_email = payload
+            .billing_email
+            .filter(|email| *email != org.billing_email);
+
+        if let Some(email) = &new_billing_email {
+            let email_in_use = state
+                .auth_service
+                .check_email_in_use(email)
+                .await
+                .or(Err(internal_error_res()))?;
+
+            if email_in_use {
+                return Err((StatusCode::BAD_REQUEST, SimpleError::from(EMAIL_IN_USE)));
+            }
+        }
+
+        let billing_email_changed = new_billing_email.is_some();
+
         organization::Entity::update_many()
             .apply_if(payload.name, |query, v| {
                 query.col_expr(organization::Column::Name, Expr::value(v))
             })
-            .apply_if(payload.billing_email, |query, v| {
-                query.col_expr(organization::Column::BillingEmail, Expr::value(v))
+            .apply_if(new_billing_email, |query, v| {
+                // a confirmation token sent to the previous billing email must not
+                // be able to verify the new one, so its cleared as well
+                query
+                    .col_expr(organization::Column::BillingEmail, Expr::value(v))
+                    .col_expr(
+                        organization::Column::BillingEmailVerified,
+                        Expr::value(false),
+                    )
+                    .col_expr(
+                        organization::Column::ConfirmBillingEmailToken,
+                        Expr::value(Option::<String>::None),
+                    )
+            })
+            .apply_if(payload.email_sender, |query, v| {
+                query.col_expr(organization::Column::EmailSender, Expr::value(v))
+            })
+            .apply_if(payload.default_access_level_id, |query, v| {
+                query.col_expr(organization::Column::DefaultAccessLevelId, Expr::value(v))
             })
             .filter(organization::Column::Id.eq(org.id))
             .exec(&db)
             .await
             .map_err(DbError::from)?;
 
-        return Ok(Json(org));
+        let updated_org = find_org_or_404(&db, org.id).await?;
+
+        if billing_email_changed && payload.send_confirmation_email {
+            let token = state
+                .auth_service
+                .gen_and_set_org_confirm_email_token(updated_org.id)
+                .await
+                .or(Err(internal_error_res()))?;
+
+            // the organization was already updated, so failing to queue the email is not
+            // a error for the request, the confirmation can be requested again later
+            if let Err(err) = state
+                .mailer_service
+                .send_confirm_email_address_email(
+                    updated_org.billing_email.clone(),
+                    token,
+                    ConfirmEmailRecipientType::Organization,
+                    updated_org.email_sender.clone(),
+                )
+                .await
+            {
+                tracing::error!("failed to queue billing email confirmation: {:?}", err);
+            }
+        }
+
+        return Ok(Json(auth::dto::OrganizationDto::from(updated_org)));
     }
 
     Err((
@@ -102,6 +573,186 @@ pub async fn update_org(
     ))
 }
 
+/// Sets the organization IP allowlist
+///
+/// Required permissions: UPDATE_ORGANIZATION
+///
+/// Once set, requests of the organization users are refused with `IP_NOT_ALLOWED` unless
+/// sent from one of the allowlisted networks. To prevent locking the organization out,
+/// the list must contain the ip of the request setting it.
+#[utoipa::path(
+    put,
+    tag = "organization",
+    path = "/organization/ip-allowlist",
+    security(("session_id" = [])),
+    request_body = SetIpAllowlistDto,
+    responses(
+        (
+            status = OK,
+            description = "the updated organization",
+            body = OrganizationDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / IP_NOT_ALLOWED",
+            body = SimpleError,
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user lacks permissions",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn set_ip_allowlist(
+    OrganizationId(org_id): OrganizationId,
+    SecureClientIp(client_ip): SecureClientIp,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<SetIpAllowlistDto>,
+) -> Result<Json<auth::dto::OrganizationDto>, (StatusCode, SimpleError)> {
+    if let Some(networks) = &dto.networks {
+        if !ip_allowlist::is_allowed(networks, client_ip) {
+            return Err((StatusCode::BAD_REQUEST, SimpleError::from(IP_NOT_ALLOWED)));
+        }
+    }
+
+    let org = find_org_or_404(&db, org_id).await?;
+
+    let mut org: organization::ActiveModel = org.into();
+
+    org.ip_allowlist = Set(dto.networks);
+
+    let org = org.update(&db).await.map_err(DbError::from)?;
+
+    tracing::info!(org_id, ip_allowlist = ?org.ip_allowlist, "organization ip allowlist set");
+
+    Ok(Json(auth::dto::OrganizationDto::from(org)))
+}
+
+/// Transfers the organization ownership
+///
+/// Only accessible to the organization owner, sets another user of the organization as
+/// its owner and grants the new owner the organization root (fixed) access level, the
+/// transfer is recorded on the organization activity feed.
+#[utoipa::path(
+    post,
+    tag = "organization",
+    path = "/organization/transfer-ownership",
+    security(("session_id" = [])),
+    request_body = TransferOwnershipDto,
+    responses(
+        (
+            status = OK,
+            description = "the updated organization",
+            body = OrganizationDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / user already owns the organization",
+            body = SimpleError,
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not the organization owner",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "new owner not found on the organization",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn transfer_ownership(
+    Extension(req_user): Extension<RequestUser>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<TransferOwnershipDto>,
+) -> Result<Json<auth::dto::OrganizationDto>, (StatusCode, SimpleError)> {
+    let org = find_org_or_404(&db, org_id).await?;
+
+    let previous_owner_id = req_user.0.id;
+    let new_owner_id = dto.new_owner_id;
+
+    if org.owner_id != Some(previous_owner_id) {
+        return Err((
+            StatusCode::FORBIDDEN,
+            SimpleError::from("only the organization owner can transfer its ownership"),
+        ));
+    }
+
+    if new_owner_id == previous_owner_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("user already owns the organization"),
+        ));
+    }
+
+    let new_owner = user::Entity::find_by_id_and_org_id(new_owner_id, org_id, &db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((StatusCode::NOT_FOUND, SimpleError::from("user not found")))?;
+
+    let root_access_level = access_level::Entity::find()
+        .filter(access_level::Column::OrganizationId.eq(org_id))
+        .filter(access_level::Column::IsFixed.eq(true))
+        .one(&db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or_else(internal_error_res)?;
+
+    let org = db
+        .transaction::<_, organization::Model, DbErr>(|tx| {
+            Box::pin(async move {
+                if new_owner.access_level_id != root_access_level.id {
+                    let mut new_owner: user::ActiveModel = new_owner.into();
+
+                    new_owner.access_level_id = Set(root_access_level.id);
+                    new_owner.update(tx).await?;
+                }
+
+                let mut org: organization::ActiveModel = org.into();
+
+                org.owner_id = Set(Some(new_owner_id));
+
+                let org = org.update(tx).await?;
+
+                activity::record_activity(
+                    tx,
+                    org_id,
+                    ActivityEvent::OwnershipTransferred(OwnershipTransferActivity {
+                        previous_owner_id,
+                        new_owner_id,
+                    }),
+                )
+                .await?;
+
+                Ok(org)
+            })
+        })
+        .await
+        .map_err(|_| internal_error_res())?;
+
+    tracing::info!(
+        org_id,
+        previous_owner_id,
+        new_owner_id,
+        "organization ownership transferred"
+    );
+
+    Ok(Json(auth::dto::OrganizationDto::from(org)))
+}
+
 /// Requests org email address confirmation
 ///
 /// Required permissions: UPDATE_ORGANIZATION
@@ -130,6 +781,16 @@ pub async fn update_org(
             description = "invalid dto error message / EMAIL_ALREADY_CONFIRMED",
             body = SimpleError,
         ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "EMAIL_SERVICE_UNAVAILABLE, the message broker is unreachable",
+            body = SimpleError,
+        ),
+        (
+            status = BAD_GATEWAY,
+            description = "EMAIL_REJECTED, the message broker refused the email",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn request_email_address_confirmation(
@@ -156,9 +817,9 @@ pub async fn request_email_address_confirmation(
                 user_org.billing_email,
                 token,
                 ConfirmEmailRecipientType::Organization,
+                user_org.email_sender,
             )
-            .await
-            .or(Err(internal_error_res()))?;
+            .await?;
 
         return Ok(Json("email address confirmation email queued successfully"));
     }
@@ -240,3 +901,375 @@ pub async fn confirm_email_address_by_token(
         SimpleError::from("user not found with this reset password token"),
     ))
 }
+
+/// sorts the flags by name, so they are always listed in the same order
+fn into_feature_flag_dtos(flags: OrgFeatureFlags) -> Vec<FeatureFlagDto> {
+    let mut dtos: Vec<FeatureFlagDto> = flags
+        .into_iter()
+        .map(|(flag, enabled)| FeatureFlagDto { flag, enabled })
+        .collect();
+
+    dtos.sort_by_key(|dto| dto.flag.to_string());
+
+    dtos
+}
+
+/// Lists the feature flags of the user organization
+///
+/// lists every feature flag, flags not set for the organization have their default value
+#[utoipa::path(
+    get,
+    tag = "organization",
+    path = "/organization/feature-flags",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "the organization feature flags",
+            body = Vec<FeatureFlagDto>,
+        ),
+    ),
+)]
+pub async fn get_org_feature_flags(
+    State(state): State<AppState>,
+    OrganizationId(org_id): OrganizationId,
+) -> Result<Json<Vec<FeatureFlagDto>>, (StatusCode, SimpleError)> {
+    let flags = state
+        .feature_flags
+        .get_all(org_id)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(into_feature_flag_dtos(flags)))
+}
+
+/// Lists the feature flags of a organization
+///
+/// Only accessible to superusers, lists every feature flag of the organization,
+/// flags not set for the organization have their default value
+#[utoipa::path(
+    get,
+    tag = "organization",
+    path = "/organization/{org_id}/feature-flags",
+    security(("session_id" = [])),
+    params(
+        ("org_id" = i32, Path, description = "id of the organization"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the organization feature flags",
+            body = Vec<FeatureFlagDto>,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "organization not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_org_feature_flags_by_org_id(
+    _: SuperUser,
+    Path(org_id): Path<i32>,
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<Vec<FeatureFlagDto>>, (StatusCode, SimpleError)> {
+    find_org_or_404(&db, org_id).await?;
+
+    let flags = state
+        .feature_flags
+        .get_all(org_id)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(into_feature_flag_dtos(flags)))
+}
+
+/// Sets a feature flag of a organization
+///
+/// Only accessible to superusers, enables or disables a feature for the
+/// organization, returning all of the organization feature flags.
+///
+/// flag changes might take up to a minute to take effect on every API instance.
+#[utoipa::path(
+    put,
+    tag = "organization",
+    path = "/organization/{org_id}/feature-flags",
+    security(("session_id" = [])),
+    params(
+        ("org_id" = i32, Path, description = "id of the organization"),
+    ),
+    request_body = SetFeatureFlagDto,
+    responses(
+        (
+            status = OK,
+            description = "the organization feature flags",
+            body = Vec<FeatureFlagDto>,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "organization not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn set_org_feature_flag(
+    _: SuperUser,
+    Path(org_id): Path<i32>,
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<SetFeatureFlagDto>,
+) -> Result<Json<Vec<FeatureFlagDto>>, (StatusCode, SimpleError)> {
+    find_org_or_404(&db, org_id).await?;
+
+    state
+        .feature_flags
+        .set(org_id, dto.flag, dto.enabled)
+        .await
+        .map_err(DbError::from)?;
+
+    tracing::info!(org_id, flag = %dto.flag, enabled = dto.enabled, "feature flag set");
+
+    let flags = state
+        .feature_flags
+        .get_all(org_id)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(into_feature_flag_dtos(flags)))
+}
+
+/// Sets the plan limits of a organization
+///
+/// Only accessible to superusers, limits lower than the amount of entities
+/// the organization already has only prevent new entities from being created.
+#[utoipa::path(
+    put,
+    tag = "organization",
+    path = "/organization/{org_id}/limits",
+    security(("session_id" = [])),
+    params(
+        ("org_id" = i32, Path, description = "id of the organization"),
+    ),
+    request_body = SetOrganizationLimitsDto,
+    responses(
+        (
+            status = OK,
+            description = "the updated organization",
+            body = OrganizationDto,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "organization not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn set_org_limits(
+    _: SuperUser,
+    Path(org_id): Path<i32>,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<SetOrganizationLimitsDto>,
+) -> Result<Json<auth::dto::OrganizationDto>, (StatusCode, SimpleError)> {
+    let org = find_org_or_404(&db, org_id).await?;
+
+    let mut org: organization::ActiveModel = org.into();
+
+    org.max_vehicles = Set(dto.max_vehicles);
+    org.max_trackers = Set(dto.max_trackers);
+
+    let org = org.update(&db).await.map_err(DbError::from)?;
+
+    tracing::info!(
+        org_id,
+        max_vehicles = dto.max_vehicles,
+        max_trackers = dto.max_trackers,
+        "organization limits set"
+    );
+
+    Ok(Json(auth::dto::OrganizationDto::from(org)))
+}
+
+/// Blocks or unblocks a organization
+///
+/// Only accessible to superusers, users of blocked organizations cannot use the API,
+/// besides signing out and getting the organization billing status.
+#[utoipa::path(
+    put,
+    tag = "organization",
+    path = "/organization/{org_id}/blocked",
+    security(("session_id" = [])),
+    params(
+        ("org_id" = i32, Path, description = "id of the organization"),
+    ),
+    request_body = SetOrganizationBlockedDto,
+    responses(
+        (
+            status = OK,
+            description = "the updated organization",
+            body = OrganizationDto,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "organization not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn set_org_blocked(
+    _: SuperUser,
+    Path(org_id): Path<i32>,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<SetOrganizationBlockedDto>,
+) -> Result<Json<auth::dto::OrganizationDto>, (StatusCode, SimpleError)> {
+    let org = find_org_or_404(&db, org_id).await?;
+
+    let mut org: organization::ActiveModel = org.into();
+
+    org.blocked = Set(dto.blocked);
+
+    let org = org.update(&db).await.map_err(DbError::from)?;
+
+    tracing::info!(
+        org_id,
+        blocked = dto.blocked,
+        "organization blocked status set"
+    );
+
+    Ok(Json(auth::dto::OrganizationDto::from(org)))
+}
+
+/// Gets the request user organization billing status
+///
+/// accessible to users of blocked organizations, so clients can tell why the
+/// organization is blocked and who to contact to settle its billing
+#[utoipa::path(
+    get,
+    tag = "organization",
+    path = "/organization/billing-status",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "the organization billing status",
+            body = BillingStatusDto,
+        ),
+    ),
+)]
+pub async fn get_billing_status(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<BillingStatusDto>, (StatusCode, SimpleError)> {
+    let org = find_org_or_404(&db, org_id).await?;
+
+    Ok(Json(BillingStatusDto {
+        blocked: org.blocked,
+        billing_contact_email: app_config().billing_contact_email.clone(),
+    }))
+}
+
+async fn find_org_or_404(
+    db: &DatabaseConnection,
+    org_id: i32,
+) -> Result<organization::Model, (StatusCode, SimpleError)> {
+    organization::Entity::find_by_id(org_id)
+        .one(db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            SimpleError::from("organization not found"),
+        ))
+}
+
+/// Lists the organization activity
+///
+/// events of every source (eg: impersonations) in a single
+/// feed, from the most to the least recent
+#[utoipa::path(
+    get,
+    tag = "organization",
+    path = "/organization/activity",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListActivityDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of activity events",
+            content_type = "application/json",
+            body = PaginatedOrganizationActivity,
+        ),
+    ),
+)]
+pub async fn list_activity(
+    OrganizationId(org_id): OrganizationId,
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListActivityDto>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<ActivityDto>>, (StatusCode, SimpleError)> {
+    let paginator = organization_activity::Entity::find()
+        .filter(organization_activity::Column::OrganizationId.eq(org_id))
+        .apply_if(filter.activity_type, |query, activity_type| {
+            query.filter(organization_activity::Column::Type.eq(activity_type.to_string()))
+        })
+        .apply_if(filter.after, |query, after| {
+            query.filter(organization_activity::Column::CreatedAt.gt(after))
+        })
+        .apply_if(filter.before, |query, before| {
+            query.filter(organization_activity::Column::CreatedAt.lt(before))
+        })
+        .order_by_desc(organization_activity::Column::CreatedAt)
+        .order_by_desc(organization_activity::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?
+        .iter()
+        .map(|record| {
+            Ok(ActivityDto {
+                id: record.id,
+                created_at: record.created_at,
+                event: ActivityEvent::from_record(record)?,
+            })
+        })
+        .collect::<Result<Vec<_>, serde_json::Error>>()
+        .or(Err(internal_error_res()))?;
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
@@ -1,13 +1,44 @@
-use serde::Deserialize;
+use crate::modules::auth::middleware::RequestUser;
+use crate::modules::common::validators::REGEX_CONTAINS_NUMBER;
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+use shared::{constants::Permission, entity::sim_card};
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
+/// shown instead of the PIN and PUK codes of a SIM card to users who cannot see them
+pub const SIM_SECRET_MASK: &str = "****";
+
+/// the SIM card as returned to the request user, its PIN and PUK codes are
+/// masked unless the user has the `VIEW_SIM_SECRETS` permission, codes that
+/// are not set stay `null` so users still know if the SIM card has them
+pub fn sim_card_for_user(sim_card: sim_card::Model, req_user: &RequestUser) -> sim_card::Model {
+    if req_user
+        .get_missing_permissions(&[Permission::ViewSimSecrets])
+        .is_empty()
+    {
+        return sim_card;
+    }
+
+    let mask = |code: Option<String>| code.map(|_| String::from(SIM_SECRET_MASK));
+
+    sim_card::Model {
+        pin: mask(sim_card.pin),
+        pin2: mask(sim_card.pin2),
+        puk: mask(sim_card.puk),
+        puk2: mask(sim_card.puk2),
+        ..sim_card
+    }
+}
+
 #[derive(Deserialize, ToSchema, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct CreateSimCardDto {
     #[validate(length(min = 1))]
     pub ssn: String,
 
+    /// in international format or in the national format of the API default region,
+    /// stored in E.164 format (eg: `+5511987654321`)
     pub phone_number: String,
 
     pub apn_user: String,
@@ -23,6 +54,10 @@ pub struct CreateSimCardDto {
     /// ID of the vehicle to associate with the tracker
     #[validate(range(min = 1))]
     pub vehicle_tracker_id: Option<i32>,
+
+    /// Monthly data quota of the SIM card plan in MB
+    #[validate(range(min = 1))]
+    pub data_quota_mb: Option<i32>,
 }
 
 #[derive(Deserialize, ToSchema, Validate)]
@@ -30,6 +65,7 @@ pub struct CreateSimCardDto {
 pub struct UpdateSimCardDto {
     pub ssn: Option<String>,
 
+    /// stored in E.164 format, see `CreateSimCardDto`
     pub phone_number: Option<String>,
 
     pub apn_user: Option<String>,
@@ -49,18 +85,55 @@ pub struct UpdateSimCardDto {
 
     #[serde(default, with = "::serde_with::rust::double_option")]
     pub puk2: Option<Option<String>>,
+
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    #[validate(range(min = 1))]
+    pub data_quota_mb: Option<Option<i32>>,
 }
 
 #[derive(Deserialize, IntoParams, Validate)]
 #[serde(rename_all = "camelCase")]
 #[into_params(parameter_in = Query)]
 pub struct ListSimCardsDto {
-    /// Search SIM cards by phone
+    /// Search SIM cards by phone, only the digits are compared so any
+    /// formatting is ignored and partial national numbers are matched
+    #[validate(regex(
+        path = "REGEX_CONTAINS_NUMBER",
+        message = "phone number search must contain a digit"
+    ))]
     pub phone_number: Option<String>,
 
     /// If the sim cards should be filtered if they are associated
     /// to a tracker or not, `None` means `any`
     pub with_associated_tracker: Option<bool>,
+
+    /// Only list SIM cards whose data usage on the current month reached
+    /// this percentage of their data quota, eg: `80`
+    #[validate(range(min = 1, max = 100))]
+    pub approaching_quota_percent: Option<u8>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct RecordSimCardDataUsageDto {
+    /// Any day of the month the usage refers to, eg: `2024-02-01`
+    pub period: NaiveDate,
+
+    /// Total data used by the SIM card on the month, recording
+    /// the usage of the same month again replaces the previous value
+    #[validate(range(min = 0.0))]
+    pub data_usage_mb: f64,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct ListSimCardDataUsageDto {
+    /// List usage of months on or after the month of this date
+    pub from: Option<NaiveDate>,
+
+    /// List usage of months on or before the month of this date
+    pub to: Option<NaiveDate>,
 }
 
 #[derive(Deserialize, ToSchema, Validate)]
@@ -76,3 +149,136 @@ pub struct SetSimCardTrackerDto {
     #[validate(required)]
     pub vehicle_tracker_id: Option<Option<i32>>,
 }
+
+#[derive(Serialize, Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct SimCardAssignmentDto {
+    /// ID of the SIM card to install on the tracker
+    #[validate(range(min = 1))]
+    pub sim_card_id: i32,
+
+    /// ID of the tracker to install the SIM card on
+    #[validate(range(min = 1))]
+    pub vehicle_tracker_id: i32,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkAssignSimCardsDto {
+    /// SIM card and tracker pairs, applied in order
+    #[validate(length(min = 1, max = 100))]
+    #[validate]
+    pub assignments: Vec<SimCardAssignmentDto>,
+}
+
+/// New APN settings of the SIM cards selected by their IDs and/or current APN address,
+/// at least one of `ids` or `currentApnAddress` is required
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkUpdateApnDto {
+    /// Only update these SIM cards
+    #[validate(length(min = 1, max = 1000))]
+    pub ids: Option<Vec<i32>>,
+
+    /// Only update the SIM cards with this APN address, eg: the address a carrier is replacing
+    #[validate(length(min = 1, max = 255))]
+    pub current_apn_address: Option<String>,
+
+    #[validate(length(min = 1, max = 255))]
+    pub apn_address: String,
+
+    #[validate(length(max = 255))]
+    pub apn_user: String,
+
+    #[validate(length(max = 255))]
+    pub apn_password: String,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkUpdateApnResultDto {
+    /// amount of SIM cards updated
+    pub updated: u64,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SimCardAssignmentResultDto {
+    pub sim_card_id: i32,
+
+    pub vehicle_tracker_id: i32,
+
+    /// if the SIM card was assigned to the tracker
+    pub success: bool,
+
+    /// why the assignment failed, `None` on success
+    pub error: Option<String>,
+}
+
+/// Result of normalizing the phone numbers of the existing SIM cards, by SIM card ID
+#[derive(Serialize, ToSchema, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct NormalizePhoneNumbersResultDto {
+    /// SIM cards whose phone number was normalized, or would be on dry runs
+    pub normalized: Vec<i32>,
+
+    /// SIM cards whose phone number is not valid
+    pub invalid: Vec<i32>,
+
+    /// SIM cards whose normalized phone number is used by another SIM card of the organization
+    pub conflicting: Vec<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::common::fixtures::{self, user_with_permissions};
+
+    fn sim_card_with_secrets() -> sim_card::Model {
+        sim_card::Model {
+            pin: Some(String::from("1234")),
+            puk: Some(String::from("12345678")),
+            puk2: Some(String::from("87654321")),
+            ..fixtures::sim_card(1)
+        }
+    }
+
+    #[test]
+    fn masks_secrets_for_users_without_view_sim_secrets() {
+        let user = user_with_permissions(&[Permission::UpdateSimCard]);
+
+        let sim_card = sim_card_for_user(sim_card_with_secrets(), &user);
+
+        assert_eq!(sim_card.pin.as_deref(), Some(SIM_SECRET_MASK));
+        assert_eq!(sim_card.puk.as_deref(), Some(SIM_SECRET_MASK));
+        assert_eq!(sim_card.puk2.as_deref(), Some(SIM_SECRET_MASK));
+
+        // codes that are not set are not masked, so users know they are missing
+        assert_eq!(sim_card.pin2, None);
+
+        assert_eq!(sim_card.ssn, sim_card_with_secrets().ssn);
+        assert_eq!(sim_card.apn_user, sim_card_with_secrets().apn_user);
+    }
+
+    #[test]
+    fn keeps_secrets_for_users_with_view_sim_secrets() {
+        let user = user_with_permissions(&[Permission::UpdateSimCard, Permission::ViewSimSecrets]);
+
+        let sim_card = sim_card_for_user(sim_card_with_secrets(), &user);
+
+        assert_eq!(sim_card, sim_card_with_secrets());
+    }
+
+    #[test]
+    fn phone_number_searches_without_digits_are_refused() {
+        let search = |phone_number: &str| ListSimCardsDto {
+            phone_number: Some(String::from(phone_number)),
+            with_associated_tracker: None,
+            approaching_quota_percent: None,
+        };
+
+        assert!(search("(11) 9876").validate().is_ok());
+        assert!(search("+").validate().is_err());
+        assert!(search("").validate().is_err());
+    }
+}
@@ -1,15 +1,25 @@
-use super::dto::{self, CreateSimCardDto, ListSimCardsDto};
+use super::dto::{
+    self, sim_card_for_user, CreateSimCardDto, ListSimCardDataUsageDto, ListSimCardsDto,
+    RecordSimCardDataUsageDto,
+};
 use crate::{
     database::{self, error::DbError, helpers::set_if_some},
     modules::{
-        auth::{self, middleware::AclLayer},
+        auth::{
+            self,
+            middleware::{AclLayer, RequestUser},
+        },
         common::{
-            dto::{Pagination, PaginationResult},
+            dto::{
+                BulkDeleteDto, BulkDeleteResultDto, CountDto, DryRun, Pagination, PaginationResult,
+            },
+            error_codes::INVALID_PHONE_NUMBER,
             extractors::{
-                DbConnection, OrgBoundEntityFromPathId, OrganizationId, ValidatedJson,
+                DbConnection, OrgBoundEntityFromPathId, OrganizationId, SuperUser, ValidatedJson,
                 ValidatedQuery,
             },
             responses::{internal_error_msg, SimpleError},
+            validators::normalize_phone_number,
         },
     },
     server::controller::AppState,
@@ -17,20 +27,29 @@ use crate::{
 use axum::{
     extract::Path,
     routing::{delete, get, post, put},
-    Json, Router,
+    Extension, Json, Router,
 };
+use chrono::{Datelike, NaiveDate, Utc};
 use http::StatusCode;
 use migration::Expr;
-use sea_orm::{
-    sea_query::extension::postgres::PgExpr, ActiveModelTrait, QuerySelect, Set, TryIntoModel,
-};
+use sea_orm::sea_query::OnConflict;
+use sea_orm::{ActiveModelTrait, ConnectionTrait, DbErr, QuerySelect, Select, Set};
 use sea_orm::{ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder, QueryTrait};
+use sea_orm::{TransactionTrait, TryIntoModel};
 use shared::constants::Permission;
-use shared::entity::{sim_card, traits::QueryableByIdAndOrgId, vehicle_tracker};
+use shared::entity::{sim_card, sim_card_data_usage, vehicle_tracker};
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+static SIM_SLOTS_OVERFLOW_ERR: &str =
+    "associating the sim card with the tracker would overflow the SIM slots for the tracker model";
+
+static SIM_TRACKER_ORG_MISMATCH_ERR: &str =
+    "the sim card and the tracker do not belong to the same organization";
 
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/", get(list_sim_cards))
+        .route("/count", get(count_sim_cards))
         //
         .route(
             "/",
@@ -49,6 +68,33 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             delete(delete_sim_card).layer(AclLayer::single(Permission::DeleteSimCard)),
         )
         //
+        .route(
+            "/:sim_card_id/data-usage",
+            put(record_sim_card_data_usage).layer(AclLayer::single(Permission::UpdateSimCard)),
+        )
+        //
+        .route("/:sim_card_id/data-usage", get(list_sim_card_data_usage))
+        //
+        .route(
+            "/bulk-delete",
+            post(bulk_delete_sim_cards).layer(AclLayer::single(Permission::DeleteSimCard)),
+        )
+        //
+        .route(
+            "/bulk-update-apn",
+            post(bulk_update_sim_card_apn).layer(AclLayer::single(Permission::UpdateSimCard)),
+        )
+        //
+        .route(
+            "/normalize-phone-numbers",
+            post(normalize_sim_card_phone_numbers),
+        )
+        //
+        .route(
+            "/bulk-assign",
+            post(bulk_assign_sim_cards).layer(AclLayer::single(Permission::UpdateTracker)),
+        )
+        //
         .route(
             "/:sim_card_id/tracker",
             put(set_sim_card_tracker).layer(AclLayer::single(Permission::UpdateTracker)),
@@ -60,9 +106,19 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         ))
 }
 
+/// the phone number in the E.164 format SIM card phone numbers are stored in
+fn phone_number_to_store(phone_number: &str) -> Result<String, (StatusCode, SimpleError)> {
+    normalize_phone_number(phone_number).ok_or((
+        StatusCode::BAD_REQUEST,
+        SimpleError::from(INVALID_PHONE_NUMBER),
+    ))
+}
+
 /// Creates a SIM card
 ///
 /// Required permissions: CREATE_SIM_CARD
+///
+/// the PIN and PUK codes are masked unless the user has the VIEW_SIM_SECRETS permission
 #[utoipa::path(
     post,
     tag = "sim-card",
@@ -78,7 +134,7 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         ),
         (
             status = BAD_REQUEST,
-            description = "invalid dto error message / SSN_IN_USE / PHONE_NUMBER_IN_USE",
+            description = "invalid dto error message / SSN_IN_USE / PHONE_NUMBER_IN_USE / INVALID_PHONE_NUMBER",
             body = SimpleError,
         ),
     ),
@@ -86,24 +142,32 @@ pub fn create_router(state: AppState) -> Router<AppState> {
 pub async fn create_sim_card(
     OrganizationId(org_id): OrganizationId,
     DbConnection(db): DbConnection,
+    Extension(req_user): Extension<RequestUser>,
     ValidatedJson(dto): ValidatedJson<CreateSimCardDto>,
 ) -> Result<Json<sim_card::Model>, (StatusCode, SimpleError)> {
+    let phone_number = phone_number_to_store(&dto.phone_number)?;
+
+    let txn = db.begin().await.map_err(DbError::from)?;
+
     if let Some(vehicle_tracker_id) = dto.vehicle_tracker_id {
-        let tracker =
-            vehicle_tracker::Entity::find_by_id_and_org_id(vehicle_tracker_id, org_id, &db)
-                .await
-                .map_err(DbError::from)?
-                .ok_or((
-                    StatusCode::BAD_REQUEST,
-                    SimpleError::from("vehicle_tracker not found"),
-                ))?;
+        // locked so concurrent assignments to the tracker cannot both pass the SIM slots check
+        let tracker = vehicle_tracker::Entity::find_by_id(vehicle_tracker_id)
+            .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+            .lock_exclusive()
+            .one(&txn)
+            .await
+            .map_err(DbError::from)?
+            .ok_or((
+                StatusCode::BAD_REQUEST,
+                SimpleError::from("vehicle_tracker not found"),
+            ))?;
 
         let sim_cards_on_tracker_count: i64 = sim_card::Entity::find()
             .select_only()
             .column_as(sim_card::Column::Id.count(), "count")
             .filter(sim_card::Column::VehicleTrackerId.eq(vehicle_tracker_id))
             .into_tuple()
-            .one(&db)
+            .one(&txn)
             .await
             .map_err(DbError::from)?
             .unwrap_or(0);
@@ -119,7 +183,7 @@ pub async fn create_sim_card(
 
     let created_sim_card = sim_card::ActiveModel {
         ssn: Set(dto.ssn),
-        phone_number: Set(dto.phone_number),
+        phone_number: Set(phone_number),
 
         apn_user: Set(dto.apn_user),
         apn_password: Set(dto.apn_password),
@@ -132,21 +196,26 @@ pub async fn create_sim_card(
         puk2: Set(dto.puk2),
 
         vehicle_tracker_id: Set(dto.vehicle_tracker_id),
+        data_quota_mb: Set(dto.data_quota_mb),
         organization_id: Set(org_id),
         ..Default::default()
     }
-    .save(&db)
+    .save(&txn)
     .await
     .map_err(DbError::from)?
     .try_into_model()
     .map_err(DbError::from)?;
 
-    Ok(Json(created_sim_card))
+    txn.commit().await.map_err(DbError::from)?;
+
+    Ok(Json(sim_card_for_user(created_sim_card, &req_user)))
 }
 
 /// Updates a SIM card
 ///
 /// Required permissions: UPDATE_SIM_CARD
+///
+/// the PIN and PUK codes are masked unless the user has the VIEW_SIM_SECRETS permission
 #[utoipa::path(
     put,
     tag = "sim-card",
@@ -162,18 +231,29 @@ pub async fn create_sim_card(
             description = "the updated SIM card",
             content_type = "application/json",
             body = entity::sim_card::Model,
-        )
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / SSN_IN_USE / PHONE_NUMBER_IN_USE / INVALID_PHONE_NUMBER",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn update_sim_card(
     DbConnection(db): DbConnection,
+    Extension(req_user): Extension<RequestUser>,
     OrgBoundEntityFromPathId(sim_to_update): OrgBoundEntityFromPathId<sim_card::Entity>,
     ValidatedJson(dto): ValidatedJson<dto::UpdateSimCardDto>,
 ) -> Result<Json<sim_card::Model>, (StatusCode, SimpleError)> {
     let mut v: sim_card::ActiveModel = sim_to_update.into();
 
     v.ssn = set_if_some(dto.ssn);
-    v.phone_number = set_if_some(dto.phone_number);
+    v.phone_number = set_if_some(
+        dto.phone_number
+            .as_deref()
+            .map(phone_number_to_store)
+            .transpose()?,
+    );
     v.apn_user = set_if_some(dto.apn_user);
     v.apn_address = set_if_some(dto.apn_address);
     v.apn_password = set_if_some(dto.apn_password);
@@ -181,10 +261,11 @@ pub async fn update_sim_card(
     v.pin2 = set_if_some(dto.pin2);
     v.puk = set_if_some(dto.puk);
     v.puk2 = set_if_some(dto.puk2);
+    v.data_quota_mb = set_if_some(dto.data_quota_mb);
 
     let updated_sim_card = v.update(&db).await.map_err(DbError::from)?;
 
-    Ok(Json(updated_sim_card))
+    Ok(Json(sim_card_for_user(updated_sim_card, &req_user)))
 }
 
 /// Sets a sim card tracker
@@ -209,7 +290,7 @@ pub async fn update_sim_card(
         ),
         (
             status = BAD_REQUEST,
-            description = "sim card <id> is already has a tracker",
+            description = "sim card <id> is already has a tracker / sim card and tracker organizations mismatch",
             body = SimpleError,
         ),
     ),
@@ -226,10 +307,14 @@ pub async fn set_sim_card_tracker(
         .vehicle_tracker_id
         .ok_or(internal_error_msg("error parsing sim_card_id"))?;
 
+    let txn = db.begin().await.map_err(DbError::from)?;
+
     if let Some(new_tracker_id) = tracker_id_or_none {
+        // locked so concurrent assignments to the tracker cannot both pass the SIM slots check
         let tracker = vehicle_tracker::Entity::find_by_id(new_tracker_id)
             .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
-            .one(&db)
+            .lock_exclusive()
+            .one(&txn)
             .await
             .map_err(DbError::from)?
             .ok_or((
@@ -237,6 +322,13 @@ pub async fn set_sim_card_tracker(
                 SimpleError::from("tracker not found"),
             ))?;
 
+        if !same_organization(&sim_card, &tracker) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                SimpleError::from(SIM_TRACKER_ORG_MISMATCH_ERR),
+            ));
+        }
+
         if sim_card.vehicle_tracker_id == Some(new_tracker_id) {
             let success_msg = format!(
                 "sim card is already associated with tracker: {}",
@@ -250,14 +342,16 @@ pub async fn set_sim_card_tracker(
             .column_as(sim_card::Column::Id.count(), "count")
             .filter(sim_card::Column::VehicleTrackerId.eq(new_tracker_id))
             .into_tuple()
-            .one(&db)
+            .one(&txn)
             .await
             .map_err(DbError::from)?
             .unwrap_or(0);
 
-        if sim_cards_associated_with_tracker + 1 > tracker.model.get_info().sim_card_slots.into() {
-            let err_msg = "associating the sim card with the tracker would overflow the SIM slots for the tracker model";
-            return Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)));
+        if overflows_sim_card_slots(&tracker, sim_cards_associated_with_tracker, 1) {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                SimpleError::from(SIM_SLOTS_OVERFLOW_ERR),
+            ));
         }
     }
 
@@ -268,13 +362,394 @@ pub async fn set_sim_card_tracker(
         )
         .filter(sim_card::Column::Id.eq(sim_card_id))
         .filter(sim_card::Column::OrganizationId.eq(org_id))
-        .exec(&db)
+        .exec(&txn)
         .await
         .map_err(DbError::from)?;
 
+    txn.commit().await.map_err(DbError::from)?;
+
     Ok(Json(String::from("sim card tracker set successfully")))
 }
 
+/// checks if the sim card and the tracker belong to the same organization, both are
+/// always queried by the request organization, but this invariant is also enforced
+/// by the `sim_card_vehicle_tracker_same_org_foreign` constraint and is checked
+/// explicitly so a relaxed query never associates entities of different organizations
+fn same_organization(sim_card: &sim_card::Model, tracker: &vehicle_tracker::Model) -> bool {
+    sim_card.organization_id == tracker.organization_id
+}
+
+/// checks if installing `additional` SIM cards on a tracker that already
+/// has `installed` SIM cards exceeds the SIM slots of the tracker model
+fn overflows_sim_card_slots(
+    tracker: &vehicle_tracker::Model,
+    installed: i64,
+    additional: i64,
+) -> bool {
+    installed + additional > tracker.model.clone().get_info().sim_card_slots.into()
+}
+
+/// validates a pair of a SIM card assignment, where `already_assigned` tells if the
+/// SIM card was assigned by a previous pair and `installed` is the amount of SIM cards
+/// on the tracker, returning if the SIM card needs to be updated to the tracker
+fn validate_assignment(
+    sim_card: Option<&sim_card::Model>,
+    tracker: Option<&vehicle_tracker::Model>,
+    already_assigned: bool,
+    installed: i64,
+) -> Result<bool, &'static str> {
+    match (sim_card, tracker) {
+        (None, _) => Err("sim card not found"),
+        (_, None) => Err("tracker not found"),
+        (Some(_), Some(_)) if already_assigned => {
+            Err("sim card already assigned by a previous pair")
+        }
+        (Some(sim_card), Some(tracker)) if !same_organization(sim_card, tracker) => {
+            Err(SIM_TRACKER_ORG_MISMATCH_ERR)
+        }
+        (Some(sim_card), Some(tracker)) if sim_card.vehicle_tracker_id == Some(tracker.id) => {
+            Ok(false)
+        }
+        (Some(_), Some(tracker)) if overflows_sim_card_slots(tracker, installed, 1) => {
+            Err(SIM_SLOTS_OVERFLOW_ERR)
+        }
+        (Some(_), Some(_)) => Ok(true),
+    }
+}
+
+/// Assigns many SIM cards to trackers
+///
+/// Every pair is validated in order, taking into account the SIM cards
+/// already installed on the trackers and the ones assigned by previous
+/// pairs, the valid pairs are applied on a single transaction.
+///
+/// Required permissions: UPDATE_TRACKER
+#[utoipa::path(
+    post,
+    tag = "sim-card",
+    path = "/sim-card/bulk-assign",
+    security(("session_id" = [])),
+    request_body = BulkAssignSimCardsDto,
+    responses(
+        (
+            status = OK,
+            description = "the result of every assignment, in the same order of the request",
+            content_type = "application/json",
+            body = Vec<SimCardAssignmentResultDto>,
+        ),
+    ),
+)]
+pub async fn bulk_assign_sim_cards(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<dto::BulkAssignSimCardsDto>,
+) -> Result<Json<Vec<dto::SimCardAssignmentResultDto>>, (StatusCode, SimpleError)> {
+    let sim_card_ids: Vec<i32> = dto.assignments.iter().map(|a| a.sim_card_id).collect();
+    let tracker_ids: Vec<i32> = dto
+        .assignments
+        .iter()
+        .map(|a| a.vehicle_tracker_id)
+        .collect();
+
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    // the trackers are locked so concurrent assignments to them wait for this
+    // transaction, otherwise both could pass the SIM slots check, rows are
+    // locked in the order of their ids so concurrent requests do not deadlock
+    let trackers: HashMap<i32, vehicle_tracker::Model> = vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::Id.is_in(tracker_ids.clone()))
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .order_by_asc(vehicle_tracker::Column::Id)
+        .lock_exclusive()
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|t| (t.id, t))
+        .collect();
+
+    let sim_cards: HashMap<i32, sim_card::Model> = sim_card::Entity::find()
+        .filter(sim_card::Column::Id.is_in(sim_card_ids))
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .order_by_asc(sim_card::Column::Id)
+        .lock_exclusive()
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|s| (s.id, s))
+        .collect();
+
+    let installed_cnt_rows: Vec<(Option<i32>, i64)> = sim_card::Entity::find()
+        .select_only()
+        .column(sim_card::Column::VehicleTrackerId)
+        .column_as(sim_card::Column::Id.count(), "count")
+        .filter(sim_card::Column::VehicleTrackerId.is_in(tracker_ids))
+        .group_by(sim_card::Column::VehicleTrackerId)
+        .into_tuple()
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    // amount of SIM cards on each tracker, updated as the pairs are validated
+    // so the proposed assignments are counted and not only the existing ones
+    let mut installed_cnt: HashMap<i32, i64> = installed_cnt_rows
+        .into_iter()
+        .filter_map(|(tracker_id, cnt)| tracker_id.map(|id| (id, cnt)))
+        .collect();
+
+    let mut assigned_sim_cards: HashSet<i32> = HashSet::new();
+    let mut results: Vec<dto::SimCardAssignmentResultDto> = Vec::new();
+    let mut to_update: Vec<(i32, i32)> = Vec::new();
+
+    for assignment in dto.assignments {
+        let sim_card_id = assignment.sim_card_id;
+        let tracker_id = assignment.vehicle_tracker_id;
+
+        let validation = validate_assignment(
+            sim_cards.get(&sim_card_id),
+            trackers.get(&tracker_id),
+            assigned_sim_cards.contains(&sim_card_id),
+            installed_cnt.get(&tracker_id).copied().unwrap_or(0),
+        );
+
+        if let Ok(needs_update) = validation {
+            assigned_sim_cards.insert(sim_card_id);
+
+            if needs_update {
+                *installed_cnt.entry(tracker_id).or_insert(0) += 1;
+
+                // moving the SIM card frees a slot on its previous tracker
+                if let Some(old_tracker_id) = sim_cards[&sim_card_id].vehicle_tracker_id {
+                    if let Some(cnt) = installed_cnt.get_mut(&old_tracker_id) {
+                        *cnt -= 1;
+                    }
+                }
+
+                to_update.push((sim_card_id, tracker_id));
+            }
+        }
+
+        results.push(dto::SimCardAssignmentResultDto {
+            sim_card_id,
+            vehicle_tracker_id: tracker_id,
+            success: validation.is_ok(),
+            error: validation.err().map(String::from),
+        });
+    }
+
+    for (sim_card_id, tracker_id) in to_update {
+        sim_card::Entity::update_many()
+            .col_expr(sim_card::Column::VehicleTrackerId, Expr::value(tracker_id))
+            .filter(sim_card::Column::Id.eq(sim_card_id))
+            .filter(sim_card::Column::OrganizationId.eq(org_id))
+            .exec(&txn)
+            .await
+            .map_err(DbError::from)?;
+    }
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    Ok(Json(results))
+}
+
+/// Updates the APN of many SIM cards
+///
+/// Sets the APN settings of the request user organization SIM cards matching every filter,
+/// eg: the SIM cards still using the APN address a carrier replaced.
+///
+/// Required permissions: UPDATE_SIM_CARD
+#[utoipa::path(
+    post,
+    tag = "sim-card",
+    path = "/sim-card/bulk-update-apn",
+    security(("session_id" = [])),
+    request_body = BulkUpdateApnDto,
+    responses(
+        (
+            status = OK,
+            description = "the amount of updated SIM cards",
+            content_type = "application/json",
+            body = BulkUpdateApnResultDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn bulk_update_sim_card_apn(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<dto::BulkUpdateApnDto>,
+) -> Result<Json<dto::BulkUpdateApnResultDto>, (StatusCode, SimpleError)> {
+    // without filters every SIM card of the organization would be updated
+    if dto.ids.is_none() && dto.current_apn_address.is_none() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("ids or currentApnAddress is required"),
+        ));
+    }
+
+    // a single statement, so either every matching SIM card is updated or none is
+    let result = sim_card::Entity::update_many()
+        .col_expr(sim_card::Column::ApnAddress, Expr::value(dto.apn_address))
+        .col_expr(sim_card::Column::ApnUser, Expr::value(dto.apn_user))
+        .col_expr(sim_card::Column::ApnPassword, Expr::value(dto.apn_password))
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .apply_if(dto.ids, |query, ids| {
+            query.filter(sim_card::Column::Id.is_in(ids))
+        })
+        .apply_if(dto.current_apn_address, |query, apn_address| {
+            query.filter(sim_card::Column::ApnAddress.eq(apn_address))
+        })
+        .exec(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(dto::BulkUpdateApnResultDto {
+        updated: result.rows_affected,
+    }))
+}
+
+/// Normalizes the phone numbers of the existing SIM cards
+///
+/// Only accessible to superusers, converts the phone numbers of the SIM cards of every
+/// organization that are not in E.164 format to it, for the SIM cards created before
+/// phone numbers were normalized.
+///
+/// phone numbers that are not valid, or whose normalized form is already used by another
+/// SIM card of the organization, are kept as they are and returned to be fixed manually.
+///
+/// the SIM cards of each organization are normalized on a transaction, on dry runs the
+/// transactions are rolled back, returning what would be normalized
+#[utoipa::path(
+    post,
+    tag = "sim-card",
+    path = "/sim-card/normalize-phone-numbers",
+    security(("session_id" = [])),
+    params(DryRun),
+    responses(
+        (
+            status = OK,
+            description = "the normalized, invalid and conflicting SIM cards",
+            content_type = "application/json",
+            body = NormalizePhoneNumbersResultDto,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn normalize_sim_card_phone_numbers(
+    _: SuperUser,
+    DbConnection(db): DbConnection,
+    ValidatedQuery(query): ValidatedQuery<DryRun>,
+) -> Result<Json<dto::NormalizePhoneNumbersResultDto>, (StatusCode, SimpleError)> {
+    let sim_cards: Vec<(i32, i32, String)> = sim_card::Entity::find()
+        .select_only()
+        .columns([
+            sim_card::Column::Id,
+            sim_card::Column::OrganizationId,
+            sim_card::Column::PhoneNumber,
+        ])
+        .filter(Expr::cust(
+            r#""sim_card"."phone_number" !~ '^\+[1-9][0-9]{1,14}$'"#,
+        ))
+        .order_by_asc(sim_card::Column::Id)
+        .into_tuple()
+        .all(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    let mut sim_cards_by_org: BTreeMap<i32, Vec<(i32, String)>> = BTreeMap::new();
+
+    for (id, org_id, phone_number) in sim_cards {
+        sim_cards_by_org
+            .entry(org_id)
+            .or_default()
+            .push((id, phone_number));
+    }
+
+    let mut result = dto::NormalizePhoneNumbersResultDto::default();
+
+    for (org_id, sim_cards) in sim_cards_by_org {
+        let txn = db.begin().await.map_err(DbError::from)?;
+
+        normalize_org_phone_numbers(&txn, org_id, sim_cards, &mut result)
+            .await
+            .map_err(DbError::from)?;
+
+        if query.dry_run {
+            txn.rollback().await.map_err(DbError::from)?;
+        } else {
+            txn.commit().await.map_err(DbError::from)?;
+        }
+    }
+
+    Ok(Json(result))
+}
+
+/// normalizes the phone numbers of SIM cards of the organization, adding their ids to `result`
+async fn normalize_org_phone_numbers<C: ConnectionTrait>(
+    db: &C,
+    org_id: i32,
+    sim_cards: Vec<(i32, String)>,
+    result: &mut dto::NormalizePhoneNumbersResultDto,
+) -> Result<(), DbErr> {
+    let mut normalized_sim_cards: Vec<(i32, String)> = Vec::new();
+
+    for (id, phone_number) in sim_cards {
+        match normalize_phone_number(&phone_number) {
+            Some(normalized) => normalized_sim_cards.push((id, normalized)),
+            None => result.invalid.push(id),
+        }
+    }
+
+    // normalized phone numbers already used by SIM cards of the organization, the
+    // ones of the SIM cards normalized below are added as they are normalized
+    let mut taken: HashSet<String> = sim_card::Entity::find()
+        .select_only()
+        .column(sim_card::Column::PhoneNumber)
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .filter(
+            sim_card::Column::PhoneNumber.is_in(
+                normalized_sim_cards
+                    .iter()
+                    .map(|(_, normalized)| normalized),
+            ),
+        )
+        .into_tuple::<String>()
+        .all(db)
+        .await?
+        .into_iter()
+        .collect();
+
+    for (id, normalized) in normalized_sim_cards {
+        if taken.contains(&normalized) {
+            result.conflicting.push(id);
+            continue;
+        }
+
+        sim_card::Entity::update_many()
+            .col_expr(
+                sim_card::Column::PhoneNumber,
+                Expr::value(normalized.as_str()),
+            )
+            .filter(sim_card::Column::Id.eq(id))
+            .exec(db)
+            .await?;
+
+        taken.insert(normalized);
+        result.normalized.push(id);
+    }
+
+    Ok(())
+}
+
 /// Deletes a SIM card
 ///
 /// Required permissions: DELETE_SIM_CARD
@@ -294,6 +769,11 @@ pub async fn set_sim_card_tracker(
             content_type = "application/json",
             example = json!("SIM card deleted successfully"),
         ),
+        (
+            status = NOT_FOUND,
+            description = "SIM card not found",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn delete_sim_card(
@@ -301,22 +781,94 @@ pub async fn delete_sim_card(
     OrganizationId(org_id): OrganizationId,
     DbConnection(db): DbConnection,
 ) -> Result<Json<String>, (StatusCode, SimpleError)> {
+    let deleted = delete_org_sim_card(&db, sim_card_id, org_id)
+        .await
+        .map_err(DbError::from)?;
+
+    if deleted {
+        Ok(Json(String::from("sim card deleted successfully")))
+    } else {
+        Err((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))
+    }
+}
+
+/// deletes the SIM card of the organization, returns `false` if the
+/// organization has no SIM card with the id
+async fn delete_org_sim_card<C: ConnectionTrait>(
+    db: &C,
+    sim_card_id: i32,
+    org_id: i32,
+) -> Result<bool, DbErr> {
     let delete_result = sim_card::Entity::delete_many()
         .filter(sim_card::Column::Id.eq(sim_card_id))
         .filter(sim_card::Column::OrganizationId.eq(org_id))
-        .exec(&db)
+        .exec(db)
+        .await?;
+
+    Ok(delete_result.rows_affected > 0)
+}
+
+/// Deletes many SIM cards
+///
+/// Required permissions: DELETE_SIM_CARD
+///
+/// SIM cards that do not exist or do not belong to the request user
+/// organization are ignored, all the others are deleted on a single transaction
+///
+/// on dry runs the transaction is rolled back, returning the SIM cards that would be deleted
+#[utoipa::path(
+    post,
+    tag = "sim-card",
+    path = "/sim-card/bulk-delete",
+    security(("session_id" = [])),
+    params(DryRun),
+    request_body = BulkDeleteDto,
+    responses(
+        (
+            status = OK,
+            description = "the deleted and not found SIM card ids",
+            body = BulkDeleteResultDto,
+            content_type = "application/json",
+        ),
+    ),
+)]
+pub async fn bulk_delete_sim_cards(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedQuery(query): ValidatedQuery<DryRun>,
+    ValidatedJson(dto): ValidatedJson<BulkDeleteDto>,
+) -> Result<Json<BulkDeleteResultDto>, (StatusCode, SimpleError)> {
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    let sim_card_ids: Vec<i32> = sim_card::Entity::find()
+        .select_only()
+        .column(sim_card::Column::Id)
+        .filter(sim_card::Column::Id.is_in(dto.ids.clone()))
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .into_tuple()
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    sim_card::Entity::delete_many()
+        .filter(sim_card::Column::Id.is_in(sim_card_ids.clone()))
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .exec(&txn)
         .await
         .map_err(DbError::from)?;
 
-    if delete_result.rows_affected < 1 {
-        let err_msg = "SIM card does not exist or does not belong to the request user organization";
-        Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)))
+    if query.dry_run {
+        txn.rollback().await.map_err(DbError::from)?;
     } else {
-        Ok(Json(String::from("sim card deleted successfully")))
+        txn.commit().await.map_err(DbError::from)?;
     }
+
+    Ok(Json(BulkDeleteResultDto::new(&dto.ids, sim_card_ids)))
 }
 
 /// Get a SIM card by ID
+///
+/// the PIN and PUK codes are masked unless the user has the VIEW_SIM_SECRETS permission
 #[utoipa::path(
     get,
     tag = "sim-card",
@@ -336,11 +888,54 @@ pub async fn delete_sim_card(
 )]
 pub async fn get_sim_card(
     OrgBoundEntityFromPathId(sim_card): OrgBoundEntityFromPathId<sim_card::Entity>,
+    Extension(req_user): Extension<RequestUser>,
 ) -> Result<Json<sim_card::Model>, (StatusCode, SimpleError)> {
-    Ok(Json(sim_card))
+    Ok(Json(sim_card_for_user(sim_card, &req_user)))
+}
+
+/// query of the SIM cards of the organization matching the filter,
+/// shared by the list and count endpoints so their results are consistent
+fn filtered_sim_cards(org_id: i32, filter: ListSimCardsDto) -> Select<sim_card::Entity> {
+    sim_card::Entity::find()
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .apply_if(filter.with_associated_tracker, |query, with_vehicle| {
+            if with_vehicle {
+                query.filter(sim_card::Column::VehicleTrackerId.is_not_null())
+            } else {
+                query.filter(sim_card::Column::VehicleTrackerId.is_null())
+            }
+        })
+        .apply_if(filter.approaching_quota_percent, |query, percent| {
+            let current_period = first_day_of_month(Utc::now().date_naive());
+
+            query.filter(Expr::cust_with_values(
+                r#"EXISTS (
+                    SELECT 1 FROM "sim_card_data_usage" AS u
+                    WHERE u."sim_card_id" = "sim_card"."id" AND u."period" = ?
+                    AND u."data_usage_mb" >= "sim_card"."data_quota_mb" * ? / 100.0
+                )"#,
+                [
+                    sea_orm::Value::from(current_period),
+                    sea_orm::Value::from(f64::from(percent)),
+                ],
+            ))
+        })
+        .apply_if(filter.phone_number, |query, phone| {
+            // compares only the digits, so the search matches partial national numbers of
+            // normalized phone numbers and of the ones stored before they were normalized,
+            // the search is validated to have digits so it always filters
+            let digits: String = phone.chars().filter(char::is_ascii_digit).collect();
+
+            query.filter(Expr::cust_with_values(
+                r#"regexp_replace("sim_card"."phone_number", '[^0-9]', '', 'g') LIKE ?"#,
+                [format!("%{}%", digits)],
+            ))
+        })
 }
 
 /// Lists the SIM cards that belong to the same org as the request user
+///
+/// the PIN and PUK codes are masked unless the user has the VIEW_SIM_SECRETS permission
 #[utoipa::path(
     get,
     tag = "sim-card",
@@ -363,31 +958,238 @@ pub async fn list_sim_cards(
     ValidatedQuery(pagination): ValidatedQuery<Pagination>,
     ValidatedQuery(filter): ValidatedQuery<ListSimCardsDto>,
     OrganizationId(org_id): OrganizationId,
+    Extension(req_user): Extension<RequestUser>,
     DbConnection(db): DbConnection,
 ) -> Result<Json<PaginationResult<sim_card::Model>>, (StatusCode, SimpleError)> {
-    let db_query = sim_card::Entity::find()
-        .filter(sim_card::Column::OrganizationId.eq(org_id))
-        .apply_if(filter.with_associated_tracker, |query, with_vehicle| {
-            if with_vehicle {
-                query.filter(sim_card::Column::VehicleTrackerId.is_not_null())
-            } else {
-                query.filter(sim_card::Column::VehicleTrackerId.is_null())
-            }
-        })
-        .apply_if(filter.phone_number, |query, phone| {
-            if !phone.is_empty() {
-                let col = Expr::col((sim_card::Entity, sim_card::Column::PhoneNumber));
-                query.filter(col.ilike(format!("%{}%", phone)))
-            } else {
-                query
-            }
-        })
+    let db_query = filtered_sim_cards(org_id, filter)
         .order_by_asc(sim_card::Column::Id)
         .paginate(&db, pagination.page_size);
 
-    let result = database::helpers::paginated_query_to_pagination_result(db_query, pagination)
+    let result =
+        database::helpers::paginated_query_to_pagination_result(db_query, pagination).await?;
+
+    Ok(Json(PaginationResult {
+        records: result
+            .records
+            .into_iter()
+            .map(|card| sim_card_for_user(card, &req_user))
+            .collect(),
+        ..result
+    }))
+}
+
+/// Counts the SIM cards that belong to the same org as the request user
+///
+/// accepts the same filters as the SIM card list, without fetching the SIM cards
+#[utoipa::path(
+    get,
+    tag = "sim-card",
+    path = "/sim-card/count",
+    security(("session_id" = [])),
+    params(ListSimCardsDto),
+    responses(
+        (
+            status = OK,
+            description = "amount of SIM cards matching the filter",
+            content_type = "application/json",
+            body = CountDto,
+        ),
+    ),
+)]
+pub async fn count_sim_cards(
+    ValidatedQuery(filter): ValidatedQuery<ListSimCardsDto>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<CountDto>, (StatusCode, SimpleError)> {
+    let count = filtered_sim_cards(org_id, filter)
+        .count(&db)
         .await
         .map_err(DbError::from)?;
 
-    Ok(Json(result))
+    Ok(Json(CountDto { count }))
+}
+
+/// normalizes a date to the first day of its month, that is the
+/// period of the SIM card data usage the date belongs to
+fn first_day_of_month(date: NaiveDate) -> NaiveDate {
+    date.with_day(1).unwrap_or(date)
+}
+
+/// Records the data usage of a SIM card
+///
+/// Required permissions: UPDATE_SIM_CARD
+///
+/// The usage is recorded per month, recording the usage of a month that was already
+/// recorded replaces it, so recording the same value multiple times has no effect.
+#[utoipa::path(
+    put,
+    tag = "sim-card",
+    path = "/sim-card/{sim_card_id}/data-usage",
+    security(("session_id" = [])),
+    params(
+        ("sim_card_id" = u128, Path, description = "id of the SIM card"),
+    ),
+    request_body = RecordSimCardDataUsageDto,
+    responses(
+        (
+            status = OK,
+            description = "the recorded data usage",
+            content_type = "application/json",
+            body = entity::sim_card_data_usage::Model,
+        ),
+    ),
+)]
+pub async fn record_sim_card_data_usage(
+    DbConnection(db): DbConnection,
+    OrgBoundEntityFromPathId(sim_card): OrgBoundEntityFromPathId<sim_card::Entity>,
+    ValidatedJson(dto): ValidatedJson<RecordSimCardDataUsageDto>,
+) -> Result<Json<sim_card_data_usage::Model>, (StatusCode, SimpleError)> {
+    let usage = sim_card_data_usage::Model {
+        sim_card_id: sim_card.id,
+        period: first_day_of_month(dto.period),
+        data_usage_mb: dto.data_usage_mb,
+        updated_at: Utc::now(),
+    };
+
+    sim_card_data_usage::Entity::insert(sim_card_data_usage::ActiveModel::from(usage.clone()))
+        .on_conflict(
+            OnConflict::columns([
+                sim_card_data_usage::Column::SimCardId,
+                sim_card_data_usage::Column::Period,
+            ])
+            .update_columns([
+                sim_card_data_usage::Column::DataUsageMb,
+                sim_card_data_usage::Column::UpdatedAt,
+            ])
+            .to_owned(),
+        )
+        .exec(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(usage))
+}
+
+/// Lists the data usage of a SIM card
+///
+/// Returns the monthly data usage records of the SIM card, newest first
+#[utoipa::path(
+    get,
+    tag = "sim-card",
+    path = "/sim-card/{sim_card_id}/data-usage",
+    security(("session_id" = [])),
+    params(
+        ("sim_card_id" = u128, Path, description = "id of the SIM card"),
+        ListSimCardDataUsageDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the SIM card monthly data usage",
+            content_type = "application/json",
+            body = Vec<entity::sim_card_data_usage::Model>,
+        ),
+    ),
+)]
+pub async fn list_sim_card_data_usage(
+    DbConnection(db): DbConnection,
+    OrgBoundEntityFromPathId(sim_card): OrgBoundEntityFromPathId<sim_card::Entity>,
+    ValidatedQuery(filter): ValidatedQuery<ListSimCardDataUsageDto>,
+) -> Result<Json<Vec<sim_card_data_usage::Model>>, (StatusCode, SimpleError)> {
+    let usage = sim_card_data_usage::Entity::find()
+        .filter(sim_card_data_usage::Column::SimCardId.eq(sim_card.id))
+        .apply_if(filter.from, |query, from| {
+            query.filter(sim_card_data_usage::Column::Period.gte(first_day_of_month(from)))
+        })
+        .apply_if(filter.to, |query, to| {
+            query.filter(sim_card_data_usage::Column::Period.lte(first_day_of_month(to)))
+        })
+        .order_by_desc(sim_card_data_usage::Column::Period)
+        .all(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(usage))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+    use crate::modules::common::fixtures::{sim_card, tracker};
+    use migration::seeder;
+
+    #[test]
+    fn refuses_assigning_sim_cards_to_trackers_of_another_organization() {
+        let sim_card = sim_card(1);
+
+        assert!(!same_organization(&sim_card, &tracker(2)));
+        assert_eq!(
+            validate_assignment(Some(&sim_card), Some(&tracker(2)), false, 0),
+            Err(SIM_TRACKER_ORG_MISMATCH_ERR)
+        );
+        assert_eq!(
+            validate_assignment(Some(&sim_card), Some(&tracker(1)), false, 0),
+            Ok(true)
+        );
+    }
+
+    #[test]
+    fn validates_assignments_in_order() {
+        let tracker = tracker(1);
+
+        assert_eq!(
+            validate_assignment(
+                Some(&sim_card::Model {
+                    vehicle_tracker_id: Some(tracker.id),
+                    ..sim_card(1)
+                }),
+                Some(&tracker),
+                false,
+                1
+            ),
+            Ok(false)
+        );
+        assert_eq!(
+            validate_assignment(Some(&sim_card(1)), Some(&tracker), true, 0),
+            Err("sim card already assigned by a previous pair")
+        );
+        assert_eq!(
+            validate_assignment(Some(&sim_card(1)), Some(&tracker), false, 1),
+            Err(SIM_SLOTS_OVERFLOW_ERR)
+        );
+        assert_eq!(
+            validate_assignment(None, Some(&tracker), false, 0),
+            Err("sim card not found")
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database with PostGIS and TimescaleDB, see database::test_db"]
+    async fn sim_cards_of_other_organizations_are_not_deleted() {
+        let txn = test_db::begin().await;
+
+        let org_id = seeder::gen_organization(&txn).await.unwrap();
+        let other_org_id = seeder::gen_organization(&txn).await.unwrap();
+        let sim_card_id = seeder::gen_sim_card(&txn, org_id, None).await.unwrap();
+
+        let deleted = delete_org_sim_card(&txn, sim_card_id, other_org_id)
+            .await
+            .unwrap();
+
+        assert!(!deleted);
+
+        let sim_card = sim_card::Entity::find_by_id(sim_card_id)
+            .one(&txn)
+            .await
+            .unwrap();
+
+        assert!(sim_card.is_some());
+
+        let deleted = delete_org_sim_card(&txn, sim_card_id, org_id)
+            .await
+            .unwrap();
+
+        assert!(deleted);
+    }
 }
@@ -1,16 +1,36 @@
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use shared::constants::TrackerModel;
+use shared::entity::{sim_card, vehicle, vehicle_tracker};
 use utoipa::{IntoParams, ToSchema};
 use validator::{Validate, ValidationError};
 
-use crate::modules::common::dto::AscOrDescOrder;
+use crate::modules::{
+    common::{dto::AscOrDescOrder, validators::is_valid_tracker_imei},
+    tracking::dto::PositionDto,
+};
 
 fn is_supported_tracker_model(model: &str) -> Result<(), ValidationError> {
     let allowed_models = TrackerModel::to_string_vec();
 
     if !allowed_models.contains(&String::from(model)) {
-        return Err(ValidationError::new("model not allowed"));
+        let mut err = ValidationError::new("model not allowed");
+        err.message = Some(format!("model must be one of: {}", allowed_models.join(", ")).into());
+
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+fn is_tracker_imei(imei: &str) -> Result<(), ValidationError> {
+    if !is_valid_tracker_imei(imei) {
+        let mut err = ValidationError::new("invalid IMEI");
+        err.message = Some(
+            "IMEI must have 1 to 20 digits, 15 digit IMEIs must have a valid check digit".into(),
+        );
+
+        return Err(err);
     }
 
     Ok(())
@@ -22,20 +42,62 @@ pub struct CreateTrackerDto {
     #[validate(custom = "is_supported_tracker_model")]
     pub model: String,
 
-    #[validate(length(min = 1))]
+    /// the tracker IMEI or numeric serial number, at most 20 digits, 15 digit
+    /// IMEIs must have a valid Luhn check digit
+    #[validate(custom = "is_tracker_imei")]
     pub imei: String,
 
     /// ID of the vehicle to associate with the tracker
     #[validate(range(min = 1))]
     pub vehicle_id: Option<i32>,
+
+    #[validate(length(min = 1, max = 255))]
+    pub firmware_version: Option<String>,
+
+    #[validate(length(max = 2000))]
+    pub notes: Option<String>,
+
+    pub installed_at: Option<DateTime<Utc>>,
+
+    /// seconds between the positions the tracker is configured to send, at most a week,
+    /// if not set the API default is assumed
+    #[validate(range(min = 1, max = 604800))]
+    pub reporting_interval_seconds: Option<i32>,
+
+    /// If the locations held while the IMEI was not registered should be added to the
+    /// tracker location history, they are discarded otherwise
+    #[serde(default)]
+    pub backfill_unknown_imei_locations: bool,
 }
 
 #[derive(Deserialize, ToSchema, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateTrackerDto {
+    /// the tracker IMEI or numeric serial number, at most 20 digits, 15 digit
+    /// IMEIs must have a valid Luhn check digit
+    #[validate(custom = "is_tracker_imei")]
     pub imei: Option<String>,
 
     pub model: Option<TrackerModel>,
+
+    /// `null` clears the firmware version
+    #[validate(length(min = 1, max = 255))]
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub firmware_version: Option<Option<String>>,
+
+    /// `null` clears the notes
+    #[validate(length(max = 2000))]
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub notes: Option<Option<String>>,
+
+    /// `null` clears the installation date
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub installed_at: Option<Option<DateTime<Utc>>>,
+
+    /// `null` resets the reporting interval to the API default
+    #[validate(range(min = 1, max = 604800))]
+    #[serde(default, with = "::serde_with::rust::double_option")]
+    pub reporting_interval_seconds: Option<Option<i32>>,
 }
 
 #[derive(Deserialize, IntoParams, Validate)]
@@ -48,6 +110,9 @@ pub struct ListTrackersDto {
     /// If the trackers should be filtered if they are associated
     /// to a vehicle or not, `None` means `any`
     pub with_associated_vehicle: Option<bool>,
+
+    /// Filter trackers by their exact firmware version
+    pub firmware_version: Option<String>,
 }
 
 #[derive(Deserialize, ToSchema, Validate)]
@@ -72,6 +137,17 @@ pub struct DeleteTrackerDto {
     pub delete_associated_sim_cards: Option<bool>,
 }
 
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkDeleteTrackersDto {
+    /// IDs of the trackers to delete
+    #[validate(length(min = 1, max = 100))]
+    pub ids: Vec<i32>,
+
+    /// If the sim cards associated with the trackers to be deleted, should be deleted aswell
+    pub delete_associated_sim_cards: Option<bool>,
+}
+
 #[derive(Deserialize, ToSchema, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct GetTrackerPositionsDto {
@@ -82,32 +158,157 @@ pub struct GetTrackerPositionsDto {
     pub before: Option<DateTime<Utc>>,
 
     #[validate(range(min = 1, max = 100))]
-    /// Limit the number of positions to be queried
+    /// Limit the number of positions to be queried, defaults to 15 and cannot exceed 100
     pub limit: Option<u64>,
 
     #[serde(default)]
     pub order: AscOrDescOrder,
+
+    /// The `nextCursor` of the previous page, to continue listing from where it stopped
+    pub cursor: Option<DateTime<Utc>>,
 }
 
+/// A page of tracker positions
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
-pub struct TrackerLocationDto {
-    pub time: DateTime<Utc>,
+pub struct TrackerPositionsPageDto {
+    pub positions: Vec<PositionDto>,
 
-    pub point: Point,
+    /// cursor to fetch the next page with, `null` if there are no more positions
+    pub next_cursor: Option<DateTime<Utc>>,
 }
 
+/// A tracker with its vehicle and SIM cards
 #[derive(Serialize, ToSchema)]
-pub struct Point {
-    pub x: f64,
-    pub y: f64,
+#[serde(rename_all = "camelCase")]
+pub struct TrackerDetailsDto {
+    pub tracker: vehicle_tracker::Model,
+
+    /// vehicle the tracker is installed on, `null` if the tracker is not installed
+    pub vehicle: Option<vehicle::Model>,
+
+    pub sim_cards: Vec<sim_card::Model>,
+
+    /// time of the last position of the tracker, `null` if it never sent one
+    pub last_seen_at: Option<DateTime<Utc>>,
+
+    /// if the tracker sent a position within the amount of reporting intervals
+    /// it can miss before it is considered offline
+    pub online: bool,
 }
 
-impl From<geo_types::Point<f64>> for Point {
-    fn from(value: geo_types::Point<f64>) -> Self {
-        Self {
-            x: value.x(),
-            y: value.y(),
-        }
-    }
+/// Query of trackers without recent positions
+#[derive(Deserialize, IntoParams, Validate)]
+#[into_params(parameter_in = Query)]
+#[serde(rename_all = "camelCase")]
+pub struct ListStaleTrackersDto {
+    /// trackers whose last position is older than this many minutes (or that never
+    /// sent a position) are stale, at most a year, if not set trackers are stale once
+    /// they are offline, according to their reporting interval
+    #[serde(alias = "threshold_minutes")]
+    #[validate(range(min = 1, max = 525600))]
+    pub threshold_minutes: Option<i64>,
+}
+
+/// A tracker that did not send positions recently
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StaleTrackerDto {
+    pub tracker: vehicle_tracker::Model,
+
+    /// time of the last position of the tracker, `null` if it never sent one
+    pub last_seen_at: Option<DateTime<Utc>>,
+
+    /// seconds since the last position of the tracker, `null` if it never sent one
+    pub stale_for_seconds: Option<i64>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeTrackerHistoryDto {
+    /// ID of the tracker whose location history is moved to the path tracker
+    #[validate(range(min = 1))]
+    pub source_tracker_id: i32,
+
+    /// If the source tracker should be deleted once its history is moved
+    #[serde(default)]
+    pub delete_source: bool,
+}
+
+/// Result of merging the location history of a tracker into another
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MergeTrackerHistoryResultDto {
+    /// locations moved from the source tracker to the target tracker
+    pub moved_locations: u64,
+
+    /// locations of the source tracker discarded because the target tracker
+    /// already had a location at the same time
+    pub discarded_locations: u64,
+
+    pub source_deleted: bool,
+}
+
+/// IMEIs to check before creating their trackers
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct CheckImeisDto {
+    #[validate(length(min = 1, max = 100))]
+    pub imeis: Vec<String>,
+}
+
+/// If a tracker can be created with a IMEI
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImeiAvailabilityDto {
+    pub imei: String,
+
+    /// if the IMEI is well formed, 15 digit IMEIs must have a valid Luhn check digit
+    pub valid: bool,
+
+    /// if no tracker of the organization has the IMEI
+    pub available: bool,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct LoanTrackerDto {
+    /// ID of the organization the tracker is loaned to
+    #[validate(range(min = 1))]
+    pub organization_id: i32,
+
+    /// when the loan ends, at most 90 days from now
+    pub expires_at: DateTime<Utc>,
+}
+
+/// A tracker event received from the decoder, streamed as is for debugging
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TrackerLiveFeedEventDto {
+    /// routing key of the event on the tracker events exchange, `{protocol}.{event_type}.{imei}`
+    pub routing_key: String,
+
+    pub protocol: String,
+
+    pub event_type: String,
+
+    /// when the API received the event
+    pub received_at: DateTime<Utc>,
+
+    /// size of the message body in bytes
+    pub size_bytes: usize,
+
+    /// the decoded event, `null` if the message body is not valid JSON
+    #[schema(value_type = Option<Object>)]
+    pub data: Option<serde_json::Value>,
+
+    /// the message body, only set when it is not valid JSON
+    pub raw: Option<String>,
+
+    /// why the API would fail to parse the event, `null` if it was parsed
+    /// or the protocol and event type are not supported by the API
+    pub parse_error: Option<String>,
+
+    /// events dropped before this one because the client was not keeping up
+    pub dropped_events: u64,
 }
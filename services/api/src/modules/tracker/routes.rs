@@ -1,48 +1,71 @@
 use super::dto::{
-    self, CreateTrackerDto, DeleteTrackerDto, GetTrackerPositionsDto, ListTrackersDto,
-    UpdateTrackerDto,
+    self, BulkDeleteTrackersDto, CheckImeisDto, CreateTrackerDto, DeleteTrackerDto,
+    GetTrackerPositionsDto, ImeiAvailabilityDto, ListStaleTrackersDto, ListTrackersDto,
+    LoanTrackerDto, MergeTrackerHistoryDto, MergeTrackerHistoryResultDto, StaleTrackerDto,
+    TrackerDetailsDto, TrackerLiveFeedEventDto, TrackerPositionsPageDto, UpdateTrackerDto,
 };
 use crate::{
+    config::app_config,
     database::{self, error::DbError, helpers::set_if_some},
     modules::{
-        auth::{self, middleware::AclLayer},
+        auth::{
+            self,
+            middleware::{AclLayer, RequestUser},
+        },
         common::{
-            dto::{Pagination, PaginationResult},
+            dto::{
+                AscOrDescOrder, BulkDeleteResultDto, CountDto, DryRun, Pagination, PaginationResult,
+            },
             extractors::{
-                DbConnection, OrgBoundEntityFromPathId, OrganizationId, ValidatedJson,
-                ValidatedQuery,
+                DbConnection, OrgBoundEntityFromPathId, OrgReadableEntityFromPathId,
+                OrganizationId, SuperUser, ValidatedJson, ValidatedQuery,
             },
             responses::{internal_error_res, SimpleError},
+            validators::{is_valid_tracker_imei, REGEX_IS_TRACKER_IMEI},
         },
         globals::TRACKER_ID_CACHE,
+        organization::limits::{self, PlanLimit},
+        sim_card::dto::sim_card_for_user,
+        tracking::{
+            dto::PositionDto,
+            utils::{log_unexpected_geometry, StoredLocation},
+        },
     },
     server::controller::AppState,
+    services::outbox::OutboxMessage,
 };
 use axum::{
-    extract::{Path, Query},
+    extract::{Path, Query, State},
+    response::sse::{Event, KeepAlive, Sse},
     routing::{delete, get, post, put},
-    Json, Router,
+    Extension, Json, Router,
 };
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
+use futures_util::Stream;
 use http::StatusCode;
+use lapin::message::Delivery;
 use migration::Expr;
 use sea_orm::sea_query::extension::postgres::PgExpr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
-    QuerySelect, QueryTrait, Set, TryIntoModel,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    FromQueryResult, JoinType, Order, PaginatorTrait, QueryFilter, QueryOrder, QueryResult,
+    QuerySelect, QueryTrait, RelationTrait, Select, Set, Statement, TransactionTrait, TryIntoModel,
 };
 use sea_query::{Cond, PostgresQueryBuilder, Query as SeaQuery};
 use sea_query_binder::SqlxBinder;
 use shared::entity::{
-    sim_card, traits::QueryableByIdAndOrgId, vehicle_tracker, vehicle_tracker_last_location,
-    vehicle_tracker_location,
+    sim_card, traits::QueryableByIdAndOrgId, unknown_imei_location, vehicle_tracker,
+    vehicle_tracker_last_location, vehicle_tracker_location,
 };
 use shared::{
     constants::{Permission, TrackerModel},
-    entity::vehicle,
+    dto::decoder::h02::LocationMsg,
+    entity::{organization, vehicle},
 };
-use std::str::FromStr;
-use tracing::{info, Instrument, Span};
+use std::{collections::HashSet, convert::Infallible, str::FromStr};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio_stream::{wrappers::ReceiverStream, StreamExt};
+use tracing::{error, info, Instrument, Span};
 
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
@@ -52,9 +75,21 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         )
         //
         .route("/", get(list_trackers))
+        .route("/count", get(count_trackers))
+        .route("/stale", get(list_stale_trackers))
+        .route("/borrowed", get(list_borrowed_trackers))
+        //
+        .route(
+            "/check-imeis",
+            post(check_imeis).layer(AclLayer::single(Permission::CreateTracker)),
+        )
         //
         .route("/:tracker_id", get(get_tracker))
         //
+        .route("/by-imei/:imei", get(get_tracker_by_imei))
+        //
+        .route("/:tracker_id/merge-history", post(merge_tracker_history))
+        //
         .route(
             "/:tracker_id",
             put(update_tracker).layer(AclLayer::single(Permission::UpdateTracker)),
@@ -65,14 +100,33 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             delete(delete_tracker).layer(AclLayer::single(Permission::DeleteTracker)),
         )
         //
+        .route(
+            "/bulk-delete",
+            post(bulk_delete_trackers).layer(AclLayer::single(Permission::DeleteTracker)),
+        )
+        //
+        .route(
+            "/:tracker_id/loan",
+            put(loan_tracker)
+                .delete(end_tracker_loan)
+                .layer(AclLayer::single(Permission::UpdateTracker)),
+        )
+        //
         .route(
             "/:tracker_id/vehicle",
             put(set_tracker_vehicle).layer(AclLayer::single(Permission::UpdateTracker)),
         )
         //
         .route("/:tracker_id/get-location-list", post(get_location_list))
+        .route("/:tracker_id/get-location-page", post(get_location_page))
         .route("/:tracker_id/last-location", get(get_tracker_location))
         .route("/:tracker_id/sim-cards", get(list_tracker_sim_cards))
+        .route("/:tracker_id/details", get(get_tracker_details))
+        //
+        .route(
+            "/:tracker_id/live-feed",
+            get(stream_tracker_live_feed).layer(AclLayer::single(Permission::UpdateTracker)),
+        )
         //
         .layer(axum::middleware::from_fn_with_state(
             state,
@@ -90,6 +144,8 @@ async fn delete_tracker_imei_from_cache(imei: String) {
 }
 
 /// Get a tracker by ID
+///
+/// also available for trackers loaned to the request user organization
 #[utoipa::path(
     get,
     tag = "tracker",
@@ -107,11 +163,135 @@ async fn delete_tracker_imei_from_cache(imei: String) {
     ),
 )]
 pub async fn get_tracker(
-    OrgBoundEntityFromPathId(tracker): OrgBoundEntityFromPathId<vehicle_tracker::Entity>,
+    OrgReadableEntityFromPathId(tracker): OrgReadableEntityFromPathId<vehicle_tracker::Entity>,
 ) -> Result<Json<vehicle_tracker::Model>, (StatusCode, SimpleError)> {
     Ok(Json(tracker))
 }
 
+/// Get a tracker by IMEI
+///
+/// finds the tracker with the exact IMEI, eg: when scanning the tracker barcode
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/by-imei/{imei}",
+    security(("session_id" = [])),
+    params(
+        ("imei" = String, Path, description = "IMEI of the tracker"),
+    ),
+    responses(
+        (
+            status = OK,
+            content_type = "application/json",
+            body = entity::vehicle_tracker::Model,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid IMEI",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "tracker not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_tracker_by_imei(
+    Path(imei): Path<String>,
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+) -> Result<Json<vehicle_tracker::Model>, (StatusCode, SimpleError)> {
+    if !REGEX_IS_TRACKER_IMEI.is_match(&imei) {
+        return Err((StatusCode::BAD_REQUEST, SimpleError::from("invalid IMEI")));
+    }
+
+    let cached_id = match TRACKER_ID_CACHE.get() {
+        Some(cache) => cache.write().await.get(&imei).await.ok().flatten(),
+        None => None,
+    };
+
+    if let Some(id) = cached_id {
+        let tracker = vehicle_tracker::Entity::find_by_id_and_org_id(id, org_id, &db)
+            .await
+            .map_err(DbError::from)?;
+
+        // the cached ID might be stale if the IMEI was changed by
+        // another API instance, so a exact match is still required
+        if let Some(tracker) = tracker.filter(|t| t.imei == imei) {
+            return Ok(Json(tracker));
+        }
+    }
+
+    // the cache might skip the database for IMEIs that recently failed
+    // to be found, so misses are always checked on the database
+    let tracker = vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::Imei.eq(imei))
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .one(&db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))?;
+
+    Ok(Json(tracker))
+}
+
+/// Checks IMEIs availability
+///
+/// Required permissions: CREATE_TRACKER
+///
+/// checks, for every IMEI, if it is valid and not used by another tracker of the
+/// organization, so a batch of trackers can be checked before being created
+#[utoipa::path(
+    post,
+    tag = "tracker",
+    path = "/tracker/check-imeis",
+    security(("session_id" = [])),
+    request_body(content = CheckImeisDto, content_type = "application/json"),
+    responses(
+        (
+            status = OK,
+            description = "availability of the IMEIs, in the same order they were sent",
+            content_type = "application/json",
+            body = Vec<ImeiAvailabilityDto>,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn check_imeis(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<CheckImeisDto>,
+) -> Result<Json<Vec<ImeiAvailabilityDto>>, (StatusCode, SimpleError)> {
+    let in_use: HashSet<String> = vehicle_tracker::Entity::find()
+        .select_only()
+        .column(vehicle_tracker::Column::Imei)
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .filter(vehicle_tracker::Column::Imei.is_in(dto.imeis.clone()))
+        .into_tuple()
+        .all(&db)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .collect();
+
+    let result = dto
+        .imeis
+        .into_iter()
+        .map(|imei| ImeiAvailabilityDto {
+            valid: is_valid_tracker_imei(&imei),
+            available: !in_use.contains(&imei),
+            imei,
+        })
+        .collect();
+
+    Ok(Json(result))
+}
+
 /// Update a tracker
 #[utoipa::path(
     put,
@@ -155,6 +335,11 @@ pub async fn update_tracker(
         t.model = Set(model)
     }
 
+    t.firmware_version = set_if_some(dto.firmware_version);
+    t.notes = set_if_some(dto.notes);
+    t.installed_at = set_if_some(dto.installed_at);
+    t.reporting_interval_seconds = set_if_some(dto.reporting_interval_seconds);
+
     let updated_tracker = t.update(&db).await.map_err(DbError::from)?;
 
     // If the imei has changed, we need to delete the old IMEI from the cache
@@ -167,6 +352,176 @@ pub async fn update_tracker(
     Ok(Json(updated_tracker))
 }
 
+/// maximum duration of a tracker loan
+const MAX_TRACKER_LOAN_DAYS: i64 = 90;
+
+/// Loans a tracker to another organization
+///
+/// the organization the tracker is loaned to can read the tracker and its positions, but
+/// not modify it, until the loan expires, loaning a loaned tracker replaces its loan
+///
+/// Required permissions: UPDATE_TRACKER
+#[utoipa::path(
+    put,
+    tag = "tracker",
+    path = "/tracker/{tracker_id}/loan",
+    security(("session_id" = [])),
+    params(
+        ("tracker_id" = u128, Path, description = "id of the tracker to loan"),
+    ),
+    request_body(content = LoanTrackerDto, content_type = "application/json"),
+    responses(
+        (
+            status = OK,
+            content_type = "application/json",
+            body = entity::vehicle_tracker::Model,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid loan expiration or organization",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "tracker not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn loan_tracker(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    OrgBoundEntityFromPathId(tracker): OrgBoundEntityFromPathId<vehicle_tracker::Entity>,
+    ValidatedJson(dto): ValidatedJson<LoanTrackerDto>,
+) -> Result<Json<vehicle_tracker::Model>, (StatusCode, SimpleError)> {
+    if dto.organization_id == org_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("cannot loan a tracker to its own organization"),
+        ));
+    }
+
+    let now = Utc::now();
+
+    if dto.expires_at <= now || dto.expires_at > now + Duration::days(MAX_TRACKER_LOAN_DAYS) {
+        let err_msg = format!(
+            "loan must expire within the next {} days",
+            MAX_TRACKER_LOAN_DAYS
+        );
+        return Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)));
+    }
+
+    organization::Entity::find_by_id(dto.organization_id)
+        .one(&db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(format!("organization: {} not found", dto.organization_id)),
+        ))?;
+
+    // extending a active loan keeps its start, so the borrower does not lose
+    // access to the locations it could already read
+    let loaned_at = tracker
+        .loaned_at
+        .filter(|_| tracker.loaned_to_org_id == Some(dto.organization_id))
+        .filter(|_| {
+            tracker
+                .loan_expires_at
+                .is_some_and(|expires_at| expires_at > now)
+        })
+        .unwrap_or(now);
+
+    let mut t: vehicle_tracker::ActiveModel = tracker.into();
+
+    t.loaned_to_org_id = Set(Some(dto.organization_id));
+    t.loan_expires_at = Set(Some(dto.expires_at));
+    t.loaned_at = Set(Some(loaned_at));
+
+    let updated_tracker = t.update(&db).await.map_err(DbError::from)?;
+
+    Ok(Json(updated_tracker))
+}
+
+/// Ends a tracker loan
+///
+/// the organization the tracker was loaned to immediately loses access to it
+///
+/// Required permissions: UPDATE_TRACKER
+#[utoipa::path(
+    delete,
+    tag = "tracker",
+    path = "/tracker/{tracker_id}/loan",
+    security(("session_id" = [])),
+    params(
+        ("tracker_id" = u128, Path, description = "id of the loaned tracker"),
+    ),
+    responses(
+        (
+            status = OK,
+            content_type = "application/json",
+            body = entity::vehicle_tracker::Model,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "tracker not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn end_tracker_loan(
+    DbConnection(db): DbConnection,
+    OrgBoundEntityFromPathId(tracker): OrgBoundEntityFromPathId<vehicle_tracker::Entity>,
+) -> Result<Json<vehicle_tracker::Model>, (StatusCode, SimpleError)> {
+    let mut t: vehicle_tracker::ActiveModel = tracker.into();
+
+    t.loaned_to_org_id = Set(None);
+    t.loan_expires_at = Set(None);
+    t.loaned_at = Set(None);
+
+    let updated_tracker = t.update(&db).await.map_err(DbError::from)?;
+
+    Ok(Json(updated_tracker))
+}
+
+/// Lists the trackers loaned to the request user organization
+///
+/// only trackers whose loan did not expire are listed, they can be read
+/// with the same endpoints as the organization trackers but not modified
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/borrowed",
+    security(("session_id" = [])),
+    params(Pagination),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of trackers",
+            content_type = "application/json",
+            body = PaginatedVehicleTracker,
+        ),
+    ),
+)]
+pub async fn list_borrowed_trackers(
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<vehicle_tracker::Model>>, (StatusCode, SimpleError)> {
+    let db_query = vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::LoanedToOrgId.eq(org_id))
+        .filter(vehicle_tracker::Column::LoanExpiresAt.gt(Utc::now()))
+        .order_by_asc(vehicle_tracker::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let result =
+        database::helpers::paginated_query_to_pagination_result(db_query, pagination).await?;
+
+    Ok(Json(result))
+}
+
 /// Deletes a tracker
 #[utoipa::path(
     delete,
@@ -223,47 +578,478 @@ pub async fn delete_tracker(
 
     tokio::spawn(delete_tracker_imei_from_cache(tracker.imei).instrument(span));
 
-    Ok(Json(String::from("tracker deleted successfully")))
+    Ok(Json(String::from("tracker deleted successfully")))
+}
+
+/// Deletes many trackers
+///
+/// Required permissions: DELETE_TRACKER
+///
+/// Trackers that do not exist or do not belong to the request user organization are
+/// ignored, all the others are deleted on a single transaction with their location history
+///
+/// on dry runs the transaction is rolled back, returning the trackers that would be deleted
+#[utoipa::path(
+    post,
+    tag = "tracker",
+    path = "/tracker/bulk-delete",
+    security(("session_id" = [])),
+    params(DryRun),
+    request_body = BulkDeleteTrackersDto,
+    responses(
+        (
+            status = OK,
+            description = "the deleted and not found tracker ids",
+            body = BulkDeleteResultDto,
+            content_type = "application/json",
+        ),
+    ),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn bulk_delete_trackers(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedQuery(query): ValidatedQuery<DryRun>,
+    ValidatedJson(dto): ValidatedJson<BulkDeleteTrackersDto>,
+) -> Result<Json<BulkDeleteResultDto>, (StatusCode, SimpleError)> {
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    let trackers = vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::Id.is_in(dto.ids.clone()))
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    let tracker_ids: Vec<i32> = trackers.iter().map(|t| t.id).collect();
+
+    if dto.delete_associated_sim_cards.unwrap_or(false) {
+        sim_card::Entity::delete_many()
+            .filter(sim_card::Column::VehicleTrackerId.is_in(tracker_ids.clone()))
+            .filter(sim_card::Column::OrganizationId.eq(org_id))
+            .exec(&txn)
+            .await
+            .map_err(DbError::from)?;
+    }
+
+    vehicle_tracker::Entity::delete_many()
+        .filter(vehicle_tracker::Column::Id.is_in(tracker_ids.clone()))
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .exec(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    // see `delete_tracker` for why the locations are deleted manually
+    vehicle_tracker_location::Entity::delete_many()
+        .filter(vehicle_tracker_location::Column::VehicleTrackerId.is_in(tracker_ids.clone()))
+        .exec(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    if query.dry_run {
+        txn.rollback().await.map_err(DbError::from)?;
+        return Ok(Json(BulkDeleteResultDto::new(&dto.ids, tracker_ids)));
+    }
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    for tracker in trackers {
+        let span = Span::current();
+        tokio::spawn(delete_tracker_imei_from_cache(tracker.imei).instrument(span));
+    }
+
+    Ok(Json(BulkDeleteResultDto::new(&dto.ids, tracker_ids)))
+}
+
+/// Merges the location history of a tracker into another
+///
+/// Only accessible to superusers, moves every location of the source tracker to the path
+/// tracker, for devices that were replaced but whose history should be kept, both trackers
+/// must belong to the same organization.
+///
+/// locations of the source tracker at the same time as a location of the target tracker are
+/// discarded, keeping the target ones, the target last location is replaced by the source one
+/// if it is more recent. the whole merge (and deleting the source, if requested) runs on a single
+/// transaction.
+///
+/// the moved locations are not accounted on the target tracker odometer.
+#[utoipa::path(
+    post,
+    tag = "tracker",
+    path = "/tracker/{tracker_id}/merge-history",
+    security(("session_id" = [])),
+    params(
+        ("tracker_id" = i32, Path, description = "id of the tracker to move the history to"),
+    ),
+    request_body = MergeTrackerHistoryDto,
+    responses(
+        (
+            status = OK,
+            description = "amount of moved and discarded locations",
+            body = MergeTrackerHistoryResultDto,
+            content_type = "application/json",
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "the trackers are the same or belong to different organizations",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "tracker not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[tracing::instrument(skip_all)]
+pub async fn merge_tracker_history(
+    _: SuperUser,
+    Path(target_id): Path<i32>,
+    DbConnection(db): DbConnection,
+    ValidatedJson(dto): ValidatedJson<MergeTrackerHistoryDto>,
+) -> Result<Json<MergeTrackerHistoryResultDto>, (StatusCode, SimpleError)> {
+    let source_id = dto.source_tracker_id;
+
+    if source_id == target_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("cannot merge the history of a tracker into itself"),
+        ));
+    }
+
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    // lock both trackers so they cannot be deleted or moved while merging
+    let trackers = vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::Id.is_in([source_id, target_id]))
+        .order_by_asc(vehicle_tracker::Column::Id)
+        .lock_exclusive()
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    let find_tracker = |id: i32| {
+        trackers
+            .iter()
+            .find(|t| t.id == id)
+            .ok_or((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))
+    };
+
+    let (source, target) = (find_tracker(source_id)?, find_tracker(target_id)?);
+
+    if source.organization_id != target.organization_id {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("trackers belong to different organizations"),
+        ));
+    }
+
+    let discarded_locations = vehicle_tracker_location::Entity::delete_many()
+        .filter(vehicle_tracker_location::Column::VehicleTrackerId.eq(source_id))
+        .filter(Expr::cust_with_values(
+            r#"EXISTS (
+                SELECT 1 FROM "vehicle_tracker_location" AS t
+                WHERE t."vehicle_tracker_id" = ? AND t."time" = "vehicle_tracker_location"."time"
+            )"#,
+            [sea_orm::Value::from(target_id)],
+        ))
+        .exec(&txn)
+        .await
+        .map_err(DbError::from)?
+        .rows_affected;
+
+    let moved_locations = vehicle_tracker_location::Entity::update_many()
+        .col_expr(
+            vehicle_tracker_location::Column::VehicleTrackerId,
+            Expr::value(target_id),
+        )
+        .filter(vehicle_tracker_location::Column::VehicleTrackerId.eq(source_id))
+        .exec(&txn)
+        .await
+        .map_err(DbError::from)?
+        .rows_affected;
+
+    // same as the last location trigger a older location must not replace a more recent one, but
+    // on ties the target location is kept, as it is the one kept on the location history as well
+    txn.execute(Statement::from_sql_and_values(
+        txn.get_database_backend(),
+        r#"
+        INSERT INTO "vehicle_tracker_last_location" (vehicle_tracker_id, point, time, device_time, received_at)
+        SELECT $1, point, time, device_time, received_at
+        FROM "vehicle_tracker_last_location" WHERE vehicle_tracker_id = $2
+        ON CONFLICT (vehicle_tracker_id) DO UPDATE SET
+        point = EXCLUDED.point,
+        time = EXCLUDED.time,
+        device_time = EXCLUDED.device_time,
+        received_at = EXCLUDED.received_at
+        WHERE "vehicle_tracker_last_location".time < EXCLUDED.time
+        "#,
+        [target_id.into(), source_id.into()],
+    ))
+    .await
+    .map_err(DbError::from)?;
+
+    vehicle_tracker_last_location::Entity::delete_many()
+        .filter(vehicle_tracker_last_location::Column::VehicleTrackerId.eq(source_id))
+        .exec(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    if dto.delete_source {
+        vehicle_tracker::Entity::delete_by_id(source_id)
+            .exec(&txn)
+            .await
+            .map_err(DbError::from)?;
+    }
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    if dto.delete_source {
+        let span = Span::current();
+        tokio::spawn(delete_tracker_imei_from_cache(source.imei.clone()).instrument(span));
+    }
+
+    Ok(Json(MergeTrackerHistoryResultDto {
+        moved_locations,
+        discarded_locations,
+        source_deleted: dto.delete_source,
+    }))
+}
+
+/// List SIM cards that belong to a tracker
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/{tracker_id}/sim-cards",
+    security(("session_id" = [])),
+    params(
+        ("tracker_id" = u128, Path, description = "id of the tracker"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "tracker sim cards",
+            body = Vec<entity::sim_card::Model>,
+            content_type = "application/json",
+        ),
+    ),
+)]
+pub async fn list_tracker_sim_cards(
+    Path(tracker_id): Path<i32>,
+    OrganizationId(org_id): OrganizationId,
+    Extension(req_user): Extension<RequestUser>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<Vec<sim_card::Model>>, (StatusCode, SimpleError)> {
+    let cards = sim_card::Entity::find()
+        .filter(sim_card::Column::VehicleTrackerId.eq(tracker_id))
+        .filter(sim_card::Column::OrganizationId.eq(org_id))
+        .all(&db)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|card| sim_card_for_user(card, &req_user))
+        .collect();
+
+    Ok(Json(cards))
+}
+
+/// Get a tracker with its vehicle and SIM cards
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/{tracker_id}/details",
+    security(("session_id" = [])),
+    params(
+        ("tracker_id" = u128, Path, description = "id of the tracker"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "tracker with its vehicle and sim cards",
+            body = TrackerDetailsDto,
+            content_type = "application/json",
+        ),
+    ),
+)]
+pub async fn get_tracker_details(
+    OrgBoundEntityFromPathId(tracker): OrgBoundEntityFromPathId<vehicle_tracker::Entity>,
+    Extension(req_user): Extension<RequestUser>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<TrackerDetailsDto>, (StatusCode, SimpleError)> {
+    let vehicle_query = async {
+        match tracker.vehicle_id {
+            Some(vehicle_id) => {
+                vehicle::Entity::find_by_id_and_org_id(vehicle_id, tracker.organization_id, &db)
+                    .await
+            }
+            None => Ok(None),
+        }
+    };
+
+    let sim_cards_query = sim_card::Entity::find()
+        .filter(sim_card::Column::VehicleTrackerId.eq(tracker.id))
+        .filter(sim_card::Column::OrganizationId.eq(tracker.organization_id))
+        .all(&db);
+
+    let last_seen_query = vehicle_tracker_last_location::Entity::find()
+        .select_only()
+        .column(vehicle_tracker_last_location::Column::Time)
+        .filter(vehicle_tracker_last_location::Column::VehicleTrackerId.eq(tracker.id))
+        .into_tuple::<DateTime<Utc>>()
+        .one(&db);
+
+    let (vehicle, sim_cards, last_seen_at) =
+        tokio::try_join!(vehicle_query, sim_cards_query, last_seen_query).map_err(DbError::from)?;
+
+    let offline_after =
+        app_config().tracker_offline_after_seconds(tracker.reporting_interval_seconds);
+
+    let online = last_seen_at
+        .is_some_and(|last_seen_at| (Utc::now() - last_seen_at).num_seconds() <= offline_after);
+
+    Ok(Json(TrackerDetailsDto {
+        tracker,
+        vehicle,
+        sim_cards: sim_cards
+            .into_iter()
+            .map(|card| sim_card_for_user(card, &req_user))
+            .collect(),
+        last_seen_at,
+        online,
+    }))
+}
+
+/// default number of positions listed by `get_location_list` and `get_location_page`
+const DEFAULT_LOCATION_LIST_LIMIT: u64 = 15;
+
+/// lists the tracker locations, fetching one more location than `limit` so the caller
+/// can tell if there are more locations to be listed
+///
+/// the locations of a tracker have unique times, so the cursor is the time of the last
+/// listed location and continuing from it never skips or repeats a location
+/// the period of the tracker locations the organization can read if it is not the tracker
+/// owner, as organizations a tracker is loaned to can only read the locations of the loan
+fn borrowed_locations_period(
+    tracker: &vehicle_tracker::Model,
+    org_id: i32,
+) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+    if tracker.organization_id == org_id {
+        return None;
+    }
+
+    // readable trackers not owned by the organization are always loaned to it,
+    // if that is not the case no location is readable
+    let loan_expires_at = tracker.loan_expires_at.unwrap_or_default();
+    let loaned_at = tracker.loaned_at.unwrap_or(loan_expires_at);
+
+    Some((loaned_at, loan_expires_at))
+}
+
+async fn query_location_list(
+    db: &DatabaseConnection,
+    tracker_id: i32,
+    search_query: &GetTrackerPositionsDto,
+    borrowed_period: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    limit: u64,
+) -> Result<Vec<StoredLocation>, (StatusCode, SimpleError)> {
+    let time_col = vehicle_tracker_location::Column::Time;
+
+    let borrowed_filter = borrowed_period.map(|(start, end)| {
+        Cond::all()
+            .add(Expr::col(time_col).gte(start))
+            .add(Expr::col(time_col).lte(end))
+    });
+
+    let cursor_filter = search_query.cursor.map(|cursor| match search_query.order {
+        AscOrDescOrder::Asc => Expr::col(time_col).gt(cursor),
+        AscOrDescOrder::Desc => Expr::col(time_col).lt(cursor),
+    });
+
+    let (q, args) = SeaQuery::select()
+        .column(vehicle_tracker_location::Column::Time)
+        .column(vehicle_tracker_location::Column::Point)
+        .column(vehicle_tracker_location::Column::VehicleTrackerId)
+        .column(vehicle_tracker_location::Column::DeviceTime)
+        .column(vehicle_tracker_location::Column::ReceivedAt)
+        .from(vehicle_tracker_location::Entity)
+        .cond_where(
+            Cond::all()
+                .add(Expr::col(vehicle_tracker_location::Column::VehicleTrackerId).eq(tracker_id))
+                .add_option(search_query.after.map(|a| Expr::col(time_col).gt(a)))
+                .add_option(search_query.before.map(|b| Expr::col(time_col).lt(b)))
+                .add_option(cursor_filter)
+                .add_option(borrowed_filter),
+        )
+        .order_by(time_col, search_query.order.into())
+        .limit(limit + 1)
+        .to_owned()
+        .build_sqlx(PostgresQueryBuilder);
+
+    sqlx::query_as_with(&q, args)
+        .fetch_all(db.get_postgres_connection_pool())
+        .await
+        .map_err(|_| internal_error_res())
 }
 
-/// List SIM cards that belong to a tracker
+/// Get a list of tracker locations
+///
+/// also available for trackers loaned to the request user organization, which
+/// can only list the locations sent while the tracker is loaned to it
 #[utoipa::path(
-    get,
+    post,
     tag = "tracker",
-    path = "/tracker/{tracker_id}/sim-cards",
+    path = "/tracker/{tracker_id}/get-location-list",
     security(("session_id" = [])),
+    request_body(content = GetTrackerPositionsDto),
     params(
         ("tracker_id" = u128, Path, description = "id of the tracker"),
     ),
     responses(
         (
             status = OK,
-            description = "tracker sim cards",
-            body = Vec<entity::sim_card::Model>,
+            description = "tracker location",
+            body = Vec<PositionDto>,
             content_type = "application/json",
         ),
     ),
 )]
-pub async fn list_tracker_sim_cards(
-    Path(tracker_id): Path<i32>,
+pub async fn get_location_list(
     OrganizationId(org_id): OrganizationId,
+    OrgReadableEntityFromPathId(tracker): OrgReadableEntityFromPathId<vehicle_tracker::Entity>,
     DbConnection(db): DbConnection,
-) -> Result<Json<Vec<sim_card::Model>>, (StatusCode, SimpleError)> {
-    let cards = sim_card::Entity::find()
-        .filter(sim_card::Column::VehicleTrackerId.eq(tracker_id))
-        .filter(sim_card::Column::OrganizationId.eq(org_id))
-        .all(&db)
-        .await
-        .map_err(DbError::from)?;
+    ValidatedJson(search_query): ValidatedJson<GetTrackerPositionsDto>,
+) -> Result<Json<Vec<PositionDto>>, (StatusCode, SimpleError)> {
+    let limit = search_query.limit.unwrap_or(DEFAULT_LOCATION_LIST_LIMIT);
 
-    Ok(Json(cards))
+    let borrowed_period = borrowed_locations_period(&tracker, org_id);
+
+    let mut rows =
+        query_location_list(&db, tracker.id, &search_query, borrowed_period, limit).await?;
+    rows.truncate(limit as usize);
+
+    let positions: Vec<PositionDto> = rows
+        .iter()
+        .filter_map(|row| log_unexpected_geometry(PositionDto::from_stored_location(row)))
+        .collect();
+
+    Ok(Json(positions))
 }
 
-/// Get a list of tracker locations
+/// Get a page of tracker locations
+///
+/// same as `get-location-list` but returns a cursor to continue listing the locations,
+/// send it as the `cursor` of the next request (keeping the other filters and the order)
+/// until `nextCursor` is `null` to page through the whole history, pages have at most
+/// 100 locations
 #[utoipa::path(
     post,
     tag = "tracker",
-    path = "/tracker/{tracker_id}/get-location-list",
+    path = "/tracker/{tracker_id}/get-location-page",
     security(("session_id" = [])),
     request_body(content = GetTrackerPositionsDto),
     params(
@@ -272,71 +1058,237 @@ pub async fn list_tracker_sim_cards(
     responses(
         (
             status = OK,
-            description = "tracker location",
-            body = Vec<TrackerLocationDto>,
+            description = "page of tracker locations",
+            body = TrackerPositionsPageDto,
             content_type = "application/json",
         ),
     ),
 )]
-pub async fn get_location_list(
-    OrgBoundEntityFromPathId(tracker): OrgBoundEntityFromPathId<vehicle_tracker::Entity>,
+pub async fn get_location_page(
+    OrganizationId(org_id): OrganizationId,
+    OrgReadableEntityFromPathId(tracker): OrgReadableEntityFromPathId<vehicle_tracker::Entity>,
     DbConnection(db): DbConnection,
     ValidatedJson(search_query): ValidatedJson<GetTrackerPositionsDto>,
-) -> Result<Json<Vec<dto::TrackerLocationDto>>, (StatusCode, SimpleError)> {
-    let (q, args) = SeaQuery::select()
-        .column(vehicle_tracker_location::Column::Time)
-        .column(vehicle_tracker_location::Column::Point)
-        .from(vehicle_tracker_location::Entity)
-        .cond_where(
-            Cond::all()
-                .add(Expr::col(vehicle_tracker_location::Column::VehicleTrackerId).eq(tracker.id))
-                .add_option(
-                    search_query
-                        .after
-                        .map(|a| Expr::col(vehicle_tracker_location::Column::Time).gt(a)),
-                )
-                .add_option(
-                    search_query
-                        .before
-                        .map(|b| Expr::col(vehicle_tracker_location::Column::Time).lt(b)),
-                ),
-        )
-        .order_by(
-            vehicle_tracker_location::Column::Time,
-            search_query.order.into(),
-        )
-        .limit(search_query.limit.unwrap_or(15))
-        .to_owned()
-        .build_sqlx(PostgresQueryBuilder);
+) -> Result<Json<TrackerPositionsPageDto>, (StatusCode, SimpleError)> {
+    let limit = search_query.limit.unwrap_or(DEFAULT_LOCATION_LIST_LIMIT);
 
-    let rows: Vec<(
-        DateTime<Utc>,
-        geozero::wkb::Decode<geo_types::Geometry<f64>>,
-    )> = sqlx::query_as_with(&q, args)
-        .fetch_all(db.get_postgres_connection_pool())
-        .await
-        .map_err(|_| internal_error_res())?;
+    let borrowed_period = borrowed_locations_period(&tracker, org_id);
+
+    let mut rows =
+        query_location_list(&db, tracker.id, &search_query, borrowed_period, limit).await?;
+
+    let has_more = rows.len() as u64 > limit;
+    rows.truncate(limit as usize);
+
+    // the cursor is taken from the rows and not the positions, as rows with
+    // unexpected geometries are skipped and would otherwise be listed again
+    let next_cursor = rows.last().filter(|_| has_more).map(|row| row.time);
 
-    let positions: Vec<dto::TrackerLocationDto> = rows
+    let positions: Vec<PositionDto> = rows
         .iter()
-        .filter_map(|row| {
-            if let Some(geo_types::Geometry::Point(point)) = row.1.geometry {
-                let loc = dto::TrackerLocationDto {
-                    point: point.into(),
-                    time: row.0,
+        .filter_map(|row| log_unexpected_geometry(PositionDto::from_stored_location(row)))
+        .collect();
+
+    Ok(Json(TrackerPositionsPageDto {
+        positions,
+        next_cursor,
+    }))
+}
+
+/// maximum amount of tracker events buffered for a live feed client that is not
+/// keeping up, on the broker queue and on the API, events exceeding it are dropped
+const LIVE_FEED_BROKER_BUFFER: u32 = 256;
+const LIVE_FEED_API_BUFFER: usize = 64;
+
+/// maximum amount of live feeds open at once, as every feed holds a RabbitMQ
+/// channel with its own queue and a task until the client disconnects
+pub const MAX_LIVE_FEEDS: usize = 64;
+
+/// Streams the tracker events as they are received from the decoder
+///
+/// Required permissions: UPDATE_TRACKER
+///
+/// meant for debugging trackers, every event is sent as a server sent event named after
+/// the tracker event type (eg: `location`) containing the decoded event and how the API
+/// parsed it. a `error` event is sent before the stream ends if the API stops receiving
+/// the tracker events, eg: when the RabbitMQ connection is lost.
+///
+/// at most `MAX_LIVE_FEEDS` feeds can be open at once, further feeds are refused until
+/// some are closed.
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/{tracker_id}/live-feed",
+    security(("session_id" = [])),
+    params(
+        ("tracker_id" = u128, Path, description = "id of the tracker"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "stream of tracker events",
+            body = TrackerLiveFeedEventDto,
+            content_type = "text/event-stream",
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "the tracker IMEI is not digits only",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "tracker not found",
+            body = SimpleError,
+        ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "the tracker events cannot be received at the moment or too many live feeds are open",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn stream_tracker_live_feed(
+    State(state): State<AppState>,
+    OrgBoundEntityFromPathId(tracker): OrgBoundEntityFromPathId<vehicle_tracker::Entity>,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, (StatusCode, SimpleError)> {
+    // the IMEI is interpolated on the binding key, so a IMEI with wildcards (eg: `#`)
+    // would receive the events of every tracker, IMEIs are validated on creation but
+    // trackers created before might still have them
+    if !REGEX_IS_TRACKER_IMEI.is_match(&tracker.imei) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("the tracker IMEI must have only digits to stream its events"),
+        ));
+    }
+
+    // the permit is held by the feed task, so it is released when the feed ends
+    let permit = state
+        .live_feed_permits
+        .clone()
+        .try_acquire_owned()
+        .map_err(|_| {
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                SimpleError::from("too many live feeds are open, try again later"),
+            )
+        })?;
+
+    // tracker events routing keys are {protocol}.{event_type}.{imei}
+    let routing_key = format!("*.*.{}", tracker.imei);
+
+    let (channel, mut consumer) = state
+        .rmq
+        .subscribe_to_tracker_events(&routing_key, LIVE_FEED_BROKER_BUFFER)
+        .await
+        .map_err(|e| {
+            error!(
+                tracker_id = tracker.id,
+                "failed to subscribe to tracker events: {e}"
+            );
+            (
+                StatusCode::SERVICE_UNAVAILABLE,
+                SimpleError::from("tracker events are unavailable, try again later"),
+            )
+        })?;
+
+    let (tx, rx) = mpsc::channel::<Event>(LIVE_FEED_API_BUFFER);
+
+    tokio::spawn(
+        async move {
+            let mut dropped_events: u64 = 0;
+
+            loop {
+                // the receiver is dropped when the client disconnects, so wait
+                // for it too as the tracker might not be sending any events
+                let delivery = tokio::select! {
+                    _ = tx.closed() => break,
+                    delivery = consumer.next() => delivery,
+                };
+
+                let Some(Ok(delivery)) = delivery else {
+                    let end = Event::default()
+                        .event("error")
+                        .data("stopped receiving the tracker events");
+
+                    let _ = tx.try_send(end);
+                    break;
                 };
 
-                return Some(loc);
+                let event = live_feed_event(&delivery, dropped_events);
+
+                match tx.try_send(event) {
+                    Ok(_) => dropped_events = 0,
+                    Err(TrySendError::Full(_)) => dropped_events += 1,
+                    Err(TrySendError::Closed(_)) => break,
+                }
             }
 
-            None
-        })
-        .collect();
+            // deletes the exclusive queue of the feed
+            if let Err(e) = channel.close(200, "live feed ended").await {
+                error!("failed to close live feed channel: {e}");
+            }
 
-    Ok(Json(positions))
+            drop(permit);
+        }
+        .instrument(Span::current()),
+    );
+
+    let stream = ReceiverStream::new(rx).map(Ok);
+
+    Ok(Sse::new(stream).keep_alive(KeepAlive::default()))
+}
+
+/// creates the live feed server sent event of a tracker event delivery
+fn live_feed_event(delivery: &Delivery, dropped_events: u64) -> Event {
+    let routing_key = delivery.routing_key.to_string();
+
+    let mut key_parts = routing_key.split('.');
+    let protocol = key_parts.next().unwrap_or_default().to_owned();
+    let event_type = key_parts.next().unwrap_or_default().to_owned();
+
+    let (data, raw, mut parse_error) =
+        match serde_json::from_slice::<serde_json::Value>(&delivery.data) {
+            Ok(value) => (Some(value), None, None),
+            Err(e) => (
+                None,
+                Some(String::from_utf8_lossy(&delivery.data).into_owned()),
+                Some(e.to_string()),
+            ),
+        };
+
+    // only h02 locations are handled by the API, see `tracking::background`
+    if let (Some(value), "h02", "location") = (&data, protocol.as_str(), event_type.as_str()) {
+        parse_error = serde_json::from_value::<LocationMsg>(value.clone())
+            .err()
+            .map(|e| e.to_string());
+    }
+
+    let dto = TrackerLiveFeedEventDto {
+        size_bytes: delivery.data.len(),
+        received_at: Utc::now(),
+        routing_key,
+        protocol,
+        event_type,
+        data,
+        raw,
+        parse_error,
+        dropped_events,
+    };
+
+    Event::default()
+        .event(&dto.event_type)
+        .json_data(&dto)
+        .unwrap_or_else(|_| {
+            Event::default()
+                .event("error")
+                .data("failed to serialize event")
+        })
 }
 
 /// Get the most recent tracker location
+///
+/// also available for trackers loaned to the request user organization,
+/// if the location was sent while the tracker is loaned to it
 #[utoipa::path(
     get,
     tag = "tracker",
@@ -349,46 +1301,49 @@ pub async fn get_location_list(
         (
             status = OK,
             description = "tracker location",
-            body = Option<TrackerLocationDto>,
+            body = Option<PositionDto>,
             content_type = "application/json",
         ),
+        (
+            status = NOT_FOUND,
+            description = "tracker not found",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn get_tracker_location(
-    Path(tracker_id): Path<i32>,
+    OrganizationId(org_id): OrganizationId,
+    OrgReadableEntityFromPathId(tracker): OrgReadableEntityFromPathId<vehicle_tracker::Entity>,
     DbConnection(db): DbConnection,
-) -> Result<Json<Option<dto::TrackerLocationDto>>, (StatusCode, SimpleError)> {
+) -> Result<Json<Option<PositionDto>>, (StatusCode, SimpleError)> {
     let (q, args) =
         SeaQuery::select()
             .column(vehicle_tracker_last_location::Column::Time)
             .column(vehicle_tracker_last_location::Column::Point)
+            .column(vehicle_tracker_last_location::Column::VehicleTrackerId)
+            .column(vehicle_tracker_last_location::Column::DeviceTime)
+            .column(vehicle_tracker_last_location::Column::ReceivedAt)
             .from(vehicle_tracker_last_location::Entity)
             .cond_where(Cond::all().add(
-                Expr::col(vehicle_tracker_last_location::Column::VehicleTrackerId).eq(tracker_id),
+                Expr::col(vehicle_tracker_last_location::Column::VehicleTrackerId).eq(tracker.id),
             ))
             .to_owned()
             .build_sqlx(PostgresQueryBuilder);
 
-    let row: Option<(
-        DateTime<Utc>,
-        geozero::wkb::Decode<geo_types::Geometry<f64>>,
-    )> = sqlx::query_as_with(&q, args)
+    let row: Option<StoredLocation> = sqlx::query_as_with(&q, args)
         .fetch_optional(db.get_postgres_connection_pool())
         .await
         .map_err(|_| internal_error_res())?;
 
-    if let Some(time_and_loc) = row {
-        if let Some(geo_types::Geometry::Point(point)) = time_and_loc.1.geometry {
-            let loc = dto::TrackerLocationDto {
-                point: point.into(),
-                time: time_and_loc.0,
-            };
+    let row = row.filter(|row| {
+        borrowed_locations_period(&tracker, org_id)
+            .is_none_or(|(start, end)| row.time >= start && row.time <= end)
+    });
 
-            return Ok(Json(Some(loc)));
-        }
-    }
+    let position =
+        row.and_then(|row| log_unexpected_geometry(PositionDto::from_stored_location(&row)));
 
-    Ok(Json(None))
+    Ok(Json(position))
 }
 
 /// Sets a tracker vehicle
@@ -486,6 +1441,11 @@ pub async fn set_tracker_vehicle(
             description = "invalid dto error message / IMEI_IN_USE",
             body = SimpleError,
         ),
+        (
+            status = PAYMENT_REQUIRED,
+            description = "LIMIT_REACHED",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn create_tracker(
@@ -531,22 +1491,90 @@ pub async fn create_tracker(
         SimpleError::from("invalid tracker model"),
     )))?;
 
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    limits::ensure_below_limit(&txn, org_id, PlanLimit::Trackers).await?;
+
     let created_tracker = vehicle_tracker::ActiveModel {
         imei: Set(dto.imei),
         model: Set(tracker_model),
         vehicle_id: Set(dto.vehicle_id),
         organization_id: Set(org_id),
+        firmware_version: Set(dto.firmware_version),
+        notes: Set(dto.notes),
+        installed_at: Set(dto.installed_at),
+        reporting_interval_seconds: Set(dto.reporting_interval_seconds),
         ..Default::default()
     }
-    .save(&db)
+    .save(&txn)
     .await
     .map_err(DbError::from)?
     .try_into_model()
     .map_err(DbError::from)?;
 
+    if dto.backfill_unknown_imei_locations {
+        // held locations can repeat a time, the most recently received one is kept
+        txn.execute(Statement::from_sql_and_values(
+            txn.get_database_backend(),
+            r#"
+            INSERT INTO "vehicle_tracker_location" (time, vehicle_tracker_id, point, device_time, received_at)
+            SELECT DISTINCT ON (time) time, $1, point, device_time, received_at
+            FROM "unknown_imei_location" WHERE imei = $2
+            ORDER BY time, received_at DESC, id DESC
+            ON CONFLICT DO NOTHING
+            "#,
+            [created_tracker.id.into(), created_tracker.imei.clone().into()],
+        ))
+        .await
+        .map_err(DbError::from)?;
+    }
+
+    unknown_imei_location::Entity::delete_many()
+        .filter(unknown_imei_location::Column::Imei.eq(&created_tracker.imei))
+        .exec(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    OutboxMessage::api_event("tracker", created_tracker.id, "created", &created_tracker)
+        .map_err(|_| internal_error_res())?
+        .enqueue(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    // the IMEI lookups that failed while it was not registered are cached
+    let span = Span::current();
+    tokio::spawn(delete_tracker_imei_from_cache(created_tracker.imei.clone()).instrument(span));
+
     Ok(Json(created_tracker))
 }
 
+/// query of the trackers of the organization matching the filter,
+/// shared by the list and count endpoints so their results are consistent
+fn filtered_trackers(org_id: i32, filter: ListTrackersDto) -> Select<vehicle_tracker::Entity> {
+    vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .apply_if(filter.with_associated_vehicle, |query, with_vehicle| {
+            if with_vehicle {
+                query.filter(vehicle_tracker::Column::VehicleId.is_not_null())
+            } else {
+                query.filter(vehicle_tracker::Column::VehicleId.is_null())
+            }
+        })
+        .apply_if(filter.imei, |query, imei| {
+            if !imei.is_empty() {
+                let col = Expr::col((vehicle_tracker::Entity, vehicle_tracker::Column::Imei));
+                query.filter(col.ilike(format!("%{}%", imei)))
+            } else {
+                query
+            }
+        })
+        .apply_if(filter.firmware_version, |query, firmware_version| {
+            query.filter(vehicle_tracker::Column::FirmwareVersion.eq(firmware_version))
+        })
+}
+
 /// Lists the trackers that belong to the same org as the request user
 #[utoipa::path(
     get,
@@ -572,29 +1600,210 @@ pub async fn list_trackers(
     OrganizationId(org_id): OrganizationId,
     DbConnection(db): DbConnection,
 ) -> Result<Json<PaginationResult<vehicle_tracker::Model>>, (StatusCode, SimpleError)> {
-    let db_query = vehicle_tracker::Entity::find()
-        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
-        .apply_if(filter.with_associated_vehicle, |query, with_vehicle| {
-            if with_vehicle {
-                query.filter(vehicle_tracker::Column::VehicleId.is_not_null())
-            } else {
-                query.filter(vehicle_tracker::Column::VehicleId.is_null())
-            }
-        })
-        .apply_if(filter.imei, |query, imei| {
-            if !imei.is_empty() {
-                let col = Expr::col((vehicle_tracker::Entity, vehicle_tracker::Column::Imei));
-                query.filter(col.ilike(format!("%{}%", imei)))
-            } else {
-                query
-            }
+    let db_query = filtered_trackers(org_id, filter)
+        .order_by_asc(vehicle_tracker::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let result =
+        database::helpers::paginated_query_to_pagination_result(db_query, pagination).await?;
+
+    Ok(Json(result))
+}
+
+/// Counts the trackers that belong to the same org as the request user
+///
+/// accepts the same filters as the tracker list, without fetching the trackers
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/count",
+    security(("session_id" = [])),
+    params(ListTrackersDto),
+    responses(
+        (
+            status = OK,
+            description = "amount of trackers matching the filter",
+            content_type = "application/json",
+            body = CountDto,
+        ),
+    ),
+)]
+pub async fn count_trackers(
+    ValidatedQuery(filter): ValidatedQuery<ListTrackersDto>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<CountDto>, (StatusCode, SimpleError)> {
+    let count = filtered_trackers(org_id, filter)
+        .count(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(CountDto { count }))
+}
+
+/// A tracker row with the time of its last position
+struct TrackerWithLastSeenRow {
+    tracker: vehicle_tracker::Model,
+    last_seen_at: Option<DateTime<Utc>>,
+}
+
+// sea-orm 0.12 does not support nested models on `FromQueryResult` derives, so the
+// tracker columns are read by the model implementation, keeping it in sync with the entity
+impl FromQueryResult for TrackerWithLastSeenRow {
+    fn from_query_result(res: &QueryResult, pre: &str) -> Result<Self, DbErr> {
+        Ok(Self {
+            tracker: vehicle_tracker::Model::from_query_result(res, pre)?,
+            last_seen_at: res.try_get(pre, "last_seen_at")?,
         })
+    }
+}
+
+/// Lists trackers without recent positions
+///
+/// lists the trackers of the request user organization whose last position is older
+/// than the threshold or that never sent a position, from the most to the least stale,
+/// without a threshold trackers are stale once they miss the amount of reporting intervals
+/// after which they are considered offline
+#[utoipa::path(
+    get,
+    tag = "tracker",
+    path = "/tracker/stale",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListStaleTrackersDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of stale trackers",
+            content_type = "application/json",
+            body = PaginatedStaleTracker,
+        ),
+    ),
+)]
+pub async fn list_stale_trackers(
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListStaleTrackersDto>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<StaleTrackerDto>>, (StatusCode, SimpleError)> {
+    let now = Utc::now();
+
+    let last_seen_col = Expr::col((
+        vehicle_tracker_last_location::Entity,
+        vehicle_tracker_last_location::Column::Time,
+    ));
+
+    let is_stale = match filter.threshold_minutes {
+        Some(threshold_minutes) => last_seen_col
+            .clone()
+            .lt(now - Duration::minutes(threshold_minutes)),
+        None => {
+            let cfg = app_config();
+
+            Expr::cust_with_values(
+                r#""vehicle_tracker_last_location"."time" < ? - make_interval(
+                    secs => COALESCE("vehicle_tracker"."reporting_interval_seconds", ?) * ?
+                )"#,
+                [
+                    sea_orm::Value::from(now),
+                    sea_orm::Value::from(cfg.tracker_reporting_interval_seconds),
+                    sea_orm::Value::from(cfg.tracker_offline_after_missed_reports),
+                ],
+            )
+        }
+    };
+
+    let paginator = vehicle_tracker::Entity::find()
+        .join(
+            JoinType::LeftJoin,
+            vehicle_tracker::Relation::VehicleTrackerLastLocation.def(),
+        )
+        .column_as(last_seen_col.clone(), "last_seen_at")
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .filter(
+            Cond::any()
+                .add(last_seen_col.clone().is_null())
+                .add(is_stale),
+        )
+        // trackers that never sent a position first, then by the oldest position
+        .order_by(last_seen_col.clone().is_null(), Order::Desc)
+        .order_by(last_seen_col, Order::Asc)
         .order_by_asc(vehicle_tracker::Column::Id)
+        .into_model::<TrackerWithLastSeenRow>()
         .paginate(&db, pagination.page_size);
 
-    let result = database::helpers::paginated_query_to_pagination_result(db_query, pagination)
+    let n = paginator
+        .num_items_and_pages()
         .await
         .map_err(DbError::from)?;
 
-    Ok(Json(result))
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|row| StaleTrackerDto {
+            stale_for_seconds: row
+                .last_seen_at
+                .map(|last_seen_at| (now - last_seen_at).num_seconds()),
+            last_seen_at: row.last_seen_at,
+            tracker: row.tracker,
+        })
+        .collect();
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::common::fixtures;
+
+    #[tokio::test]
+    async fn tracker_routes_fall_back_to_the_database_when_the_cache_is_not_initialized() {
+        assert!(TRACKER_ID_CACHE.get().is_none());
+
+        // removing a IMEI from the cache is a no-op, instead of a panic
+        delete_tracker_imei_from_cache(String::from("490154203237518")).await;
+
+        // the handler only errors if it queried the database
+        let result = get_tracker_by_imei(
+            Path(String::from("490154203237518")),
+            DbConnection(fixtures::unreachable_db()),
+            OrganizationId(1),
+        )
+        .await;
+
+        assert_eq!(
+            result.map(|_| ()).map_err(|(status, _)| status),
+            Err(StatusCode::INTERNAL_SERVER_ERROR)
+        );
+    }
+
+    #[test]
+    fn borrowers_can_only_read_the_locations_of_the_loan() {
+        let loaned_at = Utc::now() - Duration::days(1);
+        let loan_expires_at = Utc::now() + Duration::days(1);
+
+        let tracker = vehicle_tracker::Model {
+            loaned_to_org_id: Some(2),
+            loaned_at: Some(loaned_at),
+            loan_expires_at: Some(loan_expires_at),
+            ..fixtures::tracker(1)
+        };
+
+        assert_eq!(borrowed_locations_period(&tracker, 1), None);
+        assert_eq!(
+            borrowed_locations_period(&tracker, 2),
+            Some((loaned_at, loan_expires_at))
+        );
+    }
 }
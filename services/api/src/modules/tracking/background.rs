@@ -1,6 +1,10 @@
-use super::decoder::h02;
-use crate::{modules::globals::TRACKER_ID_CACHE, rabbitmq::Rmq};
-use lapin::{message::Delivery, options::BasicConsumeOptions, types::FieldTable};
+use super::{decoder::h02, unknown_imei::on_unknown_imei_location};
+use crate::{config::app_config, modules::globals::get_tracker_id, rabbitmq::Rmq};
+use lapin::{
+    message::Delivery,
+    options::{BasicAckOptions, BasicConsumeOptions},
+    types::FieldTable,
+};
 use sea_orm::DatabaseConnection;
 use socketioxide::SocketIo;
 use std::{sync::Arc, time::Duration};
@@ -10,7 +14,7 @@ use tracing::{error, warn, Instrument};
 /// RabbitMQ delivery, this mainly passes the message to the appropriate function
 /// based on the `protocol`, `event_type` and the `imei` on the delivery routing key
 #[tracing::instrument(skip_all)]
-async fn on_tracker_event(delivery: Delivery, db: &DatabaseConnection, socket: &SocketIo) {
+async fn on_tracker_event(delivery: &Delivery, db: &DatabaseConnection, socket: &SocketIo) {
     let routing_key = delivery.routing_key.to_string();
 
     // tracking events routing keys have the following pattern
@@ -44,19 +48,21 @@ async fn on_tracker_event(delivery: Delivery, db: &DatabaseConnection, socket: &
         return;
     }
 
-    let tracker_cache = TRACKER_ID_CACHE
-        .get()
-        .expect("tracker id cache not initialized");
-
-    let tracker_id: i32 = match tracker_cache.write().await.get(imei).await {
-        Some(id) => id,
-        None => {
-            warn!("tracker: {imei} does not exist");
+    let tracker_id: i32 = match get_tracker_id(imei, db).await {
+        Ok(Some(id)) => id,
+        Ok(None) => match on_unknown_imei_location(delivery, imei, db).await {
+            Some(id) => id,
+            None => return,
+        },
+        // it is not known if the IMEI is registered, so the unknown IMEI policy
+        // is not applied as it could register a tracker for a existing IMEI
+        Err(e) => {
+            warn!("tracker: {imei} could not be looked up, dropping its location: {e}");
             return;
         }
     };
 
-    let _ = h02::handle_location(&delivery, socket, tracker_id, db).await;
+    let _ = h02::handle_location(delivery, socket, tracker_id, db).await;
 }
 
 /// Starts a RabbitMQ consumer that listens for any tracker event
@@ -67,12 +73,9 @@ async fn on_tracker_event(delivery: Delivery, db: &DatabaseConnection, socket: &
 /// thus so does the consumer.
 pub fn start_positions_consumer(rmq: Arc<Rmq>, socket_io: SocketIo, db: DatabaseConnection) {
     tokio::task::spawn(async move {
-        // Important: use automatic acknowledgement mode because we will recieve a
-        // lot of positions per seconds and we dont really care if a tiny few are lost
-        let consume_options = BasicConsumeOptions {
-            no_ack: true,
-            ..Default::default()
-        };
+        // deliveries are acknowledged manually so the prefetch limits the events
+        // delivered to the API at once, see `tracker_events_prefetch`
+        let consume_options = BasicConsumeOptions::default();
 
         let db_ref = &db;
         let socket_ref = &socket_io;
@@ -85,15 +88,23 @@ pub fn start_positions_consumer(rmq: Arc<Rmq>, socket_io: SocketIo, db: Database
                 .consume(
                     shared::constants::rabbitmq::TRACKER_EVENTS_QUEUE,
                     "api_tracker_events_consumer",
+                    app_config().tracker_events_prefetch,
                     consume_options,
                     FieldTable::default(),
                     |delivery: Delivery| async move {
                         let (span, delivery) =
                             shared::tracer::correlate_trace_from_delivery(delivery);
 
-                        on_tracker_event(delivery, db_ref, socket_ref)
+                        on_tracker_event(&delivery, db_ref, socket_ref)
                             .instrument(span)
-                            .await
+                            .await;
+
+                        // events that failed to be handled are acknowledged as well, since we
+                        // recieve a lot of positions per second and we dont really care if a
+                        // tiny few are lost, redelivering them would only delay the newer ones
+                        if let Err(e) = delivery.ack(BasicAckOptions::default()).await {
+                            error!("[RMQ] failed to ack tracker event: {e}");
+                        }
                     },
                 )
                 .await;
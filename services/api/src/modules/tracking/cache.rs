@@ -1,21 +1,66 @@
+use lru::LruCache;
+use opentelemetry::{metrics::Counter, Context};
 use sea_orm::entity::prelude::*;
 use sea_orm::{DatabaseConnection, DbErr, EntityTrait, QueryFilter, QuerySelect};
 use shared::entity::vehicle_tracker;
-use std::collections::HashMap;
-use std::time::Instant;
+use std::num::NonZeroUsize;
+use std::time::{Duration, Instant};
+use std::{error, fmt};
 
-/// A tracker ID cache, this is basically a HashMap
-/// where the key is the tracker IMEI and the val its ID
-///
-/// the catch is that since this cache might be hit multiple
-/// times with a non existing ID consecutively, it avoids accessing
-/// the database if there are too many failed attempts to get
-/// a ID by a certain IMEI within a time window
-pub struct TrackerIdCache {
-    db: DatabaseConnection,
+/// A tracker ID lookup that could not tell if a tracker with the IMEI exists
+#[derive(Debug)]
+pub enum TrackerIdLookupError {
+    /// the database could not be queried
+    Db(DbErr),
+
+    /// the IMEI failed to be found too many times recently,
+    /// so the database was not queried, see `TrackerIdCache::get`
+    Throttled,
+}
+
+impl fmt::Display for TrackerIdLookupError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Db(e) => write!(f, "failed to query the database: {e}"),
+            Self::Throttled => write!(f, "too many recent failed lookups"),
+        }
+    }
+}
+
+impl error::Error for TrackerIdLookupError {}
 
-    /// IMEI -> ID
-    cache: HashMap<String, i32>,
+/// hit, miss and eviction counters of the cache, exported
+/// if a opentelemetry meter provider is installed
+struct CacheMetrics {
+    hits: Counter<u64>,
+    misses: Counter<u64>,
+    evictions: Counter<u64>,
+}
+
+impl CacheMetrics {
+    fn new() -> Self {
+        let meter = opentelemetry::global::meter("tracker_id_cache");
+
+        Self {
+            hits: meter.u64_counter("tracker_id_cache.hits").init(),
+            misses: meter.u64_counter("tracker_id_cache.misses").init(),
+            evictions: meter.u64_counter("tracker_id_cache.evictions").init(),
+        }
+    }
+}
+
+/// The in memory part of the `TrackerIdCache`, this is basically a bounded
+/// LRU map where the key is the tracker IMEI and the val its ID, that also
+/// counts the failed lookups of each IMEI within a time window
+struct TrackerIdLru {
+    /// IMEI -> (ID, time the entry was cached)
+    ///
+    /// when full, the least recently used entry is evicted
+    cache: LruCache<String, (i32, Instant)>,
+
+    /// how long a entry is kept on the cache before it expires, if `None` entries
+    /// only leave the cache when evicted or deleted
+    ttl: Option<Duration>,
 
     /// the maximun amount of times a IMEI within a time window
     /// a IMEI can fail to retrieve a ID from the DB before any further
@@ -33,17 +78,118 @@ pub struct TrackerIdCache {
     time_window_seconds: u64,
 
     /// IMEI -> (attempt_count, first_failed_time)
-    failed_attempts: HashMap<String, (u32, Instant)>,
+    ///
+    /// bounded by the same capacity of the cache, since unknown IMEIs
+    /// would otherwise make it grow unbounded
+    failed_attempts: LruCache<String, (u32, Instant)>,
+
+    metrics: CacheMetrics,
 }
 
-impl TrackerIdCache {
-    pub fn new(db: DatabaseConnection) -> Self {
+impl TrackerIdLru {
+    fn new(capacity: NonZeroUsize, ttl: Option<Duration>) -> Self {
         Self {
-            db,
-            cache: HashMap::new(),
-            failed_attempts: HashMap::new(),
+            ttl,
+            cache: LruCache::new(capacity),
+            failed_attempts: LruCache::new(capacity),
             max_attempts: 10,
             time_window_seconds: 5 * 60,
+            metrics: CacheMetrics::new(),
+        }
+    }
+
+    /// if the IMEI failed to be found `max_attempts` times within the time window
+    fn is_throttled(&mut self, imei: &str) -> bool {
+        match self.failed_attempts.get(imei) {
+            Some((attempt_count, first_error)) => {
+                let is_within_time_windown =
+                    first_error.elapsed().as_secs() < self.time_window_seconds;
+
+                is_within_time_windown && *attempt_count >= self.max_attempts
+            }
+            None => false,
+        }
+    }
+
+    /// gets a cached ID, removing the entry if it expired
+    fn get(&mut self, imei: &str) -> Option<i32> {
+        let cached = self.cache.get(imei).copied();
+
+        let id = match cached {
+            Some((_, cached_at)) if self.ttl.is_some_and(|ttl| cached_at.elapsed() >= ttl) => {
+                self.cache.pop(imei);
+                None
+            }
+            Some((id, _)) => Some(id),
+            None => None,
+        };
+
+        match id {
+            Some(_) => self.metrics.hits.add(&Context::current(), 1, &[]),
+            None => self.metrics.misses.add(&Context::current(), 1, &[]),
+        }
+
+        id
+    }
+
+    fn insert(&mut self, imei: &str, id: i32) {
+        let evicted = self
+            .cache
+            .push(imei.to_string(), (id, Instant::now()))
+            .is_some_and(|(evicted_imei, _)| evicted_imei != imei);
+
+        if evicted {
+            self.metrics.evictions.add(&Context::current(), 1, &[]);
+        }
+    }
+
+    /// counts a failed lookup of the IMEI, restarting the
+    /// time window if the previous one has passed
+    fn record_failure(&mut self, imei: &str) {
+        let time_window_seconds = self.time_window_seconds;
+
+        match self.failed_attempts.get_mut(imei) {
+            Some((attempt_count, first_failure_time)) => {
+                let elapsed_seconds = first_failure_time.elapsed().as_secs();
+
+                if elapsed_seconds < time_window_seconds {
+                    *attempt_count += 1;
+                } else {
+                    *attempt_count = 1;
+                    *first_failure_time = Instant::now();
+                }
+            }
+            None => {
+                self.failed_attempts
+                    .put(imei.to_string(), (1, Instant::now()));
+            }
+        }
+    }
+
+    fn delete(&mut self, imei: &str) {
+        self.cache.pop(imei);
+        self.failed_attempts.pop(imei);
+    }
+}
+
+/// A tracker ID cache, that looks up the trackers missing on a `TrackerIdLru` on the database
+///
+/// the catch is that since this cache might be hit multiple
+/// times with a non existing ID consecutively, it avoids accessing
+/// the database if there are too many failed attempts to get
+/// a ID by a certain IMEI within a time window
+pub struct TrackerIdCache {
+    db: DatabaseConnection,
+    lru: TrackerIdLru,
+}
+
+impl TrackerIdCache {
+    /// creates the cache holding at most `capacity` IMEIs, with entries
+    /// expiring after `ttl` if set
+    pub fn new(db: DatabaseConnection, capacity: NonZeroUsize, ttl: Option<Duration>) -> Self {
+        Self {
+            db,
+            lru: TrackerIdLru::new(capacity, ttl),
         }
     }
 
@@ -53,67 +199,104 @@ impl TrackerIdCache {
     /// ### IMPORTANT
     ///
     /// If there was too many failed attempts within the a time window
-    /// None is returned without accessing the database.
+    /// `TrackerIdLookupError::Throttled` is returned without accessing the database.
     ///
     /// [PROD-TODO]
     /// in order to make this write to the cache and the DB, this needs to be mutable
     /// and since this is used in a multithreaded context and wrapped by a mutex this
     /// is locked quite often, which is not desirable
-    pub async fn get(&mut self, imei: &str) -> Option<i32> {
-        if let Some((attempt_count, first_error)) = self.failed_attempts.get_mut(imei) {
-            let is_within_time_windown = first_error.elapsed().as_secs() < self.time_window_seconds;
-
-            let max_attempts_reached = *attempt_count >= self.max_attempts;
-
-            // If the current attempt is within the time window and the maximun amount
-            // of attempts has been reached, avoid trying to get the value from the
-            // cache or the database as it will most likely be none.
-            if is_within_time_windown && max_attempts_reached {
-                return None;
-            }
+    pub async fn get(&mut self, imei: &str) -> Result<Option<i32>, TrackerIdLookupError> {
+        // If the maximun amount of attempts within the time window has been reached, avoid
+        // trying to get the value from the cache or the database as it will most likely be none.
+        if self.lru.is_throttled(imei) {
+            return Err(TrackerIdLookupError::Throttled);
         }
 
-        let cached_value = self.cache.get(imei).cloned();
-        if cached_value.is_some() {
-            return cached_value;
+        if let Some(id) = self.lru.get(imei) {
+            return Ok(Some(id));
         }
 
-        if let Some(id) = self.get_from_db(imei).await.unwrap_or(None) {
-            self.cache.insert(imei.to_string(), id);
-            return Some(id);
+        let found = find_tracker_id(imei, &self.db)
+            .await
+            .map_err(TrackerIdLookupError::Db)?;
+
+        match found {
+            Some(id) => self.lru.insert(imei, id),
+            None => self.lru.record_failure(imei),
         }
 
-        self.failed_attempts
-            .entry(imei.to_string())
-            .and_modify(|(attempt_count, first_failure_time)| {
-                let elapsed_seconds = first_failure_time.elapsed().as_secs();
+        Ok(found)
+    }
 
-                let is_within_time_windown = elapsed_seconds < self.time_window_seconds;
+    /// removes a IMEI from the cache, this must be called whenever the
+    /// IMEI of a tracker changes or the tracker is deleted
+    pub fn delete(&mut self, imei: &str) {
+        self.lru.delete(imei);
+    }
+}
 
-                if is_within_time_windown {
-                    *attempt_count += 1;
-                } else {
-                    *attempt_count = 1;
-                    *first_failure_time = Instant::now();
-                }
-            })
-            .or_insert((1, Instant::now()));
+/// finds a tracker ID by IMEI on the database, without any caching
+pub async fn find_tracker_id(imei: &str, db: &DatabaseConnection) -> Result<Option<i32>, DbErr> {
+    vehicle_tracker::Entity::find()
+        .select_only()
+        .column(vehicle_tracker::Column::Id)
+        .filter(vehicle_tracker::Column::Imei.eq(imei))
+        .into_tuple()
+        .one(db)
+        .await
+}
 
-        None
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lru(capacity: usize, ttl: Option<Duration>) -> TrackerIdLru {
+        TrackerIdLru::new(NonZeroUsize::new(capacity).unwrap(), ttl)
     }
 
-    pub fn delete(&mut self, imei: &str) {
-        self.cache.remove(imei);
-        self.failed_attempts.remove(imei);
+    #[test]
+    fn evicts_the_least_recently_used_imei_when_full() {
+        let mut lru = lru(2, None);
+
+        lru.insert("1", 1);
+        lru.insert("2", 2);
+
+        // makes "2" the least recently used
+        assert_eq!(lru.get("1"), Some(1));
+
+        lru.insert("3", 3);
+
+        assert_eq!(lru.get("1"), Some(1));
+        assert_eq!(lru.get("2"), None);
+        assert_eq!(lru.get("3"), Some(3));
     }
 
-    async fn get_from_db(&self, imei: &str) -> Result<Option<i32>, DbErr> {
-        vehicle_tracker::Entity::find()
-            .select_only()
-            .column(vehicle_tracker::Column::Id)
-            .filter(vehicle_tracker::Column::Imei.eq(imei))
-            .into_tuple()
-            .one(&self.db)
-            .await
+    #[test]
+    fn expires_entries_after_the_ttl() {
+        let mut lru = lru(2, Some(Duration::ZERO));
+
+        lru.insert("1", 1);
+
+        assert_eq!(lru.get("1"), None);
+        assert_eq!(lru.cache.len(), 0);
+    }
+
+    #[test]
+    fn throttles_imeis_after_max_failed_attempts() {
+        let mut lru = lru(2, None);
+
+        for _ in 0..lru.max_attempts - 1 {
+            lru.record_failure("1");
+        }
+
+        assert!(!lru.is_throttled("1"));
+
+        lru.record_failure("1");
+
+        assert!(lru.is_throttled("1"));
+
+        lru.delete("1");
+
+        assert!(!lru.is_throttled("1"));
     }
 }
@@ -0,0 +1,412 @@
+//! Downsampling of old tracker locations.
+//!
+//! Locations older than `LOCATION_DOWNSAMPLE_AFTER_DAYS` are reduced to one every
+//! `LOCATION_DOWNSAMPLE_RESOLUTION_SECONDS`, except the ones where the vehicle stops,
+//! starts moving or changes direction, so old trips keep their shape. Every tracker has
+//! a cursor with the time its locations were compacted up to, so every run only reads
+//! the locations after it instead of rescanning the whole location history.
+
+use crate::{
+    config::app_config,
+    modules::{
+        tracking::utils::{decode_location_point, log_unexpected_geometry, StoredPoint},
+        vehicle::odometer::{distance_km, MIN_MOVEMENT_KM},
+    },
+};
+use chrono::{DateTime, Utc};
+use sea_orm::{
+    sea_query::OnConflict, ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter,
+    QuerySelect, Set, TransactionTrait,
+};
+use sea_query::{Cond, Expr, Order, PostgresQueryBuilder, Query as SeaQuery};
+use sea_query_binder::SqlxBinder;
+use shared::entity::{
+    vehicle_tracker, vehicle_tracker_location, vehicle_tracker_location_compaction_cursor,
+    vehicle_tracker_odometer_cursor,
+};
+use std::collections::HashMap;
+use tracing::error;
+
+/// Maximum amount of locations of a tracker processed on every run
+static MAX_LOCATIONS_PER_RUN: u64 = 5000;
+
+struct Location {
+    time: DateTime<Utc>,
+    lat: f64,
+    lng: f64,
+}
+
+impl Location {
+    fn coordinates(&self) -> (f64, f64) {
+        (self.lat, self.lng)
+    }
+
+    /// initial great circle bearing in degrees from this location to another
+    fn bearing_to(&self, to: &Location) -> f64 {
+        let (from_lat, to_lat) = (self.lat.to_radians(), to.lat.to_radians());
+        let d_lng = (to.lng - self.lng).to_radians();
+
+        let y = d_lng.sin() * to_lat.cos();
+        let x = from_lat.cos() * to_lat.sin() - from_lat.sin() * to_lat.cos() * d_lng.cos();
+
+        y.atan2(x).to_degrees()
+    }
+}
+
+/// Difference in degrees between the direction from `prev` to `cur` and from `cur` to `next`
+fn heading_change(prev: &Location, cur: &Location, next: &Location) -> f64 {
+    let change = (cur.bearing_to(next) - prev.bearing_to(cur)).abs() % 360.0;
+
+    if change > 180.0 {
+        360.0 - change
+    } else {
+        change
+    }
+}
+
+/// Selects which of the time ordered `locations` are kept when downsampling, the first and last
+/// locations are always kept, so the trip is not cut between runs
+fn select_kept(
+    locations: &[Location],
+    resolution: chrono::Duration,
+    min_heading_change: f64,
+) -> Vec<bool> {
+    let mut kept = vec![true; locations.len()];
+
+    let Some(first) = locations.first() else {
+        return kept;
+    };
+
+    let mut last_kept_time = first.time;
+
+    for i in 1..locations.len().saturating_sub(1) {
+        let (prev, cur, next) = (&locations[i - 1], &locations[i], &locations[i + 1]);
+
+        let moving_before = distance_km(prev.coordinates(), cur.coordinates()) >= MIN_MOVEMENT_KM;
+        let moving_after = distance_km(cur.coordinates(), next.coordinates()) >= MIN_MOVEMENT_KM;
+
+        // the vehicle stopped or started moving at this location
+        let is_stop_boundary = moving_before != moving_after;
+
+        let is_turn =
+            moving_before && moving_after && heading_change(prev, cur, next) >= min_heading_change;
+
+        kept[i] = is_stop_boundary || is_turn || cur.time - last_kept_time >= resolution;
+
+        if kept[i] {
+            last_kept_time = cur.time;
+        }
+    }
+
+    kept
+}
+
+async fn get_locations_between(
+    db: &DatabaseConnection,
+    tracker_id: i32,
+    from: Option<DateTime<Utc>>,
+    until: DateTime<Utc>,
+) -> Result<Vec<Location>, DbErr> {
+    let mut cond = Cond::all()
+        .add(Expr::col(vehicle_tracker_location::Column::VehicleTrackerId).eq(tracker_id))
+        .add(Expr::col(vehicle_tracker_location::Column::Time).lt(until));
+
+    if let Some(from) = from {
+        cond = cond.add(Expr::col(vehicle_tracker_location::Column::Time).gte(from));
+    }
+
+    let (q, args) = SeaQuery::select()
+        .column(vehicle_tracker_location::Column::Time)
+        .column(vehicle_tracker_location::Column::Point)
+        .from(vehicle_tracker_location::Entity)
+        .cond_where(cond)
+        .order_by(vehicle_tracker_location::Column::Time, Order::Asc)
+        .limit(MAX_LOCATIONS_PER_RUN)
+        .to_owned()
+        .build_sqlx(PostgresQueryBuilder);
+
+    let rows: Vec<(DateTime<Utc>, StoredPoint)> = sqlx::query_as_with(&q, args)
+        .fetch_all(db.get_postgres_connection_pool())
+        .await
+        .map_err(|e| DbErr::Custom(e.to_string()))?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|(time, point)| {
+            let p = log_unexpected_geometry(decode_location_point(tracker_id, point))?;
+
+            Some(Location {
+                time: *time,
+                lat: p.x(),
+                lng: p.y(),
+            })
+        })
+        .collect())
+}
+
+/// Downsamples the locations of the tracker from its cursor until `until`, returning
+/// the amount of deleted locations
+async fn compact_tracker_locations(
+    db: &DatabaseConnection,
+    tracker_id: i32,
+    cursor: Option<DateTime<Utc>>,
+    until: DateTime<Utc>,
+) -> Result<u64, DbErr> {
+    let locations = get_locations_between(db, tracker_id, cursor, until).await?;
+
+    let compacted_until = match locations.last() {
+        Some(last) if Some(last.time) != cursor => last.time,
+        _ => return Ok(0),
+    };
+
+    let config = app_config();
+
+    let kept = select_kept(
+        &locations,
+        chrono::Duration::seconds(config.location_downsample_resolution_seconds as i64),
+        config.location_downsample_min_heading_change_degrees,
+    );
+
+    let discarded: Vec<DateTime<Utc>> = locations
+        .iter()
+        .zip(kept)
+        .filter(|(_, kept)| !kept)
+        .map(|(location, _)| location.time)
+        .collect();
+
+    let txn = db.begin().await?;
+
+    let mut deleted = 0;
+
+    if !discarded.is_empty() {
+        deleted = vehicle_tracker_location::Entity::delete_many()
+            .filter(vehicle_tracker_location::Column::VehicleTrackerId.eq(tracker_id))
+            .filter(vehicle_tracker_location::Column::Time.is_in(discarded))
+            .exec(&txn)
+            .await?
+            .rows_affected;
+    }
+
+    vehicle_tracker_location_compaction_cursor::Entity::insert(
+        vehicle_tracker_location_compaction_cursor::ActiveModel {
+            vehicle_tracker_id: Set(tracker_id),
+            compacted_until: Set(compacted_until),
+        },
+    )
+    .on_conflict(
+        OnConflict::column(vehicle_tracker_location_compaction_cursor::Column::VehicleTrackerId)
+            .update_column(vehicle_tracker_location_compaction_cursor::Column::CompactedUntil)
+            .to_owned(),
+    )
+    .exec(&txn)
+    .await?;
+
+    txn.commit().await?;
+
+    Ok(deleted)
+}
+
+/// the time until which the locations of a tracker can be downsampled, that is the `cutoff`
+/// unless the tracker is installed on a vehicle, in which case the locations not yet accounted
+/// on the vehicle odometer are kept, and so trackers without a odometer cursor are skipped
+fn downsample_until(
+    cutoff: DateTime<Utc>,
+    has_vehicle: bool,
+    odometer_processed_until: Option<DateTime<Utc>>,
+) -> Option<DateTime<Utc>> {
+    match (has_vehicle, odometer_processed_until) {
+        (false, _) => Some(cutoff),
+        (true, Some(processed_until)) => Some(cutoff.min(processed_until)),
+        (true, None) => None,
+    }
+}
+
+/// Downsamples the locations older than `LOCATION_DOWNSAMPLE_AFTER_DAYS` of every tracker,
+/// returning the amount of deleted locations.
+///
+/// locations not yet accounted on the odometer of a vehicle are never downsampled, so
+/// the distance traveled is not underestimated if the odometer falls behind
+pub async fn compact_old_locations(db: &DatabaseConnection) -> Result<u64, DbErr> {
+    let Some(after_days) = app_config().location_downsample_after_days else {
+        return Ok(0);
+    };
+
+    let cutoff = Utc::now() - chrono::Duration::days(after_days as i64);
+
+    let trackers: Vec<(i32, Option<i32>)> = vehicle_tracker::Entity::find()
+        .select_only()
+        .column(vehicle_tracker::Column::Id)
+        .column(vehicle_tracker::Column::VehicleId)
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    let cursors: HashMap<i32, DateTime<Utc>> =
+        vehicle_tracker_location_compaction_cursor::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|c| (c.vehicle_tracker_id, c.compacted_until))
+            .collect();
+
+    let odometer_cursors: HashMap<i32, DateTime<Utc>> =
+        vehicle_tracker_odometer_cursor::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|c| (c.vehicle_tracker_id, c.processed_until))
+            .collect();
+
+    let mut deleted = 0;
+
+    for (tracker_id, vehicle_id) in trackers {
+        let odometer_cursor = odometer_cursors.get(&tracker_id).copied();
+
+        let Some(until) = downsample_until(cutoff, vehicle_id.is_some(), odometer_cursor) else {
+            continue;
+        };
+
+        let cursor = cursors.get(&tracker_id).copied();
+
+        match compact_tracker_locations(db, tracker_id, cursor, until).await {
+            Ok(n) => deleted += n,
+            Err(e) => error!(
+                "failed to compact locations of tracker {}: {}",
+                tracker_id, e
+            ),
+        }
+    }
+
+    Ok(deleted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// locations 10 seconds apart on the coordinates
+    fn locations(coordinates: &[(f64, f64)]) -> Vec<Location> {
+        let start = Utc::now();
+
+        coordinates
+            .iter()
+            .enumerate()
+            .map(|(i, &(lat, lng))| Location {
+                time: start + chrono::Duration::seconds(10 * i as i64),
+                lat,
+                lng,
+            })
+            .collect()
+    }
+
+    fn kept_indexes(kept: Vec<bool>) -> Vec<usize> {
+        kept.iter()
+            .enumerate()
+            .filter(|(_, kept)| **kept)
+            .map(|(i, _)| i)
+            .collect()
+    }
+
+    #[test]
+    fn measures_heading_changes_on_the_shortest_direction() {
+        let turn_right = locations(&[(0.0, 0.0), (0.001, 0.0), (0.001, 0.001)]);
+        let straight = locations(&[(0.0, 0.0), (0.001, 0.0), (0.002, 0.0)]);
+
+        // heading south, to south east then south west, the bearings wrap around 180
+        let zigzag = locations(&[(0.002, 0.0), (0.001, 0.0001), (0.0, 0.0)]);
+
+        let change = |l: &[Location]| heading_change(&l[0], &l[1], &l[2]);
+
+        assert!((change(&turn_right) - 90.0).abs() < 0.1);
+        assert!(change(&straight) < 0.1);
+        assert!((change(&zigzag) - 11.4).abs() < 0.1);
+    }
+
+    #[test]
+    fn keeps_the_locations_where_the_vehicle_stops_or_starts_moving() {
+        let hour = chrono::Duration::hours(1);
+
+        let locations = locations(&[
+            (0.0, 0.0),
+            (0.001, 0.0),
+            (0.002, 0.0),
+            (0.002, 0.0),
+            (0.002, 0.0),
+            (0.003, 0.0),
+            (0.004, 0.0),
+        ]);
+
+        assert_eq!(
+            kept_indexes(select_kept(&locations, hour, 30.0)),
+            vec![0, 2, 4, 6]
+        );
+    }
+
+    #[test]
+    fn keeps_turns_of_at_least_the_min_heading_change() {
+        let hour = chrono::Duration::hours(1);
+
+        let locations = locations(&[
+            (0.0, 0.0),
+            (0.001, 0.0),
+            (0.002, 0.0),
+            (0.002, 0.001),
+            (0.002, 0.002),
+        ]);
+
+        let turn = heading_change(&locations[1], &locations[2], &locations[3]);
+
+        assert_eq!(
+            kept_indexes(select_kept(&locations, hour, turn)),
+            vec![0, 2, 4]
+        );
+        assert_eq!(
+            kept_indexes(select_kept(&locations, hour, turn + 1.0)),
+            vec![0, 4]
+        );
+    }
+
+    #[test]
+    fn collapses_straight_lines_to_the_resolution() {
+        let coordinates: Vec<(f64, f64)> = (0..=60).map(|i| (0.001 * i as f64, 0.0)).collect();
+
+        let kept = select_kept(
+            &locations(&coordinates),
+            chrono::Duration::seconds(60),
+            30.0,
+        );
+
+        assert_eq!(kept_indexes(kept), (0..=60).step_by(6).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn keeps_the_first_and_last_locations() {
+        let hour = chrono::Duration::hours(1);
+
+        let stopped = locations(&[(0.0, 0.0); 5]);
+
+        assert_eq!(kept_indexes(select_kept(&stopped, hour, 30.0)), vec![0, 4]);
+        assert_eq!(
+            kept_indexes(select_kept(&stopped[..1], hour, 30.0)),
+            vec![0]
+        );
+        assert!(select_kept(&[], hour, 30.0).is_empty());
+    }
+
+    #[test]
+    fn keeps_the_locations_not_yet_on_the_odometer() {
+        let cutoff = Utc::now() - chrono::Duration::days(30);
+        let processed_until = cutoff - chrono::Duration::days(1);
+
+        assert_eq!(downsample_until(cutoff, false, None), Some(cutoff));
+        assert_eq!(
+            downsample_until(cutoff, true, Some(processed_until)),
+            Some(processed_until)
+        );
+        assert_eq!(
+            downsample_until(cutoff, true, Some(Utc::now())),
+            Some(cutoff)
+        );
+        assert_eq!(downsample_until(cutoff, true, None), None);
+    }
+}
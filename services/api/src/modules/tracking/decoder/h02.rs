@@ -1,9 +1,10 @@
 use super::super::utils;
 use crate::modules::tracking::dto::PositionDto;
+use chrono::Utc;
 use lapin::message::Delivery;
 use sea_orm::DatabaseConnection;
 use socketioxide::SocketIo;
-use tracing::error;
+use tracing::{error, warn};
 
 #[tracing::instrument(skip_all)]
 pub async fn handle_location(
@@ -17,22 +18,46 @@ pub async fn handle_location(
 
     match parse_result {
         Ok(decoded) => {
-            let _ = utils::insert_vehicle_tracker_location(
-                db,
-                decoded.timestamp,
-                tracker_id,
-                decoded.lat,
-                decoded.lng,
-            )
-            .await;
-
-            let position = PositionDto {
-                lat: decoded.lat,
-                lng: decoded.lng,
-                timestamp: decoded.timestamp,
+            if let Err(reason) = utils::check_location_plausibility(decoded.lat, decoded.lng) {
+                warn!(
+                    tracker_id,
+                    lat = decoded.lat,
+                    lng = decoded.lng,
+                    "dropping implausible H02 location: {reason}"
+                );
+
+                utils::record_rejected_location(reason);
+                return;
+            }
+
+            let received_at = decoded.received_at.unwrap_or_else(Utc::now);
+            let (time, device_time_implausible) =
+                utils::location_time(decoded.timestamp, received_at);
+
+            if device_time_implausible {
+                warn!(
+                    tracker_id,
+                    device_time = %decoded.timestamp,
+                    received_at = %received_at,
+                    "implausible H02 device time, storing the location at the time it was received"
+                );
+            }
+
+            let location = utils::NewLocation {
                 tracker_id,
+                time,
+                device_time: decoded.timestamp,
+                received_at,
+                lat: utils::round_coordinate(decoded.lat),
+                lng: utils::round_coordinate(decoded.lng),
             };
 
+            let _ = utils::insert_vehicle_tracker_location(db, &location).await;
+
+            let position = PositionDto::new(tracker_id, time, location.lat, location.lng)
+                .with_device_time_and_received_at(Some(decoded.timestamp), Some(received_at))
+                .with_speed_and_heading(decoded.speed, decoded.direction);
+
             let _ = socket
                 .of("/tracking")
                 .expect("/tracking socket io namespace not available")
@@ -44,3 +69,38 @@ pub async fn handle_location(
         }
     }
 }
+
+/// holds a location of a tracker whose IMEI is not registered, see `utils::insert_unknown_imei_location`
+#[tracing::instrument(skip_all)]
+pub async fn hold_location(delivery: &Delivery, imei: &str, db: &DatabaseConnection) {
+    let decoded: shared::dto::decoder::h02::LocationMsg =
+        match serde_json::from_slice(delivery.data.as_slice()) {
+            Ok(decoded) => decoded,
+            Err(e) => {
+                error!("failed to parse H02 location: {e}");
+                return;
+            }
+        };
+
+    if let Err(reason) = utils::check_location_plausibility(decoded.lat, decoded.lng) {
+        utils::record_rejected_location(reason);
+        return;
+    }
+
+    let received_at = decoded.received_at.unwrap_or_else(Utc::now);
+    let (time, _) = utils::location_time(decoded.timestamp, received_at);
+
+    let location = utils::NewUnknownImeiLocation {
+        imei,
+        protocol: "h02",
+        time,
+        device_time: decoded.timestamp,
+        received_at,
+        lat: utils::round_coordinate(decoded.lat),
+        lng: utils::round_coordinate(decoded.lng),
+    };
+
+    if let Err(e) = utils::insert_unknown_imei_location(db, &location).await {
+        error!("failed to hold H02 location of unknown imei {imei}: {e}");
+    }
+}
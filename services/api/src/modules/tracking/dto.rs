@@ -1,22 +1,141 @@
+use super::utils::{decode_location_point, StoredLocation, UnexpectedGeometry};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use utoipa::ToSchema;
+use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
+/// A tracker position, returned by every endpoint and event with tracker positions
 #[derive(Serialize, ToSchema)]
 #[serde(rename_all = "camelCase")]
 pub struct PositionDto {
+    pub tracker_id: i32,
     pub lat: f64,
     pub lng: f64,
+
+    /// time of the position, the time reported by the tracker unless it
+    /// was implausible, in which case the time the position was received
+    pub time: DateTime<Utc>,
+
+    /// time reported by the tracker, `null` on positions stored before it was recorded
+    pub device_time: Option<DateTime<Utc>>,
+
+    /// time the position was received, `null` on positions stored before it was recorded,
+    /// compare it to `deviceTime` to detect tracker clock skew and late positions
+    pub received_at: Option<DateTime<Utc>>,
+
+    /// if the time reported by the tracker was implausible and not used as the position `time`
+    pub device_time_implausible: bool,
+
+    /// speed in km/h, only present on positions sent in real time
+    pub speed: Option<f64>,
+
+    /// direction in degrees (0 = north, 90 = east), only present on positions sent in real time
+    pub heading: Option<i32>,
+
+    /// same as `time`, kept for backward compatibility
+    #[schema(deprecated)]
     pub timestamp: DateTime<Utc>,
-    pub tracker_id: i32,
+
+    /// `lat` and `lng` as `x` and `y`, kept for backward compatibility
+    #[schema(deprecated)]
+    pub point: Point,
+}
+
+impl PositionDto {
+    pub fn new(tracker_id: i32, time: DateTime<Utc>, lat: f64, lng: f64) -> Self {
+        Self {
+            tracker_id,
+            lat,
+            lng,
+            time,
+            device_time: None,
+            received_at: None,
+            device_time_implausible: false,
+            speed: None,
+            heading: None,
+            timestamp: time,
+            point: Point { x: lat, y: lng },
+        }
+    }
+
+    /// creates the position from a stored location point, where `x` is the latitude
+    pub fn from_point(tracker_id: i32, time: DateTime<Utc>, point: geo_types::Point<f64>) -> Self {
+        Self::new(tracker_id, time, point.x(), point.y())
+    }
+
+    /// creates the position from a decoded stored location, failing if its not a point
+    pub fn from_location(
+        tracker_id: i32,
+        time: DateTime<Utc>,
+        location: &geozero::wkb::Decode<geo_types::Geometry<f64>>,
+    ) -> Result<Self, UnexpectedGeometry> {
+        let point = decode_location_point(tracker_id, location)?;
+
+        Ok(Self::from_point(tracker_id, time, point))
+    }
+
+    /// creates the position from a stored location row, failing if its not a point
+    pub fn from_stored_location(location: &StoredLocation) -> Result<Self, UnexpectedGeometry> {
+        Ok(
+            Self::from_location(location.vehicle_tracker_id, location.time, &location.point)?
+                .with_device_time_and_received_at(location.device_time, location.received_at),
+        )
+    }
+
+    pub fn with_device_time_and_received_at(
+        mut self,
+        device_time: Option<DateTime<Utc>>,
+        received_at: Option<DateTime<Utc>>,
+    ) -> Self {
+        self.device_time_implausible = device_time.is_some_and(|t| t != self.time);
+        self.device_time = device_time;
+        self.received_at = received_at;
+        self
+    }
+
+    /// swaps `lat` and `lng`, `/tracking/last-positions` always returned the stored `y` as the
+    /// latitude and clients rely on it, so it keeps the swapped axes to not break them
+    pub fn with_legacy_last_position_axes(mut self) -> Self {
+        std::mem::swap(&mut self.lat, &mut self.lng);
+        self.point = Point {
+            x: self.lat,
+            y: self.lng,
+        };
+        self
+    }
+
+    pub fn with_speed_and_heading(mut self, speed: f64, heading: i32) -> Self {
+        self.speed = Some(speed);
+        self.heading = Some(heading);
+        self
+    }
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct Point {
+    pub x: f64,
+    pub y: f64,
 }
 
 /// SocketIO connection payload
 #[derive(Deserialize)]
 pub struct AuthPayload {
-    /// A short lived token for a rastercar API user
-    pub token: String,
+    /// A single use connection ticket, see: `POST /tracking/connection-ticket`
+    pub ticket: Option<String>,
+
+    /// A short lived token for a rastercar API user, only accepted
+    /// if `SOCKETIO_ALLOW_JWT_AUTH` is enabled
+    pub token: Option<String>,
+}
+
+/// A single use ticket to authenticate a SocketIO connection
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConnectionTicketDto {
+    /// the ticket to send as `ticket` on the SocketIO handshake payload
+    pub ticket: String,
+
+    pub expires_at: DateTime<Utc>,
 }
 
 #[derive(Deserialize, Validate, ToSchema)]
@@ -25,3 +144,161 @@ pub struct GetTrackersLastPositionsDto {
     #[validate(length(min = 1, max = 20))]
     pub ids: Vec<i32>,
 }
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetClusteredLastPositionsDto {
+    /// southmost latitude of the bounding box, the bounding box is required unless `area` is set
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub min_lat: Option<f64>,
+
+    /// westmost longitude of the bounding box
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub min_lng: Option<f64>,
+
+    /// northmost latitude of the bounding box
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub max_lat: Option<f64>,
+
+    /// eastmost longitude of the bounding box
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub max_lng: Option<f64>,
+
+    /// polygon to get the positions within, as WKT or hex encoded (E)WKB with the
+    /// longitude as `x` (eg: `POLYGON((-46.7 -23.6, -46.6 -23.6, -46.6 -23.5, -46.7 -23.6))`),
+    /// without the bounding box fields its bounding box is used
+    #[validate(length(max = 65536))]
+    pub area: Option<String>,
+
+    /// zoom level of the map, as in web mercator tiles (0 shows the whole world)
+    #[validate(range(min = 0, max = 22))]
+    pub zoom: u8,
+}
+
+/// A cluster of trackers last positions
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PositionClusterDto {
+    /// latitude of the cluster centroid
+    pub lat: f64,
+
+    /// longitude of the cluster centroid
+    pub lng: f64,
+
+    /// amount of trackers on the cluster
+    pub count: i64,
+
+    /// id of the tracker if the cluster contains a single tracker
+    pub tracker_id: Option<i32>,
+}
+
+#[derive(Deserialize, Validate, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GetPositionsHeatmapDto {
+    /// southmost latitude of the bounding box
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub min_lat: f64,
+
+    /// westmost longitude of the bounding box
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub min_lng: f64,
+
+    /// northmost latitude of the bounding box
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub max_lat: f64,
+
+    /// eastmost longitude of the bounding box
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub max_lng: f64,
+
+    /// use positions from this timestamp onwards
+    pub after: DateTime<Utc>,
+
+    /// use positions before this timestamp, at most 31 days after `after`
+    pub before: DateTime<Utc>,
+
+    /// size in degrees of the grid cells positions are binned on, the bounding box
+    /// must not be split in more than 100000 cells
+    #[validate(range(min = 0.0001, max = 1.0))]
+    pub cell_size: f64,
+}
+
+/// A heatmap grid cell with positions
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HeatmapCellDto {
+    /// latitude of the centroid of the cell positions
+    pub lat: f64,
+
+    /// longitude of the centroid of the cell positions
+    pub lng: f64,
+
+    /// amount of positions on the cell
+    pub count: i64,
+
+    /// seconds vehicles spent on the cell, the time between each position and the next
+    /// one of the same tracker, at most 30 minutes per position
+    pub dwell_seconds: i64,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct ListUnknownImeisDto {
+    /// Search by IMEI
+    pub imei: Option<String>,
+}
+
+/// A unregistered IMEI that is sending locations
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownImeiDto {
+    pub imei: String,
+
+    /// protocol the IMEI locations were decoded from, eg: `h02`
+    pub protocol: String,
+
+    /// amount of held locations of the IMEI
+    pub location_count: i64,
+
+    /// time the oldest held location was received
+    pub first_received_at: DateTime<Utc>,
+
+    /// time the most recent held location was received
+    pub last_received_at: DateTime<Utc>,
+}
+
+/// A held location of a unregistered IMEI
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UnknownImeiLocationDto {
+    pub lat: f64,
+    pub lng: f64,
+
+    /// time of the location, the time reported by the tracker unless it
+    /// was implausible, in which case the time the location was received
+    pub time: DateTime<Utc>,
+
+    /// time reported by the tracker
+    pub device_time: DateTime<Utc>,
+
+    /// time the location was received
+    pub received_at: DateTime<Utc>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn legacy_last_position_axes_swap_lat_and_lng() {
+        let stored = geo_types::Point::new(-22.9, -43.2);
+
+        let position = PositionDto::from_point(1, Utc::now(), stored);
+        assert_eq!((position.lat, position.lng), (-22.9, -43.2));
+
+        let legacy = position.with_legacy_last_position_axes();
+        assert_eq!((legacy.lat, legacy.lng), (-43.2, -22.9));
+        assert_eq!((legacy.point.x, legacy.point.y), (-43.2, -22.9));
+    }
+}
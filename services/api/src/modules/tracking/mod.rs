@@ -1,6 +1,8 @@
 pub mod background;
 pub mod cache;
+pub mod compaction;
 pub mod decoder;
 pub mod dto;
 pub mod routes;
+pub mod unknown_imei;
 pub mod utils;
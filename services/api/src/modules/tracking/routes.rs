@@ -1,27 +1,74 @@
-use super::dto::{AuthPayload, GetTrackersLastPositionsDto, PositionDto};
+use super::dto::{
+    AuthPayload, ConnectionTicketDto, GetClusteredLastPositionsDto, GetPositionsHeatmapDto,
+    GetTrackersLastPositionsDto, HeatmapCellDto, ListUnknownImeisDto, PositionClusterDto,
+    PositionDto, UnknownImeiDto, UnknownImeiLocationDto,
+};
+use super::utils::{log_unexpected_geometry, StoredLocation, StoredPoint};
 use crate::{
+    config::app_config,
+    database::error::DbError,
     modules::{
-        auth::{self, jwt, service::AuthService},
+        auth::{self, jwt, middleware::RequestUser, service::AuthService},
         common::{
-            extractors::{DbConnection, OrganizationId, ValidatedJson},
+            dto::{Pagination, PaginationResult},
+            error_codes::{IP_NOT_ALLOWED, ORGANIZATION_BLOCKED},
+            extractors::{DbConnection, OrganizationId, SuperUser, ValidatedJson, ValidatedQuery},
+            geometry,
             responses::{internal_error_res, SimpleError},
         },
+        organization::ip_allowlist,
     },
     server::controller::AppState,
 };
-use axum::{routing::post, Json, Router};
+use anyhow::Context;
+use axum::{
+    extract::Path,
+    routing::{get, post},
+    Extension, Json, Router,
+};
+use axum_client_ip::{SecureClientIp, SecureClientIpSource};
 use chrono::{DateTime, Utc};
+use geo_types::Geometry;
 use http::StatusCode;
-use sea_orm::{entity::prelude::*, QuerySelect, QueryTrait};
+use sea_orm::{entity::prelude::*, FromQueryResult, Order, QueryOrder, QuerySelect, QueryTrait};
 use sea_query::{Cond, PostgresQueryBuilder, Query as SeaQuery};
 use sea_query_binder::SqlxBinder;
-use shared::entity::{user, vehicle_tracker, vehicle_tracker_last_location};
+use shared::constants::FeatureFlag;
+use shared::entity::{
+    organization, unknown_imei_location, user, vehicle_tracker, vehicle_tracker_last_location,
+};
 use socketioxide::extract::{Data, SocketRef, State, TryData};
+use std::net::IpAddr;
 
 /// The maximun amount of trackers a user can
 /// listen to for realtime position updates
 const TRACKER_SUBSCRIPTION_PER_USER_LIMIT: usize = 20;
 
+/// From this zoom level onwards last positions are not clustered
+const MAX_CLUSTERING_ZOOM: u8 = 16;
+
+/// Approximate size in pixels of a cluster on the map, used to
+/// calculate the size of the clustering grid cells for a zoom level
+const CLUSTER_PIXEL_SIZE: f64 = 64.0;
+
+/// Maximum time range of a positions heatmap query
+const MAX_HEATMAP_DAYS: i64 = 31;
+
+/// Maximum amount of grid cells the bounding box of a positions heatmap can be split in
+const MAX_HEATMAP_CELLS: f64 = 100_000.0;
+
+/// Maximum time between two positions of a tracker counted as time spent on the cell of the
+/// first one, so a tracker that stopped reporting does not inflate the cell it was last seen
+const MAX_HEATMAP_DWELL_SECONDS: f64 = 30.0 * 60.0;
+
+/// Speed up to which the heatmap dwell times are exact, see `heatmap_scan_bbox`
+const HEATMAP_MAX_EXACT_SPEED_KMH: f64 = 110.0;
+
+/// Maximum time a positions heatmap query can run for before being cancelled
+const HEATMAP_STATEMENT_TIMEOUT_SECONDS: u32 = 30;
+
+const KM_PER_LAT_DEGREE: f64 = 111.32;
+
 /// The authenticated user connected to a socket
 #[derive(Clone, Copy)]
 struct SocketUser {
@@ -33,6 +80,17 @@ struct SocketUser {
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/last-positions", post(get_trackers_last_positions))
+        .route(
+            "/last-positions/clustered",
+            post(get_clustered_last_positions),
+        )
+        .route("/heatmap", post(get_positions_heatmap))
+        .route("/connection-ticket", post(create_connection_ticket))
+        .route("/unknown-imeis", get(list_unknown_imeis))
+        .route(
+            "/unknown-imeis/:imei/locations",
+            get(list_unknown_imei_locations),
+        )
         .layer(axum::middleware::from_fn_with_state(
             state,
             auth::middleware::require_user,
@@ -40,6 +98,12 @@ pub fn create_router(state: AppState) -> Router<AppState> {
 }
 
 /// Gets the most recent positions of a few trackers
+///
+/// at most 20 trackers can be requested, so this is not paginated, positions
+/// are ordered by tracker id
+///
+/// for backward compatibility `lat` and `lng` are swapped in relation to the other
+/// endpoints returning positions, eg: `GET /tracker/{tracker_id}/last-location`
 #[utoipa::path(
     post,
     tag = "tracking",
@@ -81,6 +145,8 @@ pub async fn get_trackers_last_positions(
         .column(vehicle_tracker_last_location::Column::Time)
         .column(vehicle_tracker_last_location::Column::Point)
         .column(vehicle_tracker_last_location::Column::VehicleTrackerId)
+        .column(vehicle_tracker_last_location::Column::DeviceTime)
+        .column(vehicle_tracker_last_location::Column::ReceivedAt)
         .from(vehicle_tracker_last_location::Entity)
         .cond_where(
             Cond::all().add(
@@ -88,37 +154,371 @@ pub async fn get_trackers_last_positions(
                     .is_in(valid_tracker_ids),
             ),
         )
+        .order_by(
+            vehicle_tracker_last_location::Column::VehicleTrackerId,
+            sea_query::Order::Asc,
+        )
         .to_owned()
         .build_sqlx(PostgresQueryBuilder);
 
     let positions: Vec<PositionDto> = sqlx::query_as_with(&q, args)
+        .fetch_all(db.get_postgres_connection_pool())
+        .await
+        .map_err(|_| internal_error_res())?
+        .iter()
+        .filter_map(|row: &StoredLocation| {
+            log_unexpected_geometry(PositionDto::from_stored_location(row))
+        })
+        .map(PositionDto::with_legacy_last_position_axes)
+        .collect();
+
+    Ok(Json(positions))
+}
+
+/// Gets the last positions of the organization trackers clustered
+///
+/// clusters the last positions within a bounding box on a grid with cells sized
+/// according to the zoom level, returning the amount of trackers and the centroid
+/// of each cluster. From zoom level 16 onwards positions are not clustered and every
+/// tracker is returned individually.
+///
+/// instead of (or in addition to) the bounding box a polygon `area` can be sent as
+/// WKT or hex encoded WKB, to only cluster the positions within it, the polygon must
+/// be closed, simple and without holes.
+///
+/// Required feature flag: CLUSTERED_POSITIONS
+#[utoipa::path(
+    post,
+    tag = "tracking",
+    path = "/tracking/last-positions/clustered",
+    security(("session_id" = [])),
+    request_body = GetClusteredLastPositionsDto,
+    responses(
+        (
+            status = OK,
+            description = "the clustered positions",
+            body = Vec<PositionClusterDto>,
+            content_type = "application/json",
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto, bounding box or area",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "FEATURE_NOT_ENABLED",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[tracing::instrument(
+    skip_all,
+    fields(
+        org_id = %org_id,
+        zoom = %dto.zoom,
+    )
+)]
+pub async fn get_clustered_last_positions(
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+    axum::extract::State(state): axum::extract::State<AppState>,
+    ValidatedJson(dto): ValidatedJson<GetClusteredLastPositionsDto>,
+) -> Result<Json<Vec<PositionClusterDto>>, (StatusCode, SimpleError)> {
+    state
+        .feature_flags
+        .require(org_id, FeatureFlag::ClusteredPositions)
+        .await?;
+
+    let area = dto
+        .area
+        .as_deref()
+        .map(geometry::parse_polygon)
+        .transpose()
+        .map_err(|e| (StatusCode::BAD_REQUEST, SimpleError::from(e)))?;
+
+    let bounding_box = match (dto.min_lat, dto.min_lng, dto.max_lat, dto.max_lng, &area) {
+        (Some(min_lat), Some(min_lng), Some(max_lat), Some(max_lng), _) => {
+            (min_lat, min_lng, max_lat, max_lng)
+        }
+        (None, None, None, None, Some(area)) => {
+            let rect = geometry::polygon_bounding_rect(area).ok_or_else(internal_error_res)?;
+            (rect.min().x, rect.min().y, rect.max().x, rect.max().y)
+        }
+        _ => {
+            return Err((
+                StatusCode::BAD_REQUEST,
+                SimpleError::from("either all the bounding box coordinates or a area are required"),
+            ))
+        }
+    };
+
+    let (min_lat, min_lng, max_lat, max_lng) = bounding_box;
+
+    if min_lat > max_lat || min_lng > max_lng {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("bounding box min coordinates must not exceed its max coordinates"),
+        ));
+    }
+
+    let area_wkt = area
+        .as_ref()
+        .map(geometry::polygon_to_wkt)
+        .transpose()
+        .map_err(|_| internal_error_res())?;
+
+    // points are stored with the latitude as X and the longitude as Y,
+    // see: `insert_vehicle_tracker_location`
+    let grouping = if dto.zoom >= MAX_CLUSTERING_ZOOM {
+        "l.vehicle_tracker_id"
+    } else {
+        "ST_SnapToGrid(l.point, $7)"
+    };
+
+    let sql = format!(
+        r#"
+SELECT
+    ST_X(ST_Centroid(ST_Collect(l.point))),
+    ST_Y(ST_Centroid(ST_Collect(l.point))),
+    count(*),
+    min(l.vehicle_tracker_id)
+FROM vehicle_tracker_last_location l
+INNER JOIN vehicle_tracker t ON t.id = l.vehicle_tracker_id
+WHERE t.organization_id = $1
+AND l.point && ST_MakeEnvelope($2, $3, $4, $5, 4326)
+AND ($6::text IS NULL OR ST_Within(l.point, ST_GeomFromText($6, 4326)))
+GROUP BY {}
+        "#,
+        grouping
+    );
+
+    // a web mercator tile is 256 pixels wide and covers 360 / 2^zoom degrees
+    let grid_cell_size = 360.0 / 2f64.powi(dto.zoom as i32) / 256.0 * CLUSTER_PIXEL_SIZE;
+
+    let mut query = sqlx::query_as::<_, (f64, f64, i64, i32)>(&sql)
+        .bind(org_id)
+        .bind(min_lat)
+        .bind(min_lng)
+        .bind(max_lat)
+        .bind(max_lng)
+        .bind(area_wkt);
+
+    if dto.zoom < MAX_CLUSTERING_ZOOM {
+        query = query.bind(grid_cell_size);
+    }
+
+    let clusters = query
         .fetch_all(db.get_postgres_connection_pool())
         .await
         .map_err(|_| internal_error_res())?
         .into_iter()
-        .filter_map(
-            |row: (
-                DateTime<Utc>,
-                geozero::wkb::Decode<geo_types::Geometry<f64>>,
-                i32,
-            )| {
-                if let Some(geo_types::Geometry::Point(point)) = row.1.geometry {
-                    let loc = PositionDto {
-                        lat: point.y(),
-                        lng: point.x(),
-                        timestamp: row.0,
-                        tracker_id: row.2,
-                    };
-
-                    return Some(loc);
-                }
+        .map(|(lat, lng, count, tracker_id)| PositionClusterDto {
+            lat,
+            lng,
+            count,
+            tracker_id: (count == 1).then_some(tracker_id),
+        })
+        .collect();
 
-                None
-            },
-        )
+    Ok(Json(clusters))
+}
+
+/// Gets a heatmap of the organization trackers positions
+///
+/// bins the positions within a bounding box and time range on a grid, returning the
+/// centroid of the positions of each cell with positions, along with how many positions
+/// there are and how long vehicles spent on it. Unlike the clustered last positions,
+/// this uses every position so it shows where vehicles spend their time.
+///
+/// the time range must not exceed 31 days and the bounding box must not be split
+/// in more than 100000 cells, heatmaps taking over 30 seconds are cancelled.
+#[utoipa::path(
+    post,
+    tag = "tracking",
+    path = "/tracking/heatmap",
+    security(("session_id" = [])),
+    request_body = GetPositionsHeatmapDto,
+    responses(
+        (
+            status = OK,
+            description = "the heatmap cells",
+            body = Vec<HeatmapCellDto>,
+            content_type = "application/json",
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto, bounding box, time range or cell size",
+            body = SimpleError,
+        ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "the heatmap took too long and was cancelled",
+            body = SimpleError,
+        ),
+    ),
+)]
+#[tracing::instrument(
+    skip_all,
+    fields(
+        org_id = %org_id,
+        cell_size = %dto.cell_size,
+    )
+)]
+pub async fn get_positions_heatmap(
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+    ValidatedJson(dto): ValidatedJson<GetPositionsHeatmapDto>,
+) -> Result<Json<Vec<HeatmapCellDto>>, (StatusCode, SimpleError)> {
+    if dto.min_lat > dto.max_lat || dto.min_lng > dto.max_lng {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("bounding box min coordinates must not exceed its max coordinates"),
+        ));
+    }
+
+    if dto.before <= dto.after {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("before must be after the after timestamp"),
+        ));
+    }
+
+    if dto.before - dto.after > chrono::Duration::days(MAX_HEATMAP_DAYS) {
+        let err_msg = format!("time range must not exceed {MAX_HEATMAP_DAYS} days");
+        return Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)));
+    }
+
+    let cell_count = ((dto.max_lat - dto.min_lat) / dto.cell_size)
+        .ceil()
+        .max(1.0)
+        * ((dto.max_lng - dto.min_lng) / dto.cell_size)
+            .ceil()
+            .max(1.0);
+
+    if cell_count > MAX_HEATMAP_CELLS {
+        let err_msg = format!("cell size too small, at most {MAX_HEATMAP_CELLS} cells are allowed");
+        return Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)));
+    }
+
+    // the dwell time is computed before filtering by the bounding box, so the time until
+    // a tracker leaves the bounding box is counted on the cell it was last seen within it,
+    // only the locations within the expanded scan bounding box are needed for it.
+    //
+    // points are stored with the latitude as X and the longitude as Y,
+    // see: `insert_vehicle_tracker_location`
+    let sql = r#"
+WITH l AS (
+    SELECT
+        l.point,
+        COALESCE(
+            EXTRACT(EPOCH FROM lead(l.time) OVER (PARTITION BY l.vehicle_tracker_id ORDER BY l.time) - l.time),
+            0
+        )::float8 AS dwell_seconds
+    FROM vehicle_tracker_location l
+    INNER JOIN vehicle_tracker t ON t.id = l.vehicle_tracker_id
+    WHERE t.organization_id = $1
+    AND l.time >= $6
+    AND l.time < $7
+    AND l.point && ST_MakeEnvelope($10, $11, $12, $13, 4326)
+)
+SELECT
+    ST_X(ST_Centroid(ST_Collect(l.point))),
+    ST_Y(ST_Centroid(ST_Collect(l.point))),
+    count(*),
+    round(sum(LEAST(l.dwell_seconds, $9)))::bigint
+FROM l
+WHERE l.point && ST_MakeEnvelope($2, $3, $4, $5, 4326)
+GROUP BY ST_SnapToGrid(l.point, $8)
+    "#;
+
+    let (scan_min_lat, scan_min_lng, scan_max_lat, scan_max_lng) =
+        heatmap_scan_bbox(dto.min_lat, dto.min_lng, dto.max_lat, dto.max_lng);
+
+    let mut tx = db
+        .get_postgres_connection_pool()
+        .begin()
+        .await
+        .map_err(|_| internal_error_res())?;
+
+    // big organizations on wide bounding boxes and time ranges can still read millions
+    // of locations, so the query is cancelled instead of holding the connection
+    sqlx::query(&format!(
+        "SET LOCAL statement_timeout = '{HEATMAP_STATEMENT_TIMEOUT_SECONDS}s'"
+    ))
+    .execute(&mut *tx)
+    .await
+    .map_err(|_| internal_error_res())?;
+
+    let rows = sqlx::query_as::<_, (f64, f64, i64, i64)>(sql)
+        .bind(org_id)
+        .bind(dto.min_lat)
+        .bind(dto.min_lng)
+        .bind(dto.max_lat)
+        .bind(dto.max_lng)
+        .bind(dto.after)
+        .bind(dto.before)
+        .bind(dto.cell_size)
+        .bind(MAX_HEATMAP_DWELL_SECONDS)
+        .bind(scan_min_lat)
+        .bind(scan_min_lng)
+        .bind(scan_max_lat)
+        .bind(scan_max_lng)
+        .fetch_all(&mut *tx)
+        .await
+        .map_err(|e| {
+            // 57014 is the query_canceled error code, raised on statement timeouts
+            if e.as_database_error().and_then(|e| e.code()).as_deref() == Some("57014") {
+                let err_msg = "the heatmap took too long, use a smaller bounding box or time range";
+                return (StatusCode::SERVICE_UNAVAILABLE, SimpleError::from(err_msg));
+            }
+
+            internal_error_res()
+        })?;
+
+    // only reads, so the transaction is rolled back when dropped
+    drop(tx);
+
+    let cells = rows
+        .into_iter()
+        .map(|(lat, lng, count, dwell_seconds)| HeatmapCellDto {
+            lat,
+            lng,
+            count,
+            dwell_seconds,
+        })
         .collect();
 
-    Ok(Json(positions))
+    Ok(Json(cells))
+}
+
+/// the bounding box of the locations read to compute a heatmap, the heatmap bounding box
+/// expanded by how far a vehicle at `HEATMAP_MAX_EXACT_SPEED_KMH` travels in
+/// `MAX_HEATMAP_DWELL_SECONDS`, so the next location of a tracker that left the heatmap
+/// bounding box is still read, unless it is so far that its dwell time would be capped
+fn heatmap_scan_bbox(
+    min_lat: f64,
+    min_lng: f64,
+    max_lat: f64,
+    max_lng: f64,
+) -> (f64, f64, f64, f64) {
+    let margin_km = HEATMAP_MAX_EXACT_SPEED_KMH * MAX_HEATMAP_DWELL_SECONDS / 3600.0;
+    let lat_margin = margin_km / KM_PER_LAT_DEGREE;
+
+    let min_lat = (min_lat - lat_margin).max(-90.0);
+    let max_lat = (max_lat + lat_margin).min(90.0);
+
+    // longitude degrees shrink towards the poles, so the margin is
+    // widened for the latitude of the box closest to them
+    let max_abs_lat = min_lat.abs().max(max_lat.abs());
+    let lng_margin = (lat_margin / max_abs_lat.to_radians().cos()).min(360.0);
+
+    (
+        min_lat,
+        (min_lng - lng_margin).max(-180.0),
+        max_lat,
+        (max_lng + lng_margin).min(180.0),
+    )
 }
 
 /// Given a vec of tracker ids, return only those that
@@ -145,12 +545,238 @@ async fn get_existing_tracker_ids(
     Ok(cnt)
 }
 
-/// extracts a user ID from the JWT within a SocketIO payload
-fn get_user_id_from_token(
+/// A unregistered IMEI with the stats of its held locations
+#[derive(FromQueryResult)]
+struct UnknownImeiRow {
+    imei: String,
+    protocol: String,
+    location_count: i64,
+    first_received_at: DateTime<Utc>,
+    last_received_at: DateTime<Utc>,
+}
+
+/// List unregistered IMEIs
+///
+/// lists the IMEIs of the trackers sending locations that are not registered, so installers
+/// can check a device is reporting before registering it, most recently seen IMEIs first.
+///
+/// locations of unregistered IMEIs are only held for a while, see `UNKNOWN_IMEI_LOCATION_RETENTION_HOURS`
+#[utoipa::path(
+    get,
+    tag = "tracking",
+    path = "/tracking/unknown-imeis",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListUnknownImeisDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of unregistered IMEIs",
+            content_type = "application/json",
+            body = PaginatedUnknownImei,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_unknown_imeis(
+    _: SuperUser,
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListUnknownImeisDto>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<UnknownImeiDto>>, (StatusCode, SimpleError)> {
+    let received_at_col = Expr::col((
+        unknown_imei_location::Entity,
+        unknown_imei_location::Column::ReceivedAt,
+    ));
+
+    let paginator = unknown_imei_location::Entity::find()
+        .select_only()
+        .column(unknown_imei_location::Column::Imei)
+        .column_as(
+            Expr::col(unknown_imei_location::Column::Protocol).max(),
+            "protocol",
+        )
+        .column_as(
+            Expr::col(unknown_imei_location::Column::Id).count(),
+            "location_count",
+        )
+        .column_as(received_at_col.clone().min(), "first_received_at")
+        .column_as(received_at_col.clone().max(), "last_received_at")
+        .apply_if(filter.imei, |query, imei| {
+            if !imei.is_empty() {
+                query.filter(unknown_imei_location::Column::Imei.contains(imei))
+            } else {
+                query
+            }
+        })
+        .group_by(unknown_imei_location::Column::Imei)
+        .order_by(received_at_col.max(), Order::Desc)
+        .order_by_asc(unknown_imei_location::Column::Imei)
+        .into_model::<UnknownImeiRow>()
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|row| UnknownImeiDto {
+            imei: row.imei,
+            protocol: row.protocol,
+            location_count: row.location_count,
+            first_received_at: row.first_received_at,
+            last_received_at: row.last_received_at,
+        })
+        .collect();
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
+/// A held location of a unregistered IMEI
+#[derive(sqlx::FromRow)]
+struct UnknownImeiLocationRow {
+    time: DateTime<Utc>,
+    point: StoredPoint,
+    device_time: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+}
+
+/// List the locations of a unregistered IMEI
+///
+/// lists the held locations of a unregistered IMEI, most recently received first,
+/// at most `UNKNOWN_IMEI_LOCATION_MAX_PER_IMEI` locations are held per IMEI
+#[utoipa::path(
+    get,
+    tag = "tracking",
+    path = "/tracking/unknown-imeis/{imei}/locations",
+    security(("session_id" = [])),
+    params(
+        ("imei" = String, Path, description = "the unregistered IMEI"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the held locations of the IMEI",
+            body = Vec<UnknownImeiLocationDto>,
+            content_type = "application/json",
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user is not a superuser",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_unknown_imei_locations(
+    _: SuperUser,
+    Path(imei): Path<String>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<Vec<UnknownImeiLocationDto>>, (StatusCode, SimpleError)> {
+    let rows: Vec<UnknownImeiLocationRow> = sqlx::query_as(
+        r#"
+SELECT time, point, device_time, received_at
+FROM unknown_imei_location
+WHERE imei = $1
+ORDER BY received_at DESC, id DESC
+LIMIT $2
+        "#,
+    )
+    .bind(&imei)
+    .bind(app_config().unknown_imei_location_max_per_imei as i64)
+    .fetch_all(db.get_postgres_connection_pool())
+    .await
+    .map_err(|_| internal_error_res())?;
+
+    let locations = rows
+        .into_iter()
+        .filter_map(|row| match row.point.geometry {
+            Some(Geometry::Point(point)) => Some(UnknownImeiLocationDto {
+                lat: point.x(),
+                lng: point.y(),
+                time: row.time,
+                device_time: row.device_time,
+                received_at: row.received_at,
+            }),
+            _ => {
+                tracing::error!(imei, "held location of unknown imei is not a point");
+                None
+            }
+        })
+        .collect();
+
+    Ok(Json(locations))
+}
+
+/// Creates a SocketIO connection ticket
+///
+/// creates a single use ticket, valid for a few seconds, to authenticate the request
+/// user on the `/tracking` SocketIO namespace, by sending it as the `ticket` field of
+/// the handshake payload, so long lived credentials are never sent on the handshake.
+#[utoipa::path(
+    post,
+    tag = "tracking",
+    path = "/tracking/connection-ticket",
+    security(("session_id" = [])),
+    responses(
+        (
+            status = OK,
+            description = "the connection ticket",
+            body = ConnectionTicketDto,
+            content_type = "application/json",
+        ),
+    ),
+)]
+pub async fn create_connection_ticket(
+    axum::extract::State(state): axum::extract::State<AppState>,
+    Extension(req_user): Extension<RequestUser>,
+) -> Result<Json<ConnectionTicketDto>, (StatusCode, SimpleError)> {
+    let (ticket, expires_at) = state
+        .auth_service
+        .gen_socket_connection_ticket(req_user.0.id)
+        .await
+        .map_err(|_| internal_error_res())?;
+
+    Ok(Json(ConnectionTicketDto { ticket, expires_at }))
+}
+
+/// extracts a user ID from the connection ticket or JWT within a SocketIO payload
+async fn get_user_id_from_auth_payload(
     TryData(auth_payload): TryData<AuthPayload>,
     auth_service: &AuthService,
 ) -> anyhow::Result<i32> {
-    let token = auth_payload?.token;
+    let auth_payload = auth_payload?;
+
+    if let Some(ticket) = auth_payload.ticket {
+        return auth_service
+            .consume_socket_connection_ticket(&ticket)
+            .await?
+            .context("invalid connection ticket");
+    }
+
+    if !app_config().socketio_allow_jwt_auth {
+        anyhow::bail!("connection ticket required");
+    }
+
+    let token = auth_payload
+        .token
+        .context("connection ticket or token required")?;
     let decoded_token = jwt::decode(&token)?;
 
     let user_id = auth_service.get_user_id_from_token_aud(decoded_token.claims.aud)?;
@@ -227,16 +853,38 @@ async fn on_change_trackers_to_listen(s: SocketRef, Data(tracker_ids): Data<Vec<
     let _ = s.join(rooms);
 }
 
+/// the ip of the client of a SocketIO connection, as extracted by `SecureClientIp`
+fn get_socket_client_ip(socket: &SocketRef) -> Option<IpAddr> {
+    let parts = socket.req_parts();
+    let ip_source = parts.extensions.get::<SecureClientIpSource>()?;
+
+    SecureClientIp::from(ip_source, &parts.headers, &parts.extensions)
+        .ok()
+        .map(|ip| ip.0)
+}
+
+/// if a SocketIO client can connect from its ip, applying the same organization ip allowlist
+/// check of `require_user`, as connections authenticated by a JWT never go through it and
+/// connection tickets can be used from another ip than the one they were generated from
+fn is_socket_ip_allowed(ip_allowlist: Option<&[String]>, client_ip: Option<IpAddr>) -> bool {
+    match ip_allowlist {
+        Some(allowlist) => client_ip.is_some_and(|ip| ip_allowlist::is_allowed(allowlist, ip)),
+        None => true,
+    }
+}
+
 /// callback for when a SocketIO connection is established
 ///
-/// authenticates the user with the JWT with the connection payload
-/// and stablishes the callbacks for client sent events
+/// authenticates the user with the connection ticket (or JWT) of the connection
+/// payload and stablishes the callbacks for client sent events, connections of
+/// users of blocked organizations or from ips not in their organization ip
+/// allowlist are refused
 pub async fn on_connect(
     socket: SocketRef,
     State(state): State<AppState>,
     auth_payload: TryData<AuthPayload>,
 ) {
-    let maybe_user_id = get_user_id_from_token(auth_payload, &state.auth_service);
+    let maybe_user_id = get_user_id_from_auth_payload(auth_payload, &state.auth_service).await;
 
     if maybe_user_id.is_err() {
         let _ = socket.disconnect();
@@ -245,9 +893,31 @@ pub async fn on_connect(
 
     let user_id = maybe_user_id.unwrap_or(0);
 
-    let fetch_user_result = user::Entity::find_by_id(user_id).one(&state.db).await;
+    let fetch_user_result = user::Entity::find_by_id(user_id)
+        .find_also_related(organization::Entity)
+        .one(&state.db)
+        .await;
+
+    if let Ok(Some((user, org))) = fetch_user_result {
+        if let Some(org) = org {
+            let error_code = if org.blocked {
+                Some(ORGANIZATION_BLOCKED)
+            } else if !is_socket_ip_allowed(
+                org.ip_allowlist.as_deref(),
+                get_socket_client_ip(&socket),
+            ) {
+                Some(IP_NOT_ALLOWED)
+            } else {
+                None
+            };
+
+            if let Some(error_code) = error_code {
+                send_error(&socket, error_code);
+                let _ = socket.disconnect();
+                return;
+            }
+        }
 
-    if let Ok(Some(user)) = fetch_user_result {
         let socket_user = SocketUser {
             org_id: user.organization_id,
         };
@@ -262,3 +932,51 @@ pub async fn on_connect(
 
     let _ = socket.disconnect();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_the_heatmap_scan_bbox_by_the_dwell_distance() {
+        // 110 km/h for 30 minutes are 55 km, half a latitude degree
+        let (min_lat, min_lng, max_lat, max_lng) = heatmap_scan_bbox(-1.0, -1.0, 1.0, 1.0);
+
+        assert!((min_lat - -1.494).abs() < 0.001);
+        assert!((max_lat - 1.494).abs() < 0.001);
+        assert!(min_lng < -1.494 && min_lng > -1.5);
+        assert!(max_lng > 1.494 && max_lng < 1.5);
+
+        // the longitude margin widens towards the poles
+        let (_, min_lng, _, max_lng) = heatmap_scan_bbox(59.0, 10.0, 60.0, 11.0);
+
+        assert!((10.0 - min_lng) > 0.98 && (max_lng - 11.0) > 0.98);
+
+        // and never exceeds the valid coordinates
+        assert_eq!(
+            heatmap_scan_bbox(-90.0, -180.0, 90.0, 180.0),
+            (-90.0, -180.0, 90.0, 180.0)
+        );
+    }
+
+    #[test]
+    fn allows_sockets_of_organizations_without_allowlist() {
+        assert!(is_socket_ip_allowed(None, "203.0.113.10".parse().ok()));
+        assert!(is_socket_ip_allowed(None, None));
+    }
+
+    #[test]
+    fn refuses_sockets_from_ips_not_in_the_allowlist() {
+        let allowlist = [String::from("203.0.113.0/24")];
+
+        assert!(is_socket_ip_allowed(
+            Some(&allowlist),
+            "203.0.113.10".parse().ok()
+        ));
+        assert!(!is_socket_ip_allowed(
+            Some(&allowlist),
+            "198.51.100.10".parse().ok()
+        ));
+        assert!(!is_socket_ip_allowed(Some(&allowlist), None));
+    }
+}
@@ -0,0 +1,144 @@
+//! Handling of locations sent by trackers whose IMEI is not registered, see `UNKNOWN_IMEI_POLICY`
+
+use super::decoder::h02;
+use crate::{
+    config::{app_config, UnknownImeiPolicy},
+    modules::{
+        common::validators::is_valid_tracker_imei,
+        globals::TRACKER_ID_CACHE,
+        organization::limits::{self, PlanLimit},
+    },
+    services::outbox::OutboxMessage,
+};
+use anyhow::{anyhow, bail};
+use lapin::message::Delivery;
+use sea_orm::{
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter, QuerySelect, Set,
+    TransactionTrait,
+};
+use shared::{constants::TrackerModel, entity::vehicle_tracker};
+use tracing::{error, info, warn};
+
+/// registers a H02 tracker with the IMEI on the organization, returning its ID, if another
+/// tracker with the IMEI was registered on the organization meanwhile its ID is returned instead.
+///
+/// fails if the IMEI is registered on another organization, as IMEIs are only unique per
+/// organization and a duplicate tracker could get the locations of the existing one, or
+/// if it is not a valid IMEI, as trackers created by users must have valid IMEIs too
+async fn register_tracker(
+    imei: &str,
+    org_id: i32,
+    db: &DatabaseConnection,
+) -> Result<i32, anyhow::Error> {
+    if !is_valid_tracker_imei(imei) {
+        bail!("the IMEI is not a valid tracker IMEI");
+    }
+
+    let txn = db.begin().await?;
+
+    // locks the organization row, so concurrent registrations of the same IMEI are serialized
+    let remaining = limits::remaining(&txn, org_id, PlanLimit::Trackers)
+        .await
+        .map_err(|_| anyhow!("failed to get the tracker limit of organization {org_id}"))?;
+
+    let existing: Option<(i32, i32)> = vehicle_tracker::Entity::find()
+        .select_only()
+        .column(vehicle_tracker::Column::Id)
+        .column(vehicle_tracker::Column::OrganizationId)
+        .filter(vehicle_tracker::Column::Imei.eq(imei))
+        .into_tuple()
+        .one(&txn)
+        .await?;
+
+    match existing {
+        Some((id, existing_org_id)) if existing_org_id == org_id => return Ok(id),
+        Some((id, existing_org_id)) => {
+            bail!("the IMEI is registered as tracker {id} of organization {existing_org_id}")
+        }
+        None => {}
+    }
+
+    if remaining == Some(0) {
+        bail!("organization {org_id} reached its tracker limit");
+    }
+
+    let tracker = vehicle_tracker::ActiveModel {
+        imei: Set(imei.to_owned()),
+        model: Set(TrackerModel::H02),
+        organization_id: Set(org_id),
+        notes: Set(Some(String::from(
+            "registered automatically when it first reported a location",
+        ))),
+        ..Default::default()
+    }
+    .insert(&txn)
+    .await?;
+
+    OutboxMessage::api_event("tracker", tracker.id, "created", &tracker)?
+        .enqueue(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    // failed lookups of the IMEI are counted by the cache, which could make it skip the database
+    if let Some(tracker_id_cache) = TRACKER_ID_CACHE.get() {
+        tracker_id_cache.write().await.delete(imei);
+    }
+
+    Ok(tracker.id)
+}
+
+/// handles a H02 location of a tracker whose IMEI is not registered according to
+/// `UNKNOWN_IMEI_POLICY`, returning the ID of the tracker to store the location on
+/// if one was registered for it
+#[tracing::instrument(skip(delivery, db))]
+pub async fn on_unknown_imei_location(
+    delivery: &Delivery,
+    imei: &str,
+    db: &DatabaseConnection,
+) -> Option<i32> {
+    let config = app_config();
+
+    match config.unknown_imei_policy {
+        UnknownImeiPolicy::Drop => {
+            warn!("tracker: {imei} does not exist, dropping its location");
+            None
+        }
+        UnknownImeiPolicy::Hold => {
+            warn!("tracker: {imei} does not exist, holding its location");
+            h02::hold_location(delivery, imei, db).await;
+            None
+        }
+        UnknownImeiPolicy::AutoRegister => {
+            // required with this policy when the config is loaded
+            let org_id = config.unknown_imei_auto_register_org_id?;
+
+            match register_tracker(imei, org_id, db).await {
+                Ok(tracker_id) => {
+                    info!("tracker: {imei} does not exist, registered it as tracker {tracker_id} on organization {org_id}");
+                    Some(tracker_id)
+                }
+                Err(e) => {
+                    error!("tracker: {imei} does not exist and could not be registered, dropping its location: {e}");
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::modules::common::fixtures;
+
+    #[tokio::test]
+    async fn refuses_registering_invalid_imeis_before_querying_the_database() {
+        // the Luhn check digit of the IMEI is 8
+        let result = register_tracker("490154203237519", 1, &fixtures::unreachable_db()).await;
+
+        let err = result.expect_err("the IMEI should be refused");
+
+        assert_eq!(err.to_string(), "the IMEI is not a valid tracker IMEI");
+    }
+}
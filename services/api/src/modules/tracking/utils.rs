@@ -1,23 +1,320 @@
-use chrono::{DateTime, Utc};
+use crate::config::app_config;
+use chrono::{DateTime, Duration, Utc};
+use geo_types::Geometry;
 use geozero::wkb;
+use opentelemetry::{metrics::Counter, Context, KeyValue};
 use sea_orm::DatabaseConnection;
 use sqlx::postgres::PgQueryResult;
+use std::{fmt, sync::OnceLock};
 
+/// A decoded `point` column of a stored tracker location
+pub type StoredPoint = wkb::Decode<Geometry<f64>>;
+
+/// A stored tracker location, selected with its `time`, `point`, `vehicle_tracker_id`,
+/// `device_time` and `received_at` columns
+#[derive(sqlx::FromRow)]
+pub struct StoredLocation {
+    pub time: DateTime<Utc>,
+    pub point: StoredPoint,
+    pub vehicle_tracker_id: i32,
+    pub device_time: Option<DateTime<Utc>>,
+    pub received_at: Option<DateTime<Utc>>,
+}
+
+/// A stored tracker location whose geometry is not a point, locations are always
+/// inserted as points so this means the location row is corrupted
+#[derive(Debug)]
+pub struct UnexpectedGeometry {
+    pub tracker_id: i32,
+
+    /// type of the decoded geometry, `None` if it could not be decoded
+    pub found: Option<&'static str>,
+}
+
+impl fmt::Display for UnexpectedGeometry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "location of tracker {} is a {} geometry instead of a point",
+            self.tracker_id,
+            self.found.unwrap_or("undecodable")
+        )
+    }
+}
+
+impl std::error::Error for UnexpectedGeometry {}
+
+fn geometry_type_name(geometry: &Geometry<f64>) -> &'static str {
+    match geometry {
+        Geometry::Point(_) => "Point",
+        Geometry::Line(_) => "Line",
+        Geometry::LineString(_) => "LineString",
+        Geometry::Polygon(_) => "Polygon",
+        Geometry::MultiPoint(_) => "MultiPoint",
+        Geometry::MultiLineString(_) => "MultiLineString",
+        Geometry::MultiPolygon(_) => "MultiPolygon",
+        Geometry::GeometryCollection(_) => "GeometryCollection",
+        Geometry::Rect(_) => "Rect",
+        Geometry::Triangle(_) => "Triangle",
+    }
+}
+
+/// gets the point of a decoded tracker location, note that locations are stored
+/// with the latitude as the `x` coordinate and the longitude as the `y` coordinate.
+pub fn decode_location_point(
+    tracker_id: i32,
+    location: &wkb::Decode<Geometry<f64>>,
+) -> Result<geo_types::Point<f64>, UnexpectedGeometry> {
+    match &location.geometry {
+        Some(Geometry::Point(point)) => Ok(*point),
+        geometry => Err(UnexpectedGeometry {
+            tracker_id,
+            found: geometry.as_ref().map(geometry_type_name),
+        }),
+    }
+}
+
+/// logs the error of a location that is not a point, so corrupted locations can
+/// be skipped by endpoints listing locations without failing the whole request
+pub fn log_unexpected_geometry<T>(result: Result<T, UnexpectedGeometry>) -> Option<T> {
+    result
+        .map_err(|err| tracing::error!(tracker_id = err.tracker_id, "{}", err))
+        .ok()
+}
+
+/// A decoded tracker location that cannot be a real position
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImplausibleLocation {
+    /// latitude not in [-90, 90] (or NaN)
+    LatitudeOutOfRange,
+    /// longitude not in [-180, 180] (or NaN)
+    LongitudeOutOfRange,
+    /// exactly (0, 0), usually sent by trackers without a GPS fix
+    NullIsland,
+}
+
+impl ImplausibleLocation {
+    fn as_str(&self) -> &'static str {
+        match self {
+            ImplausibleLocation::LatitudeOutOfRange => "latitude_out_of_range",
+            ImplausibleLocation::LongitudeOutOfRange => "longitude_out_of_range",
+            ImplausibleLocation::NullIsland => "null_island",
+        }
+    }
+}
+
+impl fmt::Display for ImplausibleLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// counter of the decoded locations dropped for not being plausible, by reason,
+/// exported if a opentelemetry meter provider is installed
+static REJECTED_LOCATIONS: OnceLock<Counter<u64>> = OnceLock::new();
+
+/// checks a decoded location can be a real position before it is stored, as a single
+/// garbage location corrupts every trip and odometer calculation it is part of.
+///
+/// (0, 0) is only rejected if `LOCATION_REJECT_NULL_ISLAND` is enabled
+pub fn check_location_plausibility(lat: f64, lng: f64) -> Result<(), ImplausibleLocation> {
+    if !(-90.0..=90.0).contains(&lat) {
+        return Err(ImplausibleLocation::LatitudeOutOfRange);
+    }
+
+    if !(-180.0..=180.0).contains(&lng) {
+        return Err(ImplausibleLocation::LongitudeOutOfRange);
+    }
+
+    if app_config().location_reject_null_island && lat == 0.0 && lng == 0.0 {
+        return Err(ImplausibleLocation::NullIsland);
+    }
+
+    Ok(())
+}
+
+/// increments the rejected locations counter
+pub fn record_rejected_location(reason: ImplausibleLocation) {
+    REJECTED_LOCATIONS
+        .get_or_init(|| {
+            opentelemetry::global::meter("tracking")
+                .u64_counter("tracking.rejected_locations")
+                .init()
+        })
+        .add(
+            &Context::current(),
+            1,
+            &[KeyValue::new("reason", reason.as_str())],
+        );
+}
+
+/// the time a location is stored at, which is the time reported by the tracker unless it is
+/// implausibly ahead or behind the time it was received (see `DEVICE_TIME_MAX_AHEAD_SECONDS`
+/// and `DEVICE_TIME_MAX_BEHIND_DAYS`), in which case the time it was received is used, as a
+/// location at a wrong time would be placed on the wrong trip and day.
+///
+/// returns the time and if the device time was implausible
+pub fn location_time(
+    device_time: DateTime<Utc>,
+    received_at: DateTime<Utc>,
+) -> (DateTime<Utc>, bool) {
+    let config = app_config();
+
+    let max_ahead = Duration::seconds(config.device_time_max_ahead_seconds as i64);
+    let max_behind = Duration::days(config.device_time_max_behind_days as i64);
+
+    if device_time > received_at + max_ahead || device_time < received_at - max_behind {
+        (received_at, true)
+    } else {
+        (device_time, false)
+    }
+}
+
+/// rounds a coordinate to the configured `LOCATION_COORDINATE_DECIMALS`, if any
+pub fn round_coordinate(coordinate: f64) -> f64 {
+    match app_config().location_coordinate_decimals {
+        Some(decimals) => {
+            let factor = 10f64.powi(decimals as i32);
+            (coordinate * factor).round() / factor
+        }
+        None => coordinate,
+    }
+}
+
+/// A tracker location to be stored
+pub struct NewLocation {
+    pub tracker_id: i32,
+
+    /// time the location is stored at, see `location_time`
+    pub time: DateTime<Utc>,
+
+    /// time reported by the tracker
+    pub device_time: DateTime<Utc>,
+
+    /// time the location was received by the decoder
+    pub received_at: DateTime<Utc>,
+
+    pub lat: f64,
+    pub lng: f64,
+}
+
+/// inserts a tracker location, unless `LOCATION_DEDUPE_SECONDS` is set and the tracker
+/// last location has the same coordinates and was received less than said seconds before,
+/// in which case no row is affected.
 pub async fn insert_vehicle_tracker_location(
     db: &DatabaseConnection,
-    timestamp: DateTime<Utc>,
-    tracker_id: i32,
-    lat: f64,
-    lng: f64,
+    location: &NewLocation,
 ) -> Result<PgQueryResult, sqlx::Error> {
-    let point: geo_types::Geometry<f64> = geo_types::Point::new(lat, lng).into();
+    let point: geo_types::Geometry<f64> = geo_types::Point::new(location.lat, location.lng).into();
 
+    let Some(dedupe_seconds) = app_config().location_dedupe_seconds else {
+        return sqlx::query(
+            "INSERT INTO vehicle_tracker_location (time, vehicle_tracker_id, point, device_time, received_at) VALUES ($1, $2, ST_SetSRID($3, 4326), $4, $5)",
+        )
+        .bind(location.time)
+        .bind(location.tracker_id)
+        .bind(wkb::Encode(point))
+        .bind(location.device_time)
+        .bind(location.received_at)
+        .execute(db.get_postgres_connection_pool())
+        .await;
+    };
+
+    // the last location is kept up to date by a trigger on `vehicle_tracker_location`,
+    // locations received out of order (before the last one) are never deduplicated
     sqlx::query(
-        "INSERT INTO vehicle_tracker_location (time, vehicle_tracker_id, point) VALUES ($1, $2, ST_SetSRID($3, 4326))",
+        r#"
+INSERT INTO vehicle_tracker_location (time, vehicle_tracker_id, point, device_time, received_at)
+SELECT $1, $2, ST_SetSRID($3, 4326), $4, $5
+WHERE NOT EXISTS (
+    SELECT 1 FROM vehicle_tracker_last_location l
+    WHERE l.vehicle_tracker_id = $2
+    AND l.time <= $1
+    AND l.time > $1 - make_interval(secs => $6)
+    AND ST_Equals(l.point, ST_SetSRID($3, 4326))
+)
+        "#,
     )
-    .bind(timestamp)
-    .bind(tracker_id)
+    .bind(location.time)
+    .bind(location.tracker_id)
     .bind(wkb::Encode(point))
+    .bind(location.device_time)
+    .bind(location.received_at)
+    .bind(dedupe_seconds as f64)
     .execute(db.get_postgres_connection_pool())
     .await
 }
+
+/// A location of a tracker whose IMEI is not registered, to be held until it is
+pub struct NewUnknownImeiLocation<'a> {
+    pub imei: &'a str,
+
+    /// protocol the location was decoded from, eg: `h02`
+    pub protocol: &'a str,
+
+    /// time the location is stored at, see `location_time`
+    pub time: DateTime<Utc>,
+
+    /// time reported by the tracker
+    pub device_time: DateTime<Utc>,
+
+    /// time the location was received by the decoder
+    pub received_at: DateTime<Utc>,
+
+    pub lat: f64,
+    pub lng: f64,
+}
+
+/// holds a location of a tracker whose IMEI is not registered, the amount of held
+/// locations is bounded by `prune_unknown_imei_locations` instead of on every insert
+pub async fn insert_unknown_imei_location(
+    db: &DatabaseConnection,
+    location: &NewUnknownImeiLocation<'_>,
+) -> Result<PgQueryResult, sqlx::Error> {
+    let point: geo_types::Geometry<f64> = geo_types::Point::new(location.lat, location.lng).into();
+
+    sqlx::query(
+        "INSERT INTO unknown_imei_location (imei, protocol, time, device_time, received_at, point) VALUES ($1, $2, $3, $4, $5, ST_SetSRID($6, 4326))",
+    )
+    .bind(location.imei)
+    .bind(location.protocol)
+    .bind(location.time)
+    .bind(location.device_time)
+    .bind(location.received_at)
+    .bind(wkb::Encode(point))
+    .execute(db.get_postgres_connection_pool())
+    .await
+}
+
+/// deletes the held locations of unregistered IMEIs older than `UNKNOWN_IMEI_LOCATION_RETENTION_HOURS`
+/// and the oldest ones over `UNKNOWN_IMEI_LOCATION_MAX_PER_IMEI` and `UNKNOWN_IMEI_LOCATION_MAX_ROWS`,
+/// returning the amount of deleted locations
+pub async fn prune_unknown_imei_locations(db: &DatabaseConnection) -> Result<u64, sqlx::Error> {
+    let config = app_config();
+
+    let result = sqlx::query(
+        r#"
+DELETE FROM unknown_imei_location
+WHERE received_at < now() - make_interval(secs => $1)
+OR id IN (
+    SELECT id FROM (
+        SELECT id, row_number() OVER (PARTITION BY imei ORDER BY received_at DESC, id DESC) AS n
+        FROM unknown_imei_location
+    ) AS ranked
+    WHERE ranked.n > $2
+)
+OR id IN (
+    SELECT id FROM unknown_imei_location
+    ORDER BY received_at DESC, id DESC
+    OFFSET $3
+)
+        "#,
+    )
+    .bind((config.unknown_imei_location_retention_hours * 60 * 60) as f64)
+    .bind(config.unknown_imei_location_max_per_imei as i64)
+    .bind(config.unknown_imei_location_max_rows as i64)
+    .execute(db.get_postgres_connection_pool())
+    .await?;
+
+    Ok(result.rows_affected())
+}
@@ -32,8 +32,9 @@ pub struct CreateUserDto {
     #[validate(length(min = 5, max = 32))]
     pub username: String,
 
+    /// access level of the user, if `null` the organization default access level is used
     #[validate(range(min = 1))]
-    pub access_level_id: i32,
+    pub access_level_id: Option<i32>,
 
     #[validate(length(min = 5, max = 256))]
     #[validate(regex(
@@ -82,6 +83,26 @@ pub struct UpdateUserDto {
     pub description: Option<Option<String>>,
 }
 
+/// A user to create on the request user organization, that sets his own
+/// password with the link of the invite email sent to him
+#[derive(ToSchema, Validate, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InviteUserDto {
+    #[validate(email)]
+    pub email: String,
+
+    #[validate(regex(
+        path = "REGEX_IS_LOWERCASE_ALPHANUMERIC_WITH_UNDERSCORES",
+        message = "username must contain only lowercase alphanumeric characters and underscores"
+    ))]
+    #[validate(length(min = 5, max = 32))]
+    pub username: String,
+
+    /// access level of the user, if `null` the organization default access level is used
+    #[validate(range(min = 1))]
+    pub access_level_id: Option<i32>,
+}
+
 #[derive(ToSchema, Validate, Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct ChangePasswordDto {
@@ -105,6 +126,11 @@ pub struct ChangePasswordDto {
         message = "password must contain a lowercase character"
     ))]
     pub new_password: String,
+
+    /// if the session changing the password should be signed out as well, the other
+    /// sessions of the user are always signed out
+    #[serde(default)]
+    pub sign_out_current_session: bool,
 }
 
 #[derive(Serialize, Clone, ToSchema)]
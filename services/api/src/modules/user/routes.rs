@@ -2,11 +2,14 @@ use super::super::auth::dto as auth_dto;
 use super::dto::{self, ListUsersDto, SimpleUserDto};
 use crate::database::error::DbError;
 use crate::modules::access_level::dto::AccessLevelDto;
-use crate::modules::auth::dto::SessionDto;
+use crate::modules::auth::dto::{OrganizationDto, SessionDto};
 use crate::modules::auth::middleware::{AclLayer, RequestUserPassword};
 use crate::modules::auth::session::SessionId;
+use crate::modules::common::conditional_requests::{s3_object_response, Preconditions};
 use crate::modules::common::dto::{Pagination, PaginationResult, SingleImageDto};
-use crate::modules::common::error_codes::EMAIL_ALREADY_VERIFIED;
+use crate::modules::common::error_codes::{
+    EMAIL_ALREADY_VERIFIED, EMAIL_IN_USE, NO_DEFAULT_ACCESS_LEVEL, USERNAME_IN_USE,
+};
 use crate::modules::common::extractors::{
     DbConnection, OrgBoundEntityFromPathId, OrganizationId, ValidatedQuery,
 };
@@ -25,23 +28,25 @@ use crate::{
     services::s3::S3Key,
 };
 use axum::extract::Path;
+use axum::response::Response;
 use axum::{
     extract::State,
     routing::{delete, get, post, put},
     Extension, Json, Router,
 };
 use axum_typed_multipart::TypedMultipart;
-use bcrypt::{hash, verify, DEFAULT_COST};
-use http::StatusCode;
+use bcrypt::verify;
+use http::{HeaderMap, Method, StatusCode};
 use migration::Expr;
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, PaginatorTrait, QueryFilter, QueryOrder,
-    QueryTrait, Set, TryIntoModel,
+    ActiveModelTrait, ColumnTrait, DatabaseConnection, EntityTrait, PaginatorTrait, QueryFilter,
+    QueryOrder, QueryTrait, Set, TryIntoModel,
 };
 use sea_query::extension::postgres::PgExpr;
 use shared::constants::Permission;
 use shared::entity::traits::QueryableByIdAndOrgId;
-use shared::entity::{access_level, user};
+use shared::entity::{access_level, login_history, user};
+use uuid::Uuid;
 
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
@@ -49,13 +54,23 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             "/",
             post(create_user).route_layer(AclLayer::single(Permission::CreateUser)),
         )
+        .route(
+            "/invite",
+            post(invite_user).route_layer(AclLayer::single(Permission::CreateUser)),
+        )
         .route("/", get(list_users))
         .route("/:user_id", get(get_user))
+        .route("/:user_id/profile-picture", get(get_user_profile_picture))
         .route(
             "/:user_id",
             delete(delete_user).route_layer(AclLayer::single(Permission::DeleteUser)),
         )
         //
+        .route(
+            "/:user_id/request-email-confirmation",
+            post(request_email_confirmation_for_user)
+                .route_layer(AclLayer::single(Permission::CreateUser)),
+        )
         .route(
             "/:user_id/session",
             get(get_user_sessions).route_layer(AclLayer::single(Permission::ListUserSessions)),
@@ -72,10 +87,13 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         .route("/me", get(me).patch(update_me))
         .route("/me/short-lived-token", get(get_short_lived_token))
         .route("/me/session", get(get_request_user_sessions))
+        .route("/me/login-history", get(list_request_user_login_history))
         .route("/me/password", put(put_password))
         .route(
             "/me/profile-picture",
-            put(put_profile_picture).delete(delete_profile_picture),
+            get(get_profile_picture)
+                .put(put_profile_picture)
+                .delete(delete_profile_picture),
         )
         .route(
             "/me/request-email-address-confirmation",
@@ -116,40 +134,85 @@ pub async fn get_short_lived_token(
     Ok(Json(token))
 }
 
+/// the access level of a new user of the organization, that is the given one,
+/// if it belongs to the organization, or the organization default access level
+async fn resolve_new_user_access_level_id(
+    db: &DatabaseConnection,
+    org: &OrganizationDto,
+    access_level_id: Option<i32>,
+) -> Result<i32, (StatusCode, SimpleError)> {
+    let Some(access_level_id) = access_level_id else {
+        return org.default_access_level_id.ok_or((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(NO_DEFAULT_ACCESS_LEVEL),
+        ));
+    };
+
+    access_level::Entity::find_by_id_and_org_id(access_level_id, org.id, db)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((
+            StatusCode::NOT_FOUND,
+            SimpleError::from("access level not found"),
+        ))?;
+
+    Ok(access_level_id)
+}
+
+/// Creates a user on the request user organization
+///
+/// Required permissions: CREATE_USER
+///
+/// if no access level is given the organization default access level is used
 #[utoipa::path(
     post,
     tag="user",
     path="/user",
     security(("session_id" = [])),
+    request_body = CreateUserDto,
     responses(
         (
             status = OK,
             body = user::dto::SimpleUserDto,
         ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / NO_DEFAULT_ACCESS_LEVEL",
+            body = SimpleError,
+        ),
         (
             status = UNAUTHORIZED,
             description = "invalid session",
             body = SimpleError,
         ),
+        (
+            status = NOT_FOUND,
+            description = "access level not found",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn create_user(
     DbConnection(db): DbConnection,
-    OrganizationId(org_id): OrganizationId,
+    Extension(req_user): Extension<RequestUser>,
     ValidatedJson(dto): ValidatedJson<dto::CreateUserDto>,
 ) -> Result<Json<dto::SimpleUserDto>, (StatusCode, SimpleError)> {
-    access_level::Entity::find_by_id_and_org_id(dto.access_level_id, org_id, &db)
-        .await
-        .map_err(DbError::from)?;
+    let org = req_user.0.organization.ok_or((
+        StatusCode::FORBIDDEN,
+        SimpleError::from("only users of a organization can create users"),
+    ))?;
 
-    let password_hash = hash(dto.password, DEFAULT_COST).map_err(|_| internal_error_res())?;
+    let access_level_id = resolve_new_user_access_level_id(&db, &org, dto.access_level_id).await?;
+
+    let password_hash =
+        auth::service::hash_password(dto.password).map_err(|_| internal_error_res())?;
 
     let user = user::ActiveModel {
         email: Set(dto.email),
         password: Set(password_hash),
         username: Set(dto.username),
-        organization_id: Set(Some(org_id)),
-        access_level_id: Set(dto.access_level_id),
+        organization_id: Set(Some(org.id)),
+        access_level_id: Set(access_level_id),
         ..Default::default()
     }
     .save(&db)
@@ -161,6 +224,98 @@ pub async fn create_user(
     Ok(Json(dto::SimpleUserDto::from(user)))
 }
 
+/// Invites a user to the request user organization
+///
+/// Required permissions: CREATE_USER
+///
+/// Creates the user without a usable password and sends him a invite email, with a
+/// link to set his password (with `/auth/change-password-by-recovery-token`) valid for
+/// 7 days, if no access level is given the organization default access level is used
+#[utoipa::path(
+    post,
+    tag = "user",
+    path = "/user/invite",
+    security(("session_id" = [])),
+    request_body = InviteUserDto,
+    responses(
+        (
+            status = OK,
+            description = "the invited user",
+            body = user::dto::SimpleUserDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / NO_DEFAULT_ACCESS_LEVEL / EMAIL_IN_USE / USERNAME_IN_USE",
+            body = SimpleError,
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "access level not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn invite_user(
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    Extension(req_user): Extension<RequestUser>,
+    ValidatedJson(dto): ValidatedJson<dto::InviteUserDto>,
+) -> Result<Json<dto::SimpleUserDto>, (StatusCode, SimpleError)> {
+    let org = req_user.0.organization.ok_or((
+        StatusCode::FORBIDDEN,
+        SimpleError::from("only users of a organization can invite users"),
+    ))?;
+
+    let access_level_id = resolve_new_user_access_level_id(&db, &org, dto.access_level_id).await?;
+
+    // the user sets his password by accepting the invite, until then
+    // the account has a random password nobody knows
+    let password_hash = auth::service::hash_password(Uuid::new_v4().to_string())
+        .map_err(|_| internal_error_res())?;
+
+    let user = user::ActiveModel {
+        email: Set(dto.email),
+        password: Set(password_hash),
+        username: Set(dto.username),
+        organization_id: Set(Some(org.id)),
+        access_level_id: Set(access_level_id),
+        ..Default::default()
+    }
+    .insert(&db)
+    .await
+    .map_err(DbError::from)?;
+
+    let token = state
+        .auth_service
+        .gen_and_set_user_invite_token(user.id)
+        .await
+        .or(Err(internal_error_res()))?;
+
+    // the user was already created, so failing to queue the email is not
+    // a error for the request, the invite can be resent by a password recovery
+    if let Err(err) = state
+        .mailer_service
+        .send_invite_user_email(
+            user.email.clone(),
+            token,
+            user.username.clone(),
+            org.id,
+            org.name,
+            org.email_sender,
+        )
+        .await
+    {
+        tracing::error!("failed to queue user invite email: {:?}", err);
+    }
+
+    Ok(Json(dto::SimpleUserDto::from(user)))
+}
+
 /// List all sessions for the request user
 #[utoipa::path(
     get,
@@ -213,6 +368,60 @@ pub async fn get_request_user_sessions(
     Ok(Json(sessions))
 }
 
+/// List the request user login history
+///
+/// lists when the request user signed in and from where, most recent logins first,
+/// logins are kept after their sessions end, for `LOGIN_HISTORY_RETENTION_DAYS`
+#[utoipa::path(
+    get,
+    tag = "user",
+    path = "/user/me/login-history",
+    security(("session_id" = [])),
+    params(Pagination),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of the user logins",
+            content_type = "application/json",
+            body = PaginatedLoginHistory,
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_request_user_login_history(
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    Extension(req_user): Extension<RequestUser>,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<login_history::Model>>, (StatusCode, SimpleError)> {
+    let paginator = login_history::Entity::find()
+        .filter(login_history::Column::UserId.eq(req_user.0.id))
+        .order_by_desc(login_history::Column::CreatedAt)
+        .order_by_desc(login_history::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
 /// List users belonging to a organization
 #[utoipa::path(
     get,
@@ -546,6 +755,11 @@ pub async fn me(Extension(req_user): Extension<RequestUser>) -> Json<UserDto> {
             description = "the updated user",
             body = UserDto,
         ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / USERNAME_IN_USE / EMAIL_IN_USE",
+            body = SimpleError,
+        ),
         (
             status = UNAUTHORIZED,
             description = "invalid session",
@@ -554,12 +768,45 @@ pub async fn me(Extension(req_user): Extension<RequestUser>) -> Json<UserDto> {
     ),
 )]
 pub async fn update_me(
+    State(state): State<AppState>,
     DbConnection(db): DbConnection,
     Extension(req_user): Extension<RequestUser>,
     ValidatedJson(payload): ValidatedJson<dto::UpdateUserDto>,
 ) -> Result<Json<auth_dto::UserDto>, (StatusCode, SimpleError)> {
     let mut req_user = req_user.0;
 
+    if let Some(username) = payload
+        .username
+        .as_ref()
+        .filter(|u| **u != req_user.username)
+    {
+        let username_in_use = state
+            .auth_service
+            .get_user_id_by_username(username)
+            .await
+            .or(Err(internal_error_res()))?
+            .is_some();
+
+        if username_in_use {
+            return Err((StatusCode::BAD_REQUEST, SimpleError::from(USERNAME_IN_USE)));
+        }
+    }
+
+    if let Some(email) = payload.email.as_ref().filter(|e| **e != req_user.email) {
+        let email_in_use = state
+            .auth_service
+            .check_email_in_use(email)
+            .await
+            .or(Err(internal_error_res()))?;
+
+        if email_in_use {
+            return Err((StatusCode::BAD_REQUEST, SimpleError::from(EMAIL_IN_USE)));
+        }
+    }
+
+    // the checks above do not prevent a concurrent request from taking the username or email
+    // before the update, in that case the unique violation is mapped to the same error codes
+
     user::Entity::update_many()
         .apply_if(payload.description.clone(), |query, v| {
             query.col_expr(user::Column::Description, Expr::value(v))
@@ -591,6 +838,9 @@ pub async fn update_me(
 }
 
 /// Changes the user password
+///
+/// Signs out every other session of the user, the current session
+/// is signed out as well if `signOutCurrentSession` is true.
 #[utoipa::path(
     put,
     tag = "user",
@@ -617,11 +867,12 @@ pub async fn update_me(
     ),
 )]
 async fn put_password(
-    DbConnection(db): DbConnection,
+    State(state): State<AppState>,
+    Extension(session): Extension<SessionId>,
     Extension(req_user): Extension<RequestUser>,
     Extension(req_user_password): Extension<RequestUserPassword>,
     ValidatedJson(payload): ValidatedJson<dto::ChangePasswordDto>,
-) -> Result<Json<&'static str>, (StatusCode, SimpleError)> {
+) -> Result<(HeaderMap, Json<&'static str>), (StatusCode, SimpleError)> {
     let request_user = req_user.0;
 
     let old_password_valid =
@@ -634,17 +885,30 @@ async fn put_password(
         ));
     }
 
-    let new_password_hash = hash(payload.new_password, DEFAULT_COST)
+    let new_password_hash = auth::service::hash_password(payload.new_password)
         .or(Err(internal_error_msg("error hashing password")))?;
 
-    user::Entity::update_many()
-        .col_expr(user::Column::Password, Expr::value(new_password_hash))
-        .filter(user::Column::Id.eq(request_user.id))
-        .exec(&db)
+    let session_to_keep = (!payload.sign_out_current_session).then_some(&session);
+
+    let signed_out_sessions = state
+        .auth_service
+        .change_password(request_user.id, new_password_hash, session_to_keep)
         .await
-        .map_err(DbError::from)?;
+        .or(Err(internal_error_msg("failed to change password")))?;
+
+    tracing::info!(
+        user_id = request_user.id,
+        signed_out_sessions,
+        "password changed"
+    );
 
-    Ok(Json("password changed successfully"))
+    let mut headers = HeaderMap::new();
+
+    if payload.sign_out_current_session {
+        headers.insert("Set-Cookie", session.into_delete_cookie_header());
+    }
+
+    Ok((headers, Json("password changed successfully")))
 }
 
 /// Replaces the request user profile picture
@@ -719,6 +983,103 @@ async fn put_profile_picture(
     Ok(Json(String::from(key)))
 }
 
+/// Gets the request user profile picture
+///
+/// HEAD requests are supported to fetch only the picture metadata. the response contains the
+/// `ETag` and `Last-Modified` headers, so clients can send `If-None-Match` or `If-Modified-Since`
+/// to receive a `304 Not Modified` without a body if their cached picture is up to date.
+#[utoipa::path(
+    get,
+    tag = "user",
+    path = "/user/me/profile-picture",
+    security(("session_id" = [])),
+    params(
+        ("If-None-Match" = Option<String>, Header, description = "ETag of the cached picture"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified of the cached picture"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the profile picture",
+            content_type = "image/*",
+            body = Vec<u8>,
+        ),
+        (
+            status = NOT_MODIFIED,
+            description = "the cached picture is up to date",
+        ),
+        (
+            status = NOT_FOUND,
+            description = "user without a profile picture",
+            body = SimpleError,
+        ),
+    ),
+)]
+async fn get_profile_picture(
+    method: Method,
+    preconditions: Preconditions,
+    State(state): State<AppState>,
+    Extension(req_user): Extension<RequestUser>,
+) -> Result<Response, (StatusCode, SimpleError)> {
+    profile_picture_response(&state, req_user.0.profile_picture, &method, &preconditions).await
+}
+
+/// Gets the profile picture of a user
+///
+/// HEAD requests are supported to fetch only the picture metadata. the response contains the
+/// `ETag` and `Last-Modified` headers, so clients can send `If-None-Match` or `If-Modified-Since`
+/// to receive a `304 Not Modified` without a body if their cached picture is up to date.
+#[utoipa::path(
+    get,
+    tag = "user",
+    path = "/user/{user_id}/profile-picture",
+    security(("session_id" = [])),
+    params(
+        ("user_id" = u128, Path, description = "id of the user"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of the cached picture"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified of the cached picture"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the profile picture",
+            content_type = "image/*",
+            body = Vec<u8>,
+        ),
+        (
+            status = NOT_MODIFIED,
+            description = "the cached picture is up to date",
+        ),
+        (
+            status = NOT_FOUND,
+            description = "user not found or without a profile picture",
+            body = SimpleError,
+        ),
+    ),
+)]
+async fn get_user_profile_picture(
+    method: Method,
+    preconditions: Preconditions,
+    State(state): State<AppState>,
+    OrgBoundEntityFromPathId(user): OrgBoundEntityFromPathId<user::Entity>,
+) -> Result<Response, (StatusCode, SimpleError)> {
+    profile_picture_response(&state, user.profile_picture, &method, &preconditions).await
+}
+
+async fn profile_picture_response(
+    state: &AppState,
+    profile_picture: Option<String>,
+    method: &Method,
+    preconditions: &Preconditions,
+) -> Result<Response, (StatusCode, SimpleError)> {
+    let key = profile_picture.ok_or((
+        StatusCode::NOT_FOUND,
+        SimpleError::from("user does not have a profile picture"),
+    ))?;
+
+    s3_object_response(&state.s3, key, method, preconditions).await
+}
+
 /// Removes the request user profile picture
 #[utoipa::path(
     delete,
@@ -790,6 +1151,16 @@ async fn delete_profile_picture(
             description = "invalid dto error message / EMAIL_ALREADY_CONFIRMED",
             body = SimpleError,
         ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "EMAIL_SERVICE_UNAVAILABLE, the message broker is unreachable",
+            body = SimpleError,
+        ),
+        (
+            status = BAD_GATEWAY,
+            description = "EMAIL_REJECTED, the message broker refused the email",
+            body = SimpleError,
+        ),
     ),
 )]
 pub async fn request_user_email_address_confirmation(
@@ -811,9 +1182,98 @@ pub async fn request_user_email_address_confirmation(
 
     state
         .mailer_service
-        .send_confirm_email_address_email(req_user.0.email, token, ConfirmEmailRecipientType::User)
+        .send_confirm_email_address_email(
+            req_user.0.email,
+            token,
+            ConfirmEmailRecipientType::User,
+            req_user.0.organization.and_then(|org| org.email_sender),
+        )
+        .await?;
+
+    Ok(Json("email address confirmation email queued successfully"))
+}
+
+/// Requests a email address confirmation email for a user
+///
+/// Required permissions: CREATE_USER
+///
+/// sends a email address confirmation email to the email address of another
+/// user of the organization, eg: when onboarding a new organization user
+#[utoipa::path(
+    post,
+    tag = "user",
+    path = "/user/{user_id}/request-email-confirmation",
+    security(("session_id" = [])),
+    params(
+        ("user_id" = u128, Path, description = "id of the user to send the confirmation email to"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "success message",
+            body = String,
+            content_type = "application/json",
+            example = json!("email address confirmation email queued successfully"),
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+        (
+            status = FORBIDDEN,
+            description = "user lacks permissions",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "user not found on the organization",
+            body = SimpleError,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "EMAIL_ALREADY_VERIFIED",
+            body = SimpleError,
+        ),
+        (
+            status = SERVICE_UNAVAILABLE,
+            description = "EMAIL_SERVICE_UNAVAILABLE, the message broker is unreachable",
+            body = SimpleError,
+        ),
+        (
+            status = BAD_GATEWAY,
+            description = "EMAIL_REJECTED, the message broker refused the email",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn request_email_confirmation_for_user(
+    State(state): State<AppState>,
+    Extension(req_user): Extension<RequestUser>,
+    OrgBoundEntityFromPathId(user): OrgBoundEntityFromPathId<user::Entity>,
+) -> Result<Json<&'static str>, (StatusCode, SimpleError)> {
+    if user.email_verified {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(EMAIL_ALREADY_VERIFIED),
+        ));
+    }
+
+    let token = state
+        .auth_service
+        .gen_and_set_user_confirm_email_token(user.id)
         .await
         .or(Err(internal_error_res()))?;
 
+    state
+        .mailer_service
+        .send_confirm_email_address_email(
+            user.email,
+            token,
+            ConfirmEmailRecipientType::User,
+            req_user.0.organization.and_then(|org| org.email_sender),
+        )
+        .await?;
+
     Ok(Json("email address confirmation email queued successfully"))
 }
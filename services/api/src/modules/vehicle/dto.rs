@@ -1,7 +1,12 @@
-use crate::modules::common::validators::REGEX_IS_MERCOSUL_OR_BR_VEHICLE_PLATE;
+use crate::modules::{
+    common::{responses::FieldError, validators::REGEX_IS_MERCOSUL_OR_BR_VEHICLE_PLATE},
+    tracking::dto::PositionDto,
+};
 use axum::body::Bytes;
 use axum_typed_multipart::{FieldData, TryFromMultipart};
-use serde::Deserialize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use shared::entity::vehicle;
 use utoipa::{IntoParams, ToSchema};
 use validator::Validate;
 
@@ -13,6 +18,72 @@ pub struct ListVehiclesDto {
     pub plate: Option<String>,
 }
 
+/// Filters of the vehicles listed with their positions
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct ListVehiclesWithPositionsDto {
+    /// Search by plate
+    pub plate: Option<String>,
+
+    /// Only list these vehicles
+    #[validate(length(min = 1, max = 1000))]
+    pub ids: Option<Vec<i32>>,
+}
+
+/// A vehicle with the last position of its tracker
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleWithPositionDto {
+    pub vehicle: vehicle::Model,
+
+    /// `null` if the vehicle has no tracker or its tracker never sent a position
+    pub position: Option<PositionDto>,
+}
+
+#[derive(Deserialize, IntoParams, Validate)]
+#[serde(rename_all = "camelCase")]
+#[into_params(parameter_in = Query)]
+pub struct GetOdometerDto {
+    /// Only count the distance traveled since this date, the distance
+    /// is stored per day, so the time of the date is ignored
+    pub since: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleOdometerDto {
+    /// Total kilometers traveled by the vehicle
+    pub odometer_km: f64,
+
+    /// Kilometers traveled since the requested date, or
+    /// the same as `odometer_km` if no date was requested
+    pub distance_km: f64,
+
+    pub since: Option<DateTime<Utc>>,
+}
+
+/// The result of deleting a vehicle
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DeletedVehicleDto {
+    /// ids of the trackers that were installed on the vehicle, now without a vehicle
+    pub unassigned_tracker_ids: Vec<i32>,
+}
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct GetVehicleLocationHistoryDto {
+    /// List locations from this timestamp onwards
+    pub after: DateTime<Utc>,
+
+    /// List locations before this timestamp, at most 31 days after `after`
+    pub before: DateTime<Utc>,
+
+    /// Limit the number of locations to be queried, defaults to 500
+    #[validate(range(min = 1, max = 5000))]
+    pub limit: Option<i64>,
+}
+
 #[derive(TryFromMultipart, ToSchema, Validate)]
 #[try_from_multipart(rename_all = "camelCase")]
 pub struct CreateVehicleDto {
@@ -42,6 +113,75 @@ pub struct CreateVehicleDto {
     pub additional_info: Option<String>,
 }
 
+/// CSV file of vehicles to create, see `import_vehicles`
+#[derive(TryFromMultipart, ToSchema)]
+#[try_from_multipart(rename_all = "camelCase")]
+pub struct ImportVehiclesDto {
+    #[schema(value_type = String, format = Binary)]
+    pub file: FieldData<Bytes>,
+
+    /// if no vehicle should be created when any row is invalid,
+    /// by default the vehicles of the valid rows are created
+    pub all_or_nothing: Option<bool>,
+}
+
+/// A row of a vehicles CSV, the columns are matched by their header
+#[derive(Deserialize, Validate)]
+pub struct VehicleCsvRow {
+    #[validate(regex(
+        path = "REGEX_IS_MERCOSUL_OR_BR_VEHICLE_PLATE",
+        message = "vehicle plate must be in format AAA#A## or AAA#### (A: a-z, #: 0-9)"
+    ))]
+    pub plate: String,
+
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub brand: String,
+
+    #[validate(length(min = 1, message = "must not be empty"))]
+    pub model: String,
+
+    /// the vehicle model year
+    #[serde(default)]
+    #[validate(range(min = 1900, max = 2100, message = "must be between 1900 and 2100"))]
+    pub year: Option<i16>,
+
+    #[serde(default)]
+    pub color: Option<String>,
+
+    #[serde(default)]
+    pub chassis: Option<String>,
+}
+
+/// A vehicle created from a row of the imported CSV
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportedVehicleDto {
+    /// line of the row on the CSV, the header is line 1
+    pub row: u64,
+
+    pub vehicle: vehicle::Model,
+}
+
+/// A row of the imported CSV whose vehicle was not created
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct FailedVehicleImportRowDto {
+    /// line of the row on the CSV, the header is line 1
+    pub row: u64,
+
+    /// why the row is invalid, `field` is the CSV column
+    pub errors: Vec<FieldError>,
+}
+
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct VehicleImportResultDto {
+    pub created: Vec<ImportedVehicleDto>,
+
+    /// ordered by row
+    pub failed: Vec<FailedVehicleImportRowDto>,
+}
+
 #[derive(Deserialize, ToSchema, Validate)]
 #[serde(rename_all = "camelCase")]
 pub struct UpdateVehicleDto {
@@ -74,3 +214,40 @@ pub struct UpdateVehicleDto {
     #[serde(default, with = "::serde_with::rust::double_option")]
     pub fabrication_year: Option<Option<i16>>,
 }
+
+#[derive(Deserialize, ToSchema, Validate)]
+#[serde(rename_all = "camelCase")]
+pub struct InitiatePhotoUploadDto {
+    /// name of the photo file, only used to get the file extension
+    #[validate(length(min = 1, max = 255))]
+    pub filename: String,
+}
+
+/// A part of a photo upload stored on the server
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UploadedPartDto {
+    pub part_number: i32,
+    pub size: i64,
+}
+
+/// A chunked photo upload, to resume a upload only the parts
+/// missing from `parts` need to be uploaded
+#[derive(Serialize, ToSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PhotoUploadDto {
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+
+    /// incomplete uploads are aborted after this date
+    pub expires_at: DateTime<Utc>,
+
+    /// size every part but the last must have at least
+    pub min_part_size_bytes: usize,
+
+    /// maximum size of a part
+    pub max_part_size_bytes: usize,
+
+    /// parts already uploaded, ordered by their number
+    pub parts: Vec<UploadedPartDto>,
+}
@@ -1,3 +1,5 @@
 pub mod dto;
+pub mod odometer;
+pub mod photo_upload;
 pub mod repository;
 pub mod routes;
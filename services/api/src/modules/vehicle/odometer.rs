@@ -0,0 +1,327 @@
+//! Incremental accumulation of the distance traveled by vehicles.
+//!
+//! Every tracker installed on a vehicle has a cursor with the last location
+//! accounted on the vehicle odometer, so every run only reads the locations
+//! received after it instead of rescanning the whole location history.
+
+use crate::{
+    config::{app_config, LocationTimestamp},
+    modules::tracking::utils::{decode_location_point, log_unexpected_geometry, StoredPoint},
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use sea_orm::{
+    sea_query::{Expr, OnConflict},
+    ColumnTrait, DatabaseConnection, DbErr, EntityTrait, QueryFilter, Set, TransactionTrait,
+};
+use sea_query::{Cond, Order, PostgresQueryBuilder, Query as SeaQuery};
+use sea_query_binder::SqlxBinder;
+use shared::entity::{
+    vehicle, vehicle_daily_distance, vehicle_tracker, vehicle_tracker_last_location,
+    vehicle_tracker_location, vehicle_tracker_odometer_cursor,
+};
+use std::collections::{BTreeMap, HashMap};
+use tracing::error;
+
+/// Mean earth radius in kilometers
+static EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Speed between two locations above which the newest location is
+/// considered a GPS error and ignored
+static MAX_PLAUSIBLE_SPEED_KMH: f64 = 250.0;
+
+/// Distance between two locations under which the movement is considered
+/// GPS jitter of a stopped vehicle and not accounted on the odometer
+pub static MIN_MOVEMENT_KM: f64 = 0.015;
+
+/// Maximum amount of locations of a tracker processed on every run
+static MAX_LOCATIONS_PER_RUN: u64 = 5000;
+
+struct Location {
+    time: DateTime<Utc>,
+    received_at: Option<DateTime<Utc>>,
+    lat: f64,
+    lng: f64,
+}
+
+impl Location {
+    /// day the distance traveled up to the location is accounted on, see `DAILY_DISTANCE_TIMESTAMP`
+    fn day(&self) -> NaiveDate {
+        match app_config().daily_distance_timestamp {
+            LocationTimestamp::Device => self.time.date_naive(),
+            LocationTimestamp::Received => self.received_at.unwrap_or(self.time).date_naive(),
+        }
+    }
+}
+
+fn haversine_km(from: &Location, to: &Location) -> f64 {
+    distance_km((from.lat, from.lng), (to.lat, to.lng))
+}
+
+/// Great circle distance in kilometers between two (lat, lng) coordinates, using the haversine formula
+pub fn distance_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (from_lat, to_lat) = (from.0.to_radians(), to.0.to_radians());
+    let d_lat = (to.0 - from.0).to_radians();
+    let d_lng = (to.1 - from.1).to_radians();
+
+    let a =
+        (d_lat / 2.0).sin().powi(2) + from_lat.cos() * to_lat.cos() * (d_lng / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+/// Decodes a location point, logging and skipping locations that are not points
+fn to_location(
+    tracker_id: i32,
+    time: DateTime<Utc>,
+    received_at: Option<DateTime<Utc>>,
+    point: &geozero::wkb::Decode<geo_types::Geometry<f64>>,
+) -> Option<Location> {
+    let p = log_unexpected_geometry(decode_location_point(tracker_id, point))?;
+
+    Some(Location {
+        time,
+        received_at,
+        lat: p.x(),
+        lng: p.y(),
+    })
+}
+
+async fn get_locations_after(
+    db: &DatabaseConnection,
+    tracker_id: i32,
+    after: DateTime<Utc>,
+) -> Result<Vec<Location>, DbErr> {
+    let (q, args) = SeaQuery::select()
+        .column(vehicle_tracker_location::Column::Time)
+        .column(vehicle_tracker_location::Column::Point)
+        .column(vehicle_tracker_location::Column::ReceivedAt)
+        .from(vehicle_tracker_location::Entity)
+        .cond_where(
+            Cond::all()
+                .add(Expr::col(vehicle_tracker_location::Column::VehicleTrackerId).eq(tracker_id))
+                .add(Expr::col(vehicle_tracker_location::Column::Time).gt(after)),
+        )
+        .order_by(vehicle_tracker_location::Column::Time, Order::Asc)
+        .limit(MAX_LOCATIONS_PER_RUN)
+        .to_owned()
+        .build_sqlx(PostgresQueryBuilder);
+
+    let rows: Vec<(DateTime<Utc>, StoredPoint, Option<DateTime<Utc>>)> =
+        sqlx::query_as_with(&q, args)
+            .fetch_all(db.get_postgres_connection_pool())
+            .await
+            .map_err(|e| DbErr::Custom(e.to_string()))?;
+
+    Ok(rows
+        .iter()
+        .filter_map(|(time, point, received_at)| {
+            to_location(tracker_id, *time, *received_at, point)
+        })
+        .collect())
+}
+
+async fn get_last_location(
+    db: &DatabaseConnection,
+    tracker_id: i32,
+) -> Result<Option<Location>, DbErr> {
+    let (q, args) = SeaQuery::select()
+        .column(vehicle_tracker_last_location::Column::Time)
+        .column(vehicle_tracker_last_location::Column::Point)
+        .column(vehicle_tracker_last_location::Column::ReceivedAt)
+        .from(vehicle_tracker_last_location::Entity)
+        .cond_where(
+            Expr::col(vehicle_tracker_last_location::Column::VehicleTrackerId).eq(tracker_id),
+        )
+        .to_owned()
+        .build_sqlx(PostgresQueryBuilder);
+
+    let row: Option<(DateTime<Utc>, StoredPoint, Option<DateTime<Utc>>)> =
+        sqlx::query_as_with(&q, args)
+            .fetch_optional(db.get_postgres_connection_pool())
+            .await
+            .map_err(|e| DbErr::Custom(e.to_string()))?;
+
+    Ok(row
+        .and_then(|(time, point, received_at)| to_location(tracker_id, time, received_at, &point)))
+}
+
+/// Accumulates the distance traveled on `locations` starting from the cursor, ignoring GPS
+/// jitter and implausible jumps, returns the distance traveled per day and the last location
+/// accounted on the odometer.
+fn accumulate_distance(
+    start: Location,
+    locations: Vec<Location>,
+) -> (BTreeMap<NaiveDate, f64>, Location) {
+    let mut km_per_day: BTreeMap<NaiveDate, f64> = BTreeMap::new();
+    let mut prev = start;
+
+    for location in locations {
+        let km = haversine_km(&prev, &location);
+
+        if km < MIN_MOVEMENT_KM {
+            continue;
+        }
+
+        let hours = (location.time - prev.time).num_seconds() as f64 / 3600.0;
+
+        if hours <= 0.0 || km / hours > MAX_PLAUSIBLE_SPEED_KMH {
+            continue;
+        }
+
+        *km_per_day.entry(location.day()).or_insert(0.0) += km;
+        prev = location;
+    }
+
+    (km_per_day, prev)
+}
+
+async fn save_cursor(
+    db: &impl sea_orm::ConnectionTrait,
+    tracker_id: i32,
+    vehicle_id: i32,
+    processed_until: DateTime<Utc>,
+    point: &Location,
+) -> Result<(), DbErr> {
+    vehicle_tracker_odometer_cursor::Entity::insert(vehicle_tracker_odometer_cursor::ActiveModel {
+        vehicle_tracker_id: Set(tracker_id),
+        vehicle_id: Set(vehicle_id),
+        processed_until: Set(processed_until),
+        point_time: Set(point.time),
+        lat: Set(point.lat),
+        lng: Set(point.lng),
+    })
+    .on_conflict(
+        OnConflict::column(vehicle_tracker_odometer_cursor::Column::VehicleTrackerId)
+            .update_columns([
+                vehicle_tracker_odometer_cursor::Column::VehicleId,
+                vehicle_tracker_odometer_cursor::Column::ProcessedUntil,
+                vehicle_tracker_odometer_cursor::Column::PointTime,
+                vehicle_tracker_odometer_cursor::Column::Lat,
+                vehicle_tracker_odometer_cursor::Column::Lng,
+            ])
+            .to_owned(),
+    )
+    .exec(db)
+    .await?;
+
+    Ok(())
+}
+
+/// Updates the odometer of the vehicle with the locations of the tracker received after its
+/// cursor, when the tracker does not have a cursor or was moved to another vehicle the cursor
+/// is (re)started at its last location, so past locations are never accounted on a vehicle
+/// the tracker was not installed on.
+async fn update_tracker_vehicle_odometer(
+    db: &DatabaseConnection,
+    tracker_id: i32,
+    vehicle_id: i32,
+    cursor: Option<&vehicle_tracker_odometer_cursor::Model>,
+) -> Result<(), DbErr> {
+    let cursor = match cursor {
+        Some(c) if c.vehicle_id == vehicle_id => c,
+        _ => {
+            if let Some(last_location) = get_last_location(db, tracker_id).await? {
+                let time = last_location.time;
+                save_cursor(db, tracker_id, vehicle_id, time, &last_location).await?;
+            }
+
+            return Ok(());
+        }
+    };
+
+    let locations = get_locations_after(db, tracker_id, cursor.processed_until).await?;
+
+    let processed_until = match locations.last() {
+        Some(last) => last.time,
+        None => return Ok(()),
+    };
+
+    let start = Location {
+        time: cursor.point_time,
+        received_at: None,
+        lat: cursor.lat,
+        lng: cursor.lng,
+    };
+
+    let (km_per_day, last_accounted) = accumulate_distance(start, locations);
+    let total_km: f64 = km_per_day.values().sum();
+
+    let txn = db.begin().await?;
+
+    for (day, km) in km_per_day {
+        vehicle_daily_distance::Entity::insert(vehicle_daily_distance::ActiveModel {
+            vehicle_id: Set(vehicle_id),
+            day: Set(day),
+            distance_km: Set(km),
+        })
+        .on_conflict(
+            OnConflict::columns([
+                vehicle_daily_distance::Column::VehicleId,
+                vehicle_daily_distance::Column::Day,
+            ])
+            .value(
+                vehicle_daily_distance::Column::DistanceKm,
+                Expr::col((
+                    vehicle_daily_distance::Entity,
+                    vehicle_daily_distance::Column::DistanceKm,
+                ))
+                .add(km),
+            )
+            .to_owned(),
+        )
+        .exec(&txn)
+        .await?;
+    }
+
+    if total_km > 0.0 {
+        vehicle::Entity::update_many()
+            .col_expr(
+                vehicle::Column::OdometerKm,
+                Expr::col(vehicle::Column::OdometerKm).add(total_km),
+            )
+            .filter(vehicle::Column::Id.eq(vehicle_id))
+            .exec(&txn)
+            .await?;
+    }
+
+    save_cursor(
+        &txn,
+        tracker_id,
+        vehicle_id,
+        processed_until,
+        &last_accounted,
+    )
+    .await?;
+
+    txn.commit().await
+}
+
+/// Updates the odometer of every vehicle with a tracker installed
+pub async fn update_odometers(db: &DatabaseConnection) -> Result<(), DbErr> {
+    let trackers = vehicle_tracker::Entity::find()
+        .filter(vehicle_tracker::Column::VehicleId.is_not_null())
+        .all(db)
+        .await?;
+
+    let cursors: HashMap<i32, vehicle_tracker_odometer_cursor::Model> =
+        vehicle_tracker_odometer_cursor::Entity::find()
+            .all(db)
+            .await?
+            .into_iter()
+            .map(|c| (c.vehicle_tracker_id, c))
+            .collect();
+
+    for tracker in trackers {
+        if let Some(vehicle_id) = tracker.vehicle_id {
+            let cursor = cursors.get(&tracker.id);
+
+            if let Err(e) =
+                update_tracker_vehicle_odometer(db, tracker.id, vehicle_id, cursor).await
+            {
+                error!("failed to update odometer of vehicle {}: {}", vehicle_id, e);
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,119 @@
+//! Chunked (multipart) uploads of vehicle photos.
+//!
+//! clients on unstable connections upload the photo in parts, that can be retried
+//! individually and listed to resume the upload. Uploads are tracked on the
+//! `vehicle_photo_upload` table until completed or aborted, incomplete uploads are
+//! aborted after `MAX_UPLOAD_AGE_HOURS` so their parts are not stored (and billed) forever.
+
+use crate::services::s3::{UploadedPart, S3};
+use chrono::{Duration, Utc};
+use sea_orm::{ColumnTrait, DatabaseConnection, EntityTrait, QueryFilter};
+use shared::entity::vehicle_photo_upload;
+use tracing::{error, info};
+
+/// Minimum size of every part but the last, imposed by S3
+pub const MIN_PART_SIZE_BYTES: usize = 5 * 1024 * 1024;
+
+/// Maximum size of a part
+pub static MAX_PART_SIZE_BYTES: usize = 10 * 1024 * 1024;
+
+/// Maximum size of a photo, that is the sum of the size of its parts
+pub const MAX_PHOTO_SIZE_BYTES: i64 = 50 * 1024 * 1024;
+
+/// Maximum amount of parts of a upload, since every part but the last has at least
+/// `MIN_PART_SIZE_BYTES` a photo with more parts would be bigger than the maximum size
+pub const MAX_PARTS: i32 = (MAX_PHOTO_SIZE_BYTES / MIN_PART_SIZE_BYTES as i64) as i32;
+
+/// Hours after which incomplete uploads are aborted
+pub static MAX_UPLOAD_AGE_HOURS: i64 = 24;
+
+/// checks if the start of a file is of a JPEG, PNG or WEBP image by its magic bytes,
+/// the image types `get_image_extension_or_fail_request` accepts
+pub fn is_image(start: &[u8]) -> bool {
+    let is_jpeg = start.starts_with(&[0xFF, 0xD8, 0xFF]);
+    let is_png = start.starts_with(&[0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A]);
+    let is_webp = start.len() >= 12 && start.starts_with(b"RIFF") && &start[8..12] == b"WEBP";
+
+    is_jpeg || is_png || is_webp
+}
+
+/// the size of a photo after uploading a part of `size` bytes numbered `part_number`,
+/// the already uploaded part with the same number (if any) is replaced by it
+pub fn photo_size_with_part(uploaded: &[UploadedPart], part_number: i32, size: i64) -> i64 {
+    let others: i64 = uploaded
+        .iter()
+        .filter(|part| part.part_number != part_number)
+        .map(|part| part.size)
+        .sum();
+
+    others + size
+}
+
+/// Aborts the multipart uploads older than `MAX_UPLOAD_AGE_HOURS`
+///
+/// the pending uploads are listed from S3 instead of the `vehicle_photo_upload` table,
+/// so uploads without a record (eg: the vehicle was deleted) are aborted as well.
+pub async fn abort_stale_uploads(db: &DatabaseConnection, s3: &S3) -> anyhow::Result<()> {
+    let stale_since = Utc::now() - Duration::hours(MAX_UPLOAD_AGE_HOURS);
+
+    let stale_uploads = s3
+        .list_pending_multipart_uploads()
+        .await?
+        .into_iter()
+        .filter(|upload| upload.initiated_at.is_some_and(|t| t < stale_since));
+
+    for upload in stale_uploads {
+        match s3
+            .abort_multipart_upload(upload.key.clone(), &upload.upload_id)
+            .await
+        {
+            Ok(_) => info!("[S3] aborted stale multipart upload: {}", upload.key),
+            Err(e) => error!("[S3] failed to abort stale multipart upload: {}", e),
+        }
+    }
+
+    vehicle_photo_upload::Entity::delete_many()
+        .filter(vehicle_photo_upload::Column::CreatedAt.lt(stale_since))
+        .exec(db)
+        .await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn part(part_number: i32, size: i64) -> UploadedPart {
+        UploadedPart {
+            part_number,
+            e_tag: format!("etag-{}", part_number),
+            size,
+        }
+    }
+
+    #[test]
+    fn max_parts_of_the_min_size_fit_the_max_photo_size() {
+        assert_eq!(MAX_PARTS, 10);
+    }
+
+    #[test]
+    fn photo_size_replaces_the_part_with_the_same_number() {
+        let uploaded = [part(1, 6), part(2, 5), part(3, 2)];
+
+        assert_eq!(photo_size_with_part(&[], 1, 4), 4);
+        assert_eq!(photo_size_with_part(&uploaded, 4, 1), 14);
+        assert_eq!(photo_size_with_part(&uploaded, 2, 1), 9);
+    }
+
+    #[test]
+    fn detects_images_by_their_magic_bytes() {
+        assert!(is_image(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00, 0x10]));
+        assert!(is_image(b"\x89PNG\r\n\x1a\n\x00\x00\x00\rIHDR"));
+        assert!(is_image(b"RIFF\x24\x00\x00\x00WEBPVP8 "));
+
+        assert!(!is_image(b"RIFF\x24\x00\x00\x00WAVEfmt "));
+        assert!(!is_image(b"%PDF-1.7"));
+        assert!(!is_image(b""));
+    }
+}
@@ -1,10 +1,10 @@
 use super::dto::CreateVehicleDto;
 use crate::database::error::DbError;
-use sea_orm::{ActiveModelTrait, DatabaseConnection, Set};
+use sea_orm::{ActiveModelTrait, ConnectionTrait, Set};
 use shared::entity::vehicle;
 
-pub async fn create_vehicle(
-    conn: &DatabaseConnection,
+pub async fn create_vehicle<C: ConnectionTrait>(
+    conn: &C,
     dto: &CreateVehicleDto,
     org_id: i32,
 ) -> Result<vehicle::Model, DbError> {
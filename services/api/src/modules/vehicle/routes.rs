@@ -1,4 +1,14 @@
-use super::dto::{CreateVehicleDto, ListVehiclesDto, UpdateVehicleDto};
+use super::dto::{
+    CreateVehicleDto, DeletedVehicleDto, FailedVehicleImportRowDto, GetOdometerDto,
+    GetVehicleLocationHistoryDto, ImportVehiclesDto, ImportedVehicleDto, InitiatePhotoUploadDto,
+    ListVehiclesDto, ListVehiclesWithPositionsDto, PhotoUploadDto, UpdateVehicleDto,
+    UploadedPartDto, VehicleCsvRow, VehicleImportResultDto, VehicleOdometerDto,
+    VehicleWithPositionDto,
+};
+use super::photo_upload::{
+    is_image, photo_size_with_part, MAX_PARTS, MAX_PART_SIZE_BYTES, MAX_PHOTO_SIZE_BYTES,
+    MAX_UPLOAD_AGE_HOURS, MIN_PART_SIZE_BYTES,
+};
 use crate::{
     database::{
         error::DbError,
@@ -7,43 +17,75 @@ use crate::{
     modules::{
         auth::{self, middleware::AclLayer},
         common::{
-            dto::{Pagination, PaginationResult, SingleImageDto},
+            conditional_requests::{s3_object_response, Preconditions},
+            dto::{CountDto, Pagination, PaginationResult, SingleImageDto},
+            error_codes::{LIMIT_REACHED, PLATE_IN_USE},
             extractors::{
                 DbConnection, OrgBoundEntityFromPathId, OrganizationId, ValidatedJson,
                 ValidatedMultipart, ValidatedQuery,
             },
             multipart_form_data,
-            responses::{internal_error_msg, SimpleError},
+            responses::{
+                internal_error_msg, internal_error_res, FieldError, SimpleError,
+                ValidationErrorResponse,
+            },
+        },
+        organization::limits::{self, PlanLimit},
+        tracking::{
+            dto::PositionDto,
+            utils::{log_unexpected_geometry, StoredLocation},
         },
         vehicle::repository,
     },
     server::controller::AppState,
-    services::s3::S3Key,
+    services::s3::{S3Key, UploadedPart},
 };
-use axum::extract::{Path, State};
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Path, State};
+use axum::response::Response;
 use axum::{
     routing::{delete, get, post, put},
     Json, Router,
 };
 use axum_typed_multipart::TypedMultipart;
-use http::StatusCode;
+use chrono::{DateTime, Utc};
+use http::{Method, StatusCode};
 use migration::{extension::postgres::PgExpr, Expr};
 use sea_orm::{
-    ActiveModelTrait, ColumnTrait, EntityTrait, ModelTrait, PaginatorTrait, QueryFilter,
-    QueryOrder, QueryTrait,
+    ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr, EntityTrait,
+    FromQueryResult, JoinType, ModelTrait, PaginatorTrait, QueryFilter, QueryOrder, QuerySelect,
+    QueryTrait, RelationTrait, Select, Set, TransactionTrait,
 };
 use shared::constants::Permission;
-use shared::entity::{vehicle, vehicle_tracker};
+use shared::entity::{
+    vehicle, vehicle_daily_distance, vehicle_photo_upload, vehicle_tracker,
+    vehicle_tracker_last_location,
+};
+use std::collections::{HashMap, HashSet};
+use validator::Validate;
+
+/// Maximum time range of a vehicle location history query
+const MAX_LOCATION_HISTORY_DAYS: i64 = 31;
 
 pub fn create_router(state: AppState) -> Router<AppState> {
     Router::new()
         .route("/", get(list_vehicles))
+        .route("/count", get(count_vehicles))
+        //
+        .route("/with-positions", post(list_vehicles_with_positions))
         //
         .route(
             "/",
             post(create_vehicle).route_layer(AclLayer::single(Permission::CreateVehicle)),
         )
         //
+        .route(
+            "/import",
+            post(import_vehicles)
+                .route_layer(AclLayer::single(Permission::CreateVehicle))
+                .layer(DefaultBodyLimit::max(MAX_VEHICLE_IMPORT_BYTES)),
+        )
+        //
         .route("/:vehicle_id", get(vehicle_by_id))
         //
         .route(
@@ -58,6 +100,15 @@ pub fn create_router(state: AppState) -> Router<AppState> {
         //
         .route("/:vehicle_id/tracker", get(get_vehicle_tracker))
         //
+        .route("/:vehicle_id/odometer", get(get_vehicle_odometer))
+        //
+        .route(
+            "/:vehicle_id/location-history",
+            post(get_vehicle_location_history),
+        )
+        //
+        .route("/:vehicle_id/photo", get(get_vehicle_photo))
+        //
         .route(
             "/:vehicle_id/photo",
             put(update_vehicle_photo).route_layer(AclLayer::single(Permission::UpdateVehicle)),
@@ -68,6 +119,36 @@ pub fn create_router(state: AppState) -> Router<AppState> {
             delete(delete_vehicle_photo).route_layer(AclLayer::single(Permission::UpdateVehicle)),
         )
         //
+        .route(
+            "/:vehicle_id/photo/upload",
+            post(initiate_vehicle_photo_upload)
+                .route_layer(AclLayer::single(Permission::UpdateVehicle)),
+        )
+        //
+        .route(
+            "/:vehicle_id/photo/upload/:upload_id",
+            get(get_vehicle_photo_upload).route_layer(AclLayer::single(Permission::UpdateVehicle)),
+        )
+        //
+        .route(
+            "/:vehicle_id/photo/upload/:upload_id",
+            delete(abort_vehicle_photo_upload)
+                .route_layer(AclLayer::single(Permission::UpdateVehicle)),
+        )
+        //
+        .route(
+            "/:vehicle_id/photo/upload/:upload_id/part/:part_number",
+            put(upload_vehicle_photo_part)
+                .route_layer(AclLayer::single(Permission::UpdateVehicle))
+                .layer(DefaultBodyLimit::max(MAX_PART_SIZE_BYTES)),
+        )
+        //
+        .route(
+            "/:vehicle_id/photo/upload/:upload_id/complete",
+            post(complete_vehicle_photo_upload)
+                .route_layer(AclLayer::single(Permission::UpdateVehicle)),
+        )
+        //
         .route_layer(axum::middleware::from_fn_with_state(
             state,
             auth::middleware::require_user,
@@ -129,6 +210,134 @@ pub async fn get_vehicle_tracker(
     Ok(Json(tracker))
 }
 
+/// Get the kilometers traveled by a vehicle
+#[utoipa::path(
+    get,
+    tag = "vehicle",
+    path = "/vehicle/{vehicle_id}/odometer",
+    security(("session_id" = [])),
+    params(
+        ("vehicle_id" = u128, Path, description = "id of the vehicle"),
+        GetOdometerDto,
+    ),
+    responses(
+        (
+            status = OK,
+            content_type = "application/json",
+            body = VehicleOdometerDto,
+        ),
+    ),
+)]
+pub async fn get_vehicle_odometer(
+    DbConnection(db): DbConnection,
+    ValidatedQuery(query): ValidatedQuery<GetOdometerDto>,
+    OrgBoundEntityFromPathId(v): OrgBoundEntityFromPathId<vehicle::Entity>,
+) -> Result<Json<VehicleOdometerDto>, (StatusCode, SimpleError)> {
+    let distance_km = match query.since {
+        Some(since) => vehicle_daily_distance::Entity::find()
+            .select_only()
+            .column_as(vehicle_daily_distance::Column::DistanceKm.sum(), "sum")
+            .filter(vehicle_daily_distance::Column::VehicleId.eq(v.id))
+            .filter(vehicle_daily_distance::Column::Day.gte(since.date_naive()))
+            .into_tuple::<Option<f64>>()
+            .one(&db)
+            .await
+            .map_err(DbError::from)?
+            .flatten()
+            .unwrap_or(0.0),
+        None => v.odometer_km,
+    };
+
+    Ok(Json(VehicleOdometerDto {
+        odometer_km: v.odometer_km,
+        since: query.since,
+        distance_km,
+    }))
+}
+
+/// Get the locations of a vehicle
+///
+/// Lists the locations of every tracker that was installed on the vehicle,
+/// only including the ones sent while the tracker was installed on it, in
+/// chronological order.
+#[utoipa::path(
+    post,
+    tag = "vehicle",
+    path = "/vehicle/{vehicle_id}/location-history",
+    security(("session_id" = [])),
+    params(
+        ("vehicle_id" = u128, Path, description = "id of the vehicle"),
+    ),
+    request_body(content = GetVehicleLocationHistoryDto, content_type = "application/json"),
+    responses(
+        (
+            status = OK,
+            description = "the vehicle locations",
+            body = Vec<PositionDto>,
+            content_type = "application/json",
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / invalid time range",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_vehicle_location_history(
+    DbConnection(db): DbConnection,
+    OrgBoundEntityFromPathId(v): OrgBoundEntityFromPathId<vehicle::Entity>,
+    ValidatedJson(dto): ValidatedJson<GetVehicleLocationHistoryDto>,
+) -> Result<Json<Vec<PositionDto>>, (StatusCode, SimpleError)> {
+    if dto.before <= dto.after {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("before must be after the after timestamp"),
+        ));
+    }
+
+    if dto.before - dto.after > chrono::Duration::days(MAX_LOCATION_HISTORY_DAYS) {
+        let err_msg = format!("time range must not exceed {MAX_LOCATION_HISTORY_DAYS} days");
+        return Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)));
+    }
+
+    // the assignments split the time range in the intervals each tracker was installed
+    // on the vehicle, the time range is also applied to the locations directly so only
+    // the location chunks of the requested range are scanned
+    let sql = r#"
+SELECT l.time, l.point, l.vehicle_tracker_id, l.device_time, l.received_at
+FROM vehicle_tracker_assignment a
+INNER JOIN vehicle_tracker_location l
+    ON l.vehicle_tracker_id = a.vehicle_tracker_id
+    AND l.time >= a.started_at
+    AND (a.ended_at IS NULL OR l.time < a.ended_at)
+WHERE a.vehicle_id = $1
+AND a.organization_id = $2
+AND a.started_at < $4
+AND (a.ended_at IS NULL OR a.ended_at > $3)
+AND l.time >= $3
+AND l.time < $4
+ORDER BY l.time ASC
+LIMIT $5
+    "#;
+
+    let rows: Vec<StoredLocation> = sqlx::query_as(sql)
+        .bind(v.id)
+        .bind(v.organization_id)
+        .bind(dto.after)
+        .bind(dto.before)
+        .bind(dto.limit.unwrap_or(500))
+        .fetch_all(db.get_postgres_connection_pool())
+        .await
+        .map_err(|_| internal_error_res())?;
+
+    let positions = rows
+        .into_iter()
+        .filter_map(|row| log_unexpected_geometry(PositionDto::from_stored_location(&row)))
+        .collect();
+
+    Ok(Json(positions))
+}
+
 /// Update a vehicle
 #[utoipa::path(
     put,
@@ -234,6 +443,53 @@ pub async fn update_vehicle_photo(
     Ok(Json(String::from(key)))
 }
 
+/// Gets a vehicle photo
+///
+/// HEAD requests are supported to fetch only the photo metadata. the response contains the
+/// `ETag` and `Last-Modified` headers, so clients can send `If-None-Match` or `If-Modified-Since`
+/// to receive a `304 Not Modified` without a body if their cached photo is up to date.
+#[utoipa::path(
+    get,
+    tag = "vehicle",
+    path = "/vehicle/{vehicle_id}/photo",
+    security(("session_id" = [])),
+    params(
+        ("vehicle_id" = i32, Path, description = "id of the vehicle"),
+        ("If-None-Match" = Option<String>, Header, description = "ETag of the cached photo"),
+        ("If-Modified-Since" = Option<String>, Header, description = "Last-Modified of the cached photo"),
+    ),
+    responses(
+        (
+            status = OK,
+            description = "the vehicle photo",
+            content_type = "image/*",
+            body = Vec<u8>,
+        ),
+        (
+            status = NOT_MODIFIED,
+            description = "the cached photo is up to date",
+        ),
+        (
+            status = NOT_FOUND,
+            description = "vehicle not found or without a photo",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn get_vehicle_photo(
+    method: Method,
+    preconditions: Preconditions,
+    State(state): State<AppState>,
+    OrgBoundEntityFromPathId(req_vehicle): OrgBoundEntityFromPathId<vehicle::Entity>,
+) -> Result<Response, (StatusCode, SimpleError)> {
+    let photo = req_vehicle.photo.ok_or((
+        StatusCode::NOT_FOUND,
+        SimpleError::from("vehicle does not have a photo"),
+    ))?;
+
+    s3_object_response(&state.s3, photo, &method, &preconditions).await
+}
+
 /// Deletes a vehicle photo
 #[utoipa::path(
     delete,
@@ -278,175 +534,1135 @@ pub async fn delete_vehicle_photo(
     Ok(Json(String::from("photo deleted successfuly")))
 }
 
-/// Deletes a vehicle
+/// Starts a chunked vehicle photo upload
+///
+/// Required permissions: UPDATE_VEHICLE
+///
+/// for large photos or unstable connections, the photo is uploaded in parts that can be
+/// retried individually and the upload can be resumed by listing its uploaded parts.
+/// once all parts are uploaded the upload must be completed, or aborted if no longer needed.
+/// incomplete uploads are aborted after 24 hours.
 #[utoipa::path(
-    delete,
+    post,
     tag = "vehicle",
-    path = "/vehicle/{vehicle_id}",
+    path = "/vehicle/{vehicle_id}/photo/upload",
     security(("session_id" = [])),
     params(
-        ("vehicle_id" = u128, Path, description = "id of the vehicle to delete"),
+        ("vehicle_id" = i32, Path, description = "id of the vehicle to upload the photo"),
     ),
+    request_body = InitiatePhotoUploadDto,
     responses(
         (
             status = OK,
-            body = String,
-            content_type = "application/json",
-            description = "success message",
-            example = json!("vehicle deleted successfully"),
+            description = "the started upload",
+            body = PhotoUploadDto,
         ),
         (
-            status = UNAUTHORIZED,
-            description = "invalid session",
+            status = BAD_REQUEST,
+            description = "invalid file extension",
             body = SimpleError,
         ),
     ),
 )]
-pub async fn delete_vehicle(
+pub async fn initiate_vehicle_photo_upload(
     Path(vehicle_id): Path<i32>,
     State(state): State<AppState>,
     DbConnection(db): DbConnection,
     OrganizationId(org_id): OrganizationId,
-    OrgBoundEntityFromPathId(req_vehicle): OrgBoundEntityFromPathId<vehicle::Entity>,
-) -> Result<Json<String>, (StatusCode, SimpleError)> {
-    let delete_result = vehicle::Entity::delete_many()
-        .filter(vehicle::Column::Id.eq(vehicle_id))
-        .filter(vehicle::Column::OrganizationId.eq(org_id))
-        .exec(&db)
+    OrgBoundEntityFromPathId(_): OrgBoundEntityFromPathId<vehicle::Entity>,
+    ValidatedJson(dto): ValidatedJson<InitiatePhotoUploadDto>,
+) -> Result<Json<PhotoUploadDto>, (StatusCode, SimpleError)> {
+    let key = String::from(S3Key {
+        folder: format!("organization/{}/vehicle/{}", org_id, vehicle_id),
+        filename: multipart_form_data::filename_from_img_name("photo", &dto.filename)?,
+    });
+
+    let s3_upload_id = state
+        .s3
+        .initiate_multipart_upload(key.clone())
         .await
-        .map_err(DbError::from)?;
+        .map_err(|_| internal_error_msg("failed to start vehicle photo upload"))?;
 
-    if let Some(photo) = req_vehicle.photo {
-        let _ = state.s3.delete(photo).await;
+    let upload = vehicle_photo_upload::ActiveModel {
+        s3_key: Set(key),
+        s3_upload_id: Set(s3_upload_id),
+        vehicle_id: Set(vehicle_id),
+        organization_id: Set(org_id),
+        ..Default::default()
     }
+    .insert(&db)
+    .await
+    .map_err(DbError::from)?;
 
-    if delete_result.rows_affected < 1 {
-        let err_msg = "vehicle does not exist or does not belong to the request user organization";
-        Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)))
-    } else {
-        Ok(Json(String::from("vehicle deleted successfully")))
-    }
+    Ok(Json(photo_upload_dto(upload, vec![])))
 }
 
-/// Lists the vehicles that belong to the same org as the request user
+/// Gets a chunked vehicle photo upload
+///
+/// Required permissions: UPDATE_VEHICLE
+///
+/// lists the parts already uploaded, to resume the upload
 #[utoipa::path(
     get,
     tag = "vehicle",
-    path = "/vehicle",
+    path = "/vehicle/{vehicle_id}/photo/upload/{upload_id}",
     security(("session_id" = [])),
     params(
-        Pagination,
-        ListVehiclesDto
+        ("vehicle_id" = i32, Path, description = "id of the vehicle"),
+        ("upload_id" = i32, Path, description = "id of the upload"),
     ),
     responses(
         (
             status = OK,
-            description = "paginated list of vehicles",
-            content_type = "application/json",
-            body = PaginatedVehicle,
+            description = "the upload",
+            body = PhotoUploadDto,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "upload not found",
+            body = SimpleError,
         ),
     ),
 )]
-pub async fn list_vehicles(
-    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
-    ValidatedQuery(filter): ValidatedQuery<ListVehiclesDto>,
-    OrganizationId(org_id): OrganizationId,
+pub async fn get_vehicle_photo_upload(
+    Path((vehicle_id, upload_id)): Path<(i32, i32)>,
+    State(state): State<AppState>,
     DbConnection(db): DbConnection,
-) -> Result<Json<PaginationResult<vehicle::Model>>, (StatusCode, SimpleError)> {
-    let db_query = vehicle::Entity::find()
-        .filter(vehicle::Column::OrganizationId.eq(org_id))
-        .apply_if(filter.plate, |query, plate| {
-            if !plate.is_empty() {
-                let col = Expr::col((vehicle::Entity, vehicle::Column::Plate));
-                query.filter(col.ilike(format!("%{}%", plate)))
-            } else {
-                query
-            }
-        })
-        .order_by_asc(vehicle::Column::Id)
-        .paginate(&db, pagination.page_size);
+    OrganizationId(org_id): OrganizationId,
+) -> Result<Json<PhotoUploadDto>, (StatusCode, SimpleError)> {
+    let upload = find_photo_upload(&db, org_id, vehicle_id, upload_id).await?;
 
-    let result = paginated_query_to_pagination_result(db_query, pagination)
+    let parts = state
+        .s3
+        .list_parts(upload.s3_key.clone(), &upload.s3_upload_id)
         .await
-        .map_err(DbError::from)?;
+        .map_err(|_| internal_error_msg("failed to list uploaded parts"))?;
 
-    Ok(Json(result))
+    Ok(Json(photo_upload_dto(upload, parts)))
 }
 
-/// Creates a new vehicle
+/// Uploads a part of a chunked vehicle photo upload
 ///
-/// Required permissions: CREATE_VEHICLE
+/// Required permissions: UPDATE_VEHICLE
+///
+/// the request body is the binary content of the part, every part but the last must have
+/// at least 5MB and at most 10MB. Parts are numbered from 1 to 10 and uploading a part
+/// with a already uploaded number replaces it, so failed parts can just be retried.
+///
+/// the first part must start with the content of a JPEG, PNG or WEBP image and parts
+/// that would make the photo bigger than 50MB are refused.
 #[utoipa::path(
-    post,
+    put,
     tag = "vehicle",
-    path = "/vehicle",
+    path = "/vehicle/{vehicle_id}/photo/upload/{upload_id}/part/{part_number}",
     security(("session_id" = [])),
-    request_body(content = CreateVehicleDto, content_type = "multipart/form-data"),
+    params(
+        ("vehicle_id" = i32, Path, description = "id of the vehicle"),
+        ("upload_id" = i32, Path, description = "id of the upload"),
+        ("part_number" = i32, Path, description = "number of the part, from 1 to 10"),
+    ),
+    request_body(content = Vec<u8>, content_type = "application/octet-stream"),
     responses(
         (
             status = OK,
-            description = "the created vehicle",
-            content_type = "application/json",
-            body = entity::vehicle::Model,
+            description = "the uploaded part",
+            body = UploadedPartDto,
         ),
         (
             status = BAD_REQUEST,
-            description = "invalid dto error message / PLATE_IN_USE",
+            description = "invalid part number, empty part or the first part is not of a image",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "upload not found",
             body = SimpleError,
         ),
+        (
+            status = PAYLOAD_TOO_LARGE,
+            description = "part bigger than 10MB or the photo would be bigger than 50MB",
+        ),
     ),
 )]
-pub async fn create_vehicle(
+pub async fn upload_vehicle_photo_part(
+    Path((vehicle_id, upload_id, part_number)): Path<(i32, i32, i32)>,
     State(state): State<AppState>,
+    DbConnection(db): DbConnection,
     OrganizationId(org_id): OrganizationId,
-    ValidatedMultipart(dto): ValidatedMultipart<CreateVehicleDto>,
-) -> Result<Json<vehicle::Model>, (StatusCode, SimpleError)> {
-    let created_vehicle = repository::create_vehicle(&state.db, &dto, org_id).await?;
-
-    if let Some(photo) = dto.photo {
-        let img_validation = multipart_form_data::filename_from_img("photo", &photo);
-
-        let filename = match img_validation {
-            Ok(filename) => filename,
-            Err(e) => {
-                // Creating the vehicle without the uploaded photo is not acceptable
-                // therefore delete the created vehicle and return a error response.
-                let _ = created_vehicle.delete(&state.db).await;
-
-                return Err(e);
-            }
-        };
+    body: Bytes,
+) -> Result<Json<UploadedPartDto>, (StatusCode, SimpleError)> {
+    if !(1..=MAX_PARTS).contains(&part_number) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(format!("part number must be between 1 and {}", MAX_PARTS)),
+        ));
+    }
 
-        let folder = format!("organization/{}/vehicle/{}", org_id, created_vehicle.id);
+    if body.is_empty() {
+        return Err((StatusCode::BAD_REQUEST, SimpleError::from("empty part")));
+    }
 
-        let key = S3Key { folder, filename };
+    // the photo starts with the first part, so checking it is enough to refuse non image files
+    if part_number == 1 && !is_image(&body) {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("the photo must be a JPEG, PNG or WEBP image"),
+        ));
+    }
 
-        if state
-            .s3
-            .upload(key.clone().into(), photo.contents)
-            .await
-            .is_err()
-        {
-            let _ = created_vehicle.delete(&state.db).await;
+    let upload = find_photo_upload(&db, org_id, vehicle_id, upload_id).await?;
 
-            return Err(internal_error_msg("failed to upload vehicle photo"));
-        };
+    let size = body.len() as i64;
 
-        let uploaded_photo = String::from(key.clone());
+    let uploaded_parts = state
+        .s3
+        .list_parts(upload.s3_key.clone(), &upload.s3_upload_id)
+        .await
+        .map_err(|_| internal_error_msg("failed to list uploaded parts"))?;
 
-        let update_photo_on_db_result = vehicle::Entity::update_many()
-            .col_expr(vehicle::Column::Photo, Expr::value(uploaded_photo.clone()))
-            .filter(vehicle::Column::Id.eq(created_vehicle.id))
-            .exec(&state.db)
-            .await;
+    if photo_size_with_part(&uploaded_parts, part_number, size) > MAX_PHOTO_SIZE_BYTES {
+        return Err((
+            StatusCode::PAYLOAD_TOO_LARGE,
+            SimpleError::from("the photo must have at most 50MB"),
+        ));
+    }
 
-        if update_photo_on_db_result.is_err() {
-            let _ = state.s3.delete(uploaded_photo).await;
-            let _ = created_vehicle.delete(&state.db).await;
+    state
+        .s3
+        .upload_part(upload.s3_key, &upload.s3_upload_id, part_number, body)
+        .await
+        .map_err(|_| internal_error_msg("failed to upload part"))?;
 
-            return Err(internal_error_msg("failed to set vehicle photo"));
-        }
-    }
+    Ok(Json(UploadedPartDto { part_number, size }))
+}
 
-    Ok(Json(created_vehicle))
+/// Completes a chunked vehicle photo upload
+///
+/// Required permissions: UPDATE_VEHICLE
+///
+/// assembles the photo from the uploaded parts, ordered by their numbers,
+/// and sets it as the vehicle photo, returning its S3 object key.
+///
+/// the first part must be uploaded and the photo must have at most 50MB.
+#[utoipa::path(
+    post,
+    tag = "vehicle",
+    path = "/vehicle/{vehicle_id}/photo/upload/{upload_id}/complete",
+    security(("session_id" = [])),
+    params(
+        ("vehicle_id" = i32, Path, description = "id of the vehicle"),
+        ("upload_id" = i32, Path, description = "id of the upload"),
+    ),
+    responses(
+        (
+            status = OK,
+            body = String,
+            content_type = "application/json",
+            description = "S3 object key of the new vehicle photo",
+            example = json!("rastercar/organization/1/vehicle/2/photo-10-2023_00:19:17.jpeg"),
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "no parts uploaded, the first part is missing, a part other than the last is smaller than 5MB or the photo is bigger than 50MB",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "upload not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn complete_vehicle_photo_upload(
+    Path((vehicle_id, upload_id)): Path<(i32, i32)>,
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+) -> Result<Json<String>, (StatusCode, SimpleError)> {
+    let upload = find_photo_upload(&db, org_id, vehicle_id, upload_id).await?;
+
+    let parts = state
+        .s3
+        .list_parts(upload.s3_key.clone(), &upload.s3_upload_id)
+        .await
+        .map_err(|_| internal_error_msg("failed to list uploaded parts"))?;
+
+    if parts.is_empty() {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("no parts uploaded"),
+        ));
+    }
+
+    // parts are listed ordered by their numbers, and only the first one is checked to be of a image
+    if parts[0].part_number != 1 {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("the first part was not uploaded"),
+        ));
+    }
+
+    if parts.iter().map(|part| part.size).sum::<i64>() > MAX_PHOTO_SIZE_BYTES {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("the photo must have at most 50MB"),
+        ));
+    }
+
+    let has_small_part = parts[..parts.len() - 1]
+        .iter()
+        .any(|part| (part.size as usize) < MIN_PART_SIZE_BYTES);
+
+    if has_small_part {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from("every part but the last must have at least 5MB"),
+        ));
+    }
+
+    state
+        .s3
+        .complete_multipart_upload(upload.s3_key.clone(), &upload.s3_upload_id, parts)
+        .await
+        .map_err(|_| internal_error_msg("failed to complete vehicle photo upload"))?;
+
+    let old_photo = vehicle::Entity::find_by_id(vehicle_id)
+        .one(&db)
+        .await
+        .map_err(DbError::from)?
+        .and_then(|v| v.photo);
+
+    vehicle::Entity::update_many()
+        .col_expr(vehicle::Column::Photo, Expr::value(upload.s3_key.clone()))
+        .filter(vehicle::Column::Id.eq(vehicle_id))
+        .exec(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    vehicle_photo_upload::Entity::delete_by_id(upload.id)
+        .exec(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    if let Some(old_photo) = old_photo {
+        let _ = state.s3.delete(old_photo).await;
+    }
+
+    Ok(Json(upload.s3_key))
+}
+
+/// Aborts a chunked vehicle photo upload
+///
+/// Required permissions: UPDATE_VEHICLE
+///
+/// deletes the uploaded parts, the upload cannot be resumed afterwards
+#[utoipa::path(
+    delete,
+    tag = "vehicle",
+    path = "/vehicle/{vehicle_id}/photo/upload/{upload_id}",
+    security(("session_id" = [])),
+    params(
+        ("vehicle_id" = i32, Path, description = "id of the vehicle"),
+        ("upload_id" = i32, Path, description = "id of the upload"),
+    ),
+    responses(
+        (
+            status = OK,
+            body = String,
+            content_type = "application/json",
+            description = "success message",
+            example = json!("upload aborted successfully"),
+        ),
+        (
+            status = NOT_FOUND,
+            description = "upload not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn abort_vehicle_photo_upload(
+    Path((vehicle_id, upload_id)): Path<(i32, i32)>,
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+) -> Result<Json<String>, (StatusCode, SimpleError)> {
+    let upload = find_photo_upload(&db, org_id, vehicle_id, upload_id).await?;
+
+    // the record is only deleted if the parts were deleted, otherwise the
+    // upload is aborted by the cleanup cronjob once it gets stale
+    state
+        .s3
+        .abort_multipart_upload(upload.s3_key.clone(), &upload.s3_upload_id)
+        .await
+        .map_err(|_| internal_error_msg("failed to abort vehicle photo upload"))?;
+
+    vehicle_photo_upload::Entity::delete_by_id(upload.id)
+        .exec(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(String::from("upload aborted successfully")))
+}
+
+async fn find_photo_upload(
+    db: &DatabaseConnection,
+    org_id: i32,
+    vehicle_id: i32,
+    upload_id: i32,
+) -> Result<vehicle_photo_upload::Model, (StatusCode, SimpleError)> {
+    vehicle_photo_upload::Entity::find_by_id(upload_id)
+        .filter(vehicle_photo_upload::Column::VehicleId.eq(vehicle_id))
+        .filter(vehicle_photo_upload::Column::OrganizationId.eq(org_id))
+        .one(db)
+        .await
+        .map_err(|_| internal_error_res())?
+        .ok_or((StatusCode::NOT_FOUND, SimpleError::from("upload not found")))
+}
+
+fn photo_upload_dto(
+    upload: vehicle_photo_upload::Model,
+    parts: Vec<UploadedPart>,
+) -> PhotoUploadDto {
+    PhotoUploadDto {
+        id: upload.id,
+        created_at: upload.created_at,
+        expires_at: upload.created_at + chrono::Duration::hours(MAX_UPLOAD_AGE_HOURS),
+        min_part_size_bytes: MIN_PART_SIZE_BYTES,
+        max_part_size_bytes: MAX_PART_SIZE_BYTES,
+        parts: parts
+            .into_iter()
+            .map(|part| UploadedPartDto {
+                part_number: part.part_number,
+                size: part.size,
+            })
+            .collect(),
+    }
+}
+
+/// Deletes a vehicle
+///
+/// trackers installed on the vehicle are uninstalled from it in the same transaction,
+/// ending their assignments, so they are kept without a vehicle.
+#[utoipa::path(
+    delete,
+    tag = "vehicle",
+    path = "/vehicle/{vehicle_id}",
+    security(("session_id" = [])),
+    params(
+        ("vehicle_id" = u128, Path, description = "id of the vehicle to delete"),
+    ),
+    responses(
+        (
+            status = OK,
+            body = DeletedVehicleDto,
+            content_type = "application/json",
+            description = "the trackers uninstalled from the deleted vehicle",
+        ),
+        (
+            status = UNAUTHORIZED,
+            description = "invalid session",
+            body = SimpleError,
+        ),
+        (
+            status = NOT_FOUND,
+            description = "vehicle not found",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn delete_vehicle(
+    Path(vehicle_id): Path<i32>,
+    State(state): State<AppState>,
+    DbConnection(db): DbConnection,
+    OrganizationId(org_id): OrganizationId,
+    OrgBoundEntityFromPathId(req_vehicle): OrgBoundEntityFromPathId<vehicle::Entity>,
+) -> Result<Json<DeletedVehicleDto>, (StatusCode, SimpleError)> {
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    let unassigned_tracker_ids = delete_org_vehicle(&txn, vehicle_id, org_id)
+        .await
+        .map_err(DbError::from)?
+        .ok_or((StatusCode::NOT_FOUND, SimpleError::entity_not_found()))?;
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    if let Some(photo) = req_vehicle.photo {
+        let _ = state.s3.delete(photo).await;
+    }
+
+    Ok(Json(DeletedVehicleDto {
+        unassigned_tracker_ids,
+    }))
+}
+
+/// deletes the vehicle of the organization uninstalling its trackers, returns the ids of
+/// the uninstalled trackers or `None` if the organization has no vehicle with the id
+async fn delete_org_vehicle<C: ConnectionTrait>(
+    db: &C,
+    vehicle_id: i32,
+    org_id: i32,
+) -> Result<Option<Vec<i32>>, DbErr> {
+    let unassigned_tracker_ids: Vec<i32> = vehicle_tracker::Entity::find()
+        .select_only()
+        .column(vehicle_tracker::Column::Id)
+        .filter(vehicle_tracker::Column::VehicleId.eq(vehicle_id))
+        .filter(vehicle_tracker::Column::OrganizationId.eq(org_id))
+        .into_tuple()
+        .all(db)
+        .await?;
+
+    // the foreign key would set the trackers vehicle to null anyway, but this
+    // does it explicitly so the trackers assignment history is ended by
+    // its trigger regardless of the foreign key behavior
+    vehicle_tracker::Entity::update_many()
+        .col_expr(
+            vehicle_tracker::Column::VehicleId,
+            Expr::value::<Option<i32>>(None),
+        )
+        .filter(vehicle_tracker::Column::Id.is_in(unassigned_tracker_ids.clone()))
+        .exec(db)
+        .await?;
+
+    let delete_result = vehicle::Entity::delete_many()
+        .filter(vehicle::Column::Id.eq(vehicle_id))
+        .filter(vehicle::Column::OrganizationId.eq(org_id))
+        .exec(db)
+        .await?;
+
+    if delete_result.rows_affected < 1 {
+        return Ok(None);
+    }
+
+    Ok(Some(unassigned_tracker_ids))
+}
+
+/// query of the vehicles of the organization matching the filter,
+/// shared by the list and count endpoints so their results are consistent
+fn filtered_vehicles(org_id: i32, filter: ListVehiclesDto) -> Select<vehicle::Entity> {
+    vehicle::Entity::find()
+        .filter(vehicle::Column::OrganizationId.eq(org_id))
+        .apply_if(filter.plate, |query, plate| {
+            if !plate.is_empty() {
+                let col = Expr::col((vehicle::Entity, vehicle::Column::Plate));
+                query.filter(col.ilike(format!("%{}%", plate)))
+            } else {
+                query
+            }
+        })
+}
+
+/// Lists the vehicles that belong to the same org as the request user
+#[utoipa::path(
+    get,
+    tag = "vehicle",
+    path = "/vehicle",
+    security(("session_id" = [])),
+    params(
+        Pagination,
+        ListVehiclesDto
+    ),
+    responses(
+        (
+            status = OK,
+            description = "paginated list of vehicles",
+            content_type = "application/json",
+            body = PaginatedVehicle,
+        ),
+    ),
+)]
+pub async fn list_vehicles(
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    ValidatedQuery(filter): ValidatedQuery<ListVehiclesDto>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<PaginationResult<vehicle::Model>>, (StatusCode, SimpleError)> {
+    let db_query = filtered_vehicles(org_id, filter)
+        .order_by_asc(vehicle::Column::Id)
+        .paginate(&db, pagination.page_size);
+
+    let result = paginated_query_to_pagination_result(db_query, pagination).await?;
+
+    Ok(Json(result))
+}
+
+/// Counts the vehicles that belong to the same org as the request user
+///
+/// accepts the same filters as the vehicle list, without fetching the vehicles
+#[utoipa::path(
+    get,
+    tag = "vehicle",
+    path = "/vehicle/count",
+    security(("session_id" = [])),
+    params(ListVehiclesDto),
+    responses(
+        (
+            status = OK,
+            description = "amount of vehicles matching the filter",
+            content_type = "application/json",
+            body = CountDto,
+        ),
+    ),
+)]
+pub async fn count_vehicles(
+    ValidatedQuery(filter): ValidatedQuery<ListVehiclesDto>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+) -> Result<Json<CountDto>, (StatusCode, SimpleError)> {
+    let count = filtered_vehicles(org_id, filter)
+        .count(&db)
+        .await
+        .map_err(DbError::from)?;
+
+    Ok(Json(CountDto { count }))
+}
+
+/// A vehicle row with the last location of its tracker
+#[derive(FromQueryResult)]
+struct VehicleWithPositionRow {
+    id: i32,
+    created_at: DateTime<Utc>,
+    plate: String,
+    photo: Option<String>,
+    model_year: Option<i16>,
+    fabrication_year: Option<i16>,
+    chassis_number: Option<String>,
+    brand: Option<String>,
+    model: Option<String>,
+    color: Option<String>,
+    additional_info: Option<String>,
+    organization_id: i32,
+    odometer_km: f64,
+    tracker_id: Option<i32>,
+    position_time: Option<DateTime<Utc>>,
+    device_time: Option<DateTime<Utc>>,
+    received_at: Option<DateTime<Utc>>,
+    lat: Option<f64>,
+    lng: Option<f64>,
+}
+
+/// Lists vehicles with their positions
+///
+/// lists the vehicles that belong to the same org as the request user together with the last
+/// position of their tracker, so maps can be rendered without fetching every position
+#[utoipa::path(
+    post,
+    tag = "vehicle",
+    path = "/vehicle/with-positions",
+    security(("session_id" = [])),
+    params(Pagination),
+    request_body = ListVehiclesWithPositionsDto,
+    responses(
+        (
+            status = OK,
+            description = "paginated list of vehicles with their positions",
+            content_type = "application/json",
+            body = PaginatedVehicleWithPosition,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn list_vehicles_with_positions(
+    ValidatedQuery(pagination): ValidatedQuery<Pagination>,
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    ValidatedJson(filter): ValidatedJson<ListVehiclesWithPositionsDto>,
+) -> Result<Json<PaginationResult<VehicleWithPositionDto>>, (StatusCode, SimpleError)> {
+    // a vehicle has at most one tracker, so the joins never duplicate vehicles
+    let paginator = vehicle::Entity::find()
+        .join(JoinType::LeftJoin, vehicle::Relation::VehicleTracker.def())
+        .join(
+            JoinType::LeftJoin,
+            vehicle_tracker::Relation::VehicleTrackerLastLocation.def(),
+        )
+        .column_as(
+            Expr::col((
+                vehicle_tracker_last_location::Entity,
+                vehicle_tracker_last_location::Column::VehicleTrackerId,
+            )),
+            "tracker_id",
+        )
+        .column_as(
+            Expr::col((
+                vehicle_tracker_last_location::Entity,
+                vehicle_tracker_last_location::Column::Time,
+            )),
+            "position_time",
+        )
+        .column_as(
+            Expr::col((
+                vehicle_tracker_last_location::Entity,
+                vehicle_tracker_last_location::Column::DeviceTime,
+            )),
+            "device_time",
+        )
+        .column_as(
+            Expr::col((
+                vehicle_tracker_last_location::Entity,
+                vehicle_tracker_last_location::Column::ReceivedAt,
+            )),
+            "received_at",
+        )
+        // locations are stored with the latitude as x
+        .column_as(
+            Expr::cust("ST_X(vehicle_tracker_last_location.point)"),
+            "lat",
+        )
+        .column_as(
+            Expr::cust("ST_Y(vehicle_tracker_last_location.point)"),
+            "lng",
+        )
+        .filter(vehicle::Column::OrganizationId.eq(org_id))
+        .apply_if(filter.plate, |query, plate| {
+            if !plate.is_empty() {
+                let col = Expr::col((vehicle::Entity, vehicle::Column::Plate));
+                query.filter(col.ilike(format!("%{}%", plate)))
+            } else {
+                query
+            }
+        })
+        .apply_if(filter.ids, |query, ids| {
+            query.filter(vehicle::Column::Id.is_in(ids))
+        })
+        .order_by_asc(vehicle::Column::Id)
+        .into_model::<VehicleWithPositionRow>()
+        .paginate(&db, pagination.page_size);
+
+    let n = paginator
+        .num_items_and_pages()
+        .await
+        .map_err(DbError::from)?;
+
+    let records = paginator
+        .fetch_page(pagination.page - 1)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .map(|row| {
+            let position = match (row.tracker_id, row.position_time, row.lat, row.lng) {
+                (Some(tracker_id), Some(time), Some(lat), Some(lng)) => Some(
+                    PositionDto::new(tracker_id, time, lat, lng)
+                        .with_device_time_and_received_at(row.device_time, row.received_at),
+                ),
+                _ => None,
+            };
+
+            VehicleWithPositionDto {
+                position,
+                vehicle: vehicle::Model {
+                    id: row.id,
+                    created_at: row.created_at,
+                    plate: row.plate,
+                    photo: row.photo,
+                    model_year: row.model_year,
+                    fabrication_year: row.fabrication_year,
+                    chassis_number: row.chassis_number,
+                    brand: row.brand,
+                    model: row.model,
+                    color: row.color,
+                    additional_info: row.additional_info,
+                    organization_id: row.organization_id,
+                    odometer_km: row.odometer_km,
+                },
+            }
+        })
+        .collect();
+
+    Ok(Json(PaginationResult {
+        page: pagination.page,
+        records,
+        page_size: pagination.page_size,
+        item_count: n.number_of_items,
+        page_count: n.number_of_pages,
+    }))
+}
+
+/// maximum amount of rows of a imported vehicles CSV
+const MAX_VEHICLE_IMPORT_ROWS: usize = 1000;
+
+/// maximum size of a imported vehicles CSV
+const MAX_VEHICLE_IMPORT_BYTES: usize = 1024 * 1024;
+
+/// CSV columns a vehicle cannot be created without
+const REQUIRED_VEHICLE_CSV_COLUMNS: [&str; 3] = ["plate", "brand", "model"];
+
+fn row_error(field: Option<&str>, message: impl Into<String>) -> FieldError {
+    FieldError {
+        field: field.map(String::from),
+        message: message.into(),
+    }
+}
+
+/// Creates vehicles from a CSV file
+///
+/// Required permissions: CREATE_VEHICLE
+///
+/// The CSV must have a header row with the columns `plate`, `brand` and `model` and optionally
+/// `year` (the model year), `color` and `chassis`, in any order, at most 1000 rows. plates are
+/// lowercased, so they can be written in uppercase.
+///
+/// Every row is validated, rows with a plate already used by a vehicle of the organization
+/// or by a previous row are invalid. the vehicles of the valid rows are created on a single
+/// transaction, unless `allOrNothing` is set, in which case no vehicle is created if any row
+/// is invalid. rows that would exceed the organization plan fail with `LIMIT_REACHED`, or the
+/// whole import when `allOrNothing` is set.
+#[utoipa::path(
+    post,
+    tag = "vehicle",
+    path = "/vehicle/import",
+    security(("session_id" = [])),
+    request_body(content = ImportVehiclesDto, content_type = "multipart/form-data"),
+    responses(
+        (
+            status = OK,
+            description = "the created vehicles and the invalid rows",
+            content_type = "application/json",
+            body = VehicleImportResultDto,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "the file is not a valid CSV, lacks a required column or has too many rows",
+            body = SimpleError,
+        ),
+        (
+            status = PAYMENT_REQUIRED,
+            description = "LIMIT_REACHED, only when allOrNothing is set",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn import_vehicles(
+    OrganizationId(org_id): OrganizationId,
+    DbConnection(db): DbConnection,
+    TypedMultipart(dto): TypedMultipart<ImportVehiclesDto>,
+) -> Result<Json<VehicleImportResultDto>, (StatusCode, SimpleError)> {
+    let all_or_nothing = dto.all_or_nothing.unwrap_or(false);
+
+    // flexible so rows with missing columns are reported as invalid rows
+    let mut reader = csv::ReaderBuilder::new()
+        .trim(csv::Trim::All)
+        .flexible(true)
+        .from_reader(dto.file.contents.as_ref());
+
+    let invalid_csv = |e: csv::Error| {
+        (
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(format!("invalid CSV: {}", e)),
+        )
+    };
+
+    // columns are matched case insensitively
+    let headers: csv::StringRecord = reader
+        .headers()
+        .map_err(invalid_csv)?
+        .iter()
+        .map(str::to_lowercase)
+        .collect();
+
+    if let Some(missing) = REQUIRED_VEHICLE_CSV_COLUMNS
+        .iter()
+        .find(|column| !headers.iter().any(|header| header == **column))
+    {
+        return Err((
+            StatusCode::BAD_REQUEST,
+            SimpleError::from(format!("CSV is missing the {} column", missing)),
+        ));
+    }
+
+    let mut valid_rows: Vec<(u64, VehicleCsvRow)> = Vec::new();
+    let mut failed: Vec<FailedVehicleImportRowDto> = Vec::new();
+
+    // the row each plate was first used on
+    let mut plate_rows: HashMap<String, u64> = HashMap::new();
+
+    for (index, record) in reader.records().enumerate() {
+        if index >= MAX_VEHICLE_IMPORT_ROWS {
+            let err_msg = format!("CSV has more than {} rows", MAX_VEHICLE_IMPORT_ROWS);
+            return Err((StatusCode::BAD_REQUEST, SimpleError::from(err_msg)));
+        }
+
+        let record = record.map_err(invalid_csv)?;
+        let row = record.position().map_or(index as u64 + 2, |p| p.line());
+
+        let mut csv_row: VehicleCsvRow = match record.deserialize(Some(&headers)) {
+            Ok(csv_row) => csv_row,
+            Err(e) => {
+                let field = match e.kind() {
+                    csv::ErrorKind::Deserialize { err, .. } => err
+                        .field()
+                        .and_then(|i| headers.get(i as usize).map(String::from)),
+                    _ => None,
+                };
+
+                failed.push(FailedVehicleImportRowDto {
+                    row,
+                    errors: vec![row_error(field.as_deref(), e.to_string())],
+                });
+                continue;
+            }
+        };
+
+        csv_row.plate = csv_row.plate.to_lowercase();
+
+        let mut errors = match csv_row.validate() {
+            Ok(_) => Vec::new(),
+            Err(e) => ValidationErrorResponse::from(e).errors,
+        };
+
+        match plate_rows.get(&csv_row.plate) {
+            Some(first_row) => errors.push(row_error(
+                Some("plate"),
+                format!("plate already used on row {}", first_row),
+            )),
+            None => {
+                plate_rows.insert(csv_row.plate.clone(), row);
+            }
+        }
+
+        if errors.is_empty() {
+            valid_rows.push((row, csv_row));
+        } else {
+            failed.push(FailedVehicleImportRowDto { row, errors });
+        }
+    }
+
+    let txn = db.begin().await.map_err(DbError::from)?;
+
+    let remaining = limits::remaining(&txn, org_id, PlanLimit::Vehicles).await?;
+
+    let plates_in_use: HashSet<String> = vehicle::Entity::find()
+        .select_only()
+        .column(vehicle::Column::Plate)
+        .filter(vehicle::Column::OrganizationId.eq(org_id))
+        .filter(vehicle::Column::Plate.is_in(valid_rows.iter().map(|(_, r)| r.plate.clone())))
+        .into_tuple()
+        .all(&txn)
+        .await
+        .map_err(DbError::from)?
+        .into_iter()
+        .collect();
+
+    let (valid_rows, rows_in_use): (Vec<_>, Vec<_>) = valid_rows
+        .into_iter()
+        .partition(|(_, csv_row)| !plates_in_use.contains(&csv_row.plate));
+
+    failed.extend(
+        rows_in_use
+            .into_iter()
+            .map(|(row, _)| FailedVehicleImportRowDto {
+                row,
+                errors: vec![row_error(Some("plate"), PLATE_IN_USE)],
+            }),
+    );
+
+    let allowed = remaining.map_or(valid_rows.len(), |r| valid_rows.len().min(r as usize));
+
+    if all_or_nothing && allowed < valid_rows.len() {
+        return Err((
+            StatusCode::PAYMENT_REQUIRED,
+            SimpleError::from(LIMIT_REACHED),
+        ));
+    }
+
+    failed.extend(
+        valid_rows[allowed..]
+            .iter()
+            .map(|(row, _)| FailedVehicleImportRowDto {
+                row: *row,
+                errors: vec![row_error(None, LIMIT_REACHED)],
+            }),
+    );
+
+    failed.sort_by_key(|f| f.row);
+
+    if all_or_nothing && !failed.is_empty() {
+        return Ok(Json(VehicleImportResultDto {
+            created: Vec::new(),
+            failed,
+        }));
+    }
+
+    let mut created: Vec<ImportedVehicleDto> = Vec::with_capacity(allowed);
+
+    for (row, csv_row) in valid_rows.into_iter().take(allowed) {
+        let vehicle = vehicle::ActiveModel {
+            plate: Set(csv_row.plate),
+            brand: Set(Some(csv_row.brand)),
+            model: Set(Some(csv_row.model)),
+            color: Set(csv_row.color),
+            model_year: Set(csv_row.year),
+            chassis_number: Set(csv_row.chassis),
+            organization_id: Set(org_id),
+            ..Default::default()
+        }
+        .insert(&txn)
+        .await
+        .map_err(DbError::from)?;
+
+        created.push(ImportedVehicleDto { row, vehicle });
+    }
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    Ok(Json(VehicleImportResultDto { created, failed }))
+}
+
+/// Creates a new vehicle
+///
+/// Required permissions: CREATE_VEHICLE
+#[utoipa::path(
+    post,
+    tag = "vehicle",
+    path = "/vehicle",
+    security(("session_id" = [])),
+    request_body(content = CreateVehicleDto, content_type = "multipart/form-data"),
+    responses(
+        (
+            status = OK,
+            description = "the created vehicle",
+            content_type = "application/json",
+            body = entity::vehicle::Model,
+        ),
+        (
+            status = BAD_REQUEST,
+            description = "invalid dto error message / PLATE_IN_USE",
+            body = SimpleError,
+        ),
+        (
+            status = PAYMENT_REQUIRED,
+            description = "LIMIT_REACHED",
+            body = SimpleError,
+        ),
+    ),
+)]
+pub async fn create_vehicle(
+    State(state): State<AppState>,
+    OrganizationId(org_id): OrganizationId,
+    ValidatedMultipart(dto): ValidatedMultipart<CreateVehicleDto>,
+) -> Result<Json<vehicle::Model>, (StatusCode, SimpleError)> {
+    let txn = state.db.begin().await.map_err(DbError::from)?;
+
+    limits::ensure_below_limit(&txn, org_id, PlanLimit::Vehicles).await?;
+
+    let created_vehicle = repository::create_vehicle(&txn, &dto, org_id).await?;
+
+    txn.commit().await.map_err(DbError::from)?;
+
+    if let Some(photo) = dto.photo {
+        let img_validation = multipart_form_data::filename_from_img("photo", &photo);
+
+        let filename = match img_validation {
+            Ok(filename) => filename,
+            Err(e) => {
+                // Creating the vehicle without the uploaded photo is not acceptable
+                // therefore delete the created vehicle and return a error response.
+                let _ = created_vehicle.delete(&state.db).await;
+
+                return Err(e);
+            }
+        };
+
+        let folder = format!("organization/{}/vehicle/{}", org_id, created_vehicle.id);
+
+        let key = S3Key { folder, filename };
+
+        if state
+            .s3
+            .upload(key.clone().into(), photo.contents)
+            .await
+            .is_err()
+        {
+            let _ = created_vehicle.delete(&state.db).await;
+
+            return Err(internal_error_msg("failed to upload vehicle photo"));
+        };
+
+        let uploaded_photo = String::from(key.clone());
+
+        let update_photo_on_db_result = vehicle::Entity::update_many()
+            .col_expr(vehicle::Column::Photo, Expr::value(uploaded_photo.clone()))
+            .filter(vehicle::Column::Id.eq(created_vehicle.id))
+            .exec(&state.db)
+            .await;
+
+        if update_photo_on_db_result.is_err() {
+            let _ = state.s3.delete(uploaded_photo).await;
+            let _ = created_vehicle.delete(&state.db).await;
+
+            return Err(internal_error_msg("failed to set vehicle photo"));
+        }
+    }
+
+    Ok(Json(created_vehicle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::database::test_db;
+    use migration::seeder;
+    use shared::entity::vehicle_tracker_assignment;
+
+    #[tokio::test]
+    #[ignore = "needs a database with PostGIS and TimescaleDB, see database::test_db"]
+    async fn deleting_a_vehicle_uninstalls_its_trackers() {
+        let txn = test_db::begin().await;
+
+        let org_id = seeder::gen_organization(&txn).await.unwrap();
+        let vehicle_id = seeder::gen_vehicle(&txn, org_id).await.unwrap();
+        let tracker_id = seeder::gen_tracker(&txn, org_id, Some(vehicle_id))
+            .await
+            .unwrap();
+
+        let unassigned = delete_org_vehicle(&txn, vehicle_id, org_id).await.unwrap();
+
+        assert_eq!(unassigned, Some(vec![tracker_id]));
+
+        let tracker = vehicle_tracker::Entity::find_by_id(tracker_id)
+            .one(&txn)
+            .await
+            .unwrap()
+            .expect("the tracker should not be deleted with the vehicle");
+
+        assert_eq!(tracker.vehicle_id, None);
+
+        let open_assignments = vehicle_tracker_assignment::Entity::find()
+            .filter(vehicle_tracker_assignment::Column::VehicleTrackerId.eq(tracker_id))
+            .filter(vehicle_tracker_assignment::Column::EndedAt.is_null())
+            .count(&txn)
+            .await
+            .unwrap();
+
+        assert_eq!(open_assignments, 0);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a database with PostGIS and TimescaleDB, see database::test_db"]
+    async fn vehicles_of_other_organizations_are_not_deleted() {
+        let txn = test_db::begin().await;
+
+        let org_id = seeder::gen_organization(&txn).await.unwrap();
+        let other_org_id = seeder::gen_organization(&txn).await.unwrap();
+        let vehicle_id = seeder::gen_vehicle(&txn, org_id).await.unwrap();
+        let tracker_id = seeder::gen_tracker(&txn, org_id, Some(vehicle_id))
+            .await
+            .unwrap();
+
+        let unassigned = delete_org_vehicle(&txn, vehicle_id, other_org_id)
+            .await
+            .unwrap();
+
+        assert_eq!(unassigned, None);
+
+        let tracker = vehicle_tracker::Entity::find_by_id(tracker_id)
+            .one(&txn)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(tracker.vehicle_id, Some(vehicle_id));
+    }
 }
@@ -1,44 +1,134 @@
 use lapin::{
     message::Delivery,
     options::{
-        BasicConsumeOptions, BasicPublishOptions, ExchangeDeclareOptions, QueueBindOptions,
-        QueueDeclareOptions,
+        BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, ConfirmSelectOptions,
+        ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
     },
-    publisher_confirm::PublisherConfirm,
-    types::FieldTable,
-    BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind,
+    publisher_confirm::{Confirmation, PublisherConfirm},
+    types::{AMQPValue, FieldTable},
+    BasicProperties, Channel, Connection, ConnectionProperties, Consumer, ExchangeKind,
+};
+use std::{
+    fmt,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
 };
-use std::time::Duration;
 use tokio::{sync::RwLock, time::sleep};
 use tokio_stream::StreamExt;
-use tracing::error;
+use tracing::{error, info};
+
+/// Declaration options of a queue
+#[derive(Debug, Clone)]
+pub struct QueueOptions {
+    pub durable: bool,
+    pub auto_delete: bool,
+    /// `x-message-ttl` argument, messages older than this are discarded or dead lettered
+    pub message_ttl_ms: Option<u32>,
+    /// `x-dead-letter-exchange` argument
+    pub dead_letter_exchange: Option<String>,
+}
+
+impl QueueOptions {
+    fn declare_options(&self) -> QueueDeclareOptions {
+        QueueDeclareOptions {
+            passive: false,
+            durable: self.durable,
+            exclusive: false,
+            auto_delete: self.auto_delete,
+            nowait: false,
+        }
+    }
+
+    fn arguments(&self) -> FieldTable {
+        let mut args = FieldTable::default();
+
+        if let Some(ttl) = self.message_ttl_ms {
+            args.insert("x-message-ttl".into(), AMQPValue::LongUInt(ttl));
+        }
+
+        if let Some(exchange) = &self.dead_letter_exchange {
+            args.insert(
+                "x-dead-letter-exchange".into(),
+                AMQPValue::LongString(exchange.clone().into()),
+            );
+        }
+
+        args
+    }
+}
+
+/// Error publishing a message and waiting for the broker to confirm it
+#[derive(Debug)]
+pub enum PublishError {
+    /// the message could not be delivered to the broker (eg: the connection is down)
+    Unreachable(lapin::Error),
+    /// the broker refused the message, either by nacking it or because
+    /// it was published as `mandatory` and could not be routed to any queue
+    Rejected,
+}
+
+impl fmt::Display for PublishError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PublishError::Unreachable(err) => write!(f, "broker unreachable: {}", err),
+            PublishError::Rejected => write!(f, "message rejected by the broker"),
+        }
+    }
+}
+
+impl std::error::Error for PublishError {}
 
 struct ConnectionEntities {
     connection: Connection,
-    publish_channel: Channel,
+    publish_channels: Vec<Channel>,
 }
 
 pub struct Rmq {
     /// RabbitMQ connetion URI
     amqp_uri: String,
 
+    /// declaration options of the tracker events queue
+    tracker_events_queue: QueueOptions,
+
+    /// amount of channels to publish messages on
+    publish_channel_count: usize,
+
     /// RabbitMQ connection
     connection: RwLock<Option<Connection>>,
 
-    /// channel for publishing messages, see:
+    /// channels for publishing messages, used in a round robin so concurrent publishes
+    /// are not serialized on a single channel, empty while disconnected, see:
     ///
     /// https://stackoverflow.com/questions/25070042/rabbitmq-consuming-and-publishing-on-same-channel
-    publish_channel: RwLock<Option<Channel>>,
+    publish_channels: RwLock<Vec<Channel>>,
+
+    /// counter used to pick the next publish channel
+    next_publish_channel: AtomicUsize,
 }
 
 /// Main abstraction for using RabbitMQ
 impl Rmq {
-    pub async fn new(amqp_uri: &str) -> Self {
-        if let Ok(c) = Self::connect(amqp_uri).await {
+    /// # PANICS
+    ///
+    /// panics if `publish_channel_count` is 0
+    pub async fn new(
+        amqp_uri: &str,
+        tracker_events_queue: QueueOptions,
+        publish_channel_count: usize,
+    ) -> Self {
+        assert!(
+            publish_channel_count > 0,
+            "at least one publish channel is required"
+        );
+
+        if let Ok(c) = Self::connect(amqp_uri, &tracker_events_queue, publish_channel_count).await {
             return Rmq {
                 connection: RwLock::new(Some(c.connection)),
                 amqp_uri: String::from(amqp_uri),
-                publish_channel: RwLock::new(Some(c.publish_channel)),
+                tracker_events_queue,
+                publish_channel_count,
+                publish_channels: RwLock::new(c.publish_channels),
+                next_publish_channel: AtomicUsize::new(0),
             };
         }
 
@@ -46,13 +136,19 @@ impl Rmq {
         Rmq {
             connection: RwLock::new(None),
             amqp_uri: String::from(amqp_uri),
-            publish_channel: RwLock::new(None),
+            tracker_events_queue,
+            publish_channel_count,
+            publish_channels: RwLock::new(Vec::new()),
+            next_publish_channel: AtomicUsize::new(0),
         }
     }
 
     /// Creates a new channel and starts a consumer
     /// passing messages to the `handler` arg.
     ///
+    /// `prefetch_count` limits the unacknowledged deliveries sent to the consumer
+    /// (`0` means unlimited), RabbitMQ ignores it for `no_ack` consumers
+    ///
     /// returns `Err` whenever failing to create the consumer channel,
     /// starting the consumer or the consumer ended due to a bad connection
     ///
@@ -61,6 +157,7 @@ impl Rmq {
         &self,
         queue: &str,
         consumer_tag: &str,
+        prefetch_count: u16,
         options: BasicConsumeOptions,
         args: FieldTable,
         handler: F,
@@ -80,6 +177,10 @@ impl Rmq {
             .create_channel()
             .await?;
 
+        consume_channel
+            .basic_qos(prefetch_count, BasicQosOptions::default())
+            .await?;
+
         let mut consumer = consume_channel
             .basic_consume(queue, consumer_tag, options, args)
             .await?;
@@ -99,6 +200,94 @@ impl Rmq {
         Ok(())
     }
 
+    /// Creates a new channel with a exclusive, server named queue binded to the tracker
+    /// events exchange with `routing_key` and starts a `no_ack` consumer on it.
+    ///
+    /// the queue holds at most `max_length` messages, dropping the oldest ones if the
+    /// consumer does not keep up, and is deleted by the broker once the returned channel
+    /// is closed, so callers must close it when done consuming
+    pub async fn subscribe_to_tracker_events(
+        &self,
+        routing_key: &str,
+        max_length: u32,
+    ) -> lapin::Result<(Channel, Consumer)> {
+        let channel = self
+            .connection
+            .read()
+            .await
+            .as_ref()
+            .ok_or(lapin::Error::InvalidChannelState(
+                lapin::ChannelState::Error,
+            ))?
+            .create_channel()
+            .await?;
+
+        match Self::consume_tracker_events(&channel, routing_key, max_length).await {
+            Ok(consumer) => Ok((channel, consumer)),
+            Err(err) => {
+                // the channel is not returned, so it would stay open on the connection
+                if let Err(close_err) = channel
+                    .close(200, "tracker events subscription failed")
+                    .await
+                {
+                    error!("[RMQ] failed to close tracker events channel: {close_err}");
+                }
+
+                Err(err)
+            }
+        }
+    }
+
+    /// declares the exclusive queue of a tracker events subscription on the channel,
+    /// binds it and starts consuming it, see `subscribe_to_tracker_events`
+    async fn consume_tracker_events(
+        channel: &Channel,
+        routing_key: &str,
+        max_length: u32,
+    ) -> lapin::Result<Consumer> {
+        let mut args = FieldTable::default();
+        args.insert("x-max-length".into(), AMQPValue::LongUInt(max_length));
+
+        let queue = channel
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    passive: false,
+                    durable: false,
+                    exclusive: true,
+                    auto_delete: true,
+                    nowait: false,
+                },
+                args,
+            )
+            .await?;
+
+        channel
+            .queue_bind(
+                queue.name().as_str(),
+                shared::constants::rabbitmq::TRACKER_EVENTS_EXCHANGE,
+                routing_key,
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await?;
+
+        let consumer = channel
+            .basic_consume(
+                queue.name().as_str(),
+                "",
+                BasicConsumeOptions {
+                    no_ack: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await?;
+
+        Ok(consumer)
+    }
+
+    /// Publishes a message on the next publish channel
     pub async fn publish(
         &self,
         exchange: &str,
@@ -107,17 +296,47 @@ impl Rmq {
         payload: &[u8],
         properties: BasicProperties,
     ) -> lapin::Result<PublisherConfirm> {
-        self.publish_channel
-            .read()
-            .await
-            .as_ref()
-            .ok_or(lapin::Error::InvalidChannelState(
+        let channels = self.publish_channels.read().await;
+
+        if channels.is_empty() {
+            return Err(lapin::Error::InvalidChannelState(
                 lapin::ChannelState::Closed,
-            ))?
+            ));
+        }
+
+        channels[self.next_publish_channel_index(channels.len())]
             .basic_publish(exchange, routing_key, options, payload, properties)
             .await
     }
 
+    /// index of the channel to publish the next message on, cycling through the pool
+    fn next_publish_channel_index(&self, channel_count: usize) -> usize {
+        self.next_publish_channel.fetch_add(1, Ordering::Relaxed) % channel_count
+    }
+
+    /// Publishes a message and waits for the broker to confirm it, messages
+    /// the broker nacks or returns (see `mandatory`) are `PublishError::Rejected`
+    pub async fn publish_confirmed(
+        &self,
+        exchange: &str,
+        routing_key: &str,
+        options: BasicPublishOptions,
+        payload: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), PublishError> {
+        let confirmation = self
+            .publish(exchange, routing_key, options, payload, properties)
+            .await
+            .map_err(PublishError::Unreachable)?
+            .await
+            .map_err(PublishError::Unreachable)?;
+
+        match confirmation {
+            Confirmation::Ack(None) | Confirmation::NotRequested => Ok(()),
+            Confirmation::Ack(Some(_)) | Confirmation::Nack(_) => Err(PublishError::Rejected),
+        }
+    }
+
     /// Creates a connection to RabbitMQ, creating the
     /// needed exchanges for the application to work
     ///
@@ -128,16 +347,34 @@ impl Rmq {
     /// configuration of existing exchanges/queues on the RabbitMQ
     /// instance and the config on the code, this kind of error wont
     /// work on retries unless this is panic 'worthy'
-    async fn connect(amqp_uri: &str) -> lapin::Result<ConnectionEntities> {
+    async fn connect(
+        amqp_uri: &str,
+        tracker_events_queue: &QueueOptions,
+        publish_channel_count: usize,
+    ) -> lapin::Result<ConnectionEntities> {
         let connecion_properties = ConnectionProperties::default()
             .with_executor(tokio_executor_trait::Tokio::current())
             .with_reactor(tokio_reactor_trait::Tokio);
 
         let connection = Connection::connect(amqp_uri, connecion_properties).await?;
-        println!("[RMQ] connected to RabbitMQ");
+        info!("[RMQ] connected to RabbitMQ");
+
+        let mut publish_channels = Vec::with_capacity(publish_channel_count);
+
+        for _ in 0..publish_channel_count {
+            let channel = connection.create_channel().await?;
+            channel
+                .confirm_select(ConfirmSelectOptions::default())
+                .await?;
+            publish_channels.push(channel);
+        }
+        info!(
+            "[RMQ] {} publish channels created with publisher confirms",
+            publish_channel_count
+        );
 
-        let publish_channel = connection.create_channel().await?;
-        println!("[RMQ] publish channel created");
+        // exchanges and queues only need to be declared once, on any channel
+        let publish_channel = &publish_channels[0];
 
         panic_on_err(
             publish_channel
@@ -155,24 +392,36 @@ impl Rmq {
                 )
                 .await,
         );
-        println!("[RMQ] tracker events exchange declared");
+        info!("[RMQ] tracker events exchange declared");
 
         panic_on_err(
             publish_channel
-                .queue_declare(
-                    shared::constants::rabbitmq::TRACKER_EVENTS_QUEUE,
-                    QueueDeclareOptions {
-                        passive: false,
-                        durable: false,
-                        exclusive: false,
-                        auto_delete: true,
+                .exchange_declare(
+                    shared::constants::rabbitmq::API_EVENTS_EXCHANGE,
+                    ExchangeKind::Topic,
+                    ExchangeDeclareOptions {
                         nowait: false,
+                        passive: false,
+                        durable: true,
+                        internal: false,
+                        auto_delete: false,
                     },
                     FieldTable::default(),
                 )
                 .await,
         );
-        println!("[RMQ] tracker events queue declared");
+        info!("[RMQ] api events exchange declared");
+
+        panic_on_err(
+            publish_channel
+                .queue_declare(
+                    shared::constants::rabbitmq::TRACKER_EVENTS_QUEUE,
+                    tracker_events_queue.declare_options(),
+                    tracker_events_queue.arguments(),
+                )
+                .await,
+        );
+        info!("[RMQ] tracker events queue declared");
 
         // bind the tracker events queue to the tracker events exchange and listen to all events (#)
         publish_channel
@@ -184,11 +433,11 @@ impl Rmq {
                 FieldTable::default(),
             )
             .await?;
-        println!("[RMQ] tracker events queue binded to tracker events exchange");
+        info!("[RMQ] tracker events queue binded to tracker events exchange");
 
         Ok(ConnectionEntities {
             connection,
-            publish_channel,
+            publish_channels,
         })
     }
 
@@ -209,12 +458,19 @@ impl Rmq {
             }
 
             *self.connection.write().await = None;
-            *self.publish_channel.write().await = None;
+            self.publish_channels.write().await.clear();
 
-            match Self::connect(&self.amqp_uri).await {
+            let connection_result = Self::connect(
+                &self.amqp_uri,
+                &self.tracker_events_queue,
+                self.publish_channel_count,
+            )
+            .await;
+
+            match connection_result {
                 Ok(c) => {
                     *self.connection.write().await = Some(c.connection);
-                    *self.publish_channel.write().await = Some(c.publish_channel);
+                    *self.publish_channels.write().await = c.publish_channels;
                 }
                 Err(err) => {
                     error!("[RMQ] reconnection failed: {:?}", err);
@@ -224,14 +480,14 @@ impl Rmq {
     }
 
     pub async fn shutdown(&self) {
-        println!("[RMQ] closing publish channel");
-        if let Some(chan) = self.publish_channel.read().await.as_ref() {
+        info!("[RMQ] closing publish channels");
+        for chan in self.publish_channels.read().await.iter() {
             if let Err(chan_close_err) = chan.close(200, "user shutdown").await {
                 error!("[RMQ] failed to close channel: {}", chan_close_err)
             }
         }
 
-        println!("[RMQ] closing connection");
+        info!("[RMQ] closing connection");
         if let Some(conn) = self.connection.read().await.as_ref() {
             if let Err(conn_close_err) = conn.close(200, "user shutdown").await {
                 error!("[RMQ] failed to close connection: {}", conn_close_err)
@@ -239,7 +495,7 @@ impl Rmq {
         }
 
         *self.connection.write().await = None;
-        *self.publish_channel.write().await = None;
+        self.publish_channels.write().await.clear();
     }
 }
 
@@ -248,3 +504,135 @@ fn panic_on_err<T>(err: Result<T, lapin::Error>) {
         panic!("[RMQ] critical error: {}", e);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a `Rmq` whose broker is unreachable, so it has no publish channels
+    async fn disconnected_rmq(publish_channel_count: usize) -> Rmq {
+        let queue = QueueOptions {
+            durable: false,
+            auto_delete: true,
+            message_ttl_ms: None,
+            dead_letter_exchange: None,
+        };
+
+        Rmq::new("amqp://127.0.0.1:1", queue, publish_channel_count).await
+    }
+
+    #[tokio::test]
+    async fn cycles_through_the_publish_channels() {
+        let rmq = disconnected_rmq(3).await;
+
+        let indexes: Vec<usize> = (0..7).map(|_| rmq.next_publish_channel_index(3)).collect();
+
+        assert_eq!(indexes, vec![0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[tokio::test]
+    async fn refuses_to_publish_while_disconnected() {
+        let rmq = disconnected_rmq(3).await;
+
+        let result = rmq
+            .publish(
+                "",
+                "queue",
+                BasicPublishOptions::default(),
+                b"payload",
+                BasicProperties::default(),
+            )
+            .await;
+
+        assert!(matches!(
+            result,
+            Err(lapin::Error::InvalidChannelState(
+                lapin::ChannelState::Closed
+            ))
+        ));
+    }
+
+    #[tokio::test]
+    #[should_panic(expected = "at least one publish channel is required")]
+    async fn requires_at_least_one_publish_channel() {
+        disconnected_rmq(0).await;
+    }
+
+    #[tokio::test]
+    #[ignore = "needs a RabbitMQ broker, set TEST_AMQP_URI and run with --ignored"]
+    async fn concurrent_publishes_get_their_own_confirms() {
+        let amqp_uri = std::env::var("TEST_AMQP_URI")
+            .expect("TEST_AMQP_URI must be set to run the RabbitMQ tests");
+
+        let queue = QueueOptions {
+            durable: false,
+            auto_delete: true,
+            message_ttl_ms: None,
+            dead_letter_exchange: None,
+        };
+
+        let rmq = std::sync::Arc::new(Rmq::new(&amqp_uri, queue, 4).await);
+
+        let confirms_queue = rmq
+            .connection
+            .read()
+            .await
+            .as_ref()
+            .expect("failed to connect to TEST_AMQP_URI")
+            .create_channel()
+            .await
+            .unwrap()
+            .queue_declare(
+                "",
+                QueueDeclareOptions {
+                    exclusive: true,
+                    auto_delete: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .unwrap();
+
+        let routable_key = confirms_queue.name().to_string();
+
+        let mut publishes = tokio::task::JoinSet::new();
+
+        // mandatory publishes without a queue to route them to are returned, so interleaving
+        // them with routable ones on every channel checks each publish gets its own confirm
+        for i in 0..200 {
+            let rmq = rmq.clone();
+            let routable = i % 2 == 0;
+
+            let routing_key = if routable {
+                routable_key.clone()
+            } else {
+                String::from("api_publish_confirms_test_unroutable")
+            };
+
+            publishes.spawn(async move {
+                let result = rmq
+                    .publish_confirmed(
+                        "",
+                        &routing_key,
+                        BasicPublishOptions {
+                            mandatory: true,
+                            ..Default::default()
+                        },
+                        format!("message {}", i).as_bytes(),
+                        BasicProperties::default(),
+                    )
+                    .await;
+
+                (routable, result)
+            });
+        }
+
+        while let Some(publish) = publishes.join_next().await {
+            match publish.unwrap() {
+                (true, result) => assert!(result.is_ok(), "routable publish failed: {result:?}"),
+                (false, result) => assert!(matches!(result, Err(PublishError::Rejected))),
+            }
+        }
+    }
+}
@@ -1,27 +1,37 @@
-use super::open_api;
+use super::{
+    open_api, payload_logging,
+    rate_limit::{self, RateLimiters},
+    request_id::{self, X_REQUEST_ID, X_TRACE_ID},
+};
 use crate::{
     config::app_config,
     modules::{
         access_level,
         auth::{self, service::AuthService},
-        organization, sim_card, tracker,
+        common::error_codes::ERROR_CODES,
+        mailer,
+        organization::{self, feature_flags::FeatureFlagCache},
+        sim_card, tracker,
         tracking::{self},
         user, vehicle,
     },
     rabbitmq::Rmq,
-    services::{mailer::service::MailerService, s3::S3},
+    services::{mailer::service::MailerService, s3::S3, ses::Ses},
     utils::string::StringExt,
 };
-use axum::{body::Body, routing::get, Router};
+use axum::{body::Body, routing::get, Json, Router};
 use axum_client_ip::SecureClientIpSource;
 use http::{header, HeaderValue, Method, Request, StatusCode};
 use rand_chacha::ChaCha8Rng;
 use rand_core::{OsRng, RngCore, SeedableRng};
 use sea_orm::DatabaseConnection;
+use std::collections::BTreeMap;
 use std::sync::Arc;
+use tokio::sync::Semaphore;
 use tower::ServiceBuilder;
 use tower_http::{
     cors::CorsLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, SetRequestIdLayer},
     trace::{DefaultOnResponse, TraceLayer},
 };
 use tracing::{info, Level, Span};
@@ -32,22 +42,39 @@ use tracing::{info, Level, Span};
 #[derive(Clone)]
 pub struct AppState {
     pub s3: S3,
+    pub ses: Ses,
     pub db: DatabaseConnection,
     pub auth_service: AuthService,
     pub mailer_service: MailerService,
+    pub feature_flags: FeatureFlagCache,
+    pub rmq: Arc<Rmq>,
+    pub rate_limiters: RateLimiters,
+    /// permits for the open tracker live feeds, see `tracker::routes::stream_tracker_live_feed`
+    pub live_feed_permits: Arc<Semaphore>,
 }
 
 /// Creates the main axum router/controller to be served over https
-pub fn new(db: DatabaseConnection, s3: S3, rmq: Arc<Rmq>) -> Router {
+pub fn new(
+    db: DatabaseConnection,
+    s3: S3,
+    ses: Ses,
+    rmq: Arc<Rmq>,
+    rate_limiters: RateLimiters,
+) -> Router {
     let rng = ChaCha8Rng::seed_from_u64(OsRng.next_u64());
 
     let positions_consumer_rmq = rmq.clone();
 
     let state = AppState {
         s3,
+        ses,
         db: db.clone(),
         auth_service: AuthService::new(db.clone(), rng),
-        mailer_service: MailerService::new(rmq),
+        mailer_service: MailerService::new(rmq.clone()),
+        feature_flags: FeatureFlagCache::new(db.clone()),
+        rmq,
+        rate_limiters,
+        live_feed_permits: Arc::new(Semaphore::new(tracker::routes::MAX_LIVE_FEEDS)),
     };
 
     let (socket_io_layer, socket_io) = socketioxide::SocketIo::builder()
@@ -66,6 +93,7 @@ pub fn new(db: DatabaseConnection, s3: S3, rmq: Arc<Rmq>) -> Router {
     let cors = CorsLayer::new()
         .allow_methods([
             Method::GET,
+            Method::HEAD,
             Method::PUT,
             Method::POST,
             Method::PATCH,
@@ -78,7 +106,20 @@ pub fn new(db: DatabaseConnection, s3: S3, rmq: Arc<Rmq>) -> Router {
                 .expect("failed to parse CORS allowed origins"),
         )
         .allow_credentials(true)
-        .allow_headers([header::ACCEPT, header::AUTHORIZATION, header::CONTENT_TYPE]);
+        .allow_headers([
+            header::ACCEPT,
+            header::AUTHORIZATION,
+            header::CONTENT_TYPE,
+            header::IF_NONE_MATCH,
+            header::IF_MODIFIED_SINCE,
+            X_REQUEST_ID.clone(),
+        ])
+        .expose_headers([
+            X_REQUEST_ID.clone(),
+            X_TRACE_ID.clone(),
+            header::ETAG,
+            header::LAST_MODIFIED,
+        ]);
 
     // extracts the client IP from the request, this is harder than it sounds and should be
     // done by a lib to deal with edge cases such as extracting the original IP from a header
@@ -86,20 +127,30 @@ pub fn new(db: DatabaseConnection, s3: S3, rmq: Arc<Rmq>) -> Router {
     let ip_extractor_layer = SecureClientIpSource::ConnectInfo.into_extension();
 
     let tracing_layer = TraceLayer::new_for_http()
+        .make_span_with(request_id::make_request_span)
         .on_request(|request: &Request<Body>, _span: &Span| {
             info!("{} {}", request.method(), request.uri().path())
         })
         .on_response(DefaultOnResponse::new().level(Level::INFO));
 
+    // the request id layers must be the outermost ones, so the id is
+    // available for all the other layers and set on every response
     let global_middlewares = ServiceBuilder::new()
+        .layer(axum::middleware::from_fn(
+            request_id::discard_invalid_request_id,
+        ))
+        .layer(SetRequestIdLayer::new(
+            X_REQUEST_ID.clone(),
+            MakeRequestUuid,
+        ))
+        .layer(PropagateRequestIdLayer::new(X_REQUEST_ID.clone()))
         .layer(ip_extractor_layer)
         .layer(tracing_layer)
+        .layer(axum::middleware::from_fn(request_id::set_trace_id_header))
         .layer(cors)
         .layer(socket_io_layer);
 
-    Router::new()
-        .merge(open_api::create_openapi_router())
-        .route("/healthcheck", get(healthcheck))
+    let api_router = Router::new()
         .nest("/auth", auth::routes::create_router(state.clone()))
         .nest("/user", user::routes::create_router(state.clone()))
         .nest("/vehicle", vehicle::routes::create_router(state.clone()))
@@ -114,8 +165,24 @@ pub fn new(db: DatabaseConnection, s3: S3, rmq: Arc<Rmq>) -> Router {
             "/organization",
             organization::routes::create_router(state.clone()),
         )
-        .layer(global_middlewares)
-        .with_state(state)
+        .nest("/mailer", mailer::routes::create_router(state.clone()))
+        .layer(axum::middleware::from_fn_with_state(
+            state.rate_limiters.api.clone(),
+            rate_limit::limit_by_ip,
+        ));
+
+    let mut router = Router::new()
+        .merge(open_api::create_openapi_router())
+        .route("/healthcheck", get(healthcheck))
+        .route("/error-codes", get(list_error_codes))
+        .merge(api_router);
+
+    // inside the global middlewares so the payloads are logged on the request span
+    if app_config().log_payloads() {
+        router = router.layer(axum::middleware::from_fn(payload_logging::log_payloads));
+    }
+
+    router.layer(global_middlewares).with_state(state)
 }
 
 #[utoipa::path(
@@ -127,3 +194,30 @@ pub fn new(db: DatabaseConnection, s3: S3, rmq: Arc<Rmq>) -> Router {
 pub async fn healthcheck() -> StatusCode {
     StatusCode::OK
 }
+
+/// Lists the error codes of the API
+///
+/// every error code that can be sent on error responses with its description, so clients
+/// can keep their error handling in sync with the API
+#[utoipa::path(
+    get,
+    tag = "meta",
+    path = "/error-codes",
+    responses(
+        (
+            status = OK,
+            description = "error code to its description",
+            content_type = "application/json",
+            body = HashMap<String, String>,
+            example = json!({ "EMAIL_IN_USE": "a entity could not be created or updated with a given email because its already in use by another entity" }),
+        ),
+    ),
+)]
+pub async fn list_error_codes() -> Json<BTreeMap<&'static str, String>> {
+    let error_codes = ERROR_CODES
+        .iter()
+        .map(|error_code| (error_code.code, error_code.description()))
+        .collect();
+
+    Json(error_codes)
+}
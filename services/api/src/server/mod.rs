@@ -1,2 +1,5 @@
 pub mod controller;
-mod open_api;
+pub mod open_api;
+pub mod payload_logging;
+pub mod rate_limit;
+pub mod request_id;
@@ -1,4 +1,4 @@
-use crate::modules::{auth, common, user, organization, vehicle, tracker, sim_card, access_level, tracking};
+use crate::modules::{auth, common, user, organization, vehicle, tracker, sim_card, access_level, tracking, mailer};
 use crate::server::controller;
 use utoipa::openapi::security::{ApiKey, ApiKeyValue, SecurityScheme};
 use utoipa::openapi::{ContactBuilder, InfoBuilder};
@@ -12,24 +12,45 @@ use shared::entity;
 #[openapi(
     components(schemas(
         shared::constants::TrackerModel,
+        shared::constants::FeatureFlag,
 
         entity::vehicle::Model,
         entity::sim_card::Model,
+        entity::sim_card_data_usage::Model,
+        entity::impersonation_log::Model,
+        entity::login_history::Model,
         entity::vehicle_tracker::Model,
         
         common::dto::PaginatedUser,
         common::dto::PaginatedSimCard,
         common::dto::PaginatedVehicle,
+        common::dto::PaginatedAccessLevel,
+        common::dto::PaginatedAccessLevelUsage,
         common::dto::PaginatedVehicleTracker,
+        common::dto::PaginatedOrganizationSummary,
+        common::dto::PaginatedImpersonationLog,
+        common::dto::PaginatedLoginHistory,
+        common::dto::PaginatedOrgSession,
+        common::dto::PaginatedOrganizationActivity,
+        common::dto::PaginatedStaleTracker,
+        common::dto::PaginatedVehicleWithPosition,
+        common::dto::PaginatedUnknownImei,
 
         common::dto::Token,
         common::dto::EmailAddress,
+        common::dto::BulkDeleteDto,
+        common::dto::BulkDeleteResultDto,
         common::dto::SingleImageDto,
         common::dto::AscOrDescOrder,
+        common::dto::CountDto,
         
         common::responses::SimpleError,
+        common::responses::FieldError,
+        common::responses::ValidationErrorResponse,
         
         user::dto::SimpleUserDto,
+        user::dto::CreateUserDto,
+        user::dto::InviteUserDto,
         user::dto::UpdateUserDto,
         user::dto::ChangePasswordDto,
         user::dto::ChangeUserAccessLevelDto,
@@ -37,99 +58,230 @@ use shared::entity;
         auth::dto::SignIn,
         auth::dto::UserDto,
         auth::dto::SessionDto,
+        auth::dto::OrgSessionDto,
+        auth::dto::ClearedSessionsDto,
         auth::dto::ResetPassword,
         auth::dto::SignInResponse,
         auth::dto::OrganizationDto,
         auth::dto::RegisterOrganization,
+        auth::dto::ValidateToken,
+        auth::dto::UserTokenKind,
+        auth::dto::TokenValidationDto,
+        auth::dto::InvalidTokenReason,
 
         vehicle::dto::CreateVehicleDto,
+        vehicle::dto::ImportVehiclesDto,
+        vehicle::dto::ImportedVehicleDto,
+        vehicle::dto::FailedVehicleImportRowDto,
+        vehicle::dto::VehicleImportResultDto,
         vehicle::dto::UpdateVehicleDto,
+        vehicle::dto::VehicleOdometerDto,
+        vehicle::dto::DeletedVehicleDto,
+        vehicle::dto::ListVehiclesWithPositionsDto,
+        vehicle::dto::VehicleWithPositionDto,
+        vehicle::dto::GetVehicleLocationHistoryDto,
+        vehicle::dto::InitiatePhotoUploadDto,
+        vehicle::dto::UploadedPartDto,
+        vehicle::dto::PhotoUploadDto,
         
-        tracker::dto::Point,
         tracker::dto::UpdateTrackerDto,
         tracker::dto::CreateTrackerDto,
-        tracker::dto::TrackerLocationDto,
         tracker::dto::SetTrackerVehicleDto,
         tracker::dto::GetTrackerPositionsDto,
+        tracker::dto::TrackerPositionsPageDto,
+        tracker::dto::BulkDeleteTrackersDto,
+        tracker::dto::TrackerDetailsDto,
+        tracker::dto::TrackerLiveFeedEventDto,
+        tracker::dto::StaleTrackerDto,
+        tracker::dto::MergeTrackerHistoryDto,
+        tracker::dto::MergeTrackerHistoryResultDto,
+        tracker::dto::CheckImeisDto,
+        tracker::dto::ImeiAvailabilityDto,
+        tracker::dto::LoanTrackerDto,
 
         tracking::dto::PositionDto,
+        tracking::dto::Point,
         tracking::dto::GetTrackersLastPositionsDto,
+        tracking::dto::GetClusteredLastPositionsDto,
+        tracking::dto::PositionClusterDto,
+        tracking::dto::GetPositionsHeatmapDto,
+        tracking::dto::HeatmapCellDto,
+        tracking::dto::ConnectionTicketDto,
+        tracking::dto::UnknownImeiDto,
+        tracking::dto::UnknownImeiLocationDto,
         
         sim_card::dto::CreateSimCardDto,
         sim_card::dto::UpdateSimCardDto,
         sim_card::dto::SetSimCardTrackerDto,
+        sim_card::dto::SimCardAssignmentDto,
+        sim_card::dto::BulkAssignSimCardsDto,
+        sim_card::dto::BulkUpdateApnDto,
+        sim_card::dto::BulkUpdateApnResultDto,
+        sim_card::dto::NormalizePhoneNumbersResultDto,
+        sim_card::dto::SimCardAssignmentResultDto,
+        sim_card::dto::RecordSimCardDataUsageDto,
 
         access_level::dto::AccessLevelDto,
+        access_level::dto::AccessLevelTemplateDto,
+        access_level::dto::AccessLevelUsageDto,
         access_level::dto::UpdateAccessLevelDto,
         access_level::dto::CreateAccessLevelDto,
 
         organization::dto::UpdateOrganizationDto,
+        organization::dto::OrganizationSummaryDto,
+        organization::dto::SetFeatureFlagDto,
+        organization::dto::SetOrganizationLimitsDto,
+        organization::dto::SetOrganizationBlockedDto,
+        organization::dto::BillingStatusDto,
+        organization::dto::EmailSenderStatus,
+        organization::dto::EmailSenderStatusDto,
+        organization::dto::CreateEmailIdentityDto,
+        organization::dto::EmailIdentityDto,
+        organization::dto::SetIpAllowlistDto,
+        organization::dto::TransferOwnershipDto,
+        organization::dto::FeatureFlagDto,
+        organization::dto::ActivityDto,
+        organization::activity::ActivityType,
+        organization::activity::ActivityEvent,
+        organization::activity::ImpersonationActivity,
+        organization::activity::OwnershipTransferActivity,
+        mailer::dto::PreviewEmailTemplateDto,
     )),
     paths(
         controller::healthcheck,
+        controller::list_error_codes,
         
         user::routes::me,
         user::routes::update_me,
         user::routes::list_users,
+        user::routes::get_user,
         user::routes::put_password,
         user::routes::create_user,
+        user::routes::invite_user,
         user::routes::get_user_sessions,
         user::routes::delete_user,
         user::routes::get_short_lived_token,
         user::routes::put_profile_picture,
         user::routes::get_user_access_level,
         user::routes::delete_profile_picture,
+        user::routes::get_profile_picture,
+        user::routes::get_user_profile_picture,
         user::routes::change_user_access_level,
         user::routes::get_request_user_sessions,
+        user::routes::list_request_user_login_history,
         user::routes::request_user_email_address_confirmation,
+        user::routes::request_email_confirmation_for_user,
         
         auth::routes::sign_up,
         auth::routes::sign_in,
         auth::routes::sign_out,
         auth::routes::delete_session,
+        auth::routes::get_session,
+        auth::routes::get_request_user_permissions,
         auth::routes::sign_out_session_by_id,
+        auth::routes::impersonate_user,
+        auth::routes::stop_impersonation,
+        auth::routes::list_impersonation_log,
+        auth::routes::clear_expired_sessions,
+        auth::routes::list_org_sessions,
         auth::routes::request_recover_password_email,
         auth::routes::change_password_by_recovery_token,
         auth::routes::confirm_user_email_address_by_token,
+        auth::routes::validate_token,
+        auth::routes::request_magic_link,
+        auth::routes::sign_in_by_magic_link,
         
         vehicle::routes::list_vehicles,
+        vehicle::routes::count_vehicles,
+        vehicle::routes::list_vehicles_with_positions,
         vehicle::routes::vehicle_by_id,
         vehicle::routes::create_vehicle,
+        vehicle::routes::import_vehicles,
         vehicle::routes::update_vehicle,
         vehicle::routes::delete_vehicle,
         vehicle::routes::get_vehicle_tracker,
+        vehicle::routes::get_vehicle_odometer,
+        vehicle::routes::get_vehicle_location_history,
         vehicle::routes::update_vehicle_photo,
         vehicle::routes::delete_vehicle_photo,
+        vehicle::routes::get_vehicle_photo,
+        vehicle::routes::initiate_vehicle_photo_upload,
+        vehicle::routes::get_vehicle_photo_upload,
+        vehicle::routes::upload_vehicle_photo_part,
+        vehicle::routes::complete_vehicle_photo_upload,
+        vehicle::routes::abort_vehicle_photo_upload,
         
         sim_card::routes::get_sim_card,
         sim_card::routes::list_sim_cards,
+        sim_card::routes::count_sim_cards,
         sim_card::routes::delete_sim_card,
+        sim_card::routes::bulk_delete_sim_cards,
+        sim_card::routes::record_sim_card_data_usage,
+        sim_card::routes::list_sim_card_data_usage,
         sim_card::routes::create_sim_card,
         sim_card::routes::update_sim_card,
         sim_card::routes::set_sim_card_tracker,
+        sim_card::routes::bulk_assign_sim_cards,
+        sim_card::routes::bulk_update_sim_card_apn,
+        sim_card::routes::normalize_sim_card_phone_numbers,
         
         tracker::routes::get_tracker,
+        tracker::routes::get_tracker_by_imei,
         tracker::routes::list_trackers,
+        tracker::routes::count_trackers,
         tracker::routes::create_tracker,
         tracker::routes::delete_tracker,
+        tracker::routes::bulk_delete_trackers,
         tracker::routes::update_tracker,
         tracker::routes::set_tracker_vehicle,
         tracker::routes::get_tracker_location,
         tracker::routes::list_tracker_sim_cards,
+        tracker::routes::get_tracker_details,
+        tracker::routes::stream_tracker_live_feed,
+        tracker::routes::list_stale_trackers,
+        tracker::routes::merge_tracker_history,
+        tracker::routes::check_imeis,
         tracker::routes::get_location_list,
+        tracker::routes::get_location_page,
+        tracker::routes::loan_tracker,
+        tracker::routes::end_tracker_loan,
+        tracker::routes::list_borrowed_trackers,
 
 
         tracking::routes::get_trackers_last_positions,
+        tracking::routes::get_clustered_last_positions,
+        tracking::routes::get_positions_heatmap,
+        tracking::routes::create_connection_ticket,
+        tracking::routes::list_unknown_imeis,
+        tracking::routes::list_unknown_imei_locations,
 
         access_level::routes::list_access_level,
         access_level::routes::access_level_by_id,
         access_level::routes::create_access_level,
+        access_level::routes::list_access_level_usage,
+        access_level::routes::export_access_level,
+        access_level::routes::import_access_level,
         access_level::routes::update_access_level,
         access_level::routes::delete_access_level,
+        access_level::routes::sync_fixed_access_level_permissions,
         
+        organization::routes::list_organizations,
         organization::routes::update_org,
+        organization::routes::create_email_identity,
+        organization::routes::get_email_sender_status,
         organization::routes::confirm_email_address_by_token,
         organization::routes::request_email_address_confirmation,
+        organization::routes::get_org_feature_flags,
+        organization::routes::get_org_feature_flags_by_org_id,
+        organization::routes::set_org_feature_flag,
+        organization::routes::set_org_limits,
+        organization::routes::set_org_blocked,
+        organization::routes::get_billing_status,
+        organization::routes::set_ip_allowlist,
+        organization::routes::transfer_ownership,
+        organization::routes::list_activity,
+
+        mailer::routes::preview_email_template,
     ),
     modifiers(&SessionIdCookieSecurityScheme),
 )]
@@ -157,7 +309,8 @@ impl Modify for SessionIdCookieSecurityScheme {
     }
 }
 
-pub fn create_openapi_router() -> Router<controller::AppState> {
+/// Builds the complete openapi documentation of the API
+pub fn create_openapi_doc() -> utoipa::openapi::OpenApi {
     let builder: OpenApiBuilder = ApiDoc::openapi().into();
 
     let info = InfoBuilder::new()
@@ -173,9 +326,97 @@ pub fn create_openapi_router() -> Router<controller::AppState> {
         ))
         .build();
 
-    let api_doc = builder.info(info).build();
+    builder.info(info).build()
+}
+
+/// Writes the openapi documentation as pretty printed JSON to `path`, this does not
+/// require a database or any other service so it can be used to generate API clients
+pub fn write_openapi_doc(path: &str) -> Result<(), String> {
+    let json = create_openapi_doc()
+        .to_pretty_json()
+        .map_err(|e| format!("failed to serialize openapi doc: {}", e))?;
+
+    std::fs::write(path, json).map_err(|e| format!("failed to write {}: {}", path, e))
+}
+
+pub fn create_openapi_router() -> Router<controller::AppState> {
+    let api_doc = create_openapi_doc();
 
     Router::new()
         .merge(SwaggerUi::new("/swagger").url("/docs/openapi.json", api_doc))
         .merge(RapiDoc::new("/docs/openapi.json").path("/rapidoc"))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+    use std::{collections::HashSet, fs, path::Path};
+
+    /// the names of the handlers of every `.route(...)` call of a source file
+    fn routed_handlers(source: &str) -> Vec<String> {
+        let method_router = Regex::new(r"\b(?:get|post|put|patch|delete)\((\w+)\)").unwrap();
+        let mut handlers = Vec::new();
+
+        for (start, _) in source.match_indices(".route(") {
+            let mut depth = 0;
+
+            let end = source[start..]
+                .char_indices()
+                .find(|(_, c)| {
+                    match c {
+                        '(' => depth += 1,
+                        ')' => depth -= 1,
+                        _ => {}
+                    };
+                    *c == ')' && depth == 0
+                })
+                .map(|(i, _)| start + i)
+                .unwrap();
+
+            handlers.extend(
+                method_router
+                    .captures_iter(&source[start..end])
+                    .map(|captures| captures[1].to_string()),
+            );
+        }
+
+        handlers
+    }
+
+    fn source_files(dir: &Path, out: &mut Vec<String>) {
+        for entry in fs::read_dir(dir).unwrap() {
+            let path = entry.unwrap().path();
+
+            if path.is_dir() {
+                source_files(&path, out);
+            } else if path.extension().is_some_and(|ext| ext == "rs") {
+                out.push(fs::read_to_string(path).unwrap());
+            }
+        }
+    }
+
+    #[test]
+    fn every_routed_handler_is_documented() {
+        let documented: HashSet<String> = create_openapi_doc()
+            .paths
+            .paths
+            .values()
+            .flat_map(|item| item.operations.values())
+            .filter_map(|operation| operation.operation_id.clone())
+            .collect();
+
+        let mut sources = Vec::new();
+        source_files(
+            Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src")),
+            &mut sources,
+        );
+
+        for handler in sources.iter().flat_map(|source| routed_handlers(source)) {
+            assert!(
+                documented.contains(&handler),
+                "handler {handler} is routed but not registered on ApiDoc"
+            );
+        }
+    }
+}
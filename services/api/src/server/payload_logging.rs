@@ -0,0 +1,170 @@
+//! Logging of request and response bodies, for debugging on development.
+//!
+//! only JSON bodies are logged, and only after redacting their sensitive fields, bodies
+//! that are not valid JSON are never logged as their sensitive fields cannot be found.
+
+use axum::{
+    body::{to_bytes, Body, HttpBody},
+    extract::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use http::{header, HeaderMap, StatusCode};
+use serde_json::Value;
+use tracing::debug;
+
+/// bodies larger than this are not logged, so uploads and exports are not buffered
+const MAX_LOGGED_BODY_BYTES: usize = 64 * 1024;
+
+/// value logged in place of the redacted fields
+const REDACTED: &str = "[REDACTED]";
+
+/// fields redacted if their (lowercase, without `_` and `-`) name contains any of these
+const REDACTED_FIELD_PARTS: [&str; 8] = [
+    "password",
+    "token",
+    "ticket",
+    "secret",
+    "session",
+    "cookie",
+    "authorization",
+    "apikey",
+];
+
+/// fields redacted if their (lowercase, without `_` and `-`) name is any of these,
+/// the sim card pins and puks, too short to be matched as part of other names
+const REDACTED_FIELDS: [&str; 4] = ["pin", "pin2", "puk", "puk2"];
+
+fn is_redacted_field(name: &str) -> bool {
+    let name = name.to_lowercase().replace(['_', '-'], "");
+
+    REDACTED_FIELDS.contains(&name.as_str())
+        || REDACTED_FIELD_PARTS.iter().any(|part| name.contains(part))
+}
+
+/// replaces the values of the sensitive fields of every object within the value
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (name, field) in fields.iter_mut() {
+                if is_redacted_field(name) {
+                    *field = Value::String(String::from(REDACTED));
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// the body as redacted JSON, or a placeholder if it is not valid JSON
+fn redacted_body(bytes: &[u8]) -> String {
+    match serde_json::from_slice::<Value>(bytes) {
+        Ok(mut value) => {
+            redact(&mut value);
+            value.to_string()
+        }
+        Err(_) => format!("<{} bytes of invalid json>", bytes.len()),
+    }
+}
+
+fn is_json(headers: &HeaderMap) -> bool {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("application/json"))
+}
+
+fn content_length(headers: &HeaderMap) -> Option<usize> {
+    headers
+        .get(header::CONTENT_LENGTH)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse().ok())
+}
+
+/// the length of the body if it is known without reading it, from the content length
+/// header or the body itself, since the responses built in memory have no such header
+fn known_body_len(headers: &HeaderMap, body: &Body) -> Option<usize> {
+    content_length(headers).or_else(|| {
+        body.size_hint()
+            .exact()
+            .and_then(|len| usize::try_from(len).ok())
+    })
+}
+
+/// Middleware logging the JSON request and response bodies at debug level, with
+/// their sensitive fields redacted, must only be used on development
+pub async fn log_payloads(request: Request, next: Next) -> Response {
+    let request_is_logged = is_json(request.headers())
+        && content_length(request.headers()).is_some_and(|len| len <= MAX_LOGGED_BODY_BYTES);
+
+    let request = if request_is_logged {
+        let (parts, body) = request.into_parts();
+
+        let Ok(bytes) = to_bytes(body, MAX_LOGGED_BODY_BYTES).await else {
+            return (StatusCode::BAD_REQUEST, "failed to read request body").into_response();
+        };
+
+        debug!(body = %redacted_body(&bytes), "request payload");
+
+        Request::from_parts(parts, Body::from(bytes))
+    } else {
+        request
+    };
+
+    let response = next.run(request).await;
+
+    let response_is_logged = is_json(response.headers())
+        && known_body_len(response.headers(), response.body())
+            .is_some_and(|len| len <= MAX_LOGGED_BODY_BYTES);
+
+    if !response_is_logged {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+
+    let Ok(bytes) = to_bytes(body, MAX_LOGGED_BODY_BYTES).await else {
+        return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+    };
+
+    debug!(status = %parts.status, body = %redacted_body(&bytes), "response payload");
+
+    Response::from_parts(parts, Body::from(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn redacts_the_sensitive_fields_of_nested_objects() {
+        let mut value = serde_json::json!({
+            "username": "raster",
+            "newPassword": "hunter22",
+            "simCards": [{ "phoneNumber": "+5511999999999", "puk2": "12345678" }],
+            "socketTicket": { "ticket": "abc", "expiresAt": "2024-01-01T00:00:00Z" },
+        });
+
+        redact(&mut value);
+
+        assert_eq!(
+            value,
+            serde_json::json!({
+                "username": "raster",
+                "newPassword": REDACTED,
+                "simCards": [{ "phoneNumber": "+5511999999999", "puk2": REDACTED }],
+                "socketTicket": REDACTED,
+            })
+        );
+    }
+
+    #[test]
+    fn in_memory_bodies_have_a_known_length() {
+        let body = Body::from("{}");
+
+        assert_eq!(known_body_len(&HeaderMap::new(), &body), Some(2));
+    }
+}
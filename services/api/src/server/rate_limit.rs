@@ -0,0 +1,224 @@
+//! Rate limiting of HTTP requests.
+//!
+//! requests are counted on fixed windows per key (eg: the client ip), every response
+//! of a governed route reports the client budget with the `x-ratelimit-limit`,
+//! `x-ratelimit-remaining` and `x-ratelimit-reset` headers, requests over the limit
+//! are refused with `429 Too Many Requests` and a `retry-after` header.
+//!
+//! routes can be governed by more than one limiter (eg: the auth routes are governed by the
+//! auth limiter and the API wide one), in this case the headers report the budget of the
+//! limiter closest to being exhausted.
+
+use crate::{config::app_config, modules::common::responses::SimpleError};
+use axum::{
+    extract::{Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use axum_client_ip::SecureClientIp;
+use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+pub static X_RATELIMIT_LIMIT: HeaderName = HeaderName::from_static("x-ratelimit-limit");
+
+pub static X_RATELIMIT_REMAINING: HeaderName = HeaderName::from_static("x-ratelimit-remaining");
+
+pub static X_RATELIMIT_RESET: HeaderName = HeaderName::from_static("x-ratelimit-reset");
+
+struct Window {
+    started_at: Instant,
+    count: u32,
+}
+
+/// Budget of a key on its current window
+pub struct RateLimitStatus {
+    pub limit: u32,
+    pub remaining: u32,
+    /// time until the current window ends and the budget is restored
+    pub reset_after: Duration,
+    /// if the request that consumed the budget is over the limit
+    pub exceeded: bool,
+}
+
+impl RateLimitStatus {
+    /// sets the rate limit headers, including `retry-after` if the limit was exceeded
+    pub fn write_headers(&self, headers: &mut HeaderMap) {
+        // round up so clients never retry before the window ends
+        let reset_seconds =
+            self.reset_after.as_secs() + u64::from(self.reset_after.subsec_nanos() > 0);
+
+        headers.insert(X_RATELIMIT_LIMIT.clone(), HeaderValue::from(self.limit));
+        headers.insert(
+            X_RATELIMIT_REMAINING.clone(),
+            HeaderValue::from(self.remaining),
+        );
+        headers.insert(X_RATELIMIT_RESET.clone(), HeaderValue::from(reset_seconds));
+
+        if self.exceeded {
+            headers.insert(http::header::RETRY_AFTER, HeaderValue::from(reset_seconds));
+        }
+    }
+}
+
+/// Fixed window rate limiter, allows `limit` requests per key on every `window`
+pub struct RateLimiter {
+    limit: u32,
+    window: Duration,
+    windows: Mutex<HashMap<String, Window>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// consumes one request of the key budget, returning the budget left
+    pub fn check(&self, key: &str) -> RateLimitStatus {
+        let now = Instant::now();
+        let mut windows = self.windows.lock().unwrap();
+
+        let window = windows.entry(key.to_string()).or_insert(Window {
+            started_at: now,
+            count: 0,
+        });
+
+        if now.duration_since(window.started_at) >= self.window {
+            window.started_at = now;
+            window.count = 0;
+        }
+
+        window.count = window.count.saturating_add(1);
+
+        RateLimitStatus {
+            limit: self.limit,
+            remaining: self.limit.saturating_sub(window.count),
+            reset_after: self
+                .window
+                .saturating_sub(now.duration_since(window.started_at)),
+            exceeded: window.count > self.limit,
+        }
+    }
+
+    /// removes the keys whose window ended, as they would be reset on their next request
+    /// anyway, this should be called periodically so the keys of clients that stopped
+    /// making requests do not accumulate, see `start_prune_rate_limiters_cronjob`
+    pub fn prune(&self) {
+        let now = Instant::now();
+
+        self.windows
+            .lock()
+            .unwrap()
+            .retain(|_, w| now.duration_since(w.started_at) < self.window);
+    }
+}
+
+/// The rate limiters of the API
+#[derive(Clone)]
+pub struct RateLimiters {
+    /// limiter of the routes that do not require a session (eg: sign in), keyed by client ip
+    pub auth: Arc<RateLimiter>,
+
+    /// limiter of every API route, keyed by client ip
+    pub api: Arc<RateLimiter>,
+}
+
+impl RateLimiters {
+    pub fn from_config() -> Self {
+        let cfg = app_config();
+
+        Self {
+            auth: Arc::new(RateLimiter::new(
+                cfg.auth_rate_limit,
+                Duration::from_secs(cfg.auth_rate_limit_window_seconds),
+            )),
+            api: Arc::new(RateLimiter::new(
+                cfg.api_rate_limit,
+                Duration::from_secs(cfg.api_rate_limit_window_seconds),
+            )),
+        }
+    }
+
+    pub fn prune(&self) {
+        self.auth.prune();
+        self.api.prune();
+    }
+}
+
+/// if the budget of `status` should be reported instead of the one already on the
+/// response headers, set by a inner limiter, so the lowest remaining budget is reported
+fn should_write_headers(status: &RateLimitStatus, headers: &HeaderMap) -> bool {
+    let inner_remaining = headers
+        .get(&X_RATELIMIT_REMAINING)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<u32>().ok());
+
+    inner_remaining.is_none_or(|remaining| status.remaining < remaining)
+}
+
+/// Middleware limiting the requests per client ip with the limiter on its state
+pub async fn limit_by_ip(
+    State(limiter): State<Arc<RateLimiter>>,
+    SecureClientIp(ip): SecureClientIp,
+    request: Request,
+    next: Next,
+) -> Response {
+    let status = limiter.check(&ip.to_string());
+
+    let mut response = if status.exceeded {
+        (
+            StatusCode::TOO_MANY_REQUESTS,
+            SimpleError::from("too many requests"),
+        )
+            .into_response()
+    } else {
+        next.run(request).await
+    };
+
+    if should_write_headers(&status, response.headers()) {
+        status.write_headers(response.headers_mut());
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prune_removes_only_the_ended_windows() {
+        let limiter = RateLimiter::new(10, Duration::from_millis(50));
+
+        limiter.check("a");
+        std::thread::sleep(Duration::from_millis(60));
+        limiter.check("b");
+
+        limiter.prune();
+
+        assert_eq!(limiter.windows.lock().unwrap().len(), 1);
+        assert_eq!(limiter.check("b").remaining, 8);
+    }
+
+    #[test]
+    fn reports_the_budget_closest_to_being_exhausted() {
+        let mut headers = HeaderMap::new();
+
+        let inner = RateLimiter::new(5, Duration::from_secs(60)).check("ip");
+        assert!(should_write_headers(&inner, &headers));
+        inner.write_headers(&mut headers);
+
+        let outer = RateLimiter::new(100, Duration::from_secs(60)).check("ip");
+        assert!(!should_write_headers(&outer, &headers));
+
+        let exhausted_outer = RateLimiter::new(1, Duration::from_secs(60)).check("ip");
+        assert!(should_write_headers(&exhausted_outer, &headers));
+    }
+}
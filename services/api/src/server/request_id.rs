@@ -0,0 +1,128 @@
+//! Correlation of HTTP requests with their traces.
+//!
+//! every request has a id, read from the `x-request-id` header or generated if missing or
+//! invalid (see `is_valid_request_id`), the id is echoed on the response headers (even for
+//! error responses) and recorded on the request span, together with the `x-trace-id` response
+//! header this allows finding all the spans of a request (including the ones of other services)
+//! with the id a user reports.
+
+use axum::{body::Body, extract::Request, middleware::Next, response::Response};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use opentelemetry::{propagation::Extractor, trace::TraceContextExt};
+use tracing::{info_span, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+pub static X_REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+pub static X_TRACE_ID: HeaderName = HeaderName::from_static("x-trace-id");
+
+/// Maximum length of a client provided request id
+const MAX_REQUEST_ID_LEN: usize = 64;
+
+/// if a client provided request id can be used, ids are echoed on the response headers
+/// and written to logs and spans, so only short alphanumeric ids (and dashes) are accepted
+fn is_valid_request_id(id: &[u8]) -> bool {
+    (1..=MAX_REQUEST_ID_LEN).contains(&id.len())
+        && id.iter().all(|c| c.is_ascii_alphanumeric() || *c == b'-')
+}
+
+/// Middleware that removes the `x-request-id` header of requests with a invalid id, so a
+/// new id is generated for them. must be placed before the layer setting the request ids
+pub async fn discard_invalid_request_id(mut request: Request, next: Next) -> Response {
+    let is_invalid = request
+        .headers()
+        .get_all(&X_REQUEST_ID)
+        .iter()
+        .enumerate()
+        .any(|(i, id)| i > 0 || !is_valid_request_id(id.as_bytes()));
+
+    if is_invalid {
+        request.headers_mut().remove(&X_REQUEST_ID);
+    }
+
+    next.run(request).await
+}
+
+/// Extracts otel span contexts from HTTP headers
+struct HeaderExtractor<'a>(&'a HeaderMap);
+
+impl<'a> Extractor for HeaderExtractor<'a> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|v| v.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|k| k.as_str()).collect()
+    }
+}
+
+/// Creates the span of a HTTP request with its request id, if the request
+/// headers contain a trace context the span is created as its child.
+///
+/// the `impersonator_id` field is recorded by the `require_user` middleware
+/// for requests made with impersonation sessions.
+pub fn make_request_span(request: &Request<Body>) -> Span {
+    let request_id = request
+        .headers()
+        .get(&X_REQUEST_ID)
+        .and_then(|v| v.to_str().ok())
+        .unwrap_or_default();
+
+    let span = info_span!(
+        "http_request",
+        method = %request.method(),
+        uri = %request.uri(),
+        request_id = %request_id,
+        impersonator_id = tracing::field::Empty,
+    );
+
+    let parent_cx = opentelemetry::global::get_text_map_propagator(|propagator| {
+        propagator.extract(&HeaderExtractor(request.headers()))
+    });
+
+    span.set_parent(parent_cx);
+
+    span
+}
+
+/// Middleware that sets the `x-trace-id` response header with the
+/// trace id of the current span, if the request is being traced.
+pub async fn set_trace_id_header(request: Request, next: Next) -> Response {
+    let trace_id = Span::current().context().span().span_context().trace_id();
+
+    let mut response = next.run(request).await;
+
+    if trace_id != opentelemetry::trace::TraceId::INVALID {
+        if let Ok(value) = HeaderValue::from_str(&trace_id.to_string()) {
+            response.headers_mut().insert(X_TRACE_ID.clone(), value);
+        }
+    }
+
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_short_alphanumeric_ids_with_dashes() {
+        assert!(is_valid_request_id(b"a"));
+        assert!(is_valid_request_id(b"5f0c6ba2-7e3c-4d1b-9a51-2b1f0d7ea8c4"));
+        assert!(is_valid_request_id(
+            "A".repeat(MAX_REQUEST_ID_LEN).as_bytes()
+        ));
+    }
+
+    #[test]
+    fn rejects_empty_long_or_non_alphanumeric_ids() {
+        assert!(!is_valid_request_id(b""));
+        assert!(!is_valid_request_id(
+            "A".repeat(MAX_REQUEST_ID_LEN + 1).as_bytes()
+        ));
+        assert!(!is_valid_request_id(b"id with spaces"));
+        assert!(!is_valid_request_id(b"id\nforged log line"));
+        assert!(!is_valid_request_id(b"id_with_underscores"));
+        assert!(!is_valid_request_id("\u{e9}".as_bytes()));
+    }
+}
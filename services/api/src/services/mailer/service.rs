@@ -1,17 +1,85 @@
-use super::templates::{ConfirmEmailReplacements, RecoverPasswordReplacements};
-use crate::{config::app_config, rabbitmq::Rmq};
-use anyhow::Result;
-use lapin::{
-    options::BasicPublishOptions, publisher_confirm::PublisherConfirm, types::FieldTable,
-    BasicProperties,
+use super::templates::{
+    ConfirmEmailReplacements, EmailTemplate, InviteUserReplacements, MagicLinkReplacements,
+    RecoverPasswordReplacements,
 };
+use crate::{
+    config::app_config,
+    modules::common::{
+        error_codes::{EMAIL_REJECTED, EMAIL_SERVICE_UNAVAILABLE},
+        responses::{internal_error_res, SimpleError},
+    },
+    rabbitmq::{PublishError, Rmq},
+};
+use http::StatusCode;
+use lapin::{options::BasicPublishOptions, types::FieldTable, BasicProperties};
 use shared::dto::mailer::{EmailRecipient, SendEmailIn};
-use std::fs;
-use std::sync::Arc;
-use tracing::Span;
+use std::{sync::Arc, time::Duration};
+use tokio::time::sleep;
+use tracing::{warn, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 use url;
 
+/// times a email is published to the mailer queue before giving up if the broker is unreachable
+const MAX_PUBLISH_ATTEMPTS: u32 = 3;
+
+/// delay before the first publish retry, doubled on every retry
+const PUBLISH_RETRY_DELAY: Duration = Duration::from_millis(200);
+
+type Result<T> = std::result::Result<T, QueueEmailError>;
+
+/// Error queueing a email to be sent by the mailer service
+#[derive(Debug)]
+pub enum QueueEmailError {
+    /// the email could not be built (eg: failed to read its template)
+    Build(anyhow::Error),
+    /// the message broker could not be reached, even after retrying
+    BrokerUnreachable(lapin::Error),
+    /// the message broker refused the email
+    Rejected,
+}
+
+impl From<PublishError> for QueueEmailError {
+    fn from(err: PublishError) -> Self {
+        match err {
+            PublishError::Unreachable(e) => QueueEmailError::BrokerUnreachable(e),
+            PublishError::Rejected => QueueEmailError::Rejected,
+        }
+    }
+}
+
+impl From<url::ParseError> for QueueEmailError {
+    fn from(err: url::ParseError) -> Self {
+        QueueEmailError::Build(err.into())
+    }
+}
+
+impl From<std::io::Error> for QueueEmailError {
+    fn from(err: std::io::Error) -> Self {
+        QueueEmailError::Build(err.into())
+    }
+}
+
+impl From<serde_json::Error> for QueueEmailError {
+    fn from(err: serde_json::Error) -> Self {
+        QueueEmailError::Build(err.into())
+    }
+}
+
+impl From<QueueEmailError> for (StatusCode, SimpleError) {
+    fn from(err: QueueEmailError) -> Self {
+        match err {
+            QueueEmailError::Build(_) => internal_error_res(),
+            QueueEmailError::BrokerUnreachable(_) => (
+                StatusCode::SERVICE_UNAVAILABLE,
+                SimpleError::from(EMAIL_SERVICE_UNAVAILABLE),
+            ),
+            QueueEmailError::Rejected => {
+                (StatusCode::BAD_GATEWAY, SimpleError::from(EMAIL_REJECTED))
+            }
+        }
+    }
+}
+
 pub enum ConfirmEmailRecipientType {
     User,
     Organization,
@@ -28,34 +96,52 @@ impl MailerService {
         MailerService { rmq }
     }
 
+    /// publishes a RPC call to the mailer queue and waits for the broker to confirm it,
+    /// retrying with a exponential backoff while the broker is unreachable
     #[tracing::instrument(skip(self, payload))]
-    async fn publish_to_mailer_service(
-        &self,
-        payload: &[u8],
-        rpc_name: &str,
-    ) -> Result<PublisherConfirm> {
+    async fn publish_to_mailer_service(&self, payload: &[u8], rpc_name: &str) -> Result<()> {
         let span = Span::current();
         let ctx = span.context();
 
         let amqp_headers = shared::tracer::create_amqp_headers_with_span_ctx(&ctx);
 
-        Ok(self
-            .rmq
-            .publish(
-                shared::constants::rabbitmq::DEFAULT_EXCHANGE,
-                shared::constants::rabbitmq::MAILER_QUEUE,
-                BasicPublishOptions::default(),
-                payload,
-                BasicProperties::default()
-                    .with_content_type("application/json".into())
-                    .with_kind(rpc_name.into())
-                    .with_headers(FieldTable::from(amqp_headers)),
-            )
-            .await?)
+        let mut attempt = 1;
+        let mut retry_delay = PUBLISH_RETRY_DELAY;
+
+        loop {
+            let result = self
+                .rmq
+                .publish_confirmed(
+                    shared::constants::rabbitmq::DEFAULT_EXCHANGE,
+                    shared::constants::rabbitmq::MAILER_QUEUE,
+                    // mandatory so the broker returns the email if the mailer queue does not exist
+                    BasicPublishOptions {
+                        mandatory: true,
+                        ..Default::default()
+                    },
+                    payload,
+                    BasicProperties::default()
+                        .with_content_type("application/json".into())
+                        .with_kind(rpc_name.into())
+                        .with_headers(FieldTable::from(amqp_headers.clone())),
+                )
+                .await;
+
+            match result {
+                Err(PublishError::Unreachable(err)) if attempt < MAX_PUBLISH_ATTEMPTS => {
+                    warn!(attempt, "failed to publish to the mailer queue: {}", err);
+
+                    sleep(retry_delay).await;
+                    retry_delay *= 2;
+                    attempt += 1;
+                }
+                result => return Ok(result?),
+            }
+        }
     }
 
     #[tracing::instrument(skip_all)]
-    pub async fn send_email(&self, input: SendEmailIn) -> Result<PublisherConfirm> {
+    pub async fn send_email(&self, input: SendEmailIn) -> Result<()> {
         self.publish_to_mailer_service(
             serde_json::to_string(&input)?.as_bytes(),
             shared::constants::rabbitmq::OP_SEND_EMAIL,
@@ -63,13 +149,16 @@ impl MailerService {
         .await
     }
 
+    /// sends the recover password email, `sender` is the email address to send
+    /// the email from, if `None` the mailer service default sender is used
     #[tracing::instrument(skip(self, reset_password_token))]
     pub async fn send_recover_password_email(
         &self,
         email: String,
         reset_password_token: String,
         username: String,
-    ) -> Result<PublisherConfirm> {
+        sender: Option<String>,
+    ) -> Result<()> {
         let mut link = create_frontend_link("auth/change-password")?;
         link.set_query(Some(format!("token={}", reset_password_token).as_str()));
 
@@ -78,9 +167,10 @@ impl MailerService {
             reset_password_link: link.into(),
         }));
 
-        let html = &read_template("recover-password")?;
+        let html = &EmailTemplate::RecoverPassword.read()?;
 
         let email = SendEmailIn::default()
+            .with_sender(sender)
             .with_subject("Rastercar: recover password")
             .with_body_html(html)
             .with_to(vec![EmailRecipient {
@@ -91,13 +181,81 @@ impl MailerService {
         self.send_email(email).await
     }
 
+    /// sends the magic link email to sign in without a password, `sender` is the email
+    /// address to send the email from, if `None` the mailer service default sender is used
+    #[tracing::instrument(skip(self, magic_link_token))]
+    pub async fn send_magic_link_email(
+        &self,
+        email: String,
+        magic_link_token: String,
+        username: String,
+        sender: Option<String>,
+    ) -> Result<()> {
+        let mut link = create_frontend_link("auth/magic-link")?;
+        link.set_query(Some(format!("token={}", magic_link_token).as_str()));
+
+        let replacements = Some(Into::into(MagicLinkReplacements {
+            username,
+            sign_in_link: link.into(),
+        }));
+
+        let email = SendEmailIn::default()
+            .with_sender(sender)
+            .with_subject("Rastercar: sign in")
+            .with_body_html(&EmailTemplate::MagicLink.read()?)
+            .with_to(vec![EmailRecipient {
+                email,
+                replacements,
+            }]);
+
+        self.send_email(email).await
+    }
+
+    /// sends the email inviting a user created by a organization member to set the
+    /// password of his account, `sender` is the email address to send the email
+    /// from, if `None` the mailer service default sender is used
+    #[tracing::instrument(skip(self, invite_token))]
+    pub async fn send_invite_user_email(
+        &self,
+        email: String,
+        invite_token: String,
+        username: String,
+        organization_id: i32,
+        organization_name: String,
+        sender: Option<String>,
+    ) -> Result<()> {
+        let mut link = create_frontend_link("auth/accept-invite")?;
+        link.set_query(Some(format!("token={}", invite_token).as_str()));
+
+        let replacements = Some(Into::into(InviteUserReplacements {
+            username,
+            organization_name: organization_name.clone(),
+            accept_invite_link: link.into(),
+        }));
+
+        let email = SendEmailIn::default()
+            .with_sender(sender)
+            .with_organization_id(Some(organization_id))
+            .with_subject(&format!("Rastercar: join {}", organization_name))
+            .with_body_html(&EmailTemplate::InviteUser.read()?)
+            .with_to(vec![EmailRecipient {
+                email,
+                replacements,
+            }]);
+
+        self.send_email(email).await
+    }
+
+    /// sends the confirm email address email, `sender` is the email address to send
+    /// the email from, if `None` the mailer service default sender is used
     #[tracing::instrument(skip(self, reset_password_token, recipient_type))]
     pub async fn send_confirm_email_address_email(
         &self,
         email: String,
         reset_password_token: String,
         recipient_type: ConfirmEmailRecipientType,
-    ) -> Result<PublisherConfirm> {
+        sender: Option<String>,
+    ) -> Result<()> {
         let mut link = create_frontend_link("auth/confirm-email-address")?;
 
         let (query, title) = match recipient_type {
@@ -120,8 +278,9 @@ impl MailerService {
         }));
 
         let email = SendEmailIn::default()
+            .with_sender(sender)
             .with_subject("Rastercar: confirm email")
-            .with_body_html(&read_template("confirm-email")?)
+            .with_body_html(&EmailTemplate::ConfirmEmail.read()?)
             .with_to(vec![EmailRecipient {
                 email,
                 replacements,
@@ -132,11 +291,6 @@ impl MailerService {
 }
 
 /// creates a link to the rastercar frontend
-fn create_frontend_link(path: &str) -> Result<url::Url, url::ParseError> {
+fn create_frontend_link(path: &str) -> std::result::Result<url::Url, url::ParseError> {
     app_config().frontend_url.join(path)
 }
-
-/// creates a link to the rastercar frontend
-fn read_template(template: &str) -> std::io::Result<String> {
-    fs::read_to_string(format!("templates/{}.hbs", template))
-}
@@ -1,6 +1,54 @@
-//! Structs containing the needed replacements for email templates
+//! Email templates and structs containing their needed replacements
 
-use std::collections::HashMap;
+use std::{collections::HashMap, fs};
+
+/// Every email template sent by the API, templates are handlebars
+/// files on the `templates` folder named after the template
+#[derive(Clone, Copy, Debug)]
+pub enum EmailTemplate {
+    RecoverPassword,
+    ConfirmEmail,
+    MagicLink,
+    InviteUser,
+}
+
+impl EmailTemplate {
+    pub const ALL: [EmailTemplate; 4] = [
+        EmailTemplate::RecoverPassword,
+        EmailTemplate::ConfirmEmail,
+        EmailTemplate::MagicLink,
+        EmailTemplate::InviteUser,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmailTemplate::RecoverPassword => "recover-password",
+            EmailTemplate::ConfirmEmail => "confirm-email",
+            EmailTemplate::MagicLink => "magic-link",
+            EmailTemplate::InviteUser => "invite-user",
+        }
+    }
+
+    /// finds a template by its name
+    pub fn from_name(name: &str) -> Option<EmailTemplate> {
+        Self::ALL.into_iter().find(|t| t.name() == name)
+    }
+
+    /// replacements the template needs to be rendered
+    pub fn required_replacements(&self) -> &'static [&'static str] {
+        match self {
+            EmailTemplate::RecoverPassword => &["username", "resetPasswordLink"],
+            EmailTemplate::ConfirmEmail => &["title", "confirmationLink"],
+            EmailTemplate::MagicLink => &["username", "signInLink"],
+            EmailTemplate::InviteUser => &["username", "organizationName", "acceptInviteLink"],
+        }
+    }
+
+    /// reads the template handlebars file
+    pub fn read(&self) -> std::io::Result<String> {
+        fs::read_to_string(format!("templates/{}.hbs", self.name()))
+    }
+}
 
 pub struct RecoverPasswordReplacements {
     pub username: String,
@@ -29,3 +77,33 @@ impl From<ConfirmEmailReplacements> for HashMap<String, String> {
         ])
     }
 }
+
+pub struct MagicLinkReplacements {
+    pub username: String,
+    pub sign_in_link: String,
+}
+
+impl From<MagicLinkReplacements> for HashMap<String, String> {
+    fn from(val: MagicLinkReplacements) -> Self {
+        HashMap::from([
+            (String::from("username"), val.username),
+            (String::from("signInLink"), val.sign_in_link),
+        ])
+    }
+}
+
+pub struct InviteUserReplacements {
+    pub username: String,
+    pub organization_name: String,
+    pub accept_invite_link: String,
+}
+
+impl From<InviteUserReplacements> for HashMap<String, String> {
+    fn from(val: InviteUserReplacements) -> Self {
+        HashMap::from([
+            (String::from("username"), val.username),
+            (String::from("organizationName"), val.organization_name),
+            (String::from("acceptInviteLink"), val.accept_invite_link),
+        ])
+    }
+}
@@ -1,2 +1,4 @@
 pub mod mailer;
+pub mod outbox;
 pub mod s3;
+pub mod ses;
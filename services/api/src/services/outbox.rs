@@ -0,0 +1,225 @@
+//! Transactional outbox for RabbitMQ messages.
+//!
+//! Publishing a message right after a database change is not safe, if the process
+//! crashes between the commit and the publishing the message is lost. Instead the
+//! message is written to the `outbox` table on the same transaction as the change and
+//! a background relay publishes the pending rows, marking them as published.
+//!
+//! This gives at least once delivery, a message might be published more than once if
+//! the relay crashes after publishing but before marking it (or takes longer than the
+//! claim duration to publish it), so consumers should use the `message_id` property
+//! (the outbox row id) to discard duplicates.
+
+use crate::rabbitmq::Rmq;
+use chrono::{DateTime, Utc};
+use lapin::{options::BasicPublishOptions, BasicProperties};
+use sea_orm::{
+    sea_query::Expr, ActiveModelTrait, ColumnTrait, ConnectionTrait, DatabaseConnection, DbErr,
+    EntityTrait, QueryFilter, Set, Statement, TransactionTrait,
+};
+use serde::Serialize;
+use shared::entity::outbox;
+use std::collections::HashSet;
+use tracing::error;
+
+/// Maximum amount of messages published on every relay run
+static RELAY_BATCH_SIZE: u64 = 100;
+
+/// Key of the postgres advisory lock held while claiming messages, this way only a
+/// single API instance claims messages at a time and the order is preserved
+static RELAY_ADVISORY_LOCK_KEY: i64 = 7_204_118_311;
+
+/// Seconds the messages claimed by a relay are not claimed by other relays, if the relay
+/// crashes while publishing them they are published again once the claim expires
+static RELAY_CLAIM_SECONDS: u64 = 60;
+
+/// A message to be written to the outbox
+pub struct OutboxMessage {
+    pub aggregate_type: String,
+    pub aggregate_id: String,
+    pub exchange: String,
+    pub routing_key: String,
+    pub payload: serde_json::Value,
+}
+
+impl OutboxMessage {
+    /// Creates a message for a event on the API events exchange with the routing
+    /// key `<aggregate_type>.<event>.<aggregate_id>`, eg: `tracker.created.1`
+    pub fn api_event<T: Serialize>(
+        aggregate_type: &str,
+        aggregate_id: impl ToString,
+        event: &str,
+        payload: &T,
+    ) -> Result<OutboxMessage, serde_json::Error> {
+        let aggregate_id = aggregate_id.to_string();
+
+        Ok(OutboxMessage {
+            routing_key: format!("{}.{}.{}", aggregate_type, event, aggregate_id),
+            exchange: shared::constants::rabbitmq::API_EVENTS_EXCHANGE.to_string(),
+            aggregate_type: aggregate_type.to_string(),
+            payload: serde_json::to_value(payload)?,
+            aggregate_id,
+        })
+    }
+
+    /// Writes the message to the outbox, `db` should be the transaction
+    /// used to write the change that originated the message.
+    pub async fn enqueue<C: ConnectionTrait>(self, db: &C) -> Result<(), DbErr> {
+        outbox::ActiveModel {
+            aggregate_type: Set(self.aggregate_type),
+            aggregate_id: Set(self.aggregate_id),
+            exchange: Set(self.exchange),
+            routing_key: Set(self.routing_key),
+            payload: Set(self.payload),
+            ..Default::default()
+        }
+        .insert(db)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Deletes the messages published before `published_before`, returning the amount deleted.
+///
+/// unpublished messages are never deleted, regardless of how old they are
+pub async fn prune_published_messages(
+    db: &DatabaseConnection,
+    published_before: DateTime<Utc>,
+) -> Result<u64, DbErr> {
+    let result = outbox::Entity::delete_many()
+        .filter(outbox::Column::PublishedAt.lt(published_before))
+        .exec(db)
+        .await?;
+
+    Ok(result.rows_affected)
+}
+
+/// Claims the oldest unpublished messages not claimed by another relay, skipping the
+/// aggregates with messages still claimed so their messages are not published out of order
+async fn claim_pending_messages(db: &DatabaseConnection) -> Result<Vec<outbox::Model>, DbErr> {
+    let txn = db.begin().await?;
+
+    let lock_acquired = txn
+        .query_one(Statement::from_sql_and_values(
+            txn.get_database_backend(),
+            "SELECT pg_try_advisory_xact_lock($1) AS locked",
+            [RELAY_ADVISORY_LOCK_KEY.into()],
+        ))
+        .await?
+        .map(|row| row.try_get::<bool>("", "locked"))
+        .transpose()?
+        .unwrap_or(false);
+
+    if !lock_acquired {
+        return Ok(Vec::new());
+    }
+
+    let mut claimed = outbox::Entity::find()
+        .from_raw_sql(Statement::from_sql_and_values(
+            txn.get_database_backend(),
+            r#"
+UPDATE "outbox" SET "claimed_until" = now() + make_interval(secs => $1)
+WHERE "id" IN (
+    SELECT o."id" FROM "outbox" o
+    WHERE o."published_at" IS NULL
+    AND (o."claimed_until" IS NULL OR o."claimed_until" < now())
+    AND NOT EXISTS (
+        SELECT 1 FROM "outbox" c
+        WHERE c."published_at" IS NULL
+        AND c."claimed_until" >= now()
+        AND c."aggregate_type" = o."aggregate_type"
+        AND c."aggregate_id" = o."aggregate_id"
+    )
+    ORDER BY o."id"
+    LIMIT $2
+)
+RETURNING *
+            "#,
+            [
+                (RELAY_CLAIM_SECONDS as f64).into(),
+                (RELAY_BATCH_SIZE as i64).into(),
+            ],
+        ))
+        .all(&txn)
+        .await?;
+
+    txn.commit().await?;
+
+    claimed.sort_by_key(|msg| msg.id);
+
+    Ok(claimed)
+}
+
+/// Publishes the oldest unpublished outbox messages, returning the amount published.
+///
+/// The messages are claimed on a short transaction and published after it is committed,
+/// so no transaction or lock is held while waiting for the broker. Messages are published
+/// in order and if publishing a message fails the following messages of the same aggregate
+/// are left for the next run, so the order of the messages of a aggregate is always kept.
+pub async fn relay_pending_messages(db: &DatabaseConnection, rmq: &Rmq) -> Result<usize, DbErr> {
+    let claimed = claim_pending_messages(db).await?;
+
+    let mut published_ids: Vec<i64> = Vec::new();
+    let mut unpublished_ids: Vec<i64> = Vec::new();
+    let mut failed_aggregates: HashSet<(String, String)> = HashSet::new();
+
+    for msg in claimed {
+        let aggregate = (msg.aggregate_type.clone(), msg.aggregate_id.clone());
+
+        if failed_aggregates.contains(&aggregate) {
+            unpublished_ids.push(msg.id);
+            continue;
+        }
+
+        let properties = BasicProperties::default()
+            .with_content_type("application/json".into())
+            .with_message_id(msg.id.to_string().into())
+            .with_timestamp(msg.created_at.timestamp() as u64);
+
+        let publish_result = rmq
+            .publish_confirmed(
+                &msg.exchange,
+                &msg.routing_key,
+                BasicPublishOptions::default(),
+                msg.payload.to_string().as_bytes(),
+                properties,
+            )
+            .await;
+
+        match publish_result {
+            Ok(_) => published_ids.push(msg.id),
+            Err(e) => {
+                error!("[RMQ] failed to publish outbox message {}: {}", msg.id, e);
+                unpublished_ids.push(msg.id);
+                failed_aggregates.insert(aggregate);
+            }
+        }
+    }
+
+    // only marks rows not marked yet, so re-running the
+    // relay after a crash never changes their publishing date
+    if !published_ids.is_empty() {
+        outbox::Entity::update_many()
+            .col_expr(outbox::Column::PublishedAt, Expr::value(Utc::now()))
+            .filter(outbox::Column::Id.is_in(published_ids.clone()))
+            .filter(outbox::Column::PublishedAt.is_null())
+            .exec(db)
+            .await?;
+    }
+
+    // releases the claim of the messages that were not published so the next
+    // run retries them, instead of waiting for the claim to expire
+    if !unpublished_ids.is_empty() {
+        outbox::Entity::update_many()
+            .col_expr(
+                outbox::Column::ClaimedUntil,
+                Expr::value(Option::<DateTime<Utc>>::None),
+            )
+            .filter(outbox::Column::Id.is_in(unpublished_ids))
+            .exec(db)
+            .await?;
+    }
+
+    Ok(published_ids.len())
+}
@@ -1,21 +1,65 @@
 use crate::config::{app_config, aws_config};
 use aws_sdk_s3 as s3;
 use axum::body::Bytes;
+use chrono::{DateTime, Utc};
 use s3::{
     error::SdkError,
     operation::{
+        abort_multipart_upload::{AbortMultipartUploadError, AbortMultipartUploadOutput},
+        complete_multipart_upload::{CompleteMultipartUploadError, CompleteMultipartUploadOutput},
+        create_multipart_upload::CreateMultipartUploadError,
         delete_object::{DeleteObjectError, DeleteObjectOutput},
+        get_object::GetObjectError,
+        head_object::HeadObjectError,
+        list_multipart_uploads::ListMultipartUploadsError,
+        list_parts::ListPartsError,
         put_object::{PutObjectError, PutObjectOutput},
+        upload_part::UploadPartError,
     },
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
     Client,
 };
+use std::time::SystemTime;
 use tracing::error;
 
+/// Metadata of a S3 object, used to answer conditional and HEAD requests
+#[derive(Clone, Debug)]
+pub struct ObjectMetadata {
+    pub e_tag: Option<String>,
+    pub last_modified: Option<SystemTime>,
+    pub content_type: Option<String>,
+    pub content_length: Option<i64>,
+}
+
+/// A S3 object with its body not yet read
+pub struct S3Object {
+    pub metadata: ObjectMetadata,
+    pub body: ByteStream,
+}
+
+/// A part of a multipart upload already uploaded to S3
+#[derive(Clone, Debug)]
+pub struct UploadedPart {
+    pub part_number: i32,
+    pub e_tag: String,
+    pub size: i64,
+}
+
+/// A multipart upload that was initiated but not completed nor aborted
+#[derive(Clone, Debug)]
+pub struct PendingMultipartUpload {
+    pub key: String,
+    pub upload_id: String,
+    pub initiated_at: Option<DateTime<Utc>>,
+}
+
 /// a AWS S3 key to store rastercar objects
 ///
-/// this is primarily used to create a tenant aware S3 object key in the format:
+/// this is primarily used to create a environment and tenant aware S3 object key in the format:
 ///
-/// `tenant`/`folder`/`filename` where for now tenant is always rastercar
+/// `prefix`/`folder`/`filename` where the prefix is set by the `AWS_UPLOADS_KEY_PREFIX`
+/// env var, defaulting to the tenant slug
 #[derive(Clone)]
 pub struct S3Key {
     /// the "folder" a file using this key will be stored into
@@ -27,14 +71,16 @@ pub struct S3Key {
     pub filename: String,
 }
 
+impl S3Key {
+    /// the full object key of this key under `prefix`
+    pub fn with_prefix(&self, prefix: &str) -> String {
+        format!("{}/{}/{}", prefix, self.folder, self.filename)
+    }
+}
+
 impl From<S3Key> for String {
     fn from(v: S3Key) -> Self {
-        format!(
-            "{}/{}/{}",
-            app_config().tenant_slug.clone(),
-            v.folder,
-            v.filename
-        )
+        v.with_prefix(app_config().uploads_key_prefix())
     }
 }
 
@@ -42,6 +88,8 @@ impl From<S3Key> for String {
 pub struct S3 {
     client: Client,
     uploads_bucket: String,
+    /// prefix of every object key, see `S3Key`
+    key_prefix: String,
 }
 
 impl S3 {
@@ -49,6 +97,7 @@ impl S3 {
         Self {
             client: s3::Client::new(aws_config().await),
             uploads_bucket: app_config().aws_uploads_bucket_name.clone(),
+            key_prefix: app_config().uploads_key_prefix().to_owned(),
         }
     }
 
@@ -91,4 +140,268 @@ impl S3 {
 
         result
     }
+
+    /// fetches the metadata of a object without its body, returning `None` if it does not exist
+    pub async fn head(
+        &self,
+        key: String,
+    ) -> Result<Option<ObjectMetadata>, SdkError<HeadObjectError>> {
+        let result = self
+            .client
+            .head_object()
+            .bucket(&self.uploads_bucket)
+            .key(key.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(Some(ObjectMetadata {
+                e_tag: output.e_tag().map(String::from),
+                last_modified: output
+                    .last_modified()
+                    .and_then(|t| SystemTime::try_from(*t).ok()),
+                content_type: output.content_type().map(String::from),
+                content_length: output.content_length(),
+            })),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_not_found()) => Ok(None),
+            Err(e) => {
+                error!("[S3] failed to fetch S3 object metadata: {}", key);
+                Err(e)
+            }
+        }
+    }
+
+    /// fetches a object and its metadata, returning `None` if it does not exist
+    pub async fn get(&self, key: String) -> Result<Option<S3Object>, SdkError<GetObjectError>> {
+        let result = self
+            .client
+            .get_object()
+            .bucket(&self.uploads_bucket)
+            .key(key.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(Some(S3Object {
+                metadata: ObjectMetadata {
+                    e_tag: output.e_tag().map(String::from),
+                    last_modified: output
+                        .last_modified()
+                        .and_then(|t| SystemTime::try_from(*t).ok()),
+                    content_type: output.content_type().map(String::from),
+                    content_length: output.content_length(),
+                },
+                body: output.body,
+            })),
+            Err(e) if e.as_service_error().is_some_and(|e| e.is_no_such_key()) => Ok(None),
+            Err(e) => {
+                error!("[S3] failed to get S3 object: {}", key);
+                Err(e)
+            }
+        }
+    }
+
+    /// starts a multipart upload for a object, returning the upload ID that
+    /// is needed to upload, list, complete and abort the upload parts
+    pub async fn initiate_multipart_upload(
+        &self,
+        key: String,
+    ) -> Result<String, SdkError<CreateMultipartUploadError>> {
+        let result = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.uploads_bucket)
+            .key(key.clone())
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output.upload_id().unwrap_or_default().to_owned()),
+            Err(e) => {
+                error!("[S3] failed to initiate multipart upload: {}", key);
+                Err(e)
+            }
+        }
+    }
+
+    /// uploads a part of a multipart upload, returning the part ETag, uploading a
+    /// part with the same number of a already uploaded one overwrites it
+    pub async fn upload_part(
+        &self,
+        key: String,
+        upload_id: &str,
+        part_number: i32,
+        bytes: Bytes,
+    ) -> Result<String, SdkError<UploadPartError>> {
+        let result = self
+            .client
+            .upload_part()
+            .bucket(&self.uploads_bucket)
+            .key(key.clone())
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(bytes.into())
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(output.e_tag().unwrap_or_default().to_owned()),
+            Err(e) => {
+                error!("[S3] failed to upload part {} of: {}", part_number, key);
+                Err(e)
+            }
+        }
+    }
+
+    /// lists the parts already uploaded of a multipart upload, ordered by part number
+    ///
+    /// only the first 1000 parts are listed, so uploads should not have more parts than that
+    pub async fn list_parts(
+        &self,
+        key: String,
+        upload_id: &str,
+    ) -> Result<Vec<UploadedPart>, SdkError<ListPartsError>> {
+        let output = self
+            .client
+            .list_parts()
+            .bucket(&self.uploads_bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await?;
+
+        let parts = output
+            .parts()
+            .iter()
+            .map(|part| UploadedPart {
+                part_number: part.part_number().unwrap_or_default(),
+                e_tag: part.e_tag().unwrap_or_default().to_owned(),
+                size: part.size().unwrap_or_default(),
+            })
+            .collect();
+
+        Ok(parts)
+    }
+
+    /// completes a multipart upload, assembling the object from the parts
+    pub async fn complete_multipart_upload(
+        &self,
+        key: String,
+        upload_id: &str,
+        parts: Vec<UploadedPart>,
+    ) -> Result<CompleteMultipartUploadOutput, SdkError<CompleteMultipartUploadError>> {
+        let completed_parts = parts
+            .into_iter()
+            .map(|part| {
+                CompletedPart::builder()
+                    .part_number(part.part_number)
+                    .e_tag(part.e_tag)
+                    .build()
+            })
+            .collect();
+
+        let result = self
+            .client
+            .complete_multipart_upload()
+            .bucket(&self.uploads_bucket)
+            .key(key.clone())
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(completed_parts))
+                    .build(),
+            )
+            .send()
+            .await;
+
+        if result.is_err() {
+            error!("[S3] failed to complete multipart upload: {}", key);
+        }
+
+        result
+    }
+
+    /// aborts a multipart upload, deleting its uploaded parts
+    pub async fn abort_multipart_upload(
+        &self,
+        key: String,
+        upload_id: &str,
+    ) -> Result<AbortMultipartUploadOutput, SdkError<AbortMultipartUploadError>> {
+        let result = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.uploads_bucket)
+            .key(key.clone())
+            .upload_id(upload_id)
+            .send()
+            .await;
+
+        if result.is_err() {
+            error!("[S3] failed to abort multipart upload: {}", key);
+        }
+
+        result
+    }
+
+    /// lists all the multipart uploads under the key prefix that were not completed nor aborted
+    pub async fn list_pending_multipart_uploads(
+        &self,
+    ) -> Result<Vec<PendingMultipartUpload>, SdkError<ListMultipartUploadsError>> {
+        let mut uploads = vec![];
+        let mut key_marker: Option<String> = None;
+        let mut upload_id_marker: Option<String> = None;
+
+        loop {
+            let output = self
+                .client
+                .list_multipart_uploads()
+                .bucket(&self.uploads_bucket)
+                .prefix(format!("{}/", self.key_prefix))
+                .set_key_marker(key_marker)
+                .set_upload_id_marker(upload_id_marker)
+                .send()
+                .await?;
+
+            uploads.extend(output.uploads().iter().map(|upload| {
+                PendingMultipartUpload {
+                    key: upload.key().unwrap_or_default().to_owned(),
+                    upload_id: upload.upload_id().unwrap_or_default().to_owned(),
+                    initiated_at: upload
+                        .initiated()
+                        .and_then(|t| DateTime::from_timestamp(t.secs(), 0)),
+                }
+            }));
+
+            if !output.is_truncated().unwrap_or(false) {
+                break;
+            }
+
+            key_marker = output.next_key_marker().map(String::from);
+            upload_id_marker = output.next_upload_id_marker().map(String::from);
+        }
+
+        Ok(uploads)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn composes_the_object_key_under_the_prefix() {
+        let key = S3Key {
+            folder: String::from("organization/1/vehicle/2"),
+            filename: String::from("photo.jpeg"),
+        };
+
+        assert_eq!(
+            key.with_prefix("rastercar"),
+            "rastercar/organization/1/vehicle/2/photo.jpeg"
+        );
+        assert_eq!(
+            key.with_prefix("staging/rastercar"),
+            "staging/rastercar/organization/1/vehicle/2/photo.jpeg"
+        );
+    }
 }
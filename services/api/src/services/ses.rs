@@ -0,0 +1,230 @@
+use crate::config::aws_config;
+use aws_sdk_sesv2 as ses;
+use lru::LruCache;
+use ses::{
+    error::SdkError,
+    operation::{
+        create_email_identity::CreateEmailIdentityError, get_email_identity::GetEmailIdentityError,
+    },
+    types::VerificationStatus,
+    Client,
+};
+use std::{
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tracing::error;
+
+/// How long the status of a identity is cached, so checking a sender
+/// repeatedly (eg: while its DNS records propagate) does not hit SES every time
+const IDENTITY_STATUS_TTL: Duration = Duration::from_secs(60);
+
+/// Maximum amount of identities whose status is cached, the least recently used is evicted
+const IDENTITY_STATUS_CACHE_CAPACITY: NonZeroUsize = match NonZeroUsize::new(1000) {
+    Some(capacity) => capacity,
+    None => unreachable!(),
+};
+
+/// Verification status of a SES identity (a email address or domain)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdentityStatus {
+    /// emails can be sent from the identity
+    Verified,
+    /// the identity verification was not started or did not finish yet
+    Pending,
+    /// the identity verification failed, it must be verified again
+    Failed,
+    /// the identity was not added to SES
+    NotFound,
+}
+
+/// the SES identities emails from a address can be sent with, the address itself and its
+/// domain, in lowercase as they are stored on `organization_email_identity`
+pub fn sender_identities(email: &str) -> Vec<String> {
+    let email = email.to_lowercase();
+
+    match email.rsplit_once('@') {
+        Some((_, domain)) => vec![domain.to_owned(), email.clone()],
+        None => vec![email],
+    }
+}
+
+#[derive(Clone)]
+pub struct Ses {
+    client: Client,
+
+    /// identity -> (status, time the status was fetched)
+    status_cache: Arc<Mutex<LruCache<String, (IdentityStatus, Instant)>>>,
+}
+
+impl Ses {
+    pub async fn new() -> Self {
+        Self {
+            client: ses::Client::new(aws_config().await),
+            status_cache: Arc::new(Mutex::new(LruCache::new(IDENTITY_STATUS_CACHE_CAPACITY))),
+        }
+    }
+
+    /// fetches the verification status of a SES identity, without caching
+    async fn fetch_identity_status(
+        &self,
+        identity: &str,
+    ) -> Result<IdentityStatus, SdkError<GetEmailIdentityError>> {
+        let result = self
+            .client
+            .get_email_identity()
+            .email_identity(identity)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) if output.verified_for_sending_status() => Ok(IdentityStatus::Verified),
+            Ok(output) => match output.verification_status() {
+                Some(VerificationStatus::Failed | VerificationStatus::TemporaryFailure) => {
+                    Ok(IdentityStatus::Failed)
+                }
+                _ => Ok(IdentityStatus::Pending),
+            },
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_not_found_exception()) =>
+            {
+                Ok(IdentityStatus::NotFound)
+            }
+            Err(e) => {
+                error!("[SES] failed to get email identity: {}", identity);
+                Err(e)
+            }
+        }
+    }
+
+    /// gets the verification status of a SES identity, cached for `IDENTITY_STATUS_TTL`
+    async fn identity_status(
+        &self,
+        identity: &str,
+    ) -> Result<IdentityStatus, SdkError<GetEmailIdentityError>> {
+        let cached = self.status_cache.lock().unwrap().get(identity).copied();
+
+        if let Some((status, fetched_at)) = cached {
+            if fetched_at.elapsed() < IDENTITY_STATUS_TTL {
+                return Ok(status);
+            }
+        }
+
+        let status = self.fetch_identity_status(identity).await?;
+
+        self.status_cache
+            .lock()
+            .unwrap()
+            .put(identity.to_owned(), (status, Instant::now()));
+
+        Ok(status)
+    }
+
+    /// checks if a SES identity exists and is verified for sending, returning
+    /// `false` if the identity does not exist
+    async fn is_identity_verified(
+        &self,
+        identity: &str,
+    ) -> Result<bool, SdkError<GetEmailIdentityError>> {
+        Ok(self.fetch_identity_status(identity).await? == IdentityStatus::Verified)
+    }
+
+    /// gets the verification status of a owned identity, identities not in `owned_identities`
+    /// are `IdentityStatus::NotFound` without checking SES, see `is_verified_sender`
+    async fn owned_identity_status(
+        &self,
+        identity: &str,
+        owned_identities: &[String],
+    ) -> Result<IdentityStatus, SdkError<GetEmailIdentityError>> {
+        if !owned_identities.iter().any(|owned| owned == identity) {
+            return Ok(IdentityStatus::NotFound);
+        }
+
+        self.identity_status(identity).await
+    }
+
+    /// gets the verification status of a email address as a sender, which is verified if the
+    /// address itself or its domain are verified, otherwise the status of the address identity
+    /// is returned, or of its domain if the address was not added to SES. Only the
+    /// `owned_identities` are checked, just like on `is_verified_sender`.
+    ///
+    /// the statuses are cached for `IDENTITY_STATUS_TTL`, use `is_verified_sender`
+    /// to check if a sender can be used right before using it
+    pub async fn sender_status(
+        &self,
+        email: &str,
+        owned_identities: &[String],
+    ) -> Result<IdentityStatus, SdkError<GetEmailIdentityError>> {
+        let email = email.to_lowercase();
+
+        let address_status = self.owned_identity_status(&email, owned_identities).await?;
+
+        let Some((_, domain)) = email.rsplit_once('@') else {
+            return Ok(address_status);
+        };
+
+        if address_status == IdentityStatus::Verified {
+            return Ok(address_status);
+        }
+
+        let domain_status = self.owned_identity_status(domain, owned_identities).await?;
+
+        if domain_status == IdentityStatus::Verified || address_status == IdentityStatus::NotFound {
+            return Ok(domain_status);
+        }
+
+        Ok(address_status)
+    }
+
+    /// starts the verification of a SES identity, returning the DKIM tokens to create the DNS
+    /// records of domain identities with, or `None` if the identity already exists
+    pub async fn create_identity(
+        &self,
+        identity: &str,
+    ) -> Result<Option<Vec<String>>, SdkError<CreateEmailIdentityError>> {
+        let result = self
+            .client
+            .create_email_identity()
+            .email_identity(identity)
+            .send()
+            .await;
+
+        match result {
+            Ok(output) => Ok(Some(
+                output
+                    .dkim_attributes()
+                    .map(|dkim| dkim.tokens().to_vec())
+                    .unwrap_or_default(),
+            )),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_already_exists_exception()) =>
+            {
+                Ok(None)
+            }
+            Err(e) => {
+                error!("[SES] failed to create email identity: {}", identity);
+                Err(e)
+            }
+        }
+    }
+
+    /// checks if emails can be sent from a email address, that is if the address itself or
+    /// its domain are a SES identity verified for sending. Only the `owned_identities` are
+    /// checked, since SES identities are shared by every organization, see `sender_identities`
+    pub async fn is_verified_sender(
+        &self,
+        email: &str,
+        owned_identities: &[String],
+    ) -> Result<bool, SdkError<GetEmailIdentityError>> {
+        for identity in sender_identities(email) {
+            if owned_identities.contains(&identity) && self.is_identity_verified(&identity).await? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
@@ -1,29 +1,40 @@
+use shared::tracer::{self, LogFormat, TracingOpts};
 use tracing::subscriber::SetGlobalDefaultError;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, Registry};
 
 /// initialize the API tracing, creating a layer that exports spans to Jaeger
-/// using opentelemetry and a stdout layer if `with_stdout` is true
-pub fn init(service_name: &str, with_stdout: bool) -> Result<(), SetGlobalDefaultError> {
+/// using opentelemetry and a stdout layer writing logs in `log_format`, the
+/// Jaeger layer is not created if tracing is disabled on `opts`
+pub fn init(
+    service_name: &str,
+    log_format: LogFormat,
+    opts: &TracingOpts,
+) -> Result<(), SetGlobalDefaultError> {
     opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
 
-    let tracer = opentelemetry_jaeger::new_agent_pipeline()
-        .with_service_name(service_name)
-        .with_auto_split_batch(true)
-        .install_batch(opentelemetry::runtime::Tokio)
-        .expect("failed to initialize tracer");
+    let telemetry_layer = if opts.enabled {
+        let tracer = opentelemetry_jaeger::new_agent_pipeline()
+            .with_service_name(service_name)
+            .with_auto_split_batch(true)
+            .with_trace_config(opts.trace_config())
+            .install_batch(opentelemetry::runtime::Tokio)
+            .expect("failed to initialize tracer");
 
-    let telemetry_layer = tracing_opentelemetry::layer().with_tracer(tracer);
-
-    let stdout_layer = if with_stdout {
-        Some(tracing_subscriber::fmt::Layer::default())
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
     } else {
         None
     };
 
+    if let Some(interval) = opts.metrics_export_interval {
+        tracer::init_metrics(service_name, interval).expect("failed to initialize metrics");
+    }
+
+    let stdout_layer = tracer::stdout_layer(service_name, log_format);
+
     let subscriber = Registry::default().with(stdout_layer).with(telemetry_layer);
 
     tracing::subscriber::set_global_default(subscriber)?;
 
-    println!("[TRACER] initialized");
+    println!("[TRACER] initialized, exporting spans: {}", opts.enabled);
     Ok(())
 }
@@ -1,4 +1,10 @@
+use crate::server::diagnostics::RawFrameStore;
+use crate::server::listeners::{ConnectionTimeouts, SocketOptions};
 use serde::Deserialize;
+use shared::tracer::LogFormat;
+use socket2::TcpKeepalive;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::time::Duration;
 
 fn def_debug() -> bool {
     false
@@ -20,6 +26,66 @@ fn def_port_h02() -> usize {
     3003
 }
 
+fn def_tcp_idle_timeout_seconds() -> u64 {
+    600
+}
+
+fn def_tcp_max_connection_lifetime_seconds() -> u64 {
+    60 * 60 * 24
+}
+
+fn def_tcp_nodelay() -> bool {
+    true
+}
+
+fn def_tcp_keepalive_enabled() -> bool {
+    true
+}
+
+fn def_tcp_keepalive_time_seconds() -> u64 {
+    60
+}
+
+fn def_tcp_keepalive_interval_seconds() -> u64 {
+    10
+}
+
+fn def_tcp_keepalive_probes() -> u32 {
+    5
+}
+
+fn def_raw_frames_per_tracker() -> usize {
+    0
+}
+
+fn def_raw_frames_max_trackers() -> NonZeroUsize {
+    NonZeroUsize::new(1000).unwrap()
+}
+
+fn def_raw_frames_retention_seconds() -> u64 {
+    60 * 60
+}
+
+fn def_port_diagnostics() -> usize {
+    3010
+}
+
+fn def_rmq_outage_alarm_failures() -> u32 {
+    10
+}
+
+fn def_rmq_outage_alarm_seconds() -> u64 {
+    5 * 60
+}
+
+fn def_tracer_enabled() -> bool {
+    true
+}
+
+fn def_tracer_sample_ratio() -> f64 {
+    1.0
+}
+
 #[derive(Deserialize, Debug)]
 pub struct AppConfig {
     /// If the application should be run in debug mode and print additional info to stdout
@@ -30,6 +96,16 @@ pub struct AppConfig {
     #[serde(default = "def_rmq_uri")]
     pub rmq_uri: String,
 
+    /// Consecutive failed RabbitMQ reconnection attempts after which the outage
+    /// is logged as a error on every attempt, the decoder keeps retrying regardless
+    #[serde(default = "def_rmq_outage_alarm_failures")]
+    pub rmq_outage_alarm_failures: u32,
+
+    /// Seconds the RabbitMQ connection can be down before the outage is reported, same
+    /// as `rmq_outage_alarm_failures`, whichever is reached first
+    #[serde(default = "def_rmq_outage_alarm_seconds")]
+    pub rmq_outage_alarm_seconds: u64,
+
     /// Name of the exchange to publish email events (positions, alarms, etc)
     #[serde(default = "def_tracker_events_exchange")]
     pub tracker_events_exchange: String,
@@ -38,9 +114,75 @@ pub struct AppConfig {
     #[serde(default = "def_tracer_service_name")]
     pub tracer_service_name: String,
 
+    /// If tracing spans should be exported to jaeger
+    #[serde(default = "def_tracer_enabled")]
+    pub tracer_enabled: bool,
+
+    /// Ratio of traces to sample, from 0 to 1, traces started by the decoder
+    /// are sampled here and the decision is respected by the other services
+    #[serde(default = "def_tracer_sample_ratio")]
+    pub tracer_sample_ratio: f64,
+
+    /// seconds between each export of the service metrics to stdout, if not set metrics are not exported
+    pub metrics_export_interval_seconds: Option<NonZeroU64>,
+
+    /// Format of the logs written to stdout, `pretty` or `json`
+    #[serde(default)]
+    pub log_format: LogFormat,
+
     /// Default port to listen for trackers with the H02 protocol
     #[serde(default = "def_port_h02")]
     pub port_h02: usize,
+
+    /// Seconds a tracker connection can go without sending any data before being closed,
+    /// should be greater than the heartbeat interval of the trackers
+    #[serde(default = "def_tcp_idle_timeout_seconds")]
+    pub tcp_idle_timeout_seconds: u64,
+
+    /// Seconds a tracker connection can stay open before being closed, even if active,
+    /// trackers reconnect afterwards, so this only bounds connections that are never dropped
+    #[serde(default = "def_tcp_max_connection_lifetime_seconds")]
+    pub tcp_max_connection_lifetime_seconds: u64,
+
+    /// If TCP_NODELAY should be set on tracker connections, so acks are sent without
+    /// being delayed by Nagle's algorithm, protocols like GT06 expect prompt acks
+    #[serde(default = "def_tcp_nodelay")]
+    pub tcp_nodelay: bool,
+
+    /// If TCP keepalive probes should be sent on idle tracker connections
+    #[serde(default = "def_tcp_keepalive_enabled")]
+    pub tcp_keepalive_enabled: bool,
+
+    /// Seconds a tracker connection must be idle before keepalive probes are sent
+    #[serde(default = "def_tcp_keepalive_time_seconds")]
+    pub tcp_keepalive_time_seconds: u64,
+
+    /// Seconds between keepalive probes
+    #[serde(default = "def_tcp_keepalive_interval_seconds")]
+    pub tcp_keepalive_interval_seconds: u64,
+
+    /// Unanswered keepalive probes after which the connection is considered dead and dropped
+    #[serde(default = "def_tcp_keepalive_probes")]
+    pub tcp_keepalive_probes: u32,
+
+    /// Amount of the last raw frames of each tracker kept in memory for diagnostics,
+    /// frames might contain personal data so `0` (the default) disables keeping them
+    #[serde(default = "def_raw_frames_per_tracker")]
+    pub raw_frames_per_tracker: usize,
+
+    /// Maximum amount of trackers whose raw frames are kept, the frames of the
+    /// least recently active tracker are dropped to make room for new ones
+    #[serde(default = "def_raw_frames_max_trackers")]
+    pub raw_frames_max_trackers: NonZeroUsize,
+
+    /// Seconds the raw frames of a tracker are kept after its connection is closed
+    #[serde(default = "def_raw_frames_retention_seconds")]
+    pub raw_frames_retention_seconds: u64,
+
+    /// Port of the HTTP server to get the raw frames of trackers, only
+    /// started if keeping raw frames is enabled
+    #[serde(default = "def_port_diagnostics")]
+    pub port_diagnostics: usize,
 }
 
 impl AppConfig {
@@ -57,4 +199,51 @@ impl AppConfig {
             Err(error) => Err(error),
         }
     }
+
+    pub fn rmq_outage_alarm(&self) -> shared::backoff::OutageAlarm {
+        shared::backoff::OutageAlarm {
+            after_failures: self.rmq_outage_alarm_failures,
+            after: Duration::from_secs(self.rmq_outage_alarm_seconds),
+        }
+    }
+
+    pub fn connection_timeouts(&self) -> ConnectionTimeouts {
+        ConnectionTimeouts {
+            idle: Duration::from_secs(self.tcp_idle_timeout_seconds),
+            max_lifetime: Duration::from_secs(self.tcp_max_connection_lifetime_seconds),
+        }
+    }
+
+    pub fn socket_options(&self) -> SocketOptions {
+        let keepalive = self.tcp_keepalive_enabled.then(|| {
+            TcpKeepalive::new()
+                .with_time(Duration::from_secs(self.tcp_keepalive_time_seconds))
+                .with_interval(Duration::from_secs(self.tcp_keepalive_interval_seconds))
+                .with_retries(self.tcp_keepalive_probes)
+        });
+
+        SocketOptions {
+            nodelay: self.tcp_nodelay,
+            keepalive,
+        }
+    }
+
+    pub fn raw_frame_store(&self) -> RawFrameStore {
+        RawFrameStore::new(
+            self.raw_frames_per_tracker,
+            self.raw_frames_max_trackers,
+            Duration::from_secs(self.raw_frames_retention_seconds),
+        )
+    }
+
+    pub fn tracing_opts(&self) -> shared::tracer::TracingOpts {
+        shared::tracer::TracingOpts {
+            enabled: self.tracer_enabled,
+            sample_ratio: self.tracer_sample_ratio,
+            metrics_export_interval: self
+                .metrics_export_interval_seconds
+                .map(|seconds| Duration::from_secs(seconds.get())),
+            ..Default::default()
+        }
+    }
 }
@@ -1,6 +1,6 @@
 use config::AppConfig;
 use rabbitmq::{RmqListener, RmqMessage};
-use server::{h02, listeners};
+use server::{diagnostics, h02, listeners};
 use signal_hook::{
     consts::{SIGINT, SIGTERM},
     iterator::Signals,
@@ -20,7 +20,12 @@ mod tracer;
 async fn main() {
     let config = AppConfig::from_env().expect("failed to load application config");
 
-    tracer::init(config.tracer_service_name.to_owned()).expect("failed to init tracer");
+    tracer::init(
+        config.tracer_service_name.to_owned(),
+        config.log_format,
+        &config.tracing_opts(),
+    )
+    .expect("failed to init tracer");
 
     let mut signals = Signals::new([SIGINT, SIGTERM]).expect("failed to setup signals hook");
 
@@ -42,9 +47,21 @@ async fn main() {
         }
     });
 
+    let raw_frames = Arc::new(config.raw_frame_store());
+
+    if raw_frames.is_enabled() {
+        diagnostics::start_http_server(
+            format!("127.0.0.1:{}", config.port_diagnostics).as_str(),
+            raw_frames.clone(),
+        );
+    }
+
     listeners::start_tcp_listener(
         format!("127.0.0.1:{}", config.port_h02).as_str(),
         sender,
+        config.connection_timeouts(),
+        config.socket_options(),
+        raw_frames,
         h02::stream_handler,
     )
     .await
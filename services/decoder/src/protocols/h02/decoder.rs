@@ -14,6 +14,16 @@ pub enum Message {
     Location(Decoded<LocationMsg>),
 }
 
+impl Message {
+    /// IMEI of the tracker that sent the message
+    pub fn imei(&self) -> &str {
+        match self {
+            Message::Heartbeat(decoded) => &decoded.imei,
+            Message::Location(decoded) => &decoded.imei,
+        }
+    }
+}
+
 pub fn decode(packets: &[u8]) -> Result<Message, String> {
     let packets = from_utf8(packets)
         .or(Err("failed to read packets as utf8"))?
@@ -128,6 +128,7 @@ impl LocationPackets<'_> {
             status: self.parse_status()?,
             direction: self.parse_direction()?,
             timestamp: self.parse_timestamp()?,
+            received_at: Some(Utc::now()),
         })
     }
 
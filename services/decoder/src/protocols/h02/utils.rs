@@ -22,6 +22,22 @@ pub fn get_message_frame(h02_str: String) -> Result<String, String> {
     Ok(h02_str[start..end].to_string())
 }
 
+/// Best effort read of the IMEI of the tracker that sent the H02 packets, that
+/// is the first field of the message (eg: `*HQ,<imei>,V1,...#`), so the sender
+/// of packets that cannot be decoded can still be known.
+pub fn peek_imei(packets: &[u8]) -> Option<String> {
+    let packets = String::from_utf8_lossy(packets);
+
+    let frame = &packets[packets.find("*HQ,")? + 4..];
+    let imei = frame.split([',', '#']).next()?;
+
+    if imei.is_empty() || !imei.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    Some(imei.to_string())
+}
+
 /// Decodes a H02 lat/lng string to its decimal representation.
 ///
 /// A H02 lat/lng string has the first 2 or 3 digits represent the degrees
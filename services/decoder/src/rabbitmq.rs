@@ -5,9 +5,13 @@ use lapin::{
     types::FieldTable,
     BasicProperties, Channel, Connection, ConnectionProperties, ExchangeKind,
 };
-use std::{thread, time};
-use tokio::sync::{mpsc::UnboundedReceiver, RwLock};
-use tracing::{Instrument, Span};
+use shared::backoff::ReconnectBackoff;
+use std::{sync::Mutex, time::Duration};
+use tokio::{
+    sync::{mpsc::UnboundedReceiver, RwLock},
+    time::sleep,
+};
+use tracing::{error, info, Instrument, Span};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
 
 struct Options {
@@ -38,6 +42,10 @@ pub struct RmqListener {
 
     /// channel to receive messages to publish to the tracker events exchange
     receiver: RwLock<UnboundedReceiver<(RmqMessage, tracing::Span)>>,
+
+    /// backoff of the reconnection attempts, kept short as positions received
+    /// while disconnected are lost, but jittered so decoders do not reconnect at once
+    reconnect_backoff: Mutex<ReconnectBackoff>,
 }
 
 #[derive(Debug)]
@@ -64,6 +72,12 @@ impl RmqListener {
             channel: RwLock::new(None),
             connection: RwLock::new(None),
             receiver: RwLock::new(receiver),
+            reconnect_backoff: Mutex::new(ReconnectBackoff::new(
+                "rabbitmq",
+                Duration::from_secs(1),
+                Duration::from_secs(30),
+                cfg.rmq_outage_alarm(),
+            )),
         }
     }
 
@@ -72,11 +86,16 @@ impl RmqListener {
     pub async fn start(&self) {
         loop {
             if let Err(err) = self.run().await {
-                println!("[RMQ] connection error: {}", err)
+                error!(error = %err, "[RMQ] connection error")
             }
 
-            thread::sleep(time::Duration::from_secs(5));
-            println!("[RMQ] reconnecting");
+            let reconnect_delay = self.reconnect_backoff.lock().unwrap().failed();
+
+            sleep(reconnect_delay).await;
+            info!(
+                reconnect_delay_ms = reconnect_delay.as_millis() as u64,
+                "[RMQ] reconnecting"
+            );
         }
     }
 
@@ -93,10 +112,10 @@ impl RmqListener {
             .with_reactor(tokio_reactor_trait::Tokio);
 
         let connection = Connection::connect(&self.options.rmq_uri, conn_options).await?;
-        println!("[RMQ] connected");
+        info!("[RMQ] connected");
 
         let channel = connection.create_channel().await?;
-        println!("[RMQ] channel created");
+        info!("[RMQ] channel created");
 
         let declare_exchange_result = channel
             .exchange_declare(
@@ -119,11 +138,13 @@ impl RmqListener {
         //
         // This is required for the whole application to work so exit on failure
         errors::exit_on_err(declare_exchange_result);
-        println!("[RMQ] tracker events exchange created");
+        info!("[RMQ] tracker events exchange created");
 
         *self.connection.write().await = Some(connection);
         *self.channel.write().await = Some(channel);
 
+        self.reconnect_backoff.lock().unwrap().connected();
+
         while let Some((delivery, span)) = self.receiver.write().await.recv().await {
             if let Err(err) = self.send_message(&delivery).instrument(span).await {
                 match err {
@@ -146,7 +167,7 @@ impl RmqListener {
             }
         }
 
-        println!("[RMQ] receiver channel closed");
+        info!("[RMQ] receiver channel closed");
 
         Ok(())
     }
@@ -179,17 +200,17 @@ impl RmqListener {
 
     /// closes self.channel and self.connection and then sets both to `None`
     pub async fn shutdown(&self) {
-        println!("[RMQ] closing channel");
+        info!("[RMQ] closing channel");
         if let Some(chan) = self.channel.read().await.as_ref() {
             if let Err(chan_close_err) = chan.close(200, "user shutdown").await {
-                println!("[RMQ] failed to close channel: {}", chan_close_err)
+                error!(error = %chan_close_err, "[RMQ] failed to close channel")
             }
         }
 
-        println!("[RMQ] closing connection");
+        info!("[RMQ] closing connection");
         if let Some(conn) = self.connection.read().await.as_ref() {
             if let Err(conn_close_err) = conn.close(200, "user shutdown").await {
-                println!("[RMQ] failed to close connection: {}", conn_close_err)
+                error!(error = %conn_close_err, "[RMQ] failed to close connection")
             }
         }
 
@@ -0,0 +1,313 @@
+//! Tracker diagnostics.
+//!
+//! when enabled the last raw frames sent by each tracker are kept in memory, together with
+//! the result of decoding them, so support can inspect what a misbehaving device is sending.
+//!
+//! frames may contain personal data (eg: positions) so retention is opt-in and bounded, by the
+//! amount of frames per tracker, the amount of trackers (the least recently active trackers are
+//! evicted) and by time, the frames of a tracker are dropped once its connection is closed for
+//! longer than the retention window. only frames of valid IMEIs are kept, so a client sending
+//! garbage cannot fill the store with made up trackers.
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    routing::get,
+    Json, Router,
+};
+use chrono::{DateTime, Utc};
+use lru::LruCache;
+use serde::Serialize;
+use shared::imei::is_valid_tracker_imei;
+use std::{
+    collections::VecDeque,
+    num::NonZeroUsize,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+use tokio::{net::TcpListener, task::JoinHandle, time};
+
+/// A chunk of bytes read from a tracker connection
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct RawFrame {
+    pub received_at: DateTime<Utc>,
+
+    /// the frame bytes, hex encoded
+    pub hex: String,
+
+    /// the frame bytes as text, invalid UTF-8 sequences are replaced
+    pub text: String,
+
+    /// if the frame was decoded successfully
+    pub parsed: bool,
+
+    /// why the frame could not be decoded
+    pub error: Option<String>,
+}
+
+struct TrackerFrames {
+    frames: VecDeque<RawFrame>,
+
+    /// amount of open connections of the tracker, as a misbehaving
+    /// tracker might open a new connection before the old one is closed
+    open_connections: usize,
+
+    /// when the last connection of the tracker was closed
+    closed_at: Option<Instant>,
+}
+
+/// Bounded in memory store of the last raw frames of each tracker, by IMEI
+pub struct RawFrameStore {
+    /// maximum amount of frames kept per tracker, `0` disables the store
+    frames_per_tracker: usize,
+
+    /// how long the frames of a tracker are kept after its connection is closed
+    retention: Duration,
+
+    /// frames by tracker IMEI, bounded by the amount of trackers
+    trackers: Mutex<LruCache<String, TrackerFrames>>,
+}
+
+impl RawFrameStore {
+    pub fn new(frames_per_tracker: usize, max_trackers: NonZeroUsize, retention: Duration) -> Self {
+        Self {
+            frames_per_tracker,
+            retention,
+            trackers: Mutex::new(LruCache::new(max_trackers)),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.frames_per_tracker > 0
+    }
+
+    /// creates the recorder of the frames of a new tracker connection
+    pub fn connection(self: &Arc<Self>) -> ConnectionFrames {
+        ConnectionFrames {
+            store: self.clone(),
+            imei: None,
+        }
+    }
+
+    /// records a frame of a tracker, `new_connection` is if the frame is the first one of
+    /// the tracker on its connection, evicting the least recently active tracker if full
+    fn record(&self, imei: &str, frame: RawFrame, new_connection: bool) {
+        let mut trackers = self.trackers.lock().unwrap();
+
+        let tracker = trackers.get_or_insert_mut(imei.to_string(), || TrackerFrames {
+            frames: VecDeque::with_capacity(self.frames_per_tracker),
+            open_connections: 0,
+            closed_at: None,
+        });
+
+        // a tracker without open connections recording frames was evicted while connected
+        if new_connection || tracker.open_connections == 0 {
+            tracker.open_connections += 1;
+            tracker.closed_at = None;
+        }
+
+        if tracker.frames.len() >= self.frames_per_tracker {
+            tracker.frames.pop_front();
+        }
+
+        tracker.frames.push_back(frame);
+    }
+
+    fn connection_closed(&self, imei: &str) {
+        if let Some(tracker) = self.trackers.lock().unwrap().get_mut(imei) {
+            tracker.open_connections = tracker.open_connections.saturating_sub(1);
+
+            if tracker.open_connections == 0 {
+                tracker.closed_at = Some(Instant::now());
+            }
+        }
+    }
+
+    /// the frames of a tracker, from the oldest to the most recent
+    pub fn get(&self, imei: &str) -> Option<Vec<RawFrame>> {
+        self.prune();
+
+        self.trackers
+            .lock()
+            .unwrap()
+            .peek(imei)
+            .map(|tracker| tracker.frames.iter().cloned().collect())
+    }
+
+    /// drops the frames of trackers disconnected for longer than the retention window
+    pub fn prune(&self) {
+        let mut trackers = self.trackers.lock().unwrap();
+
+        let expired: Vec<String> = trackers
+            .iter()
+            .filter(|(_, tracker)| {
+                tracker
+                    .closed_at
+                    .is_some_and(|closed_at| closed_at.elapsed() >= self.retention)
+            })
+            .map(|(imei, _)| imei.clone())
+            .collect();
+
+        for imei in expired {
+            trackers.pop(&imei);
+        }
+    }
+}
+
+/// Records the frames of a single tracker connection.
+///
+/// frames are stored by the IMEI of the connection tracker, so frames
+/// read before the tracker is identified are not kept, dropping this
+/// marks the connection as closed, starting the retention window.
+pub struct ConnectionFrames {
+    store: Arc<RawFrameStore>,
+    imei: Option<String>,
+}
+
+impl ConnectionFrames {
+    /// records a frame read from the connection, `imei` is the IMEI the frame was sent by
+    /// (if it could be read, invalid IMEIs are ignored) and `error` why it could not be
+    /// decoded (if it failed)
+    pub fn record(&mut self, frame: &[u8], imei: Option<&str>, error: Option<&str>) {
+        if !self.store.is_enabled() {
+            return;
+        }
+
+        let mut identified = false;
+
+        if let Some(imei) = imei.filter(|imei| is_valid_tracker_imei(imei)) {
+            if self.imei.as_deref() != Some(imei) {
+                if let Some(previous) = self.imei.take() {
+                    self.store.connection_closed(&previous);
+                }
+
+                self.imei = Some(imei.to_string());
+                identified = true;
+            }
+        }
+
+        let Some(imei) = &self.imei else {
+            return;
+        };
+
+        self.store.record(
+            imei,
+            RawFrame {
+                received_at: Utc::now(),
+                hex: hex::encode(frame),
+                text: String::from_utf8_lossy(frame).into_owned(),
+                parsed: error.is_none(),
+                error: error.map(String::from),
+            },
+            identified,
+        );
+    }
+}
+
+impl Drop for ConnectionFrames {
+    fn drop(&mut self) {
+        if let Some(imei) = &self.imei {
+            self.store.connection_closed(imei);
+        }
+    }
+}
+
+/// Lists the last raw frames sent by a tracker, from the oldest to the most recent
+async fn get_tracker_frames(
+    State(store): State<Arc<RawFrameStore>>,
+    Path(imei): Path<String>,
+) -> Result<Json<Vec<RawFrame>>, (StatusCode, &'static str)> {
+    store
+        .get(&imei)
+        .map(Json)
+        .ok_or((StatusCode::NOT_FOUND, "no frames retained for this tracker"))
+}
+
+/// Starts the diagnostics HTTP server and a task that periodically prunes the store
+pub fn start_http_server(addr: &str, store: Arc<RawFrameStore>) -> JoinHandle<()> {
+    let addr = addr.to_string();
+
+    let pruned_store = store.clone();
+
+    tokio::spawn(async move {
+        let mut interval = time::interval(Duration::from_secs(60));
+
+        loop {
+            interval.tick().await;
+            pruned_store.prune();
+        }
+    });
+
+    tokio::spawn(async move {
+        let router = Router::new()
+            .route("/trackers/:imei/frames", get(get_tracker_frames))
+            .with_state(store);
+
+        let listener = TcpListener::bind(addr.clone())
+            .await
+            .expect("failed to start diagnostics HTTP listener");
+
+        println!("[HTTP] diagnostics server started at: {}", addr);
+
+        axum::serve(listener, router)
+            .await
+            .expect("diagnostics HTTP server failed");
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const IMEI: &str = "490154203237518";
+    const OTHER_IMEI: &str = "356938035643809";
+
+    fn store(max_trackers: usize) -> Arc<RawFrameStore> {
+        Arc::new(RawFrameStore::new(
+            2,
+            NonZeroUsize::new(max_trackers).unwrap(),
+            Duration::from_secs(60),
+        ))
+    }
+
+    #[test]
+    fn keeps_the_last_frames_of_each_tracker() {
+        let store = store(10);
+        let mut connection = store.connection();
+
+        connection.record(b"first", Some(IMEI), None);
+        connection.record(b"second", None, Some("invalid"));
+        connection.record(b"third", Some(IMEI), None);
+
+        let frames = store.get(IMEI).unwrap();
+
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].text, "second");
+        assert!(!frames[0].parsed);
+        assert_eq!(frames[1].text, "third");
+    }
+
+    #[test]
+    fn ignores_frames_with_invalid_imeis() {
+        let store = store(10);
+        let mut connection = store.connection();
+
+        connection.record(b"frame", Some("490154203237510"), None);
+        connection.record(b"frame", Some("not-a-imei"), None);
+
+        assert!(store.get("490154203237510").is_none());
+        assert!(store.get("not-a-imei").is_none());
+    }
+
+    #[test]
+    fn evicts_the_least_recently_active_tracker() {
+        let store = store(1);
+
+        store.connection().record(b"frame", Some(IMEI), None);
+        store.connection().record(b"frame", Some(OTHER_IMEI), None);
+
+        assert!(store.get(IMEI).is_none());
+        assert!(store.get(OTHER_IMEI).is_some());
+    }
+}
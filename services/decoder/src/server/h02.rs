@@ -2,12 +2,16 @@ use crate::protocols::common::Decoded;
 use crate::protocols::h02;
 use crate::protocols::h02::decoder::Message;
 use crate::rabbitmq::RmqMessage;
+use crate::server::diagnostics::RawFrameStore;
 use crate::server::listeners::{BUFFER_SIZE, INVALID_PACKET_LIMIT};
 use serde::Serialize;
+use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::sync::mpsc::UnboundedSender;
-use tracing::{error, info_span, span, Level};
+use tokio::time;
+use tracing::{error, info, info_span, span, Level};
 
 type RmqMsgSender = UnboundedSender<(RmqMessage, tracing::Span)>;
 
@@ -46,14 +50,33 @@ fn handle_decoded_message(message: Message, sender: &RmqMsgSender) -> Option<Box
     }
 }
 
-pub async fn stream_handler(stream: TcpStream, sender: RmqMsgSender) {
+pub async fn stream_handler(
+    stream: TcpStream,
+    sender: RmqMsgSender,
+    idle_timeout: Duration,
+    raw_frames: Arc<RawFrameStore>,
+) {
     let mut buffer = vec![0; BUFFER_SIZE];
 
+    let mut connection_frames = raw_frames.connection();
+
     let (mut reader, mut writer) = io::split(stream);
 
     let mut invalid_packets_cnt: usize = 0;
 
-    while let Ok(n) = reader.read(&mut buffer).await {
+    loop {
+        let n = match time::timeout(idle_timeout, reader.read(&mut buffer)).await {
+            Ok(Ok(n)) => n,
+            Ok(Err(_)) => break,
+            Err(_) => {
+                info!(
+                    "closing connection idle for {} seconds",
+                    idle_timeout.as_secs()
+                );
+                break;
+            }
+        };
+
         if n == 0 {
             // EOF
             break;
@@ -72,6 +95,15 @@ pub async fn stream_handler(stream: TcpStream, sender: RmqMsgSender) {
 
         let decode_result = h02::decoder::decode(packets);
 
+        match &decode_result {
+            Ok(msg) => connection_frames.record(packets, Some(msg.imei()), None),
+            Err(err_msg) => connection_frames.record(
+                packets,
+                h02::utils::peek_imei(packets).as_deref(),
+                Some(err_msg),
+            ),
+        }
+
         match decode_result {
             Ok(msg) => {
                 if let Some(response_to_tracker) = handle_decoded_message(msg, &sender) {
@@ -1,10 +1,22 @@
+use super::diagnostics::RawFrameStore;
 use crate::rabbitmq::RmqMessage;
-use std::{future::Future, marker::Send};
+use socket2::{SockRef, TcpKeepalive};
+use std::{
+    future::Future,
+    marker::Send,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use tokio::{
     net::{TcpListener, TcpStream},
     sync::mpsc::UnboundedSender,
     task::JoinHandle,
+    time,
 };
+use tracing::{debug, info, warn};
 
 /// The buffer size to be used when reading tracker connections.
 ///
@@ -17,16 +29,57 @@ pub const BUFFER_SIZE: usize = 512;
 /// before its connection should be dropped
 pub const INVALID_PACKET_LIMIT: usize = 10;
 
-/// A TCP handle receives the tcp stream to handle and a unbounded sender
+/// Timeouts of the tracker TCP connections, so connections of dead trackers
+/// (eg: half open connections behind flaky cellular links) are not kept forever
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionTimeouts {
+    /// connections that do not send any data for this long are closed
+    pub idle: Duration,
+
+    /// connections open for this long are closed, even if active
+    pub max_lifetime: Duration,
+}
+
+/// Options set on every accepted tracker TCP socket
+#[derive(Debug, Clone)]
+pub struct SocketOptions {
+    /// disables Nagle's algorithm, so small writes such as protocol acks are sent right away
+    pub nodelay: bool,
+
+    /// keepalive probes to send on idle connections, so connections silently dropped
+    /// by carriers or NATs are detected before the idle timeout, `None` disables them
+    pub keepalive: Option<TcpKeepalive>,
+}
+
+impl SocketOptions {
+    fn apply(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.nodelay)?;
+
+        if let Some(keepalive) = &self.keepalive {
+            SockRef::from(stream).set_tcp_keepalive(keepalive)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A TCP handle receives the tcp stream to handle, a unbounded sender
 /// to send the decoded tracker events sent over the TCP connection (such
-/// as a new position or tracker command response)
-type TcpHandler<R> = fn(TcpStream, UnboundedSender<(RmqMessage, tracing::Span)>) -> R;
+/// as a new position or tracker command response), the idle timeout and
+/// the store to record the raw frames of the connection on, returning when
+/// the connection should be closed
+type TcpHandler<R> =
+    fn(TcpStream, UnboundedSender<(RmqMessage, tracing::Span)>, Duration, Arc<RawFrameStore>) -> R;
 
 /// Start a new tokio task that binds a TcpListener to addr and pass all
-/// incoming connections to the the handler on another task.
+/// incoming connections to the the handler on another task, closing the
+/// connections that exceed the max lifetime.
 pub fn start_tcp_listener(
     addr: &str,
     sender: UnboundedSender<(RmqMessage, tracing::Span)>,
+    timeouts: ConnectionTimeouts,
+    socket_options: SocketOptions,
+    raw_frames: Arc<RawFrameStore>,
     handler: TcpHandler<impl Future<Output = ()> + 'static + Send>,
 ) -> JoinHandle<()> {
     let addr = addr.to_string();
@@ -38,8 +91,30 @@ pub fn start_tcp_listener(
 
         println!("[TCP] listener started at: {}", addr);
 
-        while let Ok((stream, _)) = listener.accept().await {
-            tokio::spawn(handler(stream, sender.clone()));
+        let active_connections = Arc::new(AtomicUsize::new(0));
+
+        while let Ok((stream, peer_addr)) = listener.accept().await {
+            if let Err(error) = socket_options.apply(&stream) {
+                warn!(%peer_addr, %error, "failed to set socket options");
+            }
+
+            let active_connections = active_connections.clone();
+            let connection = handler(stream, sender.clone(), timeouts.idle, raw_frames.clone());
+
+            active_connections.fetch_add(1, Ordering::Relaxed);
+
+            tokio::spawn(async move {
+                // dropping the handler future drops the stream, closing the connection
+                if time::timeout(timeouts.max_lifetime, connection)
+                    .await
+                    .is_err()
+                {
+                    info!(%peer_addr, "closing connection that reached the max lifetime");
+                }
+
+                let remaining = active_connections.fetch_sub(1, Ordering::Relaxed) - 1;
+                debug!(%peer_addr, active_connections = remaining, "connection closed");
+            });
         }
 
         println!("[TCP] listener at: {} stopped", addr);
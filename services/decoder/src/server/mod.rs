@@ -1,2 +1,3 @@
+pub mod diagnostics;
 pub mod h02;
 pub mod listeners;
@@ -1,20 +1,39 @@
+use shared::tracer::{self, LogFormat, TracingOpts};
 use tracing::subscriber::SetGlobalDefaultError;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, Registry};
 
-pub fn init(service_name: String) -> Result<(), SetGlobalDefaultError> {
+/// Initializes the decoder tracing, the sampling decision taken here is propagated
+/// on the headers of the published tracker events, so the consumers keep it.
+pub fn init(
+    service_name: String,
+    log_format: LogFormat,
+    opts: &TracingOpts,
+) -> Result<(), SetGlobalDefaultError> {
+    let stdout_layer = tracer::stdout_layer(&service_name, log_format);
+
+    if let Some(interval) = opts.metrics_export_interval {
+        tracer::init_metrics(&service_name, interval).expect("failed to initialize metrics");
+    }
+
     opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
 
-    let tracer = opentelemetry_jaeger::new_agent_pipeline()
-        .with_service_name(service_name)
-        .with_auto_split_batch(true)
-        .install_batch(opentelemetry::runtime::Tokio)
-        .expect("failed to initialize tracer");
+    let telemetry = if opts.enabled {
+        let tracer = opentelemetry_jaeger::new_agent_pipeline()
+            .with_service_name(service_name)
+            .with_auto_split_batch(true)
+            .with_trace_config(opts.trace_config())
+            .install_batch(opentelemetry::runtime::Tokio)
+            .expect("failed to initialize tracer");
+
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
+    } else {
+        None
+    };
 
-    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
-    let subscriber = Registry::default().with(telemetry);
+    let subscriber = Registry::default().with(stdout_layer).with(telemetry);
 
     tracing::subscriber::set_global_default(subscriber)?;
 
-    println!("[TRACER] initialized");
+    println!("[TRACER] initialized, exporting spans: {}", opts.enabled);
     Ok(())
 }
@@ -1,6 +1,8 @@
-use std::sync::OnceLock;
+use std::{num::NonZeroU64, sync::OnceLock, time::Duration};
 
 use serde::Deserialize;
+use shared::tracer::LogFormat;
+use validator::Validate;
 
 fn def_app_debug() -> bool {
     false
@@ -11,7 +13,15 @@ fn def_rmq_uri() -> String {
 }
 
 fn def_rmq_queue() -> String {
-    String::from("mailer")
+    String::from("mailer_requests")
+}
+
+fn def_rmq_dead_letter_exchange() -> String {
+    String::from("mailer_dead_letter")
+}
+
+fn def_rmq_dead_letter_queue() -> String {
+    String::from("mailer_dead_letter")
 }
 
 fn def_rmq_consumer_tag() -> String {
@@ -42,20 +52,92 @@ fn def_aws_ses_max_emails_per_second() -> u32 {
     1
 }
 
+fn def_org_max_emails_per_minute() -> u32 {
+    60
+}
+
+fn def_max_concurrent_send_email_ops() -> usize {
+    32
+}
+
+fn def_max_recipients_per_request() -> usize {
+    1000
+}
+
+fn def_max_attachments_bytes() -> usize {
+    7 * 1024 * 1024
+}
+
+fn def_rmq_deferred_queue() -> String {
+    String::from("mailer_deferred")
+}
+
+fn def_deferral_check_interval_seconds() -> u32 {
+    60
+}
+
+fn def_max_deferral_hours() -> u32 {
+    72
+}
+
+fn def_rmq_prefetch() -> u16 {
+    10
+}
+
+fn def_rmq_outage_alarm_failures() -> u32 {
+    10
+}
+
+fn def_rmq_outage_alarm_seconds() -> u64 {
+    5 * 60
+}
+
 fn def_http_port() -> u16 {
     3005
 }
 
-#[derive(Deserialize, Debug)]
+fn def_tracer_enabled() -> bool {
+    true
+}
+
+fn def_tracer_sample_ratio() -> f64 {
+    1.0
+}
+
+fn def_tracer_parent_based() -> bool {
+    true
+}
+
+#[derive(Deserialize, Debug, Validate)]
 pub struct AppConfig {
     /// If the application should be run in debug mode and print additional info to stdout
     #[serde(default = "def_app_debug")]
+    #[allow(dead_code)]
     pub app_debug: bool,
 
     /// The service name to be used on the tracing spans
     #[serde(default = "def_tracer_service_name")]
     pub tracer_service_name: String,
 
+    /// If tracing spans should be exported to jaeger
+    #[serde(default = "def_tracer_enabled")]
+    pub tracer_enabled: bool,
+
+    /// Ratio of traces to sample, from 0 to 1
+    #[serde(default = "def_tracer_sample_ratio")]
+    pub tracer_sample_ratio: f64,
+
+    /// If the sampling decision of parent spans (eg: from the api service) should be respected
+    #[serde(default = "def_tracer_parent_based")]
+    pub tracer_parent_based: bool,
+
+    /// seconds between each export of the service metrics to stdout, if not set metrics are not exported
+    pub metrics_export_interval_seconds: Option<NonZeroU64>,
+
+    /// Format of the logs written to stdout, `pretty` or `json`
+    #[serde(default)]
+    pub log_format: LogFormat,
+
     /// Rabbitmq uri
     #[serde(default = "def_rmq_uri")]
     pub rmq_uri: String,
@@ -68,6 +150,46 @@ pub struct AppConfig {
     #[serde(default = "def_rmq_consumer_tag")]
     pub rmq_consumer_tag: String,
 
+    /// Name of the exchange deliveries of the mailer queue are dead lettered to, such as
+    /// deliveries that can never be handled (eg: malformed) or that expired on the queue
+    #[serde(default = "def_rmq_dead_letter_exchange")]
+    pub rmq_dead_letter_exchange: String,
+
+    /// Name of the queue bound to the dead letter exchange, storing the dead lettered deliveries
+    #[serde(default = "def_rmq_dead_letter_queue")]
+    pub rmq_dead_letter_queue: String,
+
+    /// Name of the queue email requests are parked on until they can be sent (see `not_before`
+    /// on the email request), deferred requests are persisted so they survive restarts
+    #[serde(default = "def_rmq_deferred_queue")]
+    pub rmq_deferred_queue: String,
+
+    /// Seconds deferred email requests wait on the deferred queue before being checked again,
+    /// requests are sent at most this late after their `not_before` time
+    #[serde(default = "def_deferral_check_interval_seconds")]
+    pub deferral_check_interval_seconds: u32,
+
+    /// Maximum hours a email request can be deferred for, requests with a later `not_before`
+    /// are rejected as they are most likely a mistake and would be parked indefinitely
+    #[serde(default = "def_max_deferral_hours")]
+    pub max_deferral_hours: u32,
+
+    /// Consecutive failed RabbitMQ reconnection attempts after which the outage is logged as a
+    /// error on every attempt and `GET /ready` fails, the service keeps retrying regardless
+    #[serde(default = "def_rmq_outage_alarm_failures")]
+    pub rmq_outage_alarm_failures: u32,
+
+    /// Seconds the RabbitMQ connection can be down before the outage is reported, same
+    /// as `rmq_outage_alarm_failures`, whichever is reached first
+    #[serde(default = "def_rmq_outage_alarm_seconds")]
+    pub rmq_outage_alarm_seconds: u64,
+
+    /// Consumer prefetch count, the maximum amount of email requests delivered to the service
+    /// and not yet acknowledged, as requests can take a while to be sent due to the SES rate
+    /// limit a prefetch of 1 is too low, but a unlimited one (`0`) could exhaust the service memory
+    #[serde(default = "def_rmq_prefetch")]
+    pub rmq_prefetch: u16,
+
     /// Name of the exchange to publish email events (clicks, opens, etc)
     #[serde(default = "def_email_events_exchange")]
     pub rmq_email_events_exchange: String,
@@ -88,8 +210,38 @@ pub struct AppConfig {
     /// defaults to 1, the value for sandbox accounts
     /// see: https://docs.aws.amazon.com/ses/latest/dg/manage-sending-quotas.html
     #[serde(default = "def_aws_ses_max_emails_per_second")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
     pub aws_ses_max_emails_per_second: u32,
 
+    /// Maximum amount of sendEmail operations per minute for a single organization, layered
+    /// under `aws_ses_max_emails_per_second` so a organization sending lots of emails cannot
+    /// exhaust the SES quota for the others, requests without a organization are not limited.
+    ///
+    /// requests of organizations without budget for all their operations are deferred until
+    /// they have it, requests with more operations than this are sent once the budget is full
+    #[serde(default = "def_org_max_emails_per_minute")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub org_max_emails_per_minute: u32,
+
+    /// Maximum amount of sendEmail operations running at once, regardless of the SES rate
+    /// limit, emails of a request are only built when there is room for them to be sent,
+    /// limiting the memory used by requests with lots of recipients
+    #[serde(default = "def_max_concurrent_send_email_ops")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub max_concurrent_send_email_ops: usize,
+
+    /// Maximum amount of recipients of a single email request, requests with more recipients
+    /// are rejected to protect against accidental mass sends exhausting the SES quota
+    #[serde(default = "def_max_recipients_per_request")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub max_recipients_per_request: usize,
+
+    /// Maximum total size in bytes of the attachments of a single email request, the encoded
+    /// raw message is about a third larger and must stay within the SES message size limit
+    #[serde(default = "def_max_attachments_bytes")]
+    #[validate(range(min = 1, message = "must be greater than 0"))]
+    pub max_attachments_bytes: usize,
+
     #[serde(default = "def_http_port")]
     pub http_port: u16,
 
@@ -100,11 +252,38 @@ pub struct AppConfig {
 
 impl AppConfig {
     pub fn from_env() -> AppConfig {
-        match envy::from_env::<AppConfig>() {
+        let config = match envy::from_env::<AppConfig>() {
             Ok(config) => config,
             Err(error) => {
                 panic!("[CFG] failed to load application config, {:#?}", error)
             }
+        };
+
+        if let Err(errors) = config.validate() {
+            panic!(
+                "[CFG] invalid application config\n{}",
+                shared::config::describe_validation_errors(&errors)
+            )
+        }
+
+        config
+    }
+
+    pub fn rmq_outage_alarm(&self) -> shared::backoff::OutageAlarm {
+        shared::backoff::OutageAlarm {
+            after_failures: self.rmq_outage_alarm_failures,
+            after: Duration::from_secs(self.rmq_outage_alarm_seconds),
+        }
+    }
+
+    pub fn tracing_opts(&self) -> shared::tracer::TracingOpts {
+        shared::tracer::TracingOpts {
+            enabled: self.tracer_enabled,
+            sample_ratio: self.tracer_sample_ratio,
+            parent_based: self.tracer_parent_based,
+            metrics_export_interval: self
+                .metrics_export_interval_seconds
+                .map(|seconds| Duration::from_secs(seconds.get())),
         }
     }
 }
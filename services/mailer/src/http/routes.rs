@@ -11,8 +11,10 @@ use axum::{
     http::{Request, StatusCode},
     middleware::Next,
     response::Response,
+    Json,
 };
 use convert_case::{Case, Casing};
+use serde::Serialize;
 use tracing::{error, event, Level};
 
 #[tracing::instrument(skip_all)]
@@ -129,3 +131,40 @@ pub async fn check_aws_sns_arn_middleware(
 
     Ok(nxt.run(req).await)
 }
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct DeadLetterQueueStats {
+    /// amount of messages currently on the dead letter queue
+    message_count: u32,
+
+    /// amount of deliveries this instance rejected to the dead letter queue since it started
+    rejected_since_start: u64,
+}
+
+/// Returns the amount of dead lettered deliveries of the mailer queue
+#[tracing::instrument(skip_all)]
+pub async fn get_dead_letter_queue_stats(
+    State(state): State<AppState>,
+) -> Result<Json<DeadLetterQueueStats>, (StatusCode, String)> {
+    let message_count = state
+        .mailer_rmq
+        .dead_letter_queue_message_count()
+        .await
+        .map_err(|e| (StatusCode::SERVICE_UNAVAILABLE, e))?;
+
+    Ok(Json(DeadLetterQueueStats {
+        message_count,
+        rejected_since_start: state.mailer_rmq.dead_lettered_count(),
+    }))
+}
+
+/// Readiness probe, fails while the RabbitMQ connection is down for longer than
+/// the configured outage alarm, so the instance is reported as unhealthy
+pub async fn get_readiness(State(state): State<AppState>) -> (StatusCode, &'static str) {
+    if state.mailer_rmq.is_connection_critical() {
+        return (StatusCode::SERVICE_UNAVAILABLE, "rabbitmq connection down");
+    }
+
+    (StatusCode::OK, "ok")
+}
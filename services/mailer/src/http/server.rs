@@ -1,11 +1,13 @@
 use crate::{
     config::app_config,
-    http::routes::{check_aws_sns_arn_middleware, handle_ses_event},
+    http::routes::{
+        check_aws_sns_arn_middleware, get_dead_letter_queue_stats, get_readiness, handle_ses_event,
+    },
     queue::MailerRabbitmq,
 };
 use axum::{
     middleware::{self},
-    routing::post,
+    routing::{get, post},
     Router,
 };
 use std::{
@@ -33,6 +35,8 @@ pub async fn start(mailer_rmq: Arc<MailerRabbitmq>) {
             state.clone(),
             check_aws_sns_arn_middleware,
         ))
+        .route("/dead-letter-queue", get(get_dead_letter_queue_stats))
+        .route("/ready", get(get_readiness))
         .with_state(state);
 
     let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), cfg.http_port);
@@ -2,25 +2,37 @@ use crate::{
     config::app_config,
     queue::controller::dto::events::EmailSendingErrorEvent,
     queue::{self},
+    raw_email::{Attachment, RawEmail},
 };
 use aws_sdk_sesv2::{
     config::Region,
     error::SdkError,
     operation::send_email::{builders::SendEmailFluentBuilder, SendEmailError, SendEmailOutput},
-    types::{Body, Content, Destination, EmailContent, Message, MessageTag},
+    primitives::Blob,
+    types::{Body, Content, Destination, EmailContent, Message, MessageTag, RawMessage},
     Client,
 };
+use chrono::{DateTime, Utc};
 use governor::{
-    clock::{QuantaClock, QuantaInstant},
+    clock::{Clock, QuantaClock, QuantaInstant},
     middleware::NoOpMiddleware,
-    state::{InMemoryState, NotKeyed},
-    Quota,
+    state::{keyed::DefaultKeyedStateStore, InMemoryState, NotKeyed},
+    NegativeMultiDecision, Quota,
 };
 use handlebars::Handlebars;
-use shared::dto::mailer::EmailRecipient;
-use std::{num::NonZeroU32, sync::Arc, thread, time};
-use tokio::task::JoinSet;
-use tracing::{error, event, Instrument, Level};
+use lru::LruCache;
+use shared::dto::mailer::{EmailAttachment, EmailRecipient};
+use std::{
+    num::{NonZeroU32, NonZeroUsize},
+    sync::{Arc, Mutex},
+    thread,
+    time::{self, Duration, Instant},
+};
+use tokio::{
+    sync::{OwnedSemaphorePermit, Semaphore},
+    task::JoinSet,
+};
+use tracing::{error, event, warn, Instrument, Level};
 use uuid::Uuid;
 
 /// see: https://docs.aws.amazon.com/ses/latest/APIReference/API_SendEmail.html
@@ -33,6 +45,20 @@ static MAX_EMAIL_RETRY_ATTEMPT: u8 = 4;
 
 static RETRY_ATTEMPTS_INTERVAL: u8 = 5;
 
+/// How long the verification status of a sender identity is cached, so sending
+/// emails from the same sender does not hit SES for every email request
+const SENDER_IDENTITY_STATUS_TTL: Duration = Duration::from_secs(60);
+
+/// How often organizations that did not send emails recently are removed from the
+/// organization rate limiter, so its memory does not grow with every organization
+const ORG_RATE_LIMITER_RETAIN_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Maximum amount of sender identities whose status is cached, the least recently used is evicted
+const SENDER_IDENTITY_CACHE_CAPACITY: NonZeroUsize = match NonZeroUsize::new(1000) {
+    Some(capacity) => capacity,
+    None => unreachable!(),
+};
+
 #[derive(Debug)]
 pub struct SendEmailOptions {
     pub to: Vec<EmailRecipient>,
@@ -42,6 +68,9 @@ pub struct SendEmailOptions {
     pub body_html: Option<String>,
     pub reply_to_addresses: Option<Vec<String>>,
 
+    /// files to attach to the email, emails with attachments are sent as raw MIME messages
+    pub attachments: Vec<EmailAttachment>,
+
     /// Uuid of the email request, used to publish error/finished events when all the deliveries for the request finish
     pub uuid: Uuid,
 
@@ -55,23 +84,185 @@ pub struct SendEmailOptions {
     pub track_events: bool,
 }
 
+/// Error when sending the emails of a request
+#[derive(Debug)]
+pub enum SendEmailsError {
+    /// the emails could not be built from the request, retrying it would fail again
+    Invalid(String),
+
+    /// no email could be sent due to errors that might not happen again, such as SES
+    /// throttling or network errors, since no email was sent the request can be retried
+    Transient(String),
+}
+
+/// errors building the emails are the only ones described as strings
+impl From<String> for SendEmailsError {
+    fn from(err: String) -> Self {
+        SendEmailsError::Invalid(err)
+    }
+}
+
 type RateLimiter =
     governor::RateLimiter<NotKeyed, InMemoryState, QuantaClock, NoOpMiddleware<QuantaInstant>>;
 
+/// rate limiter keyed by the organization id
+type OrgRateLimiter = governor::RateLimiter<
+    i32,
+    DefaultKeyedStateStore<i32>,
+    QuantaClock,
+    NoOpMiddleware<QuantaInstant>,
+>;
+
 pub struct Mailer {
     pub mailer_rmq: Arc<queue::MailerRabbitmq>,
     pub aws_client: Client,
     pub rate_limiter: Arc<RateLimiter>,
+    /// limits the emails sent by each organization, see `ORG_MAX_EMAILS_PER_MINUTE`
+    pub org_rate_limiter: Arc<OrgRateLimiter>,
+    /// clock of `org_rate_limiter`, to know when a organization will have budget again
+    org_rate_limiter_clock: QuantaClock,
+    /// limits the amount of send email tasks running at once, so requests with
+    /// thousands of recipients do not keep thousands of emails in memory
+    pub send_permits: Arc<Semaphore>,
+    /// maximum amount of recipients of a email request, regardless of how they are chunked
+    pub max_recipients_per_request: usize,
+    /// maximum total size in bytes of the (decoded) attachments of a email request
+    pub max_attachments_bytes: usize,
+    /// maximum time a email request can be deferred for
+    pub max_deferral: chrono::Duration,
     pub default_sender: String,
     pub aws_ses_tracking_config_set: String,
+    /// identity -> (if it is verified for sending, time the status was fetched)
+    sender_identity_cache: Mutex<LruCache<String, (bool, Instant)>>,
+}
+
+/// if the SES error might not happen on a later attempt
+fn is_transient_ses_error(err: &SdkError<SendEmailError>) -> bool {
+    match err {
+        SdkError::ServiceError(service_err) => service_err.err().is_too_many_requests_exception(),
+        SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) => {
+            true
+        }
+        _ => false,
+    }
+}
+
+/// amount of recipients of each send email operation for recipients without replacements, if
+/// tracking events emails are sent individually so events can be traced to the recipient
+fn recipients_per_send_op(track_events: bool) -> usize {
+    if track_events {
+        1
+    } else {
+        MAX_RECIPIENTS_PER_SEND_EMAIL_OP
+    }
+}
+
+/// amount of send email operations needed to send a email to the recipients, see `send_emails`
+pub fn send_operations_count(to: &[EmailRecipient], track_events: bool) -> usize {
+    let with_replacements = to.iter().filter(|r| r.has_replacements()).count();
+    let without_replacements = to.len() - with_replacements;
+
+    with_replacements + without_replacements.div_ceil(recipients_per_send_op(track_events))
+}
+
+/// Takes the budget of `operations` send email operations of the organization, if it does not
+/// have the budget returns how long until it does, without taking any of it.
+///
+/// requests with more operations than the whole budget take all of it instead, so they are
+/// sent once the organization budget is full and the following requests wait for it to refill
+fn take_org_budget(
+    limiter: &OrgRateLimiter,
+    clock: &QuantaClock,
+    organization_id: i32,
+    operations: usize,
+) -> Result<(), Duration> {
+    let operations = u32::try_from(operations).unwrap_or(u32::MAX);
+
+    let Some(operations) = NonZeroU32::new(operations) else {
+        return Ok(());
+    };
+
+    let decision = match limiter.check_key_n(&organization_id, operations) {
+        Err(NegativeMultiDecision::InsufficientCapacity(capacity)) => {
+            match NonZeroU32::new(capacity) {
+                Some(capacity) => limiter.check_key_n(&organization_id, capacity),
+                None => return Ok(()),
+            }
+        }
+        decision => decision,
+    };
+
+    match decision {
+        Err(NegativeMultiDecision::BatchNonConforming(_, not_until)) => {
+            Err(not_until.wait_time_from(clock.now()))
+        }
+        _ => Ok(()),
+    }
 }
 
 fn to_utf8_content(input: &str) -> Result<Content, aws_sdk_sesv2::error::BuildError> {
     Content::builder().data(input).charset("UTF-8").build()
 }
 
-#[tracing::instrument(skip(rate_limiter, send_email_op, server))]
+/// size of the bytes encoded by a base64 string, without decoding it
+fn base64_decoded_len(encoded: &str) -> usize {
+    let padding = encoded.bytes().rev().take_while(|b| *b == b'=').count();
+
+    (encoded.len() / 4 * 3).saturating_sub(padding)
+}
+
+/// Email content shared by every send email operation of a request
+struct EmailParts<'a> {
+    from: &'a str,
+    subject: &'a str,
+    text: &'a str,
+    reply_to: Option<&'a [String]>,
+    attachments: &'a [Attachment],
+}
+
+impl EmailParts<'_> {
+    /// builds the content of a email to the recipients, emails without attachments are
+    /// sent as a SES simple message, since it does not support attachments emails with
+    /// them are sent as a raw MIME message instead, the email tags and configuration set
+    /// are set on the send email operation so tracking works the same for both
+    fn content(&self, html: &str, to: &[String]) -> Result<EmailContent, String> {
+        if self.attachments.is_empty() {
+            let body = Body::builder()
+                .html(to_utf8_content(html).map_err(|_| String::from("failed to build html"))?)
+                .text(to_utf8_content(self.text).map_err(|_| String::from("failed to build text"))?)
+                .build();
+
+            let subject = to_utf8_content(self.subject)
+                .map_err(|_| String::from("failed to build subject"))?;
+
+            let msg = Message::builder().subject(subject).body(body).build();
+
+            return Ok(EmailContent::builder().simple(msg).build());
+        }
+
+        let mime = RawEmail {
+            from: self.from,
+            to,
+            reply_to: self.reply_to,
+            subject: self.subject,
+            html,
+            text: self.text,
+            attachments: self.attachments,
+        }
+        .to_mime();
+
+        let raw = RawMessage::builder()
+            .data(Blob::new(mime))
+            .build()
+            .map_err(|_| String::from("failed to build raw message"))?;
+
+        Ok(EmailContent::builder().raw(raw).build())
+    }
+}
+
+#[tracing::instrument(skip(_permit, rate_limiter, send_email_op, server))]
 async fn send_with_rate_limiter(
+    _permit: OwnedSemaphorePermit,
     rate_limiter: Arc<RateLimiter>,
     send_email_op: SendEmailFluentBuilder,
     request_uuid: uuid::Uuid,
@@ -117,9 +308,32 @@ impl Mailer {
             .load()
             .await;
 
-        let time_limit = NonZeroU32::new(cfg.aws_ses_max_emails_per_second).unwrap();
+        let time_limit = NonZeroU32::new(cfg.aws_ses_max_emails_per_second)
+            .expect("AWS_SES_MAX_EMAILS_PER_SECOND is validated to be greater than 0");
         let rate_limiter = governor::RateLimiter::direct(Quota::per_second(time_limit));
 
+        let org_limit = NonZeroU32::new(cfg.org_max_emails_per_minute)
+            .expect("ORG_MAX_EMAILS_PER_MINUTE is validated to be greater than 0");
+        let org_rate_limiter_clock = QuantaClock::default();
+        let org_rate_limiter = Arc::new(governor::RateLimiter::dashmap_with_clock(
+            Quota::per_minute(org_limit),
+            &org_rate_limiter_clock,
+        ));
+
+        let retained_org_rate_limiter = org_rate_limiter.clone();
+
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(ORG_RATE_LIMITER_RETAIN_INTERVAL);
+
+            loop {
+                interval.tick().await;
+                retained_org_rate_limiter.retain_recent();
+                retained_org_rate_limiter.shrink_to_fit();
+            }
+        });
+
+        let max_concurrent_sends = cfg.max_concurrent_send_email_ops;
+
         let client = Client::new(&aws_cfg);
 
         // quick check to test if the SES client is valid
@@ -134,14 +348,177 @@ impl Mailer {
         Mailer {
             mailer_rmq,
             rate_limiter: Arc::new(rate_limiter),
+            org_rate_limiter,
+            org_rate_limiter_clock,
+            send_permits: Arc::new(Semaphore::new(max_concurrent_sends)),
+            max_recipients_per_request: cfg.max_recipients_per_request,
+            max_attachments_bytes: cfg.max_attachments_bytes,
+            max_deferral: chrono::Duration::hours(cfg.max_deferral_hours.into()),
             aws_client: client,
             default_sender: cfg.app_default_email_sender.to_owned(),
             aws_ses_tracking_config_set: cfg.aws_ses_tracking_config_set.to_owned(),
+            sender_identity_cache: Mutex::new(LruCache::new(SENDER_IDENTITY_CACHE_CAPACITY)),
+        }
+    }
+
+    /// Takes the organization budget for the send email operations of a request, if the
+    /// organization does not have the budget returns when it will, see `take_org_budget`.
+    ///
+    /// requests without a organization are not limited
+    pub fn take_org_budget(
+        &self,
+        organization_id: Option<i32>,
+        operations: usize,
+    ) -> Result<(), DateTime<Utc>> {
+        let Some(organization_id) = organization_id else {
+            return Ok(());
+        };
+
+        take_org_budget(
+            &self.org_rate_limiter,
+            &self.org_rate_limiter_clock,
+            organization_id,
+            operations,
+        )
+        .map_err(|wait| Utc::now() + chrono::Duration::from_std(wait).unwrap_or_default())
+    }
+
+    /// Waits until less than `MAX_CONCURRENT_SEND_EMAIL_OPS` send email tasks are running,
+    /// the task holding the returned permit counts as running until the permit is dropped
+    async fn acquire_send_permit(&self) -> OwnedSemaphorePermit {
+        self.send_permits
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("send email semaphore is never closed")
+    }
+
+    /// Checks if a email request is within the recipients limit, the limit applies to the
+    /// total recipients of the request, so requests can not dodge it by being chunked
+    pub fn check_recipients_limit(&self, recipients: usize) -> Result<(), String> {
+        if recipients > self.max_recipients_per_request {
+            return Err(format!(
+                "request has {} recipients, the maximum is {}",
+                recipients, self.max_recipients_per_request
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the total size of the attachments of a email request is within the limit
+    pub fn check_attachments_size(&self, attachments: &[EmailAttachment]) -> Result<(), String> {
+        let size: usize = attachments
+            .iter()
+            .map(|attachment| base64_decoded_len(&attachment.content))
+            .sum();
+
+        if size > self.max_attachments_bytes {
+            return Err(format!(
+                "attachments have {} bytes, the maximum is {}",
+                size, self.max_attachments_bytes
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Checks if a email request is not deferred for longer than `MAX_DEFERRAL_HOURS`
+    pub fn check_not_before(&self, not_before: Option<DateTime<Utc>>) -> Result<(), String> {
+        let max_not_before = Utc::now() + self.max_deferral;
+
+        match not_before {
+            Some(not_before) if not_before > max_not_before => Err(format!(
+                "request cannot be sent before {}, the maximum is {}",
+                not_before, max_not_before
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Checks if a SES identity is verified for sending, cached for `SENDER_IDENTITY_STATUS_TTL`.
+    ///
+    /// failures to get the identity other than it not existing are not cached
+    async fn is_verified_identity(&self, identity: &str) -> bool {
+        let cached = self
+            .sender_identity_cache
+            .lock()
+            .unwrap()
+            .get(identity)
+            .copied();
+
+        if let Some((verified, fetched_at)) = cached {
+            if fetched_at.elapsed() < SENDER_IDENTITY_STATUS_TTL {
+                return verified;
+            }
+        }
+
+        let result = self
+            .aws_client
+            .get_email_identity()
+            .email_identity(identity)
+            .send()
+            .await;
+
+        let verified = match result {
+            Ok(output) => output.verified_for_sending_status(),
+            Err(e)
+                if e.as_service_error()
+                    .is_some_and(|e| e.is_not_found_exception()) =>
+            {
+                false
+            }
+            Err(e) => {
+                error!("failed to get email identity {}: {}", identity, e);
+                return false;
+            }
+        };
+
+        self.sender_identity_cache
+            .lock()
+            .unwrap()
+            .put(identity.to_owned(), (verified, Instant::now()));
+
+        verified
+    }
+
+    /// Checks if emails can be sent from a email address, that is if the address
+    /// itself or its domain are a SES identity verified for sending
+    async fn is_verified_sender(&self, email: &str) -> bool {
+        let domain = email.rsplit_once('@').map(|(_, domain)| domain);
+
+        for identity in [Some(email), domain].into_iter().flatten() {
+            if self.is_verified_identity(identity).await {
+                return true;
+            }
+        }
+
+        false
+    }
+
+    /// Returns the address to send emails from, falling back to the default sender if
+    /// the requested sender is not verified (eg: a organization sender that was verified
+    /// when set but had its verification revoked), so sending the email does not fail
+    async fn resolve_sender(&self, requested: Option<String>) -> String {
+        match requested {
+            Some(sender) if sender == self.default_sender => sender,
+            Some(sender) => {
+                if self.is_verified_sender(&sender).await {
+                    sender
+                } else {
+                    warn!("sender {} is not verified, using default sender", sender);
+                    self.default_sender.clone()
+                }
+            }
+            None => self.default_sender.clone(),
         }
     }
 
     /// Sends the emails for all the recipients in parallel, passing uuid to the email tags.
     ///
+    /// Requests with more than `MAX_RECIPIENTS_PER_REQUEST` recipients or attachments larger
+    /// than `MAX_ATTACHMENTS_BYTES` are refused as invalid.
+    ///
     /// Each recipient with non empty replacements have the `body_html` {{}} tags
     /// replaced by the recipients replacements. Emails are send individually for
     /// every recipient with replacements or for every recipient if `track_events` is true.
@@ -155,18 +532,41 @@ impl Mailer {
             track_events = %options.track_events
         )
     )]
-    pub async fn send_emails(&self, options: SendEmailOptions) -> Result<(), String> {
+    pub async fn send_emails(&self, options: SendEmailOptions) -> Result<(), SendEmailsError> {
+        self.check_recipients_limit(options.to.len())?;
+        self.check_attachments_size(&options.attachments)?;
+
         let html = options.body_html.unwrap_or_default();
         let text = options.body_text.unwrap_or_default();
-        let subject = to_utf8_content(&options.subject)
-            .map_err(|_| String::from("failed to build subject"))?;
+
+        let attachments = options
+            .attachments
+            .iter()
+            .map(|attachment| {
+                Ok(Attachment {
+                    filename: attachment.filename.clone(),
+                    content_type: attachment.content_type.clone(),
+                    bytes: attachment.decode().map_err(|_| {
+                        format!("attachment {} is not valid base64", attachment.filename)
+                    })?,
+                })
+            })
+            .collect::<Result<Vec<_>, String>>()?;
 
         let uuid_str = options.uuid.to_string();
 
-        let from = options.from.unwrap_or(self.default_sender.clone());
+        let from = self.resolve_sender(options.from).await;
 
         event!(Level::INFO, from,);
 
+        let parts = EmailParts {
+            from: &from,
+            subject: &options.subject,
+            text: &text,
+            reply_to: options.reply_to_addresses.as_deref(),
+            attachments: &attachments,
+        };
+
         let config_set = if options.track_events {
             Some(self.aws_ses_tracking_config_set.clone())
         } else {
@@ -195,6 +595,8 @@ impl Mailer {
             // and send the email, since emails here must be sent individually email tracing
             // will work fine.
             for recipient in recipients_with_replacements {
+                let permit = self.acquire_send_permit().await;
+
                 let recipient_html = if template_registered {
                     reg.render(&uuid_str, &recipient.replacements)
                         .unwrap_or(html.clone())
@@ -202,20 +604,8 @@ impl Mailer {
                     html.clone()
                 };
 
-                let body = Body::builder()
-                    .html(
-                        to_utf8_content(&recipient_html)
-                            .map_err(|_| String::from("failed to build html"))?,
-                    )
-                    .text(to_utf8_content(&text).map_err(|_| String::from("failed to build html"))?)
-                    .build();
-
-                let msg = Message::builder()
-                    .subject(subject.clone())
-                    .body(body)
-                    .build();
-
-                let email_content = EmailContent::builder().simple(msg).build();
+                let email_content =
+                    parts.content(&recipient_html, std::slice::from_ref(&recipient.email))?;
 
                 let dest = Destination::builder()
                     .to_addresses(recipient.email.clone())
@@ -223,6 +613,7 @@ impl Mailer {
 
                 send_email_tasks.spawn(
                     send_with_rate_limiter(
+                        permit,
                         self.rate_limiter.clone(),
                         self.aws_client
                             .send_email()
@@ -242,32 +633,18 @@ impl Mailer {
         }
 
         if !recipients_without_replacements.is_empty() {
-            // if were supposed to track events for the email, the chunk size must be `1` to send emails individually,
-            // otherwise we cannot determine the specific recipient that triggered a email event (eg: `open`, `click`)
-            let chunk_size = if options.track_events {
-                1
-            } else {
-                MAX_RECIPIENTS_PER_SEND_EMAIL_OP
-            };
+            let chunk_size = recipients_per_send_op(options.track_events);
 
             for recipient_chunk in recipients_without_replacements.chunks(chunk_size) {
+                let permit = self.acquire_send_permit().await;
+
                 let chunk_emails: Vec<String> = recipient_chunk
                     .to_vec()
                     .iter()
                     .map(|e| e.email.to_owned())
                     .collect();
 
-                let body = Body::builder()
-                    .html(to_utf8_content(&html).map_err(|_| String::from("failed to build html"))?)
-                    .text(to_utf8_content(&text).map_err(|_| String::from("failed to build text"))?)
-                    .build();
-
-                let msg = Message::builder()
-                    .subject(subject.clone())
-                    .body(body)
-                    .build();
-
-                let email_content = EmailContent::builder().simple(msg).build();
+                let email_content = parts.content(&html, &chunk_emails)?;
 
                 let dest = Destination::builder()
                     .set_to_addresses(Some(chunk_emails.clone()))
@@ -275,6 +652,7 @@ impl Mailer {
 
                 send_email_tasks.spawn(
                     send_with_rate_limiter(
+                        permit,
                         self.rate_limiter.clone(),
                         self.aws_client
                             .send_email()
@@ -293,9 +671,92 @@ impl Mailer {
             }
         }
 
+        let mut sent_count = 0;
+        let mut transient_error = None;
+
         // Wait for all tasks to finish
-        while send_email_tasks.join_next().await.is_some() {}
+        while let Some(task_res) = send_email_tasks.join_next().await {
+            match task_res {
+                Ok(Ok(_)) => sent_count += 1,
+                Ok(Err(ses_err)) if is_transient_ses_error(&ses_err) => {
+                    transient_error = Some(ses_err.to_string())
+                }
+                _ => {}
+            }
+        }
 
-        Ok(())
+        // retrying after some emails were sent would send them again,
+        // so only report transient errors when no email was sent
+        match transient_error {
+            Some(err) if sent_count == 0 => Err(SendEmailsError::Transient(err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn org_rate_limiter(per_minute: u32) -> (OrgRateLimiter, QuantaClock) {
+        let clock = QuantaClock::default();
+        let quota = Quota::per_minute(NonZeroU32::new(per_minute).unwrap());
+
+        (
+            governor::RateLimiter::dashmap_with_clock(quota, &clock),
+            clock,
+        )
+    }
+
+    #[test]
+    fn requests_over_the_org_budget_wait_for_it() {
+        let (limiter, clock) = org_rate_limiter(3);
+
+        assert_eq!(take_org_budget(&limiter, &clock, 1, 2), Ok(()));
+
+        // the budget is not taken partially, so a smaller request still fits
+        let wait = take_org_budget(&limiter, &clock, 1, 2).unwrap_err();
+        assert!(wait > Duration::ZERO && wait <= Duration::from_secs(20));
+
+        assert_eq!(take_org_budget(&limiter, &clock, 1, 1), Ok(()));
+        assert!(take_org_budget(&limiter, &clock, 1, 1).is_err());
+
+        // organizations have separate budgets
+        assert_eq!(take_org_budget(&limiter, &clock, 2, 3), Ok(()));
+    }
+
+    #[test]
+    fn requests_larger_than_the_org_budget_take_all_of_it() {
+        let (limiter, clock) = org_rate_limiter(3);
+
+        assert_eq!(take_org_budget(&limiter, &clock, 1, 10), Ok(()));
+        assert!(take_org_budget(&limiter, &clock, 1, 1).is_err());
+
+        assert_eq!(take_org_budget(&limiter, &clock, 2, 0), Ok(()));
+    }
+
+    #[test]
+    fn counts_a_send_operation_per_chunk_or_recipient_with_replacements() {
+        let recipient = |email: String, replacements: &[(&str, &str)]| EmailRecipient {
+            email,
+            replacements: Some(
+                replacements
+                    .iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect(),
+            ),
+        };
+
+        let mut to: Vec<EmailRecipient> = (0..60)
+            .map(|i| recipient(format!("{}@rastercar.com", i), &[]))
+            .collect();
+
+        to.push(recipient(
+            String::from("named@rastercar.com"),
+            &[("name", "Raster")],
+        ));
+
+        assert_eq!(send_operations_count(&to, false), 3);
+        assert_eq!(send_operations_count(&to, true), 61);
     }
 }
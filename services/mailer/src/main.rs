@@ -13,6 +13,7 @@ mod config;
 mod http;
 mod mailer;
 mod queue;
+mod raw_email;
 mod tracer;
 mod utils;
 
@@ -47,13 +47,23 @@ pub struct EmailSendingReceivedEvent {
 
     pub request_uuid: Uuid,
 
+    /// the email request, without the contents of its attachments
     pub request: SendEmailIn,
 }
 
+/// clears the contents of the request attachments, so events do not carry the files
+fn without_attachment_contents(mut request: SendEmailIn) -> SendEmailIn {
+    for attachment in request.attachments.iter_mut() {
+        attachment.content.clear();
+    }
+
+    request
+}
+
 impl EmailSendingReceivedEvent {
     pub fn started(request_uuid: uuid::Uuid, request: SendEmailIn) -> EmailSendingReceivedEvent {
         EmailSendingReceivedEvent {
-            request,
+            request: without_attachment_contents(request),
             request_uuid,
             timestamp: Utc::now(),
             status: EmailRequestStatus::STARTED,
@@ -62,7 +72,7 @@ impl EmailSendingReceivedEvent {
 
     pub fn rejected(request_uuid: uuid::Uuid, request: SendEmailIn) -> EmailSendingReceivedEvent {
         EmailSendingReceivedEvent {
-            request,
+            request: without_attachment_contents(request),
             request_uuid,
             timestamp: Utc::now(),
             status: EmailRequestStatus::REJECTED,
@@ -1,8 +1,14 @@
-use super::{routes::default, utils::get_delivery_type};
+use super::{
+    routes::default,
+    utils::{
+        ack_delivery, get_delivery_type, get_retry_count, nack_delivery, requeue_delivery,
+        DeliveryError, MAX_TRANSIENT_RETRIES,
+    },
+};
 use crate::{mailer::Mailer, queue};
 use lapin::message::Delivery;
 use std::sync::Arc;
-use tracing::error;
+use tracing::{error, warn};
 
 pub struct QueueRouter {
     pub server: Arc<queue::MailerRabbitmq>,
@@ -14,20 +20,66 @@ impl QueueRouter {
         QueueRouter { server, mailer }
     }
 
+    /// Handles the delivery, acking it on success and either retrying it later
+    /// or rejecting it to the dead letter queue depending on the handler error
     #[tracing::instrument(skip_all)]
     pub async fn handle_delivery(&self, delivery: Delivery) {
         let delivery_type = get_delivery_type(&delivery);
 
         let handler_res = match delivery_type.as_str() {
-            "sendEmail" => self.send_email_handler(delivery).await,
-            _ => default::handle_delivery_without_corresponding_rpc(delivery).await,
+            "sendEmail" => self.send_email_handler(&delivery).await,
+            _ => default::handle_delivery_without_corresponding_rpc(&delivery).await,
         };
 
-        if let Err(err) = handler_res {
-            error!(
-                "handler for delivery of type: {} returned error: {}",
-                delivery_type, err
-            );
+        let ack_res = match handler_res {
+            Ok(()) => ack_delivery(&delivery).await,
+            Err(DeliveryError::Transient(err)) => {
+                let retry_count = get_retry_count(&delivery);
+
+                if retry_count >= MAX_TRANSIENT_RETRIES {
+                    error!(
+                        "handler for delivery of type: {} failed {} times, rejecting delivery to the dead letter queue: {}",
+                        delivery_type,
+                        retry_count + 1,
+                        err
+                    );
+
+                    self.server.count_dead_lettered();
+                    nack_delivery(&delivery).await
+                } else {
+                    warn!(
+                        "handler for delivery of type: {} failed, retrying later: {}",
+                        delivery_type, err
+                    );
+
+                    // the retry is a copy on the deferred queue, so the delay does not hold a prefetch
+                    // slot, requeueing is only a fallback for when the copy could not be published
+                    match self
+                        .server
+                        .retry_delivery_later(&delivery, retry_count + 1)
+                        .await
+                    {
+                        Ok(()) => ack_delivery(&delivery).await,
+                        Err(retry_err) => {
+                            error!("failed to retry delivery later, requeueing: {}", retry_err);
+                            requeue_delivery(&delivery).await
+                        }
+                    }
+                }
+            }
+            Err(DeliveryError::Rejected(err)) => {
+                error!(
+                    "handler for delivery of type: {} rejected delivery to the dead letter queue: {}",
+                    delivery_type, err
+                );
+
+                self.server.count_dead_lettered();
+                nack_delivery(&delivery).await
+            }
+        };
+
+        if let Err(err) = ack_res {
+            error!(err);
         }
     }
 }
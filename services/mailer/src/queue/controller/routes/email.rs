@@ -1,35 +1,90 @@
 use crate::{
-    mailer::SendEmailOptions,
+    mailer::{send_operations_count, SendEmailOptions, SendEmailsError},
     queue::controller::{
         dto::events::{EmailRequestFinishedEvent, EmailSendingReceivedEvent},
         router::QueueRouter,
-        utils::ack_delivery,
+        utils::DeliveryError,
     },
 };
+use chrono::Utc;
 use lapin::message::Delivery;
 use shared::dto::mailer::SendEmailIn;
-use tracing::{event, Level};
+use tracing::{error, event, info, warn, Level};
 use uuid::Uuid;
 use validator::Validate;
 
 impl QueueRouter {
     #[tracing::instrument(skip_all)]
-    pub async fn send_email_handler(&self, delivery: Delivery) -> Result<(), String> {
-        ack_delivery(&delivery).await?;
-
+    pub async fn send_email_handler(&self, delivery: &Delivery) -> Result<(), DeliveryError> {
         let send_email_in = serde_json::from_slice::<SendEmailIn>(&delivery.data)
-            .map_err(|e| format!("parse error: {:#?}", e))?;
+            .map_err(|e| DeliveryError::Rejected(format!("parse error: {:#?}", e)))?;
 
         let uuid = send_email_in.uuid.unwrap_or(Uuid::new_v4());
 
         event!(Level::INFO, email_uuid = uuid.to_string());
 
-        if let Err(e) = send_email_in.validate() {
-            self.server
+        let validation_result = send_email_in
+            .validate()
+            .map_err(|e| e.to_string())
+            .and_then(|_| self.mailer.check_recipients_limit(send_email_in.to.len()))
+            .and_then(|_| {
+                self.mailer
+                    .check_attachments_size(&send_email_in.attachments)
+            })
+            .and_then(|_| self.mailer.check_not_before(send_email_in.not_before));
+
+        if let Err(e) = validation_result {
+            if let Err(publish_err) = self
+                .server
                 .publish_event(EmailSendingReceivedEvent::rejected(uuid, send_email_in))
-                .await?;
+                .await
+            {
+                error!("failed to publish rejected event: {}", publish_err);
+            }
 
-            return Err(e.to_string());
+            return Err(DeliveryError::Rejected(e));
+        }
+
+        // requests that cannot be sent yet are parked on the deferred queue, which delivers
+        // them back here later, the delivery is only acked once the deferred copy is confirmed
+        if let Some(not_before) = send_email_in.not_before.filter(|t| *t > Utc::now()) {
+            self.server
+                .defer_delivery(delivery)
+                .await
+                .map_err(DeliveryError::Transient)?;
+
+            info!(%not_before, "email request deferred");
+            return Ok(());
+        }
+
+        // requests of organizations out of email budget are deferred until the organization
+        // has budget for all of them instead of waiting here, so the delivery is not held
+        let send_operations =
+            send_operations_count(&send_email_in.to, send_email_in.enable_tracking);
+
+        if let Err(not_before) = self
+            .mailer
+            .take_org_budget(send_email_in.organization_id, send_operations)
+        {
+            let deferred = SendEmailIn {
+                uuid: Some(uuid),
+                ..send_email_in.with_not_before(Some(not_before))
+            };
+
+            let data = serde_json::to_vec(&deferred)
+                .map_err(|e| DeliveryError::Rejected(format!("serialize error: {:#?}", e)))?;
+
+            self.server
+                .defer_delivery_with_data(delivery, &data)
+                .await
+                .map_err(DeliveryError::Transient)?;
+
+            warn!(
+                %not_before,
+                organization_id = deferred.organization_id,
+                "organization email budget exhausted, email request deferred"
+            );
+            return Ok(());
         }
 
         self.server
@@ -37,7 +92,8 @@ impl QueueRouter {
                 uuid,
                 send_email_in.clone(),
             ))
-            .await?;
+            .await
+            .map_err(DeliveryError::Transient)?;
 
         self.mailer
             .send_emails(SendEmailOptions {
@@ -49,12 +105,23 @@ impl QueueRouter {
                 body_html: send_email_in.body_html,
                 track_events: send_email_in.enable_tracking,
                 reply_to_addresses: send_email_in.reply_to_addresses,
+                attachments: send_email_in.attachments,
             })
-            .await?;
+            .await
+            .map_err(|e| match e {
+                SendEmailsError::Invalid(err) => DeliveryError::Rejected(err),
+                SendEmailsError::Transient(err) => DeliveryError::Transient(err),
+            })?;
 
-        self.server
+        // the emails were already sent, so the delivery must not be retried
+        // even if the finished event is not published, as that would resend them
+        if let Err(publish_err) = self
+            .server
             .publish_event(EmailRequestFinishedEvent::new(uuid))
-            .await?;
+            .await
+        {
+            error!("failed to publish finished event: {}", publish_err);
+        }
 
         Ok(())
     }
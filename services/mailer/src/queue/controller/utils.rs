@@ -1,8 +1,35 @@
+use crate::queue::RETRY_COUNT_HEADER;
 use lapin::{
     message::Delivery,
     options::{BasicAckOptions, BasicNackOptions},
-    types::ShortString,
+    types::{AMQPValue, FieldTable, ShortString},
 };
+use std::fmt::Display;
+
+/// Times a delivery that failed due to a transient error is retried before
+/// being dead lettered, each retry waits on the deferred queue before running.
+pub const MAX_TRANSIENT_RETRIES: u32 = 5;
+
+/// Error returned by a delivery handler, determining what is done with the delivery
+#[derive(Debug)]
+pub enum DeliveryError {
+    /// the delivery can never be handled, eg: its malformed or of a unknown type,
+    /// so its rejected to the dead letter queue instead of being redelivered.
+    Rejected(String),
+
+    /// handling the delivery failed for a reason that might not happen again, eg: SES
+    /// throttling, so its retried after a delay, up to `MAX_TRANSIENT_RETRIES` times.
+    Transient(String),
+}
+
+impl Display for DeliveryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeliveryError::Rejected(err) => write!(f, "rejected: {}", err),
+            DeliveryError::Transient(err) => write!(f, "transient: {}", err),
+        }
+    }
+}
 
 /// Gets the value from the `type` property, defaulting to `unknown`
 pub fn get_delivery_type(delivery: &Delivery) -> String {
@@ -14,6 +41,29 @@ pub fn get_delivery_type(delivery: &Delivery) -> String {
         .to_string()
 }
 
+/// Gets the amount of times the delivery was retried, from the retry count header
+pub fn get_retry_count(delivery: &Delivery) -> u32 {
+    delivery
+        .properties
+        .headers()
+        .as_ref()
+        .map(retry_count_from_headers)
+        .unwrap_or(0)
+}
+
+fn retry_count_from_headers(headers: &FieldTable) -> u32 {
+    headers
+        .inner()
+        .get(RETRY_COUNT_HEADER)
+        .and_then(|value| match value {
+            AMQPValue::LongLongInt(v) => u32::try_from(*v).ok(),
+            AMQPValue::LongInt(v) => u32::try_from(*v).ok(),
+            AMQPValue::LongUInt(v) => Some(*v),
+            _ => None,
+        })
+        .unwrap_or(0)
+}
+
 pub async fn ack_delivery(delivery: &Delivery) -> Result<(), String> {
     delivery
         .ack(BasicAckOptions::default())
@@ -21,6 +71,7 @@ pub async fn ack_delivery(delivery: &Delivery) -> Result<(), String> {
         .or(Err(create_ack_nack_error_string(delivery)))
 }
 
+/// nacks the delivery without requeueing it, dead lettering it
 pub async fn nack_delivery(delivery: &Delivery) -> Result<(), String> {
     delivery
         .nack(BasicNackOptions::default())
@@ -28,6 +79,17 @@ pub async fn nack_delivery(delivery: &Delivery) -> Result<(), String> {
         .or(Err(create_ack_nack_error_string(delivery)))
 }
 
+/// nacks the delivery requeueing it, so its redelivered
+pub async fn requeue_delivery(delivery: &Delivery) -> Result<(), String> {
+    delivery
+        .nack(BasicNackOptions {
+            requeue: true,
+            ..Default::default()
+        })
+        .await
+        .or(Err(create_ack_nack_error_string(delivery)))
+}
+
 pub fn create_ack_nack_error_string(delivery: &Delivery) -> String {
     format!(
         "error acking/nacking, delivery with tag: {} of type: {}",
@@ -35,3 +97,34 @@ pub fn create_ack_nack_error_string(delivery: &Delivery) -> String {
         get_delivery_type(delivery)
     )
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers_with_retry_count(value: AMQPValue) -> FieldTable {
+        let mut headers = FieldTable::default();
+        headers.insert(RETRY_COUNT_HEADER.into(), value);
+        headers
+    }
+
+    #[test]
+    fn reads_the_retry_count_header() {
+        let headers = headers_with_retry_count(AMQPValue::LongLongInt(3));
+        assert_eq!(retry_count_from_headers(&headers), 3);
+
+        let headers = headers_with_retry_count(AMQPValue::LongUInt(2));
+        assert_eq!(retry_count_from_headers(&headers), 2);
+    }
+
+    #[test]
+    fn defaults_the_retry_count_to_zero_when_missing_or_invalid() {
+        assert_eq!(retry_count_from_headers(&FieldTable::default()), 0);
+
+        let headers = headers_with_retry_count(AMQPValue::LongLongInt(-1));
+        assert_eq!(retry_count_from_headers(&headers), 0);
+
+        let headers = headers_with_retry_count(AMQPValue::LongString("1".into()));
+        assert_eq!(retry_count_from_headers(&headers), 0);
+    }
+}
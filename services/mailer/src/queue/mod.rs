@@ -4,18 +4,34 @@ use crate::{config::app_config, utils::errors::ResultExt};
 use lapin::{
     message::Delivery,
     options::{
-        BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, ExchangeDeclareOptions,
-        QueueDeclareOptions,
+        BasicConsumeOptions, BasicPublishOptions, BasicQosOptions, ConfirmSelectOptions,
+        ExchangeDeclareOptions, QueueBindOptions, QueueDeclareOptions,
     },
     publisher_confirm::PublisherConfirm,
-    types::FieldTable,
+    types::{AMQPValue, FieldTable},
     BasicProperties, Channel, Connection, ConnectionProperties, Consumer, ExchangeKind,
 };
 use serde::Serialize;
-use std::{thread, time};
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
+use shared::backoff::ReconnectBackoff;
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc::UnboundedSender, RwLock},
+    time::sleep,
+};
 use tokio_stream::StreamExt;
-use tracing::{event, Level};
+use tracing::{error, event, info, Level};
+
+/// AMQP delivery mode of messages persisted to disk
+const PERSISTENT_DELIVERY_MODE: u8 = 2;
+
+/// Header with the amount of times a delivery was retried due to transient errors
+pub const RETRY_COUNT_HEADER: &str = "x-retry-count";
 
 pub trait Routable {
     /// Creates a routing to be used to send rabbitmq messages with
@@ -36,6 +52,21 @@ pub struct MailerRabbitmq {
     /// name of the exchange used to publish email events
     email_events_exchange: String,
 
+    /// name of the exchange the mailer queue dead letters deliveries to
+    dead_letter_exchange: String,
+
+    /// name of the queue bound to the dead letter exchange
+    dead_letter_queue: String,
+
+    /// name of the queue deferred deliveries wait on until they are checked again
+    deferred_queue: String,
+
+    /// amount of deliveries rejected to the dead letter queue since the service started
+    dead_lettered_count: AtomicU64,
+
+    /// backoff of the reconnection attempts, also tracks if the connection is down for too long
+    reconnect_backoff: Mutex<ReconnectBackoff>,
+
     /// channel for consuming / pooling messages
     consume_channel: RwLock<Option<Channel>>,
 
@@ -60,6 +91,17 @@ impl MailerRabbitmq {
             mailer_queue: cfg.rmq_queue.clone(),
             consumer_tag: cfg.rmq_consumer_tag.clone(),
             email_events_exchange: cfg.rmq_email_events_exchange.clone(),
+            dead_letter_exchange: cfg.rmq_dead_letter_exchange.clone(),
+            dead_letter_queue: cfg.rmq_dead_letter_queue.clone(),
+            deferred_queue: cfg.rmq_deferred_queue.clone(),
+            dead_lettered_count: AtomicU64::new(0),
+
+            reconnect_backoff: Mutex::new(ReconnectBackoff::new(
+                "rabbitmq",
+                Duration::from_secs(2),
+                Duration::from_secs(60 * 10),
+                cfg.rmq_outage_alarm(),
+            )),
 
             delivery_sender,
 
@@ -84,27 +126,26 @@ impl MailerRabbitmq {
     /// Runs the RabbitMQ mail queue consumer, attempting to reconnect endlessly
     /// if the RabbitMQ connection is dropped.
     pub async fn start_consumer(&self) {
-        let mut reconnect_delay = 2;
-
-        let max_reconnect_delay = 60 * 10;
-
         loop {
             if let Err(err) = self.connect_and_consume().await {
-                eprintln!("[RMQ] connection error: {}", err)
+                error!(error = %err, "[RMQ] connection error")
             }
 
-            thread::sleep(time::Duration::from_secs(reconnect_delay));
-            println!(
-                "[RMQ] reconnecting, next attempt in: {} seconds",
-                reconnect_delay
-            );
+            let reconnect_delay = self.reconnect_backoff.lock().unwrap().failed();
 
-            if reconnect_delay < max_reconnect_delay {
-                reconnect_delay *= 2
-            }
+            sleep(reconnect_delay).await;
+            info!(
+                reconnect_delay_ms = reconnect_delay.as_millis() as u64,
+                "[RMQ] reconnecting"
+            );
         }
     }
 
+    /// If the RabbitMQ connection is down for longer than the configured outage alarm
+    pub fn is_connection_critical(&self) -> bool {
+        self.reconnect_backoff.lock().unwrap().is_critical()
+    }
+
     /// Connects to rabbitmq, declaring all the queues, exchanges and consumers needed.
     /// lastly starts consuming deliveries from the mailer queue, returning only when the
     /// connection is dropped.
@@ -114,27 +155,25 @@ impl MailerRabbitmq {
             .with_reactor(tokio_reactor_trait::Tokio);
 
         let connection = Connection::connect(&self.uri, props).await?;
-        println!("[RMQ] connected");
+        info!("[RMQ] connected");
 
         let publish_channel = connection.create_channel().await?;
-        println!("[RMQ] consume channel created");
+        info!("[RMQ] consume channel created");
+
+        // publisher confirms, so deferred deliveries are only acked once the broker
+        // confirms their copy on the deferred queue, see `defer_delivery`
+        publish_channel
+            .confirm_select(ConfirmSelectOptions::default())
+            .await?;
 
         let mut consume_channel = connection.create_channel().await?;
-        println!("[RMQ] publish channel created");
+        info!("[RMQ] publish channel created");
 
-        // Consumer prefetch count
-        //
-        // We do not want a unlimited prefetch count to avoid crashing the service if a ton
-        // of email sending requests are received, even though that probably wont happen due
-        // to the mailer queue message TTL.
-        //
-        // Since sending emails can take a lot of time due to the rate limiter, especially if
-        // the emails have a lot of recipients and email tracking is true, a prefetch of 1 is
-        // not optimal, so set it to at least 10 (yep i chose this value arbitrarily)
+        // Consumer prefetch count, see `RMQ_PREFETCH` and:
         //
         // https://www.cloudamqp.com/blog/how-to-optimize-the-rabbitmq-prefetch-count.html
         consume_channel
-            .basic_qos(10, BasicQosOptions::default())
+            .basic_qos(app_config().rmq_prefetch, BasicQosOptions::default())
             .await?;
 
         let mut consumer = self
@@ -145,6 +184,8 @@ impl MailerRabbitmq {
         *self.consume_channel.write().await = Some(consume_channel);
         *self.publish_channel.write().await = Some(publish_channel);
 
+        self.reconnect_backoff.lock().unwrap().connected();
+
         self.consume_messages_until_error(&mut consumer).await
     }
 
@@ -169,7 +210,92 @@ impl MailerRabbitmq {
             )
             .await
             .unwrap_or_exit_process();
-        println!("[RMQ] events exchange declared");
+        info!("[RMQ] events exchange declared");
+
+        channel
+            .exchange_declare(
+                &self.dead_letter_exchange,
+                ExchangeKind::Fanout,
+                ExchangeDeclareOptions {
+                    passive: false,
+                    durable: true,
+                    auto_delete: false,
+                    internal: false,
+                    nowait: false,
+                },
+                FieldTable::default(),
+            )
+            .await
+            .unwrap_or_exit_process();
+        info!("[RMQ] dead letter exchange declared");
+
+        channel
+            .queue_declare(
+                &self.dead_letter_queue,
+                QueueDeclareOptions {
+                    nowait: false,
+                    passive: false,
+                    durable: true,
+                    exclusive: false,
+                    auto_delete: false,
+                },
+                FieldTable::default(),
+            )
+            .await
+            .unwrap_or_exit_process();
+        info!("[RMQ] dead letter queue declared");
+
+        channel
+            .queue_bind(
+                &self.dead_letter_queue,
+                &self.dead_letter_exchange,
+                "",
+                QueueBindOptions::default(),
+                FieldTable::default(),
+            )
+            .await
+            .unwrap_or_exit_process();
+        info!("[RMQ] dead letter queue binded to dead letter exchange");
+
+        let mut deferred_queue_options = FieldTable::default();
+
+        // Deferred Queue
+        //
+        // deliveries that cannot be sent yet wait here for `DEFERRAL_CHECK_INTERVAL_SECONDS`,
+        // when they expire they are dead lettered back to the mailer queue (through the default
+        // exchange) to be checked again. a queue wide TTL is used instead of per message TTLs
+        // since messages only expire at the head of the queue, so a delivery with a long TTL
+        // would hold back the ones behind it.
+        deferred_queue_options.insert(
+            "x-message-ttl".into(),
+            AMQPValue::LongUInt(app_config().deferral_check_interval_seconds * 1000),
+        );
+
+        deferred_queue_options.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString("".into()),
+        );
+
+        deferred_queue_options.insert(
+            "x-dead-letter-routing-key".into(),
+            AMQPValue::LongString(self.mailer_queue.clone().into()),
+        );
+
+        channel
+            .queue_declare(
+                &self.deferred_queue,
+                QueueDeclareOptions {
+                    nowait: false,
+                    passive: false,
+                    durable: true,
+                    exclusive: false,
+                    auto_delete: false,
+                },
+                deferred_queue_options,
+            )
+            .await
+            .unwrap_or_exit_process();
+        info!("[RMQ] deferred queue declared");
 
         let mut queue_options = FieldTable::default();
 
@@ -184,6 +310,20 @@ impl MailerRabbitmq {
             lapin::types::AMQPValue::ShortUInt(30_000),
         );
 
+        // Mailer Queue DLX
+        //
+        // deliveries rejected by the handlers (eg: malformed payloads) are dead lettered
+        // instead of dropped so they can be inspected, this includes deliveries that expired
+        // due to the TTL, the reason is kept on the `x-death` header of the dead lettered message.
+        //
+        // queue arguments cannot be changed once the queue exists, this is why the default queue
+        // name changed from `mailer` to `mailer_requests` when the DLX was added, a queue declared
+        // with other arguments fails the declaration with PRECONDITION_FAILED.
+        queue_options.insert(
+            "x-dead-letter-exchange".into(),
+            AMQPValue::LongString(self.dead_letter_exchange.clone().into()),
+        );
+
         channel
             .queue_declare(
                 &self.mailer_queue,
@@ -198,7 +338,7 @@ impl MailerRabbitmq {
             )
             .await
             .unwrap_or_exit_process();
-        println!("[RMQ] mailer queue declared");
+        info!("[RMQ] mailer queue declared");
 
         let consumer = channel
             .basic_consume(
@@ -209,7 +349,7 @@ impl MailerRabbitmq {
             )
             .await
             .unwrap_or_exit_process();
-        println!("[RMQ] mailer queue consumer started");
+        info!("[RMQ] mailer queue consumer started");
 
         consumer
     }
@@ -235,7 +375,7 @@ impl MailerRabbitmq {
                         .expect("sender channel closed");
                 }
                 Err(err) => {
-                    println!("[RMQ] mailer queue consumer error: {}", err);
+                    error!(error = %err, "[RMQ] mailer queue consumer error");
                     return Err(err);
                 }
             }
@@ -243,7 +383,7 @@ impl MailerRabbitmq {
 
         // this should be unreachable as the consumer stream should never end as long as
         // the connection is open and when its closed the error case above is triggered
-        println!("[RMQ] mailer queue consumer stopped, stream ended");
+        info!("[RMQ] mailer queue consumer stopped, stream ended");
         Ok(())
     }
 
@@ -293,26 +433,124 @@ impl MailerRabbitmq {
         .await
     }
 
+    /// Publishes a copy of the delivery to the deferred queue, to be delivered again to the
+    /// mailer queue once it expires there. the copy is persistent and this only returns after
+    /// the broker confirms it, so the original delivery can be acked without being lost
+    #[tracing::instrument(skip_all)]
+    pub async fn defer_delivery(&self, delivery: &Delivery) -> Result<(), String> {
+        self.publish_to_deferred_queue(&delivery.data, delivery.properties.clone())
+            .await
+    }
+
+    /// Same as `defer_delivery` but the copy has `data` as its payload instead of the
+    /// delivery one, used to defer a request changed by the handler (eg: its `not_before`)
+    #[tracing::instrument(skip_all)]
+    pub async fn defer_delivery_with_data(
+        &self,
+        delivery: &Delivery,
+        data: &[u8],
+    ) -> Result<(), String> {
+        self.publish_to_deferred_queue(data, delivery.properties.clone())
+            .await
+    }
+
+    /// Same as `defer_delivery` but sets the retry count header of the copy to `retry_count`,
+    /// used to retry deliveries that failed due to transient errors after a delay.
+    #[tracing::instrument(skip(self, delivery))]
+    pub async fn retry_delivery_later(
+        &self,
+        delivery: &Delivery,
+        retry_count: u32,
+    ) -> Result<(), String> {
+        let mut headers = delivery.properties.headers().clone().unwrap_or_default();
+
+        headers.insert(
+            RETRY_COUNT_HEADER.into(),
+            AMQPValue::LongLongInt(retry_count.into()),
+        );
+
+        self.publish_to_deferred_queue(
+            &delivery.data,
+            delivery.properties.clone().with_headers(headers),
+        )
+        .await
+    }
+
+    async fn publish_to_deferred_queue(
+        &self,
+        data: &[u8],
+        properties: BasicProperties,
+    ) -> Result<(), String> {
+        let properties = properties.with_delivery_mode(PERSISTENT_DELIVERY_MODE);
+
+        let confirmation = self
+            .publish("", &self.deferred_queue, data, properties)
+            .await?
+            .await
+            .map_err(|e| format!("failed to confirm deferred delivery: {}", e))?;
+
+        if confirmation.is_nack() {
+            return Err(String::from(
+                "deferred delivery was not confirmed by the broker",
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Registers that a delivery was rejected to the dead letter queue
+    pub fn count_dead_lettered(&self) {
+        self.dead_lettered_count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Amount of deliveries rejected to the dead letter queue since the service started
+    pub fn dead_lettered_count(&self) -> u64 {
+        self.dead_lettered_count.load(Ordering::Relaxed)
+    }
+
+    /// Amount of messages currently on the dead letter queue, including
+    /// deliveries dead lettered by other instances or that expired.
+    pub async fn dead_letter_queue_message_count(&self) -> Result<u32, String> {
+        let queue = self
+            .publish_channel
+            .read()
+            .await
+            .as_ref()
+            .ok_or("RMQ publishing channel is not available")?
+            .queue_declare(
+                &self.dead_letter_queue,
+                QueueDeclareOptions {
+                    passive: true,
+                    ..Default::default()
+                },
+                FieldTable::default(),
+            )
+            .await
+            .map_err(|e| format!("failed to inspect dead letter queue: {}", e))?;
+
+        Ok(queue.message_count())
+    }
+
     /// Closes the rabbitmq connection and the publish and consume channels
     pub async fn shutdown(&self) {
-        println!("[RMQ] closing publish channel");
+        info!("[RMQ] closing publish channel");
         if let Some(chan) = self.publish_channel.read().await.as_ref() {
             if let Err(chan_close_err) = chan.close(200, "user shutdown").await {
-                eprintln!("[RMQ] failed to close channel: {}", chan_close_err)
+                error!(error = %chan_close_err, "[RMQ] failed to close channel")
             }
         }
 
-        println!("[RMQ] closing consume channel");
+        info!("[RMQ] closing consume channel");
         if let Some(chan) = self.publish_channel.read().await.as_ref() {
             if let Err(chan_close_err) = chan.close(200, "user shutdown").await {
-                eprintln!("[RMQ] failed to close channel: {}", chan_close_err)
+                error!(error = %chan_close_err, "[RMQ] failed to close channel")
             }
         }
 
-        println!("[RMQ] closing connection");
+        info!("[RMQ] closing connection");
         if let Some(conn) = self.connection.read().await.as_ref() {
             if let Err(conn_close_err) = conn.close(200, "user shutdown").await {
-                eprintln!("[RMQ] failed to close connection: {}", conn_close_err)
+                error!(error = %conn_close_err, "[RMQ] failed to close connection")
             }
         }
 
@@ -0,0 +1,145 @@
+//! Raw MIME emails.
+//!
+//! the SES simple message does not support attachments, so emails with attachments are sent as
+//! a raw `multipart/mixed` message, with the html and text bodies as a `multipart/alternative`
+//! part followed by a part for every attachment, every part is base64 encoded.
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use uuid::Uuid;
+
+/// max length of the lines of base64 encoded parts, see RFC 2045
+const MAX_BASE64_LINE_LENGTH: usize = 76;
+
+/// A decoded file to attach to a email
+#[derive(Debug, Clone)]
+pub struct Attachment {
+    pub filename: String,
+    pub content_type: String,
+    pub bytes: Vec<u8>,
+}
+
+pub struct RawEmail<'a> {
+    pub from: &'a str,
+    pub to: &'a [String],
+    pub reply_to: Option<&'a [String]>,
+    pub subject: &'a str,
+    pub html: &'a str,
+    pub text: &'a str,
+    pub attachments: &'a [Attachment],
+}
+
+/// removes line breaks so a value cannot inject headers
+fn sanitize_header_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ")
+}
+
+/// encodes a header value as a RFC 2047 encoded word if it is not plain ascii
+fn encode_header_value(value: &str) -> String {
+    let value = sanitize_header_value(value);
+
+    if value.is_ascii() {
+        return value;
+    }
+
+    format!("=?UTF-8?B?{}?=", STANDARD.encode(value))
+}
+
+/// encodes a quoted header parameter (eg: a filename), escaping quotes and backslashes
+fn encode_header_param(value: &str) -> String {
+    encode_header_value(value)
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+}
+
+/// base64 encodes the bytes, breaking lines every `MAX_BASE64_LINE_LENGTH` characters
+fn encode_base64_lines(bytes: &[u8]) -> String {
+    let encoded = STANDARD.encode(bytes);
+
+    encoded
+        .as_bytes()
+        .chunks(MAX_BASE64_LINE_LENGTH)
+        .map(|line| std::str::from_utf8(line).unwrap_or_default())
+        .collect::<Vec<_>>()
+        .join("\r\n")
+}
+
+/// the attachment MIME type, falling back to `application/octet-stream` if it is not a `type/subtype`
+fn attachment_content_type(content_type: &str) -> String {
+    let content_type = sanitize_header_value(content_type.trim());
+
+    match content_type.split_once('/') {
+        Some((kind, subtype)) if !kind.is_empty() && !subtype.is_empty() => content_type,
+        _ => String::from("application/octet-stream"),
+    }
+}
+
+impl RawEmail<'_> {
+    /// builds the MIME message, lines are CRLF terminated
+    pub fn to_mime(&self) -> Vec<u8> {
+        let id = Uuid::new_v4().simple().to_string();
+        let mixed_boundary = format!("mixed-{}", id);
+        let alternative_boundary = format!("alternative-{}", id);
+
+        let mut lines = vec![
+            format!("From: {}", sanitize_header_value(self.from)),
+            format!("To: {}", sanitize_header_value(&self.to.join(", "))),
+        ];
+
+        if let Some(reply_to) = self.reply_to.filter(|reply_to| !reply_to.is_empty()) {
+            lines.push(format!(
+                "Reply-To: {}",
+                sanitize_header_value(&reply_to.join(", "))
+            ));
+        }
+
+        lines.extend([
+            format!("Subject: {}", encode_header_value(self.subject)),
+            String::from("MIME-Version: 1.0"),
+            format!(
+                "Content-Type: multipart/mixed; boundary=\"{}\"",
+                mixed_boundary
+            ),
+            String::new(),
+            format!("--{}", mixed_boundary),
+            format!(
+                "Content-Type: multipart/alternative; boundary=\"{}\"",
+                alternative_boundary
+            ),
+            String::new(),
+        ]);
+
+        for (content_type, body) in [("text/plain", self.text), ("text/html", self.html)] {
+            lines.extend([
+                format!("--{}", alternative_boundary),
+                format!("Content-Type: {}; charset=UTF-8", content_type),
+                String::from("Content-Transfer-Encoding: base64"),
+                String::new(),
+                encode_base64_lines(body.as_bytes()),
+            ]);
+        }
+
+        lines.push(format!("--{}--", alternative_boundary));
+
+        for attachment in self.attachments {
+            let filename = encode_header_param(&attachment.filename);
+
+            lines.extend([
+                format!("--{}", mixed_boundary),
+                format!(
+                    "Content-Type: {}; name=\"{}\"",
+                    attachment_content_type(&attachment.content_type),
+                    filename
+                ),
+                format!("Content-Disposition: attachment; filename=\"{}\"", filename),
+                String::from("Content-Transfer-Encoding: base64"),
+                String::new(),
+                encode_base64_lines(&attachment.bytes),
+            ]);
+        }
+
+        lines.push(format!("--{}--", mixed_boundary));
+        lines.push(String::new());
+
+        lines.join("\r\n").into_bytes()
+    }
+}
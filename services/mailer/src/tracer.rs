@@ -1,27 +1,46 @@
 use crate::config::app_config;
 use opentelemetry::sdk::trace::BatchConfig;
+use shared::tracer;
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, EnvFilter, Registry};
 
 /// Initializes application tracing, exporting spans to Jaeger
+///
+/// When tracing is disabled the subscriber is still set but no spans are exported
 pub fn init() {
     let tracer_service_name = &app_config().tracer_service_name;
+    let opts = app_config().tracing_opts();
 
     opentelemetry::global::set_text_map_propagator(opentelemetry_jaeger::Propagator::new());
 
-    let tracer = opentelemetry_jaeger::new_agent_pipeline()
-        .with_service_name(tracer_service_name)
-        .with_auto_split_batch(true)
-        .with_batch_processor_config(BatchConfig::default().with_max_export_batch_size(256))
-        .install_batch(opentelemetry::runtime::Tokio)
-        .expect("failed to initialize tracer");
+    let telemetry = if opts.enabled {
+        let tracer = opentelemetry_jaeger::new_agent_pipeline()
+            .with_service_name(tracer_service_name)
+            .with_auto_split_batch(true)
+            .with_batch_processor_config(BatchConfig::default().with_max_export_batch_size(256))
+            .with_trace_config(opts.trace_config())
+            .install_batch(opentelemetry::runtime::Tokio)
+            .expect("failed to initialize tracer");
 
-    let telemetry = tracing_opentelemetry::layer().with_tracer(tracer);
+        Some(tracing_opentelemetry::layer().with_tracer(tracer))
+    } else {
+        None
+    };
+
+    if let Some(interval) = opts.metrics_export_interval {
+        tracer::init_metrics(tracer_service_name, interval).expect("failed to initialize metrics");
+    }
+
+    let stdout_layer = tracer::stdout_layer(tracer_service_name, app_config().log_format);
 
     let subscriber = Registry::default()
+        .with(stdout_layer)
         .with(telemetry)
         .with(EnvFilter::from_default_env());
 
     tracing::subscriber::set_global_default(subscriber).expect("failed set tracing subscriber");
 
-    println!("[TRACER] initialized as service: {}", tracer_service_name);
+    println!(
+        "[TRACER] initialized as service: {}, exporting spans: {}",
+        tracer_service_name, opts.enabled
+    );
 }
@@ -5,7 +5,37 @@ mod m20240125_134615_init;
 mod m20240125_135000_hypertable_tracker_last_location;
 mod m20240125_135052_last_position_trigger;
 mod m20240128_013232_seed_test_data;
-mod seeder;
+mod m20240210_120000_outbox;
+mod m20240212_090000_vehicle_odometer;
+mod m20240214_100000_sim_card_tracker_same_org;
+mod m20240216_110000_organization_email_sender;
+mod m20240218_090000_sync_fixed_access_level_permissions;
+mod m20240220_100000_sim_card_data_usage;
+mod m20240222_090000_session_impersonation;
+mod m20240224_090000_last_location_point_index;
+mod m20240226_090000_org_feature_flag;
+mod m20240228_090000_vehicle_photo_upload;
+mod m20240301_090000_organization_plan_limits;
+mod m20240303_090000_organization_activity;
+mod m20240305_090000_vehicle_tracker_assignment;
+mod m20240307_090000_user_magic_link_token;
+mod m20240309_090000_organization_ip_allowlist;
+mod m20240311_090000_organization_default_access_level;
+mod m20240313_090000_vehicle_tracker_metadata;
+mod m20240315_090000_socket_connection_ticket;
+mod m20240317_090000_location_device_time;
+mod m20240319_090000_tracker_reporting_interval;
+mod m20240321_090000_unknown_imei_location;
+mod m20240323_090000_view_sim_secrets_permission;
+mod m20240325_090000_login_history;
+mod m20240327_090000_vehicle_tracker_loan;
+mod m20240329_090000_location_compaction_cursor;
+mod m20240331_090000_organization_email_identity;
+mod m20240402_090000_outbox_claim;
+mod m20240403_090000_odometer_cursor_precision;
+mod m20240405_090000_vehicle_tracker_loaned_at;
+mod m20240407_090000_compaction_cursor_precision;
+pub mod seeder;
 mod seeder_consts;
 
 pub struct Migrator;
@@ -19,6 +49,36 @@ impl MigratorTrait for Migrator {
             Box::new(m20240125_135000_hypertable_tracker_last_location::Migration),
             Box::new(m20240125_135052_last_position_trigger::Migration),
             Box::new(m20240128_013232_seed_test_data::Migration),
+            Box::new(m20240210_120000_outbox::Migration),
+            Box::new(m20240212_090000_vehicle_odometer::Migration),
+            Box::new(m20240214_100000_sim_card_tracker_same_org::Migration),
+            Box::new(m20240216_110000_organization_email_sender::Migration),
+            Box::new(m20240218_090000_sync_fixed_access_level_permissions::Migration),
+            Box::new(m20240220_100000_sim_card_data_usage::Migration),
+            Box::new(m20240222_090000_session_impersonation::Migration),
+            Box::new(m20240224_090000_last_location_point_index::Migration),
+            Box::new(m20240226_090000_org_feature_flag::Migration),
+            Box::new(m20240228_090000_vehicle_photo_upload::Migration),
+            Box::new(m20240301_090000_organization_plan_limits::Migration),
+            Box::new(m20240303_090000_organization_activity::Migration),
+            Box::new(m20240305_090000_vehicle_tracker_assignment::Migration),
+            Box::new(m20240307_090000_user_magic_link_token::Migration),
+            Box::new(m20240309_090000_organization_ip_allowlist::Migration),
+            Box::new(m20240311_090000_organization_default_access_level::Migration),
+            Box::new(m20240313_090000_vehicle_tracker_metadata::Migration),
+            Box::new(m20240315_090000_socket_connection_ticket::Migration),
+            Box::new(m20240317_090000_location_device_time::Migration),
+            Box::new(m20240319_090000_tracker_reporting_interval::Migration),
+            Box::new(m20240321_090000_unknown_imei_location::Migration),
+            Box::new(m20240323_090000_view_sim_secrets_permission::Migration),
+            Box::new(m20240325_090000_login_history::Migration),
+            Box::new(m20240327_090000_vehicle_tracker_loan::Migration),
+            Box::new(m20240329_090000_location_compaction_cursor::Migration),
+            Box::new(m20240331_090000_organization_email_identity::Migration),
+            Box::new(m20240402_090000_outbox_claim::Migration),
+            Box::new(m20240403_090000_odometer_cursor_precision::Migration),
+            Box::new(m20240405_090000_vehicle_tracker_loaned_at::Migration),
+            Box::new(m20240407_090000_compaction_cursor_precision::Migration),
         ]
     }
 }
@@ -16,9 +16,9 @@ impl MigrationTrait for Migration {
 
         // maintain this order
         seeder::create_test_master_user(&transaction).await?;
-        let test_user = seeder::create_test_user(&transaction).await?;
+        let test_user_org_id = seeder::create_test_user(&transaction).await?;
 
-        seeder::create_entities_for_org(&transaction, test_user.organization_id.unwrap()).await?;
+        seeder::create_entities_for_org(&transaction, test_user_org_id).await?;
 
         for _ in 0..5 {
             seeder::root_user_with_user_org(&transaction).await.unwrap();
@@ -0,0 +1,37 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+CREATE TABLE "outbox" (
+    "id" bigserial PRIMARY KEY,
+    "created_at" timestamptz(0) NOT NULL DEFAULT now(),
+    "aggregate_type" varchar(255) NOT NULL,
+    "aggregate_id" varchar(255) NOT NULL,
+    "exchange" varchar(255) NOT NULL,
+    "routing_key" varchar(255) NOT NULL,
+    "payload" jsonb NOT NULL,
+    "published_at" timestamptz(0) NULL
+);
+
+COMMENT ON
+TABLE "outbox" IS 'RabbitMQ messages written on the same transaction as the change that created them, published by the outbox relay';
+
+CREATE INDEX idx_outbox_unpublished ON "outbox" ("id") WHERE "published_at" IS NULL;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,58 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+ALTER TABLE "vehicle"
+ADD COLUMN "odometer_km" double precision NOT NULL DEFAULT 0;
+
+CREATE TABLE "vehicle_daily_distance" (
+    "vehicle_id" int NOT NULL,
+    "day" date NOT NULL,
+    "distance_km" double precision NOT NULL DEFAULT 0,
+    CONSTRAINT "vehicle_daily_distance_pkey" PRIMARY KEY ("vehicle_id", "day")
+);
+
+ALTER TABLE "vehicle_daily_distance"
+ADD CONSTRAINT "vehicle_daily_distance_vehicle_id_foreign" FOREIGN KEY ("vehicle_id") REFERENCES "vehicle" ("id")
+ON UPDATE CASCADE
+ON DELETE CASCADE;
+
+CREATE TABLE "vehicle_tracker_odometer_cursor" (
+    "vehicle_tracker_id" int PRIMARY KEY,
+    "vehicle_id" int NOT NULL,
+    "processed_until" timestamptz(0) NOT NULL,
+    "point_time" timestamptz(0) NOT NULL,
+    "lat" double precision NOT NULL,
+    "lng" double precision NOT NULL
+);
+
+COMMENT ON
+TABLE "vehicle_tracker_odometer_cursor" IS 'Last location of a tracker accounted on its vehicle odometer, so the odometer is updated incrementally';
+
+ALTER TABLE "vehicle_tracker_odometer_cursor"
+ADD CONSTRAINT "vehicle_tracker_odometer_cursor_vehicle_tracker_id_foreign" FOREIGN KEY ("vehicle_tracker_id") REFERENCES "vehicle_tracker" ("id")
+ON UPDATE CASCADE
+ON DELETE CASCADE;
+
+ALTER TABLE "vehicle_tracker_odometer_cursor"
+ADD CONSTRAINT "vehicle_tracker_odometer_cursor_vehicle_id_foreign" FOREIGN KEY ("vehicle_id") REFERENCES "vehicle" ("id")
+ON UPDATE CASCADE
+ON DELETE CASCADE;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
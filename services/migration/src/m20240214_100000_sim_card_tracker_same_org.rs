@@ -0,0 +1,39 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // a sim card can only be installed on a tracker of its own organization, this is
+        // enforced with a composite foreign key to (id, organization_id) of the tracker,
+        // since the key uses MATCH SIMPLE a sim card without a tracker is not checked.
+        //
+        // updates are not cascaded, so moving a tracker to another organization fails while
+        // it has sim cards instead of silently moving them to the other organization too.
+        let statement = r#"
+UPDATE "sim_card" AS s
+SET "vehicle_tracker_id" = NULL
+FROM "vehicle_tracker" AS t
+WHERE s."vehicle_tracker_id" = t."id" AND s."organization_id" <> t."organization_id";
+
+ALTER TABLE "vehicle_tracker"
+ADD CONSTRAINT "vehicle_tracker_id_organization_id_unique" UNIQUE ("id", "organization_id");
+
+ALTER TABLE "sim_card"
+ADD CONSTRAINT "sim_card_vehicle_tracker_same_org_foreign" FOREIGN KEY ("vehicle_tracker_id", "organization_id") REFERENCES "vehicle_tracker" ("id", "organization_id")
+ON UPDATE NO ACTION;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
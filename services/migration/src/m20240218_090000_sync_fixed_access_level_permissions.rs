@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+use sea_orm_migration::sea_orm::Statement;
+use shared::constants::Permission;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // grants every permission that exists when this migration runs to the fixed access
+        // levels, permissions created later can be synced with the API or a new migration
+        db.execute(Statement::from_sql_and_values(
+            manager.get_database_backend(),
+            r#"UPDATE "access_level" SET "permissions" = $1 WHERE "is_fixed" = true"#,
+            [Permission::to_string_vec().into()],
+        ))
+        .await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
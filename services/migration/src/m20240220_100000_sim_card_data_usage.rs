@@ -0,0 +1,38 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+ALTER TABLE "sim_card"
+ADD COLUMN "data_quota_mb" int;
+
+CREATE TABLE "sim_card_data_usage" (
+    "sim_card_id" int NOT NULL,
+    "period" date NOT NULL,
+    "data_usage_mb" double precision NOT NULL,
+    "updated_at" timestamptz(0) NOT NULL DEFAULT now(),
+    CONSTRAINT "sim_card_data_usage_pkey" PRIMARY KEY ("sim_card_id", "period"),
+    CONSTRAINT "sim_card_data_usage_period_check" CHECK (EXTRACT(DAY FROM "period") = 1)
+);
+
+ALTER TABLE "sim_card_data_usage"
+ADD CONSTRAINT "sim_card_data_usage_sim_card_id_foreign" FOREIGN KEY ("sim_card_id") REFERENCES "sim_card" ("id")
+ON UPDATE CASCADE
+ON DELETE CASCADE;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
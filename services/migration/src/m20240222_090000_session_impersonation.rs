@@ -0,0 +1,41 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the impersonation log has no foreign keys on purpose, audit records
+        // must be kept even if the users they refer to are deleted
+        let statement = r#"
+ALTER TABLE "session"
+ADD COLUMN "impersonator_id" int REFERENCES "user" (id) ON DELETE CASCADE;
+
+CREATE TABLE "impersonation_log" (
+    "id" serial PRIMARY KEY,
+    "created_at" timestamptz(0) NOT NULL DEFAULT now(),
+    "action" varchar(255) NOT NULL,
+    "impersonator_id" int NOT NULL,
+    "impersonated_user_id" int NOT NULL,
+    "session_public_id" int NOT NULL,
+    "user_agent" varchar(255) NOT NULL,
+    "ip" INET NOT NULL,
+    CONSTRAINT "impersonation_log_action_check" CHECK ("action" IN ('started', 'stopped'))
+);
+
+CREATE INDEX "impersonation_log_impersonator_id_index" ON "impersonation_log" ("impersonator_id");
+CREATE INDEX "impersonation_log_impersonated_user_id_index" ON "impersonation_log" ("impersonated_user_id");
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
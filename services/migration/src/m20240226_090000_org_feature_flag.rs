@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+CREATE TABLE "org_feature_flag" (
+    "organization_id" int NOT NULL,
+    "flag" varchar(255) NOT NULL,
+    "enabled" boolean NOT NULL,
+    "updated_at" timestamptz(0) NOT NULL DEFAULT now(),
+    CONSTRAINT "org_feature_flag_pkey" PRIMARY KEY ("organization_id", "flag")
+);
+
+ALTER TABLE "org_feature_flag"
+ADD CONSTRAINT "org_feature_flag_organization_id_foreign" FOREIGN KEY ("organization_id") REFERENCES "organization" ("id")
+ON UPDATE CASCADE
+ON DELETE CASCADE;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // NULL limits mean the organization plan has no limit
+        let statement = r#"
+ALTER TABLE "organization"
+ADD COLUMN "max_vehicles" int,
+ADD COLUMN "max_trackers" int,
+ADD CONSTRAINT "organization_max_vehicles_check" CHECK ("max_vehicles" >= 0),
+ADD CONSTRAINT "organization_max_trackers_check" CHECK ("max_trackers" >= 0);
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
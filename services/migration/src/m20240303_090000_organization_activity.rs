@@ -0,0 +1,48 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the activity feed is written by the producers of each event instead of being
+        // a view over the event tables, so listing it is a single index scan regardless
+        // of how many event sources are merged into it
+        let statement = r#"
+CREATE TABLE "organization_activity" (
+    "id" bigserial PRIMARY KEY,
+    "created_at" timestamptz(0) NOT NULL DEFAULT now(),
+    "organization_id" int NOT NULL REFERENCES "organization" (id) ON DELETE CASCADE,
+    "type" varchar(255) NOT NULL,
+    "payload" jsonb NOT NULL
+);
+
+CREATE INDEX "organization_activity_organization_id_created_at_index"
+ON "organization_activity" ("organization_id", "created_at" DESC, "id" DESC);
+
+INSERT INTO "organization_activity" ("created_at", "organization_id", "type", "payload")
+SELECT
+    l."created_at",
+    u."organization_id",
+    CASE l."action" WHEN 'started' THEN 'impersonationStarted' ELSE 'impersonationStopped' END,
+    jsonb_build_object(
+        'impersonatedUserId', l."impersonated_user_id",
+        'sessionPublicId', l."session_public_id"
+    )
+FROM "impersonation_log" l
+JOIN "user" u ON u."id" = l."impersonated_user_id"
+WHERE u."organization_id" IS NOT NULL;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
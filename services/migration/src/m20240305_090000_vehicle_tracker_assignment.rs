@@ -0,0 +1,77 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the assignments are kept even if the tracker is deleted, so the vehicle location
+        // history is not changed by deleting a tracker that was installed on it before
+        let statement = r#"
+CREATE TABLE "vehicle_tracker_assignment" (
+    "id" serial PRIMARY KEY,
+    "organization_id" int NOT NULL REFERENCES "organization" (id) ON DELETE CASCADE,
+    "vehicle_id" int NOT NULL REFERENCES "vehicle" (id) ON DELETE CASCADE,
+    "vehicle_tracker_id" int NOT NULL,
+    "started_at" timestamptz NOT NULL DEFAULT now(),
+    "ended_at" timestamptz NULL,
+    CONSTRAINT "vehicle_tracker_assignment_interval_check" CHECK ("ended_at" IS NULL OR "ended_at" >= "started_at")
+);
+
+CREATE INDEX "vehicle_tracker_assignment_vehicle_id_started_at_index"
+ON "vehicle_tracker_assignment" ("vehicle_id", "started_at");
+
+CREATE UNIQUE INDEX "vehicle_tracker_assignment_open_tracker_unique"
+ON "vehicle_tracker_assignment" ("vehicle_tracker_id") WHERE "ended_at" IS NULL;
+
+-- when the history starts the moment a tracker was installed is unknown, so
+-- trackers installed on a vehicle are considered to be since their creation
+INSERT INTO "vehicle_tracker_assignment" ("organization_id", "vehicle_id", "vehicle_tracker_id", "started_at")
+SELECT "organization_id", "vehicle_id", "id", "created_at"
+FROM "vehicle_tracker"
+WHERE "vehicle_id" IS NOT NULL;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        // keeps the assignments in sync with `vehicle_tracker.vehicle_id`, regardless of
+        // which query changed it (including the `SET NULL` of a vehicle being deleted)
+        let statement = r#"
+CREATE OR REPLACE FUNCTION track_vehicle_tracker_assignment_fn() RETURNS TRIGGER LANGUAGE PLPGSQL AS
+$BODY$
+    BEGIN
+        IF TG_OP = 'DELETE' OR (TG_OP = 'UPDATE' AND NEW.vehicle_id IS DISTINCT FROM OLD.vehicle_id) THEN
+            UPDATE vehicle_tracker_assignment SET ended_at = now()
+            WHERE vehicle_tracker_id = OLD.id AND ended_at IS NULL;
+        END IF;
+
+        IF TG_OP = 'DELETE' THEN
+            RETURN NULL;
+        END IF;
+
+        IF NEW.vehicle_id IS NOT NULL AND (TG_OP = 'INSERT' OR NEW.vehicle_id IS DISTINCT FROM OLD.vehicle_id) THEN
+            INSERT INTO vehicle_tracker_assignment (organization_id, vehicle_id, vehicle_tracker_id)
+            VALUES (NEW.organization_id, NEW.vehicle_id, NEW.id);
+        END IF;
+
+        RETURN NULL;
+    END
+$BODY$;
+
+CREATE TRIGGER track_vehicle_tracker_assignment_trigger
+AFTER INSERT OR UPDATE OF vehicle_id OR DELETE ON vehicle_tracker
+FOR EACH ROW EXECUTE PROCEDURE track_vehicle_tracker_assignment_fn();
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,28 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+ALTER TABLE "organization"
+ADD COLUMN "default_access_level_id" INTEGER NULL;
+
+ALTER TABLE "organization"
+ADD CONSTRAINT "organization_default_access_level_id_foreign" FOREIGN KEY ("default_access_level_id")
+REFERENCES "access_level" ("id") ON DELETE SET NULL ON UPDATE CASCADE;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
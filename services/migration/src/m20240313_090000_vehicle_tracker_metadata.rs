@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+ALTER TABLE "vehicle_tracker"
+ADD COLUMN "firmware_version" VARCHAR(255) NULL,
+ADD COLUMN "notes" TEXT NULL,
+ADD COLUMN "installed_at" timestamptz(0) NULL;
+
+CREATE INDEX "vehicle_tracker_organization_id_firmware_version_index"
+ON "vehicle_tracker" ("organization_id", "firmware_version");
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+CREATE TABLE "socket_connection_ticket" (
+    "ticket" varchar(64) PRIMARY KEY,
+    "created_at" timestamptz(0) NOT NULL DEFAULT now(),
+    "expires_at" timestamptz(0) NOT NULL,
+    "user_id" int NOT NULL
+);
+
+ALTER TABLE "socket_connection_ticket"
+ADD CONSTRAINT "socket_connection_ticket_user_id_foreign" FOREIGN KEY ("user_id")
+REFERENCES "user" ("id") ON DELETE CASCADE ON UPDATE CASCADE;
+
+CREATE INDEX "socket_connection_ticket_expires_at_index" ON "socket_connection_ticket" ("expires_at");
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
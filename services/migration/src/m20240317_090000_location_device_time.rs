@@ -0,0 +1,47 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // `time` stays the time locations are ordered and bucketed by, it is the time reported by
+        // the tracker unless it was implausible, in which case it is the time it was received
+        let statement = r#"
+ALTER TABLE "vehicle_tracker_location"
+ADD COLUMN "device_time" timestamptz(0) NULL,
+ADD COLUMN "received_at" timestamptz(0) NULL;
+
+ALTER TABLE "vehicle_tracker_last_location"
+ADD COLUMN "device_time" timestamptz(0) NULL,
+ADD COLUMN "received_at" timestamptz(0) NULL;
+
+CREATE OR REPLACE FUNCTION create_last_pos_trigger_fn() RETURNS TRIGGER LANGUAGE PLPGSQL AS
+$BODY$
+    BEGIN
+        INSERT INTO vehicle_tracker_last_location (vehicle_tracker_id, point, time, device_time, received_at)
+        VALUES (NEW.vehicle_tracker_id, NEW.point, NEW.time, NEW.device_time, NEW.received_at)
+        ON CONFLICT (vehicle_tracker_id) DO UPDATE SET
+        point = NEW.point,
+        time = NEW.time,
+        device_time = NEW.device_time,
+        received_at = NEW.received_at
+        -- locations arriving out of order must not replace a more recent last location
+        WHERE vehicle_tracker_last_location.time <= NEW.time;
+        RETURN NEW;
+    END
+$BODY$;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
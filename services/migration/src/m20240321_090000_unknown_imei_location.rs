@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // not a hypertable as, unlike `vehicle_tracker_location`, it is kept small by the
+        // API deleting its rows once they are older than the retention or over the limits
+        let statement = r#"
+CREATE TABLE "unknown_imei_location" (
+    "id" bigserial PRIMARY KEY,
+    "imei" varchar(255) NOT NULL,
+    "protocol" varchar(32) NOT NULL,
+    "time" timestamptz(0) NOT NULL,
+    "device_time" timestamptz(0) NOT NULL,
+    "received_at" timestamptz(0) NOT NULL,
+    "point" geometry NOT NULL
+);
+
+CREATE INDEX "unknown_imei_location_imei_received_at_idx" ON "unknown_imei_location" ("imei", "received_at" DESC);
+CREATE INDEX "unknown_imei_location_received_at_idx" ON "unknown_imei_location" ("received_at");
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // PIN and PUK codes were visible to every user, so the permission to see them is
+        // granted to the fixed access levels and to the ones that can already set them
+        let statement = r#"
+UPDATE "access_level"
+SET "permissions" = array_append("permissions", 'VIEW_SIM_SECRETS')
+WHERE NOT ('VIEW_SIM_SECRETS' = ANY("permissions"))
+AND (
+    "is_fixed" = true
+    OR 'CREATE_SIM_CARD' = ANY("permissions")
+    OR 'UPDATE_SIM_CARD' = ANY("permissions")
+);
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the session public id is not a foreign key as the history outlives the sessions
+        let statement = r#"
+CREATE TABLE "login_history" (
+    "id" serial PRIMARY KEY,
+    "created_at" timestamptz(0) NOT NULL DEFAULT now(),
+    "user_id" int NOT NULL REFERENCES "user" (id) ON DELETE CASCADE,
+    "session_public_id" int NOT NULL,
+    "user_agent" varchar(255) NOT NULL,
+    "ip" INET NOT NULL
+);
+
+CREATE INDEX "login_history_user_id_created_at_index" ON "login_history" ("user_id", "created_at" DESC);
+CREATE INDEX "login_history_created_at_index" ON "login_history" ("created_at");
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,36 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // a loan gives the borrowing organization read only access to the tracker until
+        // it expires, expired loans are ignored by the API so they do not need to be cleared.
+        // `loan_expires_at` can be set without a borrower, as deleting the borrowing
+        // organization only clears `loaned_to_org_id`
+        let statement = r#"
+ALTER TABLE "vehicle_tracker"
+ADD COLUMN "loaned_to_org_id" int NULL REFERENCES "organization" (id) ON DELETE SET NULL,
+ADD COLUMN "loan_expires_at" timestamptz(0) NULL,
+ADD CONSTRAINT "vehicle_tracker_loan_check" CHECK (
+    ("loaned_to_org_id" IS NULL OR "loan_expires_at" IS NOT NULL)
+    AND "loaned_to_org_id" IS DISTINCT FROM "organization_id"
+);
+
+CREATE INDEX "vehicle_tracker_loaned_to_org_id_index" ON "vehicle_tracker" ("loaned_to_org_id")
+WHERE "loaned_to_org_id" IS NOT NULL;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
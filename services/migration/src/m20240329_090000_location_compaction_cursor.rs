@@ -0,0 +1,34 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        let statement = r#"
+CREATE TABLE "vehicle_tracker_location_compaction_cursor" (
+    "vehicle_tracker_id" int PRIMARY KEY,
+    "compacted_until" timestamptz(0) NOT NULL
+);
+
+COMMENT ON
+TABLE "vehicle_tracker_location_compaction_cursor" IS 'Time up to which the locations of a tracker were downsampled, so old locations are compacted incrementally';
+
+ALTER TABLE "vehicle_tracker_location_compaction_cursor"
+ADD CONSTRAINT "vehicle_tracker_location_compaction_cursor_vehicle_tracker_id_foreign" FOREIGN KEY ("vehicle_tracker_id") REFERENCES "vehicle_tracker" ("id")
+ON UPDATE CASCADE
+ON DELETE CASCADE;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
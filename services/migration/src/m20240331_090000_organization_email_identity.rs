@@ -0,0 +1,32 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // SES identities are shared by every organization on the same AWS account, so the
+        // organization that started the verification of each identity is recorded to only
+        // allow it to use the identity as its email sender
+        let statement = r#"
+CREATE TABLE "organization_email_identity" (
+    "identity" varchar(255) PRIMARY KEY,
+    "created_at" timestamptz(0) NOT NULL DEFAULT now(),
+    "organization_id" int NOT NULL REFERENCES "organization" (id) ON DELETE CASCADE
+);
+
+CREATE INDEX "organization_email_identity_organization_id_index" ON "organization_email_identity" ("organization_id");
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,29 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the relay claims a batch of messages and commits before publishing them, so
+        // it does not keep a transaction open while waiting for the broker confirms.
+        // a claim expires so messages claimed by a relay that crashed are published again
+        let statement = r#"
+ALTER TABLE "outbox" ADD COLUMN "claimed_until" timestamptz NULL;
+
+COMMENT ON
+COLUMN "outbox"."claimed_until" IS 'until when the message is claimed by a outbox relay publishing it';
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
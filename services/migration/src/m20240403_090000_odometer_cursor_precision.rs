@@ -0,0 +1,25 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // timestamptz(0) rounds the cursor to the nearest second, so a cursor rounded up
+        // would skip the locations received on the fraction of second it was moved past
+        let statement = r#"
+ALTER TABLE "vehicle_tracker_odometer_cursor" ALTER COLUMN "processed_until" TYPE timestamptz;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
@@ -0,0 +1,35 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // the borrowing organization can only read the locations sent during the loan, when
+        // the loans started is unknown for the existing ones, so they are considered to start
+        // now instead of exposing the locations sent before them
+        let statement = r#"
+ALTER TABLE "vehicle_tracker"
+ADD COLUMN "loaned_at" timestamptz NULL;
+
+UPDATE "vehicle_tracker" SET "loaned_at" = now()
+WHERE "loan_expires_at" IS NOT NULL;
+
+ALTER TABLE "vehicle_tracker"
+ADD CONSTRAINT "vehicle_tracker_loaned_at_check" CHECK (
+    ("loan_expires_at" IS NULL) = ("loaned_at" IS NULL)
+);
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
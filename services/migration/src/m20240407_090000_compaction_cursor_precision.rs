@@ -0,0 +1,25 @@
+use sea_orm_migration::prelude::*;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+    async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+        let db = manager.get_connection();
+
+        // a cursor rounded down makes the next run select again the last location it kept,
+        // rounded up the locations on the fraction of second it was moved past are skipped
+        let statement = r#"
+ALTER TABLE "vehicle_tracker_location_compaction_cursor" ALTER COLUMN "compacted_until" TYPE timestamptz;
+        "#;
+
+        db.execute_unprepared(statement).await?;
+
+        Ok(())
+    }
+
+    async fn down(&self, _manager: &SchemaManager) -> Result<(), DbErr> {
+        Err(DbErr::Custom(String::from("cannot be reverted")))
+    }
+}
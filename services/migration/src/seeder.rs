@@ -1,10 +1,15 @@
-use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{
+    atomic::{AtomicU16, Ordering},
+    Mutex,
+};
 
 use crate::seeder_consts;
-use fake::{faker, Fake};
-use rand::{seq::SliceRandom, Rng};
+use fake::{faker, Dummy, Fake};
+use lazy_static::lazy_static;
+use rand::{seq::SliceRandom, Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use sea_orm_migration::{
-    sea_orm::{ActiveModelTrait, ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter, Set},
+    sea_orm::{ColumnTrait, DatabaseTransaction, EntityTrait, QueryFilter, Set},
     sea_query::Expr,
     DbErr,
 };
@@ -16,6 +21,29 @@ const NUMERIC: &str = "0123456789";
 
 static UNIQUE_CNT: AtomicU16 = AtomicU16::new(0);
 
+lazy_static! {
+    /// RNG used to generate all the seeded data, seeded from the `SEEDER_RNG_SEED`
+    /// env var if set, so the same data is generated on every run (on a empty database),
+    /// otherwise seeded from entropy so the data is different on every run.
+    static ref RNG: Mutex<ChaCha8Rng> = Mutex::new(match std::env::var("SEEDER_RNG_SEED") {
+        Ok(seed) => ChaCha8Rng::seed_from_u64(
+            seed.parse()
+                .expect("SEEDER_RNG_SEED must be a unsigned 64 bit integer"),
+        ),
+        Err(_) => ChaCha8Rng::from_entropy(),
+    });
+}
+
+/// runs `f` with the seeder RNG, all the randomness of the seeder must come from it
+pub fn with_rng<T>(f: impl FnOnce(&mut ChaCha8Rng) -> T) -> T {
+    f(&mut RNG.lock().expect("seeder RNG mutex poisoned"))
+}
+
+/// generates a fake value with the seeder RNG
+fn fake_value<U: Dummy<T>, T>(faker: T) -> U {
+    with_rng(|rng| faker.fake_with_rng(rng))
+}
+
 /// gets ID that is guaranteed to be unique during the execution of this binary
 fn get_unique_id() -> u16 {
     UNIQUE_CNT.fetch_add(1, Ordering::SeqCst) + 1
@@ -28,13 +56,11 @@ fn hash_password(plain: String) -> String {
 }
 
 fn fake_password() -> String {
-    hash_password(faker::internet::en::Password(10..50).fake::<String>())
+    hash_password(fake_value(faker::internet::en::Password(10..50)))
 }
 
 fn fake_words(range: std::ops::Range<usize>) -> String {
-    faker::lorem::en::Words(range)
-        .fake::<Vec<String>>()
-        .join(" ")
+    fake_value::<Vec<String>, _>(faker::lorem::en::Words(range)).join(" ")
 }
 
 /// Creates a brazilian vehicle plate in the `AAA9999` format, where:
@@ -42,19 +68,19 @@ fn fake_words(range: std::ops::Range<usize>) -> String {
 /// - A = uppercase alphabetic characters
 /// - 9 = numbers 0 to 9
 fn fake_br_vehicle_plate() -> String {
-    let a: String = fake::StringFaker::with(Vec::from(ALPHA), 3).fake();
-    let b: String = fake::StringFaker::with(Vec::from(NUMERIC), 4).fake();
+    let a: String = fake_value(fake::StringFaker::with(Vec::from(ALPHA), 3));
+    let b: String = fake_value(fake::StringFaker::with(Vec::from(NUMERIC), 4));
 
     a.to_string() + b.as_str()
 }
 
 fn fake_imei() -> String {
-    fake::StringFaker::with(Vec::from(ALPHA), 20).fake()
+    fake_value(fake::StringFaker::with(Vec::from(NUMERIC), 15))
 }
 
 /// Creates a random boolean with a certain % of chance to be `true`
 fn fake_bool_with_chance(chance_to_be_true: u8) -> bool {
-    let n = rand::thread_rng().gen_range(0..100);
+    let n = with_rng(|rng| rng.gen_range(0..100));
 
     n < chance_to_be_true
 }
@@ -63,73 +89,73 @@ fn fake_bool_with_chance(chance_to_be_true: u8) -> bool {
 ///
 /// see: https://www.sciencedirect.com/topics/computer-science/personal-identification-number
 fn fake_pin_number() -> String {
-    rand::thread_rng().gen_range(1000..9999).to_string()
+    with_rng(|rng| rng.gen_range(1000..9999)).to_string()
 }
 
 /// Creates a random SIM card PUK (personal unlocking key)
 ///
 /// see: https://www.sciencedirect.com/topics/computer-science/personal-identification-number
 fn fake_puk_code() -> String {
-    rand::thread_rng().gen_range(10000..999999).to_string()
+    with_rng(|rng| rng.gen_range(10000..999999)).to_string()
 }
 
 /// Creates a random SIM card SSN
 fn fake_sim_ssn() -> String {
-    format!("00{}", rand::thread_rng().gen_range(10000..999999))
+    format!("00{}", with_rng(|rng| rng.gen_range(10000..999999)))
 }
 
 fn fake_phone_number() -> String {
-    let mut rng = rand::thread_rng();
-
     // Country code (e.g., +1 for United States)
-    let country_code: u16 = rng.gen_range(1..100);
+    let country_code: u16 = with_rng(|rng| rng.gen_range(1..100));
 
     // Random 9-digit number for the national significant number
-    let national_number: u64 = rng.gen_range(1_000_000_000..1_000_000_000_000);
+    let national_number: u64 = with_rng(|rng| rng.gen_range(1_000_000_000..1_000_000_000_000));
 
     format!("+{}{}", country_code, national_number)
 }
 
-pub async fn gen_organization(db: &DatabaseTransaction) -> Result<organization::Model, DbErr> {
-    let org = organization::ActiveModel {
-        name: Set(faker::company::en::CompanyName().fake::<String>()),
+pub async fn gen_organization(db: &DatabaseTransaction) -> Result<i32, DbErr> {
+    let org_id = organization::Entity::insert(organization::ActiveModel {
+        name: Set(fake_value::<String, _>(faker::company::en::CompanyName())),
         blocked: Set(false),
-        billing_email: Set(faker::internet::en::SafeEmail().fake::<String>()),
+        billing_email: Set(fake_value::<String, _>(faker::internet::en::SafeEmail())),
         billing_email_verified: Set(true),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(org)
+    Ok(org_id)
 }
 
 pub async fn gen_tracker(
     db: &DatabaseTransaction,
     org_id: i32,
     vehicle_id: Option<i32>,
-) -> Result<vehicle_tracker::Model, DbErr> {
-    let t = vehicle_tracker::ActiveModel {
+) -> Result<i32, DbErr> {
+    let tracker_id = vehicle_tracker::Entity::insert(vehicle_tracker::ActiveModel {
         model: Set(TrackerModel::H02),
         imei: Set(fake_imei()),
         vehicle_id: Set(vehicle_id),
         organization_id: Set(org_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(t)
+    Ok(tracker_id)
 }
 
 pub async fn gen_sim_card(
     db: &DatabaseTransaction,
     org_id: i32,
     vehicle_tracker_id: Option<i32>,
-) -> Result<sim_card::Model, DbErr> {
+) -> Result<i32, DbErr> {
     let apn = seeder_consts::get_fake_apn();
 
-    let t = sim_card::ActiveModel {
+    let sim_card_id = sim_card::Entity::insert(sim_card::ActiveModel {
         phone_number: Set(fake_phone_number()),
         ssn: Set(fake_sim_ssn()),
         apn_user: Set(apn.user),
@@ -142,33 +168,31 @@ pub async fn gen_sim_card(
         vehicle_tracker_id: Set(vehicle_tracker_id),
         organization_id: Set(org_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(t)
+    Ok(sim_card_id)
 }
 
-pub async fn gen_vehicle(db: &DatabaseTransaction, org_id: i32) -> Result<vehicle::Model, DbErr> {
-    let color = seeder_consts::COLORS
-        .choose(&mut rand::thread_rng())
+pub async fn gen_vehicle(db: &DatabaseTransaction, org_id: i32) -> Result<i32, DbErr> {
+    let color = with_rng(|rng| seeder_consts::COLORS.choose(rng))
         .unwrap()
         .to_string();
 
-    let brand = seeder_consts::CAR_BRANDS
-        .choose(&mut rand::thread_rng())
+    let brand = with_rng(|rng| seeder_consts::CAR_BRANDS.choose(rng))
         .unwrap()
         .to_string();
 
     // we dont care if the model does not belong to the brand, seeded data can be silly
-    let model = seeder_consts::VEHICLE_MODELS
-        .choose(&mut rand::thread_rng())
+    let model = with_rng(|rng| seeder_consts::VEHICLE_MODELS.choose(rng))
         .unwrap()
         .to_string();
 
-    let fabrication_year = rand::thread_rng().gen_range(2000..2024);
+    let fabrication_year = with_rng(|rng| rng.gen_range(2000..2024));
 
-    let v = vehicle::ActiveModel {
+    let vehicle_id = vehicle::Entity::insert(vehicle::ActiveModel {
         plate: Set(fake_br_vehicle_plate()),
         model_year: Set(Some(fabrication_year + 1)),
         fabrication_year: Set(Some(fabrication_year)),
@@ -177,11 +201,12 @@ pub async fn gen_vehicle(db: &DatabaseTransaction, org_id: i32) -> Result<vehicl
         model: Set(Some(model)),
         organization_id: Set(org_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(v)
+    Ok(vehicle_id)
 }
 
 pub async fn gen_access_level(
@@ -189,41 +214,42 @@ pub async fn gen_access_level(
     is_fixed: bool,
     org_id: Option<i32>,
     permissions: Vec<String>,
-) -> Result<access_level::Model, DbErr> {
-    let lev = access_level::ActiveModel {
-        name: Set(faker::lorem::en::Word().fake::<String>()),
+) -> Result<i32, DbErr> {
+    let access_level_id = access_level::Entity::insert(access_level::ActiveModel {
+        name: Set(fake_value::<String, _>(faker::lorem::en::Word())),
         is_fixed: Set(is_fixed),
         description: Set(fake_words(5..10)),
         permissions: Set(permissions),
         organization_id: Set(org_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(lev)
+    Ok(access_level_id)
 }
 
 pub async fn gen_user(
     db: &DatabaseTransaction,
     org_id: i32,
     access_level_id: i32,
-) -> Result<user::Model, DbErr> {
+) -> Result<i32, DbErr> {
     // those random numbers are to void unique conflicts
     let email = format!(
         "{}_{}",
         get_unique_id(),
-        faker::internet::en::SafeEmail().fake::<String>()
+        fake_value::<String, _>(faker::internet::en::SafeEmail())
     );
 
     let username = format!(
         "{}_{}",
         get_unique_id(),
-        faker::internet::en::Username().fake::<String>()
+        fake_value::<String, _>(faker::internet::en::Username())
     );
 
-    let lev = user::ActiveModel {
-        email_verified: Set(faker::boolean::en::Boolean(50).fake::<bool>()),
+    let user_id = user::Entity::insert(user::ActiveModel {
+        email_verified: Set(fake_value::<bool, _>(faker::boolean::en::Boolean(50))),
         username: Set(username),
         password: Set(fake_password()),
         email: Set(email),
@@ -232,85 +258,90 @@ pub async fn gen_user(
         organization_id: Set(Some(org_id)),
         access_level_id: Set(access_level_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(lev)
+    Ok(user_id)
 }
 
-pub async fn create_test_master_user(db: &DatabaseTransaction) -> Result<user::Model, DbErr> {
-    let test_master_user_access_level =
+pub async fn create_test_master_user(db: &DatabaseTransaction) -> Result<i32, DbErr> {
+    let test_master_user_access_level_id =
         gen_access_level(db, true, None, Permission::to_string_vec()).await?;
 
-    let u = user::ActiveModel {
+    let user_id = user::Entity::insert(user::ActiveModel {
         username: Set(String::from("test_master_user")),
         password: Set(hash_password(String::from("testmasteruser"))),
 
         email: Set(String::from("rastercar.tests.001@gmail.com")),
         email_verified: Set(true),
-        access_level_id: Set(test_master_user_access_level.id),
+        access_level_id: Set(test_master_user_access_level_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    Ok(u)
+    Ok(user_id)
 }
 
-pub async fn create_test_user(db: &DatabaseTransaction) -> Result<user::Model, DbErr> {
-    let test_user_organization = organization::ActiveModel {
+/// creates the test user and its organization, returning the id of the organization
+pub async fn create_test_user(db: &DatabaseTransaction) -> Result<i32, DbErr> {
+    let test_user_organization_id = organization::Entity::insert(organization::ActiveModel {
         name: Set(String::from("test user org")),
         blocked: Set(false),
         billing_email: Set(String::from("testuser@gmail.com")),
         billing_email_verified: Set(false),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
-    let test_user_access_level = gen_access_level(
+    let test_user_access_level_id = gen_access_level(
         db,
         true,
-        Some(test_user_organization.id),
+        Some(test_user_organization_id),
         Permission::to_string_vec(),
     )
     .await?;
 
-    let u = user::ActiveModel {
+    let user_id = user::Entity::insert(user::ActiveModel {
         email: Set(String::from("rastercar.tests.002@gmail.com")),
         email_verified: Set(true),
         username: Set(String::from("test_user")),
         password: Set(hash_password(String::from("testuser"))),
         description: Set(Some(fake_words(5..10))),
-        organization_id: Set(Some(test_user_organization.id)),
-        access_level_id: Set(test_user_access_level.id),
+        organization_id: Set(Some(test_user_organization_id)),
+        access_level_id: Set(test_user_access_level_id),
         ..Default::default()
-    }
-    .insert(db)
-    .await?;
+    })
+    .exec(db)
+    .await?
+    .last_insert_id;
 
     organization::Entity::update_many()
-        .col_expr(organization::Column::OwnerId, Expr::value(u.id))
-        .filter(organization::Column::Id.eq(test_user_organization.id))
+        .col_expr(organization::Column::OwnerId, Expr::value(user_id))
+        .filter(organization::Column::Id.eq(test_user_organization_id))
         .exec(db)
         .await?;
 
-    Ok(u)
+    Ok(test_user_organization_id)
 }
 
 pub async fn create_entities_for_org(db: &DatabaseTransaction, org_id: i32) -> Result<(), DbErr> {
     // create some vehicles
     for _ in 0..50 {
-        let vehicle = gen_vehicle(db, org_id).await?;
+        let vehicle_id = gen_vehicle(db, org_id).await?;
 
         // for 75% of the vehicles, create a tracker and possibly its SIM card(s)
         if fake_bool_with_chance(75) {
-            let tracker = gen_tracker(db, org_id, Some(vehicle.id)).await?;
+            let tracker_id = gen_tracker(db, org_id, Some(vehicle_id)).await?;
 
             // the tracker has a 80% chance of having a SIM CARD
             if fake_bool_with_chance(80) {
-                gen_sim_card(db, org_id, Some(tracker.id)).await?;
+                gen_sim_card(db, org_id, Some(tracker_id)).await?;
             }
         }
     }
@@ -326,7 +357,7 @@ pub async fn create_entities_for_org(db: &DatabaseTransaction, org_id: i32) -> R
     }
 
     // create a secondary access level for the org
-    let org_non_root_access_level = gen_access_level(db, false, Some(org_id), vec![]).await?;
+    let org_non_root_access_level_id = gen_access_level(db, false, Some(org_id), vec![]).await?;
 
     for _ in 0..5 {
         gen_access_level(db, false, Some(org_id), vec![]).await?;
@@ -334,26 +365,26 @@ pub async fn create_entities_for_org(db: &DatabaseTransaction, org_id: i32) -> R
 
     // create some users for the org
     for _ in 0..50 {
-        gen_user(db, org_id, org_non_root_access_level.id).await?;
+        gen_user(db, org_id, org_non_root_access_level_id).await?;
     }
 
     Ok(())
 }
 
 pub async fn root_user_with_user_org(db: &DatabaseTransaction) -> Result<(), DbErr> {
-    let user_org = gen_organization(db).await?;
-    let access_level =
-        gen_access_level(db, true, Some(user_org.id), Permission::to_string_vec()).await?;
+    let user_org_id = gen_organization(db).await?;
+    let access_level_id =
+        gen_access_level(db, true, Some(user_org_id), Permission::to_string_vec()).await?;
 
-    let org_root_user = gen_user(db, user_org.id, access_level.id).await?;
+    let org_root_user_id = gen_user(db, user_org_id, access_level_id).await?;
 
     organization::Entity::update_many()
-        .col_expr(organization::Column::OwnerId, Expr::value(org_root_user.id))
-        .filter(organization::Column::Id.eq(user_org.id))
+        .col_expr(organization::Column::OwnerId, Expr::value(org_root_user_id))
+        .filter(organization::Column::Id.eq(user_org_id))
         .exec(db)
         .await?;
 
-    create_entities_for_org(db, user_org.id).await?;
+    create_entities_for_org(db, user_org_id).await?;
 
     Ok(())
 }
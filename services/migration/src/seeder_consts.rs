@@ -2,7 +2,7 @@ use lazy_static::lazy_static;
 use rand::seq::SliceRandom;
 
 /// SEE: https://github.com/cheprasov/json-colors/blob/master/colors.json
-pub const COLORS: [&str; 1302] = [
+pub static COLORS: [&str; 1302] = [
     "Absolute Zero",
     "Acid Green",
     "Aero",
@@ -2363,5 +2363,5 @@ lazy_static! {
 }
 
 pub fn get_fake_apn() -> FakeApn {
-    APN_LIST.choose(&mut rand::thread_rng()).unwrap().clone()
+    crate::seeder::with_rng(|rng| APN_LIST.choose(rng).unwrap().clone())
 }
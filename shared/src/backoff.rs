@@ -0,0 +1,113 @@
+//! Reconnection backoff shared by the services that keep a connection to RabbitMQ.
+//!
+//! the delay doubles on every failed attempt up to a maximum, a random jitter is applied so
+//! every service instance does not reconnect at the same time when the broker restarts, and
+//! once the connection is down for too long the outage is reported loudly, while retrying
+//! endlessly, so operators are alerted instead of the service silently being degraded.
+
+use opentelemetry::{metrics::Counter, Context};
+use rand::Rng;
+use std::time::{Duration, Instant};
+use tracing::{error, warn};
+
+/// When a connection outage is reported as critical
+#[derive(Debug, Clone, Copy)]
+pub struct OutageAlarm {
+    /// consecutive failed attempts after which the outage is critical
+    pub after_failures: u32,
+
+    /// time since the connection was lost after which the outage is critical
+    pub after: Duration,
+}
+
+/// Exponential backoff with jitter for reconnection attempts
+pub struct ReconnectBackoff {
+    /// name of the connection on logs and metrics, eg: `rabbitmq`
+    name: &'static str,
+
+    initial_delay: Duration,
+    max_delay: Duration,
+    alarm: OutageAlarm,
+
+    /// consecutive failed attempts
+    failures: u32,
+
+    /// when the connection was lost, `None` while connected
+    down_since: Option<Instant>,
+
+    failures_counter: Counter<u64>,
+}
+
+impl ReconnectBackoff {
+    pub fn new(
+        name: &'static str,
+        initial_delay: Duration,
+        max_delay: Duration,
+        alarm: OutageAlarm,
+    ) -> Self {
+        let failures_counter = opentelemetry::global::meter("reconnect_backoff")
+            .u64_counter(format!("{}.reconnect_failures", name))
+            .init();
+
+        Self {
+            name,
+            initial_delay,
+            max_delay,
+            alarm,
+            failures: 0,
+            down_since: None,
+            failures_counter,
+        }
+    }
+
+    /// records a failed connection attempt (or a dropped connection) and returns how long to
+    /// wait before the next attempt, logging a error on every attempt once the outage is critical
+    pub fn failed(&mut self) -> Duration {
+        self.failures = self.failures.saturating_add(1);
+        self.failures_counter.add(&Context::current(), 1, &[]);
+
+        let down_since = *self.down_since.get_or_insert_with(Instant::now);
+        let down_for = down_since.elapsed();
+
+        if self.is_critical() {
+            error!(
+                connection = self.name,
+                failures = self.failures,
+                down_for_seconds = down_for.as_secs(),
+                "connection down for too long, still retrying"
+            );
+        } else {
+            warn!(
+                connection = self.name,
+                failures = self.failures,
+                "connection attempt failed"
+            );
+        }
+
+        // equal jitter: half of the delay is fixed so attempts never get too close
+        // together, and the other half is random to spread the attempts of instances
+        let exponent = (self.failures - 1).min(16);
+        let delay = self
+            .initial_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+
+        let half = delay / 2;
+        half + half.mul_f64(rand::thread_rng().gen::<f64>())
+    }
+
+    /// records a successful connection, restarting the backoff
+    pub fn connected(&mut self) {
+        self.failures = 0;
+        self.down_since = None;
+    }
+
+    /// if the connection is down for longer or more attempts than the outage alarm allows
+    pub fn is_critical(&self) -> bool {
+        let Some(down_since) = self.down_since else {
+            return false;
+        };
+
+        self.failures >= self.alarm.after_failures || down_since.elapsed() >= self.alarm.after
+    }
+}
@@ -0,0 +1,23 @@
+use validator::ValidationErrors;
+
+/// lists the invalid env vars of a service config and why they are invalid, one per line
+pub fn describe_validation_errors(errors: &ValidationErrors) -> String {
+    let mut lines: Vec<String> = errors
+        .field_errors()
+        .into_iter()
+        .map(|(field, field_errors)| {
+            let reasons: Vec<String> = field_errors
+                .iter()
+                .map(|e| match &e.message {
+                    Some(msg) => msg.to_string(),
+                    None => e.code.to_string(),
+                })
+                .collect();
+
+            format!("  {}: {}", field.to_uppercase(), reasons.join(", "))
+        })
+        .collect();
+
+    lines.sort();
+    lines.join("\n")
+}
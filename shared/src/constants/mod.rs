@@ -28,6 +28,7 @@ pub enum Permission {
     DeleteSimCard,
     UpdateSimCard,
     CreateSimCard,
+    ViewSimSecrets,
 
     UpdateOrganization,
 }
@@ -41,6 +42,38 @@ impl Permission {
     }
 }
 
+/// Features that can be enabled or disabled per organization, used to
+/// gradually roll out features
+///
+/// stored on the `org_feature_flag` table in screaming snake case
+#[derive(
+    Eq, Copy, Hash, Clone, Debug, Display, EnumIter, ToSchema, Serialize, PartialEq, Deserialize,
+)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum FeatureFlag {
+    /// server side clustering of the trackers last positions
+    ClusteredPositions,
+}
+
+impl FeatureFlag {
+    /// if the feature is enabled for organizations that did not set the flag
+    pub const fn default_enabled(self) -> bool {
+        match self {
+            Self::ClusteredPositions => false,
+        }
+    }
+
+    /// the flag name as stored on the database
+    pub fn to_db_value(self) -> String {
+        self.to_string().to_case(Case::ScreamingSnake)
+    }
+
+    /// parses a flag stored on the database, `None` for unknown flags
+    pub fn from_db_value(value: &str) -> Option<Self> {
+        Self::iter().find(|flag| flag.to_db_value() == value)
+    }
+}
+
 pub struct TrackerModelInfo {
     /// amount of sim cards that can be installed on a tracker
     pub sim_card_slots: u8,
@@ -5,10 +5,13 @@ pub static DEFAULT_EXCHANGE: &str = "";
 pub static TRACKER_EVENTS_QUEUE: &str = "tracker";
 
 /// RabbitMQ queue to publish requests to the mailer service
-pub static MAILER_QUEUE: &str = "mailer";
+pub static MAILER_QUEUE: &str = "mailer_requests";
 
 /// RabbitMQ exchange to listen to tracker events, such as positions and alerts
 pub static TRACKER_EVENTS_EXCHANGE: &str = "tracker_events";
 
+/// RabbitMQ exchange where the API publishes events about its entities, such as a tracker being created
+pub static API_EVENTS_EXCHANGE: &str = "api_events";
+
 /// RPC operation to send a email
 pub static OP_SEND_EMAIL: &str = "sendEmail";
@@ -20,6 +20,11 @@ pub struct LocationMsg {
 
     /// vehicle date and time sent by the tracker
     pub timestamp: DateTime<Utc>,
+
+    /// date and time the decoder received the location, `None` if sent by
+    /// a decoder that did not set it yet
+    #[serde(default)]
+    pub received_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -1,6 +1,8 @@
 //! DTOS for all events and operation inputs accepted by the mailer service
 
 use super::validation::{email_vec, rfc_5322_email};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use uuid;
@@ -16,7 +18,7 @@ pub struct EmailRecipient {
     /// An array of email addresses to send the email to and the
     /// replacements to use on the email html for that email address, eg:
     ///
-    /// ```
+    /// ```json
     /// { email: "jhon@gmail.com", replacements: { "name": "jhon" } }
     /// ```
     pub replacements: Option<HashMap<String, String>>,
@@ -31,6 +33,37 @@ impl EmailRecipient {
     }
 }
 
+/// A file attached to a email
+#[derive(Debug, Validate, Deserialize, Serialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EmailAttachment {
+    /// name of the file shown to the recipients, eg: `report.pdf`
+    #[validate(length(min = 1, max = 255))]
+    pub filename: String,
+
+    /// MIME type of the file, eg: `application/pdf`
+    #[validate(length(min = 3, max = 255))]
+    pub content_type: String,
+
+    /// the file bytes, base64 encoded
+    pub content: String,
+}
+
+impl EmailAttachment {
+    pub fn new(filename: &str, content_type: &str, bytes: &[u8]) -> EmailAttachment {
+        EmailAttachment {
+            filename: String::from(filename),
+            content_type: String::from(content_type),
+            content: STANDARD.encode(bytes),
+        }
+    }
+
+    /// the file bytes, fails if `content` is not valid base64
+    pub fn decode(&self) -> Result<Vec<u8>, base64::DecodeError> {
+        STANDARD.decode(&self.content)
+    }
+}
+
 #[derive(Debug, Default, Validate, Deserialize, Serialize, Clone)]
 #[serde(rename_all = "camelCase")]
 pub struct SendEmailIn {
@@ -62,9 +95,30 @@ pub struct SendEmailIn {
     /// If tracking for email events such as clicks and opens should be enabled
     #[serde(default)]
     pub enable_tracking: bool,
+
+    /// Files to attach to the email, the mailer service limits their total size
+    #[validate]
+    #[serde(default)]
+    pub attachments: Vec<EmailAttachment>,
+
+    /// If set the emails are not sent before this time, eg: so marketing emails respect the
+    /// recipients quiet hours, the mailer defers the request until then. transactional emails
+    /// (eg: password resets) should never set it so they are sent right away
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+
+    /// Id of the organization the emails are sent on behalf of, the mailer limits the emails
+    /// sent per organization so one organization cannot exhaust the sending quota of the others
+    #[serde(default)]
+    pub organization_id: Option<i32>,
 }
 
 impl SendEmailIn {
+    pub fn with_sender(mut self, sender: Option<String>) -> SendEmailIn {
+        self.sender = sender;
+        self
+    }
+
     pub fn with_body_html(mut self, html: &str) -> SendEmailIn {
         self.body_html = Some(String::from(html));
         self
@@ -79,4 +133,19 @@ impl SendEmailIn {
         self.subject = String::from(subject);
         self
     }
+
+    pub fn with_attachments(mut self, attachments: Vec<EmailAttachment>) -> SendEmailIn {
+        self.attachments = attachments;
+        self
+    }
+
+    pub fn with_organization_id(mut self, organization_id: Option<i32>) -> SendEmailIn {
+        self.organization_id = organization_id;
+        self
+    }
+
+    pub fn with_not_before(mut self, not_before: Option<DateTime<Utc>>) -> SendEmailIn {
+        self.not_before = not_before;
+        self
+    }
 }
@@ -0,0 +1,29 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+/// Audit record of a superuser starting or stopping the impersonation of a user
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, ToSchema)]
+#[schema(as = entity::impersonation_log::Model)]
+#[sea_orm(table_name = "impersonation_log")]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub created_at: DateTime<Utc>,
+    /// `started` or `stopped`
+    pub action: String,
+    pub impersonator_id: i32,
+    pub impersonated_user_id: i32,
+    /// public id of the impersonation session
+    pub session_public_id: i32,
+    pub user_agent: String,
+    #[sea_orm(column_type = "custom(\"inet\")", select_as = "text", save_as = "inet")]
+    pub ip: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
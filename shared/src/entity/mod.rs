@@ -2,12 +2,26 @@ pub mod prelude;
 pub mod traits;
 
 pub mod access_level;
+pub mod impersonation_log;
+pub mod login_history;
+pub mod org_feature_flag;
 pub mod organization;
+pub mod organization_activity;
+pub mod organization_email_identity;
+pub mod outbox;
 pub mod session;
 pub mod sim_card;
+pub mod sim_card_data_usage;
+pub mod socket_connection_ticket;
 pub mod spatial_ref_sys;
+pub mod unknown_imei_location;
 pub mod user;
 pub mod vehicle;
+pub mod vehicle_daily_distance;
+pub mod vehicle_photo_upload;
 pub mod vehicle_tracker;
+pub mod vehicle_tracker_assignment;
 pub mod vehicle_tracker_last_location;
 pub mod vehicle_tracker_location;
+pub mod vehicle_tracker_location_compaction_cursor;
+pub mod vehicle_tracker_odometer_cursor;
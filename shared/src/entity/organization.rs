@@ -16,6 +16,25 @@ pub struct Model {
     pub confirm_billing_email_token: Option<String>,
     #[sea_orm(unique)]
     pub owner_id: Option<i32>,
+    /// email address used to send emails to the organization users, if `None`
+    /// or no longer verified on SES the default sender is used instead
+    pub email_sender: Option<String>,
+    /// maximum amount of vehicles the organization plan allows, `None` for no limit
+    pub max_vehicles: Option<i32>,
+    /// maximum amount of trackers the organization plan allows, `None` for no limit
+    pub max_trackers: Option<i32>,
+    /// networks (in CIDR notation) the organization users are allowed to send
+    /// requests from, `None` for no restriction
+    #[sea_orm(
+        column_type = "custom(\"cidr[]\")",
+        select_as = "text[]",
+        save_as = "cidr[]",
+        nullable
+    )]
+    pub ip_allowlist: Option<Vec<String>>,
+    /// access level assigned to users created or invited
+    /// to the organization without a access level
+    pub default_access_level_id: Option<i32>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
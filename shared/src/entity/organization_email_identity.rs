@@ -0,0 +1,34 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+/// A SES identity (email address or domain) whose verification was started by a organization,
+/// only the organization that owns a identity can use it as its email sender
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "organization_email_identity")]
+pub struct Model {
+    /// the email address or domain, in lowercase
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub identity: String,
+    pub created_at: DateTime<Utc>,
+    pub organization_id: i32,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::organization::Entity",
+        from = "Column::OrganizationId",
+        to = "super::organization::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Organization,
+}
+
+impl Related<super::organization::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Organization.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
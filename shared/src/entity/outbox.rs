@@ -0,0 +1,28 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "outbox")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub created_at: DateTime<Utc>,
+    /// type of the entity the message refers to, eg: `tracker`
+    pub aggregate_type: String,
+    /// id of the entity the message refers to, messages of the
+    /// same aggregate are published in the order they were created
+    pub aggregate_id: String,
+    pub exchange: String,
+    pub routing_key: String,
+    #[sea_orm(column_type = "JsonBinary")]
+    pub payload: Json,
+    pub published_at: Option<DateTime<Utc>>,
+    /// until when the message is claimed by a relay publishing it, expired
+    /// claims are ignored so messages of a crashed relay are published again
+    pub claimed_until: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
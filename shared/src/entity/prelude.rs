@@ -1,10 +1,24 @@
 pub use super::access_level::Entity as AccessLevel;
+pub use super::impersonation_log::Entity as ImpersonationLog;
+pub use super::login_history::Entity as LoginHistory;
+pub use super::org_feature_flag::Entity as OrgFeatureFlag;
 pub use super::organization::Entity as Organization;
+pub use super::organization_activity::Entity as OrganizationActivity;
+pub use super::organization_email_identity::Entity as OrganizationEmailIdentity;
+pub use super::outbox::Entity as Outbox;
 pub use super::session::Entity as Session;
 pub use super::sim_card::Entity as SimCard;
+pub use super::sim_card_data_usage::Entity as SimCardDataUsage;
+pub use super::socket_connection_ticket::Entity as SocketConnectionTicket;
 pub use super::spatial_ref_sys::Entity as SpatialRefSys;
+pub use super::unknown_imei_location::Entity as UnknownImeiLocation;
 pub use super::user::Entity as User;
 pub use super::vehicle::Entity as Vehicle;
+pub use super::vehicle_daily_distance::Entity as VehicleDailyDistance;
+pub use super::vehicle_photo_upload::Entity as VehiclePhotoUpload;
 pub use super::vehicle_tracker::Entity as VehicleTracker;
+pub use super::vehicle_tracker_assignment::Entity as VehicleTrackerAssignment;
 pub use super::vehicle_tracker_last_location::Entity as VehicleTrackerLastLocation;
 pub use super::vehicle_tracker_location::Entity as VehicleTrackerLocation;
+pub use super::vehicle_tracker_location_compaction_cursor::Entity as VehicleTrackerLocationCompactionCursor;
+pub use super::vehicle_tracker_odometer_cursor::Entity as VehicleTrackerOdometerCursor;
@@ -19,12 +19,15 @@ pub struct Model {
     #[sea_orm(column_type = "custom(\"inet\")", select_as = "text", save_as = "inet")]
     pub ip: String,
     pub user_id: i32,
+    /// id of the superuser that created this session to impersonate
+    /// the session user, `None` for regular sessions
+    pub impersonator_id: Option<i32>,
 }
 
 impl Entity {
-    pub async fn find_with_user_by_public_id(
+    pub async fn find_with_user_by_public_id<C: ConnectionTrait>(
         public_id: i32,
-        db: &DatabaseConnection,
+        db: &C,
     ) -> Result<Option<(Model, user::Model)>, DbErr> {
         let res = user::Entity::find()
             .filter(Column::PublicId.eq(public_id))
@@ -38,6 +41,16 @@ impl Entity {
 
         Ok(None)
     }
+
+    /// deletes every expired session, returning how many were deleted
+    pub async fn delete_expired<C: ConnectionTrait>(db: &C) -> Result<u64, DbErr> {
+        let res = Self::delete_many()
+            .filter(Column::ExpiresAt.lt(Utc::now()))
+            .exec(db)
+            .await?;
+
+        Ok(res.rows_affected)
+    }
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
@@ -23,6 +23,8 @@ pub struct Model {
     pub puk2: Option<String>,
     pub organization_id: i32,
     pub vehicle_tracker_id: Option<i32>,
+    /// monthly data quota of the SIM card plan
+    pub data_quota_mb: Option<i32>,
 }
 
 impl QueryableByIdAndOrgId for Entity {
@@ -59,6 +61,8 @@ pub enum Relation {
         on_delete = "SetNull"
     )]
     VehicleTracker,
+    #[sea_orm(has_many = "super::sim_card_data_usage::Entity")]
+    SimCardDataUsage,
 }
 
 impl Related<super::organization::Entity> for Entity {
@@ -73,4 +77,10 @@ impl Related<super::vehicle_tracker::Entity> for Entity {
     }
 }
 
+impl Related<super::sim_card_data_usage::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SimCardDataUsage.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
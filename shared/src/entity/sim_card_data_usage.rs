@@ -0,0 +1,38 @@
+use chrono::{DateTime, NaiveDate, Utc};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+use utoipa::ToSchema;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, ToSchema)]
+#[schema(as = entity::sim_card_data_usage::Model)]
+#[sea_orm(table_name = "sim_card_data_usage")]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub sim_card_id: i32,
+    /// first day of the month the usage refers to
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub period: NaiveDate,
+    pub data_usage_mb: f64,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::sim_card::Entity",
+        from = "Column::SimCardId",
+        to = "super::sim_card::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    SimCard,
+}
+
+impl Related<super::sim_card::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::SimCard.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -15,3 +15,19 @@ pub trait QueryableByIdAndOrgId {
         db: &DatabaseConnection,
     ) -> impl std::future::Future<Output = Result<Option<Self::Model>, DbErr>> + Send;
 }
+
+/// Trait for organization bound entities that other organizations can also read,
+/// such as trackers loaned to another organization
+///
+/// only use it for read only access, entities are modified by their organization
+/// and should be queried with `QueryableByIdAndOrgId` for that.
+pub trait ReadableByIdAndOrgId {
+    /// The model of the entity that is returned by the query
+    type Model;
+
+    fn find_readable_by_id_and_org_id(
+        id: i32,
+        org_id: i32,
+        db: &DatabaseConnection,
+    ) -> impl std::future::Future<Output = Result<Option<Self::Model>, DbErr>> + Send;
+}
@@ -0,0 +1,26 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+/// A location decoded from a tracker whose IMEI is not registered, held for a
+/// limited time so it can be backfilled once a tracker with the IMEI is created
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq)]
+#[sea_orm(table_name = "unknown_imei_location")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i64,
+    pub imei: String,
+    /// protocol the location was decoded from, eg: `h02`
+    pub protocol: String,
+    pub time: DateTime<Utc>,
+    /// time reported by the tracker
+    pub device_time: DateTime<Utc>,
+    /// time the location was received by the decoder
+    pub received_at: DateTime<Utc>,
+    #[sea_orm(column_type = "custom(\"geometry\")")]
+    pub point: String,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {}
+
+impl ActiveModelBehavior for ActiveModel {}
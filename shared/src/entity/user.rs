@@ -34,6 +34,11 @@ pub struct Model {
     #[sea_orm(column_type = "Text", nullable, unique)]
     pub confirm_email_token: Option<String>,
 
+    /// JWT to sign in without a password, cleared as soon as it is used so a
+    /// magic link can not be used to sign in more than once
+    #[sea_orm(column_type = "Text", nullable, unique)]
+    pub magic_link_token: Option<String>,
+
     pub profile_picture: Option<String>,
 
     #[sea_orm(column_type = "Text", nullable)]
@@ -4,7 +4,7 @@ use sea_orm::{entity::prelude::*, QuerySelect};
 use serde::Serialize;
 use utoipa::ToSchema;
 
-#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize, ToSchema)]
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Serialize, ToSchema)]
 #[schema(as = entity::vehicle::Model)]
 #[sea_orm(table_name = "vehicle")]
 #[serde(rename_all = "camelCase")]
@@ -22,6 +22,8 @@ pub struct Model {
     pub color: Option<String>,
     pub additional_info: Option<String>,
     pub organization_id: i32,
+    /// total kilometers traveled by the vehicle, according to its trackers positions
+    pub odometer_km: f64,
 }
 
 impl QueryableByIdAndOrgId for Entity {
@@ -70,6 +72,8 @@ pub enum Relation {
     Organization,
     #[sea_orm(has_many = "super::vehicle_tracker::Entity")]
     VehicleTracker,
+    #[sea_orm(has_many = "super::vehicle_daily_distance::Entity")]
+    VehicleDailyDistance,
 }
 
 impl Related<super::organization::Entity> for Entity {
@@ -84,4 +88,10 @@ impl Related<super::vehicle_tracker::Entity> for Entity {
     }
 }
 
+impl Related<super::vehicle_daily_distance::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::VehicleDailyDistance.def()
+    }
+}
+
 impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,32 @@
+use chrono::NaiveDate;
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "vehicle_daily_distance")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub vehicle_id: i32,
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub day: NaiveDate,
+    pub distance_km: f64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::vehicle::Entity",
+        from = "Column::VehicleId",
+        to = "super::vehicle::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    Vehicle,
+}
+
+impl Related<super::vehicle::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Vehicle.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -1,7 +1,8 @@
-use super::traits::QueryableByIdAndOrgId;
+use super::traits::{QueryableByIdAndOrgId, ReadableByIdAndOrgId};
 use crate::constants::TrackerModel;
 use chrono::{DateTime, Utc};
 use sea_orm::entity::prelude::*;
+use sea_orm::Condition;
 use serde::Serialize;
 use utoipa::ToSchema;
 
@@ -17,6 +18,21 @@ pub struct Model {
     pub imei: String,
     pub organization_id: i32,
     pub vehicle_id: Option<i32>,
+    pub firmware_version: Option<String>,
+    /// free text notes about the tracker (eg: where it is installed on the vehicle)
+    #[sea_orm(column_type = "Text", nullable)]
+    pub notes: Option<String>,
+    /// when the tracker was installed on its current vehicle
+    pub installed_at: Option<DateTime<Utc>>,
+    /// seconds between the positions the tracker is configured to send, if `None`
+    /// the API default is assumed, used to tell if the tracker is offline
+    pub reporting_interval_seconds: Option<i32>,
+    /// organization the tracker is loaned to, which can read but not modify it until the loan expires
+    pub loaned_to_org_id: Option<i32>,
+    /// when the loan ends, loans are only active while this is in the future
+    pub loan_expires_at: Option<DateTime<Utc>>,
+    /// when the loan started, the borrowing organization can only read the locations since then
+    pub loaned_at: Option<DateTime<Utc>>,
 }
 
 impl QueryableByIdAndOrgId for Entity {
@@ -35,7 +51,33 @@ impl QueryableByIdAndOrgId for Entity {
     }
 }
 
+impl ReadableByIdAndOrgId for Entity {
+    type Model = Model;
+
+    async fn find_readable_by_id_and_org_id(
+        id: i32,
+        org_id: i32,
+        db: &DatabaseConnection,
+    ) -> Result<Option<Model>, DbErr> {
+        Self::find()
+            .filter(Column::Id.eq(id))
+            .filter(Entity::readable_by_org(org_id))
+            .one(db)
+            .await
+    }
+}
+
 impl Entity {
+    /// condition of the trackers a organization can read, which are its own trackers
+    /// and the trackers loaned to it whose loan did not expire
+    pub fn readable_by_org(org_id: i32) -> Condition {
+        Condition::any().add(Column::OrganizationId.eq(org_id)).add(
+            Condition::all()
+                .add(Column::LoanedToOrgId.eq(org_id))
+                .add(Column::LoanExpiresAt.gt(Utc::now())),
+        )
+    }
+
     pub async fn find_by_vehicle_and_org_id(
         vehicle_id: i32,
         organization_id: i32,
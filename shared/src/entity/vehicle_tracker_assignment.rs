@@ -0,0 +1,39 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+use serde::Serialize;
+
+/// A period of time a tracker was installed on a vehicle, written by a
+/// trigger whenever `vehicle_tracker.vehicle_id` changes
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel, Eq, Serialize)]
+#[sea_orm(table_name = "vehicle_tracker_assignment")]
+#[serde(rename_all = "camelCase")]
+pub struct Model {
+    #[sea_orm(primary_key)]
+    pub id: i32,
+    pub organization_id: i32,
+    pub vehicle_id: i32,
+    pub vehicle_tracker_id: i32,
+    pub started_at: DateTime<Utc>,
+    /// `None` if the tracker is still installed on the vehicle
+    pub ended_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::vehicle::Entity",
+        from = "Column::VehicleId",
+        to = "super::vehicle::Column::Id",
+        on_update = "NoAction",
+        on_delete = "Cascade"
+    )]
+    Vehicle,
+}
+
+impl Related<super::vehicle::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::Vehicle.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
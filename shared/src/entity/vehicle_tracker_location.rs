@@ -10,6 +10,10 @@ pub struct Model {
     pub vehicle_tracker_id: i32,
     #[sea_orm(column_type = "custom(\"geometry\")")]
     pub point: String,
+    /// time reported by the tracker, `null` on locations stored before it was recorded
+    pub device_time: Option<DateTime<Utc>>,
+    /// time the location was received by the decoder, `null` on locations stored before it was recorded
+    pub received_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
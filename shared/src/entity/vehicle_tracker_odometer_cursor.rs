@@ -0,0 +1,37 @@
+use chrono::{DateTime, Utc};
+use sea_orm::entity::prelude::*;
+
+#[derive(Clone, Debug, PartialEq, DeriveEntityModel)]
+#[sea_orm(table_name = "vehicle_tracker_odometer_cursor")]
+pub struct Model {
+    #[sea_orm(primary_key, auto_increment = false)]
+    pub vehicle_tracker_id: i32,
+    /// vehicle the tracker was installed on when the cursor was created
+    pub vehicle_id: i32,
+    /// time of the last tracker location processed
+    pub processed_until: DateTime<Utc>,
+    /// time of the last location accounted on the odometer
+    pub point_time: DateTime<Utc>,
+    pub lat: f64,
+    pub lng: f64,
+}
+
+#[derive(Copy, Clone, Debug, EnumIter, DeriveRelation)]
+pub enum Relation {
+    #[sea_orm(
+        belongs_to = "super::vehicle_tracker::Entity",
+        from = "Column::VehicleTrackerId",
+        to = "super::vehicle_tracker::Column::Id",
+        on_update = "Cascade",
+        on_delete = "Cascade"
+    )]
+    VehicleTracker,
+}
+
+impl Related<super::vehicle_tracker::Entity> for Entity {
+    fn to() -> RelationDef {
+        Relation::VehicleTracker.def()
+    }
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,88 @@
+//! Validation of tracker identifiers.
+
+/// Maximum length of a tracker IMEI, longer than the 15 digits of IMEIs since
+/// some tracker models identify themselves by a numeric serial number
+pub const MAX_TRACKER_IMEI_LEN: usize = 20;
+
+/// Checks the Luhn check digit of a numeric string, such as the last digit of 15 digit IMEIs
+pub fn passes_luhn_check(digits: &str) -> bool {
+    let mut sum = 0;
+
+    for (i, c) in digits.chars().rev().enumerate() {
+        let Some(mut digit) = c.to_digit(10) else {
+            return false;
+        };
+
+        if i % 2 == 1 {
+            digit *= 2;
+
+            if digit > 9 {
+                digit -= 9;
+            }
+        }
+
+        sum += digit;
+    }
+
+    !digits.is_empty() && sum % 10 == 0
+}
+
+/// Checks if a tracker IMEI is valid, 15 digit IMEIs must have a valid Luhn check digit
+/// while other identifiers (eg: serial numbers) only need to have 1 to 20 digits. Only
+/// digits are allowed since the IMEI is a segment of the tracker events routing keys,
+/// where `.`, `*` and `#` are special
+pub fn is_valid_tracker_imei(imei: &str) -> bool {
+    if !(1..=MAX_TRACKER_IMEI_LEN).contains(&imei.len())
+        || !imei.chars().all(|c| c.is_ascii_digit())
+    {
+        return false;
+    }
+
+    if imei.len() == 15 {
+        return passes_luhn_check(imei);
+    }
+
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn luhn_check() {
+        let cases = [
+            ("490154203237518", true),
+            ("356938035643809", true),
+            ("0", true),
+            ("79927398713", true),
+            ("490154203237510", false),
+            ("79927398710", false),
+            ("", false),
+            ("4901542032375a8", false),
+            ("49015420-3237518", false),
+            (" 490154203237518", false),
+        ];
+
+        for (digits, valid) in cases {
+            assert_eq!(passes_luhn_check(digits), valid, "{digits:?}");
+        }
+    }
+
+    #[test]
+    fn tracker_imei() {
+        let cases = [
+            ("490154203237518", true),
+            ("490154203237510", false),
+            ("12345", true),
+            ("12345678901234567890", true),
+            ("123456789012345678901", false),
+            ("", false),
+            ("12#45", false),
+        ];
+
+        for (imei, valid) in cases {
+            assert_eq!(is_valid_tracker_imei(imei), valid, "{imei:?}");
+        }
+    }
+}
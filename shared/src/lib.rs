@@ -1,4 +1,7 @@
+pub mod backoff;
+pub mod config;
 pub mod constants;
 pub mod dto;
 pub mod entity;
+pub mod imei;
 pub mod tracer;
@@ -3,13 +3,238 @@ use lapin::{
     types::{AMQPValue, ShortString},
 };
 use opentelemetry::{
+    metrics::MetricsError,
     propagation::{Extractor, Injector},
-    Context,
+    sdk::{
+        export::metrics::{stdout, ExportLine},
+        metrics::{controllers, processors, selectors},
+        trace::{self, Sampler},
+        Resource,
+    },
+    Context, KeyValue,
 };
-use std::collections::BTreeMap;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{collections::BTreeMap, fmt, io, time::Duration};
 use tokio::time;
-use tracing::{error, info_span, warn, Span};
+use tracing::{error, info_span, warn, Span, Subscriber};
 use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{
+    filter::LevelFilter, fmt::MakeWriter, registry::LookupSpan, EnvFilter, Layer,
+};
+
+/// Tracing options common to all services
+#[derive(Debug, Clone)]
+pub struct TracingOpts {
+    /// if spans should be exported, when `false` the tracing subscriber is
+    /// still initialized but no exporter is installed, useful for local dev
+    pub enabled: bool,
+
+    /// ratio of traces to sample, from `0.0` (none) to `1.0` (all)
+    pub sample_ratio: f64,
+
+    /// if the sampling decision of the parent span should be respected, the parent
+    /// might come from another service, such as a trace correlated from the headers
+    /// of a RabbitMQ delivery, so the decision of the service that started the trace
+    /// is kept across services. the ratio is only applied to root spans.
+    pub parent_based: bool,
+
+    /// if set the metrics recorded by the service are written to stdout every interval,
+    /// otherwise no meter provider is installed and recording metrics does nothing
+    pub metrics_export_interval: Option<Duration>,
+}
+
+impl Default for TracingOpts {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sample_ratio: 1.0,
+            parent_based: true,
+            metrics_export_interval: None,
+        }
+    }
+}
+
+impl TracingOpts {
+    /// creates the sampler for the configured ratio and parent strategy
+    pub fn sampler(&self) -> Sampler {
+        let ratio_sampler = if self.sample_ratio >= 1.0 {
+            Sampler::AlwaysOn
+        } else if self.sample_ratio <= 0.0 {
+            Sampler::AlwaysOff
+        } else {
+            Sampler::TraceIdRatioBased(self.sample_ratio)
+        };
+
+        if self.parent_based {
+            Sampler::ParentBased(Box::new(ratio_sampler))
+        } else {
+            ratio_sampler
+        }
+    }
+
+    /// creates the opentelemetry trace config with the sampler
+    pub fn trace_config(&self) -> trace::Config {
+        trace::config().with_sampler(self.sampler())
+    }
+}
+
+/// Format of the logs written to stdout
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// human readable text, meant for local development
+    #[default]
+    Pretty,
+    /// one JSON object per line, meant to be parsed by log aggregators
+    Json,
+}
+
+/// creates the layer that writes the service logs (tracing events) to stdout,
+/// filtered by the `RUST_LOG` env var and defaulting to the `INFO` level
+pub fn stdout_layer<S>(service_name: &str, format: LogFormat) -> Box<dyn Layer<S> + Send + Sync>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    // only the stdout logs are filtered, so spans are still exported regardless of level
+    let filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .from_env_lossy();
+
+    match format {
+        LogFormat::Pretty => tracing_subscriber::fmt::layer().with_filter(filter).boxed(),
+        // the event fields are flattened into the log object and the spans in the scope of
+        // the event are listed with their fields, eg:
+        // {"service":"api","timestamp":"..","level":"INFO","message":"..","target":"..","spans":[{"name":"..",..}]}
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(false)
+            .with_span_list(true)
+            .with_writer(JsonStdoutWriter::new(service_name))
+            .with_filter(filter)
+            .boxed(),
+    }
+}
+
+/// Installs the global meter provider, so the metrics recorded with `opentelemetry::global::meter`
+/// (eg: the tracker id cache hits) are written to stdout every `interval` as one JSON object
+/// per metric, the metric name contains its attributes, eg:
+///
+/// ```json
+/// {"timestamp":"..","service":"api","metric":"hits{service.name=api,instrumentation.name=tracker_id_cache}","sum":10}
+/// ```
+pub fn init_metrics(service_name: &str, interval: Duration) -> Result<(), MetricsError> {
+    let service = service_name.to_string();
+
+    let exporter = stdout()
+        .with_formatter(move |batch| {
+            Ok(batch
+                .into_iter()
+                .map(|line| format!("{}\n", metric_json(&service, line)))
+                .collect())
+        })
+        .build()?;
+
+    let controller = controllers::basic(processors::factory(
+        selectors::simple::inexpensive(),
+        exporter.temporality_selector(),
+    ))
+    .with_resource(Resource::new([KeyValue::new(
+        "service.name",
+        service_name.to_string(),
+    )]))
+    .with_exporter(exporter)
+    .with_collect_period(interval)
+    .build();
+
+    controller.start(&Context::current(), opentelemetry::runtime::Tokio)?;
+    opentelemetry::global::set_meter_provider(controller);
+
+    println!("[TRACER] exporting metrics every {:?}", interval);
+    Ok(())
+}
+
+/// formats a exported metric as a JSON object
+fn metric_json(service: &str, line: ExportLine) -> Value {
+    // the exporter only exposes the values as debug, which for numbers is the number itself
+    let number = |value: &dyn fmt::Debug| {
+        let value = format!("{:?}", value);
+        value
+            .parse::<f64>()
+            .map(Value::from)
+            .unwrap_or(Value::from(value))
+    };
+
+    let mut metric = json!({
+        "timestamp": chrono::Utc::now().to_rfc3339(),
+        "service": service,
+        "metric": line.name,
+    });
+
+    if let Some(sum) = line.sum {
+        metric["sum"] = number(&sum);
+    }
+
+    if let Some(last_value) = line.last_value {
+        metric["last_value"] = number(&last_value);
+    }
+
+    metric
+}
+
+/// Makes the writers of the JSON logs, writing them to stdout with the `service` field
+struct JsonStdoutWriter {
+    /// the `service` field as a JSON object member, eg: `"service":"api",`
+    service_field: String,
+}
+
+impl JsonStdoutWriter {
+    fn new(service_name: &str) -> Self {
+        Self {
+            service_field: format!("\"service\":{},", Value::from(service_name)),
+        }
+    }
+}
+
+impl<'a> MakeWriter<'a> for JsonStdoutWriter {
+    type Writer = ServiceFieldWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        ServiceFieldWriter {
+            stdout: io::stdout().lock(),
+            service_field: &self.service_field,
+            at_start: true,
+        }
+    }
+}
+
+/// Writes a JSON log to stdout, adding the service field as the first member of the log object
+struct ServiceFieldWriter<'a> {
+    stdout: io::StdoutLock<'static>,
+    service_field: &'a str,
+    at_start: bool,
+}
+
+impl<'a> io::Write for ServiceFieldWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if std::mem::take(&mut self.at_start) {
+            if let Some(members) = buf.strip_prefix(b"{") {
+                self.stdout.write_all(b"{")?;
+                self.stdout.write_all(self.service_field.as_bytes())?;
+                self.stdout.write_all(members)?;
+
+                return Ok(buf.len());
+            }
+        }
+
+        self.stdout.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stdout.flush()
+    }
+}
 
 /// struct to Injecting and Extracting otel span contexts into/from a
 /// rabbitmq delivery using its headers
@@ -63,6 +288,10 @@ pub fn create_amqp_headers_with_span_ctx(ctx: &Context) -> BTreeMap<ShortString,
 
 /// Extracts the text map propagator from the AMQP headers and creates a span
 /// with the extracted context as the parent context.
+///
+/// the extracted context carries the sampling decision of the publisher, so services
+/// using a parent based sampler (see `TracingOpts`) only export the span if the
+/// publisher sampled it.
 pub fn correlate_trace_from_delivery(delivery: Delivery) -> (Span, Delivery) {
     let span = info_span!("correlate_trace_from_delivery");
 